@@ -0,0 +1,316 @@
+pub mod ber;
+mod types;
+mod macros;
+
+pub use types::*;
+
+use std::io::{Error, ErrorKind};
+
+/// X.680 8.1 tag classes, as in bits 8 and 7 of the identifier octets of X.690 8.1.2
+/// NOTE: the order is the one of the canonical order of tags, X.680 8.6
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Class {
+    Universal = 0,
+    Application = 1,
+    ContextSpecific = 2,
+    Private = 3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tag {
+    pub class: Class,
+    pub number: u32,
+}
+
+impl Tag {
+    // X.680 8.4 universal class tags
+    /// end-of-contents octets of the indefinite length form
+    pub const END_OF_CONTENTS: Tag = Tag::universal(0);
+    pub const BOOLEAN: Tag = Tag::universal(1);
+    pub const INTEGER: Tag = Tag::universal(2);
+    pub const BIT_STRING: Tag = Tag::universal(3);
+    pub const OCTET_STRING: Tag = Tag::universal(4);
+    pub const NULL: Tag = Tag::universal(5);
+    pub const OBJECT_IDENTIFIER: Tag = Tag::universal(6);
+    pub const OBJECT_DESCRIPTOR: Tag = Tag::universal(7);
+    pub const EXTERNAL: Tag = Tag::universal(8);
+    pub const REAL: Tag = Tag::universal(9);
+    pub const ENUMERATED: Tag = Tag::universal(10);
+    pub const EMBEDDED_PDV: Tag = Tag::universal(11);
+    pub const UTF8_STRING: Tag = Tag::universal(12);
+    pub const RELATIVE_OID: Tag = Tag::universal(13);
+    pub const SEQUENCE: Tag = Tag::universal(16);
+    pub const SET: Tag = Tag::universal(17);
+    pub const NUMERIC_STRING: Tag = Tag::universal(18);
+    pub const PRINTABLE_STRING: Tag = Tag::universal(19);
+    pub const TELETEX_STRING: Tag = Tag::universal(20);
+    pub const VIDEOTEX_STRING: Tag = Tag::universal(21);
+    pub const IA5_STRING: Tag = Tag::universal(22);
+    pub const UTC_TIME: Tag = Tag::universal(23);
+    pub const GENERALIZED_TIME: Tag = Tag::universal(24);
+    pub const GRAPHIC_STRING: Tag = Tag::universal(25);
+    pub const VISIBLE_STRING: Tag = Tag::universal(26);
+    pub const GENERAL_STRING: Tag = Tag::universal(27);
+    pub const UNIVERSAL_STRING: Tag = Tag::universal(28);
+    pub const BMP_STRING: Tag = Tag::universal(30);
+
+    pub const fn universal(number: u32) -> Tag {
+        return Tag { class: Class::Universal, number: number };
+    }
+
+    pub const fn application(number: u32) -> Tag {
+        return Tag { class: Class::Application, number: number };
+    }
+
+    pub const fn context(number: u32) -> Tag {
+        return Tag { class: Class::ContextSpecific, number: number };
+    }
+
+    pub const fn private(number: u32) -> Tag {
+        return Tag { class: Class::Private, number: number };
+    }
+}
+
+/// X.690 - basic, canonical and distinguished encoding rules
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodingRules {
+    /// definite lengths when encoding, everything X.690 allows when decoding
+    Ber,
+    /// X.690 9 - indefinite lengths for constructed encodings, strings in segments of 1000 octets
+    Cer,
+    /// X.690 10 - definite lengths, no constructed strings
+    Der,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Content {
+    Primitive(Vec<u8>),
+    Constructed(Vec<Element>),
+}
+
+/// one TLV of the encoding, not yet resp. no longer mapped to a type - also what open types carry
+#[derive(Clone, Debug, PartialEq)]
+pub struct Element {
+    pub tag: Tag,
+    pub content: Content,
+}
+
+fn invalid(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, message.to_string());
+}
+
+impl Element {
+    pub fn primitive(tag: Tag, value: Vec<u8>) -> Element {
+        return Element { tag: tag, content: Content::Primitive(value) };
+    }
+
+    pub fn constructed(tag: Tag, elements: Vec<Element>) -> Element {
+        return Element { tag: tag, content: Content::Constructed(elements) };
+    }
+
+    pub fn is_constructed(&self) -> bool {
+        return matches!(self.content, Content::Constructed(_));
+    }
+
+    /// contents octets of a primitive encoding
+    pub fn value(&self) -> Result<&[u8], Error> {
+        match &self.content {
+            Content::Primitive(value) => { return Ok(value); },
+            Content::Constructed(_) => { return Err(invalid("primitive encoding expected")); }
+        }
+    }
+
+    /// nested elements of a constructed encoding
+    pub fn elements(&self) -> Result<&[Element], Error> {
+        match &self.content {
+            Content::Constructed(elements) => { return Ok(elements); },
+            Content::Primitive(_) => { return Err(invalid("constructed encoding expected")); }
+        }
+    }
+
+    /// X.690 8.14.3 - implicit tagging replaces the tag
+    pub fn implicit(mut self, tag: Tag) -> Element {
+        self.tag = tag;
+        return self;
+    }
+
+    /// X.690 8.14.2 - explicit tagging wraps the encoding into a constructed one
+    pub fn explicit(self, tag: Tag) -> Element {
+        return Element::constructed(tag, vec![self]);
+    }
+
+    /// the element inside an explicit tag
+    pub fn inner(&self) -> Result<&Element, Error> {
+        match self.elements()? {
+            [inner] => { return Ok(inner); },
+            _ => { return Err(invalid("explicit tagging contains exactly one element")); }
+        }
+    }
+
+    /// contents of a string type - X.690 8.7.3 in BER and CER also the constructed form, as segments of the universal string type
+    pub fn octets(&self, rules: EncodingRules) -> Result<Vec<u8>, Error> {
+        match &self.content {
+            Content::Primitive(value) => { return Ok(value.clone()); },
+            Content::Constructed(_) if rules == EncodingRules::Der => { return Err(invalid("constructed string encoding in DER")); },
+            Content::Constructed(segments) => {
+                let mut octets = vec![];
+                for segment in segments {
+                    if segment.tag != Tag::OCTET_STRING {
+                        return Err(invalid("segment of constructed string not an OCTET STRING"));
+                    }
+                    octets.extend(segment.octets(rules)?);
+                }
+                return Ok(octets);
+            }
+        }
+    }
+
+    pub fn encode(&self, rules: EncodingRules) -> Vec<u8> {
+        let mut buffer = vec![];
+        ber::encode(self, rules, &mut buffer);
+        return buffer;
+    }
+
+    /// exactly one element, no more data behind it
+    pub fn decode(data: &[u8], rules: EncodingRules) -> Result<Element, Error> {
+        let (element, length) = ber::decode(data, rules)?;
+        if length != data.len() {
+            return Err(invalid("data behind the encoding"));
+        }
+        return Ok(element);
+    }
+}
+
+/// mapping of a Rust type to an ASN.1 type
+/// NOTE: from_element() gets an element whose tag the caller has checked with matches() - it is the type's own or the one of implicit tagging
+pub trait Asn1: Sized {
+    /// tag of the type - none for untagged CHOICE and open types
+    const TAG: Option<Tag>;
+
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error>;
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error>;
+
+    /// whether an element with this tag may be of this type - for OPTIONAL components and CHOICE alternatives
+    fn matches(tag: Tag) -> bool {
+        return Self::TAG == Some(tag);
+    }
+
+    /// whether the value goes into the encoding at all - only OPTIONAL components may be missing
+    fn is_present(&self) -> bool {
+        return true;
+    }
+
+    /// the value of a missing component, if it may be missing
+    fn absent() -> Option<Self> {
+        return None;
+    }
+
+    fn encode(&self, rules: EncodingRules) -> Result<Vec<u8>, Error> {
+        return Ok(self.to_element(rules)?.encode(rules));
+    }
+
+    fn decode(data: &[u8], rules: EncodingRules) -> Result<Self, Error> {
+        let element = Element::decode(data, rules)?;
+        if !Self::matches(element.tag) {
+            return Err(invalid("unexpected tag"));
+        }
+        return Self::from_element(&element, rules);
+    }
+}
+
+/// X.680 31 tagging of a component resp. alternative
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tagging {
+    Implicit(Tag),
+    Explicit(Tag),
+}
+
+impl Tagging {
+    /// X.680 31.2.7 - implicit tagging of an untagged CHOICE resp. open type is explicit tagging
+    fn effective<T: Asn1>(tagging: Option<Tagging>) -> Option<Tagging> {
+        match tagging {
+            Some(Tagging::Implicit(tag)) if T::TAG.is_none() => { return Some(Tagging::Explicit(tag)); },
+            _ => { return tagging; }
+        }
+    }
+}
+
+pub fn encode_component<T: Asn1>(value: &T, tagging: Option<Tagging>, rules: EncodingRules) -> Result<Element, Error> {
+    let element = value.to_element(rules)?;
+    match Tagging::effective::<T>(tagging) {
+        None => { return Ok(element); },
+        Some(Tagging::Implicit(tag)) => { return Ok(element.implicit(tag)); },
+        Some(Tagging::Explicit(tag)) => { return Ok(element.explicit(tag)); }
+    }
+}
+
+pub fn matches_component<T: Asn1>(tag: Tag, tagging: Option<Tagging>) -> bool {
+    match tagging {
+        None => { return T::matches(tag); },
+        Some(Tagging::Implicit(tagged)) | Some(Tagging::Explicit(tagged)) => { return tag == tagged; }
+    }
+}
+
+/// the tag has been checked with matches_component()
+pub fn decode_component<T: Asn1>(element: &Element, tagging: Option<Tagging>, rules: EncodingRules) -> Result<T, Error> {
+    match Tagging::effective::<T>(tagging) {
+        None | Some(Tagging::Implicit(_)) => { return T::from_element(element, rules); },
+        Some(Tagging::Explicit(_)) => {
+            let inner = element.inner()?;
+            if !T::matches(inner.tag) {
+                return Err(invalid("unexpected tag inside explicit tagging"));
+            }
+            return T::from_element(inner, rules);
+        }
+    }
+}
+
+/// the components of a SEQUENCE resp. SET being decoded
+pub struct Components<'a> {
+    elements: &'a [Element],
+    used: Vec<bool>,
+    /// SEQUENCE - where the next component is
+    position: usize,
+}
+
+impl<'a> Components<'a> {
+    pub fn new(elements: &'a [Element]) -> Self {
+        return Components { elements: elements, used: vec![false; elements.len()], position: 0 };
+    }
+
+    fn component<T: Asn1>(&mut self, index: Option<usize>, tagging: Option<Tagging>, rules: EncodingRules) -> Result<T, Error> {
+        match index {
+            Some(index) => {
+                self.used[index] = true;
+                return decode_component(&self.elements[index], tagging, rules);
+            },
+            None => { return T::absent().ok_or(invalid("component missing")); }
+        }
+    }
+
+    /// SEQUENCE - the next component, if its tag matches, otherwise it has to be OPTIONAL
+    pub fn next<T: Asn1>(&mut self, tagging: Option<Tagging>, rules: EncodingRules) -> Result<T, Error> {
+        let index = match self.elements.get(self.position) {
+            Some(element) if matches_component::<T>(element.tag, tagging) => Some(self.position),
+            _ => None
+        };
+        if index.is_some() {
+            self.position += 1;
+        }
+        return self.component(index, tagging, rules);
+    }
+
+    /// SET - the component with the matching tag, wherever it is
+    pub fn take<T: Asn1>(&mut self, tagging: Option<Tagging>, rules: EncodingRules) -> Result<T, Error> {
+        let index = (0..self.elements.len()).find(|index| !self.used[*index] && matches_component::<T>(self.elements[*index].tag, tagging));
+        return self.component(index, tagging, rules);
+    }
+
+    /// X.680 52 - unknown components are an error unless the type is extensible
+    pub fn finish(self, extensible: bool) -> Result<(), Error> {
+        if !extensible && self.used.iter().any(|used| !used) {
+            return Err(invalid("unknown component resp. components out of order"));
+        }
+        return Ok(());
+    }
+}
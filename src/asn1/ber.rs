@@ -0,0 +1,599 @@
+use std::io::{Error, ErrorKind, Read, Write};
+
+use super::{Class, Content, Element, EncodingRules, Tag};
+
+/// X.690 8.1.2.2 bit 6 of the identifier octets
+const CONSTRUCTED: u8 = 0x20;
+/// X.690 8.1.2.4 tag number in the subsequent octets
+const HIGH_TAG_NUMBER: u8 = 0x1f;
+/// X.690 8.1.3.6 length octet of the indefinite form
+const INDEFINITE_LENGTH: u8 = 0x80;
+/// X.690 8.1.3.5 c) - reserved for future extension
+const RESERVED_LENGTH: u8 = 0xff;
+/// nesting of constructed encodings accepted when decoding - the decoder recurses
+const MAXIMUM_DEPTH: usize = 64;
+/// default limit on the octets of one element the Decoder resp. read_element() takes in - the lengths come from the peer
+pub const MAXIMUM_LENGTH: usize = 1 << 24;
+
+fn invalid(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, message.to_string());
+}
+
+/// the data ends within the encoding - more of it may follow
+fn truncated() -> Error {
+    return Error::new(ErrorKind::UnexpectedEof, "encoding incomplete");
+}
+
+fn too_long() -> Error {
+    return invalid("encoding longer than the limit");
+}
+
+fn encode_identifier(tag: Tag, constructed: bool, buffer: &mut Vec<u8>) {
+    let first = ((tag.class as u8) << 6) | if constructed { CONSTRUCTED } else { 0 };
+    if tag.number < HIGH_TAG_NUMBER as u32 {
+        buffer.push(first | tag.number as u8);
+        return;
+    }
+    buffer.push(first | HIGH_TAG_NUMBER);
+    encode_base128(tag.number as u64, buffer);
+}
+
+/// X.690 8.1.2.4.2 resp. 8.19.2 - 7 bits per octet, bit 8 set on all but the last
+pub(super) fn encode_base128(value: u64, buffer: &mut Vec<u8>) {
+    let mut shift = (64 - value.leading_zeros()).div_ceil(7).max(1) * 7;
+    while shift > 7 {
+        shift -= 7;
+        buffer.push(0x80 | ((value >> shift) & 0x7f) as u8);
+    }
+    buffer.push((value & 0x7f) as u8);
+}
+
+/// returns the value and the number of octets - X.690 8.1.2.4.2 c) no leading 0x80
+pub(super) fn decode_base128(data: &[u8]) -> Result<(u64, usize), Error> {
+    let mut value: u64 = 0;
+    for (index, octet) in data.iter().enumerate() {
+        if index == 0 && *octet == 0x80 {
+            return Err(invalid("subidentifier resp. tag number with leading zero bits"));
+        }
+        if value >> 57 != 0 {
+            return Err(invalid("subidentifier resp. tag number too large"));
+        }
+        value = (value << 7) | (octet & 0x7f) as u64;
+        if octet & 0x80 == 0 {
+            return Ok((value, index + 1));
+        }
+    }
+    return Err(truncated());
+}
+
+/// X.690 8.1.3 definite form, short resp. long with the minimum number of octets
+fn encode_length(length: usize, buffer: &mut Vec<u8>) {
+    if length < 0x80 {
+        buffer.push(length as u8);
+        return;
+    }
+    let octets = length.to_be_bytes();
+    let start = octets.iter().position(|octet| *octet != 0).expect("length of long form");
+    buffer.push(0x80 | (octets.len() - start) as u8);
+    buffer.extend_from_slice(&octets[start..]);
+}
+
+/// X.690 9.1 - CER has the indefinite length form for all constructed encodings
+fn indefinite(element: &Element, rules: EncodingRules) -> bool {
+    return rules == EncodingRules::Cer && element.is_constructed();
+}
+
+/// length of the contents octets in definite form
+fn content_length(element: &Element, rules: EncodingRules) -> usize {
+    match &element.content {
+        Content::Primitive(value) => { return value.len(); },
+        Content::Constructed(elements) => { return elements.iter().map(|element| encoded_length(element, rules)).sum(); }
+    }
+}
+
+fn encoded_length(element: &Element, rules: EncodingRules) -> usize {
+    let mut header = vec![];
+    encode_identifier(element.tag, element.is_constructed(), &mut header);
+    if indefinite(element, rules) {
+        // NOTE: 0x80, the nested elements and the end-of-contents octets
+        return header.len() + 1 + content_length(element, rules) + 2;
+    }
+    let length = content_length(element, rules);
+    encode_length(length, &mut header);
+    return header.len() + length;
+}
+
+pub fn encode(element: &Element, rules: EncodingRules, buffer: &mut Vec<u8>) {
+    encode_identifier(element.tag, element.is_constructed(), buffer);
+    match &element.content {
+        Content::Primitive(value) => {
+            encode_length(value.len(), buffer);
+            buffer.extend_from_slice(value);
+        },
+        Content::Constructed(elements) => {
+            if indefinite(element, rules) {
+                buffer.push(INDEFINITE_LENGTH);
+            } else {
+                //TODO optimize - lengths of nested elements are computed again for each level
+                encode_length(content_length(element, rules), buffer);
+            }
+            for nested in elements {
+                encode(nested, rules, buffer);
+            }
+            if indefinite(element, rules) {
+                buffer.extend_from_slice(&[0, 0]);
+            }
+        }
+    }
+}
+
+/// returns tag, constructed flag and the number of octets
+fn decode_identifier(data: &[u8], rules: EncodingRules) -> Result<(Tag, bool, usize), Error> {
+    let first = *data.first().ok_or(truncated())?;
+    let class = match first >> 6 {
+        0 => Class::Universal,
+        1 => Class::Application,
+        2 => Class::ContextSpecific,
+        _ => Class::Private
+    };
+    let constructed = first & CONSTRUCTED != 0;
+    if first & HIGH_TAG_NUMBER != HIGH_TAG_NUMBER {
+        return Ok((Tag { class: class, number: (first & HIGH_TAG_NUMBER) as u32 }, constructed, 1));
+    }
+    let (number, length) = decode_base128(&data[1..])?;
+    if number > u32::MAX as u64 {
+        return Err(invalid("tag number too large"));
+    }
+    if number < HIGH_TAG_NUMBER as u64 && rules != EncodingRules::Ber {
+        return Err(invalid("tag number in high tag number form"));
+    }
+    return Ok((Tag { class: class, number: number as u32 }, constructed, 1 + length));
+}
+
+/// returns the length, none for the indefinite form, and the number of octets
+fn decode_length(data: &[u8], rules: EncodingRules) -> Result<(Option<usize>, usize), Error> {
+    let first = *data.first().ok_or(truncated())?;
+    if first < 0x80 {
+        return Ok((Some(first as usize), 1));
+    }
+    if first == INDEFINITE_LENGTH {
+        return Ok((None, 1));
+    }
+    if first == RESERVED_LENGTH {
+        return Err(invalid("reserved length octet"));
+    }
+    let octets = (first & 0x7f) as usize;
+    let value = data.get(1..1 + octets).ok_or(truncated())?;
+    if value[0] == 0 && rules != EncodingRules::Ber {
+        return Err(invalid("length not in the minimum number of octets"));
+    }
+    let mut length: usize = 0;
+    for octet in value {
+        if length >> (usize::BITS - 8) != 0 {
+            return Err(invalid("length too large"));
+        }
+        length = (length << 8) | *octet as usize;
+    }
+    if length < 0x80 && rules != EncodingRules::Ber {
+        return Err(invalid("long form for a length below 128"));
+    }
+    return Ok((Some(length), 1 + octets));
+}
+
+/// returns tag, constructed flag, length and the number of octets of identifier and length
+fn decode_header(data: &[u8], rules: EncodingRules) -> Result<(Tag, bool, Option<usize>, usize), Error> {
+    let (tag, constructed, identifier_length) = decode_identifier(data, rules)?;
+    let (length, length_length) = decode_length(&data[identifier_length..], rules)?;
+    match (constructed, length, rules) {
+        (false, None, _) => { return Err(invalid("indefinite length of a primitive encoding")); },
+        (true, None, EncodingRules::Der) => { return Err(invalid("indefinite length in DER")); },
+        (true, Some(_), EncodingRules::Cer) => { return Err(invalid("definite length of a constructed encoding in CER")); },
+        _ => {}
+    }
+    return Ok((tag, constructed, length, identifier_length + length_length));
+}
+
+/// one element from the start of the data, with the number of octets it took
+/// NOTE: an error of kind UnexpectedEof means the data ends within the element
+pub fn decode(data: &[u8], rules: EncodingRules) -> Result<(Element, usize), Error> {
+    return decode_nested(data, rules, 0);
+}
+
+fn decode_nested(data: &[u8], rules: EncodingRules, depth: usize) -> Result<(Element, usize), Error> {
+    if depth > MAXIMUM_DEPTH {
+        return Err(invalid("encoding nested too deeply"));
+    }
+    let (tag, constructed, length, header_length) = decode_header(data, rules)?;
+    let contents = &data[header_length..];
+    match length {
+        Some(length) => {
+            let contents = contents.get(..length).ok_or(truncated())?;
+            if !constructed {
+                return Ok((Element::primitive(tag, contents.to_vec()), header_length + length));
+            }
+            let mut elements = vec![];
+            let mut position = 0;
+            while position < contents.len() {
+                // NOTE: within the definite length, running out of data means the lengths do not fit together
+                let (element, element_length) = decode_nested(&contents[position..], rules, depth + 1).map_err(|e| {
+                    if e.kind() == ErrorKind::UnexpectedEof { invalid("nested encoding beyond the enclosing length") } else { e }
+                })?;
+                elements.push(element);
+                position += element_length;
+            }
+            return Ok((Element::constructed(tag, elements), header_length + length));
+        },
+        None => {
+            let mut elements = vec![];
+            let mut position = 0;
+            loop {
+                if contents.get(position..position + 2).ok_or(truncated())? == [0, 0] {
+                    return Ok((Element::constructed(tag, elements), header_length + position + 2));
+                }
+                let (element, element_length) = decode_nested(&contents[position..], rules, depth + 1)?;
+                if element.tag == Tag::END_OF_CONTENTS {
+                    return Err(invalid("end-of-contents with contents"));
+                }
+                elements.push(element);
+                position += element_length;
+            }
+        }
+    }
+}
+
+/// pushing stream decoder - takes the data as it arrives, e.g. in TSDUs resp. SSDUs, and gives out the elements once complete
+pub struct Decoder {
+    rules: EncodingRules,
+    buffer: Vec<u8>,
+    /// octets of one element at most
+    limit: usize,
+}
+
+impl Decoder {
+    pub fn new(rules: EncodingRules) -> Self {
+        return Decoder::with_limit(rules, MAXIMUM_LENGTH);
+    }
+
+    pub fn with_limit(rules: EncodingRules, limit: usize) -> Self {
+        return Decoder { rules: rules, buffer: vec![], limit: limit };
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// the next complete element, none if more data is needed
+    /// NOTE: an element longer than the limit is an error, the decoder is of no further use then
+    pub fn next_element(&mut self) -> Result<Option<Element>, Error> {
+        // NOTE: with definite length, no need to parse before all of it is there
+        match decode_header(&self.buffer, self.rules) {
+            Ok((_, _, Some(length), header_length)) if length > self.limit.saturating_sub(header_length) => { return Err(too_long()); },
+            Ok((_, _, Some(length), header_length)) if self.buffer.len() < header_length + length => { return Ok(None); },
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => { return Ok(None); },
+            Err(e) => { return Err(e); },
+            _ => {}
+        }
+        //TODO optimize - indefinite length encodings are parsed again from the start on each call until complete
+        match decode(&self.buffer, self.rules) {
+            Ok((element, length)) => {
+                self.buffer.drain(..length);
+                return Ok(Some(element));
+            },
+            // NOTE: an indefinite length encoding still incomplete with the limit reached will not get complete within it
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && self.buffer.len() >= self.limit => { return Err(too_long()); },
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => { return Ok(None); },
+            Err(e) => { return Err(e); }
+        }
+    }
+
+    /// data not yet decoded
+    pub fn pending(&self) -> usize {
+        return self.buffer.len();
+    }
+}
+
+/// identifier and length octets, read one at a time
+fn read_header<R: Read>(reader: &mut R, rules: EncodingRules) -> Result<(Tag, bool, Option<usize>), Error> {
+    let mut header = vec![];
+    loop {
+        let mut octet = [0u8; 1];
+        reader.read_exact(&mut octet)?;
+        header.push(octet[0]);
+        match decode_header(&header, rules) {
+            Ok((tag, constructed, length, _)) => { return Ok((tag, constructed, length)); },
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {},
+            Err(e) => { return Err(e); }
+        }
+    }
+}
+
+/// reads exactly one element from a stream, e.g. a TCP connection, without reading ahead
+pub fn read_element<R: Read>(reader: &mut R, rules: EncodingRules) -> Result<Element, Error> {
+    return read_element_with_limit(reader, rules, MAXIMUM_LENGTH);
+}
+
+/// like read_element(), with the contents octets of all nested elements together at most the limit
+pub fn read_element_with_limit<R: Read>(reader: &mut R, rules: EncodingRules, limit: usize) -> Result<Element, Error> {
+    let mut remaining = limit;
+    return read_nested(reader, rules, 0, &mut remaining);
+}
+
+fn read_nested<R: Read>(reader: &mut R, rules: EncodingRules, depth: usize, remaining: &mut usize) -> Result<Element, Error> {
+    if depth > MAXIMUM_DEPTH {
+        return Err(invalid("encoding nested too deeply"));
+    }
+    let (tag, constructed, length) = read_header(reader, rules)?;
+    match length {
+        Some(length) => {
+            if length > *remaining {
+                return Err(too_long());
+            }
+            *remaining -= length;
+            // NOTE: not allocated up front, the data has to arrive first
+            let mut contents = vec![];
+            reader.by_ref().take(length as u64).read_to_end(&mut contents)?;
+            if contents.len() < length {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "stream ends within the encoding"));
+            }
+            if !constructed {
+                return Ok(Element::primitive(tag, contents));
+            }
+            let mut elements = vec![];
+            let mut position = 0;
+            while position < contents.len() {
+                let (element, element_length) = decode_nested(&contents[position..], rules, depth + 1)?;
+                elements.push(element);
+                position += element_length;
+            }
+            return Ok(Element::constructed(tag, elements));
+        },
+        None => {
+            let mut elements = vec![];
+            loop {
+                let element = read_nested(reader, rules, depth + 1, remaining)?;
+                if element.tag == Tag::END_OF_CONTENTS {
+                    if element.is_constructed() || !element.value()?.is_empty() {
+                        return Err(invalid("end-of-contents with contents"));
+                    }
+                    return Ok(Element::constructed(tag, elements));
+                }
+                elements.push(element);
+            }
+        }
+    }
+}
+
+/// streaming encoder into any Write, for values too large to be built up as Element first
+/// NOTE: constructed encodings opened with start() have the indefinite length form, so this is for BER and CER, not DER
+pub struct Writer<W: Write> {
+    writer: W,
+    rules: EncodingRules,
+    /// constructed encodings started and not yet ended
+    open: usize,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(writer: W, rules: EncodingRules) -> Result<Self, Error> {
+        if rules == EncodingRules::Der {
+            return Err(Error::new(ErrorKind::InvalidInput, "DER has no indefinite length form"));
+        }
+        return Ok(Writer { writer: writer, rules: rules, open: 0 });
+    }
+
+    /// starts a constructed encoding, the following ones are nested in it until end()
+    pub fn start(&mut self, tag: Tag) -> Result<(), Error> {
+        let mut header = vec![];
+        encode_identifier(tag, true, &mut header);
+        header.push(INDEFINITE_LENGTH);
+        self.writer.write_all(&header)?;
+        self.open += 1;
+        return Ok(());
+    }
+
+    pub fn end(&mut self) -> Result<(), Error> {
+        if self.open == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "no constructed encoding started"));
+        }
+        self.writer.write_all(&[0, 0])?;
+        self.open -= 1;
+        return Ok(());
+    }
+
+    pub fn primitive(&mut self, tag: Tag, value: &[u8]) -> Result<(), Error> {
+        let mut header = vec![];
+        encode_identifier(tag, false, &mut header);
+        encode_length(value.len(), &mut header);
+        self.writer.write_all(&header)?;
+        self.writer.write_all(value)?;
+        return Ok(());
+    }
+
+    pub fn element(&mut self, element: &Element) -> Result<(), Error> {
+        let mut buffer = vec![];
+        encode(element, self.rules, &mut buffer);
+        self.writer.write_all(&buffer)?;
+        return Ok(());
+    }
+
+    /// all constructed encodings have to be ended - returns the underlying writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.open != 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "constructed encoding not ended"));
+        }
+        self.writer.flush()?;
+        return Ok(self.writer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const ALL: [EncodingRules; 3] = [EncodingRules::Ber, EncodingRules::Cer, EncodingRules::Der];
+
+    fn nested() -> Element {
+        return Element::constructed(Tag::SEQUENCE, vec![
+            Element::primitive(Tag::INTEGER, vec![0x01]),
+            Element::constructed(Tag::context(31), vec![Element::primitive(Tag::OCTET_STRING, vec![0xaa; 200])]),
+            Element::primitive(Tag::NULL, vec![]),
+        ]);
+    }
+
+    #[test]
+    fn length_forms() {
+        for (length, octets) in [(0, vec![0x00]), (0x7f, vec![0x7f]), (0x80, vec![0x81, 0x80]), (0x100, vec![0x82, 0x01, 0x00])] {
+            let mut buffer = vec![];
+            encode_length(length, &mut buffer);
+            assert_eq!(buffer, octets);
+            for rules in ALL {
+                assert_eq!(decode_length(&buffer, rules).unwrap(), (Some(length), octets.len()));
+            }
+        }
+        // X.690 10.1 - not in the minimum number of octets, only BER takes it
+        for octets in [&[0x81, 0x05][..], &[0x82, 0x00, 0x80][..]] {
+            assert!(decode_length(octets, EncodingRules::Ber).is_ok());
+            assert_eq!(decode_length(octets, EncodingRules::Der).unwrap_err().kind(), ErrorKind::InvalidData);
+            assert!(decode_length(octets, EncodingRules::Cer).is_err());
+        }
+        assert!(decode_length(&[RESERVED_LENGTH], EncodingRules::Ber).is_err());
+        assert_eq!(decode_length(&[0x82, 0x01], EncodingRules::Ber).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn identifiers() {
+        let mut buffer = vec![];
+        encode_identifier(Tag::context(30), true, &mut buffer);
+        encode_identifier(Tag::application(31), false, &mut buffer);
+        encode_identifier(Tag::private(201), false, &mut buffer);
+        assert_eq!(buffer, [0xbe, 0x5f, 0x1f, 0xdf, 0x81, 0x49]);
+        assert_eq!(decode_identifier(&buffer[1..], EncodingRules::Der).unwrap(), (Tag::application(31), false, 2));
+        assert_eq!(decode_identifier(&buffer[3..], EncodingRules::Der).unwrap(), (Tag::private(201), false, 3));
+        // low tag number in the high tag number form, resp. with leading zero bits
+        assert!(decode_identifier(&[0x1f, 0x05], EncodingRules::Ber).is_ok());
+        assert!(decode_identifier(&[0x1f, 0x05], EncodingRules::Der).is_err());
+        assert!(decode_identifier(&[0x1f, 0x80, 0x21], EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        for rules in ALL {
+            let encoding = nested().encode(rules);
+            assert_eq!(encoded_length(&nested(), rules), encoding.len());
+            assert_eq!(Element::decode(&encoding, rules).unwrap(), nested());
+            // what CER and DER encode is valid BER
+            assert_eq!(Element::decode(&encoding, EncodingRules::Ber).unwrap(), nested());
+        }
+    }
+
+    #[test]
+    fn indefinite_length() {
+        let encoding = nested().encode(EncodingRules::Cer);
+        assert_eq!(&encoding[..2], [0x30, INDEFINITE_LENGTH]);
+        assert_eq!(&encoding[encoding.len() - 2..], [0, 0]);
+        // DER has definite lengths only, CER indefinite ones for constructed encodings
+        assert!(Element::decode(&encoding, EncodingRules::Der).is_err());
+        assert!(Element::decode(&nested().encode(EncodingRules::Der), EncodingRules::Cer).is_err());
+        // without the end-of-contents octets
+        assert_eq!(decode(&encoding[..encoding.len() - 2], EncodingRules::Cer).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        // primitive encoding with indefinite length
+        assert!(decode(&[0x04, 0x80, 0x00, 0x00], EncodingRules::Ber).is_err());
+        // end-of-contents with contents
+        assert!(decode(&[0x30, 0x80, 0x00, 0x01, 0xff, 0x00, 0x00], EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn invalid_encodings() {
+        // nested element beyond the enclosing length
+        assert_eq!(decode(&[0x30, 0x03, 0x04, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00], EncodingRules::Ber).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(decode(&[0x04, 0x05, 0x00], EncodingRules::Ber).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert!(Element::decode(&[0x05, 0x00, 0x05], EncodingRules::Ber).is_err());
+        // nested too deeply
+        let mut encoding = vec![];
+        for _ in 0..=MAXIMUM_DEPTH + 1 {
+            encoding.extend_from_slice(&[0x30, 0x80]);
+        }
+        assert_eq!(decode(&encoding, EncodingRules::Ber).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn decoder() {
+        let mut encoding = nested().encode(EncodingRules::Cer);
+        encoding.extend(Element::primitive(Tag::BOOLEAN, vec![0xff]).encode(EncodingRules::Cer));
+        let mut decoder = Decoder::new(EncodingRules::Cer);
+        let mut elements = vec![];
+        // one octet at a time
+        for octet in encoding {
+            decoder.push(&[octet]);
+            while let Some(element) = decoder.next_element().unwrap() {
+                elements.push(element);
+            }
+        }
+        assert_eq!(elements, [nested(), Element::primitive(Tag::BOOLEAN, vec![0xff])]);
+        assert_eq!(decoder.pending(), 0);
+        let mut decoder = Decoder::new(EncodingRules::Der);
+        decoder.push(&[0x30, 0x80]);
+        assert!(decoder.next_element().is_err());
+    }
+
+    #[test]
+    fn decoder_limit() {
+        // the length says more than the limit, no need to wait for the data
+        let mut decoder = Decoder::with_limit(EncodingRules::Ber, 100);
+        decoder.push(&[0x04, 0x81, 0xc8]);
+        assert_eq!(decoder.next_element().unwrap_err().kind(), ErrorKind::InvalidData);
+        let mut decoder = Decoder::with_limit(EncodingRules::Ber, 100);
+        decoder.push(&[0x04, 0x62]);
+        decoder.push(&[0x00; 0x62]);
+        assert!(decoder.next_element().unwrap().is_some());
+        // indefinite length growing beyond the limit
+        let mut decoder = Decoder::with_limit(EncodingRules::Ber, 100);
+        decoder.push(&[0x30, 0x80]);
+        assert!(decoder.next_element().unwrap().is_none());
+        for _ in 0..50 {
+            decoder.push(&[0x05, 0x00]);
+        }
+        assert_eq!(decoder.next_element().unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn read_from_stream() {
+        for rules in ALL {
+            let mut encoding = nested().encode(rules);
+            encoding.extend_from_slice(&[0x05, 0x00]);
+            let mut reader = Cursor::new(encoding);
+            assert_eq!(read_element(&mut reader, rules).unwrap(), nested());
+            // nothing read ahead
+            assert_eq!(read_element(&mut reader, rules).unwrap(), Element::primitive(Tag::NULL, vec![]));
+            assert_eq!(read_element(&mut reader, rules).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        }
+        // a length from the peer beyond the limit, resp. beyond the end of the stream
+        let mut reader = Cursor::new(vec![0x04, 0x84, 0x7f, 0xff, 0xff, 0xff, 0x00]);
+        assert_eq!(read_element(&mut reader, EncodingRules::Ber).unwrap_err().kind(), ErrorKind::InvalidData);
+        let mut reader = Cursor::new(vec![0x04, 0x83, 0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(read_element(&mut reader, EncodingRules::Ber).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        // the limit is for all nested elements together
+        let encoding = nested().encode(EncodingRules::Cer);
+        assert!(read_element_with_limit(&mut Cursor::new(&encoding), EncodingRules::Cer, 201).is_ok());
+        assert!(read_element_with_limit(&mut Cursor::new(&encoding), EncodingRules::Cer, 200).is_err());
+    }
+
+    #[test]
+    fn writer() {
+        assert!(Writer::new(vec![], EncodingRules::Der).is_err());
+        for rules in [EncodingRules::Ber, EncodingRules::Cer] {
+            let mut writer = Writer::new(vec![], rules).unwrap();
+            writer.start(Tag::SEQUENCE).unwrap();
+            writer.primitive(Tag::INTEGER, &[0x01]).unwrap();
+            writer.element(&Element::constructed(Tag::context(31), vec![Element::primitive(Tag::OCTET_STRING, vec![0xaa; 200])])).unwrap();
+            writer.primitive(Tag::NULL, &[]).unwrap();
+            writer.end().unwrap();
+            let encoding = writer.finish().unwrap();
+            assert_eq!(Element::decode(&encoding, rules).unwrap(), nested());
+        }
+        let mut writer = Writer::new(vec![], EncodingRules::Ber).unwrap();
+        assert!(writer.end().is_err());
+        writer.start(Tag::SET).unwrap();
+        assert!(writer.finish().is_err());
+    }
+}
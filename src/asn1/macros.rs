@@ -0,0 +1,409 @@
+// macro_rules mapping of Rust structs and enums to ASN.1 type definitions, close to the ASN.1 notation:
+//
+//     asn1_sequence! {
+//         #[derive(Clone, Debug, PartialEq)]
+//         pub struct Example {
+//             pub number: i64,
+//             [CONTEXT 0] IMPLICIT pub name: Option<OctetString>,
+//             [APPLICATION 1] EXPLICIT pub choice: SomeChoice,
+//             ...
+//         }
+//     }
+//
+// OPTIONAL components are Option<T>, the tag class is one of UNIVERSAL, APPLICATION, CONTEXT and PRIVATE.
+// "..." marks a SEQUENCE resp. SET as extensible - unknown components are skipped when decoding.
+//TODO DEFAULT values, COMPONENTS OF
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! asn1_tag {
+    (UNIVERSAL $number:literal) => { $crate::asn1::Tag::universal($number) };
+    (APPLICATION $number:literal) => { $crate::asn1::Tag::application($number) };
+    (CONTEXT $number:literal) => { $crate::asn1::Tag::context($number) };
+    (PRIVATE $number:literal) => { $crate::asn1::Tag::private($number) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! asn1_tagging {
+    () => { None };
+    ($class:ident $number:literal IMPLICIT) => { Some($crate::asn1::Tagging::Implicit($crate::asn1_tag!($class $number))) };
+    ($class:ident $number:literal EXPLICIT) => { Some($crate::asn1::Tagging::Explicit($crate::asn1_tag!($class $number))) };
+}
+
+/// SEQUENCE resp. SET as a struct, see above
+#[doc(hidden)]
+#[macro_export]
+macro_rules! asn1_constructed {
+    (
+        $kind:ident $extensible:literal;
+        $(#[$meta:meta])*
+        $name:ident;
+        $( [$(#[$field_meta:meta])*] [$($class:ident $number:literal $mode:ident)?] $field:ident : $type:ty; )*
+    ) => {
+        $(#[$meta])*
+        pub struct $name {
+            $( $(#[$field_meta])* pub $field: $type, )*
+        }
+
+        impl $crate::asn1::Asn1 for $name {
+            const TAG: Option<$crate::asn1::Tag> = Some($crate::asn1_constructed!(@tag $kind));
+
+            fn to_element(&self, rules: $crate::asn1::EncodingRules) -> Result<$crate::asn1::Element, std::io::Error> {
+                #[allow(unused_mut)]
+                let mut elements: Vec<$crate::asn1::Element> = Vec::new();
+                $(
+                    if $crate::asn1::Asn1::is_present(&self.$field) {
+                        elements.push($crate::asn1::encode_component(&self.$field, $crate::asn1_tagging!($($class $number $mode)?), rules)?);
+                    }
+                )*
+                $crate::asn1_constructed!(@order $kind elements rules);
+                return Ok($crate::asn1::Element::constructed($crate::asn1_constructed!(@tag $kind), elements));
+            }
+
+            #[allow(unused_variables)]
+            fn from_element(element: &$crate::asn1::Element, rules: $crate::asn1::EncodingRules) -> Result<Self, std::io::Error> {
+                #[allow(unused_mut)]
+                let mut components = $crate::asn1::Components::new(element.elements()?);
+                let value = $name {
+                    $( $field: $crate::asn1_constructed!(@component $kind components $($class $number $mode)?; rules)?, )*
+                };
+                components.finish($extensible)?;
+                return Ok(value);
+            }
+        }
+    };
+    (@tag SEQUENCE) => { $crate::asn1::Tag::SEQUENCE };
+    (@tag SET) => { $crate::asn1::Tag::SET };
+    // X.690 10.3 - in CER and DER, the components of a SET in the order of their tags
+    (@order SEQUENCE $elements:ident $rules:ident) => {};
+    (@order SET $elements:ident $rules:ident) => {
+        if $rules != $crate::asn1::EncodingRules::Ber {
+            $elements.sort_by_key(|element| element.tag);
+        }
+    };
+    (@component SEQUENCE $components:ident $($class:ident $number:literal $mode:ident)?; $rules:ident) => {
+        $components.next($crate::asn1_tagging!($($class $number $mode)?), $rules)
+    };
+    (@component SET $components:ident $($class:ident $number:literal $mode:ident)?; $rules:ident) => {
+        $components.take($crate::asn1_tagging!($($class $number $mode)?), $rules)
+    };
+}
+
+/// SEQUENCE as a struct with pub fields, tagged resp. OPTIONAL components as described at the top of asn1/macros.rs
+#[macro_export]
+macro_rules! asn1_sequence {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $([$class:ident $number:literal] $mode:ident)?
+                pub $field:ident : $type:ty,
+            )*
+            ... $(,)?
+            $(
+                $(#[$addition_meta:meta])*
+                $([$addition_class:ident $addition_number:literal] $addition_mode:ident)?
+                pub $addition:ident : $addition_type:ty,
+            )*
+        }
+    ) => {
+        $crate::asn1_constructed! {
+            SEQUENCE true;
+            $(#[$meta])*
+            $name;
+            $( [$(#[$field_meta])*] [$($class $number $mode)?] $field: $type; )*
+            $( [$(#[$addition_meta])*] [$($addition_class $addition_number $addition_mode)?] $addition: $addition_type; )*
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $([$class:ident $number:literal] $mode:ident)?
+                pub $field:ident : $type:ty,
+            )*
+        }
+    ) => {
+        $crate::asn1_constructed! {
+            SEQUENCE false;
+            $(#[$meta])*
+            $name;
+            $( [$(#[$field_meta])*] [$($class $number $mode)?] $field: $type; )*
+        }
+    };
+}
+
+/// SET as a struct with pub fields, like asn1_sequence
+#[macro_export]
+macro_rules! asn1_set {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $([$class:ident $number:literal] $mode:ident)?
+                pub $field:ident : $type:ty,
+            )*
+            ... $(,)?
+            $(
+                $(#[$addition_meta:meta])*
+                $([$addition_class:ident $addition_number:literal] $addition_mode:ident)?
+                pub $addition:ident : $addition_type:ty,
+            )*
+        }
+    ) => {
+        $crate::asn1_constructed! {
+            SET true;
+            $(#[$meta])*
+            $name;
+            $( [$(#[$field_meta])*] [$($class $number $mode)?] $field: $type; )*
+            $( [$(#[$addition_meta])*] [$($addition_class $addition_number $addition_mode)?] $addition: $addition_type; )*
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $([$class:ident $number:literal] $mode:ident)?
+                pub $field:ident : $type:ty,
+            )*
+        }
+    ) => {
+        $crate::asn1_constructed! {
+            SET false;
+            $(#[$meta])*
+            $name;
+            $( [$(#[$field_meta])*] [$($class $number $mode)?] $field: $type; )*
+        }
+    };
+}
+
+/// CHOICE as an enum with one field per variant, alternatives tagged like the components of asn1_sequence
+/// NOTE: an alternative whose tag is not known is a decoding error, also with extensibility - there is no variant to put it in
+#[macro_export]
+macro_rules! asn1_choice {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $([$class:ident $number:literal] $mode:ident)?
+                $variant:ident ( $type:ty ),
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        pub enum $name {
+            $( $(#[$variant_meta])* $variant($type), )*
+        }
+
+        impl $crate::asn1::Asn1 for $name {
+            const TAG: Option<$crate::asn1::Tag> = None;
+
+            fn to_element(&self, rules: $crate::asn1::EncodingRules) -> Result<$crate::asn1::Element, std::io::Error> {
+                match self {
+                    $( $name::$variant(value) => { return $crate::asn1::encode_component(value, $crate::asn1_tagging!($($class $number $mode)?), rules); }, )*
+                }
+            }
+
+            fn from_element(element: &$crate::asn1::Element, rules: $crate::asn1::EncodingRules) -> Result<Self, std::io::Error> {
+                $(
+                    if $crate::asn1::matches_component::<$type>(element.tag, $crate::asn1_tagging!($($class $number $mode)?)) {
+                        return Ok($name::$variant($crate::asn1::decode_component(element, $crate::asn1_tagging!($($class $number $mode)?), rules)?));
+                    }
+                )*
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, concat!("unknown alternative of ", stringify!($name))));
+            }
+
+            fn matches(tag: $crate::asn1::Tag) -> bool {
+                return $( $crate::asn1::matches_component::<$type>(tag, $crate::asn1_tagging!($($class $number $mode)?)) )||*;
+            }
+        }
+    };
+}
+
+/// ENUMERATED as a fieldless enum with the values of the enumeration
+#[macro_export]
+macro_rules! asn1_enumerated {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident = $value:literal,
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        pub enum $name {
+            $( $(#[$variant_meta])* $variant = $value, )*
+        }
+
+        impl $crate::asn1::Asn1 for $name {
+            const TAG: Option<$crate::asn1::Tag> = Some($crate::asn1::Tag::ENUMERATED);
+
+            fn to_element(&self, rules: $crate::asn1::EncodingRules) -> Result<$crate::asn1::Element, std::io::Error> {
+                let value: i64 = match self {
+                    $( $name::$variant => $value, )*
+                };
+                return Ok($crate::asn1::Asn1::to_element(&value, rules)?.implicit($crate::asn1::Tag::ENUMERATED));
+            }
+
+            fn from_element(element: &$crate::asn1::Element, rules: $crate::asn1::EncodingRules) -> Result<Self, std::io::Error> {
+                match <i64 as $crate::asn1::Asn1>::from_element(element, rules)? {
+                    $( $value => { return Ok($name::$variant); }, )*
+                    _ => { return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, concat!("unknown value of ", stringify!($name)))); }
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asn1::{Asn1, Element, EncodingRules, Null, OctetString, Tag};
+    use std::io::ErrorKind;
+
+    const ALL: [EncodingRules; 3] = [EncodingRules::Ber, EncodingRules::Cer, EncodingRules::Der];
+
+    asn1_enumerated! {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub enum Colour {
+            Red = 0,
+            Green = 1,
+            Blue = 7,
+        }
+    }
+
+    asn1_choice! {
+        #[derive(Clone, Debug, PartialEq)]
+        pub enum Value {
+            Number(i64),
+            Text(String),
+            [CONTEXT 0] IMPLICIT Nothing(Null),
+            [CONTEXT 1] EXPLICIT Colour(Colour),
+        }
+    }
+
+    asn1_sequence! {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Record {
+            pub number: i64,
+            [CONTEXT 0] IMPLICIT pub name: Option<OctetString>,
+            [APPLICATION 1] EXPLICIT pub value: Value,
+            pub colour: Option<Colour>,
+            // untagged CHOICE tagged implicitly, which is explicit tagging
+            [CONTEXT 2] IMPLICIT pub other: Option<Value>,
+        }
+    }
+
+    asn1_sequence! {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Extensible {
+            pub number: i64,
+            ...,
+            [CONTEXT 5] IMPLICIT pub addition: Option<bool>,
+        }
+    }
+
+    asn1_set! {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Unordered {
+            [CONTEXT 2] IMPLICIT pub second: i64,
+            pub flag: bool,
+            [CONTEXT 1] IMPLICIT pub first: Option<i64>,
+        }
+    }
+
+    fn record() -> Record {
+        return Record {
+            number: 42,
+            name: Some(OctetString(b"name".to_vec())),
+            value: Value::Colour(Colour::Blue),
+            colour: None,
+            other: Some(Value::Text("other".to_string())),
+        };
+    }
+
+    #[test]
+    fn sequence() {
+        for rules in ALL {
+            assert_eq!(Record::decode(&record().encode(rules).unwrap(), rules).unwrap(), record());
+        }
+        let element = record().to_element(EncodingRules::Der).unwrap();
+        let tags: Vec<Tag> = element.elements().unwrap().iter().map(|component| component.tag).collect();
+        assert_eq!(tags, [Tag::INTEGER, Tag::context(0), Tag::application(1), Tag::context(2)]);
+        // explicit tagging, also for the implicitly tagged CHOICE
+        assert_eq!(element.elements().unwrap()[2].inner().unwrap().tag, Tag::context(1));
+        assert_eq!(element.elements().unwrap()[3].inner().unwrap().tag, Tag::UTF8_STRING);
+        // OPTIONAL components left out
+        let minimal = Record { number: -1, name: None, value: Value::Nothing(Null), colour: Some(Colour::Red), other: None };
+        assert_eq!(minimal.to_element(EncodingRules::Der).unwrap().elements().unwrap().len(), 3);
+        assert_eq!(Record::decode(&minimal.encode(EncodingRules::Ber).unwrap(), EncodingRules::Ber).unwrap(), minimal);
+    }
+
+    #[test]
+    fn sequence_errors() {
+        // mandatory component missing
+        let element = Element::constructed(Tag::SEQUENCE, vec![Element::primitive(Tag::INTEGER, vec![1])]);
+        assert_eq!(Record::from_element(&element, EncodingRules::Ber).unwrap_err().kind(), ErrorKind::InvalidData);
+        // components out of order resp. unknown
+        let mut elements = record().to_element(EncodingRules::Ber).unwrap().elements().unwrap().to_vec();
+        elements.swap(0, 1);
+        assert!(Record::from_element(&Element::constructed(Tag::SEQUENCE, elements), EncodingRules::Ber).is_err());
+        let mut elements = record().to_element(EncodingRules::Ber).unwrap().elements().unwrap().to_vec();
+        elements.push(Element::primitive(Tag::context(9), vec![]));
+        assert!(Record::from_element(&Element::constructed(Tag::SEQUENCE, elements), EncodingRules::Ber).is_err());
+        // unknown alternative, unknown enumeration value
+        assert!(Value::decode(&[0x01, 0x01, 0xff], EncodingRules::Ber).is_err());
+        assert!(Colour::decode(&[0x0a, 0x01, 0x02], EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn extensibility() {
+        let value = Extensible { number: 1, addition: Some(true) };
+        for rules in ALL {
+            assert_eq!(Extensible::decode(&value.encode(rules).unwrap(), rules).unwrap(), value);
+        }
+        // X.680 52 - unknown components of an extensible type are skipped
+        let element = Element::constructed(Tag::SEQUENCE, vec![
+            Element::primitive(Tag::INTEGER, vec![1]),
+            Element::primitive(Tag::context(6), vec![0x00]),
+        ]);
+        assert_eq!(Extensible::from_element(&element, EncodingRules::Ber).unwrap(), Extensible { number: 1, addition: None });
+    }
+
+    #[test]
+    fn set() {
+        let value = Unordered { second: 2, flag: true, first: Some(1) };
+        // X.690 10.3 - canonical order of the tags in CER and DER, as declared in BER
+        let tags = |rules| -> Vec<Tag> {
+            return value.to_element(rules).unwrap().elements().unwrap().iter().map(|component| component.tag).collect();
+        };
+        assert_eq!(tags(EncodingRules::Der), [Tag::BOOLEAN, Tag::context(1), Tag::context(2)]);
+        assert_eq!(tags(EncodingRules::Cer), [Tag::BOOLEAN, Tag::context(1), Tag::context(2)]);
+        assert_eq!(tags(EncodingRules::Ber), [Tag::context(2), Tag::BOOLEAN, Tag::context(1)]);
+        for rules in ALL {
+            assert_eq!(Unordered::decode(&value.encode(rules).unwrap(), rules).unwrap(), value);
+        }
+        // in any order when decoding
+        let element = Element::constructed(Tag::SET, vec![Element::primitive(Tag::context(2), vec![5]), Element::primitive(Tag::BOOLEAN, vec![0])]);
+        assert_eq!(Unordered::from_element(&element, EncodingRules::Ber).unwrap(), Unordered { second: 5, flag: false, first: None });
+    }
+
+    #[test]
+    fn choice_and_enumerated() {
+        for value in [Value::Number(-3), Value::Text("x".to_string()), Value::Nothing(Null), Value::Colour(Colour::Green)] {
+            for rules in ALL {
+                assert_eq!(Value::decode(&value.encode(rules).unwrap(), rules).unwrap(), value);
+            }
+        }
+        assert_eq!(Value::Nothing(Null).encode(EncodingRules::Der).unwrap(), [0x80, 0x00]);
+        assert_eq!(Colour::Blue.encode(EncodingRules::Der).unwrap(), [0x0a, 0x01, 0x07]);
+        assert!(Value::matches(Tag::INTEGER) && Value::matches(Tag::context(1)) && !Value::matches(Tag::context(2)));
+    }
+}
@@ -0,0 +1,979 @@
+use std::{fmt, io::{Error, ErrorKind}, str::FromStr};
+
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+
+use super::{ber, Asn1, Element, EncodingRules, Tag};
+
+fn invalid(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, message.to_string());
+}
+
+/// X.690 9.2 - CER puts strings longer than this into a constructed encoding of segments this long
+const CER_SEGMENT_LENGTH: usize = 1000;
+
+/// X.690 8.7 - primitive, or constructed of OCTET STRING segments in CER
+fn string_element(tag: Tag, octets: &[u8], rules: EncodingRules) -> Element {
+    if rules == EncodingRules::Cer && octets.len() > CER_SEGMENT_LENGTH {
+        let segments = octets.chunks(CER_SEGMENT_LENGTH).map(|segment| Element::primitive(Tag::OCTET_STRING, segment.to_vec())).collect();
+        return Element::constructed(tag, segments);
+    }
+    return Element::primitive(tag, octets.to_vec());
+}
+
+// BOOLEAN
+
+impl Asn1 for bool {
+    const TAG: Option<Tag> = Some(Tag::BOOLEAN);
+
+    /// X.690 11.1 - TRUE is all bits set in CER and DER, also used for BER
+    fn to_element(&self, _rules: EncodingRules) -> Result<Element, Error> {
+        return Ok(Element::primitive(Tag::BOOLEAN, vec![if *self { 0xff } else { 0x00 }]));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        match (element.value()?, rules) {
+            ([0x00], _) => { return Ok(false); },
+            ([0xff], _) => { return Ok(true); },
+            ([_], EncodingRules::Ber) => { return Ok(true); },
+            _ => { return Err(invalid("invalid BOOLEAN")); }
+        }
+    }
+}
+
+// INTEGER
+
+/// X.690 8.3 - two's complement in the minimum number of octets
+pub(super) fn encode_integer(value: i128) -> Vec<u8> {
+    let octets = value.to_be_bytes();
+    let mut start = 0;
+    while start < octets.len() - 1
+        && ((octets[start] == 0x00 && octets[start + 1] & 0x80 == 0) || (octets[start] == 0xff && octets[start + 1] & 0x80 != 0)) {
+        start += 1;
+    }
+    return octets[start..].to_vec();
+}
+
+/// X.690 8.3.2 - the first nine bits are not all the same
+pub(super) fn decode_integer(value: &[u8]) -> Result<i128, Error> {
+    if value.is_empty() {
+        return Err(invalid("INTEGER without contents"));
+    }
+    if value.len() > 1 && ((value[0] == 0x00 && value[1] & 0x80 == 0) || (value[0] == 0xff && value[1] & 0x80 != 0)) {
+        return Err(invalid("INTEGER not in the minimum number of octets"));
+    }
+    if value.len() > 16 {
+        return Err(invalid("INTEGER too large"));
+    }
+    let mut result: i128 = if value[0] & 0x80 != 0 { -1 } else { 0 };
+    for octet in value {
+        result = (result << 8) | *octet as i128;
+    }
+    return Ok(result);
+}
+
+macro_rules! integer {
+    ($($type:ty),*) => {
+        $(
+            impl Asn1 for $type {
+                const TAG: Option<Tag> = Some(Tag::INTEGER);
+
+                fn to_element(&self, _rules: EncodingRules) -> Result<Element, Error> {
+                    return Ok(Element::primitive(Tag::INTEGER, encode_integer(*self as i128)));
+                }
+
+                fn from_element(element: &Element, _rules: EncodingRules) -> Result<Self, Error> {
+                    return <$type>::try_from(decode_integer(element.value()?)?).map_err(|_| invalid("INTEGER out of range"));
+                }
+            }
+        )*
+    };
+}
+
+//TODO INTEGER beyond 64 bits, e.g. for certificate serial numbers
+integer!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+// ENUMERATED - see asn1_enumerated
+
+// NULL
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Null;
+
+impl Asn1 for Null {
+    const TAG: Option<Tag> = Some(Tag::NULL);
+
+    fn to_element(&self, _rules: EncodingRules) -> Result<Element, Error> {
+        return Ok(Element::primitive(Tag::NULL, vec![]));
+    }
+
+    fn from_element(element: &Element, _rules: EncodingRules) -> Result<Self, Error> {
+        if !element.value()?.is_empty() {
+            return Err(invalid("NULL with contents"));
+        }
+        return Ok(Null);
+    }
+}
+
+// OCTET STRING
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct OctetString(pub Vec<u8>);
+
+impl Asn1 for OctetString {
+    const TAG: Option<Tag> = Some(Tag::OCTET_STRING);
+
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        return Ok(string_element(Tag::OCTET_STRING, &self.0, rules));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        return Ok(OctetString(element.octets(rules)?));
+    }
+}
+
+// BIT STRING
+
+/// bits in the order of X.690 8.6.2.1 - bit 0 is the most significant bit of the first octet
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BitString {
+    pub data: Vec<u8>,
+    /// in the last octet, 0..7
+    pub unused_bits: u8,
+}
+
+impl BitString {
+    /// NOTE: trailing zero bits are left out, as DER wants for named bit lists, X.690 11.2.2
+    pub fn from_bits(bits: &[bool]) -> Self {
+        let length = bits.iter().rposition(|bit| *bit).map(|position| position + 1).unwrap_or(0);
+        let mut data = vec![0u8; length.div_ceil(8)];
+        for (index, bit) in bits[..length].iter().enumerate() {
+            if *bit {
+                data[index / 8] |= 0x80 >> (index % 8);
+            }
+        }
+        return BitString { data: data, unused_bits: ((8 - length % 8) % 8) as u8 };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.data.len() * 8 - self.unused_bits as usize;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// bits beyond the end are zero, as for named bit lists
+    pub fn bit(&self, index: usize) -> bool {
+        return index < self.len() && self.data[index / 8] & (0x80 >> (index % 8)) != 0;
+    }
+}
+
+impl Asn1 for BitString {
+    const TAG: Option<Tag> = Some(Tag::BIT_STRING);
+
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        // X.690 11.2.1 - unused bits are zero in CER and DER
+        let mut data = self.data.clone();
+        if let Some(last) = data.last_mut() {
+            *last &= 0xff << self.unused_bits;
+        }
+        // X.690 8.6.4 - each segment has its initial octet, only the last one may have unused bits
+        if rules == EncodingRules::Cer && data.len() + 1 > CER_SEGMENT_LENGTH {
+            let chunks: Vec<&[u8]> = data.chunks(CER_SEGMENT_LENGTH - 1).collect();
+            let segments = chunks.iter().enumerate().map(|(index, chunk)| {
+                let mut value = vec![if index == chunks.len() - 1 { self.unused_bits } else { 0 }];
+                value.extend_from_slice(chunk);
+                return Element::primitive(Tag::BIT_STRING, value);
+            }).collect();
+            return Ok(Element::constructed(Tag::BIT_STRING, segments));
+        }
+        let mut value = vec![self.unused_bits];
+        value.extend(data);
+        return Ok(Element::primitive(Tag::BIT_STRING, value));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        let segments = match element.elements() {
+            Ok(_) if rules == EncodingRules::Der => { return Err(invalid("constructed BIT STRING in DER")); },
+            Ok(segments) => segments.to_vec(),
+            Err(_) => vec![element.clone()]
+        };
+        let mut result = BitString::default();
+        for (index, segment) in segments.iter().enumerate() {
+            if segment.is_constructed() {
+                //TODO nested constructed segments
+                return Err(invalid("nested constructed BIT STRING"));
+            }
+            let value = segment.value()?;
+            let unused_bits = *value.first().ok_or(invalid("BIT STRING without initial octet"))?;
+            if unused_bits > 7 || (value.len() == 1 && unused_bits != 0) || (unused_bits != 0 && index != segments.len() - 1) {
+                return Err(invalid("invalid number of unused bits"));
+            }
+            if rules != EncodingRules::Ber && value.len() > 1 && value[value.len() - 1] & !(0xff << unused_bits) != 0 {
+                return Err(invalid("unused bits not zero"));
+            }
+            result.data.extend_from_slice(&value[1..]);
+            result.unused_bits = unused_bits;
+        }
+        return Ok(result);
+    }
+}
+
+// OBJECT IDENTIFIER
+
+/// X.660 object identifier, as its arcs
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectIdentifier(Vec<u32>);
+
+impl ObjectIdentifier {
+    /// X.660 - at least two arcs, the first 0, 1 or 2, the second below 40 for the first two
+    pub fn new(arcs: &[u32]) -> Result<Self, Error> {
+        if arcs.len() < 2 || arcs[0] > 2 || (arcs[0] < 2 && arcs[1] > 39) {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid object identifier arcs"));
+        }
+        return Ok(ObjectIdentifier(arcs.to_vec()));
+    }
+
+    pub fn arcs(&self) -> &[u32] {
+        return &self.0;
+    }
+}
+
+impl fmt::Display for ObjectIdentifier {
+    /// dotted form, e.g. 2.1.1 for BER
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arcs: Vec<String> = self.0.iter().map(|arc| arc.to_string()).collect();
+        return write!(f, "{}", arcs.join("."));
+    }
+}
+
+impl FromStr for ObjectIdentifier {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let arcs: Result<Vec<u32>, _> = value.split('.').map(|arc| arc.parse::<u32>()).collect();
+        return ObjectIdentifier::new(&arcs.map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid object identifier"))?);
+    }
+}
+
+impl Asn1 for ObjectIdentifier {
+    const TAG: Option<Tag> = Some(Tag::OBJECT_IDENTIFIER);
+
+    /// X.690 8.19 - first two arcs in one subidentifier
+    fn to_element(&self, _rules: EncodingRules) -> Result<Element, Error> {
+        let mut value = vec![];
+        ber::encode_base128(self.0[0] as u64 * 40 + self.0[1] as u64, &mut value);
+        for arc in &self.0[2..] {
+            ber::encode_base128(*arc as u64, &mut value);
+        }
+        return Ok(Element::primitive(Tag::OBJECT_IDENTIFIER, value));
+    }
+
+    fn from_element(element: &Element, _rules: EncodingRules) -> Result<Self, Error> {
+        let mut value = element.value()?;
+        let mut arcs = vec![];
+        while !value.is_empty() {
+            let (subidentifier, length) = ber::decode_base128(value).map_err(|_| invalid("invalid subidentifier"))?;
+            if arcs.is_empty() {
+                let first = (subidentifier / 40).min(2);
+                arcs.push(first);
+                arcs.push(subidentifier - first * 40);
+            } else {
+                arcs.push(subidentifier);
+            }
+            value = &value[length..];
+        }
+        if arcs.is_empty() || arcs.iter().any(|arc| *arc > u32::MAX as u64) {
+            return Err(invalid("invalid OBJECT IDENTIFIER"));
+        }
+        return Ok(ObjectIdentifier(arcs.into_iter().map(|arc| arc as u32).collect()));
+    }
+}
+
+// REAL
+
+/// x * 2^exponent without overflowing on the way
+fn scale(mut value: f64, mut exponent: i64) -> f64 {
+    while exponent > 1000 {
+        value *= 2f64.powi(1000);
+        exponent -= 1000;
+    }
+    while exponent < -1000 {
+        value *= 2f64.powi(-1000);
+        exponent += 1000;
+    }
+    return value * 2f64.powi(exponent as i32);
+}
+
+impl Asn1 for f64 {
+    const TAG: Option<Tag> = Some(Tag::REAL);
+
+    /// X.690 8.5 resp. 11.3 - base 2, odd mantissa, scaling factor 0
+    fn to_element(&self, _rules: EncodingRules) -> Result<Element, Error> {
+        let value = if *self == 0.0 && self.is_sign_positive() {
+            vec![]
+        } else if *self == 0.0 {
+            vec![0x43]
+        } else if self.is_nan() {
+            vec![0x42]
+        } else if self.is_infinite() {
+            vec![if *self > 0.0 { 0x40 } else { 0x41 }]
+        } else {
+            let bits = self.to_bits();
+            let raw_exponent = ((bits >> 52) & 0x7ff) as i64;
+            let (mut mantissa, mut exponent) = if raw_exponent == 0 {
+                (bits & ((1 << 52) - 1), -1074)
+            } else {
+                ((bits & ((1 << 52) - 1)) | (1 << 52), raw_exponent - 1075)
+            };
+            while mantissa & 1 == 0 {
+                mantissa >>= 1;
+                exponent += 1;
+            }
+            let exponent = encode_integer(exponent as i128);
+            let mut value = vec![0x80 | if *self < 0.0 { 0x40 } else { 0 } | (exponent.len() as u8 - 1)];
+            value.extend(exponent);
+            let mantissa = mantissa.to_be_bytes();
+            let start = mantissa.iter().position(|octet| *octet != 0).expect("mantissa not zero");
+            value.extend_from_slice(&mantissa[start..]);
+            value
+        };
+        return Ok(Element::primitive(Tag::REAL, value));
+    }
+
+    fn from_element(element: &Element, _rules: EncodingRules) -> Result<Self, Error> {
+        let value = element.value()?;
+        let first = match value.first() {
+            Some(first) => *first,
+            None => { return Ok(0.0); }
+        };
+        if first & 0x80 != 0 {
+            // binary encoding
+            let log2_base = match (first >> 4) & 0x03 {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                _ => { return Err(invalid("reserved REAL base")); }
+            };
+            let scaling = ((first >> 2) & 0x03) as i64;
+            let (exponent_length, exponent_start) = match first & 0x03 {
+                3 => (*value.get(1).ok_or(invalid("REAL exponent missing"))? as usize, 2),
+                length => (length as usize + 1, 1)
+            };
+            let exponent = value.get(exponent_start..exponent_start + exponent_length).ok_or(invalid("REAL exponent missing"))?;
+            let exponent = i64::try_from(decode_integer(exponent)?).map_err(|_| invalid("REAL exponent too large"))?;
+            let mantissa = &value[exponent_start + exponent_length..];
+            if mantissa.len() > 16 {
+                return Err(invalid("REAL mantissa too large"));
+            }
+            let mantissa = mantissa.iter().fold(0u128, |mantissa, octet| (mantissa << 8) | *octet as u128) as f64;
+            let result = scale(mantissa, exponent.saturating_mul(log2_base).saturating_add(scaling));
+            return Ok(if first & 0x40 != 0 { -result } else { result });
+        }
+        if first & 0x40 != 0 {
+            // X.690 8.5.9 special real values
+            match value {
+                [0x40] => { return Ok(f64::INFINITY); },
+                [0x41] => { return Ok(f64::NEG_INFINITY); },
+                [0x42] => { return Ok(f64::NAN); },
+                [0x43] => { return Ok(-0.0); },
+                _ => { return Err(invalid("reserved special REAL value")); }
+            }
+        }
+        // X.690 8.5.8 decimal encoding, ISO 6093 NR1, NR2 resp. NR3 form
+        let number = std::str::from_utf8(&value[1..]).map_err(|_| invalid("invalid decimal REAL"))?;
+        return number.trim_start_matches(' ').replace(',', ".").parse::<f64>().map_err(|_| invalid("invalid decimal REAL"));
+    }
+}
+
+// character strings
+
+impl Asn1 for String {
+    const TAG: Option<Tag> = Some(Tag::UTF8_STRING);
+
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        return Ok(string_element(Tag::UTF8_STRING, self.as_bytes(), rules));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        return String::from_utf8(element.octets(rules)?).map_err(|_| invalid("invalid UTF8String"));
+    }
+}
+
+/// restricted character string types with a character set out of ASCII
+macro_rules! ascii_string {
+    ($(#[$meta:meta])* $name:ident, $tag:expr, $allowed:expr) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn new(value: &str) -> Result<Self, Error> {
+                if !value.chars().all($allowed) {
+                    return Err(Error::new(ErrorKind::InvalidInput, concat!("character not allowed in ", stringify!($name))));
+                }
+                return Ok($name(value.to_string()));
+            }
+
+            pub fn as_str(&self) -> &str {
+                return &self.0;
+            }
+        }
+
+        impl Asn1 for $name {
+            const TAG: Option<Tag> = Some($tag);
+
+            fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+                return Ok(string_element($tag, self.0.as_bytes(), rules));
+            }
+
+            fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+                let octets = element.octets(rules)?;
+                if !octets.iter().all(|octet| $allowed(*octet as char)) {
+                    return Err(invalid(concat!("character not allowed in ", stringify!($name))));
+                }
+                return Ok($name(octets.into_iter().map(|octet| octet as char).collect()));
+            }
+        }
+    };
+}
+
+ascii_string!(
+    /// X.680 41.4 - digits and space
+    NumericString, Tag::NUMERIC_STRING, |c: char| c.is_ascii_digit() || c == ' '
+);
+ascii_string!(
+    /// X.680 41.4 - letters, digits, space and '()+,-./:=?
+    PrintableString, Tag::PRINTABLE_STRING, |c: char| c.is_ascii_alphanumeric() || " '()+,-./:=?".contains(c)
+);
+ascii_string!(
+    /// IA5, i.e. ASCII with the control characters
+    Ia5String, Tag::IA5_STRING, |c: char| c.is_ascii()
+);
+ascii_string!(
+    /// ISO 646, i.e. printable ASCII
+    VisibleString, Tag::VISIBLE_STRING, |c: char| (' '..='~').contains(&c)
+);
+
+/// restricted character string types with ISO 2022 escape sequences, kept as the octets
+/// NOTE: no conversion into Unicode, which depends on the registered character sets the escape sequences switch to
+macro_rules! octet_string_type {
+    ($(#[$meta:meta])* $name:ident, $tag:expr) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+        pub struct $name(pub Vec<u8>);
+
+        impl Asn1 for $name {
+            const TAG: Option<Tag> = Some($tag);
+
+            fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+                return Ok(string_element($tag, &self.0, rules));
+            }
+
+            fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+                return Ok($name(element.octets(rules)?));
+            }
+        }
+    };
+}
+
+octet_string_type!(
+    /// T61String
+    TeletexString, Tag::TELETEX_STRING
+);
+octet_string_type!(VideotexString, Tag::VIDEOTEX_STRING);
+octet_string_type!(GraphicString, Tag::GRAPHIC_STRING);
+octet_string_type!(GeneralString, Tag::GENERAL_STRING);
+octet_string_type!(
+    /// X.680 48 - [UNIVERSAL 7] IMPLICIT GraphicString
+    ObjectDescriptor, Tag::OBJECT_DESCRIPTOR
+);
+
+/// ISO 10646 in four octets per character
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct UniversalString(pub String);
+
+impl Asn1 for UniversalString {
+    const TAG: Option<Tag> = Some(Tag::UNIVERSAL_STRING);
+
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        let octets: Vec<u8> = self.0.chars().flat_map(|c| (c as u32).to_be_bytes()).collect();
+        return Ok(string_element(Tag::UNIVERSAL_STRING, &octets, rules));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        let octets = element.octets(rules)?;
+        if octets.len() % 4 != 0 {
+            return Err(invalid("UniversalString not in four octets per character"));
+        }
+        let characters: Option<String> = octets.chunks(4).map(|c| char::from_u32(u32::from_be_bytes([c[0], c[1], c[2], c[3]]))).collect();
+        return Ok(UniversalString(characters.ok_or(invalid("invalid character in UniversalString"))?));
+    }
+}
+
+/// ISO 10646 basic multilingual plane in two octets per character
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BmpString(String);
+
+impl BmpString {
+    pub fn new(value: &str) -> Result<Self, Error> {
+        if value.chars().any(|c| c as u32 > 0xffff) {
+            return Err(Error::new(ErrorKind::InvalidInput, "character beyond the basic multilingual plane"));
+        }
+        return Ok(BmpString(value.to_string()));
+    }
+
+    pub fn as_str(&self) -> &str {
+        return &self.0;
+    }
+}
+
+impl Asn1 for BmpString {
+    const TAG: Option<Tag> = Some(Tag::BMP_STRING);
+
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        let octets: Vec<u8> = self.0.chars().flat_map(|c| (c as u16).to_be_bytes()).collect();
+        return Ok(string_element(Tag::BMP_STRING, &octets, rules));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        let octets = element.octets(rules)?;
+        if octets.len() % 2 != 0 {
+            return Err(invalid("BMPString not in two octets per character"));
+        }
+        let characters: Option<String> = octets.chunks(2).map(|c| char::from_u32(u16::from_be_bytes([c[0], c[1]]) as u32)).collect();
+        return Ok(BmpString(characters.ok_or(invalid("invalid character in BMPString"))?));
+    }
+}
+
+// UTCTime, GeneralizedTime
+
+/// digits of a time value, one field after the other
+struct TimeParser<'a> {
+    value: &'a [u8],
+}
+
+impl<'a> TimeParser<'a> {
+    fn digits(&mut self, count: usize) -> Option<u32> {
+        let digits = self.value.get(..count)?;
+        if !digits.iter().all(|digit| digit.is_ascii_digit()) {
+            return None;
+        }
+        self.value = &self.value[count..];
+        return Some(digits.iter().fold(0, |result, digit| result * 10 + (digit - b'0') as u32));
+    }
+
+    /// more digits follow
+    fn has_digits(&self) -> bool {
+        return self.value.first().map(|octet| octet.is_ascii_digit()).unwrap_or(false);
+    }
+
+    /// X.680 46.3 resp. 47.3 - Z, a differential to UTC, or nothing for local time
+    fn time_zone(&mut self, rules: EncodingRules) -> Result<Option<i64>, Error> {
+        match self.value {
+            [b'Z'] => { return Ok(Some(0)); },
+            _ if rules != EncodingRules::Ber => { return Err(invalid("time not in UTC with Z in CER resp. DER")); },
+            [] => { return Ok(None); },
+            [sign @ (b'+' | b'-'), ..] => {
+                let sign = if *sign == b'-' { -1 } else { 1 };
+                self.value = &self.value[1..];
+                let hours = self.digits(2).ok_or(invalid("invalid time differential"))?;
+                let minutes = self.digits(2).ok_or(invalid("invalid time differential"))?;
+                if !self.value.is_empty() || hours > 23 || minutes > 59 {
+                    return Err(invalid("invalid time differential"));
+                }
+                return Ok(Some(sign * (hours * 60 + minutes) as i64));
+            },
+            _ => { return Err(invalid("invalid time zone")); }
+        }
+    }
+}
+
+/// time of day and time zone once the date is parsed
+fn parse_time(date: NaiveDate, parser: &mut TimeParser, generalized: bool, rules: EncodingRules) -> Result<DateTime<Utc>, Error> {
+    let hour = parser.digits(2).ok_or(invalid("invalid hour"))?;
+    // NOTE: GeneralizedTime may leave out minutes and seconds, UTCTime only seconds - not in CER and DER
+    let minute = if generalized && !parser.has_digits() { None } else { Some(parser.digits(2).ok_or(invalid("invalid minute"))?) };
+    let second = if minute.is_some() && parser.has_digits() { Some(parser.digits(2).ok_or(invalid("invalid second"))?) } else { None };
+    if second.is_none() && rules != EncodingRules::Ber {
+        return Err(invalid("time without seconds in CER resp. DER"));
+    }
+    let mut nanosecond = 0;
+    if generalized && second.is_some() && matches!(parser.value.first(), Some(b'.') | Some(b',')) {
+        if parser.value[0] == b',' && rules != EncodingRules::Ber {
+            return Err(invalid("decimal comma in CER resp. DER"));
+        }
+        parser.value = &parser.value[1..];
+        let fraction: Vec<u8> = parser.value.iter().take_while(|octet| octet.is_ascii_digit()).copied().collect();
+        if fraction.is_empty() || (rules != EncodingRules::Ber && fraction.last() == Some(&b'0')) {
+            return Err(invalid("invalid fraction of a second"));
+        }
+        parser.value = &parser.value[fraction.len()..];
+        //TODO fractions of minutes resp. hours
+        nanosecond = fraction.iter().chain([b'0'; 9].iter()).take(9).fold(0, |result, digit| result * 10 + (digit - b'0') as u32);
+    }
+    // NOTE: local time without time differential is taken as UTC
+    let offset = parser.time_zone(rules)?.unwrap_or(0);
+    let time = date.and_hms_nano_opt(hour, minute.unwrap_or(0), second.unwrap_or(0), nanosecond).ok_or(invalid("invalid time of day"))?;
+    return Ok(Utc.from_utc_datetime(&(time - chrono::Duration::minutes(offset))));
+}
+
+/// X.680 47 - years 1950 to 2049 in two digits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UtcTime(DateTime<Utc>);
+
+impl UtcTime {
+    /// NOTE: whole seconds only
+    pub fn new(time: DateTime<Utc>) -> Result<Self, Error> {
+        if !(1950..=2049).contains(&time.year()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "year out of the range of UTCTime"));
+        }
+        return Ok(UtcTime(time));
+    }
+
+    pub fn time(&self) -> DateTime<Utc> {
+        return self.0;
+    }
+}
+
+impl Asn1 for UtcTime {
+    const TAG: Option<Tag> = Some(Tag::UTC_TIME);
+
+    /// X.690 11.8 - YYMMDDhhmmssZ
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        return Ok(string_element(Tag::UTC_TIME, self.0.format("%y%m%d%H%M%SZ").to_string().as_bytes(), rules));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        let value = element.octets(rules)?;
+        let mut parser = TimeParser { value: &value };
+        let year = parser.digits(2).ok_or(invalid("invalid year"))? as i32;
+        let year = if year < 50 { 2000 + year } else { 1900 + year };
+        let month = parser.digits(2).ok_or(invalid("invalid month"))?;
+        let day = parser.digits(2).ok_or(invalid("invalid day"))?;
+        let date = NaiveDate::from_ymd_opt(year, month, day).ok_or(invalid("invalid date"))?;
+        return Ok(UtcTime(parse_time(date, &mut parser, false, rules)?));
+    }
+}
+
+/// X.680 46 - four-digit years and fractions of a second
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GeneralizedTime(pub DateTime<Utc>);
+
+impl Asn1 for GeneralizedTime {
+    const TAG: Option<Tag> = Some(Tag::GENERALIZED_TIME);
+
+    /// X.690 11.7 - YYYYMMDDhhmmss[.f]Z, fraction without trailing zeros
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        let mut value = self.0.format("%Y%m%d%H%M%S").to_string();
+        let nanosecond = self.0.timestamp_subsec_nanos();
+        if nanosecond != 0 {
+            value.push('.');
+            value.push_str(format!("{:09}", nanosecond).trim_end_matches('0'));
+        }
+        value.push('Z');
+        return Ok(string_element(Tag::GENERALIZED_TIME, value.as_bytes(), rules));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        let value = element.octets(rules)?;
+        let mut parser = TimeParser { value: &value };
+        let year = parser.digits(4).ok_or(invalid("invalid year"))? as i32;
+        let month = parser.digits(2).ok_or(invalid("invalid month"))?;
+        let day = parser.digits(2).ok_or(invalid("invalid day"))?;
+        let date = NaiveDate::from_ymd_opt(year, month, day).ok_or(invalid("invalid date"))?;
+        return Ok(GeneralizedTime(parse_time(date, &mut parser, true, rules)?));
+    }
+}
+
+//TODO EXTERNAL, EMBEDDED PDV and CHARACTER STRING, RELATIVE-OID
+
+// constructed types
+
+/// SEQUENCE OF
+impl<T: Asn1> Asn1 for Vec<T> {
+    const TAG: Option<Tag> = Some(Tag::SEQUENCE);
+
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        return Ok(Element::constructed(Tag::SEQUENCE, self.iter().map(|item| item.to_element(rules)).collect::<Result<Vec<Element>, Error>>()?));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        return element.elements()?.iter().map(|item| {
+            if !T::matches(item.tag) {
+                return Err(invalid("unexpected tag in SEQUENCE OF"));
+            }
+            return T::from_element(item, rules);
+        }).collect();
+    }
+}
+
+/// SET OF
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SetOf<T>(pub Vec<T>);
+
+impl<T: Asn1> Asn1 for SetOf<T> {
+    const TAG: Option<Tag> = Some(Tag::SET);
+
+    /// X.690 11.6 - in CER and DER, in the order of the encodings
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        let mut items = self.0.iter().map(|item| item.to_element(rules)).collect::<Result<Vec<Element>, Error>>()?;
+        if rules != EncodingRules::Ber {
+            items.sort_by_cached_key(|item| item.encode(rules));
+        }
+        return Ok(Element::constructed(Tag::SET, items));
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        let items = element.elements()?.iter().map(|item| {
+            if !T::matches(item.tag) {
+                return Err(invalid("unexpected tag in SET OF"));
+            }
+            return T::from_element(item, rules);
+        }).collect::<Result<Vec<T>, Error>>()?;
+        return Ok(SetOf(items));
+    }
+}
+
+/// OPTIONAL component
+impl<T: Asn1> Asn1 for Option<T> {
+    const TAG: Option<Tag> = T::TAG;
+
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        // NOTE: SEQUENCE and SET leave absent components out, elsewhere, e.g. in a SEQUENCE OF, there is nothing to encode
+        match self {
+            Some(value) => { return value.to_element(rules); },
+            None => { return Err(Error::new(ErrorKind::InvalidInput, "OPTIONAL value absent")); }
+        }
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        return Ok(Some(T::from_element(element, rules)?));
+    }
+
+    fn matches(tag: Tag) -> bool {
+        return T::matches(tag);
+    }
+
+    fn is_present(&self) -> bool {
+        return self.is_some();
+    }
+
+    fn absent() -> Option<Self> {
+        return Some(None);
+    }
+}
+
+/// for recursive types
+impl<T: Asn1> Asn1 for Box<T> {
+    const TAG: Option<Tag> = T::TAG;
+
+    fn to_element(&self, rules: EncodingRules) -> Result<Element, Error> {
+        return self.as_ref().to_element(rules);
+    }
+
+    fn from_element(element: &Element, rules: EncodingRules) -> Result<Self, Error> {
+        return Ok(Box::new(T::from_element(element, rules)?));
+    }
+
+    fn matches(tag: Tag) -> bool {
+        return T::matches(tag);
+    }
+}
+
+/// open type, e.g. ANY resp. a type out of a presentation context - decoded later, once it is known what it is
+#[derive(Clone, Debug, PartialEq)]
+pub struct Any(pub Element);
+
+impl Asn1 for Any {
+    const TAG: Option<Tag> = None;
+
+    fn to_element(&self, _rules: EncodingRules) -> Result<Element, Error> {
+        return Ok(self.0.clone());
+    }
+
+    fn from_element(element: &Element, _rules: EncodingRules) -> Result<Self, Error> {
+        return Ok(Any(element.clone()));
+    }
+
+    fn matches(_tag: Tag) -> bool {
+        return true;
+    }
+}
+
+impl Any {
+    pub fn decode_as<T: Asn1>(&self, rules: EncodingRules) -> Result<T, Error> {
+        if !T::matches(self.0.tag) {
+            return Err(invalid("unexpected tag"));
+        }
+        return T::from_element(&self.0, rules);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [EncodingRules; 3] = [EncodingRules::Ber, EncodingRules::Cer, EncodingRules::Der];
+
+    fn round_trip<T: Asn1 + PartialEq + fmt::Debug>(value: &T) {
+        for rules in ALL {
+            let encoding = value.encode(rules).unwrap();
+            assert_eq!(&T::decode(&encoding, rules).unwrap(), value, "{:?}", rules);
+        }
+    }
+
+    #[test]
+    fn integers() {
+        for (value, octets) in [(0, &[0x00][..]), (127, &[0x7f][..]), (128, &[0x00, 0x80][..]), (-128, &[0x80][..]), (-129, &[0xff, 0x7f][..]), (256, &[0x01, 0x00][..])] {
+            assert_eq!(encode_integer(value), octets);
+            assert_eq!(decode_integer(octets).unwrap(), value);
+        }
+        for value in [0i64, 1, -1, i64::MAX, i64::MIN] {
+            round_trip(&value);
+        }
+        round_trip(&u8::MAX);
+        // X.690 8.3.2 - not in the minimum number of octets
+        assert!(decode_integer(&[0x00, 0x7f]).is_err());
+        assert!(decode_integer(&[]).is_err());
+        // out of the range of the type
+        assert!(u8::decode(&[0x02, 0x02, 0x01, 0x00], EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn booleans() {
+        round_trip(&true);
+        round_trip(&false);
+        // X.690 11.1 - TRUE as any other value than 0xff only in BER
+        assert!(bool::decode(&[0x01, 0x01, 0x01], EncodingRules::Ber).unwrap());
+        assert!(bool::decode(&[0x01, 0x01, 0x01], EncodingRules::Der).is_err());
+        assert!(bool::decode(&[0x01, 0x00], EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn octet_strings() {
+        round_trip(&OctetString(vec![]));
+        round_trip(&OctetString(vec![0x55; 2500]));
+        // X.690 9.2 - CER segments of 1000 octets in an indefinite length encoding, DER primitive
+        let value = OctetString(vec![0x55; 2500]);
+        let element = value.to_element(EncodingRules::Cer).unwrap();
+        let segments: Vec<usize> = element.elements().unwrap().iter().map(|segment| segment.value().unwrap().len()).collect();
+        assert_eq!(segments, [1000, 1000, 500]);
+        assert_eq!(&value.encode(EncodingRules::Cer).unwrap()[..2], [0x24, 0x80]);
+        assert!(!value.to_element(EncodingRules::Der).unwrap().is_constructed());
+        assert!(!OctetString(vec![0x55; 1000]).to_element(EncodingRules::Cer).unwrap().is_constructed());
+        // a constructed encoding is no DER
+        let constructed = Element::constructed(Tag::OCTET_STRING, vec![Element::primitive(Tag::OCTET_STRING, vec![1]), Element::primitive(Tag::OCTET_STRING, vec![2])]);
+        assert_eq!(OctetString::from_element(&constructed, EncodingRules::Ber).unwrap(), OctetString(vec![1, 2]));
+        assert!(OctetString::from_element(&constructed, EncodingRules::Der).is_err());
+    }
+
+    #[test]
+    fn bit_strings() {
+        let bits = BitString::from_bits(&[true, false, true, false, false, false, false, false, false, true, false, false]);
+        assert_eq!((bits.data.clone(), bits.unused_bits, bits.len()), (vec![0xa0, 0x40], 6, 10));
+        assert!(bits.bit(9) && !bits.bit(10) && !bits.bit(100));
+        assert_eq!(bits.encode(EncodingRules::Der).unwrap(), [0x03, 0x03, 0x06, 0xa0, 0x40]);
+        round_trip(&bits);
+        round_trip(&BitString::default());
+        round_trip(&BitString { data: vec![0xf8; 2000], unused_bits: 3 });
+        // CER segments, with the unused bits in the last one only
+        let element = BitString { data: vec![0xff; 2000], unused_bits: 0 }.to_element(EncodingRules::Cer).unwrap();
+        assert_eq!(element.elements().unwrap().len(), 3);
+        // unused bits not zero
+        assert!(BitString::decode(&[0x03, 0x02, 0x01, 0x01], EncodingRules::Ber).is_ok());
+        assert!(BitString::decode(&[0x03, 0x02, 0x01, 0x01], EncodingRules::Der).is_err());
+        assert!(BitString::decode(&[0x03, 0x01, 0x01], EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn object_identifiers() {
+        let oid: ObjectIdentifier = "1.2.840.113549".parse().unwrap();
+        assert_eq!(oid.encode(EncodingRules::Der).unwrap(), [0x06, 0x06, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d]);
+        assert_eq!(oid.to_string(), "1.2.840.113549");
+        round_trip(&oid);
+        round_trip(&ObjectIdentifier::new(&[2, 999, 3]).unwrap());
+        assert!("1.40".parse::<ObjectIdentifier>().is_err());
+        assert!("3.1".parse::<ObjectIdentifier>().is_err());
+        assert!(ObjectIdentifier::new(&[1]).is_err());
+    }
+
+    #[test]
+    fn null_and_real() {
+        round_trip(&Null);
+        assert!(Null::decode(&[0x05, 0x01, 0x00], EncodingRules::Ber).is_err());
+        for value in [0.0, -0.0, 1.0, -2.5, 0.1, 1e300, f64::MIN_POSITIVE, f64::INFINITY, f64::NEG_INFINITY] {
+            round_trip(&value);
+        }
+        assert_eq!(0.0f64.encode(EncodingRules::Der).unwrap(), [0x09, 0x00]);
+        assert!(f64::decode(&f64::NAN.encode(EncodingRules::Der).unwrap(), EncodingRules::Der).unwrap().is_nan());
+    }
+
+    #[test]
+    fn character_strings() {
+        round_trip(&"grüße".to_string());
+        round_trip(&PrintableString::new("Hello (World)").unwrap());
+        assert!(PrintableString::new("a@b").is_err());
+        assert!(PrintableString::decode(&[0x13, 0x01, b'@'], EncodingRules::Ber).is_err());
+        round_trip(&NumericString::new("0123 456").unwrap());
+        round_trip(&Ia5String::new("a@b\n").unwrap());
+        round_trip(&VisibleString::new("~").unwrap());
+        round_trip(&UniversalString("𝄞x".to_string()));
+        round_trip(&BmpString::new("Ωx").unwrap());
+        assert!(BmpString::new("𝄞").is_err());
+        round_trip(&TeletexString(vec![0x1b, 0x28, 0x42, 0x41]));
+        // long strings are segmented in CER, as OCTET STRING
+        let long = "x".repeat(1001);
+        assert!(long.to_element(EncodingRules::Cer).unwrap().is_constructed());
+        round_trip(&long);
+    }
+
+    #[test]
+    fn times() {
+        let time = Utc.with_ymd_and_hms(2024, 2, 29, 23, 59, 58).unwrap();
+        let utc_time = UtcTime::new(time).unwrap();
+        assert_eq!(utc_time.to_element(EncodingRules::Der).unwrap().value().unwrap(), b"240229235958Z");
+        round_trip(&utc_time);
+        assert!(UtcTime::new(Utc.with_ymd_and_hms(2050, 1, 1, 0, 0, 0).unwrap()).is_err());
+        // 50 and above is in the 20th century
+        let decoded = UtcTime::decode(&[&[0x17, 0x0d][..], b"991231120000Z"].concat(), EncodingRules::Der).unwrap();
+        assert_eq!(decoded.time().year(), 1999);
+        let generalized = GeneralizedTime(time + chrono::Duration::milliseconds(250));
+        assert_eq!(generalized.to_element(EncodingRules::Der).unwrap().value().unwrap(), b"20240229235958.25Z");
+        round_trip(&generalized);
+        round_trip(&GeneralizedTime(time));
+        assert!(GeneralizedTime::decode(&[&[0x18, 0x0f][..], b"20240230000000Z"].concat(), EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn sequence_of_and_set_of() {
+        round_trip(&vec![1i64, 2, 3]);
+        round_trip(&Vec::<i64>::new());
+        // X.690 11.6 - DER and CER sort the SET OF by the encodings, BER keeps the order
+        let set = SetOf(vec![OctetString(vec![2]), OctetString(vec![1, 0]), OctetString(vec![1])]);
+        let element = set.to_element(EncodingRules::Der).unwrap();
+        let values: Vec<&[u8]> = element.elements().unwrap().iter().map(|item| item.value().unwrap()).collect();
+        assert_eq!(values, [&[1][..], &[2][..], &[1, 0][..]]);
+        let element = set.to_element(EncodingRules::Ber).unwrap();
+        assert_eq!(element.elements().unwrap()[0].value().unwrap(), [2]);
+        assert_eq!(SetOf::<OctetString>::decode(&set.encode(EncodingRules::Ber).unwrap(), EncodingRules::Ber).unwrap(), set);
+        assert!(Vec::<i64>::decode(&vec![Null].encode(EncodingRules::Ber).unwrap(), EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn absent_optional_value() {
+        round_trip(&Some(5i64));
+        assert_eq!(None::<i64>.to_element(EncodingRules::Ber).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(vec![Some(1i64), None].encode(EncodingRules::Der).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn open_types() {
+        let any = Any(Element::primitive(Tag::context(3), vec![0x01]));
+        round_trip(&any);
+        assert!(any.decode_as::<i64>(EncodingRules::Ber).is_err());
+        let any = Any(7i64.to_element(EncodingRules::Ber).unwrap());
+        assert_eq!(any.decode_as::<i64>(EncodingRules::Ber).unwrap(), 7);
+        round_trip(&Box::new(true));
+    }
+}
@@ -0,0 +1,45 @@
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use osistack::n::management;
+
+// NOTE: asks the running stack instance with the given network entity title on its management socket what it has learned,
+// optionally repeating every <interval> seconds. Applications start answering there with osistack::n::management::serve().
+
+pub fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() < 2 {
+        panic!("usage: {} <network-entity-title-of-running-instance> [-i <interval-seconds>]", args[0]);
+    }
+
+    let network_entity_title: &str = &args[1];
+    let mut interval: Option<u64> = None;
+
+    let mut i = 2;
+    while i < args.len() {
+        if args[i] == "-i" {
+            interval = Some(args.get(i+1).expect("missing value for -i").parse().expect("could not parse interval"));
+            i += 2;
+        } else {
+            panic!("unknown argument: {}", args[i]);
+        }
+    }
+
+    let path = management::socket_path(network_entity_title);
+    loop {
+        match management::query(&path) {
+            Ok(report) => print!("{}", report),
+            Err(e) => {
+                eprintln!("could not query stack instance on {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        if let Some(seconds) = interval {
+            thread::sleep(Duration::from_secs(seconds));
+            println!();
+        } else {
+            break;
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
@@ -6,7 +7,8 @@ use osistack::n;
 use osistack::n::NetworkService;
 
 pub fn main() {
-    let args: Vec<_> = env::args().collect();
+    // NOTE: leaked so that the NS can be shared with the management thread, it lives as long as the process anyway
+    let args: &'static [String] = env::args().collect::<Vec<_>>().leak();
     if args.len() < 4 {
         panic!("usage: {} <interface-name> <own-network-entity-title> <destination-host> [<host-name> <mac-address>]", args[0]);
     }
@@ -36,29 +38,38 @@ pub fn main() {
     }
 
     // set up network
-    let (sn, mut ns) = osistack::new(interface_name, network_entity_title, hosts);
+    let (sn, ns) = osistack::new(interface_name, network_entity_title, hosts, osistack::DEFAULT_QUEUE_CAPACITY);
+    let ns = Arc::new(Mutex::new(ns));
+    // for osinetstat
+    if let Err(e) = n::management::serve(ns.clone(), &n::management::socket_path(network_entity_title)) {
+        println!("not answering on management socket: {}", e);
+    }
 
     // application logic
 
     // send request to other host
-    let qos = n::Qos{};
+    let qos = n::Qos::default();
     //let source_nsap = ns.get_serviced_nsap().expect("failed to get own serviced NSAP");
     //TODO fix ^ 2nd borrow, Rust's borrow checker cannot look into functions which fields they actually lock
     loop {
         // normal data
-        ns.n_unitdata_request(
+        if let Err(e) = ns.lock().expect("failed to lock ns").n_unitdata_request(
             dest_host,  //TODO change to proper, which is NSAP address - there is no echo service on DL layer
             &qos,
             r"testdata".as_bytes()
-        );
+        ) {
+            println!("sending data failed: {}", e);
+        }
         // echo request
-        let source_nsap = ns.echo_request(
+        if let Err(e) = ns.lock().expect("failed to lock ns").echo_request(
             Some(dest_host.to_owned()), //TODO optimize clone
             None,
             Some(0),    //TODO just to avoid 2nd borrow on whole of ns
             None,
             &qos
-        );
+        ) {
+            println!("sending echo request failed: {}", e);
+        }
 
         thread::sleep(Duration::from_secs(2));
     }
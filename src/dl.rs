@@ -1,4 +1,4 @@
-use std::{sync::{Arc, Mutex}, thread::{Thread, JoinHandle}};
+use std::{sync::{Arc, Mutex}, thread::{Thread, JoinHandle}, io::{Error, ErrorKind, Read, Write}, time::Duration};
 
 use advmac::MacAddr6;
 use afpacket::sync::RawPacketStream;
@@ -6,8 +6,131 @@ use afpacket::sync::RawPacketStream;
 use crate::n::NUnitDataIndication;
 
 pub mod ethernet;
+pub mod llc;
+pub mod vlan;
+pub mod packet;
+pub mod mmap;
+pub mod pool;
+pub mod bpf;
+// alternative backends to the raw socket
+pub mod tap;
+pub mod loopback;
+pub mod pcap;
+pub mod udp;
+// simulated segment for multi-node tests
+pub mod hub;
 
 pub const ETHER_TYPE_CLNP: u16 = 0x8872;  // as per https://datatracker.ietf.org/doc/html/draft-kaplan-isis-ext-eth-ip-clns-2-00
+/// pseudo EtherType on Linux (ETH_P_ALL) for receiving all frames, needed to see VLAN tags
+pub const ETHER_TYPE_ALL: u16 = 0x0003;
+/// shorter payloads of an untagged frame are padded by the MAC, and the padding is handed up to the receiving NS
+/// NOTE: tagged frames have a minimum payload of 42 bytes, but a switch removing the tag pads them up to this again
+pub const MINIMUM_PAYLOAD_LENGTH: usize = 46;
+/// how often the SN looks at the link state
+pub const LINK_POLL_INTERVAL: Duration = Duration::from_millis(1000);
+/// waiting time before retrying a failed socket, doubled on every further failure up to the maximum
+pub const RETRY_BACKOFF_INITIAL: Duration = Duration::from_millis(100);
+pub const RETRY_BACKOFF_MAXIMUM: Duration = Duration::from_secs(10);
+/// ISO 9542 ES-IS group addresses on broadcast subnetworks - joined on every interface
+pub const ALL_END_SYSTEMS: MacAddr6 = MacAddr6::new([0x09, 0x00, 0x2B, 0x00, 0x00, 0x04]);
+pub const ALL_INTERMEDIATE_SYSTEMS: MacAddr6 = MacAddr6::new([0x09, 0x00, 0x2B, 0x00, 0x00, 0x05]);
+pub const OSI_GROUP_ADDRESSES: [MacAddr6; 2] = [ALL_END_SYSTEMS, ALL_INTERMEDIATE_SYSTEMS];
+/// most frames handed up to the NS before waking it up
+pub const RECEIVE_BATCH_MAXIMUM: usize = 64;
+
+/// how network layer PDUs are framed on an Ethernet interface
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encapsulation {
+    /// Ethernet II with EtherType 0x8872 as per the IETF draft above
+    #[default]
+    EthernetII,
+    /// IEEE 802.3 length field and IEEE 802.2 LLC with SAP 0xFE - what commercial OSI equipment uses
+    Llc,
+}
+
+impl Encapsulation {
+    /// EtherType to open and bind the raw socket with
+    pub fn socket_ether_type(&self) -> u16 {
+        match self {
+            Encapsulation::EthernetII => ETHER_TYPE_CLNP,
+            Encapsulation::Llc => llc::ETHER_TYPE_802_2,
+        }
+    }
+}
+
+/// default MTU of Ethernet, excluding MAC header and VLAN tags
+pub const ETHERNET_MTU: usize = 1500;
+/// usual MTU of jumbo frames, e.g. on storage networks
+pub const JUMBO_MTU: usize = 9000;
+/// MAC addresses and EtherType resp. length field
+pub const MAC_HEADER_LENGTH: usize = 14;
+
+/// per-interface settings of the subnetwork service
+/// NOTE: several service instances can be bound to the same trunk interface using different VLANs
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InterfaceConfig {
+    pub encapsulation: Encapsulation,
+    /// None is the Ethernet default - filled in from the interface by osistack::new_with_interfaces() if not given
+    pub mtu: Option<usize>,
+    /// 802.1Q VLAN to send on and accept from - None is untagged (and priority-tagged)
+    pub vlan_id: Option<u16>,
+    /// 802.1ad service VLAN (QinQ outer tag) - None is no service tag
+    pub service_vlan_id: Option<u16>,
+    /// PACKET_MMAP rings instead of a system call per frame - None is a plain raw socket
+    pub packet_ring: Option<mmap::RingConfig>,
+}
+
+impl InterfaceConfig {
+    /// EtherType to open and bind the raw socket with
+    pub fn socket_ether_type(&self) -> u16 {
+        if self.vlan_id.is_some() || self.service_vlan_id.is_some() {
+            // NOTE: we see our own outgoing frames on such a socket, the socket filter drops them
+            return ETHER_TYPE_ALL;
+        }
+        return self.encapsulation.socket_ether_type();
+    }
+
+    /// largest SN user data the interface can carry in one frame
+    pub fn maximum_sdu_size(&self) -> usize {
+        let mtu = self.mtu.unwrap_or(ETHERNET_MTU);
+        return match self.encapsulation {
+            Encapsulation::EthernetII => mtu,
+            // NOTE: the 802.3 length field cannot express more, even on a jumbo frame capable interface
+            Encapsulation::Llc => mtu.min(llc::MAX_LENGTH_FIELD as usize) - llc::HEADER_LENGTH,
+        };
+    }
+
+    /// largest frame including MAC header and VLAN tags, for sizing buffers
+    pub fn maximum_frame_size(&self) -> usize {
+        let mut tags_length = 0;
+        if self.service_vlan_id.is_some() {
+            tags_length += vlan::TAG_LENGTH;
+        }
+        // NOTE: even without a VLAN, frames may be priority-tagged
+        tags_length += vlan::TAG_LENGTH;
+        return MAC_HEADER_LENGTH + tags_length + self.mtu.unwrap_or(ETHERNET_MTU);
+    }
+
+    /// tags to send a frame with the given priority code point
+    pub fn tags_for(&self, priority_code_point: u8) -> vlan::Tags {
+        return vlan::Tags {
+            service: self.service_vlan_id.map(|vlan_id| vlan::Tag { priority_code_point: priority_code_point, drop_eligible: false, vlan_id: vlan_id }),
+            // VLAN ID 0 is priority-tagged only
+            customer: if self.vlan_id.is_some() || priority_code_point != 0 {
+                Some(vlan::Tag { priority_code_point: priority_code_point, drop_eligible: false, vlan_id: self.vlan_id.unwrap_or(0) })
+            } else {
+                None
+            },
+        };
+    }
+
+    /// whether a received frame with the given tags belongs to this service instance
+    pub fn accepts(&self, tags: &vlan::Tags) -> bool {
+        let service_vlan_id = tags.service.map(|tag| tag.vlan_id);
+        let vlan_id = tags.customer.map(|tag| tag.vlan_id).filter(|vlan_id| *vlan_id != 0);
+        return service_vlan_id == self.service_vlan_id && vlan_id == self.vlan_id;
+    }
+}
 
 // NOTE: According to X.233 5.5 "Underlying service assumed by the protocol", the CLNP can run on a data link or a real subnet, 
 // which both operate on the Data Link Layer. This is basically where telecom technology and computer networking technology meet
@@ -17,45 +140,135 @@ pub const ETHER_TYPE_CLNP: u16 = 0x8872;  // as per https://datatracker.ietf.org
 
 // is a subnetwork-dependent QoS code, different from the OSI NS QoS codes
 // TODO is ^ true?
-#[derive(Debug)]
-pub struct Qos {}
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Qos {
+    /// IEEE 802.1Q priority code point - 0 is best effort, sent untagged
+    pub priority_code_point: u8,
+    /// VLAN the frame was received on - on sending, the VLAN of the interface configuration applies
+    pub vlan_id: Option<u16>,
+    /// QinQ service VLAN the frame was received on
+    pub service_vlan_id: Option<u16>,
+}
 
 impl Qos {
     pub fn from_ns_quality_of_service(ns_quality_of_service: &super::n::Qos) -> Self {
-        //TODO implement
-        return Qos{};
+        // X.233 priority 1 to 14 (highest) go onto PCP 2 (excellent effort) to 6 (internetwork control),
+        // PCP 7 (network control) is left for the routing protocols
+        // without priority, low transit delay first goes onto PCP 4 (controlled load), low cost first onto PCP 1 (background),
+        // anything else stays best effort
+        //TODO residual error probability has no equivalent on Ethernet
+        let priority_code_point = if ns_quality_of_service.priority != 0 {
+            2 + (ns_quality_of_service.priority.min(14) - 1) * 5 / 14
+        } else if ns_quality_of_service.low_transit_delay_first() {
+            4
+        } else if ns_quality_of_service.low_cost_first() {
+            1
+        } else {
+            0
+        };
+        return Qos{ priority_code_point: priority_code_point, vlan_id: None, service_vlan_id: None };
+    }
+
+    /// reverse of from_ns_quality_of_service() for a received frame, as far as the PCP tells -
+    /// the lowest NS priority going onto the PCP, resp. low cost first for background
+    pub fn to_ns_quality_of_service(&self) -> super::n::Qos {
+        let mut ns_quality_of_service = super::n::Qos::default();
+        match self.priority_code_point {
+            0 => {},
+            1 => { ns_quality_of_service.cost = 1; },
+            2..=6 => { ns_quality_of_service.priority = 1 + (self.priority_code_point - 2) * 3; },
+            _ => { ns_quality_of_service.priority = 14; },
+        }
+        return ns_quality_of_service;
+    }
+}
+
+/// number of queues from NS to SN - the SN always serves the highest band (lowest index) first
+pub const PRIORITY_BANDS: usize = 3;
+/// ES-IS, IS-IS, echo request and echo response - should not queue behind data
+pub const BAND_CONTROL: usize = 0;
+/// data with NS priority above normal
+pub const BAND_PRIORITY: usize = 1;
+pub const BAND_NORMAL: usize = 2;
+
+pub fn band_for_quality_of_service(ns_quality_of_service: &super::n::Qos) -> usize {
+    if ns_quality_of_service.priority != 0 || ns_quality_of_service.low_transit_delay_first() {
+        return BAND_PRIORITY;
+    }
+    return BAND_NORMAL;
+}
+
+/// frame transport underneath a subnetwork service - each read and each write carries exactly one whole frame,
+/// starting at the destination MAC address
+pub trait FrameSocket: Read + Write + Send + 'static {
+    /// another handle onto the same transport for the other direction, like RawPacketStream::clone()
+    fn try_clone(&self) -> Result<Self, Error> where Self: Sized;
+    /// whether the link is usable, e.g. administratively up and carrier present - None if this transport cannot tell
+    fn link_up(&self) -> Option<bool> {
+        return None;
+    }
+    /// re-create the transport after an error, e.g. when the interface was removed and has reappeared
+    fn reopen(&mut self) -> Result<(), Error> {
+        return Err(Error::new(ErrorKind::Unsupported, "transport cannot be reopened"));
+    }
+    /// have the kernel drop frames not for this SN already, see bpf::clns_filter()
+    fn attach_filter(&mut self, _program: &[bpf::Instruction]) -> Result<(), Error> {
+        return Err(Error::new(ErrorKind::Unsupported, "transport has no socket filter"));
+    }
+    /// receive frames sent to the given group address
+    fn join_group(&mut self, _group_address: MacAddr6) -> Result<(), Error> {
+        return Err(Error::new(ErrorKind::Unsupported, "transport has no group address membership"));
+    }
+    /// receive at least one frame, and whatever further frames have arrived already, handing each to on_frame -
+    /// transports with a ring shared with the kernel override this, so that frames need not be copied into the buffer first
+    fn read_batch(&mut self, buffer: &mut [u8], on_frame: &mut dyn FnMut(&[u8])) -> Result<usize, Error> {
+        let bytes = self.read(buffer)?;
+        on_frame(&buffer[0..bytes]);
+        return Ok(1);
+    }
+}
+
+impl FrameSocket for RawPacketStream {
+    fn try_clone(&self) -> Result<Self, Error> {
+        return Ok(self.clone());
     }
 }
 
 pub trait SubnetworkService<'a> {
+    type Socket: FrameSocket;
     fn new(
-        socket: RawPacketStream,
-        n_service_from: rtrb::Consumer<SNUnitDataRequest>,
+        socket: Self::Socket,
+        interface_config: InterfaceConfig,
+        n_service_from: Vec<rtrb::Consumer<SNUnitDataRequest>>,   // one per priority band
         n_service_to: rtrb::Producer<NUnitDataIndication>,
-        n_service_to_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>
+        n_service_status: rtrb::Producer<SubnetworkEvent>,
+        n_service_to_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
+        n_service_from_waiting: Arc<Mutex<Vec<Thread>>>     // senders waiting for room in n_service_from, to be woken up
     ) -> Self where Self: Sized;    //TODO make network service exchangeable without requiring "dyn" (optimize)
     /// called by NS
     fn sn_unitdata_request(//&mut self,
         buffer_out: &mut [u8],
-        socket: &mut RawPacketStream,
+        socket: &mut Self::Socket,
+        interface_config: &InterfaceConfig,
         // actual parameters
         sn_source_address: MacAddr6,
         sn_destination_address: MacAddr6,
         sn_quality_of_service: Qos,
         sn_userdata: &[u8],
-    );
+    ) -> Result<(), Error>;
     /// called by NS
-    fn flush(&mut self);
+    fn flush(&mut self) -> Result<(), Error>;
     /// called by run()
+    /// NOTE: does not wake up the NS, the caller does that once per batch of frames
     fn sn_unitdata_indication(//&self,
         n_service_to: &mut rtrb::Producer<NUnitDataIndication>,
-        n_service_to_wakeup: &JoinHandle<Thread>,
+        buffer_pool: &pool::BufferPool,
         // actual parameters
         sn_source_address: MacAddr6,
         sn_destination_address: MacAddr6,
         sn_quality_of_service: Qos,
-        sn_userdata: &'a [u8]
-    );
+        sn_userdata: &[u8]
+    ) -> bool;
     fn run(&self,
         ns2sn_consumer_wakeup_give: Arc<Mutex<Option<JoinHandle<Thread>>>>
     );
@@ -67,4 +280,32 @@ pub struct SNUnitDataRequest {
     pub sn_destination_address: MacAddr6,
     pub sn_quality_of_service: Qos,
     pub sn_userdata: Vec<u8>,
-}
\ No newline at end of file
+}
+
+/// change of the subnetwork state as noticed by the SN, reported upward so that ES-IS and routing can react
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubnetworkEvent {
+    Up,
+    Down,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_of_service_round_trip() {
+        for priority in 0..=14 {
+            let ns_quality_of_service = crate::n::Qos { priority: priority, ..Default::default() };
+            let sn_quality_of_service = Qos::from_ns_quality_of_service(&ns_quality_of_service);
+            // lands on the same PCP again, and priority stays priority
+            let received = sn_quality_of_service.to_ns_quality_of_service();
+            assert_eq!(Qos::from_ns_quality_of_service(&received).priority_code_point, sn_quality_of_service.priority_code_point);
+            assert_eq!(received.priority != 0, priority != 0);
+        }
+        let low_cost = crate::n::Qos { cost: 2, transit_delay: 1, ..Default::default() };
+        assert!(Qos::from_ns_quality_of_service(&low_cost).to_ns_quality_of_service().low_cost_first());
+        assert_eq!(Qos::default().to_ns_quality_of_service(), crate::n::Qos::default());
+        assert_eq!(Qos { priority_code_point: 7, ..Default::default() }.to_ns_quality_of_service().priority, 14);
+    }
+}
@@ -0,0 +1,339 @@
+use std::{ffi::CString, io::{Error, ErrorKind}, os::unix::io::RawFd};
+
+use advmac::MacAddr6;
+use libc::c_int;
+
+use super::{Encapsulation, InterfaceConfig, llc, vlan};
+
+// classic BPF, see Linux Documentation/networking/filter.rst and include/uapi/linux/filter.h
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+const BPF_IMM: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_AND: u16 = 0x50;
+const BPF_JA: u16 = 0x00;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_K: u16 = 0x00;
+/// ancillary data instead of packet contents
+const SKF_AD_OFF: u32 = (-0x1000i32) as u32;
+const SKF_AD_PKTTYPE: u32 = 4;
+/// sll_pkttype of frames sent by this host, which an ETH_P_ALL socket sees as well
+const PACKET_OUTGOING: u32 = 4;
+const ACCEPT: u32 = 0xFFFF_FFFF;
+const DROP: u32 = 0;
+
+const SO_ATTACH_FILTER: c_int = 26;
+const SOL_PACKET: c_int = 263;
+const PACKET_ADD_MEMBERSHIP: c_int = 1;
+const PACKET_MR_MULTICAST: u16 = 0;
+
+/// struct sock_filter
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Instruction {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+/// struct sock_fprog
+#[repr(C)]
+struct Program {
+    len: u16,
+    filter: *const Instruction,
+}
+
+/// struct packet_mreq
+#[repr(C)]
+struct PacketMreq {
+    ifindex: c_int,
+    mr_type: u16,
+    alen: u16,
+    address: [u8; 8],
+}
+
+/// where a conditional jump goes
+#[derive(Clone, Copy)]
+enum Jump {
+    Next,
+    To(usize),
+}
+
+/// resolves labels into the relative forward jumps of classic BPF
+struct Assembler {
+    instructions: Vec<(u16, Jump, Jump, u32)>,
+    labels: Vec<Option<usize>>,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        return Assembler { instructions: vec![], labels: vec![] };
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        return self.labels.len() - 1;
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.instructions.len());
+    }
+
+    fn emit(&mut self, code: u16, k: u32) {
+        self.instructions.push((code, Jump::Next, Jump::Next, k));
+    }
+
+    fn jump(&mut self, code: u16, k: u32, jt: Jump, jf: Jump) {
+        self.instructions.push((BPF_JMP | code | BPF_K, jt, jf, k));
+    }
+
+    fn assemble(self) -> Result<Vec<Instruction>, Error> {
+        let mut program = Vec::with_capacity(self.instructions.len());
+        for (index, (code, jt, jf, k)) in self.instructions.iter().enumerate() {
+            let offset = |jump: &Jump| -> Result<u32, Error> {
+                return match jump {
+                    Jump::Next => Ok(0),
+                    Jump::To(label) => {
+                        let target = self.labels[*label].expect("BPF label not placed");
+                        if target <= index {
+                            return Err(Error::new(ErrorKind::InvalidInput, "BPF cannot jump backwards"));
+                        }
+                        Ok((target - index - 1) as u32)
+                    }
+                };
+            };
+            let mut instruction = Instruction { code: *code, jt: 0, jf: 0, k: *k };
+            if *code == BPF_JMP | BPF_JA {
+                // unconditional jumps take their offset in k and can go further
+                instruction.k = offset(jt)?;
+            } else {
+                let (jt, jf) = (offset(jt)?, offset(jf)?);
+                if jt > u8::MAX as u32 || jf > u8::MAX as u32 {
+                    return Err(Error::new(ErrorKind::InvalidInput, "BPF jump too far"));
+                }
+                instruction.jt = jt as u8;
+                instruction.jf = jf as u8;
+            }
+            program.push(instruction);
+        }
+        return Ok(program);
+    }
+}
+
+/// accepts only frames for the configured encapsulation, sent by others and destined to the own MAC address,
+/// broadcast or one of the given group addresses
+/// NOTE: VLAN membership is still checked by the SN itself
+pub fn clns_filter(interface_config: &InterfaceConfig, own_address: MacAddr6, group_addresses: &[MacAddr6]) -> Result<Vec<Instruction>, Error> {
+    let mut asm = Assembler::new();
+    let drop = asm.label();
+    let accept = asm.label();
+
+    // not our own outgoing frames
+    asm.emit(BPF_LD | BPF_B | BPF_ABS, SKF_AD_OFF + SKF_AD_PKTTYPE);
+    asm.jump(BPF_JEQ, PACKET_OUTGOING, Jump::To(drop), Jump::Next);
+
+    // destination MAC address
+    let destination_ok = asm.label();
+    let mut addresses = vec![own_address, MacAddr6::broadcast()];
+    addresses.extend_from_slice(group_addresses);
+    for address in addresses {
+        let octets = address.to_array();
+        let next_address = asm.label();
+        asm.emit(BPF_LD | BPF_W | BPF_ABS, 0);
+        asm.jump(BPF_JEQ, u32::from_be_bytes([octets[0], octets[1], octets[2], octets[3]]), Jump::Next, Jump::To(next_address));
+        asm.emit(BPF_LD | BPF_H | BPF_ABS, 4);
+        asm.jump(BPF_JEQ, u16::from_be_bytes([octets[4], octets[5]]) as u32, Jump::To(destination_ok), Jump::Next);
+        asm.place(next_address);
+    }
+    asm.emit(BPF_RET | BPF_K, DROP);
+    asm.place(destination_ok);
+
+    // find EtherType resp. length field behind the VLAN tags, if any, and put its offset into X
+    // NOTE: with VLAN offloading, the kernel has taken the tags out already
+    let service_tagged = asm.label();
+    let customer_tagged = asm.label();
+    let untagged = asm.label();
+    let type_field = asm.label();
+    asm.emit(BPF_LD | BPF_H | BPF_ABS, 12);
+    asm.jump(BPF_JEQ, vlan::TPID_SERVICE as u32, Jump::To(service_tagged), Jump::Next);
+    asm.jump(BPF_JEQ, vlan::TPID_SERVICE_LEGACY as u32, Jump::To(service_tagged), Jump::Next);
+    asm.jump(BPF_JEQ, vlan::TPID_CUSTOMER as u32, Jump::To(customer_tagged), Jump::To(untagged));
+    asm.place(service_tagged);
+    let double_tagged = asm.label();
+    asm.emit(BPF_LD | BPF_H | BPF_ABS, 16);
+    asm.jump(BPF_JEQ, vlan::TPID_CUSTOMER as u32, Jump::To(double_tagged), Jump::To(customer_tagged));
+    asm.place(double_tagged);
+    asm.emit(BPF_LDX | BPF_W | BPF_IMM, 12 + 2 * vlan::TAG_LENGTH as u32);
+    asm.jump(BPF_JA, 0, Jump::To(type_field), Jump::Next);
+    asm.place(customer_tagged);
+    asm.emit(BPF_LDX | BPF_W | BPF_IMM, 12 + vlan::TAG_LENGTH as u32);
+    asm.jump(BPF_JA, 0, Jump::To(type_field), Jump::Next);
+    asm.place(untagged);
+    asm.emit(BPF_LDX | BPF_W | BPF_IMM, 12);
+    asm.place(type_field);
+
+    // encapsulation
+    asm.emit(BPF_LD | BPF_H | BPF_IND, 0);
+    match interface_config.encapsulation {
+        Encapsulation::EthernetII => {
+            asm.jump(BPF_JEQ, super::ETHER_TYPE_CLNP as u32, Jump::To(accept), Jump::To(drop));
+        },
+        Encapsulation::Llc => {
+            // length field, then DSAP and SSAP, then UI control
+            asm.jump(BPF_JGT, llc::MAX_LENGTH_FIELD as u32, Jump::To(drop), Jump::Next);
+            asm.emit(BPF_LD | BPF_H | BPF_IND, 2);
+            // NOTE: lowest bit of SSAP is command/response, like in llc::from_buf()
+            asm.emit(BPF_ALU | BPF_AND | BPF_K, 0xFFFE);
+            asm.jump(BPF_JEQ, u16::from_be_bytes([llc::SAP_OSI, llc::SAP_OSI]) as u32, Jump::Next, Jump::To(drop));
+            asm.emit(BPF_LD | BPF_B | BPF_IND, 4);
+            asm.jump(BPF_JEQ, llc::CONTROL_UI as u32, Jump::To(accept), Jump::To(drop));
+        }
+    }
+
+    asm.place(drop);
+    asm.emit(BPF_RET | BPF_K, DROP);
+    asm.place(accept);
+    asm.emit(BPF_RET | BPF_K, ACCEPT);
+    return asm.assemble();
+}
+
+/// have the kernel run the program on every frame before it is queued to the socket
+/// NOTE: frames queued between bind() and this are not filtered, the SN still checks everything itself
+pub fn attach(fd: RawFd, program: &[Instruction]) -> Result<(), Error> {
+    let fprog = Program { len: program.len() as u16, filter: program.as_ptr() };
+    let result = unsafe { libc::setsockopt(fd, libc::SOL_SOCKET, SO_ATTACH_FILTER, &fprog as *const Program as *const libc::c_void, std::mem::size_of::<Program>() as libc::socklen_t) };
+    if result < 0 {
+        return Err(Error::last_os_error());
+    }
+    return Ok(());
+}
+
+/// have the interface receive frames to the given group address
+pub fn add_membership(fd: RawFd, interface_name: &str, group_address: MacAddr6) -> Result<(), Error> {
+    let name = CString::new(interface_name).map_err(|_| Error::new(ErrorKind::InvalidInput, "interface name contains NUL"))?;
+    let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if ifindex == 0 {
+        return Err(Error::last_os_error());
+    }
+    let mut mreq = PacketMreq { ifindex: ifindex as c_int, mr_type: PACKET_MR_MULTICAST, alen: 6, address: [0; 8] };
+    mreq.address[0..6].copy_from_slice(&group_address.to_array());
+    let result = unsafe { libc::setsockopt(fd, SOL_PACKET, PACKET_ADD_MEMBERSHIP, &mreq as *const PacketMreq as *const libc::c_void, std::mem::size_of::<PacketMreq>() as libc::socklen_t) };
+    if result < 0 {
+        return Err(Error::last_os_error());
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWN: MacAddr6 = MacAddr6::new([0x02, 0, 0, 0, 0, 0x01]);
+    const PEER: MacAddr6 = MacAddr6::new([0x02, 0, 0, 0, 0, 0x02]);
+
+    /// the part of the classic BPF machine clns_filter() uses - returns how many octets of the frame to accept
+    fn run(program: &[Instruction], frame: &[u8], pkttype: u32) -> u32 {
+        let load = |offset: u32, size: u32| -> Option<u32> {
+            if offset == SKF_AD_OFF + SKF_AD_PKTTYPE {
+                return Some(pkttype);
+            }
+            let bytes = frame.get(offset as usize..(offset + size) as usize)?;
+            return Some(bytes.iter().fold(0u32, |value, byte| (value << 8) | *byte as u32));
+        };
+        let size = |code: u16| match code & 0x18 { BPF_W => 4, BPF_H => 2, _ => 1 };
+        let (mut a, mut x, mut pc) = (0u32, 0u32, 0usize);
+        loop {
+            let instruction = program[pc];
+            pc += 1;
+            match instruction.code & 0x07 {
+                BPF_LD => {
+                    let offset = if instruction.code & 0xE0 == BPF_IND { x + instruction.k } else { instruction.k };
+                    // NOTE: the kernel drops the frame if a load runs past its end
+                    let Some(value) = load(offset, size(instruction.code)) else { return DROP; };
+                    a = value;
+                },
+                BPF_LDX => { x = instruction.k; },
+                BPF_ALU => { a &= instruction.k; },
+                BPF_JMP if instruction.code == BPF_JMP | BPF_JA => { pc += instruction.k as usize; },
+                BPF_JMP => {
+                    let taken = match instruction.code & 0xF0 { BPF_JEQ => a == instruction.k, BPF_JGT => a > instruction.k, code => panic!("unexpected jump {:x}", code) };
+                    pc += if taken { instruction.jt } else { instruction.jf } as usize;
+                },
+                BPF_RET => { return instruction.k; },
+                class => panic!("unexpected instruction class {:x}", class),
+            }
+        }
+    }
+
+    fn frame(destination: MacAddr6, tags: &[(u16, u16)], inner: &[u8]) -> Vec<u8> {
+        let mut frame = destination.to_array().to_vec();
+        frame.extend_from_slice(&PEER.to_array());
+        for (tpid, tci) in tags {
+            frame.extend_from_slice(&tpid.to_be_bytes());
+            frame.extend_from_slice(&tci.to_be_bytes());
+        }
+        frame.extend_from_slice(inner);
+        return frame;
+    }
+
+    fn clnp() -> Vec<u8> {
+        let mut inner = super::super::ETHER_TYPE_CLNP.to_be_bytes().to_vec();
+        inner.extend_from_slice(&[0x81, 1, 2, 3]);
+        return inner;
+    }
+
+    fn llc(ssap: u8) -> Vec<u8> {
+        let mut inner = 7u16.to_be_bytes().to_vec();
+        inner.extend_from_slice(&[llc::SAP_OSI, ssap, llc::CONTROL_UI, 0x81, 1, 2, 3]);
+        return inner;
+    }
+
+    #[test]
+    fn ethernet_frames() {
+        let group = MacAddr6::new([0x09, 0x00, 0x2B, 0x00, 0x00, 0x04]);
+        let program = clns_filter(&InterfaceConfig::default(), OWN, &[group]).unwrap();
+        let accepted = |frame: &[u8], pkttype: u32| run(&program, frame, pkttype) == ACCEPT;
+        // untagged, C-tag and QinQ
+        assert!(accepted(&frame(OWN, &[], &clnp()), 0));
+        assert!(accepted(&frame(OWN, &[(vlan::TPID_CUSTOMER, 100)], &clnp()), 0));
+        assert!(accepted(&frame(OWN, &[(vlan::TPID_SERVICE, 200), (vlan::TPID_CUSTOMER, 100)], &clnp()), 0));
+        assert!(accepted(&frame(OWN, &[(vlan::TPID_SERVICE_LEGACY, 200)], &clnp()), 0));
+        // broadcast and group addresses
+        assert!(accepted(&frame(MacAddr6::broadcast(), &[], &clnp()), 0));
+        assert!(accepted(&frame(group, &[], &clnp()), 0));
+        // foreign MAC, outgoing, other EtherType, LLC and runt frames
+        assert!(!accepted(&frame(MacAddr6::new([0x02, 0, 0, 0, 0, 0x03]), &[], &clnp()), 0));
+        assert!(!accepted(&frame(OWN, &[], &clnp()), PACKET_OUTGOING));
+        assert!(!accepted(&frame(OWN, &[], &[0x08, 0x00, 0x45, 0]), 0));
+        assert!(!accepted(&frame(OWN, &[], &llc(llc::SAP_OSI)), 0));
+        assert!(!accepted(&OWN.to_array(), 0));
+    }
+
+    #[test]
+    fn llc_frames() {
+        let config = InterfaceConfig { encapsulation: Encapsulation::Llc, ..Default::default() };
+        let program = clns_filter(&config, OWN, &[]).unwrap();
+        let accepted = |frame: &[u8]| run(&program, frame, 0) == ACCEPT;
+        assert!(accepted(&frame(OWN, &[], &llc(llc::SAP_OSI))));
+        assert!(accepted(&frame(OWN, &[(vlan::TPID_SERVICE, 200), (vlan::TPID_CUSTOMER, 100)], &llc(llc::SAP_OSI))));
+        // response, same as llc::from_buf()
+        assert!(accepted(&frame(OWN, &[], &llc(llc::SAP_OSI | 0x01))));
+        assert!(llc::from_buf(&llc(llc::SAP_OSI | 0x01)[2..], 7).is_ok());
+        // other SAP, not UI, EtherType instead of length
+        assert!(!accepted(&frame(OWN, &[], &llc(0x42))));
+        let mut not_ui = llc(llc::SAP_OSI);
+        not_ui[4] = 0xE3;   // TEST command
+        assert!(!accepted(&frame(OWN, &[], &not_ui)));
+        assert!(!accepted(&frame(OWN, &[], &clnp())));
+        assert!(!accepted(&frame(PEER, &[], &llc(llc::SAP_OSI))));
+    }
+}
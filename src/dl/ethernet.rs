@@ -1,104 +1,135 @@
-use std::{io::{Write, Read}, thread::{self, Thread, JoinHandle}, sync::{Arc, Mutex}};
+use std::{io::{Error, ErrorKind}, thread::{self, Thread, JoinHandle}, sync::{Arc, Mutex}};
 
 use advmac::MacAddr6;
-use afpacket::sync::RawPacketStream;
-use etherparse::{Ethernet2Header, ether_type, SingleVlanHeaderSlice};
+use etherparse::ether_type;
 extern crate simplelog; //TODO check the paris feature flag for tags, useful?
 
-use crate::n::{self, NUnitDataIndication};
+use crate::n::NUnitDataIndication;
 
-use super::{SubnetworkService, Qos, SNUnitDataRequest};
+use super::{SubnetworkService, FrameSocket, Qos, SNUnitDataRequest, SubnetworkEvent, InterfaceConfig, Encapsulation, llc, vlan, packet::PacketSocket, pool::BufferPool};
 
-pub struct Service {
-    socket: RawPacketStream,
-    buffer_in: Arc<Mutex<[u8; 1500]>>,  // from socket
-    buffer_out: Arc<Mutex<[u8; 1500]>>, // out into socket
+pub struct Service<S = PacketSocket> {
+    socket: S,
+    interface_config: InterfaceConfig,
+    buffer_in: Arc<Mutex<Vec<u8>>>,  // from socket, sized for the MTU of the interface
+    buffer_out: Arc<Mutex<Vec<u8>>>, // out into socket
 
-    n_service_from: Arc<Mutex<rtrb::Consumer<SNUnitDataRequest>>>,
+    n_service_from: Arc<Mutex<Vec<rtrb::Consumer<SNUnitDataRequest>>>>,
+    /// NS threads waiting for room in n_service_from, X.233 6.18 backpressure
+    n_service_from_waiting: Arc<Mutex<Vec<Thread>>>,
     n_service_to: Arc<Mutex<rtrb::Producer<NUnitDataIndication>>>,
+    n_service_status: Arc<Mutex<rtrb::Producer<SubnetworkEvent>>>,
     n_service_to_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
+    /// last reported subnetwork state, shared between the reading, writing and link monitoring threads
+    link_up: Arc<Mutex<bool>>,
+    /// buffers for the SN user data handed up to the NS, one for every place in the queue
+    buffer_pool: BufferPool,
 }
 
-impl<'a> SubnetworkService<'a> for Service {
+impl<'a, S: FrameSocket> SubnetworkService<'a> for Service<S> {
+    type Socket = S;
+
     fn new(
-        socket: RawPacketStream,
-        n_service_from: rtrb::Consumer<SNUnitDataRequest>,
+        socket: S,
+        interface_config: InterfaceConfig,
+        n_service_from: Vec<rtrb::Consumer<SNUnitDataRequest>>,
         n_service_to: rtrb::Producer<NUnitDataIndication>,
-        n_service_to_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>
+        n_service_status: rtrb::Producer<SubnetworkEvent>,
+        n_service_to_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
+        n_service_from_waiting: Arc<Mutex<Vec<Thread>>>
     ) -> Self {
+        let buffer_pool = BufferPool::new(interface_config.maximum_sdu_size(), n_service_to.buffer().capacity());
         Service {
             socket: socket,
-            buffer_in: Arc::new(Mutex::new([0u8; 1500])),
-            buffer_out: Arc::new(Mutex::new([0u8; 1500])),
+            interface_config: interface_config,
+            buffer_in: Arc::new(Mutex::new(vec![0u8; interface_config.maximum_frame_size()])),
+            buffer_out: Arc::new(Mutex::new(vec![0u8; interface_config.maximum_frame_size()])),
             n_service_from: Arc::new(Mutex::new(n_service_from)),
+            n_service_from_waiting: n_service_from_waiting,
             n_service_to: Arc::new(Mutex::new(n_service_to)),
-            n_service_to_wakeup: n_service_to_wakeup
+            n_service_status: Arc::new(Mutex::new(n_service_status)),
+            n_service_to_wakeup: n_service_to_wakeup,
+            link_up: Arc::new(Mutex::new(true)),
+            buffer_pool: buffer_pool,
         }
     }
 
     fn sn_unitdata_request(
         //&mut self,    // TODO optimize - instead of &mut self, we need to hand over buffer and socket
         buffer_out: &mut [u8],
-        socket: &mut RawPacketStream,
+        socket: &mut S,
+        interface_config: &InterfaceConfig,
         sn_source_address: MacAddr6,
         sn_destination_address: MacAddr6,
         sn_quality_of_service: Qos,
         sn_userdata: &[u8],
-    ) {
+    ) -> Result<(), Error> {
         // send SNSDU (Ethernet frame)
-        //TODO optimize - here an Ethernet2 header is allocated, which copies the values from sn_* - better something which borrows the values
-        let pkt_out = Ethernet2Header{
-            destination: sn_destination_address.to_array(),
-            source: sn_source_address.to_array(),
-            ether_type: crate::dl::ETHER_TYPE_CLNP,
+        // EtherType resp. 802.3 length field which comes after the addresses and the VLAN tags, if any
+        let inner_type = match interface_config.encapsulation {
+            Encapsulation::EthernetII => crate::dl::ETHER_TYPE_CLNP,
+            Encapsulation::Llc => (llc::HEADER_LENGTH + sn_userdata.len()) as u16,
         };
         //println!("writing SNSDU...");
-        //let mut buffer_out = self.buffer_out.lock().expect("failed to lock buffer");
-        let remainder = pkt_out.write_to_slice(buffer_out).expect("failed writing SNSDU into buffer");
-        //pkt_out.write(&mut self.socket).expect("failed writing frame into socket");
-        //TODO optimize is ^ cheaper or below's sn_userdata pdu.into_buf() ?
+        buffer_out[0..6].copy_from_slice(&sn_destination_address.to_array());
+        buffer_out[6..12].copy_from_slice(&sn_source_address.to_array());
+        let mut header_length = 12;
+        header_length += vlan::write_tags(&mut buffer_out[header_length..], &interface_config.tags_for(sn_quality_of_service.priority_code_point));
+        buffer_out[header_length..header_length+2].copy_from_slice(&inner_type.to_be_bytes());
+        header_length += 2;
+        if interface_config.encapsulation == Encapsulation::Llc {
+            header_length += llc::write_header(&mut buffer_out[header_length..]);
+        }
 
         // send NPDU (CLNP PDU)
         //println!("writing NPDU...");
         //let bytes = sn_userdata.into_buf(true, &mut remainder);
         let bytes = sn_userdata.len();  //TODO optimize - maybe it makes more sense to use the Vec<u8> which run2() already has
-        for i in 0..sn_userdata.len() {
-            remainder[i] = sn_userdata[i];
+        if header_length + bytes > buffer_out.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, "SN user data exceeds MTU of interface"));
         }
-        socket.write(&buffer_out[0..bytes + 14]).expect("could not write buffer into socket");    //TODO +14 is not cleanly abtracted //TODO handle network down - dont crash, but try again
-
-        //println!("flushing DL...");
-        socket.flush().expect("failed to flush socket");
+        buffer_out[header_length..header_length+bytes].copy_from_slice(sn_userdata);
+        socket.write(&buffer_out[0..header_length + bytes])?;
+        // NOTE: flushed by run() after the batch, which is when a ring-based socket actually sends
         debug!("sent via SN");
+        return Ok(());
     }
 
-    fn flush(&mut self) {
-        self.socket.flush().expect("failed to flush my own socket!");
+    fn flush(&mut self) -> Result<(), Error> {
+        return self.socket.flush();
     }
 
     fn sn_unitdata_indication(
         n_service_to: &mut rtrb::Producer<NUnitDataIndication>, //TODO optimize clunky - &mut self would be nice but complains about 2 mutable borrows to self
-        n_service_to_wakeup: &JoinHandle<Thread>,
+        buffer_pool: &BufferPool,
         // actual parameters
         sn_source_address: MacAddr6,
         sn_destination_address: MacAddr6,
         sn_quality_of_service: Qos,
-        sn_userdata: &'a [u8]
-    ) {
-        let n_quality_of_service = n::Qos{}; //TODO from sn_quality_of_service  //TODO optimize - new allocation on every call
+        sn_userdata: &[u8]
+    ) -> bool {
+        // NOTE: NS recovers priority and QoS from the options part of Data PDUs, the PCP matters for the inactive subset which has none
+        let n_quality_of_service = sn_quality_of_service.to_ns_quality_of_service();
         //TODO the source and destination addresses should probably also be converted to NSAPs for the N layer protocol
 
         // forward up from DL to N layer
         //TODO this method will need &mut self at some point, but this will create 2 borrows - one for read and one for write
         //TODO must enable 2 threads working inside NClnpService.
         //TODO modify to have NClnpService .read and .write inner parts - only these get borrowed. And these 2 only lock the shared host lists etc. when really needed.
-        n_service_to.push(NUnitDataIndication{
+        // NOTE: the only copy on the way up - from the socket buffer resp. the RX ring into a pooled buffer, which then travels up as is
+        let mut ns_userdata = buffer_pool.get();
+        ns_userdata.extend_from_slice(sn_userdata);
+        if n_service_to.push(NUnitDataIndication{
             ns_source_address: sn_source_address,
             ns_destination_address: sn_destination_address,
             ns_quality_of_service: n_quality_of_service,
-            ns_userdata: sn_userdata.to_vec()    //TODO optimize
-        }).expect("failed to push NUnitDataIndication into n_service_to");
-        n_service_to_wakeup.thread().unpark();  //TODO optimize thread() call - could be prepared by caller already
+            ns_userdata: ns_userdata
+        }).is_err() {
+            //TODO count in management information
+            warn!("NS is not keeping up, discarding SN-UNITDATA indication");
+            return false;
+        }
+        return true;
     }
 
     /// read and write to/from socket
@@ -107,80 +138,429 @@ impl<'a> SubnetworkService<'a> for Service {
     ) {
         // read SN-UNITDATA Indications from the socket
         let buffer_in_arc = self.buffer_in.clone();
-        let mut socket1 = self.socket.clone();   //TODO optimize
+        let mut socket1 = self.socket.try_clone().expect("failed to clone socket");   //TODO optimize
         let n_service_to_arc = self.n_service_to.clone();
         let n_service_to_wakeup_arc = self.n_service_to_wakeup.clone();
+        let n_service_status_arc = self.n_service_status.clone();
+        let link_up_arc = self.link_up.clone();
+        let interface_config = self.interface_config;
+        let buffer_pool = self.buffer_pool.clone();
         let _ = thread::Builder::new().name("SN Ethernet <- OS".to_string()).spawn(move || {
-            let mut buffer_in = *buffer_in_arc.lock().expect("failed to lock buffer_in");
-            //let mut buffer_in = [0u8; 1500];
+            let mut buffer_in = buffer_in_arc.lock().expect("failed to lock buffer_in");
             let mut n_service_to = n_service_to_arc.lock().expect("failed to lock n_service_to");  //TODO optimize - gets locked on every iteration
-            let n_service_to_wakeup_outer = n_service_to_wakeup_arc.lock().expect("failed to lock n_service_to_wakeup (taker)");
-            let n_service_to_wakeup = n_service_to_wakeup_outer.as_ref().unwrap();
+            // NOTE: take a copy of the thread handle, the link monitoring thread needs the mutex as well
+            let n_service_to_wakeup = n_service_to_wakeup_arc.lock().expect("failed to lock n_service_to_wakeup (taker)").as_ref().unwrap().thread().clone();
+            // kept across consecutive failures, so that a permanently broken transport is not retried in a tight loop
+            let mut backoff = crate::dl::RETRY_BACKOFF_INITIAL;
+            let mut failed = false;
             loop {
-                //let mut buffer = [0u8; 1500];
-                debug!("reading frame...");
-                let num_bytes = socket1.read(&mut buffer_in).expect("could not read DL frame from socket into buffer"); //TODO handle network down - dont crash, but try again
-
-                // hand-cooked version, because we dont care about getting IP and TCP/UDP parsed
-                let eth_header = etherparse::Ethernet2HeaderSlice::from_slice(&buffer_in).expect("could not parse Ethernet2 header");
-                debug!("destination: {:x?}  source: {:x?}  ethertype: 0x{:04x}", eth_header.destination(), eth_header.source(), eth_header.ether_type());
-                let mut vlan_len: usize = 0;
-                match eth_header.ether_type() {
-                    ether_type::VLAN_TAGGED_FRAME | ether_type::PROVIDER_BRIDGING | ether_type::VLAN_DOUBLE_TAGGED_FRAME => {
-                        let buffer_length = buffer_in.len();
-                        let vlan_header = SingleVlanHeaderSlice::from_slice(&buffer_in[eth_header.slice().len()-1..buffer_length-1]).expect("could not parse single VLAN header");
-                        debug!("vlan: {:?}", vlan_header);
-                        vlan_len = vlan_header.slice().len();
-                        //TODO handle what comes after vlan
+                debug!("reading frames...");
+                let mut pushed_any = false;
+                let result = socket1.read_batch(&mut buffer_in, &mut |frame| {
+                    if Self::receive_frame(frame, &interface_config, &mut n_service_to, &buffer_pool) {
+                        pushed_any = true;
+                    }
+                });
+                // wake up NS once for the whole batch
+                if pushed_any {
+                    n_service_to_wakeup.unpark();
+                }
+                match result {
+                    Ok(_) => {
+                        if failed {
+                            // NOTE: the link monitoring thread may have reported this already
+                            set_link_state(&link_up_arc, &n_service_status_arc, &n_service_to_wakeup_arc, SubnetworkEvent::Up);
+                            failed = false;
+                            backoff = crate::dl::RETRY_BACKOFF_INITIAL;
+                        }
                     },
-                    ether_type::IPV6 => { debug!("{}", "got ipv6, ignoring"); }
-                    ether_type::IPV4 => { debug!("{}", "got ipv4, ignoring"); }
-                    ETHER_TYPE_CLNP => { debug!("ah, got CLNP - feel warmly welcome!"); } //TODO optimize - does the order of match legs affect performance?
-                    _ => { info!("{}", "got unknown EtherType, discarding"); }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                    Err(e) => {
+                        // e.g. ENETDOWN when the interface was set down, ENODEV resp. ENXIO when it was removed
+                        warn!("could not read DL frame from socket: {}", e);
+                        set_link_state(&link_up_arc, &n_service_status_arc, &n_service_to_wakeup_arc, SubnetworkEvent::Down);
+                        recover(&mut socket1, &mut backoff);
+                        failed = true;
+                    }
                 }
-
-                // send up the stack to Subnetwork Service as SN-UNITDATA Indication
-                let qos = Qos{};    //TODO optimize allocation
-                Self::sn_unitdata_indication(
-                    &mut n_service_to, //TODO optimize clunky - &mut self would be nice but complains about 2 mutable borrows to self
-                    n_service_to_wakeup,
-                    MacAddr6::from(eth_header.source()),
-                    MacAddr6::from(eth_header.destination()),
-                    qos,
-                    &buffer_in[0+eth_header.slice().len() .. num_bytes]    //TODO plus VLAN 802.11q (?) header, if present
-                );
             }
         });
 
         // read SN-UNITDATA-REQUEST from NS
         let n_service_from_arc = self.n_service_from.clone();
-        let mut socket2 = self.socket.clone();   //TODO optimize
+        let n_service_from_waiting_arc = self.n_service_from_waiting.clone();
+        let mut socket2 = self.socket.try_clone().expect("failed to clone socket");   //TODO optimize
         let buffer_out_arc = self.buffer_out.clone();
+        let interface_config = self.interface_config;
+        let n_service_to_wakeup_arc = self.n_service_to_wakeup.clone();
+        let n_service_status_arc = self.n_service_status.clone();
+        let link_up_arc = self.link_up.clone();
         let ns2sn_consumer_wakeup = thread::Builder::new().name("SN Ethernet <- N".to_string()).spawn(move || {
             let mut n_service_from = n_service_from_arc.lock().expect("failed to lock n_service_from");
-            let mut buffer_out = *buffer_out_arc.lock().expect("failed to lock buffer_out");
+            let mut buffer_out = buffer_out_arc.lock().expect("failed to lock buffer_out");
+            // after a write error, the socket is re-created before the next write
+            let mut needs_reopen = false;
             loop {
-                // pop all
+                // pop all, always from the highest non-empty priority band
+                let mut wrote_any = false;
                 loop {
-                    if let Ok(sn_unitdata_request) = n_service_from.pop() {
+                    let mut sn_unitdata_request = None;
+                    for band in n_service_from.iter_mut() {
+                        if let Ok(request) = band.pop() {
+                            sn_unitdata_request = Some(request);
+                            break;
+                        }
+                    }
+                    if let Some(sn_unitdata_request) = sn_unitdata_request {
+                        wrote_any = true;
+                        // there is room in the queue again
+                        for waiting in n_service_from_waiting_arc.lock().expect("failed to lock n_service_from_waiting").drain(..) {
+                            waiting.unpark();
+                        }
                         debug!("got sn_unitdata_request from NS: {:?}", sn_unitdata_request);
-                        Self::sn_unitdata_request(
+                        if needs_reopen {
+                            match socket2.reopen() {
+                                Ok(_) => { needs_reopen = false; },
+                                Err(e) if e.kind() == ErrorKind::Unsupported => { needs_reopen = false; },
+                                Err(e) => {
+                                    // NOTE: CLNP is connectionless, so dropping is the right thing - not blocking the NS
+                                    debug!("socket still unusable, dropping SN-UNITDATA request: {}", e);
+                                    continue;
+                                }
+                            }
+                        }
+                        if let Err(e) = Self::sn_unitdata_request(
                             &mut buffer_out,
                             &mut socket2,
+                            &interface_config,
                             sn_unitdata_request.sn_source_address,
                             sn_unitdata_request.sn_destination_address,
                             sn_unitdata_request.sn_quality_of_service,
                             sn_unitdata_request.sn_userdata.as_slice()  //TODO optimize
-                        );
+                        ) {
+                            warn!("could not write SNSDU into socket, dropping it: {}", e);
+                            if e.kind() != ErrorKind::Interrupted && e.kind() != ErrorKind::WouldBlock {
+                                set_link_state(&link_up_arc, &n_service_status_arc, &n_service_to_wakeup_arc, SubnetworkEvent::Down);
+                                needs_reopen = true;
+                            }
+                        }
                     } else {
                         break;
                     }
                 }
+                // send the whole batch
+                if wrote_any && !needs_reopen {
+                    if let Err(e) = socket2.flush() {
+                        warn!("could not flush socket, frames may be lost: {}", e);
+                        set_link_state(&link_up_arc, &n_service_status_arc, &n_service_to_wakeup_arc, SubnetworkEvent::Down);
+                        needs_reopen = true;
+                    }
+                }
                 //TODO even when done, check again, if a new batch has arrived in the meantime (we dont notice a further wakeups while this thread is running)
                 thread::park(); // wait for unpark wakeup call from NS
             }
         }).expect("failed to start thread");
         // put thread handle into well-known place
         ns2sn_consumer_wakeup_give.lock().expect("failed to lock ns2sn_consumer_wakeup (giver)").replace(ns2sn_consumer_wakeup);
+
+        // watch the link state, because losing the carrier does not produce an error on the socket
+        let socket3 = self.socket.try_clone().expect("failed to clone socket");   //TODO optimize
+        if socket3.link_up().is_some() {
+            let n_service_to_wakeup_arc = self.n_service_to_wakeup.clone();
+            let n_service_status_arc = self.n_service_status.clone();
+            let link_up_arc = self.link_up.clone();
+            let _ = thread::Builder::new().name("SN Ethernet link".to_string()).spawn(move || {
+                loop {
+                    if let Some(up) = socket3.link_up() {
+                        let event = if up { SubnetworkEvent::Up } else { SubnetworkEvent::Down };
+                        set_link_state(&link_up_arc, &n_service_status_arc, &n_service_to_wakeup_arc, event);
+                    }
+                    thread::sleep(crate::dl::LINK_POLL_INTERVAL);
+                }
+            });
+        }
+    }
+}
+
+impl<S: FrameSocket> Service<S> {
+    /// parse a received frame and hand it up if it is for us - returns whether something was handed up
+    fn receive_frame(frame: &[u8], interface_config: &InterfaceConfig, n_service_to: &mut rtrb::Producer<NUnitDataIndication>, buffer_pool: &BufferPool) -> bool {
+        // hand-cooked version, because we dont care about getting IP and TCP/UDP parsed
+        let eth_header = match etherparse::Ethernet2HeaderSlice::from_slice(frame) {
+            Ok(eth_header) => eth_header,
+            Err(e) => {
+                debug!("discarding runt frame: {:?}", e);
+                return false;
+            }
+        };
+        debug!("destination: {:x?}  source: {:x?}  ethertype: 0x{:04x}", eth_header.destination(), eth_header.source(), eth_header.ether_type());
+        // NOTE: with VLAN offloading the kernel strips the tag before we see the frame - then frames for a configured VLAN
+        // are not recognized as such, turn it off using "ethtool -K <interface> rxvlan off" or use the PACKET_MMAP ring, which puts it back
+        //TODO read stripped tags from PACKET_AUXDATA instead
+        let (tags, inner_type, header_length) = match vlan::from_buf(frame) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                debug!("discarding frame: {}", e);
+                return false;
+            }
+        };
+        if !interface_config.accepts(&tags) {
+            debug!("frame for other VLAN {:?}, discarding", tags);
+            return false;
+        }
+        let sn_userdata: &[u8];
+        match interface_config.encapsulation {
+            Encapsulation::EthernetII => {
+                match inner_type {
+                    crate::dl::ETHER_TYPE_CLNP => { debug!("ah, got CLNP - feel warmly welcome!"); } //TODO optimize - does the order of match legs affect performance?
+                    ether_type::IPV6 => { debug!("{}", "got ipv6, ignoring"); return false; }
+                    ether_type::IPV4 => { debug!("{}", "got ipv4, ignoring"); return false; }
+                    _ => { info!("{}", "got unknown EtherType, discarding"); return false; }
+                }
+                sn_userdata = &frame[header_length..];
+            },
+            Encapsulation::Llc => {
+                match llc::from_buf(&frame[header_length..], inner_type) {
+                    Ok(llc_userdata) => { sn_userdata = llc_userdata; },
+                    Err(e) => {
+                        debug!("discarding frame: {}", e);
+                        return false;
+                    }
+                }
+            }
+        }
+
+        // send up the stack to Subnetwork Service as SN-UNITDATA Indication
+        let qos = Qos {    //TODO optimize allocation
+            priority_code_point: tags.customer.or(tags.service).map(|tag| tag.priority_code_point).unwrap_or(0),
+            vlan_id: tags.customer.map(|tag| tag.vlan_id).filter(|vlan_id| *vlan_id != 0),
+            service_vlan_id: tags.service.map(|tag| tag.vlan_id),
+        };
+        return <Self as SubnetworkService>::sn_unitdata_indication(
+            n_service_to, //TODO optimize clunky - &mut self would be nice but complains about 2 mutable borrows to self
+            buffer_pool,
+            MacAddr6::from(eth_header.source()),
+            MacAddr6::from(eth_header.destination()),
+            qos,
+            sn_userdata
+        );
+    }
+}
+
+/// report a change of the subnetwork state to the NS - only transitions are reported
+fn set_link_state(
+    link_up: &Mutex<bool>,
+    n_service_status: &Mutex<rtrb::Producer<SubnetworkEvent>>,
+    n_service_to_wakeup: &Mutex<Option<JoinHandle<Thread>>>,
+    event: SubnetworkEvent
+) {
+    let mut link_up = link_up.lock().expect("failed to lock link_up");
+    let up = event == SubnetworkEvent::Up;
+    if *link_up == up {
+        return;
+    }
+    *link_up = up;
+    info!("subnetwork is {:?}", event);
+    if n_service_status.lock().expect("failed to lock n_service_status").push(event).is_err() {
+        warn!("could not report subnetwork state change to NS, queue full");
+    }
+    if let Some(n_service_to_wakeup) = n_service_to_wakeup.lock().expect("failed to lock n_service_to_wakeup").as_ref() {
+        n_service_to_wakeup.thread().unpark();
+    }
+}
+
+/// wait until the socket is usable again, with exponential backoff - re-binds to the interface if the transport can
+fn recover<S: FrameSocket>(socket: &mut S, backoff: &mut std::time::Duration) {
+    loop {
+        thread::sleep(*backoff);
+        *backoff = (*backoff * 2).min(crate::dl::RETRY_BACKOFF_MAXIMUM);
+        if socket.link_up() == Some(false) {
+            debug!("link still down, waiting {:?}", backoff);
+            continue;
+        }
+        match socket.reopen() {
+            Ok(_) => { return; },
+            // transport has nothing to re-create, just try reading again
+            Err(e) if e.kind() == ErrorKind::Unsupported => { return; },
+            Err(e) => { debug!("could not reopen socket: {}, waiting {:?}", e, backoff); }
+        }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, io::{Read, Write}, time::{Duration, Instant}};
+
+    use super::*;
+
+    /// what the fake socket does and what was done to it, shared between all its clones
+    #[derive(Default)]
+    struct Script {
+        /// results of the next reads, frames resp. errors
+        reads: VecDeque<Result<Vec<u8>, ErrorKind>>,
+        /// failing reopen attempts before one succeeds
+        reopen_failures: usize,
+        reopened: Vec<Instant>,
+        link_up: Option<bool>,
+        write_error: Option<ErrorKind>,
+    }
+
+    #[derive(Clone)]
+    struct FakeSocket {
+        script: Arc<Mutex<Script>>,
+    }
+
+    impl Read for FakeSocket {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let next = self.script.lock().unwrap().reads.pop_front();
+            match next {
+                Some(Ok(frame)) => {
+                    buf[0..frame.len()].copy_from_slice(&frame);
+                    return Ok(frame.len());
+                },
+                Some(Err(kind)) => return Err(Error::new(kind, "scripted read error")),
+                None => {
+                    // nothing arrived, the read thread just tries again
+                    thread::sleep(Duration::from_millis(1));
+                    return Err(Error::new(ErrorKind::Interrupted, "no frame"));
+                }
+            }
+        }
+    }
+
+    impl Write for FakeSocket {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            return match self.script.lock().unwrap().write_error {
+                Some(kind) => Err(Error::new(kind, "scripted write error")),
+                None => Ok(buf.len()),
+            };
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            return Ok(());
+        }
+    }
+
+    impl FrameSocket for FakeSocket {
+        fn try_clone(&self) -> Result<Self, Error> {
+            return Ok(self.clone());
+        }
+
+        fn link_up(&self) -> Option<bool> {
+            return self.script.lock().unwrap().link_up;
+        }
+
+        fn reopen(&mut self) -> Result<(), Error> {
+            let mut script = self.script.lock().unwrap();
+            script.reopened.push(Instant::now());
+            if script.reopen_failures > 0 {
+                script.reopen_failures -= 1;
+                return Err(Error::new(ErrorKind::NotFound, "scripted reopen error"));
+            }
+            return Ok(());
+        }
+    }
+
+    struct Running {
+        script: Arc<Mutex<Script>>,
+        n_service_from: Vec<rtrb::Producer<SNUnitDataRequest>>,
+        n_service_from_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
+        n_service_to: rtrb::Consumer<NUnitDataIndication>,
+        n_service_status: rtrb::Consumer<SubnetworkEvent>,
+    }
+
+    impl Running {
+        /// wait for the next subnetwork event, up to the given time
+        fn event(&mut self, timeout: Duration) -> Option<SubnetworkEvent> {
+            let start = Instant::now();
+            while start.elapsed() < timeout {
+                if let Ok(event) = self.n_service_status.pop() {
+                    return Some(event);
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            return None;
+        }
+    }
+
+    fn run(script: Script) -> Running {
+        let script = Arc::new(Mutex::new(script));
+        let (n_service_from, consumers): (Vec<_>, Vec<_>) = (0..crate::dl::PRIORITY_BANDS).map(|_| rtrb::RingBuffer::new(4)).unzip();
+        let (n_service_to_producer, n_service_to) = rtrb::RingBuffer::new(4);
+        let (n_service_status_producer, n_service_status) = rtrb::RingBuffer::new(8);
+        let sn = <Service<FakeSocket> as SubnetworkService>::new(
+            FakeSocket { script: script.clone() },
+            InterfaceConfig::default(),
+            consumers,
+            n_service_to_producer,
+            n_service_status_producer,
+            Arc::new(Mutex::new(Some(thread::spawn(thread::current)))),
+            Arc::new(Mutex::new(vec![]))
+        );
+        let n_service_from_wakeup = Arc::new(Mutex::new(None));
+        sn.run(n_service_from_wakeup.clone());
+        return Running { script: script, n_service_from: n_service_from, n_service_from_wakeup: n_service_from_wakeup, n_service_to: n_service_to, n_service_status: n_service_status };
+    }
+
+    fn clnp_frame() -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02];
+        frame.extend_from_slice(&crate::dl::ETHER_TYPE_CLNP.to_be_bytes());
+        frame.extend_from_slice(&[0x81, 1, 2, 3]);
+        return frame;
+    }
+
+    #[test]
+    fn read_error_backoff() {
+        let mut sn = run(Script {
+            reads: VecDeque::from([Err(ErrorKind::NetworkDown), Ok(clnp_frame())]),
+            reopen_failures: 2,
+            ..Default::default()
+        });
+        assert_eq!(sn.event(Duration::from_secs(1)), Some(SubnetworkEvent::Down));
+        // back up once the socket could be reopened and a frame was read again
+        assert_eq!(sn.event(Duration::from_secs(5)), Some(SubnetworkEvent::Up));
+        assert_eq!(sn.n_service_to.pop().unwrap().ns_userdata.as_slice(), &[0x81, 1, 2, 3]);
+        // each attempt waits twice as long as the one before
+        let reopened = sn.script.lock().unwrap().reopened.clone();
+        assert_eq!(reopened.len(), 3);
+        assert!(reopened[1] - reopened[0] >= crate::dl::RETRY_BACKOFF_INITIAL * 2);
+        assert!(reopened[2] - reopened[1] >= crate::dl::RETRY_BACKOFF_INITIAL * 4);
+        // only transitions are reported
+        assert_eq!(sn.event(Duration::from_millis(200)), None);
+    }
+
+    #[test]
+    fn link_state_events() {
+        let mut sn = run(Script { link_up: Some(true), ..Default::default() });
+        // up already, nothing to report
+        assert_eq!(sn.event(crate::dl::LINK_POLL_INTERVAL / 2), None);
+        sn.script.lock().unwrap().link_up = Some(false);
+        assert_eq!(sn.event(crate::dl::LINK_POLL_INTERVAL * 3), Some(SubnetworkEvent::Down));
+        sn.script.lock().unwrap().link_up = Some(true);
+        assert_eq!(sn.event(crate::dl::LINK_POLL_INTERVAL * 3), Some(SubnetworkEvent::Up));
+    }
+
+    #[test]
+    fn write_error_down() {
+        let mut sn = run(Script { write_error: Some(ErrorKind::NetworkDown), ..Default::default() });
+        while sn.n_service_from_wakeup.lock().unwrap().is_none() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        sn.n_service_from[0].push(SNUnitDataRequest {
+            sn_source_address: MacAddr6::new([0x02, 0, 0, 0, 0, 0x01]),
+            sn_destination_address: MacAddr6::new([0x02, 0, 0, 0, 0, 0x02]),
+            sn_quality_of_service: Qos::default(),
+            sn_userdata: vec![0x81, 1, 2, 3],
+        }).unwrap();
+        sn.n_service_from_wakeup.lock().unwrap().as_ref().unwrap().thread().unpark();
+        assert_eq!(sn.event(Duration::from_secs(1)), Some(SubnetworkEvent::Down));
+        // a write failing the same way again is no new transition
+        sn.n_service_from[0].push(SNUnitDataRequest {
+            sn_source_address: MacAddr6::new([0x02, 0, 0, 0, 0, 0x01]),
+            sn_destination_address: MacAddr6::new([0x02, 0, 0, 0, 0, 0x02]),
+            sn_quality_of_service: Qos::default(),
+            sn_userdata: vec![0x81, 1, 2, 3],
+        }).unwrap();
+        sn.n_service_from_wakeup.lock().unwrap().as_ref().unwrap().thread().unpark();
+        assert_eq!(sn.event(Duration::from_millis(200)), None);
+    }
+}
@@ -0,0 +1,317 @@
+use std::{cmp::Ordering, collections::BinaryHeap, io::{Error, Read, Write}, sync::{Arc, Mutex, mpsc::{self, Sender, Receiver}}, thread::{self, Thread}, time::{Duration, Instant}};
+
+use advmac::MacAddr6;
+use rand::Rng;
+
+use super::FrameSocket;
+
+// simulated broadcast segment connecting any number of stack instances in the same process,
+// as planned in doc 05 ("dummy SN or dummy raw socket") - for testing CLNP, ES-IS and routing without raw sockets or NICs
+
+/// applied by the hub on every delivery to a port
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Impairments {
+    /// probability from 0.0 to 1.0 that a frame is lost
+    pub loss: f64,
+    /// probability that a frame is delivered twice
+    pub duplication: f64,
+    /// probability that a frame is held back and delivered after the next frame to the same port
+    pub reordering: f64,
+    pub delay: Duration,
+    /// random additional delay up to this - also reorders frames
+    pub jitter: Duration,
+    /// longest frame the segment carries (from destination MAC address on), longer ones are dropped - None is unlimited
+    pub mtu: Option<usize>,
+}
+
+/// how long a frame held back for reordering waits for the next frame to the same port before it is delivered anyway
+const HELD_BACK_TIMEOUT: Duration = Duration::from_millis(50);
+/// NOTE: the timer thread looks this often whether the hub is gone, when there is nothing to do
+const TIMER_IDLE: Duration = Duration::from_secs(1);
+
+struct Port {
+    macaddr: MacAddr6,
+    to_port: Sender<Vec<u8>>,
+    /// frame held back for reordering and since when
+    held_back: Option<(Vec<u8>, Instant)>,
+}
+
+/// frame on the delay line, due at the given time - ordered so that the BinaryHeap pops the earliest first
+struct Delayed {
+    due: Instant,
+    /// keeps frames due at the same time in order
+    sequence: u64,
+    port: usize,
+    frame: Vec<u8>,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        return self.due == other.due && self.sequence == other.sequence;
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return (other.due, other.sequence).cmp(&(self.due, self.sequence));
+    }
+}
+
+struct HubInner {
+    ports: Vec<Port>,
+    impairments: Impairments,
+    delay_line: BinaryHeap<Delayed>,
+    next_sequence: u64,
+    /// to wake up the timer thread when something is due earlier than it knows
+    timer: Option<Thread>,
+}
+
+#[derive(Clone)]
+pub struct Hub {
+    inner: Arc<Mutex<HubInner>>,
+}
+
+impl Hub {
+    /// starts the timer thread for delayed and held back frames, which ends when the hub is gone
+    pub fn new(impairments: Impairments) -> Self {
+        let inner = Arc::new(Mutex::new(HubInner { ports: vec![], impairments: impairments, delay_line: BinaryHeap::new(), next_sequence: 0, timer: None }));
+        let inner_weak = Arc::downgrade(&inner);
+        let timer = thread::Builder::new().name("SN hub timer".to_string()).spawn(move || {
+            loop {
+                let Some(inner_arc) = inner_weak.upgrade() else {
+                    return;
+                };
+                let now = Instant::now();
+                let next = inner_arc.lock().expect("failed to lock hub").run_timers(now);
+                drop(inner_arc);    // do not keep the hub alive while waiting
+                thread::park_timeout(next.map_or(TIMER_IDLE, |next| next.saturating_duration_since(now)).min(TIMER_IDLE));
+            }
+        }).expect("failed to start thread");
+        inner.lock().expect("failed to lock hub").timer = Some(timer.thread().clone());
+        return Hub { inner: inner };
+    }
+
+    /// change impairments while running, e.g. to simulate a link going bad
+    pub fn set_impairments(&self, impairments: Impairments) {
+        self.inner.lock().expect("failed to lock hub").impairments = impairments;
+    }
+
+    /// connect a new station with the given MAC address - it receives frames addressed to it and group addressed frames,
+    /// like a NIC not in promiscuous mode
+    pub fn port(&self, macaddr: MacAddr6) -> HubPort {
+        let (to_port, from_hub) = mpsc::channel();
+        let mut inner = self.inner.lock().expect("failed to lock hub");
+        inner.ports.push(Port { macaddr: macaddr, to_port: to_port, held_back: None });
+        return HubPort { index: inner.ports.len() - 1, hub: self.inner.clone(), from_hub: Arc::new(Mutex::new(from_hub)) };
+    }
+}
+
+impl HubInner {
+    fn forward(&mut self, from_index: usize, frame: &[u8]) {
+        let impairments = self.impairments;
+        if frame.len() < 6 {
+            return;
+        }
+        if let Some(mtu) = impairments.mtu {
+            if frame.len() > mtu {
+                debug!("hub: frame of {} bytes exceeds MTU {}, dropping", frame.len(), mtu);
+                return;
+            }
+        }
+        let group_address = frame[0] & 0x01 != 0;
+        let mut rng = rand::thread_rng();
+        let now = Instant::now();
+        let mut deliveries: Vec<(usize, Vec<u8>)> = vec![];
+        let mut held_any = false;
+        for (index, port) in self.ports.iter_mut().enumerate() {
+            if index == from_index || (!group_address && frame[0..6] != port.macaddr.to_array()) {
+                continue;
+            }
+            if rng.gen_bool(impairments.loss.clamp(0.0, 1.0)) {
+                debug!("hub: losing frame to port {}", index);
+                continue;
+            }
+            let copies = if rng.gen_bool(impairments.duplication.clamp(0.0, 1.0)) { 2 } else { 1 };
+            if port.held_back.is_none() && rng.gen_bool(impairments.reordering.clamp(0.0, 1.0)) {
+                port.held_back = Some((frame.to_vec(), now));
+                held_any = true;
+                continue;
+            }
+            for _ in 0..copies {
+                deliveries.push((index, frame.to_vec()));
+            }
+            if let Some((held_back, _)) = port.held_back.take() {
+                deliveries.push((index, held_back));
+            }
+        }
+        if held_any {
+            self.wake_timer();  //TODO optimize - only needed if it sleeps longer than the held back timeout
+        }
+        for (index, frame) in deliveries {
+            self.deliver(index, frame, now, &mut rng);
+        }
+    }
+
+    /// send to the port right away, or put on the delay line
+    fn deliver(&mut self, index: usize, frame: Vec<u8>, now: Instant, rng: &mut impl Rng) {
+        let mut delay = self.impairments.delay;
+        if !self.impairments.jitter.is_zero() {
+            delay += self.impairments.jitter.mul_f64(rng.gen::<f64>());
+        }
+        if delay.is_zero() {
+            let _ = self.ports[index].to_port.send(frame);  // station may be gone already
+            return;
+        }
+        let due = now + delay;
+        let earliest = self.delay_line.peek().is_none_or(|first| due < first.due);
+        self.delay_line.push(Delayed { due: due, sequence: self.next_sequence, port: index, frame: frame });
+        self.next_sequence += 1;
+        if earliest {
+            self.wake_timer();
+        }
+    }
+
+    fn wake_timer(&self) {
+        if let Some(timer) = &self.timer {
+            timer.unpark();
+        }
+    }
+
+    /// deliver what is due on the delay line and held back frames whose timeout has expired, returns when to look again
+    fn run_timers(&mut self, now: Instant) -> Option<Instant> {
+        let mut rng = rand::thread_rng();
+        for index in 0..self.ports.len() {
+            if self.ports[index].held_back.as_ref().is_some_and(|(_, since)| now >= *since + HELD_BACK_TIMEOUT) {
+                let (held_back, _) = self.ports[index].held_back.take().expect("held back frame vanished");
+                debug!("hub: no further frame to port {}, delivering held back frame", index);
+                self.deliver(index, held_back, now, &mut rng);
+            }
+        }
+        while self.delay_line.peek().is_some_and(|first| first.due <= now) {
+            let delayed = self.delay_line.pop().expect("delay line emptied");
+            let _ = self.ports[delayed.port].to_port.send(delayed.frame);    // station may be gone already
+        }
+        let held_back_next = self.ports.iter().filter_map(|port| port.held_back.as_ref().map(|(_, since)| *since + HELD_BACK_TIMEOUT)).min();
+        let delay_line_next = self.delay_line.peek().map(|first| first.due);
+        return held_back_next.into_iter().chain(delay_line_next).min();
+    }
+}
+
+/// connection of a station to the hub
+pub struct HubPort {
+    index: usize,
+    hub: Arc<Mutex<HubInner>>,
+    from_hub: Arc<Mutex<Receiver<Vec<u8>>>>,
+}
+
+impl Read for HubPort {
+    /// blocks until a frame arrives - longer frames than the buffer are truncated
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let frame = self.from_hub.lock().expect("failed to lock from_hub").recv()
+            .map_err(|_| Error::new(std::io::ErrorKind::BrokenPipe, "hub is gone"))?;
+        let bytes = frame.len().min(buf.len());
+        buf[0..bytes].copy_from_slice(&frame[0..bytes]);
+        return Ok(bytes);
+    }
+}
+
+impl Write for HubPort {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.hub.lock().expect("failed to lock hub").forward(self.index, buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        return Ok(());
+    }
+}
+
+impl FrameSocket for HubPort {
+    fn try_clone(&self) -> Result<Self, Error> {
+        return Ok(HubPort { index: self.index, hub: self.hub.clone(), from_hub: self.from_hub.clone() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: MacAddr6 = MacAddr6::new([0x02, 0, 0, 0, 0, 0x0a]);
+    const B: MacAddr6 = MacAddr6::new([0x02, 0, 0, 0, 0, 0x0b]);
+    const C: MacAddr6 = MacAddr6::new([0x02, 0, 0, 0, 0, 0x0c]);
+
+    fn frame(destination: MacAddr6, marker: u8) -> Vec<u8> {
+        let mut frame = destination.to_array().to_vec();
+        frame.extend_from_slice(&[marker; 54]);
+        return frame;
+    }
+
+    fn receive(port: &HubPort, timeout: Duration) -> Option<Vec<u8>> {
+        return port.from_hub.lock().unwrap().recv_timeout(timeout).ok();
+    }
+
+    #[test]
+    fn unicast_and_group_addressed() {
+        let hub = Hub::new(Impairments::default());
+        let mut a = hub.port(A);
+        let b = hub.port(B);
+        let c = hub.port(C);
+        a.write(&frame(B, 1)).unwrap();
+        assert_eq!(receive(&b, Duration::ZERO).unwrap()[6], 1);
+        assert!(receive(&c, Duration::ZERO).is_none());
+        a.write(&frame(MacAddr6::new([0x09, 0, 0x2b, 0, 0, 0x04]), 2)).unwrap();
+        assert_eq!(receive(&b, Duration::ZERO).unwrap()[6], 2);
+        assert_eq!(receive(&c, Duration::ZERO).unwrap()[6], 2);
+        // not back to the sender
+        assert!(receive(&a, Duration::ZERO).is_none());
+
+        hub.set_impairments(Impairments { mtu: Some(50), ..Default::default() });
+        a.write(&frame(B, 3)).unwrap();
+        hub.set_impairments(Impairments { loss: 1.0, ..Default::default() });
+        a.write(&frame(B, 4)).unwrap();
+        assert!(receive(&b, Duration::from_millis(10)).is_none());
+    }
+
+    #[test]
+    fn delayed_in_order() {
+        let delay = Duration::from_millis(30);
+        let hub = Hub::new(Impairments { delay: delay, ..Default::default() });
+        let mut a = hub.port(A);
+        let b = hub.port(B);
+        let sent = Instant::now();
+        for marker in 0..20 {
+            a.write(&frame(B, marker)).unwrap();
+        }
+        assert!(receive(&b, Duration::ZERO).is_none());
+        for marker in 0..20 {
+            assert_eq!(receive(&b, Duration::from_secs(1)).unwrap()[6], marker);
+        }
+        assert!(sent.elapsed() >= delay);
+        // all on the single timer thread
+        assert!(hub.inner.lock().unwrap().delay_line.is_empty());
+    }
+
+    #[test]
+    fn held_back_frame_reordered_or_flushed() {
+        let hub = Hub::new(Impairments { reordering: 1.0, ..Default::default() });
+        let mut a = hub.port(A);
+        let b = hub.port(B);
+        a.write(&frame(B, 1)).unwrap();
+        a.write(&frame(B, 2)).unwrap();
+        assert_eq!(receive(&b, Duration::ZERO).unwrap()[6], 2);
+        assert_eq!(receive(&b, Duration::ZERO).unwrap()[6], 1);
+
+        // nothing follows, still delivered after the timeout
+        a.write(&frame(B, 3)).unwrap();
+        assert!(receive(&b, Duration::ZERO).is_none());
+        assert_eq!(receive(&b, HELD_BACK_TIMEOUT * 20).unwrap()[6], 3);
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Error;
+
+// IEEE 802.2 Logical Link Control, Type 1 (connectionless) operation as used for carrying OSI network layer PDUs
+// on IEEE 802.3 - see ISO/IEC 8802-2 and ISO/TR 11802-1 for the SAP assignment
+
+/// link service access point of the ISO network layer protocols (CLNP, ES-IS, IS-IS)
+pub const SAP_OSI: u8 = 0xFE;
+/// unnumbered information, poll/final bit not set
+pub const CONTROL_UI: u8 = 0x03;
+/// DSAP, SSAP and control field of a Type 1 UI frame
+pub const HEADER_LENGTH: usize = 3;
+/// values of the 802.3 length/type field up to this are a length, from 0x0600 on it is an EtherType
+pub const MAX_LENGTH_FIELD: u16 = 1500;
+/// pseudo EtherType on Linux (ETH_P_802_2) for receiving 802.3 length field frames carrying LLC
+pub const ETHER_TYPE_802_2: u16 = 0x0004;
+
+/// write the LLC header for a UI frame from and to the OSI SAP, returns number of bytes written
+pub fn write_header(buffer: &mut [u8]) -> usize {
+    buffer[0] = SAP_OSI;    // DSAP
+    buffer[1] = SAP_OSI;    // SSAP, command
+    buffer[2] = CONTROL_UI;
+    return HEADER_LENGTH;
+}
+
+/// given the frame after the 802.3 length field and the value of the length field,
+/// return the LLC user data without header and without any padding added by the MAC
+pub fn from_buf(buffer: &[u8], length_field: u16) -> Result<&[u8], Error> {
+    if length_field > MAX_LENGTH_FIELD {
+        return Err(Error::new(std::io::ErrorKind::InvalidData, "not an 802.3 length field frame"));
+    }
+    let length = length_field as usize;
+    if length < HEADER_LENGTH || buffer.len() < length {
+        return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "LLC frame shorter than given by 802.3 length field"));
+    }
+    if buffer[0] != SAP_OSI || (buffer[1] & 0xFE) != SAP_OSI {  // lowest bit of SSAP is command/response
+        return Err(Error::new(std::io::ErrorKind::InvalidData, "LLC frame not for OSI network layer SAP"));
+    }
+    if buffer[2] != CONTROL_UI {
+        //TODO XID and TEST commands should be answered as per 802.2 Type 1 operation
+        return Err(Error::new(std::io::ErrorKind::Unsupported, "LLC frame is not unnumbered information"));
+    }
+    return Ok(&buffer[HEADER_LENGTH..length]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LLC header and user data as it follows the 802.3 length field, with padding by the MAC
+    fn frame(userdata: &[u8], padding: usize) -> (Vec<u8>, u16) {
+        let mut buffer = vec![0u8; HEADER_LENGTH];
+        assert_eq!(write_header(&mut buffer), HEADER_LENGTH);
+        buffer.extend_from_slice(userdata);
+        let length_field = buffer.len() as u16;
+        buffer.extend(std::iter::repeat(0).take(padding));
+        return (buffer, length_field);
+    }
+
+    #[test]
+    fn round_trip() {
+        let (buffer, length_field) = frame(&[0x81, 1, 2, 3], 0);
+        assert_eq!(&buffer[0..HEADER_LENGTH], &[SAP_OSI, SAP_OSI, CONTROL_UI]);
+        assert_eq!(from_buf(&buffer, length_field).unwrap(), &[0x81, 1, 2, 3]);
+        // padding up to the minimum frame size is not user data
+        let (padded, length_field) = frame(&[0x81, 1, 2, 3], 40);
+        assert_eq!(from_buf(&padded, length_field).unwrap(), &[0x81, 1, 2, 3]);
+    }
+
+    #[test]
+    fn truncated() {
+        let (buffer, length_field) = frame(&[0x81, 1, 2, 3], 0);
+        assert_eq!(from_buf(&buffer[0..buffer.len()-1], length_field).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+        // length field shorter than the LLC header itself
+        assert_eq!(from_buf(&buffer, 2).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+        // EtherType instead of a length
+        assert_eq!(from_buf(&buffer, MAX_LENGTH_FIELD + 1).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn wrong_sap() {
+        let (mut buffer, length_field) = frame(&[0x81, 1, 2, 3], 0);
+        // response bit in SSAP is fine
+        buffer[1] = SAP_OSI | 0x01;
+        assert!(from_buf(&buffer, length_field).is_ok());
+        buffer[1] = 0x42;
+        assert_eq!(from_buf(&buffer, length_field).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        buffer[1] = SAP_OSI;
+        buffer[0] = 0xAA;   // SNAP
+        assert_eq!(from_buf(&buffer, length_field).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        buffer[0] = SAP_OSI;
+        buffer[2] = 0xE3;   // TEST command
+        assert_eq!(from_buf(&buffer, length_field).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+    }
+}
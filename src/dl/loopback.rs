@@ -0,0 +1,50 @@
+use std::{io::{Error, Read, Write}, sync::{Arc, Mutex, mpsc::{self, Sender, Receiver}}};
+
+use super::FrameSocket;
+
+/// in-memory point-to-point link between two stacks in the same process, e.g. for testing without root privileges
+pub struct LoopbackSocket {
+    to_peer: Sender<Vec<u8>>,
+    from_peer: Arc<Mutex<Receiver<Vec<u8>>>>,
+}
+
+impl LoopbackSocket {
+    /// both ends of a new link - what is written into one end is read from the other end
+    pub fn pair() -> (LoopbackSocket, LoopbackSocket) {
+        let (a_to_b, b_from_a) = mpsc::channel();
+        let (b_to_a, a_from_b) = mpsc::channel();
+        return (
+            LoopbackSocket { to_peer: a_to_b, from_peer: Arc::new(Mutex::new(a_from_b)) },
+            LoopbackSocket { to_peer: b_to_a, from_peer: Arc::new(Mutex::new(b_from_a)) },
+        );
+    }
+}
+
+impl Read for LoopbackSocket {
+    /// blocks until the peer has written a frame - longer frames than the buffer are truncated
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let frame = self.from_peer.lock().expect("failed to lock from_peer").recv()
+            .map_err(|_| Error::new(std::io::ErrorKind::BrokenPipe, "loopback peer is gone"))?;
+        let bytes = frame.len().min(buf.len());
+        buf[0..bytes].copy_from_slice(&frame[0..bytes]);
+        return Ok(bytes);
+    }
+}
+
+impl Write for LoopbackSocket {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.to_peer.send(buf.to_vec())
+            .map_err(|_| Error::new(std::io::ErrorKind::BrokenPipe, "loopback peer is gone"))?;
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        return Ok(());
+    }
+}
+
+impl FrameSocket for LoopbackSocket {
+    fn try_clone(&self) -> Result<Self, Error> {
+        return Ok(LoopbackSocket { to_peer: self.to_peer.clone(), from_peer: self.from_peer.clone() });
+    }
+}
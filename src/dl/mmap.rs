@@ -0,0 +1,544 @@
+use std::{ffi::CString, io::{Error, ErrorKind}, os::unix::io::RawFd, ptr, sync::{Arc, Mutex, atomic::{fence, Ordering}}};
+
+use libc::c_int;
+
+// PACKET_MMAP with TPACKET_V3, see Linux Documentation/networking/packet_mmap.rst and include/uapi/linux/if_packet.h
+const SOL_PACKET: c_int = 263;
+const PACKET_RX_RING: c_int = 5;
+const PACKET_VERSION: c_int = 10;
+const PACKET_TX_RING: c_int = 13;
+const TPACKET_V3: c_int = 2;
+// block status in the RX ring
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+// frame status in the TX ring
+const TP_STATUS_AVAILABLE: u32 = 0;
+const TP_STATUS_SEND_REQUEST: u32 = 1;
+const TP_STATUS_WRONG_FORMAT: u32 = 4;
+// frame status in the RX ring - the kernel has removed the VLAN tag and put it into the header
+const TP_STATUS_VLAN_VALID: u32 = 0x10;
+const TP_STATUS_VLAN_TPID_VALID: u32 = 0x40;
+/// TPACKET3_HDRLEN minus sizeof(struct sockaddr_ll) - where the frame starts in a TX ring slot
+const TX_DATA_OFFSET: usize = 48;
+
+/// struct tpacket_req3
+#[repr(C)]
+struct TpacketReq3 {
+    block_size: u32,
+    block_nr: u32,
+    frame_size: u32,
+    frame_nr: u32,
+    retire_blk_tov: u32,
+    sizeof_priv: u32,
+    feature_req_word: u32,
+}
+
+/// struct tpacket_block_desc with struct tpacket_hdr_v1 inlined
+#[repr(C)]
+struct BlockDesc {
+    version: u32,
+    offset_to_priv: u32,
+    block_status: u32,
+    num_pkts: u32,
+    offset_to_first_pkt: u32,
+    blk_len: u32,
+    seq_num: u64,
+    ts_first_pkt: [u32; 2],
+    ts_last_pkt: [u32; 2],
+}
+
+/// struct tpacket3_hdr with struct tpacket_hdr_variant1 inlined
+#[repr(C)]
+struct Tpacket3Hdr {
+    next_offset: u32,
+    sec: u32,
+    nsec: u32,
+    snaplen: u32,
+    len: u32,
+    status: u32,
+    mac: u16,
+    net: u16,
+    rxhash: u32,
+    vlan_tci: u32,
+    vlan_tpid: u16,
+    padding: u16,
+    padding_end: [u8; 8],
+}
+
+/// layout of the rings shared with the kernel
+#[derive(Clone, Copy, Debug)]
+pub struct RingConfig {
+    /// must be a power-of-two multiple of the page size
+    pub block_size: usize,
+    pub rx_block_count: usize,
+    pub tx_block_count: usize,
+    /// TX slot size, must hold the largest frame plus 48 bytes of header
+    pub frame_size: usize,
+    /// the kernel hands over a block that is not full after this time, so that frames are not held back when there is little traffic
+    pub retire_timeout_ms: u32,
+}
+
+impl Default for RingConfig {
+    /// 4 MiB receive ring, 1 MiB transmit ring with 512 slots
+    fn default() -> Self {
+        RingConfig {
+            block_size: 1 << 18,
+            rx_block_count: 16,
+            tx_block_count: 4,
+            frame_size: 2048,
+            retire_timeout_ms: 10,
+        }
+    }
+}
+
+impl RingConfig {
+    /// TX slots large enough for the given frame size, e.g. for jumbo frames
+    pub fn for_maximum_frame_size(mut self, maximum_frame_size: usize) -> Self {
+        self.frame_size = self.frame_size.max((maximum_frame_size + TX_DATA_OFFSET).next_power_of_two());
+        self.block_size = self.block_size.max(self.frame_size);
+        return self;
+    }
+
+    fn frames_per_block(&self) -> usize {
+        return self.block_size / self.frame_size;
+    }
+
+    fn tx_frame_count(&self) -> usize {
+        return self.frames_per_block() * self.tx_block_count;
+    }
+}
+
+/// socket with its RX ring followed by its TX ring mapped into our memory
+struct Mapping {
+    fd: c_int,
+    base: *mut u8,
+    size: usize,
+    config: RingConfig,
+}
+
+// NOTE: the memory is only accessed under the locks in RingShared, the raw pointer is what keeps this from being Send automatically
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn open(interface_name: &str, ether_type: u16, config: RingConfig) -> Result<Self, Error> {
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, ether_type.to_be() as c_int) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        // from here on, Drop cleans up on error
+        let mut mapping = Mapping { fd: fd, base: ptr::null_mut(), size: (config.rx_block_count + config.tx_block_count) * config.block_size, config: config };
+        mapping.setsockopt(PACKET_VERSION, &TPACKET_V3)?;
+        mapping.setsockopt(PACKET_RX_RING, &TpacketReq3 {
+            block_size: config.block_size as u32,
+            block_nr: config.rx_block_count as u32,
+            frame_size: config.frame_size as u32,
+            frame_nr: (config.frames_per_block() * config.rx_block_count) as u32,
+            retire_blk_tov: config.retire_timeout_ms,
+            sizeof_priv: 0,
+            feature_req_word: 0,
+        })?;
+        // NOTE: the kernel insists on these being zero for the TX ring
+        mapping.setsockopt(PACKET_TX_RING, &TpacketReq3 {
+            block_size: config.block_size as u32,
+            block_nr: config.tx_block_count as u32,
+            frame_size: config.frame_size as u32,
+            frame_nr: config.tx_frame_count() as u32,
+            retire_blk_tov: 0,
+            sizeof_priv: 0,
+            feature_req_word: 0,
+        })?;
+        let base = unsafe { libc::mmap(ptr::null_mut(), mapping.size, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0) };
+        if base == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        mapping.base = base as *mut u8;
+
+        // bind to interface
+        let name = CString::new(interface_name).map_err(|_| Error::new(ErrorKind::InvalidInput, "interface name contains NUL"))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(Error::last_os_error());
+        }
+        let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_protocol = ether_type.to_be();
+        address.sll_ifindex = ifindex as c_int;
+        let result = unsafe { libc::bind(fd, &address as *const libc::sockaddr_ll as *const libc::sockaddr, std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t) };
+        if result < 0 {
+            return Err(Error::last_os_error());
+        }
+        info!("{}: mapped {} RX blocks and {} TX slots", interface_name, config.rx_block_count, config.tx_frame_count());
+        return Ok(mapping);
+    }
+
+    fn setsockopt<T>(&mut self, option: c_int, value: &T) -> Result<(), Error> {
+        let result = unsafe { libc::setsockopt(self.fd, SOL_PACKET, option, value as *const T as *const libc::c_void, std::mem::size_of::<T>() as libc::socklen_t) };
+        if result < 0 {
+            return Err(Error::last_os_error());
+        }
+        return Ok(());
+    }
+
+    fn rx_block(&self, index: usize) -> *mut BlockDesc {
+        return unsafe { self.base.add(index * self.config.block_size) } as *mut BlockDesc;
+    }
+
+    fn tx_frame(&self, index: usize) -> *mut Tpacket3Hdr {
+        let frames_per_block = self.config.frames_per_block();
+        let offset = (self.config.rx_block_count + index / frames_per_block) * self.config.block_size + (index % frames_per_block) * self.config.frame_size;
+        return unsafe { self.base.add(offset) } as *mut Tpacket3Hdr;
+    }
+
+    /// wait until the kernel has something for us resp. room - socket errors like ENETDOWN come out here
+    fn poll(&self, events: libc::c_short) -> Result<(), Error> {
+        let mut pollfd = libc::pollfd { fd: self.fd, events: events, revents: 0 };
+        let result = unsafe { libc::poll(&mut pollfd, 1, -1) };
+        if result < 0 {
+            return Err(Error::last_os_error());
+        }
+        if pollfd.revents & libc::POLLERR != 0 {
+            let mut error: c_int = 0;
+            let mut length = std::mem::size_of::<c_int>() as libc::socklen_t;
+            unsafe { libc::getsockopt(self.fd, libc::SOL_SOCKET, libc::SO_ERROR, &mut error as *mut c_int as *mut libc::c_void, &mut length) };
+            return Err(Error::from_raw_os_error(error));
+        }
+        if pollfd.revents & libc::POLLNVAL != 0 {
+            return Err(Error::new(ErrorKind::NotConnected, "socket closed"));
+        }
+        return Ok(());
+    }
+
+    /// have the kernel send all frames marked as send request
+    fn kick(&self) -> Result<(), Error> {
+        let result = unsafe { libc::send(self.fd, ptr::null(), 0, 0) };
+        if result < 0 {
+            return Err(Error::last_os_error());
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.base.is_null() {
+                libc::munmap(self.base as *mut libc::c_void, self.size);
+            }
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// where the reader is in the RX ring
+#[derive(Default)]
+struct RxCursor {
+    generation: u64,
+    block: usize,
+    /// packets of the current block already handed out
+    packet: u32,
+    /// offset of the next packet in the current block
+    offset: usize,
+    /// for frames whose VLAN tag has to be put back
+    scratch: Vec<u8>,
+}
+
+/// where the writer is in the TX ring
+#[derive(Default)]
+struct TxCursor {
+    generation: u64,
+    frame: usize,
+    /// frames marked as send request but not yet kicked off
+    pending: usize,
+}
+
+struct RingShared {
+    interface_name: String,
+    ether_type: u16,
+    config: RingConfig,
+    /// replaced on reopen - the generation tells the cursors and the other handles that it has been replaced
+    current: Mutex<(u64, Arc<Mapping>)>,
+    rx: Mutex<RxCursor>,
+    tx: Mutex<TxCursor>,
+}
+
+/// AF_PACKET socket with RX and TX rings shared with the kernel - frames are received in blocks without a system call per frame
+/// and sent in batches on flush()
+#[derive(Clone)]
+pub struct PacketRing {
+    shared: Arc<RingShared>,
+    /// generation of the mapping this handle has last seen
+    generation: u64,
+}
+
+impl PacketRing {
+    pub fn open(interface_name: &str, ether_type: u16, config: RingConfig) -> Result<Self, Error> {
+        let mapping = Mapping::open(interface_name, ether_type, config)?;
+        return Ok(PacketRing {
+            shared: Arc::new(RingShared {
+                interface_name: interface_name.to_owned(),
+                ether_type: ether_type,
+                config: config,
+                current: Mutex::new((0, Arc::new(mapping))),
+                rx: Mutex::new(RxCursor::default()),
+                tx: Mutex::new(TxCursor::default()),
+            }),
+            generation: 0,
+        });
+    }
+
+    fn current(&self) -> (u64, Arc<Mapping>) {
+        let current = self.shared.current.lock().expect("failed to lock current mapping");
+        return (current.0, current.1.clone());
+    }
+
+    /// hand the frames of ready blocks to on_frame, up to the given number - if wait is set, blocks until there is at least one
+    pub fn receive(&self, on_frame: &mut dyn FnMut(&[u8]), maximum_frames: usize, wait: bool) -> Result<usize, Error> {
+        let (generation, mapping) = self.current();
+        let mut rx = self.shared.rx.lock().expect("failed to lock rx cursor");
+        if rx.generation != generation {
+            *rx = RxCursor { generation: generation, ..RxCursor::default() };
+        }
+        let mut count = 0;
+        loop {
+            let block = mapping.rx_block(rx.block);
+            let block_status = unsafe { ptr::read_volatile(ptr::addr_of!((*block).block_status)) };
+            if block_status & TP_STATUS_USER == 0 {
+                if count > 0 || !wait {
+                    return Ok(count);
+                }
+                mapping.poll(libc::POLLIN)?;
+                continue;
+            }
+            // NOTE: see the block contents only after seeing the status
+            fence(Ordering::Acquire);
+            let num_pkts = unsafe { ptr::read_volatile(ptr::addr_of!((*block).num_pkts)) };
+            if rx.packet == 0 {
+                rx.offset = unsafe { ptr::read_volatile(ptr::addr_of!((*block).offset_to_first_pkt)) } as usize;
+            }
+            while rx.packet < num_pkts && count < maximum_frames {
+                let header = unsafe { (block as *mut u8).add(rx.offset) } as *const Tpacket3Hdr;
+                let (next_offset, snaplen, mac, status, vlan_tci, vlan_tpid) = unsafe {
+                    ((*header).next_offset as usize, (*header).snaplen as usize, (*header).mac as usize, (*header).status, (*header).vlan_tci as u16, (*header).vlan_tpid)
+                };
+                let frame = unsafe { std::slice::from_raw_parts((header as *const u8).add(mac), snaplen) };
+                if status & TP_STATUS_VLAN_VALID != 0 && frame.len() >= 12 {
+                    // put the tag the kernel has taken out back in, so that the SN sees the frame as it was on the wire
+                    let tpid = if status & TP_STATUS_VLAN_TPID_VALID != 0 { vlan_tpid } else { super::vlan::TPID_CUSTOMER };
+                    let scratch = &mut rx.scratch;
+                    scratch.clear();
+                    scratch.extend_from_slice(&frame[0..12]);
+                    scratch.extend_from_slice(&tpid.to_be_bytes());
+                    scratch.extend_from_slice(&vlan_tci.to_be_bytes());
+                    scratch.extend_from_slice(&frame[12..]);
+                    on_frame(&rx.scratch);
+                } else {
+                    on_frame(frame);
+                }
+                rx.offset += next_offset;
+                rx.packet += 1;
+                count += 1;
+            }
+            if rx.packet >= num_pkts {
+                // give block back to kernel
+                fence(Ordering::Release);
+                unsafe { ptr::write_volatile(ptr::addr_of_mut!((*block).block_status), TP_STATUS_KERNEL) };
+                rx.block = (rx.block + 1) % self.shared.config.rx_block_count;
+                rx.packet = 0;
+            }
+            if count >= maximum_frames {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// put the frame into the next TX slot - it goes out on flush(), or when half of the ring is waiting
+    pub fn send(&self, frame: &[u8]) -> Result<usize, Error> {
+        let (generation, mapping) = self.current();
+        let mut tx = self.shared.tx.lock().expect("failed to lock tx cursor");
+        if tx.generation != generation {
+            *tx = TxCursor { generation: generation, ..TxCursor::default() };
+        }
+        if frame.len() > self.shared.config.frame_size - TX_DATA_OFFSET {
+            return Err(Error::new(ErrorKind::InvalidInput, "frame does not fit into TX ring slot"));
+        }
+        let slot = mapping.tx_frame(tx.frame);
+        loop {
+            let status = unsafe { ptr::read_volatile(ptr::addr_of!((*slot).status)) };
+            if status == TP_STATUS_AVAILABLE {
+                break;
+            }
+            if status & TP_STATUS_WRONG_FORMAT != 0 {
+                warn!("kernel rejected frame in TX ring as malformed");
+                break;
+            }
+            // ring is full - kernel still sending this slot
+            if tx.pending > 0 {
+                mapping.kick()?;
+                tx.pending = 0;
+            }
+            mapping.poll(libc::POLLOUT)?;
+        }
+        fence(Ordering::Acquire);
+        unsafe {
+            ptr::copy_nonoverlapping(frame.as_ptr(), (slot as *mut u8).add(TX_DATA_OFFSET), frame.len());
+            (*slot).next_offset = 0;
+            (*slot).len = frame.len() as u32;
+            (*slot).snaplen = frame.len() as u32;
+        }
+        fence(Ordering::Release);
+        unsafe { ptr::write_volatile(ptr::addr_of_mut!((*slot).status), TP_STATUS_SEND_REQUEST) };
+        tx.frame = (tx.frame + 1) % self.shared.config.tx_frame_count();
+        tx.pending += 1;
+        if tx.pending >= self.shared.config.tx_frame_count() / 2 {
+            mapping.kick()?;
+            tx.pending = 0;
+        }
+        return Ok(frame.len());
+    }
+
+    /// send all frames put into the TX ring
+    pub fn flush(&self) -> Result<(), Error> {
+        let (_, mapping) = self.current();
+        let mut tx = self.shared.tx.lock().expect("failed to lock tx cursor");
+        if tx.pending > 0 {
+            mapping.kick()?;
+            tx.pending = 0;
+        }
+        return Ok(());
+    }
+
+    pub fn raw_fd(&self) -> RawFd {
+        return self.current().1.fd;
+    }
+
+    /// new socket and rings bound to the interface of the same name - if another handle did this already, use that one
+    pub fn reopen(&mut self) -> Result<(), Error> {
+        let mut current = self.shared.current.lock().expect("failed to lock current mapping");
+        if current.0 != self.generation {
+            self.generation = current.0;
+            return Ok(());
+        }
+        let mapping = Mapping::open(&self.shared.interface_name, self.shared.ether_type, self.shared.config)?;
+        *current = (current.0 + 1, Arc::new(mapping));
+        self.generation = current.0;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: usize = 4096;
+
+    /// rings in anonymous memory instead of shared with the kernel, on no socket - the test plays the kernel
+    fn ring() -> PacketRing {
+        let config = RingConfig { block_size: BLOCK_SIZE, rx_block_count: 2, tx_block_count: 2, frame_size: 2048, retire_timeout_ms: 10 };
+        let size = (config.rx_block_count + config.tx_block_count) * config.block_size;
+        let base = unsafe { libc::mmap(ptr::null_mut(), size, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0) };
+        assert_ne!(base, libc::MAP_FAILED);
+        let mapping = Mapping { fd: -1, base: base as *mut u8, size: size, config: config };
+        return PacketRing {
+            shared: Arc::new(RingShared {
+                interface_name: "test".to_owned(),
+                ether_type: 0,
+                config: config,
+                current: Mutex::new((0, Arc::new(mapping))),
+                rx: Mutex::new(RxCursor::default()),
+                tx: Mutex::new(TxCursor::default()),
+            }),
+            generation: 0,
+        };
+    }
+
+    /// fill RX block with the given frames, each with a VLAN TCI if the kernel took the tag out, and hand it to the user
+    fn fill_block(ring: &PacketRing, index: usize, frames: &[(&[u8], Option<u16>)]) {
+        let (_, mapping) = ring.current();
+        let block = mapping.rx_block(index);
+        let first = std::mem::size_of::<BlockDesc>().next_multiple_of(16);
+        let mut offset = first;
+        for (number, (frame, vlan_tci)) in frames.iter().enumerate() {
+            let mac = std::mem::size_of::<Tpacket3Hdr>().next_multiple_of(16);
+            let length = (mac + frame.len()).next_multiple_of(16);
+            unsafe {
+                let header = (block as *mut u8).add(offset) as *mut Tpacket3Hdr;
+                (*header).next_offset = if number + 1 < frames.len() { length as u32 } else { 0 };
+                (*header).snaplen = frame.len() as u32;
+                (*header).len = frame.len() as u32;
+                (*header).mac = mac as u16;
+                (*header).status = if vlan_tci.is_some() { TP_STATUS_VLAN_VALID } else { 0 };
+                (*header).vlan_tci = vlan_tci.unwrap_or(0) as u32;
+                (*header).vlan_tpid = 0;
+                ptr::copy_nonoverlapping(frame.as_ptr(), (header as *mut u8).add(mac), frame.len());
+            }
+            offset += length;
+            assert!(offset <= BLOCK_SIZE);
+        }
+        unsafe {
+            (*block).num_pkts = frames.len() as u32;
+            (*block).offset_to_first_pkt = first as u32;
+            (*block).block_status = TP_STATUS_USER;
+        }
+    }
+
+    fn block_status(ring: &PacketRing, index: usize) -> u32 {
+        return unsafe { (*ring.current().1.rx_block(index)).block_status };
+    }
+
+    fn receive(ring: &PacketRing, maximum_frames: usize) -> Vec<Vec<u8>> {
+        let mut frames = vec![];
+        ring.receive(&mut |frame| frames.push(frame.to_vec()), maximum_frames, false).unwrap();
+        return frames;
+    }
+
+    fn frame(last: u8) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x88, 0x72];
+        frame.extend_from_slice(&[0x81, last]);
+        return frame;
+    }
+
+    #[test]
+    fn rx_blocks_walked() {
+        let ring = ring();
+        assert!(receive(&ring, 16).is_empty());
+        fill_block(&ring, 0, &[(&frame(1), None), (&frame(2), None), (&frame(3), None)]);
+        // stops in the middle of the block and goes on from there
+        assert_eq!(receive(&ring, 2), vec![frame(1), frame(2)]);
+        assert_eq!(block_status(&ring, 0), TP_STATUS_USER);
+        assert_eq!(receive(&ring, 16), vec![frame(3)]);
+        // done with the block, back to the kernel
+        assert_eq!(block_status(&ring, 0), TP_STATUS_KERNEL);
+        // next block, then around to the first one again
+        fill_block(&ring, 1, &[(&frame(4), None)]);
+        fill_block(&ring, 0, &[(&frame(5), None)]);
+        assert_eq!(receive(&ring, 16), vec![frame(4), frame(5)]);
+        assert_eq!((block_status(&ring, 0), block_status(&ring, 1)), (TP_STATUS_KERNEL, TP_STATUS_KERNEL));
+    }
+
+    #[test]
+    fn rx_vlan_tag_put_back() {
+        let ring = ring();
+        fill_block(&ring, 0, &[(&frame(1), Some(0xA064))]);
+        let mut tagged = frame(1);
+        tagged.splice(12..12, [0x81, 0x00, 0xA0, 0x64]);
+        assert_eq!(receive(&ring, 16), vec![tagged]);
+    }
+
+    #[test]
+    fn tx_slots_filled() {
+        let ring = ring();
+        assert_eq!(ring.send(&frame(1)).unwrap(), frame(1).len());
+        let (_, mapping) = ring.current();
+        let slot = mapping.tx_frame(0);
+        unsafe {
+            assert_eq!((*slot).status, TP_STATUS_SEND_REQUEST);
+            assert_eq!((*slot).len as usize, frame(1).len());
+            assert_eq!(std::slice::from_raw_parts((slot as *const u8).add(TX_DATA_OFFSET), frame(1).len()), &frame(1)[..]);
+            assert_eq!((*mapping.tx_frame(1)).status, TP_STATUS_AVAILABLE);
+        }
+        assert_eq!(ring.shared.tx.lock().unwrap().frame, 1);
+        // larger than a slot
+        assert_eq!(ring.send(&[0; 2048]).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,151 @@
+use std::{fs, io::{Error, Read, Write}, os::unix::io::{AsRawFd, RawFd}};
+
+use advmac::MacAddr6;
+use afpacket::sync::RawPacketStream;
+
+use super::{FrameSocket, bpf, mmap::{PacketRing, RingConfig}};
+
+enum Transport {
+    /// one system call per frame
+    Stream(RawPacketStream),
+    /// PACKET_MMAP rings, for throughput
+    Ring(PacketRing),
+}
+
+/// AF_PACKET raw socket bound to a named interface - unlike a bare RawPacketStream it remembers the interface name
+/// and EtherType, so that it can look at the link state and re-bind after the interface was removed and has reappeared
+pub struct PacketSocket {
+    transport: Transport,
+    interface_name: String,
+    ether_type: u16,
+    // remembered for applying them again after reopen()
+    filter: Option<Vec<bpf::Instruction>>,
+    groups: Vec<MacAddr6>,
+}
+
+impl PacketSocket {
+    pub fn open(interface_name: &str, ether_type: u16) -> Result<Self, Error> {
+        let stream = Self::open_stream(interface_name, ether_type)?;
+        return Ok(PacketSocket { transport: Transport::Stream(stream), interface_name: interface_name.to_owned(), ether_type: ether_type, filter: None, groups: vec![] });
+    }
+
+    /// high-performance mode using RX and TX rings shared with the kernel
+    pub fn open_with_ring(interface_name: &str, ether_type: u16, ring_config: RingConfig) -> Result<Self, Error> {
+        let ring = PacketRing::open(interface_name, ether_type, ring_config)?;
+        return Ok(PacketSocket { transport: Transport::Ring(ring), interface_name: interface_name.to_owned(), ether_type: ether_type, filter: None, groups: vec![] });
+    }
+
+    fn open_stream(interface_name: &str, ether_type: u16) -> Result<RawPacketStream, Error> {
+        let mut stream = RawPacketStream::new_with_ethertype(ether_type)?;
+        stream.bind_with_ethertype(interface_name, ether_type)?;
+        return Ok(stream);
+    }
+
+    pub fn interface_name(&self) -> &str {
+        return &self.interface_name;
+    }
+
+    fn raw_fd(&self) -> RawFd {
+        match &self.transport {
+            Transport::Stream(stream) => { return stream.as_raw_fd(); },
+            Transport::Ring(ring) => { return ring.raw_fd(); }
+        }
+    }
+}
+
+impl Read for PacketSocket {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match &mut self.transport {
+            Transport::Stream(stream) => { return stream.read(buf); },
+            Transport::Ring(ring) => {
+                let mut bytes = 0;
+                ring.receive(&mut |frame| {
+                    bytes = frame.len().min(buf.len());
+                    buf[0..bytes].copy_from_slice(&frame[0..bytes]);
+                }, 1, true)?;
+                return Ok(bytes);
+            }
+        }
+    }
+}
+
+impl Write for PacketSocket {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match &mut self.transport {
+            Transport::Stream(stream) => { return stream.write(buf); },
+            Transport::Ring(ring) => { return ring.send(buf); }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.transport {
+            Transport::Stream(stream) => { return stream.flush(); },
+            Transport::Ring(ring) => { return ring.flush(); }
+        }
+    }
+}
+
+impl FrameSocket for PacketSocket {
+    fn try_clone(&self) -> Result<Self, Error> {
+        let transport = match &self.transport {
+            Transport::Stream(stream) => Transport::Stream(stream.clone()),
+            Transport::Ring(ring) => Transport::Ring(ring.clone()),
+        };
+        return Ok(PacketSocket { transport: transport, interface_name: self.interface_name.clone(), ether_type: self.ether_type, filter: self.filter.clone(), groups: self.groups.clone() });
+    }
+
+    fn attach_filter(&mut self, program: &[bpf::Instruction]) -> Result<(), Error> {
+        bpf::attach(self.raw_fd(), program)?;
+        self.filter = Some(program.to_vec());
+        return Ok(());
+    }
+
+    fn join_group(&mut self, group_address: MacAddr6) -> Result<(), Error> {
+        bpf::add_membership(self.raw_fd(), &self.interface_name, group_address)?;
+        self.groups.push(group_address);
+        return Ok(());
+    }
+
+    /// whole blocks of frames straight out of the RX ring, without copying them into the buffer first
+    fn read_batch(&mut self, buffer: &mut [u8], on_frame: &mut dyn FnMut(&[u8])) -> Result<usize, Error> {
+        match &mut self.transport {
+            Transport::Stream(stream) => {
+                let bytes = stream.read(buffer)?;
+                on_frame(&buffer[0..bytes]);
+                return Ok(1);
+            },
+            Transport::Ring(ring) => { return ring.receive(on_frame, crate::dl::RECEIVE_BATCH_MAXIMUM, true); }
+        }
+    }
+
+    /// operational state and carrier as reported by the kernel in sysfs - interface gone counts as down
+    //TODO optimize - use a netlink socket and get notified instead of polling
+    fn link_up(&self) -> Option<bool> {
+        let operstate = match fs::read_to_string(format!("/sys/class/net/{}/operstate", self.interface_name)) {
+            Ok(operstate) => operstate,
+            Err(_) => { return Some(false); }
+        };
+        // NOTE: virtual interfaces like veth or TAP may report "unknown", but then carrier still tells
+        if operstate.trim() == "down" || operstate.trim() == "notpresent" || operstate.trim() == "lowerlayerdown" {
+            return Some(false);
+        }
+        let carrier = fs::read_to_string(format!("/sys/class/net/{}/carrier", self.interface_name)).unwrap_or_default();
+        return Some(carrier.trim() == "1");
+    }
+
+    /// new socket bound to the interface of the same name, which may have a new interface index by now
+    fn reopen(&mut self) -> Result<(), Error> {
+        match &mut self.transport {
+            Transport::Stream(stream) => { *stream = Self::open_stream(&self.interface_name, self.ether_type)?; },
+            Transport::Ring(ring) => { ring.reopen()?; }
+        }
+        if let Some(program) = &self.filter {
+            bpf::attach(self.raw_fd(), program)?;
+        }
+        for group_address in &self.groups {
+            bpf::add_membership(self.raw_fd(), &self.interface_name, *group_address)?;
+        }
+        info!("{}: re-bound raw socket", self.interface_name);
+        return Ok(());
+    }
+}
@@ -0,0 +1,195 @@
+use std::{fs::File, io::{self, BufReader, BufWriter, Error, Read, Write}, path::Path, sync::{Arc, Mutex}, thread, time::{SystemTime, UNIX_EPOCH}};
+
+use super::FrameSocket;
+
+// classic libpcap file format, see https://wiki.wireshark.org/Development/LibpcapFileFormat
+const MAGIC_MICROSECONDS: u32 = 0xa1b2c3d4;
+const MAGIC_MICROSECONDS_SWAPPED: u32 = 0xd4c3b2a1;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+const SNAPSHOT_LENGTH: u32 = 65535;
+/// largest snapshot length libpcap itself writes, anything beyond in a file is corrupt
+const MAXIMUM_SNAPSHOT_LENGTH: u32 = 262144;
+const LINKTYPE_ETHERNET: u32 = 1;
+const GLOBAL_HEADER_LENGTH: usize = 24;
+const RECORD_HEADER_LENGTH: usize = 16;
+
+struct Replay {
+    reader: BufReader<File>,
+    /// file was written on a host of other endianness
+    swapped: bool,
+    /// no record may include more than this
+    snapshot_length: u32,
+}
+
+/// replays the frames of a pcap file as received frames and/or records the sent frames into a pcap file,
+/// e.g. for regression tests or for analysis using Wireshark
+pub struct PcapSocket {
+    replay: Arc<Mutex<Option<Replay>>>,
+    record: Arc<Mutex<Option<BufWriter<File>>>>,
+}
+
+impl PcapSocket {
+    pub fn new(replay_path: Option<&Path>, record_path: Option<&Path>) -> Result<Self, Error> {
+        let mut replay = None;
+        if let Some(path) = replay_path {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut header = [0u8; GLOBAL_HEADER_LENGTH];
+            reader.read_exact(&mut header)?;
+            let swapped = match u32::from_ne_bytes([header[0], header[1], header[2], header[3]]) {
+                MAGIC_MICROSECONDS => false,
+                MAGIC_MICROSECONDS_SWAPPED => true,
+                _ => { return Err(Error::new(std::io::ErrorKind::InvalidData, "not a pcap file or nanosecond format")); }
+            };
+            let snapshot_length = read_u32(&header[16..20], swapped);
+            if snapshot_length == 0 || snapshot_length > MAXIMUM_SNAPSHOT_LENGTH {
+                return Err(Error::new(std::io::ErrorKind::InvalidData, "pcap file has an invalid snapshot length"));
+            }
+            let linktype = read_u32(&header[20..24], swapped);
+            if linktype != LINKTYPE_ETHERNET {
+                return Err(Error::new(std::io::ErrorKind::InvalidData, "pcap file does not contain Ethernet frames"));
+            }
+            replay = Some(Replay { reader: reader, swapped: swapped, snapshot_length: snapshot_length });
+        }
+        let mut record = None;
+        if let Some(path) = record_path {
+            let mut writer = BufWriter::new(File::create(path)?);
+            // native byte order - the reader recognizes it by the magic number
+            writer.write_all(&MAGIC_MICROSECONDS.to_ne_bytes())?;
+            writer.write_all(&VERSION_MAJOR.to_ne_bytes())?;
+            writer.write_all(&VERSION_MINOR.to_ne_bytes())?;
+            writer.write_all(&0i32.to_ne_bytes())?;   // thiszone, GMT
+            writer.write_all(&0u32.to_ne_bytes())?;   // sigfigs
+            writer.write_all(&SNAPSHOT_LENGTH.to_ne_bytes())?;
+            writer.write_all(&LINKTYPE_ETHERNET.to_ne_bytes())?;
+            record = Some(writer);
+        }
+        return Ok(PcapSocket { replay: Arc::new(Mutex::new(replay)), record: Arc::new(Mutex::new(record)) });
+    }
+}
+
+impl Read for PcapSocket {
+    /// returns the next frame from the replay file - when there is none (anymore), blocks forever like an idle network
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        {
+            let mut replay_guard = self.replay.lock().expect("failed to lock replay");
+            if let Some(replay) = replay_guard.as_mut() {
+                let mut header = [0u8; RECORD_HEADER_LENGTH];
+                match replay.reader.read_exact(&mut header) {
+                    Ok(_) => {
+                        let included_length = read_u32(&header[8..12], replay.swapped);
+                        if included_length > replay.snapshot_length {
+                            return Err(Error::new(std::io::ErrorKind::InvalidData, "pcap record longer than snapshot length"));
+                        }
+                        // like a raw socket, the frame is truncated to the buffer
+                        let bytes = (included_length as usize).min(buf.len());
+                        replay.reader.read_exact(&mut buf[0..bytes])?;
+                        let skip = included_length as u64 - bytes as u64;
+                        if io::copy(&mut (&mut replay.reader).take(skip), &mut io::sink())? < skip {
+                            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "pcap record truncated"));
+                        }
+                        return Ok(bytes);
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                        info!("pcap replay finished");
+                        *replay_guard = None;
+                    },
+                    Err(e) => { return Err(e); }
+                }
+            }
+        }   // release lock
+        loop {
+            thread::park();
+        }
+    }
+}
+
+impl Write for PcapSocket {
+    /// records the frame, if recording - otherwise it is discarded
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if let Some(writer) = self.record.lock().expect("failed to lock record").as_mut() {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            writer.write_all(&(now.as_secs() as u32).to_ne_bytes())?;
+            writer.write_all(&now.subsec_micros().to_ne_bytes())?;
+            let included_length = buf.len().min(SNAPSHOT_LENGTH as usize);
+            writer.write_all(&(included_length as u32).to_ne_bytes())?;
+            writer.write_all(&(buf.len() as u32).to_ne_bytes())?;  // original length
+            writer.write_all(&buf[0..included_length])?;
+        }
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if let Some(writer) = self.record.lock().expect("failed to lock record").as_mut() {
+            return writer.flush();
+        }
+        return Ok(());
+    }
+}
+
+impl FrameSocket for PcapSocket {
+    fn try_clone(&self) -> Result<Self, Error> {
+        return Ok(PcapSocket { replay: self.replay.clone(), record: self.record.clone() });
+    }
+}
+
+fn read_u32(buffer: &[u8], swapped: bool) -> u32 {
+    let value = u32::from_ne_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
+    if swapped { value.swap_bytes() } else { value }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> std::path::PathBuf {
+        return std::env::temp_dir().join(format!("osistack-pcap-{}-{}.pcap", name, std::process::id()));
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let recorded = path("round-trip");
+        let mut socket = PcapSocket::new(None, Some(&recorded)).unwrap();
+        let frames: Vec<Vec<u8>> = vec![vec![0xff; 60], (0..=255).collect(), vec![0x42; 1514]];
+        for frame in &frames {
+            assert_eq!(socket.write(frame).unwrap(), frame.len());
+        }
+        socket.flush().unwrap();
+        drop(socket);
+
+        let mut replay = PcapSocket::new(Some(&recorded), None).unwrap();
+        let mut buffer = [0u8; 2048];
+        for frame in &frames {
+            let bytes = replay.read(&mut buffer).unwrap();
+            assert_eq!(&buffer[0..bytes], &frame[..]);
+        }
+        // truncated to a short buffer, the next frame still comes out whole
+        let mut replay = PcapSocket::new(Some(&recorded), None).unwrap();
+        let mut short = [0u8; 16];
+        assert_eq!(replay.read(&mut short).unwrap(), 16);
+        assert_eq!(replay.read(&mut buffer).unwrap(), 256);
+        assert_eq!(buffer[255], 255);
+        std::fs::remove_file(&recorded).unwrap();
+    }
+
+    #[test]
+    fn oversized_record_rejected() {
+        let recorded = path("oversized");
+        let mut socket = PcapSocket::new(None, Some(&recorded)).unwrap();
+        socket.write(&[0u8; 60]).unwrap();
+        socket.flush().unwrap();
+        drop(socket);
+        // claim 16 MiB of included length in the record header
+        let mut file = std::fs::read(&recorded).unwrap();
+        file[GLOBAL_HEADER_LENGTH+8..GLOBAL_HEADER_LENGTH+12].copy_from_slice(&(16u32 << 20).to_ne_bytes());
+        std::fs::write(&recorded, &file).unwrap();
+        let mut replay = PcapSocket::new(Some(&recorded), None).unwrap();
+        assert_eq!(replay.read(&mut [0u8; 2048]).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+        // and a snapshot length beyond what libpcap writes
+        file[16..20].copy_from_slice(&u32::MAX.to_ne_bytes());
+        std::fs::write(&recorded, &file).unwrap();
+        assert_eq!(PcapSocket::new(Some(&recorded), None).err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_file(&recorded).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use std::{fmt, ops::{Deref, DerefMut}, sync::{Arc, Mutex}};
+
+/// pool of frame-sized buffers, so that received frames can be handed up to the NS without an allocation per frame -
+/// a buffer goes back into the pool when the NS is done with it and drops it
+#[derive(Clone)]
+pub struct BufferPool {
+    free: Arc<Mutex<Vec<Vec<u8>>>>,     //TODO optimize - lock-free, e.g. a pair of rtrb queues
+    buffer_size: usize,
+    /// buffers beyond this are freed instead of kept, e.g. after a burst
+    maximum_free: usize,
+}
+
+impl BufferPool {
+    /// pre-allocates the given number of buffers
+    pub fn new(buffer_size: usize, count: usize) -> Self {
+        let mut free = Vec::with_capacity(count);
+        for _ in 0..count {
+            free.push(Vec::with_capacity(buffer_size));
+        }
+        return BufferPool { free: Arc::new(Mutex::new(free)), buffer_size: buffer_size, maximum_free: count };
+    }
+
+    /// empty buffer with capacity for a whole frame - allocates if the pool has run dry
+    pub fn get(&self) -> PooledBuffer {
+        let data = self.free.lock().expect("failed to lock buffer pool").pop().unwrap_or_else(|| {
+            debug!("buffer pool exhausted, allocating");
+            Vec::with_capacity(self.buffer_size)
+        });
+        return PooledBuffer { data: data, pool: Some(self.clone()) };
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        return self.buffer_size;
+    }
+}
+
+/// buffer from a BufferPool - use it like a Vec<u8>
+pub struct PooledBuffer {
+    data: Vec<u8>,
+    pool: Option<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// not belonging to any pool, just freed when dropped
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        return PooledBuffer { data: data, pool: None };
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        return &self.data;
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        return &mut self.data;
+    }
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return self.data.fmt(f);
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            let mut data = std::mem::take(&mut self.data);
+            // NOTE: someone may have grown it - do not keep oversized buffers around
+            if data.capacity() > pool.buffer_size {
+                return;
+            }
+            data.clear();
+            let mut free = pool.free.lock().expect("failed to lock buffer pool");
+            if free.len() < pool.maximum_free {
+                free.push(data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free(pool: &BufferPool) -> usize {
+        return pool.free.lock().unwrap().len();
+    }
+
+    #[test]
+    fn returned_on_drop() {
+        let pool = BufferPool::new(1500, 2);
+        let mut buffer = pool.get();
+        assert_eq!(free(&pool), 1);
+        assert!(buffer.capacity() >= 1500);
+        buffer.extend_from_slice(&[0x81, 1, 2, 3]);
+        let address = buffer.as_ptr();
+        drop(buffer);
+        assert_eq!(free(&pool), 2);
+        // the same buffer again, emptied
+        let buffer = pool.get();
+        assert_eq!(buffer.as_ptr(), address);
+        assert!(buffer.is_empty());
+        // grown beyond the buffer size, it is freed instead
+        let mut grown = pool.get();
+        grown.extend_from_slice(&[0; 4000]);
+        drop(grown);
+        assert_eq!(free(&pool), 0);
+        // and a buffer of no pool just goes away
+        drop(PooledBuffer::from_vec(vec![1, 2, 3]));
+        assert_eq!(free(&pool), 0);
+    }
+
+    #[test]
+    fn exhausted() {
+        let pool = BufferPool::new(1500, 2);
+        let buffers: Vec<PooledBuffer> = (0..3).map(|_| pool.get()).collect();
+        // the third one was allocated
+        assert_eq!(free(&pool), 0);
+        assert!(buffers[2].capacity() >= pool.buffer_size());
+        // no more than the pool was made for are kept
+        drop(buffers);
+        assert_eq!(free(&pool), 2);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{fs::{File, OpenOptions}, io::{Error, Read, Write}, os::unix::io::AsRawFd};
+
+use super::FrameSocket;
+
+// see Linux Documentation/networking/tuntap.rst
+const TUNSETIFF: u64 = 0x400454ca;
+const IFF_TAP: libc::c_short = 0x0002;
+const IFF_NO_PI: libc::c_short = 0x1000;    // no packet information header in front of the frame
+
+/// struct ifreq, only the parts needed for TUNSETIFF
+#[repr(C)]
+struct IfReq {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    padding: [u8; 22],  // rest of the union
+}
+
+/// Linux TAP device - the kernel side can be bridged to other TAP devices, VMs or a real interface
+pub struct TapDevice {
+    file: File,
+    name: String,
+}
+
+impl TapDevice {
+    /// create resp. attach to the TAP device of the given name - needs CAP_NET_ADMIN unless the device
+    /// was created beforehand for this user, e.g. using "ip tuntap add dev tap0 mode tap user <user>"
+    pub fn open(name: &str) -> Result<Self, Error> {
+        if name.len() >= libc::IFNAMSIZ {
+            return Err(Error::new(std::io::ErrorKind::InvalidInput, "TAP device name too long"));
+        }
+        let file = OpenOptions::new().read(true).write(true).open("/dev/net/tun")?;
+        let mut ifreq = IfReq {
+            name: [0; libc::IFNAMSIZ],
+            flags: IFF_TAP | IFF_NO_PI,
+            padding: [0; 22],
+        };
+        for (i, byte) in name.bytes().enumerate() {
+            ifreq.name[i] = byte as libc::c_char;
+        }
+        let result = unsafe { libc::ioctl(file.as_raw_fd(), TUNSETIFF as _, &mut ifreq as *mut IfReq) };
+        if result < 0 {
+            return Err(Error::last_os_error());
+        }
+        info!("attached to TAP device {}", name);
+        return Ok(TapDevice { file: file, name: name.to_owned() });
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+}
+
+impl Read for TapDevice {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        return self.file.read(buf);
+    }
+}
+
+impl Write for TapDevice {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        return self.file.write(buf);
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        return self.file.flush();
+    }
+}
+
+impl FrameSocket for TapDevice {
+    fn try_clone(&self) -> Result<Self, Error> {
+        return Ok(TapDevice { file: self.file.try_clone()?, name: self.name.clone() });
+    }
+}
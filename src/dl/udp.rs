@@ -0,0 +1,38 @@
+use std::{io::{Error, Read, Write}, net::{ToSocketAddrs, UdpSocket}};
+
+use super::FrameSocket;
+
+/// Ethernet frames tunneled over UDP, one frame per datagram - connects two stacks across an IP network without raw sockets
+pub struct UdpTunnel {
+    socket: UdpSocket,
+}
+
+impl UdpTunnel {
+    pub fn new<A: ToSocketAddrs, B: ToSocketAddrs>(local_address: A, remote_address: B) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(local_address)?;
+        socket.connect(remote_address)?;
+        return Ok(UdpTunnel { socket: socket });
+    }
+}
+
+impl Read for UdpTunnel {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        return self.socket.recv(buf);
+    }
+}
+
+impl Write for UdpTunnel {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        return self.socket.send(buf);
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        return Ok(());
+    }
+}
+
+impl FrameSocket for UdpTunnel {
+    fn try_clone(&self) -> Result<Self, Error> {
+        return Ok(UdpTunnel { socket: self.socket.try_clone()? });
+    }
+}
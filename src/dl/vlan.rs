@@ -0,0 +1,164 @@
+use std::io::Error;
+
+// IEEE 802.1Q VLAN tags and IEEE 802.1ad (QinQ) service tags, which sit between the source MAC address
+// and the EtherType resp. 802.3 length field
+
+/// customer VLAN tag (C-tag)
+pub const TPID_CUSTOMER: u16 = 0x8100;
+/// service VLAN tag (S-tag) as per 802.1ad
+pub const TPID_SERVICE: u16 = 0x88A8;
+/// service VLAN tag as used by pre-802.1ad equipment
+pub const TPID_SERVICE_LEGACY: u16 = 0x9100;
+/// TPID and TCI
+pub const TAG_LENGTH: usize = 4;
+const VLAN_ID_MASK: u16 = 0x0FFF;
+const DROP_ELIGIBLE_BIT: u16 = 0x1000;
+/// destination and source MAC address
+const ADDRESSES_LENGTH: usize = 12;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tag {
+    pub priority_code_point: u8,
+    pub drop_eligible: bool,
+    /// 0 means priority-tagged only
+    pub vlan_id: u16,
+}
+
+impl Tag {
+    fn from_tci(tag_control_information: u16) -> Self {
+        return Tag {
+            priority_code_point: (tag_control_information >> 13) as u8,
+            drop_eligible: tag_control_information & DROP_ELIGIBLE_BIT != 0,
+            vlan_id: tag_control_information & VLAN_ID_MASK,
+        };
+    }
+
+    fn to_tci(self) -> u16 {
+        return ((self.priority_code_point as u16 & 0x7) << 13)
+            | if self.drop_eligible { DROP_ELIGIBLE_BIT } else { 0 }
+            | (self.vlan_id & VLAN_ID_MASK);
+    }
+}
+
+/// tag stack of a frame - at most one service tag and one customer tag are supported
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tags {
+    pub service: Option<Tag>,
+    pub customer: Option<Tag>,
+}
+
+/// parse the VLAN tags of a frame starting at the destination MAC address,
+/// returns the tags, the EtherType resp. 802.3 length field after them and the offset of the payload
+pub fn from_buf(frame: &[u8]) -> Result<(Tags, u16, usize), Error> {
+    let mut tags = Tags::default();
+    let mut offset = ADDRESSES_LENGTH;
+    let mut type_field = read_u16(frame, offset)?;
+    if type_field == TPID_SERVICE || type_field == TPID_SERVICE_LEGACY {
+        tags.service = Some(Tag::from_tci(read_u16(frame, offset + 2)?));
+        offset += TAG_LENGTH;
+        type_field = read_u16(frame, offset)?;
+    }
+    if type_field == TPID_CUSTOMER {
+        tags.customer = Some(Tag::from_tci(read_u16(frame, offset + 2)?));
+        offset += TAG_LENGTH;
+        type_field = read_u16(frame, offset)?;
+    }
+    if type_field == TPID_CUSTOMER || type_field == TPID_SERVICE || type_field == TPID_SERVICE_LEGACY {
+        return Err(Error::new(std::io::ErrorKind::Unsupported, "more than two VLAN tags or tags in wrong order"));
+    }
+    return Ok((tags, type_field, offset + 2));
+}
+
+/// write the given tags (service tag first), returns number of bytes written
+pub fn write_tags(buffer: &mut [u8], tags: &Tags) -> usize {
+    let mut bytes = 0;
+    if let Some(service) = tags.service {
+        buffer[0..2].copy_from_slice(&TPID_SERVICE.to_be_bytes());
+        buffer[2..4].copy_from_slice(&service.to_tci().to_be_bytes());
+        bytes += TAG_LENGTH;
+    }
+    if let Some(customer) = tags.customer {
+        buffer[bytes..bytes+2].copy_from_slice(&TPID_CUSTOMER.to_be_bytes());
+        buffer[bytes+2..bytes+4].copy_from_slice(&customer.to_tci().to_be_bytes());
+        bytes += TAG_LENGTH;
+    }
+    return bytes;
+}
+
+fn read_u16(frame: &[u8], offset: usize) -> Result<u16, Error> {
+    if frame.len() < offset + 2 {
+        return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "frame too short for Ethernet header"));
+    }
+    return Ok(u16::from_be_bytes([frame[offset], frame[offset+1]]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(tags: &[(u16, u16)], type_field: u16) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02];
+        for (tpid, tci) in tags {
+            frame.extend_from_slice(&tpid.to_be_bytes());
+            frame.extend_from_slice(&tci.to_be_bytes());
+        }
+        frame.extend_from_slice(&type_field.to_be_bytes());
+        frame.extend_from_slice(&[0x81, 1, 2, 3]);
+        return frame;
+    }
+
+    #[test]
+    fn untagged() {
+        assert_eq!(from_buf(&frame(&[], 0x8872)).unwrap(), (Tags::default(), 0x8872, 14));
+        assert_eq!(from_buf(&frame(&[], 0x8872)[0..13]).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn single_tag() {
+        // PCP 5, drop eligible, VLAN 100
+        let (tags, type_field, offset) = from_buf(&frame(&[(TPID_CUSTOMER, 0xB064)], 0x8872)).unwrap();
+        assert_eq!(tags.customer, Some(Tag { priority_code_point: 5, drop_eligible: true, vlan_id: 100 }));
+        assert_eq!(tags.service, None);
+        assert_eq!((type_field, offset), (0x8872, 18));
+        // priority-tagged only
+        let (tags, _, _) = from_buf(&frame(&[(TPID_CUSTOMER, 0x6000)], 0x8872)).unwrap();
+        assert_eq!(tags.customer, Some(Tag { priority_code_point: 3, drop_eligible: false, vlan_id: 0 }));
+        // a lone service tag
+        let (tags, _, offset) = from_buf(&frame(&[(TPID_SERVICE_LEGACY, 200)], 0x8872)).unwrap();
+        assert_eq!((tags.service.unwrap().vlan_id, tags.customer, offset), (200, None, 18));
+        // tag cut off
+        assert_eq!(from_buf(&frame(&[(TPID_CUSTOMER, 100)], 0x8872)[0..15]).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn double_tag() {
+        let (tags, type_field, offset) = from_buf(&frame(&[(TPID_SERVICE, 0x2000 | 200), (TPID_CUSTOMER, 100)], 0x8872)).unwrap();
+        assert_eq!(tags.service, Some(Tag { priority_code_point: 1, drop_eligible: false, vlan_id: 200 }));
+        assert_eq!(tags.customer, Some(Tag { priority_code_point: 0, drop_eligible: false, vlan_id: 100 }));
+        assert_eq!((type_field, offset), (0x8872, 22));
+        // wrong order and more than two tags
+        assert_eq!(from_buf(&frame(&[(TPID_CUSTOMER, 100), (TPID_SERVICE, 200)], 0x8872)).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(from_buf(&frame(&[(TPID_SERVICE, 200), (TPID_CUSTOMER, 100), (TPID_CUSTOMER, 101)], 0x8872)).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let tags = Tags {
+            service: Some(Tag { priority_code_point: 7, drop_eligible: false, vlan_id: 4094 }),
+            customer: Some(Tag { priority_code_point: 2, drop_eligible: true, vlan_id: 1 }),
+        };
+        let mut buffer = [0u8; 2 * TAG_LENGTH];
+        assert_eq!(write_tags(&mut buffer, &tags), 2 * TAG_LENGTH);
+        assert_eq!(buffer, [0x88, 0xA8, 0xEF, 0xFE, 0x81, 0x00, 0x50, 0x01]);
+        let mut frame = frame(&[], 0x8872);
+        frame.splice(12..12, buffer);
+        assert_eq!(from_buf(&frame).unwrap(), (tags, 0x8872, 22));
+        // only what is there, customer tag alone, nothing at all
+        let customer_only = Tags { service: None, customer: tags.customer };
+        assert_eq!(write_tags(&mut buffer, &customer_only), TAG_LENGTH);
+        assert_eq!(&buffer[0..TAG_LENGTH], &[0x81, 0x00, 0x50, 0x01]);
+        assert_eq!(write_tags(&mut buffer, &Tags::default()), 0);
+        // out of range values do not spill into the other fields
+        assert_eq!(Tag { priority_code_point: 9, drop_eligible: false, vlan_id: 0x1FFF }.to_tci(), 0x2FFF);
+    }
+}
@@ -1,11 +1,15 @@
 use std::{thread::{self, Thread, JoinHandle}, sync::{Arc, Mutex}, time::Duration};
 use netconfig::Interface;
-use afpacket::sync::RawPacketStream;
+use advmac::MacAddr6;
 #[macro_use] extern crate log;
 extern crate simplelog; //TODO check the paris feature flag for tags, useful?
 
 pub mod n;
-mod dl;
+pub mod dl;
+pub mod t;
+pub mod session;
+pub mod asn1;
+pub mod presentation;
 use crate::{n::NetworkService, dl::SubnetworkService};
 
 pub fn add(left: usize, right: usize) -> usize {
@@ -23,37 +27,83 @@ mod tests {
     }
 }
 
-// TODO maybe switch to pnet-datalink. but also needs to be fixed for ethertype parameter to socket() and bind()
-pub fn new<'a>(interface_name: &'a str, network_entity_title: &'a str, hosts: Vec<(&str, &str)>) -> (dl::ethernet::Service, n::clnp::Service<'a>) {
-    // set up logging
-    simplelog::TermLogger::init(
-        simplelog::LevelFilter::Info,   // can locally increase this for dev, TODO make configurable via args - but better configure this in Cargo.toml
-        simplelog::ConfigBuilder::default()
-            .set_time_level(simplelog::LevelFilter::Off)
-            .set_thread_level(simplelog::LevelFilter::Info)
-            .set_thread_mode(simplelog::ThreadLogMode::Names)
-            .set_thread_padding(simplelog::ThreadPadding::Right(15))    // maximum thread name length on Linux
-            .set_level_padding(simplelog::LevelPadding::Right)
-            .build(),
-        simplelog::TerminalMode::Mixed, // level error and above to stderr, rest to stdout
-        simplelog::ColorChoice::Auto    // depending on whether interactive or not
-    ).expect("logging init failed");
-    info!("osi-stack starting up"); //TODO add version information
+/// capacity of the inter-layer queues if the application has no specific needs
+pub const DEFAULT_QUEUE_CAPACITY: usize = 64;
 
-    // connect raw socket to iterface, filtered by EtherType of interest
-    let mut ps = RawPacketStream::new_with_ethertype(dl::ETHER_TYPE_CLNP).expect("failed to create new raw socket on given interface");
-    ps.bind_with_ethertype(interface_name, dl::ETHER_TYPE_CLNP).expect("failed to bind to interface");
+pub fn new<'a>(interface_name: &'a str, network_entity_title: &'a str, hosts: Vec<(&str, &str)>, queue_capacity: usize) -> (dl::ethernet::Service, n::clnp::Service<'a>) {
+    return new_with_config(interface_name, network_entity_title, hosts, queue_capacity, dl::InterfaceConfig::default());
+}
 
+pub fn new_with_config<'a>(interface_name: &'a str, network_entity_title: &'a str, hosts: Vec<(&str, &str)>, queue_capacity: usize, interface_config: dl::InterfaceConfig) -> (dl::ethernet::Service, n::clnp::Service<'a>) {
+    let (mut sns, ns) = new_with_interfaces(vec![(interface_name, interface_config)], network_entity_title, on_first_subnetwork(hosts), queue_capacity);
+    return (sns.remove(0), ns);
+}
+
+/// set up the stack attached to several interfaces at once, e.g. for a multi-homed end system or an intermediate system
+/// hosts are given as name, MAC address and index of the interface they are on
+pub fn new_with_interfaces<'a>(interfaces: Vec<(&str, dl::InterfaceConfig)>, network_entity_title: &'a str, hosts: Vec<(&str, &str, usize)>, queue_capacity: usize) -> (Vec<dl::ethernet::Service>, n::clnp::Service<'a>) {
+    init_logging();
+    let mut attachments = Vec::with_capacity(interfaces.len());
+    for (interface_name, interface_config) in interfaces {
+        let (ps, macaddr, interface_config) = open_interface(interface_name, interface_config);
+        attachments.push((ps, macaddr, interface_config));
+    }
+    return compose(attachments, network_entity_title, hosts, queue_capacity);
+}
+
+// TODO maybe switch to pnet-datalink. but also needs to be fixed for ethertype parameter to socket() and bind()
+/// returns the interface config with the MTU filled in from the interface, if not given
+fn open_interface(interface_name: &str, mut interface_config: dl::InterfaceConfig) -> (dl::packet::PacketSocket, MacAddr6, dl::InterfaceConfig) {
     // configure interface
     let iface_config = Interface::try_from_name(interface_name).expect("could not look up interface by name");
 
+    // get MTU, e.g. 9000 on a jumbo frame network
+    if interface_config.mtu.is_none() {
+        match iface_config.mtu() {
+            Ok(mtu) => {
+                info!("{}: got MTU: {}", interface_name, mtu);
+                interface_config.mtu = Some(mtu as usize);
+            },
+            Err(e) => { warn!("{}: could not get MTU, assuming {}: {}", interface_name, dl::ETHERNET_MTU, e); }
+        }
+    }
+
+    // connect raw socket to iterface, filtered by EtherType of interest
+    let ps = match interface_config.packet_ring {
+        Some(ring_config) => dl::packet::PacketSocket::open_with_ring(interface_name, interface_config.socket_ether_type(), ring_config.for_maximum_frame_size(interface_config.maximum_frame_size())).expect("failed to set up PACKET_MMAP rings on given interface"),
+        None => dl::packet::PacketSocket::open(interface_name, interface_config.socket_ether_type()).expect("failed to create raw socket bound to given interface"),
+    };
+    info!("{}: using {:?} encapsulation, VLAN {:?}, service VLAN {:?}", interface_name, interface_config.encapsulation, interface_config.vlan_id, interface_config.service_vlan_id);
+
     // get MAC address
     let macaddr = iface_config.hwaddress().expect("could not get hardware address of interface");
-    info!("got SNPA address: {}", macaddr);
+    info!("{}: got SNPA address: {}", interface_name, macaddr);
 
     // dont need it anymore
     drop(iface_config);
 
+    return (ps, macaddr, interface_config);
+}
+
+/// set up the stack on any frame transport - TAP device, in-memory loopback pair, pcap file or UDP tunnel
+/// NOTE: this also allows running several stacks in the same process
+pub fn new_with_socket<'a, S: dl::FrameSocket>(socket: S, macaddr: MacAddr6, network_entity_title: &'a str, hosts: Vec<(&str, &str)>, queue_capacity: usize, interface_config: dl::InterfaceConfig) -> (dl::ethernet::Service<S>, n::clnp::Service<'a>) {
+    let (mut sns, ns) = new_with_sockets(vec![(socket, macaddr, interface_config)], network_entity_title, on_first_subnetwork(hosts), queue_capacity);
+    return (sns.remove(0), ns);
+}
+
+/// set up the stack attached to several subnetworks, given as frame transport, own SNPA address and interface configuration each
+/// hosts are given as name, MAC address and index of the subnetwork they are on
+//TODO mixing different kinds of frame transports in one network entity
+pub fn new_with_sockets<'a, S: dl::FrameSocket>(attachments: Vec<(S, MacAddr6, dl::InterfaceConfig)>, network_entity_title: &'a str, hosts: Vec<(&str, &str, usize)>, queue_capacity: usize) -> (Vec<dl::ethernet::Service<S>>, n::clnp::Service<'a>) {
+    init_logging();
+    return compose(attachments, network_entity_title, hosts, queue_capacity);
+}
+
+/// NOTE: logging is set up by the callers, new_with_interfaces() already needs it for opening the interfaces
+fn compose<'a, S: dl::FrameSocket>(attachments: Vec<(S, MacAddr6, dl::InterfaceConfig)>, network_entity_title: &'a str, hosts: Vec<(&str, &str, usize)>, queue_capacity: usize) -> (Vec<dl::ethernet::Service<S>>, n::clnp::Service<'a>) {
+    info!("osi-stack starting up with {} subnetwork(s)", attachments.len()); //TODO add version information
+
     // compose OSI network stack
     //TODO ability to configure which protocols should be built into the stack
     // NOTE: producer is where the producer (originator) of a message writes into
@@ -63,32 +113,109 @@ pub fn new<'a>(interface_name: &'a str, network_entity_title: &'a str, hosts: Ve
     // which may also take a clone of the arc<mutex of a consumer (receiver) thread as needed
     // In every thread where there are pushes into inter-layer connections done, it needs the consumer (receiver) thread handle to wake the receiver up
     // In every thread where there are pops from inter-layer connections done, it needs to give its thread handle into the arc<mutex (the well-known place) where the sender will get it from
-    let (sn2ns_producer, sn2ns_consumer) = rtrb::RingBuffer::new(7);
-    let (ns2sn_producer, ns2sn_consumer) = rtrb::RingBuffer::new(7);
     //TODO optimize - WakeupHandle does not require Arc<Mutex<WakeupHandle>>, but Arc<WakeupHandle> is enough - make use of this shortcut
+    // NOTE: all SNs wake up the same NS thread
     let sn2ns_consumer_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>> = Arc::new(Mutex::new(None));
-    let ns2sn_consumer_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>> = Arc::new(Mutex::new(None));
-    let sn = dl::ethernet::Service::new(ps, ns2sn_consumer, sn2ns_producer, sn2ns_consumer_wakeup.clone());
-    let mut ns = n::clnp::Service::new(network_entity_title, ns2sn_producer, ns2sn_consumer_wakeup.clone(), sn2ns_consumer);
-    // set own/serviced NSAPs
+    let mut sns = Vec::with_capacity(attachments.len());
+    let mut subnetworks = Vec::with_capacity(attachments.len());
+    let mut ns2sn_producers = Vec::with_capacity(attachments.len());
+    let mut ns2sn_consumer_wakeups = Vec::with_capacity(attachments.len());
+    let mut ns2sn_producer_waitings = Vec::with_capacity(attachments.len());
+    let mut sn2ns_consumers = Vec::with_capacity(attachments.len());
+    let mut sn2ns_status_consumers = Vec::with_capacity(attachments.len());
+    for (mut socket, macaddr, interface_config) in attachments {
+        // let the kernel drop what is not for us - cuts CPU load on busy segments
+        for group_address in dl::OSI_GROUP_ADDRESSES {
+            if let Err(e) = socket.join_group(group_address) {
+                debug!("not joining group {}: {}", group_address, e);
+            }
+        }
+        match dl::bpf::clns_filter(&interface_config, macaddr, &dl::OSI_GROUP_ADDRESSES) {
+            Ok(program) => {
+                if let Err(e) = socket.attach_filter(&program) {
+                    debug!("not attaching socket filter: {}", e);
+                }
+            },
+            Err(e) => { warn!("could not compile socket filter: {}", e); }
+        }
+        let (sn2ns_producer, sn2ns_consumer) = rtrb::RingBuffer::new(queue_capacity);
+        // subnetwork up/down events, separate so that they are not stuck behind data
+        let (sn2ns_status_producer, sn2ns_status_consumer) = rtrb::RingBuffer::new(queue_capacity);
+        // NS to SN has one queue per priority band, so that control traffic does not queue behind bulk data
+        let mut ns2sn_producer = Vec::with_capacity(dl::PRIORITY_BANDS);
+        let mut ns2sn_consumer = Vec::with_capacity(dl::PRIORITY_BANDS);
+        for _ in 0..dl::PRIORITY_BANDS {
+            let (producer, consumer) = rtrb::RingBuffer::new(queue_capacity);
+            ns2sn_producer.push(producer);
+            ns2sn_consumer.push(consumer);
+        }
+        let ns2sn_consumer_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>> = Arc::new(Mutex::new(None));
+        // and the other way round, NS threads waiting for room in the queues get woken up by the SN
+        let ns2sn_producer_waiting: Arc<Mutex<Vec<Thread>>> = Arc::new(Mutex::new(vec![]));
+        sns.push((dl::ethernet::Service::new(socket, interface_config, ns2sn_consumer, sn2ns_producer, sn2ns_status_producer, sn2ns_consumer_wakeup.clone(), ns2sn_producer_waiting.clone()), ns2sn_consumer_wakeup.clone()));
+        subnetworks.push(n::SubnetworkAttachment {
+            snpa: macaddr,
+            maximum_sdu_size: interface_config.maximum_sdu_size(),
+            encapsulation: interface_config.encapsulation,
+            up: true,
+        });
+        ns2sn_producers.push(ns2sn_producer);
+        ns2sn_consumer_wakeups.push(ns2sn_consumer_wakeup);
+        ns2sn_producer_waitings.push(ns2sn_producer_waiting);
+        sn2ns_consumers.push(sn2ns_consumer);
+        sn2ns_status_consumers.push(sn2ns_status_consumer);
+    }
+    let snpas: Vec<MacAddr6> = subnetworks.iter().map(|subnetwork| subnetwork.snpa).collect();
+    let mut ns = n::clnp::Service::new(network_entity_title, subnetworks, ns2sn_producers, ns2sn_consumer_wakeups, ns2sn_producer_waitings, sn2ns_consumers, sn2ns_status_consumers);
+    // set own/serviced NSAPs - one per subnetwork, in the same order
     //TODO optimize locking here - maybe it is fine to pack up ns and sn into Arc<Mutex<>> upon calling run()
-    ns.add_serviced_subnet_nsap(1, 1, macaddr);
+    for macaddr in snpas {
+        ns.add_serviced_subnet_nsap(1, 1, macaddr);
+    }
     // add known hosts
     for host in hosts {
-        ns.add_known_host(host.0.to_owned(), host.1);   //TODO optimize clone
+        ns.add_known_host(host.0.to_owned(), host.1, host.2);   //TODO optimize clone
     }
 
-    // start SN
+    // start SNs
     // NOTE: will go out of scope at end of this function, at the same time sn cannot be borrowed 2x for read and write threads
     // therefore, interior mutability and because we are multi-threaded, Arc<Mutex<>> is needed. Yay.
     //TODO optimize?
-    sn.run(ns2sn_consumer_wakeup);
+    let mut sns_running = Vec::with_capacity(sns.len());
+    for (sn, ns2sn_consumer_wakeup) in sns {
+        sn.run(ns2sn_consumer_wakeup);
+        sns_running.push(sn);
+    }
     // start NS
     ns.run(sn2ns_consumer_wakeup);
     // wait for above run() methods to give their thread wakeup handles - otherwise yet another signal channel needs to be implemented
     thread::sleep(Duration::from_millis(500));
 
-    // NOTE: must return sn with the contained RawPacketStream, otherwise it goes out of scope, even though owned by the threads in sn.run(),
-    // but they have only clones. The original must not trigger its free(). So we return it...
-    return (sn, ns);  //TODO instead of NS, return likely the ACSE for registering applications
-}
\ No newline at end of file
+    // NOTE: must return the SNs with the contained sockets, otherwise they go out of scope, even though owned by the threads in sn.run(),
+    // but they have only clones. The original must not trigger its free(). So we return them...
+    return (sns_running, ns);  //TODO instead of NS, return likely the ACSE for registering applications
+}
+
+/// hosts of a single-subnetwork stack
+fn on_first_subnetwork<'h>(hosts: Vec<(&'h str, &'h str)>) -> Vec<(&'h str, &'h str, usize)> {
+    return hosts.into_iter().map(|(name, macaddr)| (name, macaddr, 0)).collect();
+}
+
+/// set up logging - can only be done once per process, further stacks in the same process use the existing logger
+fn init_logging() {
+    let result = simplelog::TermLogger::init(
+        simplelog::LevelFilter::Info,   // can locally increase this for dev, TODO make configurable via args - but better configure this in Cargo.toml
+        simplelog::ConfigBuilder::default()
+            .set_time_level(simplelog::LevelFilter::Off)
+            .set_thread_level(simplelog::LevelFilter::Info)
+            .set_thread_mode(simplelog::ThreadLogMode::Names)
+            .set_thread_padding(simplelog::ThreadPadding::Right(15))    // maximum thread name length on Linux
+            .set_level_padding(simplelog::LevelPadding::Right)
+            .build(),
+        simplelog::TerminalMode::Mixed, // level error and above to stderr, rest to stdout
+        simplelog::ColorChoice::Auto    // depending on whether interactive or not
+    );
+    if result.is_err() {
+        debug!("logging already initialized");
+    }
+}
@@ -1,38 +1,65 @@
 pub mod clnp;
+pub mod isis;
+pub mod idrp;
+pub mod management;
 
 use std::collections::HashMap;
+use std::io::Error;
 use std::sync::{Arc, Mutex};
 use std::thread::{Thread, JoinHandle};
+use std::time::Duration;
 use chrono::prelude::*;
 
 use advmac::MacAddr6;
 
-use crate::dl::SNUnitDataRequest;
+use crate::dl::{SNUnitDataRequest, SubnetworkEvent};
 use crate::n::clnp::NOptionsPart;
 
 pub trait NetworkService<'a> {
     fn new(
         network_entity_title: &'a str,
-        sn_service_to: rtrb::Producer<SNUnitDataRequest>,
-        sn_service_to_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
-        sn_service_from: rtrb::Consumer<NUnitDataIndication>,
+        subnetworks: Vec<SubnetworkAttachment>,
+        // the following are per subnetwork, in the same order
+        sn_service_to: Vec<Vec<rtrb::Producer<SNUnitDataRequest>>>,    // one per priority band
+        sn_service_to_wakeup: Vec<Arc<Mutex<Option<JoinHandle<Thread>>>>>,
+        sn_service_to_waiting: Vec<Arc<Mutex<Vec<Thread>>>>,  // senders waiting for room in the queues, woken up by the SN
+        sn_service_from: Vec<rtrb::Consumer<NUnitDataIndication>>,
+        sn_status_from: Vec<rtrb::Consumer<SubnetworkEvent>>,
     ) -> Self;
     fn add_serviced_nsap(&mut self, authority: u16, area: u16, sub_area: u16, remainder: MacAddr6);
     fn add_serviced_subnet_nsap(&mut self, net: u16, sub_net: u16, macaddr: MacAddr6);
     fn resolve_nsap(&self, system_title: &str) -> Option<&Nsap>;
-    fn add_known_host(&mut self, system_title: String, nsap: &str);
+    /// the host is on the given subnetwork, until it is heard on a different one
+    fn add_known_host(&mut self, system_title: String, nsap: &str, subnetwork: usize);
     fn get_serviced_nsap(&self) -> Option<&Nsap>;
+    /// shared with the routing protocols, which install their routes into it
+    fn forwarding_table(&self) -> Arc<Mutex<ForwardingTable>>;
+    /// shared with ES-IS and IS-IS, which maintain their adjacencies in it
+    fn adjacencies(&self) -> Arc<Mutex<Vec<Adjacency>>>;
+    /// for ES-IS and routing to be told when a subnetwork goes down or comes back up
+    fn add_subnetwork_listener(&mut self, listener: SubnetworkListener);
+    /// management - snapshot of what this network entity believes about the network, in the spirit of RFC 1574
+    fn management_information(&self) -> ManagementInformation;
+    /// X.233 6.18 Congestion notification function - when and how to react to congestion towards the SN
+    fn set_congestion_notification(&mut self, congestion_notification: CongestionNotification);
     /// called by TS
     fn n_unitdata_request(&mut self,
         ns_destination_title: &str,
-        ns_quality_of_service: &'a Qos,
-        ns_userdata: &'a [u8]
-    );
+        ns_quality_of_service: &Qos,
+        ns_userdata: &[u8]
+    ) -> Result<(), Error>;
+    /// called by TS, when it already knows the NSAP address of the peer, e.g. from an incoming NSDU
+    fn n_unitdata_request_to_nsap(&mut self,
+        ns_destination_address: &Nsap,
+        ns_quality_of_service: &Qos,
+        ns_userdata: &[u8]
+    ) -> Result<(), Error>;
+    /// called by TS - where Data PDUs for this network entity are handed up to, and how to wake up the TS to look at them
+    /// NOTE: there may be several, e.g. TP4 and CLTP, each NSDU goes to the first one accepting it
+    fn register_ns_user(&mut self, ns_user: NsUser);
     /// called by SN
     fn n_unitdata_indication(//&self,
-        sn_service_to: &mut rtrb::Producer<SNUnitDataRequest>,
-        sn_service_to_wakeup: &JoinHandle<Thread>,
-        echo_request_correlation_table: Arc<Mutex<HashMap<u16, DateTime<Utc>>>>,
+        context: &mut ReceiveContext,
         // actual parameters
         ns_source_address: MacAddr6,
         ns_destination_address: MacAddr6,
@@ -47,7 +74,7 @@ pub trait NetworkService<'a> {
         source_address_index: Option<usize>,
         options: Option<NOptionsPart>,
         ns_quality_of_service: &Qos
-    ); //TODO clunky to return the sending Nsap, and even that is not possible inside echo_request() this should be known beforehand, but alas, Rust's no 2nd borrow on ns variable
+    ) -> Result<(), Error>; //TODO clunky to return the sending Nsap, and even that is not possible inside echo_request() this should be known beforehand, but alas, Rust's no 2nd borrow on ns variable
     fn run(&mut self,
         sn2ns_consumer_wakeup_give: Arc<Mutex<Option<JoinHandle<Thread>>>>
     );
@@ -56,7 +83,7 @@ pub trait NetworkService<'a> {
 }
 
 //TODO implement full NSAP
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Nsap {
     authority: u16, // 49 = local network
     area: u16,  //net (?)
@@ -73,6 +100,20 @@ impl Nsap {
         return 2+2+2+6;
     }
 
+    /// reverse of to_u8(), e.g. for the source address of a received Data PDU
+    //TODO fix - only understands the addresses this implementation itself sends
+    pub fn from_u8(buffer: &[u8]) -> Option<Nsap> {
+        if buffer.len() != 2+2+2+6 {
+            return None;
+        }
+        return Some(Nsap {
+            authority: u16::from_be_bytes([buffer[0], buffer[1]]),
+            area: u16::from_be_bytes([buffer[2], buffer[3]]),
+            sub_area: u16::from_be_bytes([buffer[4], buffer[5]]),
+            local_address: MacAddr6::new([buffer[6], buffer[7], buffer[8], buffer[9], buffer[10], buffer[11]]),
+        });
+    }
+
     //TODO optimize
     fn to_u8(&self) -> Vec<u8> {
         //TODO optimize https://stackoverflow.com/questions/40154150/how-do-i-concatenate-two-slices-in-rust
@@ -106,9 +147,256 @@ impl ToString for Nsap {
     }
 }
 
-#[derive(Debug)]
+/// X.213 A.5 - an NSAP address is at most 20 octets long
+pub const NSAP_MAXIMUM_LENGTH: usize = 20;
+
+/// NSAP address prefix, as used in routing protocols - length is in bits, as prefixes do not need to end on an octet boundary
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NsapPrefix {
+    pub length_bits: u8,
+    pub prefix: Vec<u8>,
+}
+
+impl NsapPrefix {
+    /// checks that the prefix is not longer than an NSAP address and has the octets its length in bits asks for
+    pub fn new(length_bits: u8, prefix: Vec<u8>) -> Result<Self, Error> {
+        let prefix = NsapPrefix { length_bits: length_bits, prefix: prefix };
+        if !prefix.is_valid() {
+            return Err(Error::new(std::io::ErrorKind::InvalidInput, "NSAP prefix length does not match prefix octets"));
+        }
+        return Ok(prefix);
+    }
+
+    pub fn is_valid(&self) -> bool {
+        return self.length_bits as usize <= NSAP_MAXIMUM_LENGTH * 8 && self.prefix.len() >= self.octets();
+    }
+
+    /// number of octets covered by the prefix, the last one possibly only partly
+    pub fn octets(&self) -> usize {
+        return (self.length_bits as usize).div_ceil(8);
+    }
+
+    /// does the given encoded NSAP address fall under this prefix
+    pub fn matches(&self, address: &[u8]) -> bool {
+        let full_octets = (self.length_bits / 8) as usize;
+        let remaining_bits = self.length_bits % 8;
+        if address.len() < self.octets() || self.prefix.len() < self.octets() {
+            return false;
+        }
+        if address[0..full_octets] != self.prefix[0..full_octets] {
+            return false;
+        }
+        if remaining_bits > 0 {
+            let mask = 0xFFu8 << (8 - remaining_bits);
+            return (address[full_octets] & mask) == (self.prefix[full_octets] & mask);
+        }
+        return true;
+    }
+}
+
+impl ToString for NsapPrefix {
+    fn to_string(&self) -> String {
+        //TODO optimize
+        let hex: Vec<String> = self.prefix.iter().map(|octet| format!("{:02x}", octet)).collect();
+        format!("{}/{}", hex.join(""), self.length_bits)
+    }
+}
+
+/// which protocol installed a forwarding table entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteSource {
+    Static,
+    EsIs,
+    IsIs,
+    Idrp,
+}
+
+#[derive(Clone, Debug)]
+pub struct ForwardingEntry {
+    pub destination: NsapPrefix,
+    /// encoded NET of the next hop system
+    pub next_hop: Vec<u8>,
+    pub metric: u32,
+    pub source: RouteSource,
+    /// subnetwork to send on - None is the one the next hop is adjacent on
+    pub subnetwork: Option<usize>,
+}
+
+/// X.233 6.5 Route PDU function - the table the route PDU function consults
+#[derive(Debug, Default)]
+pub struct ForwardingTable {
+    entries: Vec<ForwardingEntry>,
+}
+
+impl ForwardingTable {
+    /// replaces an existing entry for the same destination from the same source
+    pub fn insert(&mut self, entry: ForwardingEntry) {
+        self.remove(&entry.destination, entry.source);
+        self.entries.push(entry);
+    }
+
+    pub fn remove(&mut self, destination: &NsapPrefix, source: RouteSource) {
+        self.entries.retain(|existing| !(existing.destination == *destination && existing.source == source));
+    }
+
+    /// longest prefix match, lowest metric if there are multiple entries for the same prefix
+    pub fn lookup(&self, address: &[u8]) -> Option<&ForwardingEntry> {
+        return self.entries.iter()
+            .filter(|entry| entry.destination.matches(address))
+            .min_by_key(|entry| (u8::MAX - entry.destination.length_bits, entry.metric));
+    }
+
+    pub fn entries(&self) -> &[ForwardingEntry] {
+        return &self.entries;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NeighbourType {
+    EndSystem,
+    IntermediateSystem,
+}
+
+/// adjacency to a neighbour system on a subnetwork, as learned by ES-IS (ISO 9542) or IS-IS (ISO 10589)
+#[derive(Clone, Debug)]
+pub struct Adjacency {
+    pub source: RouteSource,
+    pub neighbour_type: NeighbourType,
+    /// encoded NET of the neighbour
+    pub neighbour: Vec<u8>,
+    pub snpa: MacAddr6,
+    /// subnetwork the neighbour is reachable on
+    pub subnetwork: usize,
+    /// when the holding time runs out
+    pub expires: DateTime<Utc>,
+}
+
+/// a subnetwork the network entity is attached to, as seen by the NS
+#[derive(Clone, Debug)]
+pub struct SubnetworkAttachment {
+    /// own SNPA address on this subnetwork
+    pub snpa: MacAddr6,
+    /// largest SN user data, derived from the MTU and the encapsulation
+    pub maximum_sdu_size: usize,
+    pub encapsulation: crate::dl::Encapsulation,
+    /// as last reported by the SN
+    pub up: bool,
+}
+
+/// called with the index of the subnetwork and what happened to it
+pub type SubnetworkListener = Box<dyn FnMut(usize, SubnetworkEvent) + Send>;
+
+/// entry of the NSAP to SNPA address resolution cache
+#[derive(Clone, Debug)]
+pub struct SnpaCacheEntry {
+    pub system_title: String,
+    pub nsap: Nsap,
+    pub snpa: MacAddr6,
+    /// where the SNPA address was configured resp. last heard on
+    pub subnetwork: Option<usize>,
+}
+
+/// returned by NetworkService::management_information()
+#[derive(Clone, Debug)]
+pub struct ManagementInformation {
+    pub network_entity_title: String,
+    pub subnetworks: Vec<SubnetworkAttachment>,
+    pub serviced_nsaps: Vec<Nsap>,
+    pub forwarding_table: Vec<ForwardingEntry>,
+    pub adjacencies: Vec<Adjacency>,
+    pub snpa_cache: Vec<SnpaCacheEntry>,
+}
+
+/// what a source does when the queue towards the SN is full
+#[derive(Clone, Copy, Debug)]
+pub enum CongestionPolicy {
+    /// wait up to the given time for the SN to make room, then discard
+    Backpressure(Duration),
+    /// discard right away
+    Discard,
+}
+
+/// X.233 6.18 Congestion notification function parameters
+#[derive(Clone, Copy, Debug)]
+pub struct CongestionNotification {
+    /// number of queued SN-UNITDATA requests from which on the congestion experienced flag is set
+    pub threshold: usize,
+    pub policy: CongestionPolicy,
+}
+
+impl CongestionNotification {
+    /// notify at 3/4 of the queue capacity and wait a short while if it is full
+    pub fn default_for_capacity(queue_capacity: usize) -> Self {
+        CongestionNotification {
+            threshold: (queue_capacity * 3 / 4).max(1),
+            policy: CongestionPolicy::Backpressure(Duration::from_millis(100)),
+        }
+    }
+}
+
+/// NS quality of service as per X.213 - the parameters besides priority are given as importance relative to each other,
+/// because CLNP can only convey pairwise preferences between transit delay, residual error probability and cost (X.233 7.5.6.3)
+/// but no absolute values
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Qos {
-    //TODO
+    /// X.233 6.17 Priority function - 0 is normal, up to 14 is highest
+    pub priority: u8,
+    /// importance of low transit delay - 0 is don't care
+    pub transit_delay: u8,
+    /// importance of low residual error probability - 0 is don't care
+    pub residual_error_probability: u8,
+    /// importance of low cost - 0 is don't care
+    pub cost: u8,
+    /// sequence preservation is preferred over low transit delay
+    pub sequencing_preference: bool,
+    //TODO protection (X.213 security), source routing
+}
+
+impl Qos {
+    /// whether anything besides priority has been asked for, which then has to be carried in the QoS maintenance parameter
+    pub fn has_preferences(&self) -> bool {
+        return self.transit_delay != 0 || self.residual_error_probability != 0 || self.cost != 0 || self.sequencing_preference;
+    }
+
+    /// low transit delay is more important than both residual error probability and cost
+    pub fn low_transit_delay_first(&self) -> bool {
+        return self.transit_delay > self.residual_error_probability && self.transit_delay > self.cost;
+    }
+
+    /// low cost is more important than both transit delay and residual error probability
+    pub fn low_cost_first(&self) -> bool {
+        return self.cost > self.transit_delay && self.cost > self.residual_error_probability;
+    }
+}
+
+/// what the receive thread of the NS hands to n_unitdata_indication()
+/// NOTE: the receive thread never waits for room in the queues towards the SN, all inbound traffic would stall
+pub struct ReceiveContext<'r> {
+    /// queues towards the SN the PDU came in on, one per priority band - for Echo Responses and Error Reports
+    pub sn_service_to: &'r mut [rtrb::Producer<SNUnitDataRequest>],
+    pub sn_service_to_wakeup: &'r JoinHandle<Thread>,
+    pub congestion_notification: CongestionNotification,
+    pub ns_users: &'r Mutex<Vec<NsUser>>,
+    pub echo_request_correlation_table: &'r Mutex<HashMap<u16, DateTime<Utc>>>,
+    /// which subnetwork the PDU came in on
+    pub arrival_subnetwork: usize,
+}
+
+/// N-UNITDATA indication from the NS to its user, the TS
+#[derive(Debug)]
+pub struct NSUnitDataIndication {
+    pub ns_source_address: Nsap,
+    pub ns_destination_address: Nsap,
+    pub ns_quality_of_service: Qos,
+    pub ns_userdata: Vec<u8>,
+}
+
+/// the TS as seen from the NS
+pub struct NsUser {
+    /// whether this NS user wants the given NSDU
+    pub accepts: fn(&[u8]) -> bool,
+    pub ns_user_to: rtrb::Producer<NSUnitDataIndication>,
+    pub ns_user_to_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
 }
 
 #[derive(Debug)]
@@ -116,5 +404,6 @@ pub struct NUnitDataIndication {
     pub ns_source_address: MacAddr6,
     pub ns_destination_address: MacAddr6,
     pub ns_quality_of_service: crate::n::Qos,
-    pub ns_userdata: Vec<u8>    //TODO optimize copying?
+    /// goes back into the SN's buffer pool when dropped
+    pub ns_userdata: crate::dl::pool::PooledBuffer
 }
\ No newline at end of file
@@ -1,13 +1,13 @@
 extern crate simplelog; //TODO check the paris feature flag for tags, useful?
 
-use std::{collections::HashMap, io::Error, thread::{self, Thread, JoinHandle}, sync::{Arc, Mutex}, time::Duration};
+use std::{collections::HashMap, io::Error, thread::{self, Thread, JoinHandle}, sync::{Arc, Mutex}, time::{Duration, Instant}};
 
 use advmac::MacAddr6;
 use rand::Rng;
 use chrono::prelude::*;
 
-use crate::dl::SNUnitDataRequest;
-use super::{Nsap, Qos, NUnitDataIndication};
+use crate::dl::{SNUnitDataRequest, SubnetworkEvent};
+use super::{Nsap, Qos, NUnitDataIndication, NSUnitDataIndication, NsUser, ReceiveContext, ForwardingTable, Adjacency, ManagementInformation, SnpaCacheEntry, CongestionNotification, CongestionPolicy, SubnetworkAttachment, SubnetworkListener};
 
 pub fn parse_macaddr(instr: &str) -> Result<MacAddr6, advmac::ParseError> {
     MacAddr6::parse_str(instr)
@@ -26,6 +26,8 @@ pub enum Pdu<'a> {
 }
 
 const VERSION_PROTOCOL_ID_EXTENSION_1: u8 = 0b0000_0001;
+/// the length indicator is one octet and 255 is reserved (X.233 7.2.2)
+const MAXIMUM_HEADER_LENGTH: usize = 254;
 
 /// actually 5 bits, so bits 8,7,6 are 0
 const TYPE_DT_PDU: u8 = 0b00011100;     // data
@@ -34,6 +36,34 @@ const TYPE_ER_PDU: u8 = 0b00000001;     // error report
 const TYPE_ERQ_PDU: u8 = 0b00011110;    // echo request
 const TYPE_ERP_PDU: u8 = 0b00011111;    // echo response
 
+// X.233 7.5 options part parameter codes
+const PARAMETER_CODE_QOS_MAINTENANCE: u8 = 0b1100_0011;
+/// X.233 7.9.5 reason for discard parameter of the ER PDU
+const PARAMETER_CODE_REASON_FOR_DISCARD: u8 = 0b1100_0001;
+/// X.233 7.9.5 reason for discard - PDU discarded due to congestion
+pub const REASON_CONGESTION: u8 = 0b0000_0011;
+/// X.233 7.5.6 QoS maintenance parameter value - bits 8 and 7 give the format
+const QOS_MAINTENANCE_FORMAT_MASK: u8 = 0b1100_0000;
+const QOS_MAINTENANCE_FORMAT_GLOBALLY_UNIQUE: u8 = 0b1100_0000;
+const QOS_MAINTENANCE_SEQUENCING_OVER_TRANSIT_DELAY: u8 = 0b0001_0000;
+const QOS_MAINTENANCE_CONGESTION_EXPERIENCED: u8 = 0b0000_1000;
+const QOS_MAINTENANCE_TRANSIT_DELAY_OVER_COST: u8 = 0b0000_0100;
+const QOS_MAINTENANCE_RESIDUAL_ERROR_PROBABILITY_OVER_TRANSIT_DELAY: u8 = 0b0000_0010;
+const QOS_MAINTENANCE_RESIDUAL_ERROR_PROBABILITY_OVER_COST: u8 = 0b0000_0001;
+/// all globally unique QoS maintenance values, so that composed PDUs can borrow their parameter value like the other constants
+static QOS_MAINTENANCE_GLOBALLY_UNIQUE_VALUES: [u8; 32] = {
+    let mut values = [0u8; 32];
+    let mut i = 0;
+    while i < 32 {
+        values[i] = QOS_MAINTENANCE_FORMAT_GLOBALLY_UNIQUE | i as u8;
+        i += 1;
+    }
+    values
+};
+const PARAMETER_CODE_PRIORITY: u8 = 0b1100_1101;
+/// X.233 7.5.7 priority values 0000 0000 (normal) to 0000 1110 (highest)
+pub const PRIORITY_HIGHEST: u8 = 14;
+
 const CHECKSUM_INVALID_IGNORE: (&u8, &u8) = (&0, &0);  // X.233 7.2.9 PDU checksum and X.233 6.19 e) for Echo Request function
 const SEGMENT_LENGTH_INVALID: u16 = 0;  // X.233 6.19 e) for Echo Request function
 
@@ -121,9 +151,7 @@ impl<'a> Pdu<'_> {
 
                 // options part
                 if let Some(opts_inner) = opts {
-                    bytes += opts_inner.len_bytes();
-                    //TODO compose the options
-                    todo!();
+                    bytes += opts_inner.into_buf(&mut buffer[bytes..]);
                 }
 
                 // reason for discard part
@@ -131,29 +159,7 @@ impl<'a> Pdu<'_> {
 
                 // now set the checksum for the header
                 if checksum_option {
-                    // calculate checksum
-                    /*
-                    see X.233 6.11 PDU header error detection function
-                    and X.233 Annex C Algorithms for PDU header error detection function
-                    ideas in Wireshark OSI protocols dissector:  https://gitlab.com/wireshark/wireshark/-/blob/master/epan/dissectors/packet-osi.c#L113
-                    efficient mod-255 computation:  https://stackoverflow.com/questions/68074457/efficient-modulo-255-computation
-                    difference modulos and remainder:  https://stackoverflow.com/questions/31210357/is-there-a-modulus-not-remainder-function-operation
-                    */
-                    //TODO optimize, this is the 1:1 naive "mod 255 arithmetic calculation variant" given in X.233
-                    let mut c0: isize = 0;
-                    let mut c1: isize = 0;
-                    for i in 0..bytes {
-                        c0 = c0 + buffer[i] as isize;
-                        c1 = c1 + c0;
-                    }
-                    let mut x = ((bytes as isize - 8) * c0 - c1).rem_euclid(255);
-                    let mut y = ((bytes as isize - 7) * (-1 * c0) + c1).rem_euclid(255);   // % operator would give wrong result for negative y
-                    if x == 0 { x = 255; }
-                    if y == 0 { y = 255; }
-
-                    // assign into fixed part field
-                    buffer[7] = x as u8;
-                    buffer[8] = y as u8;
+                    Pdu::compute_checksum(&mut buffer[0..bytes]);
                 }
 
                 // data part
@@ -179,15 +185,18 @@ impl<'a> Pdu<'_> {
         //matches!(self, Self::Inactive { .. })
     }
 
-    pub fn from_buf(buffer: &[u8]) -> Pdu { //TODO add error handling (Result)
+    /// decompose the given NPDU - malformed PDUs are returned as error, to be discarded by the caller
+    pub fn from_buf(buffer: &[u8]) -> Result<Pdu, Error> {
+        if buffer.is_empty() {
+            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "empty PDU"));
+        }
         match buffer[0] {
             NETWORK_LAYER_PROTOCOL_IDENTIFIER_CLNP_FULL => {
                 //TODO implement correct algorithm for PDU decomposition according to standard
                 // check for length and PDU type
                 let type_;
                 if buffer.len() < 5 {
-                    // too short
-                    panic!();
+                    return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "PDU too short for fixed part"));
                 }
                 // check octet 5
                 (_, _, _, type_) = NFixedPart::decompose_octet5(&buffer[4]);
@@ -213,7 +222,8 @@ impl<'a> Pdu<'_> {
                 match type_ {   //TODO optimize does the ordering of match conditions matter? should most common case be first?
                     TYPE_ER_PDU => {
                         debug!("got an error report PDU");
-                        todo!();
+                        //TODO decompose Error Report PDU
+                        return Err(Error::new(std::io::ErrorKind::Unsupported, "Error Report PDU decomposition not implemented yet"));
                     },
                     TYPE_DT_PDU | TYPE_MD_PDU | TYPE_ERQ_PDU | TYPE_ERP_PDU => {
                         match type_ {
@@ -227,38 +237,42 @@ impl<'a> Pdu<'_> {
                         // decompose PDU
 
                         // fixed part
-                        //TODO check if buffer is actually that long
                         let fixed_part_length: usize = 9; //TODO optimize const
-                        let (fixed_part, segmentation_part_present) = NFixedPart::from_buf(&buffer[0..fixed_part_length]).expect("failed to decompose fixed part");
+                        let (fixed_part, segmentation_part_present) = NFixedPart::from_buf(buffer)?;
                         if fixed_part.ms_more_segments && !fixed_part.sp_segmentation_permitted {
                             // combination not allowed
-                            panic!("sp_segmentation_permitted=false but ms_more_segments=true not allowed");
+                            return Err(Error::new(std::io::ErrorKind::InvalidData, "sp_segmentation_permitted=false but ms_more_segments=true not allowed"));
                         }
                         if fixed_part.ms_more_segments {
                             // segmentation has occured
-                            todo!();
+                            //TODO reassembly function, X.233 6.8
+                            return Err(Error::new(std::io::ErrorKind::Unsupported, "reassembly not implemented yet"));
+                        }
+                        if (*fixed_part.length_indicator.unwrap() as usize) > buffer.len() {
+                            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "PDU shorter than header length indicator"));
                         }
 
                         // address part
-                        //TODO check if buffer length is at least 1+1+1+1 bytes more
-                        let (address_part, address_part_length) = NAddressPart::from_buf(&buffer[fixed_part_length..buffer.len()]).expect("failed to decompose address part");
+                        let (address_part, address_part_length) = NAddressPart::from_buf(&buffer[fixed_part_length..buffer.len()])?;
 
                         // segmentation part
                         let segmentation_part;
                         let segmentation_part_length;
                         if segmentation_part_present {
                             //TODO optimize - is always 6 bytes
-                            (segmentation_part, segmentation_part_length) = NSegmentationPart::from_buf(&buffer[(fixed_part_length+address_part_length)..buffer.len()]).expect("failed to decompose segmentation part");
+                            (segmentation_part, segmentation_part_length) = NSegmentationPart::from_buf(&buffer[(fixed_part_length+address_part_length)..buffer.len()])?;
                         } else {
                             segmentation_part = None; segmentation_part_length = 0;
                         }
 
                         // options part
                         let options_part;
-                        let options_part_length = (*fixed_part.length_indicator.unwrap() as usize) - (fixed_part_length + address_part_length + segmentation_part_length);
+                        let options_part_length = (*fixed_part.length_indicator.unwrap() as usize).checked_sub(fixed_part_length + address_part_length + segmentation_part_length)
+                            .ok_or(Error::new(std::io::ErrorKind::InvalidData, "header length indicator shorter than fixed, address and segmentation part"))?;
                         let options_part_present = options_part_length != 0;
                         if options_part_present {
-                            options_part = NOptionsPart::from_buf(&buffer[(fixed_part_length+address_part_length+segmentation_part_length)..buffer.len()]).expect("failed to decompose options part");
+                            let options_part_start = fixed_part_length+address_part_length+segmentation_part_length;
+                            options_part = NOptionsPart::from_buf(&buffer[options_part_start..options_part_start+options_part_length])?;
                         } else {
                             options_part = None;
                         }
@@ -272,37 +286,158 @@ impl<'a> Pdu<'_> {
 
                         // data part
                         let header_length = fixed_part_length+address_part_length+segmentation_part_length+options_part_length+reason_for_discard_part_length;
-                        let data_part_length = (fixed_part.segment_length.unwrap() as usize) - header_length;   // TODO if segmented, then this is I think not correct
-                        let data_part = NDataPart::from_buf(&buffer[header_length..buffer.len()], data_part_length).expect("failed to decompose data part");  //TODO optimize conversion/casting
+                        // NOTE: using the segment length also removes any padding the SN has added to short frames
+                        let data_part_length;
+                        if fixed_part.segment_length == Some(SEGMENT_LENGTH_INVALID) {
+                            // e.g. inner Echo Response PDU as per X.233 6.19 e) - have to take all, padding cannot be recognized
+                            data_part_length = buffer.len().saturating_sub(header_length);
+                        } else {
+                            data_part_length = (fixed_part.segment_length.unwrap() as usize).saturating_sub(header_length);   // TODO if segmented, then this is I think not correct
+                        }
+                        let data_part = NDataPart::from_buf(&buffer[header_length..buffer.len()], data_part_length)?;  //TODO optimize conversion/casting
                         //TODO check for overhead bytes
 
                         //TODO decompose Echo Response contained in the Echo Request PDU data part
 
                         // assemble and return decomposed PDU
                         match type_ {
-                            TYPE_DT_PDU => { return Pdu::DataPDU { fixed: fixed_part, addr: address_part, seg: segmentation_part, opts: options_part, discard: reason_for_discard_part, data: data_part }; },
-                            TYPE_MD_PDU => { return Pdu::MulticastDataPDU { fixed: fixed_part, addr: address_part, seg: segmentation_part, opts: options_part, discard: reason_for_discard_part, data: data_part }; },
-                            TYPE_ERQ_PDU => { return Pdu::EchoRequestPDU { fixed: fixed_part, addr: address_part, seg: segmentation_part, opts: options_part, discard: reason_for_discard_part, data: data_part }; },
-                            TYPE_ERP_PDU => { return Pdu::EchoResponsePDU { fixed: fixed_part, addr: address_part, seg: segmentation_part, opts: options_part, discard: reason_for_discard_part, data: data_part }; },
-                            _ => { todo!(); }
+                            TYPE_DT_PDU => { return Ok(Pdu::DataPDU { fixed: fixed_part, addr: address_part, seg: segmentation_part, opts: options_part, discard: reason_for_discard_part, data: data_part }); },
+                            TYPE_MD_PDU => { return Ok(Pdu::MulticastDataPDU { fixed: fixed_part, addr: address_part, seg: segmentation_part, opts: options_part, discard: reason_for_discard_part, data: data_part }); },
+                            TYPE_ERQ_PDU => { return Ok(Pdu::EchoRequestPDU { fixed: fixed_part, addr: address_part, seg: segmentation_part, opts: options_part, discard: reason_for_discard_part, data: data_part }); },
+                            TYPE_ERP_PDU => { return Ok(Pdu::EchoResponsePDU { fixed: fixed_part, addr: address_part, seg: segmentation_part, opts: options_part, discard: reason_for_discard_part, data: data_part }); },
+                            _ => { unreachable!(); }
                         }
                     },
                     _ => {
                         // unknown PDU type
-                        panic!("unknown CLNP NPDU type: {}", type_);
+                        return Err(Error::new(std::io::ErrorKind::InvalidData, format!("unknown CLNP NPDU type: {}", type_)));
                     }
                 }
             },
             NETWORK_LAYER_PROTOCOL_IDENTIFIER_CLNP_INACTIVE => {
-                Pdu::Inactive {
+                return Ok(Pdu::Inactive {
                     fixed_mini: NFixedPartMiniForInactive { network_layer_protocol_identifier: &buffer[0] },
-                    data: NDataPart { data: &buffer[1..buffer.len()] }  //TODO note, we dont really know how long the data part is at this point
-                }
+                    // NOTE: there is no length in the inactive subset, so NS only uses it for PDUs which do not get padded by the SN - see can_use_inactive_subset()
+                    data: NDataPart { data: &buffer[1..buffer.len()] }
+                });
             }
             _ => {
-                todo!();
+                return Err(Error::new(std::io::ErrorKind::InvalidData, "unknown network layer protocol identifier"));
+            }
+        }
+    }
+
+    /// calculate checksum over the given PDU header and set it in the fixed part
+    fn compute_checksum(header: &mut [u8]) {
+        /*
+        see X.233 6.11 PDU header error detection function
+        and X.233 Annex C Algorithms for PDU header error detection function
+        ideas in Wireshark OSI protocols dissector:  https://gitlab.com/wireshark/wireshark/-/blob/master/epan/dissectors/packet-osi.c#L113
+        efficient mod-255 computation:  https://stackoverflow.com/questions/68074457/efficient-modulo-255-computation
+        difference modulos and remainder:  https://stackoverflow.com/questions/31210357/is-there-a-modulus-not-remainder-function-operation
+        */
+        //TODO optimize, this is the 1:1 naive "mod 255 arithmetic calculation variant" given in X.233
+        let bytes = header.len();
+        // the checksum algorithm requires 0 for the checksum bytes at first
+        header[7] = 0;
+        header[8] = 0;
+        let mut c0: isize = 0;
+        let mut c1: isize = 0;
+        for i in 0..bytes {
+            c0 = c0 + header[i] as isize;
+            c1 = c1 + c0;
+        }
+        let mut x = ((bytes as isize - 8) * c0 - c1).rem_euclid(255);
+        let mut y = ((bytes as isize - 7) * (-1 * c0) + c1).rem_euclid(255);   // % operator would give wrong result for negative y
+        if x == 0 { x = 255; }
+        if y == 0 { y = 255; }
+
+        // assign into fixed part field
+        header[7] = x as u8;
+        header[8] = y as u8;
+    }
+
+    /// X.233 6.18 Congestion notification function
+    /// set the congestion experienced flag in the QoS maintenance parameter of an already encoded PDU, if it carries one in the globally unique format
+    /// returns if the flag was set
+    pub fn set_congestion_experienced(buffer: &mut [u8]) -> bool {
+        if buffer.len() < 9 || buffer[0] != NETWORK_LAYER_PROTOCOL_IDENTIFIER_CLNP_FULL {
+            // inactive subset has no options part
+            return false;
+        }
+        let header_length = buffer[1] as usize;
+        if buffer.len() < header_length {
+            return false;
+        }
+        let (sp_segmentation_permitted, _, _, _) = NFixedPart::decompose_octet5(&buffer[4]);
+        // skip address part and segmentation part
+        let mut offset = 9;
+        for _ in 0..2 {
+            if offset >= header_length {
+                return false;
+            }
+            offset += 1 + buffer[offset] as usize;
+        }
+        if sp_segmentation_permitted {
+            offset += 6;
+        }
+        // options part
+        while offset + 2 <= header_length {
+            let parameter_code = buffer[offset];
+            let parameter_length = buffer[offset+1] as usize;
+            if offset + 2 + parameter_length > header_length {
+                return false;
+            }
+            if parameter_code == PARAMETER_CODE_QOS_MAINTENANCE && parameter_length == 1
+                && (buffer[offset+2] & QOS_MAINTENANCE_FORMAT_MASK) == QOS_MAINTENANCE_FORMAT_GLOBALLY_UNIQUE {
+                buffer[offset+2] |= QOS_MAINTENANCE_CONGESTION_EXPERIENCED;
+                // header changed, so checksum has to be recalculated - unless checksum is not in use
+                if !(buffer[7] == 0 && buffer[8] == 0) {
+                    Pdu::compute_checksum(&mut buffer[0..header_length]);
+                }
+                return true;
             }
+            offset += 2 + parameter_length;
         }
+        return false;
+    }
+
+    /// X.233 7.9 Error Report PDU for the given discarded PDU, in the non-segmenting protocol subset -
+    /// None if no ER PDU may be sent for it, because its error report flag is not set, it is an ER PDU itself (X.233 6.10)
+    /// or its header cannot be made out
+    pub fn compose_error_report(discarded: &[u8], reason: u8) -> Option<Vec<u8>> {
+        if discarded.len() < 9 || discarded[0] != NETWORK_LAYER_PROTOCOL_IDENTIFIER_CLNP_FULL {
+            return None;
+        }
+        let header_length = discarded[1] as usize;
+        if header_length < 9 || discarded.len() < header_length {
+            return None;
+        }
+        let (_, _, er_error_report, type_) = NFixedPart::decompose_octet5(&discarded[4]);
+        if !er_error_report || type_ == TYPE_ER_PDU {
+            return None;
+        }
+        let header = &discarded[0..header_length];
+        let (address_part, _) = NAddressPart::from_buf(&header[9..]).ok()?;
+
+        // fixed part - length indicator, segment length and checksum are filled in below
+        let lifetime = ((1000*10)/500) as u8;   //TODO 10 seconds, same as for DT PDUs
+        let mut buffer = vec![NETWORK_LAYER_PROTOCOL_IDENTIFIER_CLNP_FULL, 0, VERSION_PROTOCOL_ID_EXTENSION_1, lifetime, TYPE_ER_PDU, 0, 0, 0, 0];
+        // address part - back to the source of the discarded PDU
+        buffer.push(address_part.source_address.len() as u8);
+        buffer.extend_from_slice(&address_part.source_address);
+        buffer.push(address_part.destination_address.len() as u8);
+        buffer.extend_from_slice(&address_part.destination_address);
+        // reason for discard part, X.233 7.9.5 - no pointer to the octet in error for congestion
+        buffer.extend_from_slice(&[PARAMETER_CODE_REASON_FOR_DISCARD, 2, reason, 0]);
+        let er_header_length = buffer.len();
+        buffer[1] = er_header_length as u8;
+        // data part - the entire header of the discarded PDU, X.233 7.9.7
+        buffer.extend_from_slice(header);
+        let segment_length = buffer.len() as u16;
+        buffer[5..7].copy_from_slice(&segment_length.to_be_bytes());
+        Pdu::compute_checksum(&mut buffer[0..er_header_length]);
+        return Some(buffer);
     }
 
     //TODO implement and use in Pdu::new_echo_request()
@@ -581,6 +716,9 @@ impl NAddressPart<'_> {
             return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "given address part buffer too short to accomodate destination address"));
         }
         let destination_address = &buffer[1..1+(*destination_address_length_indicator as usize)];
+        if buffer.len() < 1 + (*destination_address_length_indicator as usize) + 1 {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "given address part buffer too short to accomodate source address length indicator"));
+        }
         let source_address_length_indicator: &u8 = &buffer[1+(*destination_address_length_indicator as usize)];
         if buffer.len() < 1 + (*destination_address_length_indicator as usize) + 1 + (*source_address_length_indicator as usize) {
             return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "given address part buffer too short to accomodate source address"));
@@ -615,9 +753,9 @@ impl NSegmentationPart {
             return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "given segmentation part buffer too short"));
         }
         let segmentation_part = NSegmentationPart {
-            data_unit_identifier: u16::from_be_bytes(buffer[0..1].try_into().unwrap()),    //TODO optimize these calls
-            segment_offset: u16::from_be_bytes(buffer[2..3].try_into().unwrap()),
-            total_length: u16::from_be_bytes(buffer[4..5].try_into().unwrap()),
+            data_unit_identifier: u16::from_be_bytes([buffer[0], buffer[1]]),
+            segment_offset: u16::from_be_bytes([buffer[2], buffer[3]]),
+            total_length: u16::from_be_bytes([buffer[4], buffer[5]]),
         };
         return Ok((
             Some(segmentation_part),    // decision about None or Some is made outside in Pdu::from_buf()
@@ -628,7 +766,7 @@ impl NSegmentationPart {
 
 #[derive(Debug)]
 pub struct NOptionsPart<'a> {
-    params: &'a [NParameter<'a>]
+    params: Vec<NParameter<'a>>
 }
 
 /// only contained in NOptionsPart
@@ -649,11 +787,102 @@ impl NOptionsPart<'_> {
         return bytes;
     }
 
-    fn from_buf(buffer: &[u8]) -> Result<Option<Self>, Error> {
-        todo!()
+    /// buffer has to contain exactly the options part
+    fn from_buf<'a>(buffer: &'a [u8]) -> Result<Option<NOptionsPart<'a>>, Error> {
+        let mut params = vec![];
+        let mut offset = 0;
+        while offset < buffer.len() {
+            if buffer.len() < offset + 2 {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "given options part buffer too short for parameter code and length"));
+            }
+            let parameter_length = buffer[offset+1] as usize;
+            if buffer.len() < offset + 2 + parameter_length {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "given options part buffer too short for parameter value"));
+            }
+            // X.233 7.5.1 - a parameter shall not occur more than once
+            if params.iter().any(|param: &NParameter| *param.parameter_code == buffer[offset]) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "duplicate option"));
+            }
+            params.push(NParameter {
+                parameter_code: &buffer[offset],
+                parameter_length: &buffer[offset+1],
+                parameter_value: &buffer[offset+2..offset+2+parameter_length],
+            });
+            offset += 2 + parameter_length;
+        }
+        if params.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(NOptionsPart { params: params }));
+    }
+
+    /// returns number of bytes written
+    fn into_buf(&self, buffer: &mut [u8]) -> usize {
+        let mut bytes = 0;
+        for param in &self.params {
+            buffer[bytes] = *param.parameter_code;
+            buffer[bytes+1] = param.parameter_value.len() as u8;
+            buffer[bytes+2..bytes+2+param.parameter_value.len()].copy_from_slice(param.parameter_value);
+            bytes += 2 + param.parameter_value.len();
+        }
+        return bytes;
+    }
+
+    fn get(&self, parameter_code: u8) -> Option<&NParameter<'_>> {
+        return self.params.iter().find(|param| *param.parameter_code == parameter_code);
+    }
+
+    /// X.233 7.5.7 Priority - 0 is normal, 14 is highest
+    pub fn priority(&self) -> Option<u8> {
+        return self.get(PARAMETER_CODE_PRIORITY)
+            .filter(|param| param.parameter_value.len() == 1)
+            .map(|param| param.parameter_value[0].min(PRIORITY_HIGHEST));
+    }
+
+    /// X.233 7.5.6 QoS maintenance - only the globally unique format is understood
+    pub fn qos_maintenance(&self) -> Option<u8> {
+        return self.get(PARAMETER_CODE_QOS_MAINTENANCE)
+            .filter(|param| param.parameter_value.len() == 1)
+            .filter(|param| (param.parameter_value[0] & QOS_MAINTENANCE_FORMAT_MASK) == QOS_MAINTENANCE_FORMAT_GLOBALLY_UNIQUE)
+            .map(|param| param.parameter_value[0]);
+    }
+
+    /// recover the NS quality of service from priority and QoS maintenance parameters
+    /// NOTE: importances are reconstructed from the pairwise preferences, so only their order survives the transfer
+    pub fn quality_of_service(&self) -> Qos {
+        let mut ns_quality_of_service = Qos::default();
+        ns_quality_of_service.priority = self.priority().unwrap_or(0);
+        if let Some(value) = self.qos_maintenance() {
+            let transit_delay_over_cost = value & QOS_MAINTENANCE_TRANSIT_DELAY_OVER_COST != 0;
+            let residual_error_probability_over_transit_delay = value & QOS_MAINTENANCE_RESIDUAL_ERROR_PROBABILITY_OVER_TRANSIT_DELAY != 0;
+            let residual_error_probability_over_cost = value & QOS_MAINTENANCE_RESIDUAL_ERROR_PROBABILITY_OVER_COST != 0;
+            ns_quality_of_service.transit_delay = 1 + transit_delay_over_cost as u8 + !residual_error_probability_over_transit_delay as u8;
+            ns_quality_of_service.residual_error_probability = 1 + residual_error_probability_over_transit_delay as u8 + residual_error_probability_over_cost as u8;
+            ns_quality_of_service.cost = 1 + !transit_delay_over_cost as u8 + !residual_error_probability_over_cost as u8;
+            ns_quality_of_service.sequencing_preference = value & QOS_MAINTENANCE_SEQUENCING_OVER_TRANSIT_DELAY != 0;
+        }
+        return ns_quality_of_service;
     }
 }
 
+/// X.233 7.5.6.3 globally unique QoS maintenance value for the given NS quality of service
+fn compose_qos_maintenance(ns_quality_of_service: &Qos) -> &'static u8 {
+    let mut bits = 0u8;
+    if ns_quality_of_service.sequencing_preference {
+        bits |= QOS_MAINTENANCE_SEQUENCING_OVER_TRANSIT_DELAY;
+    }
+    if ns_quality_of_service.transit_delay > ns_quality_of_service.cost {
+        bits |= QOS_MAINTENANCE_TRANSIT_DELAY_OVER_COST;
+    }
+    if ns_quality_of_service.residual_error_probability > ns_quality_of_service.transit_delay {
+        bits |= QOS_MAINTENANCE_RESIDUAL_ERROR_PROBABILITY_OVER_TRANSIT_DELAY;
+    }
+    if ns_quality_of_service.residual_error_probability > ns_quality_of_service.cost {
+        bits |= QOS_MAINTENANCE_RESIDUAL_ERROR_PROBABILITY_OVER_COST;
+    }
+    return &QOS_MAINTENANCE_GLOBALLY_UNIQUE_VALUES[bits as usize];
+}
+
 #[derive(Debug)]
 pub struct NReasonForDiscardPart<'a> {
     /// has format of a parameter from the options part
@@ -680,30 +909,58 @@ pub struct Service<'a> {
     // internal state
     pub serviced_nsaps: Vec<Nsap>,  //TODO should be via get_serviced_nsap() but this would mean a 2nd borrow (borrow-checker understands direct variable access but if it is done via a method like get_serviced_nsap() then locks the whole service variable and we have a 2nd borrow)
     known_hosts: HashMap<String, Nsap>,
+    /// which subnetwork an SNPA address was configured resp. last heard on
+    snpa_subnetworks: Arc<Mutex<HashMap<MacAddr6, usize>>>,
     network_entity_title: &'a str,   // own title
     echo_request_correlation_table: Arc<Mutex<HashMap<u16, DateTime<Utc>>>>,    //TODO harden for collisions //TODO currently this is global correlation - have this per-target-NSAP?
+    forwarding_table: Arc<Mutex<ForwardingTable>>,
+    adjacencies: Arc<Mutex<Vec<Adjacency>>>,
+    /// NOTE: shared with the receive thread, which sends Echo Responses
+    congestion_notification: Arc<Mutex<CongestionNotification>>,
+    subnetwork_listeners: Arc<Mutex<Vec<SubnetworkListener>>>,
+
+    // users of the service = transport layer and IDRP
+    ns_users: Arc<Mutex<Vec<NsUser>>>,
 
     // underlying service assumed by the protocol = subnet service on data link layer
-    sn_service_to: Arc<Mutex<rtrb::Producer<SNUnitDataRequest>>>,
-    sn_service_to_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
-    sn_service_from: Arc<Mutex<rtrb::Consumer<NUnitDataIndication>>>,
+    // NOTE: the following are per subnetwork, in the same order
+    subnetworks: Vec<SubnetworkAttachment>,
+    subnetwork_up: Arc<Mutex<Vec<bool>>>,
+    sn_service_to: Arc<Mutex<Vec<Vec<rtrb::Producer<SNUnitDataRequest>>>>>,   // one per priority band
+    sn_service_to_wakeup: Vec<Arc<Mutex<Option<JoinHandle<Thread>>>>>,
+    sn_service_to_waiting: Vec<Arc<Mutex<Vec<Thread>>>>,
+    sn_service_from: Arc<Mutex<Vec<rtrb::Consumer<NUnitDataIndication>>>>,
+    sn_status_from: Arc<Mutex<Vec<rtrb::Consumer<SubnetworkEvent>>>>,
 }
 
 impl<'a> super::NetworkService<'a> for Service<'a> {
     fn new(
         network_entity_title: &'a str,
-        sn_service_to: rtrb::Producer<SNUnitDataRequest>,
-        sn_service_to_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
-        sn_service_from: rtrb::Consumer<NUnitDataIndication>
+        subnetworks: Vec<SubnetworkAttachment>,
+        sn_service_to: Vec<Vec<rtrb::Producer<SNUnitDataRequest>>>,
+        sn_service_to_wakeup: Vec<Arc<Mutex<Option<JoinHandle<Thread>>>>>,
+        sn_service_to_waiting: Vec<Arc<Mutex<Vec<Thread>>>>,
+        sn_service_from: Vec<rtrb::Consumer<NUnitDataIndication>>,
+        sn_status_from: Vec<rtrb::Consumer<SubnetworkEvent>>
     ) -> Service<'a> {
         Service {
             serviced_nsaps: vec![],
             known_hosts: HashMap::new(),
+            snpa_subnetworks: Arc::new(Mutex::new(HashMap::new())),
             network_entity_title: network_entity_title,
             echo_request_correlation_table: Arc::new(Mutex::new(HashMap::new())),
+            forwarding_table: Arc::new(Mutex::new(ForwardingTable::default())),
+            ns_users: Arc::new(Mutex::new(vec![])),
+            adjacencies: Arc::new(Mutex::new(vec![])),
+            congestion_notification: Arc::new(Mutex::new(CongestionNotification::default_for_capacity(sn_service_to[0][0].buffer().capacity()))),   // all bands have the same capacity
+            subnetwork_listeners: Arc::new(Mutex::new(vec![])),
+            subnetwork_up: Arc::new(Mutex::new(subnetworks.iter().map(|subnetwork| subnetwork.up).collect())),
+            subnetworks: subnetworks,
             sn_service_to: Arc::new(Mutex::new(sn_service_to)),
             sn_service_to_wakeup: sn_service_to_wakeup,
+            sn_service_to_waiting: sn_service_to_waiting,
             sn_service_from: Arc::new(Mutex::new(sn_service_from)),
+            sn_status_from: Arc::new(Mutex::new(sn_status_from)),
         }
     }
 
@@ -734,12 +991,14 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
 
     //TODO quick version - implement proper name lookup
     //TODO currently we use MAC access for "NSAP"
-    fn add_known_host(&mut self, system_title: String, nsap: &str) {
+    fn add_known_host(&mut self, system_title: String, nsap: &str, subnetwork: usize) {
+        let local_address = parse_macaddr(nsap).expect("could not parse mac address");
+        self.snpa_subnetworks.lock().expect("failed to lock snpa_subnetworks").insert(local_address, subnetwork);
         self.known_hosts.insert(system_title, Nsap {
             authority: 49,
             area: 1,
             sub_area: 1,
-            local_address: parse_macaddr(nsap).expect("could not parse mac address"),
+            local_address: local_address,
         });
     }
 
@@ -748,6 +1007,49 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
         return self.serviced_nsaps.get(0);
     }
 
+    fn forwarding_table(&self) -> Arc<Mutex<ForwardingTable>> {
+        return self.forwarding_table.clone();
+    }
+
+    fn set_congestion_notification(&mut self, congestion_notification: CongestionNotification) {
+        *self.congestion_notification.lock().expect("failed to lock congestion_notification") = congestion_notification;
+    }
+
+    fn adjacencies(&self) -> Arc<Mutex<Vec<Adjacency>>> {
+        return self.adjacencies.clone();
+    }
+
+    fn add_subnetwork_listener(&mut self, listener: SubnetworkListener) {
+        self.subnetwork_listeners.lock().expect("failed to lock subnetwork_listeners").push(listener);
+    }
+
+    fn management_information(&self) -> ManagementInformation {
+        let snpa_subnetworks = self.snpa_subnetworks.lock().expect("failed to lock snpa_subnetworks");
+        let mut snpa_cache: Vec<SnpaCacheEntry> = self.known_hosts.iter().map(|(system_title, nsap)| SnpaCacheEntry {
+            system_title: system_title.clone(),
+            nsap: nsap.clone(),
+            snpa: nsap.local_address,   //TODO currently the SNPA address is simply contained in the "NSAP"
+            subnetwork: snpa_subnetworks.get(&nsap.local_address).copied(),
+        }).collect();
+        drop(snpa_subnetworks);
+        snpa_cache.sort_by(|a, b| a.system_title.cmp(&b.system_title));
+        // NOTE: expired adjacencies are removed by the maintenance thread, until then just not reported
+        let now = Utc::now();
+        let adjacencies: Vec<Adjacency> = self.adjacencies.lock().expect("failed to lock adjacencies").iter().filter(|adjacency| adjacency.expires > now).cloned().collect();
+        let mut subnetworks = self.subnetworks.clone();
+        for (subnetwork, up) in subnetworks.iter_mut().zip(self.subnetwork_up.lock().expect("failed to lock subnetwork_up").iter()) {
+            subnetwork.up = *up;
+        }
+        return ManagementInformation {
+            network_entity_title: self.network_entity_title.to_owned(),
+            subnetworks: subnetworks,
+            serviced_nsaps: self.serviced_nsaps.clone(),
+            forwarding_table: self.forwarding_table.lock().expect("failed to lock forwarding_table").entries().to_vec(),
+            adjacencies: adjacencies,
+            snpa_cache: snpa_cache,
+        };
+    }
+
     //TODO it seems this should be used only for CLNP Data PDUs (and if they go to a Multicast address, then incidentially Multicast Data PDUs) but not for Echo Request PDUs
     // because the N-UNITDATA-REQUEST does not have a parameter for CLNP PDU type so there is no way to select composition of an Echo Request PDU
     fn n_unitdata_request(
@@ -755,9 +1057,21 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
         ns_destination_title: &str,
         ns_quality_of_service: &Qos,
         ns_userdata: &[u8]
-    ) {
-        let get_serviced_nsap = self.get_serviced_nsap().expect("no serviced NSAPs").clone();   //TODO optimize clone - again the cannot borrow self 2 times issue
+    ) -> Result<(), Error> {
         let dest_nsap = self.resolve_nsap(ns_destination_title).expect("cannot resolve destination host").clone();  //TODO optimize clone - again the cannot borrow self 2 times issue
+        return self.n_unitdata_request_to_nsap(&dest_nsap, ns_quality_of_service, ns_userdata);
+    }
+
+    fn n_unitdata_request_to_nsap(
+        &mut self,
+        ns_destination_address: &Nsap,
+        ns_quality_of_service: &Qos,
+        ns_userdata: &[u8]
+    ) -> Result<(), Error> {
+        let dest_nsap = ns_destination_address.clone();    //TODO optimize clone
+        let (subnetwork, next_hop_snpa) = self.route(&dest_nsap);
+        self.check_subnetwork_up(subnetwork)?;
+        let get_serviced_nsap = self.serviced_nsap_on(subnetwork).expect("no serviced NSAPs").clone();   //TODO optimize clone - again the cannot borrow self 2 times issue
         /*
         self.n_unitdata_request_internal(
             &get_serviced_nsap,
@@ -768,52 +1082,78 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
         */
         let ns_source_address = get_serviced_nsap;
         let ns_destination_address = dest_nsap;
-        // check if we are on same Ethernet broadcast domain as destination
-        if can_use_inactive_subset(&ns_source_address, &ns_destination_address) {
-            // compose PDU(s)
-            let pdus = self.pdu_composition(true, &ns_source_address, &ns_destination_address, ns_quality_of_service, ns_userdata);
-            // unitdata request to SN
-            for mut pdu in pdus {   //TODO optimize this should iterate over &Pdu not Pdu (copy?)
-                let mut buffer = [0u8; 1500];    //TODO optimize this whole to_buf and transfer to SN
-                let bytes = pdu.into_buf(true, &mut buffer);
-                let mut thevec: Vec<u8> = Vec::with_capacity(bytes);
-                thevec.extend_from_slice(&buffer[0..bytes]);
-                self.sn_service_to.lock().expect("failed to lock sn_service_to").push(SNUnitDataRequest{
-                    sn_source_address: ns_source_address.local_address,
-                    sn_destination_address: ns_destination_address.local_address,
-                    sn_quality_of_service: crate::dl::Qos{},   //TODO optimize useless allocation; and no real conversion - the point of having two different QoS on DL and N layer is that the codes for QoS cloud be different
-                    sn_userdata: thevec,    //TODO not perfect abstraction, but should save us a memcpy
-                }).expect("failed to push into sn_service_to, SN-UNITDATA-REQUEST lost because of congestion"); //TODO congestion situation - apply congestion function
-                //self.sn_service_to.flush();   //TODO make it flush the socket - we can control this via unpark
-                self.sn_service_to_wakeup.lock().expect("failed to lock sn_service_to_wake").as_ref().expect("failed to get sn_service_to_wakeup (taker)").thread().unpark();
+        // check if we are on same Ethernet broadcast domain as destination - via an intermediate system, the NSAP addresses are needed
+        // NOTE: the inactive subset has no options part, so it cannot carry priority or QoS maintenance parameters
+        let inactive = ns_quality_of_service.priority == 0 && !ns_quality_of_service.has_preferences()
+            && next_hop_snpa == ns_destination_address.local_address
+            && can_use_inactive_subset(&ns_source_address, &ns_destination_address, ns_userdata, self.subnetworks[subnetwork].maximum_sdu_size);
+        // compose PDU(s)
+        let pdus = self.pdu_composition(inactive, &ns_source_address, &ns_destination_address, ns_quality_of_service, ns_userdata);
+        // unitdata request to SN
+        for mut pdu in pdus {   //TODO optimize this should iterate over &Pdu not Pdu (copy?)
+            // NOTE: composed right into the SN user data, sized for the largest possible header
+            let mut thevec: Vec<u8> = vec![0u8; MAXIMUM_HEADER_LENGTH + ns_userdata.len()];
+            let bytes = pdu.into_buf(true, &mut thevec);
+            thevec.truncate(bytes);
+            if bytes > self.subnetworks[subnetwork].maximum_sdu_size {
+                //TODO segmentation function, X.233 6.7
+                return Err(Error::new(std::io::ErrorKind::InvalidInput, "PDU exceeds maximum SDU size of subnetwork, segmentation not implemented yet"));
             }
-            return;
+            self.push_sn_unitdata_request(
+                subnetwork,
+                crate::dl::band_for_quality_of_service(ns_quality_of_service),
+                SNUnitDataRequest{
+                    sn_source_address: self.subnetworks[subnetwork].snpa,
+                    sn_destination_address: next_hop_snpa,
+                    sn_quality_of_service: crate::dl::Qos::from_ns_quality_of_service(ns_quality_of_service),
+                    sn_userdata: thevec,    //TODO not perfect abstraction, but should save us a memcpy
+                }
+            )?;
+            //self.sn_service_to.flush();   //TODO make it flush the socket - we can control this via unpark
         }
-        todo!();
+        return Ok(());
+    }
+
+    fn register_ns_user(&mut self, ns_user: NsUser) {
+        self.ns_users.lock().expect("failed to lock ns_users").push(ns_user);
     }
 
     //TODO implement properly (PDU decomposition)
     fn n_unitdata_indication(//&self,
-        sn_service_to: &mut rtrb::Producer<SNUnitDataRequest>,
-        sn_service_to_wakeup: &JoinHandle<Thread>,
-        echo_request_correlation_table: Arc<Mutex<HashMap<u16, DateTime<Utc>>>>,
+        context: &mut ReceiveContext,
         // actual parameters
         ns_source_address: MacAddr6,
         ns_destination_address: MacAddr6,
         ns_quality_of_service: &Qos,
         ns_userdata: &[u8]
     ) {
-        let pdu = Pdu::from_buf(ns_userdata);
-        debug!("got CLNP packet: {:?}", pdu);
+        let pdu = match Pdu::from_buf(ns_userdata) {
+            Ok(pdu) => pdu,
+            Err(e) => {
+                // X.233 6.9 PDU discard function - no Error Report, the header cannot be trusted
+                info!("discarding malformed PDU received on subnetwork {}: {}", context.arrival_subnetwork, e);
+                return;
+            }
+        };
+        debug!("got CLNP packet on subnetwork {}: {:?}", context.arrival_subnetwork, pdu);
         match pdu { //TODO optimize does match leg ordering affect performance?
             Pdu::Inactive { fixed_mini, data } => {
                 debug!("n_unitdata_indication(): got inactive protocol subset packet");
                 if let Ok(thestr) = std::str::from_utf8(&data.data) {
-                    info!("Inactive subset packet data ({} bytes, padded by SN): {}", data.data.len(), thestr);
+                    info!("Inactive subset packet data ({} bytes): {}", data.data.len(), thestr);
                 } else {
-                    info!("Inactive subset packet data ({} bytes, padded by SN): {:?}", data.data.len(), data.data);
+                    info!("Inactive subset packet data ({} bytes): {:?}", data.data.len(), data.data);
                 }
-                //TODO implement further functions
+                // NOTE: the inactive subset carries no NSAP addresses, only the SN addresses are known - same convention as add_known_host()
+                //TODO fix once NSAPs are implemented fully
+                // NOTE: no error report possible, the inactive subset has no header to report on
+                hand_up_to_ns_user(
+                    context.ns_users,
+                    Nsap { authority: 49, area: 1, sub_area: 1, local_address: ns_source_address },
+                    Nsap { authority: 49, area: 1, sub_area: 1, local_address: ns_destination_address },
+                    *ns_quality_of_service,
+                    data.data
+                );
             },
             Pdu::EchoRequestPDU { fixed, addr, seg, opts, discard, data  } => {
                 if let Some(data_inner) = data {
@@ -821,31 +1161,44 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
                     let erp_pdu_inner = Pdu::from_buf(data_inner.data);
                     debug!("got inner Echo Response: {:?}", erp_pdu_inner);
                     // respond with echo response
-                    if let Pdu::EchoResponsePDU { fixed, addr, seg, opts, discard, data } = erp_pdu_inner {
+                    if let Ok(Pdu::EchoResponsePDU { fixed, addr, seg, opts, discard, data }) = erp_pdu_inner {
                         //TODO implement correct behavior according to Echo Response function
                         //TODO add checks - otherwise this can be used for DoS attack ("please bomb that other host")
                         // send back to sender
-                        sn_service_to.push(SNUnitDataRequest {
-                            sn_source_address: ns_destination_address,
-                            sn_destination_address: ns_source_address,
-                            sn_quality_of_service: crate::dl::Qos::from_ns_quality_of_service(ns_quality_of_service),    //TODO optimize?  //TODO convert NS QoS to SN QoS
-                            sn_userdata: data_inner.data.to_vec()    //TODO security    //TODO optimize?
-                        }).expect("failed to push into sn_service_to, SN-UNITDATA-REQUEST lost because of congestion"); //TODO congestion situation, apply congestion function
-                        // wake up SN thread
-                        sn_service_to_wakeup.thread().unpark();
+                        if try_push_sn_unitdata_request(
+                            context.sn_service_to,
+                            crate::dl::BAND_CONTROL,
+                            context.sn_service_to_wakeup,
+                            &context.congestion_notification,
+                            SNUnitDataRequest {
+                                sn_source_address: ns_destination_address,
+                                sn_destination_address: ns_source_address,
+                                sn_quality_of_service: crate::dl::Qos::from_ns_quality_of_service(ns_quality_of_service),    //TODO optimize?  //TODO convert NS QoS to SN QoS
+                                sn_userdata: data_inner.data.to_vec()    //TODO security    //TODO optimize?
+                            }
+                        ).is_err() {
+                            // X.233 6.9 PDU discard function
+                            info!("echo response not sent: discarded because of congestion towards SN");
+                            report_error(context, ns_source_address, ns_destination_address, ns_userdata, REASON_CONGESTION);
+                        }
                     } else {
-                        panic!("expected inner echo response PDU inside received echo request")
+                        info!("discarding echo request PDU without a valid inner echo response PDU");
                     }
                 } else {
-                    panic!("expected inner echo response PDU inside received echo request (no data part)");
+                    info!("discarding echo request PDU without data part");
                 }
             },
             Pdu::EchoResponsePDU { fixed, addr, seg, opts, discard, data } => {
                 // correlate
                 let now = Utc::now();
-                let mut table = echo_request_correlation_table.lock().expect("failed to lock echo_request_correlation_table");
-                let correlation_data_u8 = data.expect("failed to get data part from Echo Response PDU").data;   //TODO harden
-                //TODO check if data has enough bytes for the correlation data
+                let mut table = context.echo_request_correlation_table.lock().expect("failed to lock echo_request_correlation_table");
+                let correlation_data_u8 = match data {
+                    Some(data_inner) if data_inner.data.len() >= 2 => data_inner.data,
+                    _ => {
+                        info!("discarding Echo Response PDU without correlation data");
+                        return;
+                    }
+                };
                 //TODO optimize correlation data can be just meaningless u8 data instead of nice u16 be/ne data
                 let correlation_data = u16::from_ne_bytes([correlation_data_u8[0], correlation_data_u8[1]].try_into().expect("failed to convert correlation data from ne bytes"));
                 if let Some(time_sent) = table.remove(&correlation_data) {
@@ -862,21 +1215,32 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
             }
             Pdu::DataPDU { fixed, addr, seg, opts, discard, data } => {
                 debug!("n_unitdata_indication(): got data PDU");
+                // recover NS quality of service for the NS user
+                let quality_of_service = opts.as_ref().map(|opts_inner| opts_inner.quality_of_service()).unwrap_or_default();
+                debug!("data PDU quality of service: {:?}", quality_of_service);
                 if let Some(datapart) = data {
-                    info!("data PDU data: {:?}", datapart.data);
+                    debug!("data PDU data (subnetwork {}, priority {}): {:?}", context.arrival_subnetwork, quality_of_service.priority, datapart.data);
+                    //TODO check that the destination is one of our serviced NSAPs, otherwise forward
+                    match (Nsap::from_u8(&addr.source_address), Nsap::from_u8(&addr.destination_address)) {
+                        (Some(source), Some(destination)) => {
+                            if !hand_up_to_ns_user(context.ns_users, source, destination, quality_of_service, datapart.data) {
+                                report_error(context, ns_source_address, ns_destination_address, ns_userdata, REASON_CONGESTION);
+                            }
+                        },
+                        _ => { info!("data PDU with unsupported NSAP address format, discarding"); }
+                    }
                 } else {
                     info!("data PDU data: None");
                 }
                 //TODO implement further functions
             }
             Pdu::MulticastDataPDU { fixed, addr, seg, opts, discard, data } => {
-                debug!("n_unitdata_indication(): got multicast data PDU");
-                todo!();
+                //TODO multicast extension to CLNP
+                info!("n_unitdata_indication(): multicast data PDU not supported, discarding");
             }
             Pdu::ErrorReportPDU { fixed, addr, opts, discard, data } => {
-                debug!("n_unitdata_indication(): got error report PDU");
-                todo!();
                 //TODO correlate to previously-sent PDU (how?)
+                info!("n_unitdata_indication(): got error report PDU");
             }
             _ => { info!("n_unitdata_indication(): unknown CLNP PDU type"); }
         }
@@ -890,7 +1254,7 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
         source_address_index: Option<usize>,
         options: Option<NOptionsPart>,
         quality_of_service: &crate::n::Qos
-    ) {
+    ) -> Result<(), Error> {
         // destination
         let destination_address: &Nsap;
         if let Some(ref destination_title2) = destination_title {
@@ -913,8 +1277,10 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
         } else {
             source_address = &self.resolve_nsap(self.network_entity_title).expect("failed to get own NSAP");
         }
+        let (subnetwork, next_hop_snpa) = self.route(destination_address);
+        self.check_subnetwork_up(subnetwork)?;
         //TODO super-clunky
-        info!("echo request from {} to {}: ", source_address.to_string(), destination_address.to_string());
+        info!("echo request from {} to {} on subnetwork {}: ", source_address.to_string(), destination_address.to_string(), subnetwork);
 
         // check length
         //TODO 6.19 d)
@@ -924,6 +1290,7 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
 
         // compose ERQ PDU
         let mut buffer_scratch = [0u8; 64];
+        let scratch_length = buffer_scratch.len();  // the inner Echo Response PDU is composed in there
         let mut erq_pdu = Pdu::new_echo_request(
             false,   //TODO implement non-segmenting protocol subset properly - refer to NS.operating mode or so
             &source_address,
@@ -934,19 +1301,20 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
         );
 
         // send it via data link or subnetwork
-        let sn_quality_of_service = crate::dl::Qos{};  //TODO convert Network Layer QoS to Data Link Layer QoS
-        let mut buffer = [0u8; 1500];   //TODO optimize this whole to_buf and transfer to SN
-        let bytes = erq_pdu.into_buf(true, &mut buffer);
-        let mut thevec: Vec<u8> = Vec::with_capacity(bytes);
-        thevec.extend_from_slice(&buffer[0..bytes]);
-        self.sn_service_to.lock().expect("failed to lock sn_service_to").push(SNUnitDataRequest{
-            sn_source_address: source_address.local_address,
-            sn_destination_address: destination_address.local_address,
-            sn_quality_of_service: sn_quality_of_service,
-            sn_userdata: thevec,
-        }).expect("failed to push SNUnitDataRequest into sn_service");
-        // wake up SN thread
-        self.sn_service_to_wakeup.lock().expect("failed to lock sn_service_to_wake").as_ref().expect("failed to get sn_service_to_wakeup (taker)").thread().unpark();
+        let sn_quality_of_service = crate::dl::Qos::from_ns_quality_of_service(quality_of_service);
+        let mut thevec: Vec<u8> = vec![0u8; MAXIMUM_HEADER_LENGTH + scratch_length];
+        let bytes = erq_pdu.into_buf(true, &mut thevec);
+        thevec.truncate(bytes);
+        self.push_sn_unitdata_request(
+            subnetwork,
+            crate::dl::BAND_CONTROL,
+            SNUnitDataRequest{
+                sn_source_address: self.subnetworks[subnetwork].snpa,
+                sn_destination_address: next_hop_snpa,
+                sn_quality_of_service: sn_quality_of_service,
+                sn_userdata: thevec,
+            }
+        )?;
 
         //TODO send properly via n_unitdata_request()
         //###
@@ -963,6 +1331,7 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
 
         // add entry to correlation table
         self.echo_request_correlation_table.lock().expect("failed to lock echo_request_correlation_table").insert(correlation_data, Utc::now());
+        return Ok(());
     }
 
     fn run(&mut self,
@@ -971,32 +1340,59 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
         // read N-UNITDATA-INDICATION from SN
         let sn_service_from_arc = self.sn_service_from.clone();
         let sn_service_to_arc = self.sn_service_to.clone();
-        let sn_service_to_wakeup_arc = self.sn_service_to_wakeup.clone();
+        let sn_service_to_wakeup_arcs = self.sn_service_to_wakeup.clone();
         let echo_request_correlation_table_arc = self.echo_request_correlation_table.clone();
+        let ns_users_arc = self.ns_users.clone();
+        let congestion_notification_arc = self.congestion_notification.clone();
+        let snpa_subnetworks_arc = self.snpa_subnetworks.clone();
+        let sn_status_from_arc = self.sn_status_from.clone();
+        let subnetwork_up_arc = self.subnetwork_up.clone();
+        let adjacencies_arc = self.adjacencies.clone();
+        let subnetwork_listeners_arc = self.subnetwork_listeners.clone();
         let sn2ns_consumer_wakeup = thread::Builder::new().name("N CLNP <- SN".to_string()).spawn(move || {
             // keep permanent lock on this
             let mut sn_service_from = sn_service_from_arc.lock().expect("failed to lock sn_service_from");
+            let mut sn_status_from = sn_status_from_arc.lock().expect("failed to lock sn_status_from");
             // NOTE: cannot keep permanent lock on sn_service_to because other places need it, too
             //let mut sn_service_to = sn_service_to_arc.lock().expect("failed to lock sn_service_to");
             loop {
-                // pop all
+                // pop all, taking turns between the subnetworks
                 loop {
-                    if let Ok(n_unitdata_indication) = sn_service_from.pop() {
-                        debug!("got N UnitData indication: {:?}", n_unitdata_indication);
-                        let mut sn_service_to = sn_service_to_arc.lock().expect("failed to lock sn_service_to");
-                        let sn_service_to_wakeup_outer = sn_service_to_wakeup_arc.lock().expect("failed to lock sn_service_to_wakeup (taker)");
-                        let sn_service_to_wakeup = sn_service_to_wakeup_outer.as_ref().expect("sn_service_to_wakeup is none (taker)");
-                        //TODO optimize ^ we can surely take this join handle out and clone it - dont need to lock mutex on every call
-                        Self::n_unitdata_indication(
-                            &mut *sn_service_to,
-                            sn_service_to_wakeup,
-                            echo_request_correlation_table_arc.clone(), //TODO optimize - for now clone() to avoid "use of moved value"
-                            n_unitdata_indication.ns_source_address,
-                            n_unitdata_indication.ns_destination_address,
-                            &n_unitdata_indication.ns_quality_of_service,
-                            &n_unitdata_indication.ns_userdata
-                        );
-                    } else {
+                    let mut popped_any = false;
+                    // subnetwork state changes first, so that no more is sent on a subnetwork which went down
+                    for (subnetwork, sn_status_from_subnetwork) in sn_status_from.iter_mut().enumerate() {
+                        while let Ok(event) = sn_status_from_subnetwork.pop() {
+                            subnetwork_event(subnetwork, event, &subnetwork_up_arc, &adjacencies_arc, &subnetwork_listeners_arc);
+                        }
+                    }
+                    for (arrival_subnetwork, sn_service_from_subnetwork) in sn_service_from.iter_mut().enumerate() {
+                        if let Ok(n_unitdata_indication) = sn_service_from_subnetwork.pop() {
+                            popped_any = true;
+                            debug!("got N UnitData indication: {:?}", n_unitdata_indication);
+                            // learn where the sender is, for sending back to it
+                            snpa_subnetworks_arc.lock().expect("failed to lock snpa_subnetworks").insert(n_unitdata_indication.ns_source_address, arrival_subnetwork);
+                            let mut sn_service_to = sn_service_to_arc.lock().expect("failed to lock sn_service_to");
+                            let sn_service_to_wakeup_outer = sn_service_to_wakeup_arcs[arrival_subnetwork].lock().expect("failed to lock sn_service_to_wakeup (taker)");
+                            let sn_service_to_wakeup = sn_service_to_wakeup_outer.as_ref().expect("sn_service_to_wakeup is none (taker)");
+                            //TODO optimize ^ we can surely take this join handle out and clone it - dont need to lock mutex on every call
+                            let mut context = ReceiveContext {
+                                sn_service_to: &mut sn_service_to[arrival_subnetwork],
+                                sn_service_to_wakeup: sn_service_to_wakeup,
+                                congestion_notification: *congestion_notification_arc.lock().expect("failed to lock congestion_notification"),
+                                ns_users: &ns_users_arc,
+                                echo_request_correlation_table: &echo_request_correlation_table_arc,
+                                arrival_subnetwork: arrival_subnetwork,
+                            };
+                            Self::n_unitdata_indication(
+                                &mut context,
+                                n_unitdata_indication.ns_source_address,
+                                n_unitdata_indication.ns_destination_address,
+                                &n_unitdata_indication.ns_quality_of_service,
+                                &n_unitdata_indication.ns_userdata
+                            );
+                        }
+                    }
+                    if !popped_any {
                         break;
                     }
                 }
@@ -1009,6 +1405,7 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
 
         // maintenance thread
         let echo_request_correlation_table_arc2 = self.echo_request_correlation_table.clone();
+        let adjacencies_arc2 = self.adjacencies.clone();
         let _ = thread::Builder::new().name("N CLNP".to_string()).spawn(move || {
             let timeout = chrono::Duration::seconds(5);
             loop {
@@ -1029,6 +1426,17 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
                     }
                 }   // release lock
 
+                // remove adjacencies whose holding time has run out
+                {
+                    let now = Utc::now();
+                    adjacencies_arc2.lock().expect("failed to lock adjacencies").retain(|adjacency| {
+                        if adjacency.expires <= now {
+                            info!("adjacency to {:02x?} on subnetwork {} expired", adjacency.neighbour, adjacency.subnetwork);
+                        }
+                        return adjacency.expires > now;
+                    });
+                }   // release lock
+
                 // sleep
                 thread::sleep(Duration::from_millis(1000))
             }
@@ -1045,15 +1453,238 @@ impl<'a> super::NetworkService<'a> for Service<'a> {
                 data: NDataPart { data: ns_userdata }
             }]
         } else {
-            todo!();
+            // non-segmenting protocol subset
+            //TODO full protocol with segmentation if ns_userdata does not fit into the SN's maximum SDU size
+            let mut params = vec![];
+            if ns_quality_of_service.has_preferences() {
+                // X.233 7.5.6 QoS maintenance
+                params.push(NParameter {
+                    parameter_code: &PARAMETER_CODE_QOS_MAINTENANCE,
+                    parameter_length: &1,
+                    parameter_value: std::slice::from_ref(compose_qos_maintenance(ns_quality_of_service)),
+                });
+            }
+            if ns_quality_of_service.priority != 0 {
+                // X.233 7.5.7 Priority
+                params.push(NParameter {
+                    parameter_code: &PARAMETER_CODE_PRIORITY,
+                    parameter_length: &1,
+                    parameter_value: std::slice::from_ref(&ns_quality_of_service.priority),
+                });
+            }
+            return vec![Pdu::DataPDU {
+                fixed: NFixedPart {
+                    network_layer_protocol_identifier: &NETWORK_LAYER_PROTOCOL_IDENTIFIER_CLNP_FULL,
+                    length_indicator: None,    // will be filled
+                    version_protocol_id_extension: &VERSION_PROTOCOL_ID_EXTENSION_1,
+                    lifetime: &(((1000*10)/500) as u8),   //TODO 10 seconds  //TODO optimize converts from i32 to u8
+                    sp_segmentation_permitted: false,   // non-segmenting protocol subset
+                    ms_more_segments: false,
+                    er_error_report: false, //TODO error reporting function
+                    type_: TYPE_DT_PDU,
+                    octet5: &0,  // will be filled
+                    segment_length: None,  // will be filled
+                    checksum: CHECKSUM_INVALID_IGNORE,   // will be filled
+                },
+                addr: NAddressPart {
+                    destination_address_length_indicator: None,   // will be filled
+                    destination_address: ns_destination_address.to_u8(),
+                    source_address_length_indicator: None,    // will be filled
+                    source_address: ns_source_address.to_u8(),
+                },
+                seg: None,  // only present if sp_segmentation_permitted is set
+                opts: if params.is_empty() { None } else { Some(NOptionsPart { params: params }) },
+                discard: None,
+                data: Some(NDataPart { data: ns_userdata }),
+            }];
+        }
+    }
+}
+
+impl Service<'_> {
+    /// towards the SN of the given subnetwork, into the given priority band - if the queue is full, the congestion policy decides between waiting for the SN
+    /// to make room and discarding right away
+    /// NOTE: the queues are not kept locked while waiting, so that the receive thread is not held up
+    fn push_sn_unitdata_request(&self, subnetwork: usize, band: usize, sn_unitdata_request: SNUnitDataRequest) -> Result<(), Error> {
+        // NOTE: copied, so that set_congestion_notification() is not blocked while waiting for the SN
+        let congestion_notification = *self.congestion_notification.lock().expect("failed to lock congestion_notification");
+        let try_push = |sn_unitdata_request| {
+            return try_push_sn_unitdata_request(
+                &mut self.sn_service_to.lock().expect("failed to lock sn_service_to")[subnetwork],
+                band,
+                self.sn_service_to_wakeup[subnetwork].lock().expect("failed to lock sn_service_to_wake").as_ref().expect("failed to get sn_service_to_wakeup (taker)"),
+                &congestion_notification,
+                sn_unitdata_request
+            );
+        };
+        let mut retry = match try_push(sn_unitdata_request) {
+            Ok(_) => { return Ok(()); },
+            Err(returned) => returned,
+        };
+        if let CongestionPolicy::Backpressure(timeout) = congestion_notification.policy {
+            let deadline = Instant::now() + timeout;
+            let mut pushed = false;
+            loop {
+                // registered before trying again, so that room made in between is not missed
+                self.sn_service_to_waiting[subnetwork].lock().expect("failed to lock sn_service_to_waiting").push(thread::current());
+                match try_push(retry) {
+                    Ok(_) => { pushed = true; break; },
+                    Err(returned) => { retry = returned; }
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                // woken up by the SN once it has taken requests out of the queue
+                thread::park_timeout(deadline - now);
+            }
+            self.sn_service_to_waiting[subnetwork].lock().expect("failed to lock sn_service_to_waiting").retain(|waiting_thread| waiting_thread.id() != thread::current().id());
+            if pushed {
+                return Ok(());
+            }
+        }
+        // X.233 6.9 PDU discard function - the NS user is told, an error report to ourselves would be pointless
+        warn!("SN-UNITDATA request discarded because of congestion");
+        return Err(Error::new(std::io::ErrorKind::WouldBlock, "PDU discarded because of congestion towards SN"));
+    }
+
+    /// X.233 6.5 Route PDU function, as far as implemented - which subnetwork to send a PDU for the destination on,
+    /// and the SNPA address of the next hop on it, which is the destination itself if there is no route via an intermediate system
+    fn route(&self, destination: &Nsap) -> (usize, MacAddr6) {
+        if let Some(entry) = self.forwarding_table.lock().expect("failed to lock forwarding_table").lookup(&destination.to_u8()) {
+            if let Some(adjacency) = self.adjacencies.lock().expect("failed to lock adjacencies").iter().find(|adjacency| adjacency.neighbour == entry.next_hop) {
+                return (entry.subnetwork.unwrap_or(adjacency.subnetwork), adjacency.snpa);
+            }
+            // NOTE: the NSAPs of this implementation contain the SNPA address, so the next hop is reachable without adjacency, too
+            //TODO fix once NSAPs are implemented fully
+            if let Some(next_hop) = Nsap::from_u8(&entry.next_hop) {
+                return (entry.subnetwork.unwrap_or_else(|| self.subnetwork_of(&next_hop.local_address)), next_hop.local_address);
+            }
+            info!("no adjacency to next hop {:02x?} of route to {}, sending directly", entry.next_hop, destination.to_string());
+        }
+        return (self.subnetwork_of(&destination.local_address), destination.local_address);
+    }
+
+    /// subnetwork on which the given SNPA address was configured resp. last heard on
+    fn subnetwork_of(&self, snpa: &MacAddr6) -> usize {
+        if let Some(subnetwork) = self.snpa_subnetworks.lock().expect("failed to lock snpa_subnetworks").get(snpa) {
+            return *subnetwork;
+        }
+        //TODO ES-IS query configuration function - for now, try the first subnetwork
+        debug!("subnetwork of SNPA {} not known, using subnetwork 0", snpa);
+        return 0;
+    }
+
+    /// nothing can be sent on a subnetwork while it is down - better to tell the NS user right away
+    //TODO route around it, once there are alternative routes
+    fn check_subnetwork_up(&self, subnetwork: usize) -> Result<(), Error> {
+        if !self.subnetwork_up.lock().expect("failed to lock subnetwork_up")[subnetwork] {
+            return Err(Error::new(std::io::ErrorKind::NotConnected, "subnetwork is down"));
+        }
+        return Ok(());
+    }
+
+    /// own NSAP to use as source address on the given subnetwork
+    /// NOTE: osistack::new_with_sockets() adds one serviced NSAP per subnetwork, in the same order
+    fn serviced_nsap_on(&self, subnetwork: usize) -> Option<&Nsap> {
+        return self.serviced_nsaps.get(subnetwork).or(self.serviced_nsaps.first());
+    }
+}
+
+/// N-UNITDATA indication towards the first NS user that wants the NSDU, if there is one - false if discarded because of congestion
+fn hand_up_to_ns_user(ns_users: &Mutex<Vec<NsUser>>, ns_source_address: Nsap, ns_destination_address: Nsap, ns_quality_of_service: Qos, ns_userdata: &[u8]) -> bool {
+    let mut ns_users_locked = ns_users.lock().expect("failed to lock ns_users");
+    if let Some(ns_user_inner) = ns_users_locked.iter_mut().find(|ns_user| (ns_user.accepts)(ns_userdata)) {
+        if let Err(_) = ns_user_inner.ns_user_to.push(NSUnitDataIndication {
+            ns_source_address: ns_source_address,
+            ns_destination_address: ns_destination_address,
+            ns_quality_of_service: ns_quality_of_service,
+            ns_userdata: ns_userdata.to_vec(),  //TODO optimize - hand up the pooled buffer
+        }) {
+            // NOTE: the NS users retransmit themselves
+            info!("queue towards NS user full, discarding NSDU");
+            return false;
+        }
+        if let Some(wakeup) = ns_user_inner.ns_user_to_wakeup.lock().expect("failed to lock ns_user_to_wakeup").as_ref() {
+            wakeup.thread().unpark();
+        }
+    } else {
+        debug!("no NS user registered for this NSDU, discarding");
+    }
+    return true;
+}
+
+/// SN reported that a subnetwork went down or came back up
+fn subnetwork_event(
+    subnetwork: usize,
+    event: SubnetworkEvent,
+    subnetwork_up: &Mutex<Vec<bool>>,
+    adjacencies: &Mutex<Vec<Adjacency>>,
+    subnetwork_listeners: &Mutex<Vec<SubnetworkListener>>
+) {
+    info!("subnetwork {} is {:?}", subnetwork, event);
+    subnetwork_up.lock().expect("failed to lock subnetwork_up")[subnetwork] = event == SubnetworkEvent::Up;
+    if event == SubnetworkEvent::Down {
+        // neighbours on it are unreachable now - ES-IS resp. IS-IS learn them again after it is back up
+        adjacencies.lock().expect("failed to lock adjacencies").retain(|adjacency| adjacency.subnetwork != subnetwork);
+    }
+    for listener in subnetwork_listeners.lock().expect("failed to lock subnetwork_listeners").iter_mut() {
+        listener(subnetwork, event);
+    }
+}
+
+/// push into the queue towards the SN and wake up the SN, applying the X.233 6.18 Congestion notification function:
+/// above the threshold, the congestion experienced flag is set in PDUs carrying the QoS maintenance parameter
+/// NOTE: never waits - if the queue is full, the request is given back
+fn try_push_sn_unitdata_request(
+    sn_service_to: &mut [rtrb::Producer<SNUnitDataRequest>],
+    band: usize,
+    sn_service_to_wakeup: &JoinHandle<Thread>,
+    congestion_notification: &CongestionNotification,
+    mut sn_unitdata_request: SNUnitDataRequest
+) -> Result<(), SNUnitDataRequest> {
+    let sn_service_to = &mut sn_service_to[band];
+    let queued = sn_service_to.buffer().capacity() - sn_service_to.slots();
+    if queued >= congestion_notification.threshold {
+        if Pdu::set_congestion_experienced(&mut sn_unitdata_request.sn_userdata) {
+            debug!("congestion towards SN: {} requests queued, set congestion experienced flag", queued);
+        }
+    }
+    let result = sn_service_to.push(sn_unitdata_request).map_err(|rtrb::PushError::Full(returned)| returned);
+    // also if full - make sure SN is draining the queue
+    sn_service_to_wakeup.thread().unpark();
+    return result;
+}
+
+/// X.233 6.10 Error reporting function - if the ER flag of the discarded PDU is set, an ER PDU goes back to its source
+/// NOTE: it goes to the SNPA the discarded PDU came from, and it is discarded itself if the queue is full, too
+fn report_error(context: &mut ReceiveContext, ns_source_address: MacAddr6, ns_destination_address: MacAddr6, discarded: &[u8], reason: u8) {
+    if let Some(er_pdu) = Pdu::compose_error_report(discarded, reason) {
+        if try_push_sn_unitdata_request(
+            context.sn_service_to,
+            crate::dl::BAND_CONTROL,
+            context.sn_service_to_wakeup,
+            &context.congestion_notification,
+            SNUnitDataRequest {
+                sn_source_address: ns_destination_address,
+                sn_destination_address: ns_source_address,
+                sn_quality_of_service: crate::dl::Qos::default(),
+                sn_userdata: er_pdu,
+            }
+        ).is_err() {
+            info!("error report not sent: discarded because of congestion towards SN");
         }
     }
 }
 
 //TODO
-fn can_use_inactive_subset(ns_source_address: &Nsap, ns_destination_address: &Nsap) -> bool {
+fn can_use_inactive_subset(ns_source_address: &Nsap, ns_destination_address: &Nsap, ns_userdata: &[u8], maximum_sdu_size: usize) -> bool {
     // TODO check if on same subnetwork (AKA in same Ethernet segment)
-    true
+    // the inactive subset carries no length, so the receiver could not tell padding from data -
+    // convention: short NSDUs go into a non-segmenting DT PDU, whose segment length allows removing the padding
+    //TODO optimize - on LLC encapsulation, the 802.3 length field would already suffice
+    // and it cannot be segmented, so it has to fit into the SNSDU as a whole
+    return 1 + ns_userdata.len() >= crate::dl::MINIMUM_PAYLOAD_LENGTH && 1 + ns_userdata.len() <= maximum_sdu_size;
 }
 
 enum HeaderFormatAnalysisResult {
@@ -1077,4 +1708,279 @@ fn header_format_analysis(packet: &[u8]) -> HeaderFormatAnalysisResult {
         //address serviced by this Network entity, or that an error has occurred.
         _ => HeaderFormatAnalysisResult::UnknownProtocol
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::n::{NetworkService, ForwardingEntry, NsapPrefix, RouteSource, NeighbourType};
+
+    const OWN: [MacAddr6; 2] = [MacAddr6::new([0x02, 0, 0, 0, 0, 0x01]), MacAddr6::new([0x02, 0, 0, 0, 1, 0x01])];
+
+    /// NS attached to two subnetworks, not running - returns the normal band of the queues towards each SN
+    fn service() -> (Service<'static>, Vec<rtrb::Consumer<SNUnitDataRequest>>) {
+        let mut subnetworks = vec![];
+        let mut sn_service_to = vec![];
+        let mut sn_service_to_wakeup = vec![];
+        let mut sn_service_to_waiting = vec![];
+        let mut sn_service_from = vec![];
+        let mut sn_status_from = vec![];
+        let mut normal_bands = vec![];
+        for snpa in OWN {
+            subnetworks.push(SubnetworkAttachment { snpa: snpa, maximum_sdu_size: 1497, encapsulation: crate::dl::Encapsulation::EthernetII, up: true });
+            let mut bands = vec![];
+            for band in 0..crate::dl::PRIORITY_BANDS {
+                let (producer, consumer) = rtrb::RingBuffer::new(8);
+                bands.push(producer);
+                if band == crate::dl::BAND_NORMAL {
+                    normal_bands.push(consumer);
+                }
+            }
+            sn_service_to.push(bands);
+            sn_service_to_wakeup.push(Arc::new(Mutex::new(Some(thread::spawn(thread::current)))));
+            sn_service_to_waiting.push(Arc::new(Mutex::new(vec![])));
+            sn_service_from.push(rtrb::RingBuffer::new(8).1);
+            sn_status_from.push(rtrb::RingBuffer::new(8).1);
+        }
+        let mut ns = Service::new("own", subnetworks, sn_service_to, sn_service_to_wakeup, sn_service_to_waiting, sn_service_from, sn_status_from);
+        for snpa in OWN {
+            ns.add_serviced_subnet_nsap(1, 1, snpa);
+        }
+        return (ns, normal_bands);
+    }
+
+    fn nsap(snpa: [u8; 6]) -> Nsap {
+        return Nsap { authority: 49, area: 1, sub_area: 1, local_address: MacAddr6::new(snpa) };
+    }
+
+    /// Echo Request PDU from the given source to us, with the ER flag set
+    fn echo_request_pdu(ns: &Service, source: &Nsap) -> Vec<u8> {
+        let mut buffer_scratch = [0u8; 64];
+        let mut erq_pdu = Pdu::new_echo_request(false, source, &ns.serviced_nsaps[0], &None, &[1, 2], &mut buffer_scratch);
+        let mut erq = vec![0u8; 1500];
+        let bytes = erq_pdu.into_buf(true, &mut erq);
+        erq.truncate(bytes);
+        erq[4] |= 0b0010_0000;
+        let header_length = erq[1] as usize;
+        Pdu::compute_checksum(&mut erq[0..header_length]);
+        return erq;
+    }
+
+    /// hand the given PDU to the receive path, with the queue towards the NS user resp. each queue towards the SN full or not -
+    /// returns what reached the NS user and what was sent back on the control band
+    fn receive_with(ns: &Service, pdu: &[u8], ns_user_full: bool, sn_full: bool) -> (Option<NSUnitDataIndication>, Option<SNUnitDataRequest>) {
+        let (mut ns_user_to, mut ns_user_from) = rtrb::RingBuffer::new(1);
+        if ns_user_full {
+            ns_user_to.push(NSUnitDataIndication { ns_source_address: nsap([0; 6]), ns_destination_address: nsap([0; 6]), ns_quality_of_service: Qos::default(), ns_userdata: vec![] }).unwrap();
+        }
+        let ns_users = Mutex::new(vec![NsUser { accepts: |_| true, ns_user_to: ns_user_to, ns_user_to_wakeup: Arc::new(Mutex::new(None)) }]);
+        let (mut sn_service_to, mut sn_service_from): (Vec<_>, Vec<_>) = (0..crate::dl::PRIORITY_BANDS).map(|_| rtrb::RingBuffer::new(1)).unzip();
+        if sn_full {
+            for band in sn_service_to.iter_mut() {
+                band.push(SNUnitDataRequest { sn_source_address: OWN[0], sn_destination_address: OWN[0], sn_quality_of_service: crate::dl::Qos::default(), sn_userdata: vec![] }).unwrap();
+            }
+        }
+        let mut context = ReceiveContext {
+            sn_service_to: &mut sn_service_to,
+            sn_service_to_wakeup: &thread::spawn(thread::current),
+            // would wait for long, if the receive path waited at all
+            congestion_notification: CongestionNotification { threshold: 1, policy: CongestionPolicy::Backpressure(Duration::from_secs(10)) },
+            ns_users: &ns_users,
+            echo_request_correlation_table: &ns.echo_request_correlation_table,
+            arrival_subnetwork: 0,
+        };
+        Service::n_unitdata_indication(&mut context, MacAddr6::new([0x02, 0, 0, 0, 0, 0x02]), OWN[0], &Qos::default(), pdu);
+        if ns_user_full {
+            ns_user_from.pop().unwrap();
+        }
+        let control = &mut sn_service_from[crate::dl::BAND_CONTROL];
+        if sn_full {
+            control.pop().unwrap();
+        }
+        return (ns_user_from.pop().ok(), control.pop().ok());
+    }
+
+    #[test]
+    fn congestion_notification_reaches_running_threads() {
+        let (mut ns, _sn) = service();
+        // what run() hands to the receive thread
+        let congestion_notification_arc = ns.congestion_notification.clone();
+        ns.set_congestion_notification(CongestionNotification { threshold: 1, policy: CongestionPolicy::Discard });
+        let congestion_notification = *congestion_notification_arc.lock().unwrap();
+        assert_eq!(congestion_notification.threshold, 1);
+        assert!(matches!(congestion_notification.policy, CongestionPolicy::Discard));
+
+        // and applies to sending, too - the queue holds 8, discarded right away once full
+        let destination = nsap([0x02, 0, 0, 0, 0, 0x02]);
+        for _ in 0..8 {
+            ns.n_unitdata_request_to_nsap(&destination, &Qos::default(), &[0u8; 64]).unwrap();
+        }
+        assert_eq!(ns.n_unitdata_request_to_nsap(&destination, &Qos::default(), &[0u8; 64]).unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+    }
+
+    /// hand the given PDU to the receive path and return what reached the NS user
+    fn receive(ns: &Service, pdu: &[u8]) -> Option<NSUnitDataIndication> {
+        return receive_with(ns, pdu, false, false).0;
+    }
+
+    #[test]
+    fn malformed_options_discarded() {
+        let (mut ns, mut sn) = service();
+        let destination = nsap([0x02, 0, 0, 0, 0, 0x02]);
+        let quality_of_service = Qos { cost: 2, ..Default::default() };
+        ns.n_unitdata_request_to_nsap(&destination, &quality_of_service, b"hello").unwrap();
+        let pdu = sn[0].pop().unwrap().sn_userdata;
+        // fixed part, address part of two 12 octet NSAPs, then the QoS maintenance option
+        let options_start = 9 + 1 + 12 + 1 + 12;
+        assert_eq!(pdu[options_start], PARAMETER_CODE_QOS_MAINTENANCE);
+        assert_eq!(receive(&ns, &pdu).unwrap().ns_userdata, b"hello");
+
+        // option value running past the options part
+        let mut truncated = pdu.clone();
+        truncated[options_start + 1] = 2;
+        assert!(Pdu::from_buf(&truncated).is_err());
+        assert!(receive(&ns, &truncated).is_none());
+
+        // same option twice
+        let mut repeated = pdu.clone();
+        repeated.splice(options_start + 3..options_start + 3, pdu[options_start..options_start + 3].to_vec());
+        repeated[1] += 3;
+        let segment_length = u16::from_be_bytes([repeated[5], repeated[6]]) + 3;
+        repeated[5..7].copy_from_slice(&segment_length.to_be_bytes());
+        assert_eq!(Pdu::from_buf(&repeated).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert!(receive(&ns, &repeated).is_none());
+
+        // header length indicator beyond the PDU
+        let mut short = pdu.clone();
+        short.truncate(options_start + 1);
+        assert!(receive(&ns, &short).is_none());
+    }
+
+    #[test]
+    fn error_report_on_congestion() {
+        let (ns, _sn) = service();
+        // a DT PDU towards us
+        let mut pdu = echo_request_pdu(&ns, &nsap([0x02, 0, 0, 0, 0, 0x02]));
+        let header_length = pdu[1] as usize;
+        pdu[4] = TYPE_DT_PDU;
+        Pdu::compute_checksum(&mut pdu[0..header_length]);
+
+        // without the ER flag, discarded silently
+        let (delivered, report) = receive_with(&ns, &pdu, true, false);
+        assert!(delivered.is_none());
+        assert!(report.is_none());
+
+        // with it, an ER PDU goes back to the source
+        pdu[4] |= 0b0010_0000;
+        Pdu::compute_checksum(&mut pdu[0..header_length]);
+        let (_, report) = receive_with(&ns, &pdu, true, false);
+        let report = report.unwrap();
+        assert_eq!(report.sn_destination_address, MacAddr6::new([0x02, 0, 0, 0, 0, 0x02]));
+        let er = report.sn_userdata;
+        assert_eq!(er[4], TYPE_ER_PDU);
+        assert_eq!(u16::from_be_bytes([er[5], er[6]]) as usize, er.len());
+        // addresses swapped
+        assert_eq!(&er[9..9+13], &pdu[9+13..9+26]);
+        assert_eq!(&er[9+13..9+26], &pdu[9..9+13]);
+        let er_header_length = er[1] as usize;
+        assert_eq!(er_header_length, 9 + 26 + 4);
+        assert_eq!(&er[9+26..er_header_length], &[PARAMETER_CODE_REASON_FOR_DISCARD, 2, REASON_CONGESTION, 0]);
+        // carrying the header of the discarded PDU
+        assert_eq!(&er[er_header_length..], &pdu[0..header_length]);
+
+        // never about an ER PDU
+        assert!(Pdu::compose_error_report(&er, REASON_CONGESTION).is_none());
+    }
+
+    #[test]
+    fn receive_path_never_waits() {
+        let (ns, _sn) = service();
+        let erq = echo_request_pdu(&ns, &nsap([0x02, 0, 0, 0, 0, 0x02]));
+        let start = Instant::now();
+        let (_, sent) = receive_with(&ns, &erq, false, true);
+        // neither the Echo Response nor the Error Report fit, and both are given up right away
+        assert!(sent.is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // and with room, the Echo Response goes out
+        let (_, sent) = receive_with(&ns, &erq, false, false);
+        assert_eq!(sent.unwrap().sn_destination_address, MacAddr6::new([0x02, 0, 0, 0, 0, 0x02]));
+    }
+
+    #[test]
+    fn backpressure_woken_up_by_sn() {
+        let (mut ns, mut sn) = service();
+        ns.set_congestion_notification(CongestionNotification { threshold: 1, policy: CongestionPolicy::Backpressure(Duration::from_secs(10)) });
+        let destination = nsap([0x02, 0, 0, 0, 0, 0x02]);
+        for _ in 0..8 {
+            ns.n_unitdata_request_to_nsap(&destination, &Qos::default(), &[0u8; 64]).unwrap();
+        }
+        // what the SN does once it has taken a request out of the queue
+        let waiting = ns.sn_service_to_waiting[0].clone();
+        let mut normal_band = sn.remove(0);
+        let sn_thread = thread::spawn(move || {
+            while waiting.lock().unwrap().is_empty() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            normal_band.pop().unwrap();
+            for waiting_thread in waiting.lock().unwrap().drain(..) {
+                waiting_thread.unpark();
+            }
+        });
+        let start = Instant::now();
+        ns.n_unitdata_request_to_nsap(&destination, &Qos::default(), &[0u8; 64]).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        sn_thread.join().unwrap();
+        assert!(ns.sn_service_to_waiting[0].lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn known_host_on_its_subnetwork() {
+        let (mut ns, mut sn) = service();
+        ns.add_known_host("far".to_owned(), "02:00:00:00:01:02", 1);
+        let destination = ns.resolve_nsap("far").unwrap().clone();
+        ns.n_unitdata_request_to_nsap(&destination, &Qos::default(), &[0u8; 64]).unwrap();
+        assert!(sn[0].pop().is_err());
+        let request = sn[1].pop().unwrap();
+        assert_eq!(request.sn_source_address, OWN[1]);
+        assert_eq!(request.sn_destination_address, destination.local_address);
+        assert_eq!(ns.management_information().snpa_cache[0].subnetwork, Some(1));
+    }
+
+    #[test]
+    fn route_via_intermediate_system() {
+        let (mut ns, mut sn) = service();
+        let destination = nsap([0x02, 0, 0, 0, 9, 9]);
+        let router = nsap([0x02, 0, 0, 0, 1, 0xfe]);
+        // the intermediate system is adjacent on subnetwork 1
+        ns.adjacencies().lock().unwrap().push(Adjacency {
+            source: RouteSource::EsIs,
+            neighbour_type: NeighbourType::IntermediateSystem,
+            neighbour: router.to_u8(),
+            snpa: router.local_address,
+            subnetwork: 1,
+            expires: Utc::now() + chrono::Duration::seconds(30),
+        });
+        ns.forwarding_table().lock().unwrap().insert(ForwardingEntry {
+            destination: NsapPrefix { length_bits: 6 * 8, prefix: destination.to_u8()[0..6].to_vec() },
+            next_hop: router.to_u8(),
+            metric: 1,
+            source: RouteSource::Static,
+            subnetwork: None,
+        });
+        ns.n_unitdata_request_to_nsap(&destination, &Qos::default(), &[0u8; 64]).unwrap();
+        let request = sn[1].pop().unwrap();
+        // to the SNPA of the next hop, but still addressed to the destination NSAP
+        assert_eq!(request.sn_destination_address, router.local_address);
+        match Pdu::from_buf(&request.sn_userdata).unwrap() {
+            Pdu::DataPDU { addr, .. } => { assert_eq!(addr.destination_address, destination.to_u8()); },
+            other => panic!("{:?}", other)
+        }
+        assert!(sn[0].pop().is_err());
+
+        // without adjacency, the next hop NSAP tells the SNPA
+        ns.adjacencies().lock().unwrap().clear();
+        ns.add_known_host("router".to_owned(), "02:00:00:00:01:fe", 1);
+        ns.n_unitdata_request_to_nsap(&destination, &Qos::default(), &[0u8; 64]).unwrap();
+        assert_eq!(sn[1].pop().unwrap().sn_destination_address, router.local_address);
+    }
+}
@@ -0,0 +1,1490 @@
+extern crate simplelog; //TODO check the paris feature flag for tags, useful?
+
+use std::{collections::{HashMap, VecDeque}, io::Error, sync::{Arc, Mutex}, thread::{self, Thread, JoinHandle}, time::Duration};
+
+use chrono::prelude::*;
+
+use super::{NetworkService, Nsap, NSUnitDataIndication, NsUser, Qos, NsapPrefix, ForwardingTable, ForwardingEntry, RouteSource};
+
+// NOTE: IDRP as per ISO/IEC 10747 - inter-domain routing between Boundary Intermediate Systems (BIS).
+// BISPDUs are carried in the data part of CLNP Data PDUs. The Speaker below does not own any threads or sockets,
+// it takes received BISPDUs and returns the BISPDUs to be sent - the Service at the end runs it as an NS user of CLNP.
+//TODO authentication code 1 and 2 validation pattern (MD5) - currently always sends and accepts authentication code 1 with an all-zero validation pattern
+
+pub const NETWORK_LAYER_PROTOCOL_IDENTIFIER_IDRP: u8 = 0x85;
+
+/// ISO 10747 7.1 fixed header: protocol identifier, length, type, sequence, acknowledgement, credit offered, credit available, validation pattern
+const HEADER_LENGTH: usize = 1+2+1+4+4+1+1+16;
+
+/// ISO 10747 7.2 version of the protocol carried in OPEN
+const VERSION: u8 = 1;
+
+const TYPE_OPEN: u8 = 1;
+const TYPE_UPDATE: u8 = 2;
+const TYPE_ERROR: u8 = 3;
+const TYPE_KEEPALIVE: u8 = 4;
+const TYPE_CEASE: u8 = 5;
+const TYPE_RIB_REFRESH: u8 = 6;
+
+// ISO 10747 7.12 path attribute type codes
+pub const ATTRIBUTE_ROUTE_SEPARATOR: u8 = 1;
+pub const ATTRIBUTE_EXT_INFO: u8 = 2;
+pub const ATTRIBUTE_RD_PATH: u8 = 3;
+pub const ATTRIBUTE_NEXT_HOP: u8 = 4;
+pub const ATTRIBUTE_DIST_LIST_INCL: u8 = 5;
+pub const ATTRIBUTE_DIST_LIST_EXCL: u8 = 6;
+pub const ATTRIBUTE_MULTI_EXIT_DISC: u8 = 7;
+pub const ATTRIBUTE_TRANSIT_DELAY: u8 = 8;
+pub const ATTRIBUTE_RESIDUAL_ERROR: u8 = 9;
+pub const ATTRIBUTE_EXPENSE: u8 = 10;
+pub const ATTRIBUTE_LOCALLY_DEFINED_QOS: u8 = 11;
+pub const ATTRIBUTE_HIERARCHICAL_RECORDING: u8 = 12;
+pub const ATTRIBUTE_RD_HOP_COUNT: u8 = 13;
+pub const ATTRIBUTE_SECURITY: u8 = 14;
+pub const ATTRIBUTE_CAPACITY: u8 = 15;
+pub const ATTRIBUTE_PRIORITY: u8 = 16;
+
+/// path attribute flags octet
+const ATTRIBUTE_FLAG_OPTIONAL: u8 = 0b1000_0000;
+const ATTRIBUTE_FLAG_TRANSITIVE: u8 = 0b0100_0000;
+const ATTRIBUTE_FLAG_PARTIAL: u8 = 0b0010_0000;
+
+/// RD_PATH path segment types
+const RD_PATH_SEGMENT_RD_SET: u8 = 1;
+const RD_PATH_SEGMENT_RD_SEQ: u8 = 2;
+
+/// NLRI protocol type ISO/TR 9577 and protocol CLNP
+const NLRI_PROTO_TYPE_ISO_9577: u8 = 1;
+const NLRI_PROTOCOL_CLNP: u8 = 0x81;
+
+// ISO 10747 7.10 error codes
+pub const ERROR_OPEN_PDU: u8 = 1;
+pub const ERROR_UPDATE_PDU: u8 = 2;
+pub const ERROR_HOLD_TIMER_EXPIRED: u8 = 3;
+pub const ERROR_FSM: u8 = 4;
+pub const ERROR_RIB_REFRESH_PDU: u8 = 5;
+
+/// OPEN PDU error subcodes
+const ERROR_OPEN_PDU_UNSUPPORTED_VERSION: u8 = 1;
+
+const AUTHENTICATION_CODE_INTEGRITY: u8 = 1;
+
+// RIB REFRESH opcodes
+pub const RIB_REFRESH_REQUEST: u8 = 1;
+pub const RIB_REFRESH_START: u8 = 2;
+pub const RIB_REFRESH_END: u8 = 3;
+
+/// fixed header of every BISPDU
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BispduHeader {
+    pub length: u16,
+    pub type_: u8,
+    pub sequence: u32,
+    pub acknowledgement: u32,
+    pub credit_offered: u8,
+    pub credit_available: u8,
+    pub validation_pattern: [u8; 16],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathAttribute {
+    pub flags: u8,
+    pub type_: u8,
+    pub value: Vec<u8>,
+}
+
+/// Network Layer Reachability Information - only CLNP NLRI is supported
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nlri {
+    pub prefixes: Vec<NsapPrefix>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Bispdu {
+    Open {
+        version: u8,
+        hold_time: u16,
+        max_pdu_size: u16,
+        source_rdi: Vec<u8>,
+        /// each RIB-Att is the list of distinguishing attribute types, the empty RIB-Att is the default RIB
+        rib_atts: Vec<Vec<u8>>,
+        confederations: Vec<Vec<u8>>,
+        authentication_code: u8,
+        authentication_data: Vec<u8>,
+    },
+    Update {
+        withdrawn_routes: Vec<u32>,
+        path_attributes: Vec<PathAttribute>,
+        nlri: Vec<Nlri>,
+    },
+    Error {
+        error_code: u8,
+        error_subcode: u8,
+        data: Vec<u8>,
+    },
+    Keepalive,
+    Cease,
+    RibRefresh {
+        opcode: u8,
+        rib_atts: Vec<Vec<u8>>,
+    },
+}
+
+impl Bispdu {
+    fn type_(&self) -> u8 {
+        match self {
+            Bispdu::Open { .. } => TYPE_OPEN,
+            Bispdu::Update { .. } => TYPE_UPDATE,
+            Bispdu::Error { .. } => TYPE_ERROR,
+            Bispdu::Keepalive => TYPE_KEEPALIVE,
+            Bispdu::Cease => TYPE_CEASE,
+            Bispdu::RibRefresh { .. } => TYPE_RIB_REFRESH,
+        }
+    }
+
+    /// serialize into a new buffer, header fields for reliable delivery are given by the caller
+    pub fn to_vec(&self, sequence: u32, acknowledgement: u32, credit_offered: u8, credit_available: u8) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(HEADER_LENGTH);
+        buffer.push(NETWORK_LAYER_PROTOCOL_IDENTIFIER_IDRP);
+        buffer.extend_from_slice(&[0, 0]);  // length, will be filled
+        buffer.push(self.type_());
+        buffer.extend_from_slice(&sequence.to_be_bytes());
+        buffer.extend_from_slice(&acknowledgement.to_be_bytes());
+        buffer.push(credit_offered);
+        buffer.push(credit_available);
+        buffer.extend_from_slice(&[0u8; 16]);   // validation pattern TODO MD5
+
+        match self {
+            Bispdu::Open { version, hold_time, max_pdu_size, source_rdi, rib_atts, confederations, authentication_code, authentication_data } => {
+                buffer.push(*version);
+                buffer.extend_from_slice(&hold_time.to_be_bytes());
+                buffer.extend_from_slice(&max_pdu_size.to_be_bytes());
+                buffer.push(source_rdi.len() as u8);
+                buffer.extend_from_slice(source_rdi);
+                compose_rib_atts(rib_atts, &mut buffer);
+                buffer.push(confederations.len() as u8);
+                for confederation in confederations {
+                    buffer.push(confederation.len() as u8);
+                    buffer.extend_from_slice(confederation);
+                }
+                buffer.push(*authentication_code);
+                buffer.extend_from_slice(authentication_data);
+            },
+            Bispdu::Update { withdrawn_routes, path_attributes, nlri } => {
+                buffer.extend_from_slice(&(withdrawn_routes.len() as u16).to_be_bytes());
+                for route_id in withdrawn_routes {
+                    buffer.extend_from_slice(&route_id.to_be_bytes());
+                }
+                let attributes_start = buffer.len();
+                buffer.extend_from_slice(&[0, 0]);  // total path attributes length, will be filled
+                for attribute in path_attributes {
+                    buffer.push(attribute.flags);
+                    buffer.push(attribute.type_);
+                    buffer.extend_from_slice(&(attribute.value.len() as u16).to_be_bytes());
+                    buffer.extend_from_slice(&attribute.value);
+                }
+                let attributes_length = (buffer.len() - attributes_start - 2) as u16;
+                buffer[attributes_start..attributes_start+2].copy_from_slice(&attributes_length.to_be_bytes());
+                for nlri_inner in nlri {
+                    buffer.push(NLRI_PROTO_TYPE_ISO_9577);
+                    buffer.push(1); // protocol length
+                    buffer.push(NLRI_PROTOCOL_CLNP);
+                    let address_info_start = buffer.len();
+                    buffer.extend_from_slice(&[0, 0]);  // address length, will be filled
+                    for prefix in &nlri_inner.prefixes {
+                        buffer.push(prefix.length_bits);
+                        // NOTE: padded if the prefix is short of octets, see NsapPrefix::is_valid()
+                        buffer.extend(prefix.prefix.iter().copied().chain(std::iter::repeat(0)).take(prefix.octets()));
+                    }
+                    let address_length = (buffer.len() - address_info_start - 2) as u16;
+                    buffer[address_info_start..address_info_start+2].copy_from_slice(&address_length.to_be_bytes());
+                }
+            },
+            Bispdu::Error { error_code, error_subcode, data } => {
+                buffer.push(*error_code);
+                buffer.push(*error_subcode);
+                buffer.extend_from_slice(data);
+            },
+            Bispdu::Keepalive | Bispdu::Cease => {},
+            Bispdu::RibRefresh { opcode, rib_atts } => {
+                buffer.push(*opcode);
+                compose_rib_atts(rib_atts, &mut buffer);
+            },
+        }
+
+        let length = buffer.len() as u16;
+        buffer[1..3].copy_from_slice(&length.to_be_bytes());
+        return buffer;
+    }
+
+    pub fn from_buf(buffer: &[u8]) -> Result<(BispduHeader, Bispdu), Error> {
+        if buffer.len() < HEADER_LENGTH {
+            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "given BISPDU buffer shorter than fixed header"));
+        }
+        if buffer[0] != NETWORK_LAYER_PROTOCOL_IDENTIFIER_IDRP {
+            return Err(Error::new(std::io::ErrorKind::InvalidData, "not an IDRP BISPDU"));
+        }
+        let header = BispduHeader {
+            length: u16::from_be_bytes([buffer[1], buffer[2]]),
+            type_: buffer[3],
+            sequence: u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
+            acknowledgement: u32::from_be_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]),
+            credit_offered: buffer[12],
+            credit_available: buffer[13],
+            validation_pattern: buffer[14..30].try_into().expect("failed to convert validation pattern"),
+        };
+        if (header.length as usize) < HEADER_LENGTH || buffer.len() < header.length as usize {
+            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "BISPDU length field does not match given buffer"));
+        }
+        //NOTE: anything after length is padding by the underlying service
+        let mut reader = Reader { buffer: &buffer[HEADER_LENGTH..header.length as usize], offset: 0 };
+
+        let bispdu = match header.type_ {
+            TYPE_OPEN => {
+                let version = reader.u8()?;
+                let hold_time = reader.u16()?;
+                let max_pdu_size = reader.u16()?;
+                let source_rdi_length = reader.u8()? as usize;
+                let source_rdi = reader.bytes(source_rdi_length)?.to_vec();
+                let rib_atts = decompose_rib_atts(&mut reader)?;
+                let mut confederations = vec![];
+                for _ in 0..reader.u8()? {
+                    let confederation_length = reader.u8()? as usize;
+                    confederations.push(reader.bytes(confederation_length)?.to_vec());
+                }
+                let authentication_code = reader.u8()?;
+                let authentication_data = reader.rest().to_vec();
+                Bispdu::Open { version, hold_time, max_pdu_size, source_rdi, rib_atts, confederations, authentication_code, authentication_data }
+            },
+            TYPE_UPDATE => {
+                let mut withdrawn_routes = vec![];
+                for _ in 0..reader.u16()? {
+                    withdrawn_routes.push(reader.u32()?);
+                }
+                let attributes_length = reader.u16()? as usize;
+                let mut attributes_reader = Reader { buffer: reader.bytes(attributes_length)?, offset: 0 };
+                let mut path_attributes = vec![];
+                while !attributes_reader.is_empty() {
+                    let flags = attributes_reader.u8()?;
+                    let type_ = attributes_reader.u8()?;
+                    let value_length = attributes_reader.u16()? as usize;
+                    path_attributes.push(PathAttribute { flags: flags, type_: type_, value: attributes_reader.bytes(value_length)?.to_vec() });
+                }
+                let mut nlri = vec![];
+                while !reader.is_empty() {
+                    let proto_type = reader.u8()?;
+                    let proto_length = reader.u8()? as usize;
+                    let protocol = reader.bytes(proto_length)?;
+                    let address_length = reader.u16()? as usize;
+                    let mut address_reader = Reader { buffer: reader.bytes(address_length)?, offset: 0 };
+                    if proto_type != NLRI_PROTO_TYPE_ISO_9577 || protocol != [NLRI_PROTOCOL_CLNP] {
+                        debug!("skipping NLRI for unsupported protocol {:?}", protocol);
+                        continue;
+                    }
+                    let mut prefixes = vec![];
+                    while !address_reader.is_empty() {
+                        let length_bits = address_reader.u8()?;
+                        if length_bits as usize > super::NSAP_MAXIMUM_LENGTH * 8 {
+                            return Err(Error::new(std::io::ErrorKind::InvalidData, "NLRI prefix longer than an NSAP address"));
+                        }
+                        let prefix = address_reader.bytes((length_bits as usize + 7) / 8)?.to_vec();
+                        prefixes.push(NsapPrefix::new(length_bits, prefix)?);
+                    }
+                    nlri.push(Nlri { prefixes: prefixes });
+                }
+                Bispdu::Update { withdrawn_routes, path_attributes, nlri }
+            },
+            TYPE_ERROR => {
+                let error_code = reader.u8()?;
+                let error_subcode = reader.u8()?;
+                Bispdu::Error { error_code, error_subcode, data: reader.rest().to_vec() }
+            },
+            TYPE_KEEPALIVE => Bispdu::Keepalive,
+            TYPE_CEASE => Bispdu::Cease,
+            TYPE_RIB_REFRESH => {
+                let opcode = reader.u8()?;
+                let rib_atts = decompose_rib_atts(&mut reader)?;
+                Bispdu::RibRefresh { opcode, rib_atts }
+            },
+            _ => {
+                return Err(Error::new(std::io::ErrorKind::InvalidData, "unknown BISPDU type"));
+            }
+        };
+        return Ok((header, bispdu));
+    }
+}
+
+fn compose_rib_atts(rib_atts: &[Vec<u8>], buffer: &mut Vec<u8>) {
+    buffer.push(rib_atts.len() as u8);
+    for rib_att in rib_atts {
+        //TODO distinguishing attributes with values (security, locally defined QoS, priority)
+        buffer.push(rib_att.len() as u8);
+        buffer.extend_from_slice(rib_att);
+    }
+}
+
+fn decompose_rib_atts(reader: &mut Reader) -> Result<Vec<Vec<u8>>, Error> {
+    let mut rib_atts = vec![];
+    for _ in 0..reader.u8()? {
+        let count = reader.u8()? as usize;
+        rib_atts.push(reader.bytes(count)?.to_vec());
+    }
+    return Ok(rib_atts);
+}
+
+/// bounds-checked big endian reading from a buffer
+struct Reader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.buffer.len() < self.offset + length {
+            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "BISPDU too short"));
+        }
+        let bytes = &self.buffer[self.offset..self.offset+length];
+        self.offset += length;
+        return Ok(bytes);
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        return Ok(self.bytes(1)?[0]);
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.bytes(2)?;
+        return Ok(u16::from_be_bytes([bytes[0], bytes[1]]));
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        return Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.buffer[self.offset..];
+        self.offset = self.buffer.len();
+        return rest;
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.offset >= self.buffer.len();
+    }
+}
+
+/// does the RD_PATH attribute value contain the given RDI - used for loop detection
+pub fn rd_path_contains(rd_path: &[u8], rdi: &[u8]) -> bool {
+    let mut segments = Reader { buffer: rd_path, offset: 0 };
+    while !segments.is_empty() {
+        let Ok(_segment_type) = segments.u8() else { return false; };
+        let Ok(segment_length) = segments.u16() else { return false; };
+        let Ok(segment) = segments.bytes(segment_length as usize) else { return false; };
+        let mut rdis = Reader { buffer: segment, offset: 0 };
+        while !rdis.is_empty() {
+            let Ok(rdi_length) = rdis.u8() else { return false; };
+            let Ok(segment_rdi) = rdis.bytes(rdi_length as usize) else { return false; };
+            if segment_rdi == rdi {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+/// prepend own RDI to the RD_PATH attribute value as ISO 10747 7.12.3 demands when advertising to another RD
+pub fn rd_path_prepend(rd_path: &[u8], rdi: &[u8]) -> Vec<u8> {
+    let mut prepended = Vec::with_capacity(rd_path.len() + 3 + 1 + rdi.len());
+    prepended.push(RD_PATH_SEGMENT_RD_SEQ);
+    prepended.extend_from_slice(&((1 + rdi.len()) as u16).to_be_bytes());
+    prepended.push(rdi.len() as u8);
+    prepended.extend_from_slice(rdi);
+    prepended.extend_from_slice(rd_path);
+    return prepended;
+}
+
+/// number of RDs in the RD_PATH - an RD_SET counts as one as it is the result of aggregation
+pub fn rd_path_length(rd_path: &[u8]) -> usize {
+    let mut count = 0;
+    let mut segments = Reader { buffer: rd_path, offset: 0 };
+    while !segments.is_empty() {
+        let Ok(segment_type) = segments.u8() else { break; };
+        let Ok(segment_length) = segments.u16() else { break; };
+        let Ok(segment) = segments.bytes(segment_length as usize) else { break; };
+        if segment_type == RD_PATH_SEGMENT_RD_SET {
+            count += 1;
+            continue;
+        }
+        let mut rdis = Reader { buffer: segment, offset: 0 };
+        while !rdis.is_empty() {
+            let Ok(rdi_length) = rdis.u8() else { break; };
+            if rdis.bytes(rdi_length as usize).is_err() { break; }
+            count += 1;
+        }
+    }
+    return count;
+}
+
+/// ISO 10747 7.7 reliable delivery of BISPDUs: sequence numbers, acknowledgements, credits and retransmission
+#[derive(Debug)]
+pub struct ReliableDelivery {
+    next_sequence: u32,
+    /// highest sequence number acknowledged by the peer
+    acknowledged_by_peer: u32,
+    /// highest sequence number received in order from the peer, sent as acknowledgement
+    received_in_sequence: u32,
+    /// acknowledgement last sent - the peer may send up to credit_offered BISPDUs beyond it
+    acknowledgement_sent: u32,
+    /// how many BISPDUs beyond its acknowledgement the peer is willing to accept
+    credit_from_peer: u8,
+    /// how many BISPDUs we are willing to accept
+    credit_offered: u8,
+    retransmission_queue: VecDeque<(u32, Vec<u8>, DateTime<Utc>)>,
+    /// BISPDUs waiting for credit from the peer
+    pending: VecDeque<Bispdu>,
+    retransmission_timeout: chrono::Duration,
+}
+
+impl ReliableDelivery {
+    pub fn new(credit_offered: u8, retransmission_timeout: chrono::Duration) -> Self {
+        ReliableDelivery {
+            next_sequence: 1,
+            acknowledged_by_peer: 0,
+            received_in_sequence: 0,
+            acknowledgement_sent: 0,
+            credit_from_peer: 1,    // enough for the OPEN BISPDU, the peer tells us the real value in its OPEN
+            credit_offered: credit_offered,
+            retransmission_queue: VecDeque::new(),
+            pending: VecDeque::new(),
+            retransmission_timeout: retransmission_timeout,
+        }
+    }
+
+    /// can another BISPDU be sent without exceeding the credit of the peer
+    pub fn can_send(&self) -> bool {
+        return (self.retransmission_queue.len() as u32) < self.credit_from_peer as u32;
+    }
+
+    /// BISPDUs sent and not yet acknowledged by the peer
+    pub fn outstanding(&self) -> usize {
+        return self.retransmission_queue.len();
+    }
+
+    /// BISPDUs waiting for credit from the peer
+    pub fn pending(&self) -> usize {
+        return self.pending.len();
+    }
+
+    /// BISPDUs received from the peer and not yet acknowledged
+    pub fn unacknowledged(&self) -> u32 {
+        return self.received_in_sequence.wrapping_sub(self.acknowledgement_sent);
+    }
+
+    /// what remains of the credit offered, BISPDUs received since the last acknowledgement count against it
+    pub fn credit_available(&self) -> u8 {
+        return (self.credit_offered as u32).saturating_sub(self.unacknowledged()) as u8;
+    }
+
+    /// assign the next sequence number and remember the BISPDU for retransmission until acknowledged
+    /// NOTE: does not look at the credit of the peer - for OPEN, IDRP ERROR and CEASE, which cannot wait, otherwise use send()
+    /// KEEPALIVE BISPDUs take no sequence number of their own and are not retransmitted, they are sent regularly anyway
+    /// NOTE: credit available is taken before acknowledging - the peer sees how much of the credit it had used up,
+    /// the credit it may use from now on is the credit offered beyond the acknowledgement
+    pub fn prepare(&mut self, bispdu: &Bispdu, now: DateTime<Utc>) -> Vec<u8> {
+        let credit_available = self.credit_available();
+        self.acknowledgement_sent = self.received_in_sequence;
+        if matches!(bispdu, Bispdu::Keepalive) {
+            return bispdu.to_vec(self.next_sequence.wrapping_sub(1), self.received_in_sequence, self.credit_offered, credit_available);
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let buffer = bispdu.to_vec(sequence, self.received_in_sequence, self.credit_offered, credit_available);
+        self.retransmission_queue.push_back((sequence, buffer.clone(), now)); //TODO optimize clone
+        return buffer;
+    }
+
+    /// like prepare(), but queues the BISPDU if the peer has no credit left for it
+    pub fn send(&mut self, bispdu: Bispdu, now: DateTime<Utc>) -> Option<Vec<u8>> {
+        if !self.pending.is_empty() || !self.can_send() {
+            self.pending.push_back(bispdu);
+            return None;
+        }
+        return Some(self.prepare(&bispdu, now));
+    }
+
+    /// queued BISPDUs the peer now has credit for
+    pub fn release(&mut self, now: DateTime<Utc>) -> Vec<Vec<u8>> {
+        let mut released = vec![];
+        while self.can_send() {
+            let Some(bispdu) = self.pending.pop_front() else { break; };
+            released.push(self.prepare(&bispdu, now));
+        }
+        return released;
+    }
+
+    /// process header of a received BISPDU
+    /// returns false if it is a duplicate, out of sequence or beyond the credit offered and should be ignored
+    pub fn received(&mut self, header: &BispduHeader) -> bool {
+        // acknowledgement - drop everything up to and including it from the retransmission queue
+        if header.acknowledgement.wrapping_sub(self.acknowledged_by_peer) < u32::MAX / 2 {
+            self.acknowledged_by_peer = header.acknowledgement;
+        }
+        while let Some((sequence, _, _)) = self.retransmission_queue.front() {
+            if self.acknowledged_by_peer.wrapping_sub(*sequence) < u32::MAX / 2 {
+                self.retransmission_queue.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.credit_from_peer = header.credit_offered;
+
+        if header.type_ == TYPE_KEEPALIVE {
+            return true;
+        }
+        if header.type_ == TYPE_OPEN {
+            // OPEN establishes the sequence number space of the peer
+            self.received_in_sequence = header.sequence;
+            return true;
+        }
+        if header.sequence == self.received_in_sequence.wrapping_add(1) {
+            if header.sequence.wrapping_sub(self.acknowledgement_sent) > self.credit_offered as u32 {
+                debug!("ignoring BISPDU with sequence {} beyond credit offered", header.sequence);
+                return false;
+            }
+            self.received_in_sequence = header.sequence;
+            return true;
+        }
+        debug!("ignoring BISPDU with sequence {} - expected {}", header.sequence, self.received_in_sequence.wrapping_add(1));
+        return false;
+    }
+
+    /// returns BISPDUs whose retransmission timer has expired
+    pub fn due_retransmissions(&mut self, now: DateTime<Utc>) -> Vec<Vec<u8>> {
+        let mut due = vec![];
+        for (_, buffer, sent) in self.retransmission_queue.iter_mut() {
+            if now - *sent > self.retransmission_timeout {
+                *sent = now;
+                due.push(buffer.clone());   //TODO optimize clone
+            }
+        }
+        return due;
+    }
+}
+
+/// ISO 10747 7.6 BIS finite state machine states
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BisState {
+    Closed,
+    OpenSent,
+    OpenRcvd,
+    Established,
+    CloseWait,
+}
+
+/// one route as received from a peer, resp. as advertised to a peer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub route_id: u32,
+    /// NET of the BIS the route was received from, empty for locally originated routes
+    pub peer: Vec<u8>,
+    pub path_attributes: Vec<PathAttribute>,
+    pub destinations: Vec<NsapPrefix>,
+}
+
+impl Route {
+    pub fn attribute(&self, type_: u8) -> Option<&PathAttribute> {
+        return self.path_attributes.iter().find(|attribute| attribute.type_ == type_);
+    }
+}
+
+/// policy information base hooks - the local routing policy of the RD
+pub trait Policy {
+    /// ISO 10747 7.16.1 degree of preference, None rejects the route
+    fn degree_of_preference(&self, route: &Route) -> Option<u32>;
+    /// should the route be advertised to the given peer
+    fn export_to_peer(&self, route: &Route, peer_net: &[u8], peer_rdi: &[u8]) -> bool;
+    /// should the route be installed into the CLNS forwarding table
+    fn export_to_clns(&self, route: &Route) -> bool;
+}
+
+/// accepts everything, prefers shorter RD paths, advertises everything and installs everything
+pub struct DefaultPolicy {}
+
+impl Policy for DefaultPolicy {
+    fn degree_of_preference(&self, route: &Route) -> Option<u32> {
+        let rd_path_length = route.attribute(ATTRIBUTE_RD_PATH).map_or(0, |attribute| rd_path_length(&attribute.value));
+        return Some(u32::MAX - rd_path_length as u32);
+    }
+
+    fn export_to_peer(&self, _route: &Route, _peer_net: &[u8], _peer_rdi: &[u8]) -> bool {
+        return true;
+    }
+
+    fn export_to_clns(&self, _route: &Route) -> bool {
+        return true;
+    }
+}
+
+pub struct Peer {
+    net: Vec<u8>,
+    rdi: Option<Vec<u8>>,
+    state: BisState,
+    delivery: ReliableDelivery,
+    hold_time: u16,
+    last_received: DateTime<Utc>,
+    last_sent: DateTime<Utc>,
+    /// routes received from this peer, by route ID
+    adj_rib_in: HashMap<u32, Route>,
+    /// routes advertised to this peer, by destination
+    adj_rib_out: HashMap<NsapPrefix, Route>,
+}
+
+impl Peer {
+    pub fn state(&self) -> BisState {
+        return self.state;
+    }
+
+    pub fn adj_rib_in(&self) -> &HashMap<u32, Route> {
+        return &self.adj_rib_in;
+    }
+
+    pub fn adj_rib_out(&self) -> &HashMap<NsapPrefix, Route> {
+        return &self.adj_rib_out;
+    }
+}
+
+/// BISPDUs to be sent: (NET of peer BIS, encoded BISPDU)
+pub type Outgoing = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// IDRP speaker of a BIS
+pub struct Speaker<P: Policy> {
+    local_rdi: Vec<u8>,
+    hold_time: u16,
+    max_pdu_size: u16,
+    credit: u8,
+    policy: P,
+    peers: HashMap<Vec<u8>, Peer>,
+    /// selected routes, by destination
+    loc_rib: HashMap<NsapPrefix, Route>,
+    /// routes originated by this BIS
+    local_routes: Vec<Route>,
+    next_route_id: u32,
+    forwarding_table: Arc<Mutex<ForwardingTable>>,
+}
+
+impl<P: Policy> Speaker<P> {
+    pub fn new(local_rdi: Vec<u8>, hold_time: u16, policy: P, forwarding_table: Arc<Mutex<ForwardingTable>>) -> Self {
+        Speaker {
+            local_rdi: local_rdi,
+            hold_time: hold_time,
+            max_pdu_size: 1400, //TODO from SN max SNSDU size minus CLNP header
+            credit: 8,
+            policy: policy,
+            peers: HashMap::new(),
+            loc_rib: HashMap::new(),
+            local_routes: vec![],
+            next_route_id: 1,
+            forwarding_table: forwarding_table,
+        }
+    }
+
+    pub fn peers(&self) -> &HashMap<Vec<u8>, Peer> {
+        return &self.peers;
+    }
+
+    pub fn loc_rib(&self) -> &HashMap<NsapPrefix, Route> {
+        return &self.loc_rib;
+    }
+
+    /// add a configured peer BIS and start the connection by sending OPEN
+    pub fn start(&mut self, peer_net: Vec<u8>, now: DateTime<Utc>) -> Outgoing {
+        let mut peer = Peer {
+            net: peer_net.clone(),
+            rdi: None,
+            state: BisState::Closed,
+            delivery: ReliableDelivery::new(self.credit, chrono::Duration::seconds(3)),
+            hold_time: self.hold_time,
+            last_received: now,
+            last_sent: now,
+            adj_rib_in: HashMap::new(),
+            adj_rib_out: HashMap::new(),
+        };
+        let open = self.open_bispdu();
+        let buffer = peer.delivery.prepare(&open, now);
+        peer.state = BisState::OpenSent;
+        info!("IDRP: sending OPEN to peer {:02x?}", peer_net);
+        self.peers.insert(peer_net.clone(), peer);
+        return vec![(peer_net, buffer)];
+    }
+
+    /// originate routes to destinations in our own RD
+    pub fn originate(&mut self, destinations: Vec<NsapPrefix>, now: DateTime<Utc>) -> Result<Outgoing, Error> {
+        if let Some(invalid) = destinations.iter().find(|destination| !destination.is_valid()) {
+            return Err(Error::new(std::io::ErrorKind::InvalidInput, format!("invalid NSAP prefix {}", invalid.to_string())));
+        }
+        let route = Route {
+            route_id: self.allocate_route_id(),
+            peer: vec![],
+            path_attributes: vec![PathAttribute { flags: ATTRIBUTE_FLAG_TRANSITIVE, type_: ATTRIBUTE_RD_PATH, value: vec![] }],
+            destinations: destinations,
+        };
+        self.local_routes.push(route);
+        return Ok(self.decision_process(now));
+    }
+
+    /// process a BISPDU received from the given peer
+    pub fn receive(&mut self, peer_net: &[u8], buffer: &[u8], now: DateTime<Utc>) -> Result<Outgoing, Error> {
+        let (header, bispdu) = Bispdu::from_buf(buffer)?;
+        let open = matches!(bispdu, Bispdu::Open { version: VERSION, .. }).then(|| self.open_bispdu());
+        let (credit, hold_time) = (self.credit, self.hold_time);
+        let Some(peer) = self.peers.get_mut(peer_net) else {
+            info!("IDRP: ignoring BISPDU from unconfigured BIS {:02x?}", peer_net);
+            return Ok(vec![]);
+        };
+        let mut outgoing: Outgoing = vec![];
+        // ISO 10747 7.6 - the peer restarts the connection, e.g. after an error or hold timer expiry, start over as well
+        if let (BisState::Closed, Some(open)) = (peer.state, open) {
+            info!("IDRP: peer {:02x?} opens the connection again, sending OPEN", peer.net);
+            peer.delivery = ReliableDelivery::new(credit, chrono::Duration::seconds(3));
+            peer.hold_time = hold_time;
+            peer.adj_rib_in.clear();
+            peer.adj_rib_out.clear();
+            outgoing.push((peer.net.clone(), peer.delivery.prepare(&open, now)));
+            peer.last_sent = now;
+            peer.state = BisState::OpenSent;
+        }
+        peer.last_received = now;
+        if !peer.delivery.received(&header) {
+            return Ok(outgoing);
+        }
+
+        // the peer may have granted credit for queued BISPDUs
+        for buffer in peer.delivery.release(now) {
+            outgoing.push((peer.net.clone(), buffer));
+            peer.last_sent = now;
+        }
+        let mut routes_changed = false;
+        // ISO 10747 7.6 - in OPEN-RCVD our OPEN is acknowledged by the KEEPALIVE of the peer, or by whatever it sends next if that got lost
+        if peer.state == BisState::OpenRcvd && peer.delivery.outstanding() == 0 && !matches!(bispdu, Bispdu::Open { .. } | Bispdu::Error { .. } | Bispdu::Cease) {
+            peer.state = BisState::Established;
+            info!("IDRP: connection to peer {:02x?} established", peer.net);
+            // send the full Loc-RIB to the new peer
+            routes_changed = true;
+        }
+        match (peer.state, bispdu) {
+            (_, Bispdu::Open { version, .. }) if version != VERSION => {
+                warn!("IDRP: peer {:02x?} sent OPEN with unsupported version {}, closing", peer.net, version);
+                let error = Bispdu::Error { error_code: ERROR_OPEN_PDU, error_subcode: ERROR_OPEN_PDU_UNSUPPORTED_VERSION, data: vec![VERSION] };
+                outgoing.push((peer.net.clone(), peer.delivery.prepare(&error, now)));
+                peer.state = BisState::Closed;
+                peer.adj_rib_in.clear();
+                peer.adj_rib_out.clear();
+                routes_changed = true;
+            },
+            (BisState::Closed, _) => {
+                debug!("IDRP: ignoring BISPDU in state closed");
+            },
+            (BisState::OpenSent, Bispdu::Open { hold_time, source_rdi, authentication_code, .. }) |
+            (BisState::OpenRcvd, Bispdu::Open { hold_time, source_rdi, authentication_code, .. }) => {
+                if authentication_code != AUTHENTICATION_CODE_INTEGRITY {
+                    let error = Bispdu::Error { error_code: ERROR_OPEN_PDU, error_subcode: 5, data: vec![] };   // subcode 5 = authentication failure
+                    outgoing.push((peer.net.clone(), peer.delivery.prepare(&error, now)));
+                    peer.state = BisState::Closed;
+                } else {
+                    peer.rdi = Some(source_rdi);
+                    peer.hold_time = peer.hold_time.min(hold_time);
+                    // acknowledge with KEEPALIVE, the connection is up once the peer acknowledges our OPEN the same way
+                    outgoing.push((peer.net.clone(), peer.delivery.prepare(&Bispdu::Keepalive, now)));
+                    peer.last_sent = now;
+                    if peer.delivery.outstanding() == 0 {
+                        // the KEEPALIVE of the peer overtook its OPEN
+                        peer.state = BisState::Established;
+                        info!("IDRP: connection to peer {:02x?} established", peer.net);
+                        routes_changed = true;
+                    } else {
+                        peer.state = BisState::OpenRcvd;
+                    }
+                }
+            },
+            (BisState::Established, Bispdu::Open { .. }) => {
+                // our KEEPALIVE acknowledging it got lost and the peer retransmitted its OPEN
+                debug!("IDRP: acknowledging retransmitted OPEN from peer {:02x?}", peer.net);
+                outgoing.push((peer.net.clone(), peer.delivery.prepare(&Bispdu::Keepalive, now)));
+                peer.last_sent = now;
+            },
+            (BisState::Established, Bispdu::Update { withdrawn_routes, path_attributes, nlri }) => {
+                for route_id in withdrawn_routes {
+                    peer.adj_rib_in.remove(&route_id);
+                }
+                if !nlri.is_empty() {
+                    // ROUTE_SEPARATOR carries the route ID of the advertised route
+                    let route_id = path_attributes.iter()
+                        .find(|attribute| attribute.type_ == ATTRIBUTE_ROUTE_SEPARATOR && attribute.value.len() >= 4)
+                        .map(|attribute| u32::from_be_bytes([attribute.value[0], attribute.value[1], attribute.value[2], attribute.value[3]]));
+                    let Some(route_id) = route_id else {
+                        let error = Bispdu::Error { error_code: ERROR_UPDATE_PDU, error_subcode: 2, data: vec![] };  // subcode 2 = missing well-known attribute
+                        outgoing.push((peer.net.clone(), peer.delivery.prepare(&error, now)));
+                        return Ok(outgoing);
+                    };
+                    let route = Route {
+                        route_id: route_id,
+                        peer: peer.net.clone(),
+                        path_attributes: path_attributes,
+                        destinations: nlri.into_iter().flat_map(|nlri_inner| nlri_inner.prefixes).collect(),
+                    };
+                    // loop detection
+                    if route.attribute(ATTRIBUTE_RD_PATH).map_or(false, |attribute| rd_path_contains(&attribute.value, &self.local_rdi)) {
+                        debug!("IDRP: ignoring route {} from {:02x?} containing own RDI in RD_PATH", route_id, peer.net);
+                    } else {
+                        peer.adj_rib_in.insert(route_id, route);
+                    }
+                }
+                routes_changed = true;
+            },
+            (_, Bispdu::Keepalive) => {},
+            (_, Bispdu::Error { error_code, error_subcode, .. }) => {
+                warn!("IDRP: peer {:02x?} sent error {}/{}, closing", peer.net, error_code, error_subcode);
+                peer.state = BisState::Closed;
+                peer.adj_rib_in.clear();
+                peer.adj_rib_out.clear();
+                routes_changed = true;
+            },
+            (_, Bispdu::Cease) => {
+                info!("IDRP: peer {:02x?} ceased the connection", peer.net);
+                peer.state = BisState::CloseWait;
+                peer.adj_rib_in.clear();
+                peer.adj_rib_out.clear();
+                routes_changed = true;
+            },
+            (BisState::Established, Bispdu::RibRefresh { opcode: RIB_REFRESH_REQUEST, .. }) => {
+                // send everything again
+                peer.adj_rib_out.clear();
+                routes_changed = true;
+            },
+            (BisState::Established, Bispdu::RibRefresh { .. }) => {},
+            (state, bispdu) => {
+                warn!("IDRP: unexpected BISPDU type {} in state {:?}", bispdu.type_(), state);
+                let error = Bispdu::Error { error_code: ERROR_FSM, error_subcode: 0, data: vec![] };
+                outgoing.push((peer.net.clone(), peer.delivery.prepare(&error, now)));
+                peer.state = BisState::Closed;
+            },
+        }
+
+        if routes_changed {
+            outgoing.append(&mut self.decision_process(now));
+        }
+        // acknowledge right away if nothing else goes to the peer, so that it does not run out of credit
+        if header.type_ != TYPE_KEEPALIVE && !outgoing.iter().any(|(net, _)| net == peer_net) {
+            if let Some(peer) = self.peers.get_mut(peer_net) {
+                if peer.state != BisState::Closed {
+                    outgoing.push((peer.net.clone(), peer.delivery.prepare(&Bispdu::Keepalive, now)));
+                    peer.last_sent = now;
+                }
+            }
+        }
+        return Ok(outgoing);
+    }
+
+    /// timers - retransmission, keepalive and hold timer, to be called regularly by the maintenance thread
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Outgoing {
+        let mut outgoing: Outgoing = vec![];
+        let mut routes_changed = false;
+        for peer in self.peers.values_mut() {
+            if peer.state == BisState::Closed {
+                continue;
+            }
+            for buffer in peer.delivery.due_retransmissions(now) {
+                outgoing.push((peer.net.clone(), buffer));
+            }
+            for buffer in peer.delivery.release(now) {
+                outgoing.push((peer.net.clone(), buffer));
+                peer.last_sent = now;
+            }
+            if peer.state != BisState::Established {
+                continue;
+            }
+            if now - peer.last_received > chrono::Duration::seconds(peer.hold_time as i64) {
+                warn!("IDRP: hold timer for peer {:02x?} expired", peer.net);
+                let error = Bispdu::Error { error_code: ERROR_HOLD_TIMER_EXPIRED, error_subcode: 0, data: vec![] };
+                outgoing.push((peer.net.clone(), peer.delivery.prepare(&error, now)));
+                peer.state = BisState::Closed;
+                peer.adj_rib_in.clear();
+                peer.adj_rib_out.clear();
+                routes_changed = true;
+            } else if now - peer.last_sent > chrono::Duration::seconds(peer.hold_time as i64 / 3) {
+                outgoing.push((peer.net.clone(), peer.delivery.prepare(&Bispdu::Keepalive, now)));
+                peer.last_sent = now;
+            }
+        }
+        if routes_changed {
+            outgoing.append(&mut self.decision_process(now));
+        }
+        return outgoing;
+    }
+
+    fn open_bispdu(&self) -> Bispdu {
+        return Bispdu::Open {
+            version: VERSION,
+            hold_time: self.hold_time,
+            max_pdu_size: self.max_pdu_size,
+            source_rdi: self.local_rdi.clone(),
+            rib_atts: vec![vec![]],     // only the default RIB
+            confederations: vec![],
+            authentication_code: AUTHENTICATION_CODE_INTEGRITY,
+            authentication_data: vec![],
+        };
+    }
+
+    fn allocate_route_id(&mut self) -> u32 {
+        let route_id = self.next_route_id;
+        self.next_route_id = self.next_route_id.wrapping_add(1).max(1);
+        return route_id;
+    }
+
+    /// ISO 10747 7.16 decision process: select routes into Loc-RIB, install them into the forwarding table and distribute them to the peers
+    fn decision_process(&mut self, now: DateTime<Utc>) -> Outgoing {
+        // phase 1 and 2 - calculate degree of preference and select the most preferred route per destination
+        let mut selected: HashMap<NsapPrefix, (u32, &Route)> = HashMap::new();
+        let candidates = self.local_routes.iter().map(|route| (route, true))
+            .chain(self.peers.values().filter(|peer| peer.state == BisState::Established).flat_map(|peer| peer.adj_rib_in.values().map(|route| (route, false))));
+        for (route, local) in candidates {
+            let preference = if local { Some(u32::MAX) } else { self.policy.degree_of_preference(route) };
+            let Some(preference) = preference else { continue; };
+            for destination in &route.destinations {
+                match selected.get(destination) {
+                    Some((existing_preference, _)) if *existing_preference >= preference => {},
+                    _ => { selected.insert(destination.clone(), (preference, route)); }
+                }
+            }
+        }
+        let new_loc_rib: HashMap<NsapPrefix, Route> = selected.into_iter().map(|(destination, (_, route))| (destination, route.clone())).collect();
+
+        // install into CLNS forwarding table
+        {
+            let mut forwarding_table = self.forwarding_table.lock().expect("failed to lock forwarding_table");
+            for destination in self.loc_rib.keys() {
+                if !new_loc_rib.contains_key(destination) {
+                    forwarding_table.remove(destination, RouteSource::Idrp);
+                }
+            }
+            for (destination, route) in &new_loc_rib {
+                if route.peer.is_empty() {
+                    continue;   // local route, nothing to forward to
+                }
+                if !self.policy.export_to_clns(route) {
+                    forwarding_table.remove(destination, RouteSource::Idrp);
+                    continue;
+                }
+                let next_hop = route.attribute(ATTRIBUTE_NEXT_HOP).map_or(route.peer.clone(), |attribute| attribute.value.clone());
+                forwarding_table.insert(ForwardingEntry {
+                    destination: destination.clone(),
+                    next_hop: next_hop,
+                    metric: route.attribute(ATTRIBUTE_RD_PATH).map_or(0, |attribute| rd_path_length(&attribute.value)) as u32,
+                    source: RouteSource::Idrp,
+                    subnetwork: None,   // via the adjacency of the next hop
+                });
+            }
+        }
+        self.loc_rib = new_loc_rib;
+
+        // phase 3 - route dissemination
+        let mut outgoing: Outgoing = vec![];
+        let peer_nets: Vec<Vec<u8>> = self.peers.keys().cloned().collect();   //TODO optimize
+        for peer_net in peer_nets {
+            let mut withdrawn_routes: Vec<u32> = vec![];
+            let mut advertise: Vec<NsapPrefix> = vec![];
+            {
+                let peer = self.peers.get(&peer_net).expect("peer vanished");
+                if peer.state != BisState::Established {
+                    continue;
+                }
+                let peer_rdi = peer.rdi.clone().unwrap_or_default();
+                // withdraw what is no longer selected or no longer exported
+                for (destination, advertised) in &peer.adj_rib_out {
+                    let still_exported = self.loc_rib.get(destination).map_or(false, |route| route.peer != peer_net && self.policy.export_to_peer(route, &peer_net, &peer_rdi));
+                    if !still_exported && !withdrawn_routes.contains(&advertised.route_id) {
+                        withdrawn_routes.push(advertised.route_id);
+                    }
+                }
+                // advertise what is new
+                for (destination, route) in &self.loc_rib {
+                    if route.peer == peer_net || !self.policy.export_to_peer(route, &peer_net, &peer_rdi) {
+                        continue;
+                    }
+                    if peer.adj_rib_out.get(destination).map_or(false, |advertised| advertised.path_attributes == self.advertised_attributes(route, 0)[1..]) {
+                        continue;
+                    }
+                    advertise.push(destination.clone());
+                }
+            }
+            // assign route IDs and remember in Adj-RIB-Out
+            let mut bispdus: Vec<Bispdu> = vec![];
+            if !withdrawn_routes.is_empty() {
+                let peer = self.peers.get_mut(&peer_net).expect("peer vanished");
+                peer.adj_rib_out.retain(|_, advertised| !withdrawn_routes.contains(&advertised.route_id));
+                bispdus.push(Bispdu::Update { withdrawn_routes: withdrawn_routes, path_attributes: vec![], nlri: vec![] });
+            }
+            for destination in advertise {
+                let route = self.loc_rib.get(&destination).expect("selected route vanished").clone();
+                let route_id = self.allocate_route_id();
+                let path_attributes = self.advertised_attributes(&route, route_id);
+                let peer = self.peers.get_mut(&peer_net).expect("peer vanished");
+                let previous = peer.adj_rib_out.insert(destination.clone(), Route {
+                    route_id: route_id,
+                    peer: peer_net.clone(),
+                    path_attributes: path_attributes[1..].to_vec(),
+                    destinations: vec![destination.clone()],
+                });
+                bispdus.push(Bispdu::Update {
+                    withdrawn_routes: previous.map_or(vec![], |previous| vec![previous.route_id]),
+                    path_attributes: path_attributes,
+                    nlri: vec![Nlri { prefixes: vec![destination] }],
+                });
+            }
+            let peer = self.peers.get_mut(&peer_net).expect("peer vanished");
+            for bispdu in bispdus {
+                if let Some(buffer) = peer.delivery.send(bispdu, now) {
+                    outgoing.push((peer_net.clone(), buffer));
+                    peer.last_sent = now;
+                } else {
+                    debug!("IDRP: peer {:02x?} out of credit, queueing UPDATE", peer_net);
+                }
+            }
+        }
+        return outgoing;
+    }
+
+    /// path attributes as advertised to another RD: ROUTE_SEPARATOR first, RD_PATH with own RDI prepended, only transitive attributes passed on
+    fn advertised_attributes(&self, route: &Route, route_id: u32) -> Vec<PathAttribute> {
+        let mut route_separator = route_id.to_be_bytes().to_vec();
+        route_separator.push(0);    // local preference, only meaningful inside the RD
+        let mut path_attributes = vec![
+            PathAttribute { flags: 0, type_: ATTRIBUTE_ROUTE_SEPARATOR, value: route_separator },
+            PathAttribute {
+                flags: ATTRIBUTE_FLAG_TRANSITIVE,
+                type_: ATTRIBUTE_RD_PATH,
+                value: rd_path_prepend(&route.attribute(ATTRIBUTE_RD_PATH).map_or(vec![], |attribute| attribute.value.clone()), &self.local_rdi),
+            },
+        ];
+        for attribute in &route.path_attributes {
+            if attribute.type_ == ATTRIBUTE_ROUTE_SEPARATOR || attribute.type_ == ATTRIBUTE_RD_PATH || attribute.type_ == ATTRIBUTE_NEXT_HOP {
+                continue;
+            }
+            if attribute.flags & ATTRIBUTE_FLAG_OPTIONAL != 0 && attribute.flags & ATTRIBUTE_FLAG_TRANSITIVE == 0 {
+                continue;   // optional non-transitive attributes are not passed on
+            }
+            let mut passed_on = attribute.clone();
+            if passed_on.flags & ATTRIBUTE_FLAG_OPTIONAL != 0 {
+                passed_on.flags |= ATTRIBUTE_FLAG_PARTIAL;
+            }
+            path_attributes.push(passed_on);
+        }
+        return path_attributes;
+    }
+}
+
+/// whether the NSDU is a BISPDU - for registering IDRP as a user of CLNP
+pub fn is_bispdu(nsdu: &[u8]) -> bool {
+    if nsdu.len() < HEADER_LENGTH || nsdu[0] != NETWORK_LAYER_PROTOCOL_IDENTIFIER_IDRP || !(TYPE_OPEN..=TYPE_RIB_REFRESH).contains(&nsdu[3]) {
+        return false;
+    }
+    return u16::from_be_bytes([nsdu[1], nsdu[2]]) as usize == nsdu.len();
+}
+
+/// runs a Speaker as NS user of CLNP: BISPDUs go in and out as NSDUs, addressed by the NET of the peer BIS
+pub struct Service<N: NetworkService<'static> + Send + 'static, P: Policy + Send + 'static> {
+    ns: Arc<Mutex<N>>,
+    speaker: Arc<Mutex<Speaker<P>>>,
+    ns_user_from: Arc<Mutex<rtrb::Consumer<NSUnitDataIndication>>>,
+    ns_user_from_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
+}
+
+impl<N: NetworkService<'static> + Send + 'static, P: Policy + Send + 'static> Service<N, P> {
+    /// registers itself as the user of the given NS for BISPDUs, learned routes go into its forwarding table
+    /// NOTE: has to be created before the TP4 service, which accepts everything that is not a UD TPDU
+    pub fn new(ns: Arc<Mutex<N>>, local_rdi: Vec<u8>, hold_time: u16, policy: P, queue_capacity: usize) -> Self {
+        let (ns_user_to, ns_user_from) = rtrb::RingBuffer::new(queue_capacity);
+        let ns_user_from_wakeup = Arc::new(Mutex::new(None));
+        let forwarding_table;
+        {
+            let mut ns_locked = ns.lock().expect("failed to lock ns");
+            forwarding_table = ns_locked.forwarding_table();
+            ns_locked.register_ns_user(NsUser {
+                accepts: is_bispdu,
+                ns_user_to: ns_user_to,
+                ns_user_to_wakeup: ns_user_from_wakeup.clone(),
+            });
+        }
+        return Service {
+            ns: ns,
+            speaker: Arc::new(Mutex::new(Speaker::new(local_rdi, hold_time, policy, forwarding_table))),
+            ns_user_from: Arc::new(Mutex::new(ns_user_from)),
+            ns_user_from_wakeup: ns_user_from_wakeup,
+        };
+    }
+
+    pub fn run(&mut self) {
+        // read BISPDUs from NS and feed them to the speaker
+        let ns_arc = self.ns.clone();
+        let speaker_arc = self.speaker.clone();
+        let ns_user_from_arc = self.ns_user_from.clone();
+        let ns2idrp_consumer_wakeup = thread::Builder::new().name("N IDRP <- N".to_string()).spawn(move || {
+            // keep permanent lock on this
+            let mut ns_user_from = ns_user_from_arc.lock().expect("failed to lock ns_user_from");
+            loop {
+                while let Ok(indication) = ns_user_from.pop() {
+                    let result = speaker_arc.lock().expect("failed to lock speaker").receive(&indication.ns_source_address.to_u8(), &indication.ns_userdata, Utc::now());
+                    match result {
+                        Ok(outgoing) => send(&ns_arc, outgoing),
+                        Err(e) => info!("IDRP: discarding invalid BISPDU from {}: {}", indication.ns_source_address.to_string(), e),
+                    }
+                }
+                thread::park(); // wait for unpark wakeup call from NS
+            }
+        }).expect("failed to start thread");
+        // put thread handle into well-known place
+        self.ns_user_from_wakeup.lock().expect("failed to lock ns_user_from_wakeup (giver)").replace(ns2idrp_consumer_wakeup);
+
+        // retransmission, keepalive and hold timers
+        let ns_arc = self.ns.clone();
+        let speaker_arc = self.speaker.clone();
+        thread::Builder::new().name("N IDRP timers".to_string()).spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                let outgoing = speaker_arc.lock().expect("failed to lock speaker").tick(Utc::now());
+                send(&ns_arc, outgoing);
+            }
+        }).expect("failed to start thread");
+    }
+
+    /// add a configured peer BIS by its NET and send OPEN
+    pub fn start(&self, peer: &Nsap) {
+        let outgoing = self.speaker.lock().expect("failed to lock speaker").start(peer.to_u8(), Utc::now());
+        send(&self.ns, outgoing);
+    }
+
+    /// originate routes to destinations in our own RD and advertise them to the peers
+    pub fn originate(&self, destinations: Vec<NsapPrefix>) -> Result<(), Error> {
+        let outgoing = self.speaker.lock().expect("failed to lock speaker").originate(destinations, Utc::now())?;
+        send(&self.ns, outgoing);
+        return Ok(());
+    }
+
+    /// e.g. for looking at the peers and the Loc-RIB
+    pub fn speaker(&self) -> Arc<Mutex<Speaker<P>>> {
+        return self.speaker.clone();
+    }
+}
+
+/// hand BISPDUs to CLNP - NOTE: called without holding the lock on the speaker, so that IDRP never holds both
+fn send<N: NetworkService<'static>>(ns: &Mutex<N>, outgoing: Outgoing) {
+    if outgoing.is_empty() {
+        return;
+    }
+    let mut ns = ns.lock().expect("failed to lock ns");
+    for (peer_net, buffer) in outgoing {
+        let Some(nsap) = Nsap::from_u8(&peer_net) else {
+            warn!("IDRP: cannot address peer {:02x?} over CLNP, dropping BISPDU", peer_net);
+            continue;
+        };
+        if let Err(e) = ns.n_unitdata_request_to_nsap(&nsap, &Qos::default(), &buffer) {
+            // NOTE: lost like on the network, retransmitted by reliable delivery
+            debug!("IDRP: sending BISPDU to peer {:02x?} failed: {}", peer_net, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NET_A: [u8; 12] = [0, 49, 0, 1, 0, 1, 0x02, 0, 0, 0, 0, 0x0a];
+    const NET_B: [u8; 12] = [0, 49, 0, 1, 0, 1, 0x02, 0, 0, 0, 0, 0x0b];
+
+    fn speaker(rdi: &[u8]) -> Speaker<DefaultPolicy> {
+        return Speaker::new(rdi.to_vec(), 90, DefaultPolicy {}, Arc::new(Mutex::new(ForwardingTable::default())));
+    }
+
+    /// hand BISPDUs back and forth between the two speakers until both are quiet, returns how many were exchanged
+    fn deliver(a: &mut Speaker<DefaultPolicy>, b: &mut Speaker<DefaultPolicy>, mut outgoing: Outgoing, from_a: bool) -> usize {
+        let mut exchanged = 0;
+        let mut queue: VecDeque<(bool, Vec<u8>)> = outgoing.drain(..).map(|(_, buffer)| (from_a, buffer)).collect();
+        while let Some((from_a, buffer)) = queue.pop_front() {
+            exchanged += 1;
+            let replies = if from_a { b.receive(&NET_A, &buffer, Utc::now()) } else { a.receive(&NET_B, &buffer, Utc::now()) }.unwrap();
+            queue.extend(replies.into_iter().map(|(_, buffer)| (!from_a, buffer)));
+        }
+        return exchanged;
+    }
+
+    fn established(a: &mut Speaker<DefaultPolicy>, b: &mut Speaker<DefaultPolicy>) {
+        let open_a = a.start(NET_B.to_vec(), Utc::now());
+        let open_b = b.start(NET_A.to_vec(), Utc::now());
+        deliver(a, b, open_a, true);
+        deliver(a, b, open_b, false);
+        assert_eq!(a.peers()[&NET_B.to_vec()].state(), BisState::Established);
+        assert_eq!(b.peers()[&NET_A.to_vec()].state(), BisState::Established);
+    }
+
+    fn prefix(length_bits: u8, prefix: &[u8]) -> NsapPrefix {
+        return NsapPrefix::new(length_bits, prefix.to_vec()).unwrap();
+    }
+
+    #[test]
+    fn bispdu_round_trip() {
+        let bispdus = vec![
+            Bispdu::Open {
+                version: VERSION,
+                hold_time: 90,
+                max_pdu_size: 1400,
+                source_rdi: vec![0x47, 0, 5],
+                rib_atts: vec![vec![], vec![ATTRIBUTE_TRANSIT_DELAY]],
+                confederations: vec![vec![0x47, 0, 6]],
+                authentication_code: AUTHENTICATION_CODE_INTEGRITY,
+                authentication_data: vec![],
+            },
+            Bispdu::Update {
+                withdrawn_routes: vec![3, 4],
+                path_attributes: vec![
+                    PathAttribute { flags: 0, type_: ATTRIBUTE_ROUTE_SEPARATOR, value: vec![0, 0, 0, 7, 0] },
+                    PathAttribute { flags: ATTRIBUTE_FLAG_TRANSITIVE, type_: ATTRIBUTE_RD_PATH, value: rd_path_prepend(&[], &[0x47, 0, 5]) },
+                ],
+                nlri: vec![Nlri { prefixes: vec![prefix(16, &[0, 49]), prefix(44, &[0, 49, 0, 1, 0, 0x10])] }],
+            },
+            Bispdu::Error { error_code: ERROR_UPDATE_PDU, error_subcode: 2, data: vec![1, 2, 3] },
+            Bispdu::Keepalive,
+            Bispdu::Cease,
+            Bispdu::RibRefresh { opcode: RIB_REFRESH_REQUEST, rib_atts: vec![vec![]] },
+        ];
+        // ISO 10747 7.2 byte layout of OPEN
+        let mut expected = vec![NETWORK_LAYER_PROTOCOL_IDENTIFIER_IDRP, 0, 49, TYPE_OPEN, 0, 0, 0, 5, 0, 0, 0, 4, 8, 7];
+        expected.extend_from_slice(&[0; 16]);
+        expected.extend_from_slice(&[
+            VERSION, 0, 90, 0x05, 0x78,     // version, hold time, maximum PDU size
+            3, 0x47, 0, 5,                  // source RDI
+            2, 0, 1, ATTRIBUTE_TRANSIT_DELAY,   // RIB-AttsSet
+            1, 3, 0x47, 0, 6,               // confederations
+            AUTHENTICATION_CODE_INTEGRITY,
+        ]);
+        assert_eq!(bispdus[0].to_vec(5, 4, 8, 7), expected);
+        for bispdu in bispdus {
+            let buffer = bispdu.to_vec(5, 4, 8, 7);
+            assert!(is_bispdu(&buffer));
+            let (header, decoded) = Bispdu::from_buf(&buffer).unwrap();
+            assert_eq!(decoded, bispdu);
+            assert_eq!(header.type_, bispdu.type_());
+            assert_eq!(header.length as usize, buffer.len());
+            assert_eq!((header.sequence, header.acknowledgement, header.credit_offered, header.credit_available), (5, 4, 8, 7));
+        }
+        // NOTE: padding by the underlying service is ignored
+        let mut padded = Bispdu::Cease.to_vec(1, 0, 8, 8);
+        padded.push(0);
+        assert_eq!(Bispdu::from_buf(&padded).unwrap().1, Bispdu::Cease);
+        assert!(!is_bispdu(&padded));
+    }
+
+    #[test]
+    fn nlri_prefix_checked() {
+        let update = |length_bits: u8, prefix_octets: &[u8]| {
+            let mut buffer = Bispdu::Update { withdrawn_routes: vec![], path_attributes: vec![], nlri: vec![] }.to_vec(1, 0, 8, 8);
+            buffer.extend_from_slice(&[NLRI_PROTO_TYPE_ISO_9577, 1, NLRI_PROTOCOL_CLNP]);
+            buffer.extend_from_slice(&(1 + prefix_octets.len() as u16).to_be_bytes());
+            buffer.push(length_bits);
+            buffer.extend_from_slice(prefix_octets);
+            let length = buffer.len() as u16;
+            buffer[1..3].copy_from_slice(&length.to_be_bytes());
+            return Bispdu::from_buf(&buffer);
+        };
+        assert!(update(16, &[0, 49]).is_ok());
+        // longer than any NSAP address
+        assert_eq!(update(200, &[0; 25]).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        // fewer octets than the length in bits asks for
+        assert_eq!(update(24, &[0, 49]).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn malformed_prefix_does_not_panic() {
+        assert!(NsapPrefix::new(24, vec![0, 49]).is_err());
+        assert!(NsapPrefix::new(168, vec![0; 21]).is_err());
+        let short = NsapPrefix { length_bits: 24, prefix: vec![0, 49] };
+        assert!(!short.matches(&NET_A));
+        assert!(!prefix(16, &[0, 49]).matches(&[0]));
+        assert!(prefix(16, &[0, 49]).matches(&NET_A));
+        // encoded padded to the octets the length asks for, so the peer can decode it
+        let update = Bispdu::Update { withdrawn_routes: vec![], path_attributes: vec![], nlri: vec![Nlri { prefixes: vec![short] }] };
+        match Bispdu::from_buf(&update.to_vec(1, 0, 8, 8)).unwrap().1 {
+            Bispdu::Update { nlri, .. } => assert_eq!(nlri[0].prefixes[0], prefix(24, &[0, 49, 0])),
+            other => panic!("{:?}", other)
+        }
+        let mut a = speaker(&[0x47, 0, 5]);
+        assert!(a.originate(vec![NsapPrefix { length_bits: 24, prefix: vec![0] }], Utc::now()).is_err());
+    }
+
+    #[test]
+    fn established_only_after_keepalive() {
+        let mut a = speaker(&[0x47, 0, 5]);
+        let mut b = speaker(&[0x47, 0, 6]);
+        let open_a = a.start(NET_B.to_vec(), Utc::now());
+        let open_b = b.start(NET_A.to_vec(), Utc::now());
+        assert_eq!(a.peers()[&NET_B.to_vec()].state(), BisState::OpenSent);
+
+        // OPEN alone is acknowledged with KEEPALIVE, but the connection is not up yet
+        let keepalive_b = b.receive(&NET_A, &open_a[0].1, Utc::now()).unwrap();
+        assert_eq!(b.peers()[&NET_A.to_vec()].state(), BisState::OpenRcvd);
+        assert_eq!(Bispdu::from_buf(&keepalive_b[0].1).unwrap().1, Bispdu::Keepalive);
+        let keepalive_a = a.receive(&NET_B, &open_b[0].1, Utc::now()).unwrap();
+        assert_eq!(a.peers()[&NET_B.to_vec()].state(), BisState::OpenRcvd);
+
+        // the KEEPALIVE acknowledging our OPEN brings it up
+        a.receive(&NET_B, &keepalive_b[0].1, Utc::now()).unwrap();
+        assert_eq!(a.peers()[&NET_B.to_vec()].state(), BisState::Established);
+        assert_eq!(b.peers()[&NET_A.to_vec()].state(), BisState::OpenRcvd);
+        b.receive(&NET_A, &keepalive_a[0].1, Utc::now()).unwrap();
+        assert_eq!(b.peers()[&NET_A.to_vec()].state(), BisState::Established);
+
+        // a retransmitted OPEN is acknowledged again and does not tear down the connection
+        let reply = a.receive(&NET_B, &open_b[0].1, Utc::now()).unwrap();
+        assert_eq!(Bispdu::from_buf(&reply[0].1).unwrap().1, Bispdu::Keepalive);
+        assert_eq!(a.peers()[&NET_B.to_vec()].state(), BisState::Established);
+    }
+
+    #[test]
+    fn keepalive_not_acknowledging_open() {
+        let mut a = speaker(&[0x47, 0, 5]);
+        let mut b = speaker(&[0x47, 0, 6]);
+        a.start(NET_B.to_vec(), Utc::now());
+        let open_b = b.start(NET_A.to_vec(), Utc::now());
+        a.receive(&NET_B, &open_b[0].1, Utc::now()).unwrap();
+        // KEEPALIVE from B before it has seen our OPEN
+        let keepalive = Bispdu::Keepalive.to_vec(1, 0, 8, 8);
+        a.receive(&NET_B, &keepalive, Utc::now()).unwrap();
+        assert_eq!(a.peers()[&NET_B.to_vec()].state(), BisState::OpenRcvd);
+    }
+
+    #[test]
+    fn routes_into_forwarding_table() {
+        let mut a = speaker(&[0x47, 0, 5]);
+        let mut b = speaker(&[0x47, 0, 6]);
+        established(&mut a, &mut b);
+        let destination = prefix(48, &[0, 49, 0, 1, 0, 0x10]);
+        let outgoing = a.originate(vec![destination.clone()], Utc::now()).unwrap();
+        deliver(&mut a, &mut b, outgoing, true);
+
+        let forwarding_table = b.forwarding_table.lock().unwrap();
+        let entry = forwarding_table.lookup(&[0, 49, 0, 1, 0, 0x10, 0x02, 0, 0, 0, 0, 1]).unwrap();
+        assert_eq!(entry.source, RouteSource::Idrp);
+        assert_eq!(entry.next_hop, NET_A.to_vec());
+        assert_eq!(entry.destination, destination);
+        assert_eq!(entry.metric, 1);
+        // own routes are not installed
+        assert!(a.forwarding_table.lock().unwrap().entries().is_empty());
+        assert_eq!(b.loc_rib()[&destination].peer, NET_A.to_vec());
+    }
+
+    #[test]
+    fn credit_enforced() {
+        let mut a = speaker(&[0x47, 0, 5]);
+        let mut b = speaker(&[0x47, 0, 6]);
+        b.credit = 1;
+        established(&mut a, &mut b);
+
+        // only one UPDATE may be outstanding, the second one waits for the acknowledgement
+        let first = a.originate(vec![prefix(48, &[0, 49, 0, 1, 0, 0x10])], Utc::now()).unwrap();
+        let second = a.originate(vec![prefix(48, &[0, 49, 0, 1, 0, 0x11])], Utc::now()).unwrap();
+        assert_eq!(first.len(), 1);
+        assert!(second.is_empty());
+        assert_eq!(a.peers()[&NET_B.to_vec()].delivery.pending(), 1);
+
+        // B acknowledges, which releases the queued UPDATE
+        let acknowledgement = b.receive(&NET_A, &first[0].1, Utc::now()).unwrap();
+        assert_eq!(acknowledgement.len(), 1);
+        let released = a.receive(&NET_B, &acknowledgement[0].1, Utc::now()).unwrap();
+        assert_eq!(released.len(), 1);
+        assert_eq!(a.peers()[&NET_B.to_vec()].delivery.pending(), 0);
+        deliver(&mut a, &mut b, released, true);
+        assert_eq!(b.loc_rib().len(), 2);
+    }
+
+    #[test]
+    fn beyond_credit_ignored() {
+        let mut a = speaker(&[0x47, 0, 5]);
+        let mut b = speaker(&[0x47, 0, 6]);
+        b.credit = 1;
+        established(&mut a, &mut b);
+        let first = a.originate(vec![prefix(48, &[0, 49, 0, 1, 0, 0x10])], Utc::now()).unwrap();
+        // a misbehaving peer sending beyond the credit offered - the BISPDU is dropped, then retransmitted
+        let beyond = Bispdu::Update {
+            withdrawn_routes: vec![],
+            path_attributes: vec![
+                PathAttribute { flags: 0, type_: ATTRIBUTE_ROUTE_SEPARATOR, value: vec![0, 0, 0, 9, 0] },
+                PathAttribute { flags: ATTRIBUTE_FLAG_TRANSITIVE, type_: ATTRIBUTE_RD_PATH, value: rd_path_prepend(&[], &[0x47, 0, 5]) },
+            ],
+            nlri: vec![Nlri { prefixes: vec![prefix(48, &[0, 49, 0, 1, 0, 0x11])] }],
+        };
+        let (first_header, _) = Bispdu::from_buf(&first[0].1).unwrap();
+        b.receive(&NET_A, &first[0].1, Utc::now()).unwrap();
+        // pretend B never acknowledged
+        b.peers.get_mut(&NET_A.to_vec()).unwrap().delivery.acknowledgement_sent = first_header.sequence - 1;
+        let reply = b.receive(&NET_A, &beyond.to_vec(first_header.sequence + 1, 0, 8, 8), Utc::now()).unwrap();
+        assert!(reply.is_empty());
+        assert_eq!(b.loc_rib().len(), 1);
+    }
+
+    #[test]
+    fn unsupported_version_refused() {
+        let mut a = speaker(&[0x47, 0, 5]);
+        a.start(NET_B.to_vec(), Utc::now());
+        let mut open = speaker(&[0x47, 0, 6]).open_bispdu();
+        if let Bispdu::Open { version, .. } = &mut open {
+            *version = 2;
+        }
+        let reply = a.receive(&NET_B, &open.to_vec(1, 0, 8, 8), Utc::now()).unwrap();
+        assert_eq!(reply.len(), 1);
+        assert_eq!(Bispdu::from_buf(&reply[0].1).unwrap().1, Bispdu::Error { error_code: ERROR_OPEN_PDU, error_subcode: ERROR_OPEN_PDU_UNSUPPORTED_VERSION, data: vec![VERSION] });
+        assert_eq!(a.peers()[&NET_B.to_vec()].state(), BisState::Closed);
+    }
+
+    #[test]
+    fn credit_available_advertised() {
+        let mut delivery = ReliableDelivery::new(4, chrono::Duration::seconds(3));
+        let header = |type_: u8, sequence: u32| BispduHeader { length: 0, type_: type_, sequence: sequence, acknowledgement: 0, credit_offered: 4, credit_available: 4, validation_pattern: [0; 16] };
+        assert!(delivery.received(&header(TYPE_OPEN, 10)));
+        delivery.prepare(&Bispdu::Keepalive, Utc::now());
+        assert!(delivery.received(&header(TYPE_UPDATE, 11)));
+        assert!(delivery.received(&header(TYPE_UPDATE, 12)));
+        assert_eq!(delivery.unacknowledged(), 2);
+        let (first, _) = Bispdu::from_buf(&delivery.prepare(&Bispdu::Cease, Utc::now())).unwrap();
+        assert_eq!((first.acknowledgement, first.credit_offered, first.credit_available), (12, 4, 2));
+        // all acknowledged, the full credit is available again
+        assert_eq!(delivery.unacknowledged(), 0);
+        let (second, _) = Bispdu::from_buf(&delivery.prepare(&Bispdu::Keepalive, Utc::now())).unwrap();
+        assert_eq!((second.acknowledgement, second.credit_available), (12, 4));
+    }
+
+    #[test]
+    fn reopened_after_close() {
+        let mut a = speaker(&[0x47, 0, 5]);
+        let mut b = speaker(&[0x47, 0, 6]);
+        established(&mut a, &mut b);
+        // B goes down, e.g. its hold timer expired
+        let error = b.tick(Utc::now() + chrono::Duration::seconds(100));
+        deliver(&mut a, &mut b, error, false);
+        assert_eq!(a.peers()[&NET_B.to_vec()].state(), BisState::Closed);
+        assert_eq!(b.peers()[&NET_A.to_vec()].state(), BisState::Closed);
+        // and starts over, A answers with its own OPEN instead of ignoring it
+        let open_b = b.start(NET_A.to_vec(), Utc::now());
+        deliver(&mut a, &mut b, open_b, false);
+        assert_eq!(a.peers()[&NET_B.to_vec()].state(), BisState::Established);
+        assert_eq!(b.peers()[&NET_A.to_vec()].state(), BisState::Established);
+    }
+
+    #[test]
+    fn retransmission_after_timeout() {
+        let mut a = speaker(&[0x47, 0, 5]);
+        let now = Utc::now();
+        let open = a.start(NET_B.to_vec(), now);
+        assert!(a.tick(now + chrono::Duration::seconds(1)).is_empty());
+        let retransmitted = a.tick(now + chrono::Duration::seconds(4));
+        assert_eq!(retransmitted, open);
+    }
+}
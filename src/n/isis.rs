@@ -0,0 +1,716 @@
+extern crate simplelog; //TODO check the paris feature flag for tags, useful?
+
+use std::{io::{Error, Write}, fmt, fs::File, net::{IpAddr, Ipv4Addr, Ipv6Addr}, collections::HashMap, path::PathBuf};
+
+// NOTE: Integrated IS-IS as per RFC 1195 (IPv4), RFC 5305 (extended IP reachability) and RFC 5308 (IPv6).
+// ISO 10589 IS-IS itself (adjacencies, LSP flooding, SPF) is not implemented yet - what is here are the
+// IP-related TLVs which are carried in the IS-IS Hello and LSP PDUs, plus the export of computed IP routes.
+// Route selection (compute_ip_routes()) and IpRouteExport are building blocks only, nothing calls them yet -
+// as soon as LSP database and SPF exist, they are supposed to feed them.
+
+/// ISO/TR 9577 NLPID of IS-IS
+pub const NETWORK_LAYER_PROTOCOL_IDENTIFIER_ISIS: u8 = 0x83;
+/// ISO/TR 9577 NLPIDs as used in the protocols supported TLV
+pub const NLPID_CLNP: u8 = 0x81;
+pub const NLPID_IPV4: u8 = 0xCC;
+pub const NLPID_IPV6: u8 = 0x8E;
+
+// TLV codes
+pub const TLV_IP_INTERNAL_REACHABILITY: u8 = 128;   // RFC 1195 5.1
+pub const TLV_PROTOCOLS_SUPPORTED: u8 = 129;        // RFC 1195 5.1
+pub const TLV_IP_EXTERNAL_REACHABILITY: u8 = 130;   // RFC 1195 5.1
+pub const TLV_IP_INTERFACE_ADDRESS: u8 = 132;       // RFC 1195 5.1
+pub const TLV_EXTENDED_IP_REACHABILITY: u8 = 135;   // RFC 5305 4
+pub const TLV_IPV6_INTERFACE_ADDRESS: u8 = 232;     // RFC 5308 3
+pub const TLV_IPV6_REACHABILITY: u8 = 236;          // RFC 5308 2
+
+// ISO 10589 9.5 - 9.8 PDU types
+pub const PDU_TYPE_L1_LAN_HELLO: u8 = 15;
+pub const PDU_TYPE_L2_LAN_HELLO: u8 = 16;
+pub const PDU_TYPE_P2P_HELLO: u8 = 17;
+pub const PDU_TYPE_L1_LSP: u8 = 18;
+pub const PDU_TYPE_L2_LSP: u8 = 20;
+
+/// octets of the fixed part of LAN Hello and LSP PDUs resp. point-to-point Hello PDUs, incl. the common header
+const LAN_HELLO_HEADER_LENGTH: usize = 27;
+const P2P_HELLO_HEADER_LENGTH: usize = 20;
+const LSP_HEADER_LENGTH: usize = 27;
+/// only the default ID length of 6 octets is supported
+const ID_LENGTH: usize = 6;
+
+/// maximum value of a narrow (6 bit) default metric, RFC 1195 and ISO 10589
+const NARROW_METRIC_MAX: u32 = 63;
+/// RFC 5305 4 - a prefix with a metric larger than this shall not be considered during SPF
+const WIDE_METRIC_MAX: u32 = 0xFE00_0000;
+/// ISO 10589 metric byte value for "this optional metric is not supported"
+const METRIC_UNSUPPORTED: u8 = 0b1000_0000;
+/// octets for one entry in TLV 128 and 130
+const NARROW_ENTRY_LENGTH: usize = 12;
+
+/// one TLV as found in the variable length part of IS-IS PDUs, same format as a CLNP options part parameter
+#[derive(Debug)]
+pub struct Tlv<'a> {
+    code: &'a u8,
+    length: &'a u8,
+    value: &'a [u8],
+}
+
+impl Tlv<'_> {
+    /// returns the TLV and its length in bytes incl. code and length octets
+    pub fn from_buf<'a>(buffer: &'a [u8]) -> Result<(Tlv<'a>, usize), Error> {
+        if buffer.len() < 2 {
+            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "given TLV buffer too short"));
+        }
+        let length = buffer[1] as usize;
+        if buffer.len() < 2 + length {
+            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "given TLV buffer too short to accomodate TLV value"));
+        }
+        return Ok((
+            Tlv { code: &buffer[0], length: &buffer[1], value: &buffer[2..2+length] },
+            2 + length
+        ));
+    }
+
+    /// decompose all TLVs found in the variable length part of an IS-IS PDU
+    pub fn all_from_buf<'a>(buffer: &'a [u8]) -> Result<Vec<Tlv<'a>>, Error> {
+        let mut tlvs = vec![];
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let (tlv, _) = Tlv::from_buf(&buffer[offset..])?;
+            offset += 2 + tlv.length();
+            tlvs.push(tlv);
+        }
+        return Ok(tlvs);
+    }
+
+    pub fn code(&self) -> u8 {
+        return *self.code;
+    }
+
+    /// length of the value
+    pub fn length(&self) -> usize {
+        return *self.length as usize;
+    }
+
+    pub fn value(&self) -> &[u8] {
+        return self.value;
+    }
+}
+
+/// fixed part of a Hello or LSP PDU, as far as needed to get at the TLVs in the variable length part
+#[derive(Debug, PartialEq)]
+pub struct PduHeader<'a> {
+    pub pdu_type: u8,
+    /// source ID of a Hello resp. LSP ID (system ID, pseudonode ID, LSP number) of an LSP
+    pub source_id: &'a [u8],
+    pub pdu_length: u16,
+}
+
+impl PduHeader<'_> {
+    /// returns the header and the variable length part, the latter cut to the PDU length
+    //TODO CSNP, PSNP
+    pub fn from_buf<'a>(buffer: &'a [u8]) -> Result<(PduHeader<'a>, &'a [u8]), Error> {
+        if buffer.len() < 8 {
+            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "given IS-IS PDU buffer too short for common header"));
+        }
+        if buffer[0] != NETWORK_LAYER_PROTOCOL_IDENTIFIER_ISIS {
+            return Err(Error::new(std::io::ErrorKind::InvalidData, "not an IS-IS PDU"));
+        }
+        if buffer[3] != 0 && buffer[3] as usize != ID_LENGTH {
+            return Err(Error::new(std::io::ErrorKind::Unsupported, "IS-IS ID length other than 6 octets"));
+        }
+        let pdu_type = buffer[4] & 0b0001_1111;
+        // offsets of source ID resp. LSP ID and of PDU length
+        let (header_length, source_id_offset, source_id_length, pdu_length_offset) = match pdu_type {
+            PDU_TYPE_L1_LAN_HELLO | PDU_TYPE_L2_LAN_HELLO => (LAN_HELLO_HEADER_LENGTH, 9, ID_LENGTH, 17),
+            PDU_TYPE_P2P_HELLO => (P2P_HELLO_HEADER_LENGTH, 9, ID_LENGTH, 17),
+            PDU_TYPE_L1_LSP | PDU_TYPE_L2_LSP => (LSP_HEADER_LENGTH, 12, ID_LENGTH + 2, 8),
+            _ => { return Err(Error::new(std::io::ErrorKind::Unsupported, "IS-IS PDU type not supported")); }
+        };
+        if buffer[1] as usize != header_length {
+            return Err(Error::new(std::io::ErrorKind::InvalidData, "IS-IS length indicator does not match PDU type"));
+        }
+        if buffer.len() < header_length {
+            return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "given IS-IS PDU buffer too short for fixed part"));
+        }
+        let pdu_length = u16::from_be_bytes([buffer[pdu_length_offset], buffer[pdu_length_offset+1]]);
+        if (pdu_length as usize) < header_length || pdu_length as usize > buffer.len() {
+            return Err(Error::new(std::io::ErrorKind::InvalidData, "IS-IS PDU length does not match given buffer"));
+        }
+        return Ok((
+            PduHeader { pdu_type: pdu_type, source_id: &buffer[source_id_offset..source_id_offset+source_id_length], pdu_length: pdu_length },
+            &buffer[header_length..pdu_length as usize]
+        ));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IpPrefix {
+    V4 { address: Ipv4Addr, prefix_length: u8 },
+    V6 { address: Ipv6Addr, prefix_length: u8 },
+}
+
+impl IpPrefix {
+    /// checks that the prefix length fits the address family, host bits are cleared
+    pub fn new(address: IpAddr, prefix_length: u8) -> Result<Self, Error> {
+        let prefix = match address {
+            IpAddr::V4(address) => IpPrefix::V4 { address: address, prefix_length: prefix_length },
+            IpAddr::V6(address) => IpPrefix::V6 { address: address, prefix_length: prefix_length },
+        };
+        return prefix.normalized();
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self {
+            IpPrefix::V4 { prefix_length, .. } => return *prefix_length <= 32,
+            IpPrefix::V6 { prefix_length, .. } => return *prefix_length <= 128,
+        }
+    }
+
+    /// number of octets covered by the prefix, the last one possibly only partly
+    pub fn octets(&self) -> usize {
+        match self {
+            IpPrefix::V4 { prefix_length, .. } | IpPrefix::V6 { prefix_length, .. } => return (*prefix_length as usize).div_ceil(8),
+        }
+    }
+
+    /// clear host bits, so that equal prefixes compare equal
+    pub fn normalized(&self) -> Result<IpPrefix, Error> {
+        if !self.is_valid() {
+            return Err(Error::new(std::io::ErrorKind::InvalidInput, format!("IP prefix length of {} longer than the address", self)));
+        }
+        match self {
+            IpPrefix::V4 { address, prefix_length } => {
+                let mask = if *prefix_length == 0 { 0 } else { u32::MAX << (32 - *prefix_length as u32) };
+                return Ok(IpPrefix::V4 { address: Ipv4Addr::from(u32::from(*address) & mask), prefix_length: *prefix_length });
+            },
+            IpPrefix::V6 { address, prefix_length } => {
+                let mask = if *prefix_length == 0 { 0 } else { u128::MAX << (128 - *prefix_length as u32) };
+                return Ok(IpPrefix::V6 { address: Ipv6Addr::from(u128::from(*address) & mask), prefix_length: *prefix_length });
+            },
+        }
+    }
+}
+
+impl fmt::Display for IpPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpPrefix::V4 { address, prefix_length } => write!(f, "{}/{}", address, prefix_length),
+            IpPrefix::V6 { address, prefix_length } => write!(f, "{}/{}", address, prefix_length),
+        }
+    }
+}
+
+/// one reachable IP prefix as announced in TLV 128, 130, 135 or 236
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IpReachability {
+    pub prefix: IpPrefix,
+    /// default metric - narrow TLVs (128, 130) only carry 6 bits of it
+    pub metric: u32,
+    /// external to the IS-IS domain - TLV 130 resp. the external bit of TLV 236
+    pub external: bool,
+    /// RFC 1195 I/E bit of the narrow default metric - the metric is of external type, not comparable to internal metrics
+    pub external_metric: bool,
+    /// RFC 2966 up/down bit - prefix was leaked from level 2 down into level 1
+    pub up_down: bool,
+}
+
+impl IpReachability {
+    /// compose TLV 128 or 130 (RFC 1195 narrow metrics), splitting into multiple TLVs if needed
+    /// returns number of bytes written
+    pub fn compose_narrow(entries: &[IpReachability], buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut bytes = 0;
+        for external in [false, true] {
+            let selected: Vec<&IpReachability> = entries.iter()
+                .filter(|entry| entry.external == external && matches!(entry.prefix, IpPrefix::V4 { .. }))
+                .collect();
+            //TODO optimize
+            for chunk in selected.chunks(255 / NARROW_ENTRY_LENGTH) {
+                let tlv_length = chunk.len() * NARROW_ENTRY_LENGTH;
+                if buffer.len() < bytes + 2 + tlv_length {
+                    return Err(Error::new(std::io::ErrorKind::WriteZero, "given buffer too short for IP reachability TLV"));
+                }
+                buffer[bytes] = if external { TLV_IP_EXTERNAL_REACHABILITY } else { TLV_IP_INTERNAL_REACHABILITY };
+                buffer[bytes+1] = tlv_length as u8;
+                bytes += 2;
+                for entry in chunk {
+                    let IpPrefix::V4 { address, prefix_length } = entry.prefix.normalized()? else { unreachable!() };
+                    // default metric: bit 8 up/down (RFC 2966), bit 7 I/E metric type, 6 bits metric
+                    buffer[bytes] = (if entry.up_down { 0b1000_0000 } else { 0 })
+                        | (if entry.external_metric { 0b0100_0000 } else { 0 })
+                        | (entry.metric.min(NARROW_METRIC_MAX) as u8);
+                    buffer[bytes+1] = METRIC_UNSUPPORTED;   // delay metric
+                    buffer[bytes+2] = METRIC_UNSUPPORTED;   // expense metric
+                    buffer[bytes+3] = METRIC_UNSUPPORTED;   // error metric
+                    buffer[bytes+4..bytes+8].copy_from_slice(&address.octets());
+                    let mask = if prefix_length == 0 { 0 } else { u32::MAX << (32 - prefix_length as u32) };
+                    buffer[bytes+8..bytes+12].copy_from_slice(&mask.to_be_bytes());
+                    bytes += NARROW_ENTRY_LENGTH;
+                }
+            }
+        }
+        return Ok(bytes);
+    }
+
+    /// decompose value of TLV 128 or 130
+    pub fn decompose_narrow(tlv: &Tlv) -> Result<Vec<IpReachability>, Error> {
+        if tlv.value.len() % NARROW_ENTRY_LENGTH != 0 {
+            return Err(Error::new(std::io::ErrorKind::InvalidData, "IP reachability TLV length not a multiple of 12"));
+        }
+        let mut entries = vec![];
+        for entry in tlv.value.chunks(NARROW_ENTRY_LENGTH) {
+            let mask = u32::from_be_bytes([entry[8], entry[9], entry[10], entry[11]]);
+            if mask.leading_ones() + mask.trailing_zeros() != 32 {
+                //NOTE: RFC 1195 allows non-contiguous masks, but RFC 1812 routing does not know what to do with them
+                warn!("ignoring IP reachability entry with non-contiguous subnet mask {:08x}", mask);
+                continue;
+            }
+            entries.push(IpReachability {
+                prefix: IpPrefix::V4 {
+                    address: Ipv4Addr::new(entry[4], entry[5], entry[6], entry[7]),
+                    prefix_length: mask.leading_ones() as u8,
+                }.normalized()?,
+                metric: (entry[0] & 0b0011_1111) as u32,
+                external: *tlv.code == TLV_IP_EXTERNAL_REACHABILITY,
+                external_metric: (entry[0] & 0b0100_0000) != 0,
+                up_down: (entry[0] & 0b1000_0000) != 0,
+            });
+        }
+        return Ok(entries);
+    }
+
+    /// compose TLV 135 (IPv4 prefixes) and TLV 236 (IPv6 prefixes) with wide metrics, splitting into multiple TLVs if needed
+    /// returns number of bytes written
+    pub fn compose_wide(entries: &[IpReachability], buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut bytes = 0;
+        let mut tlv_start: Option<usize> = None;
+        let mut tlv_code = 0;
+        for entry in entries {
+            let prefix = entry.prefix.normalized()?;
+            let (code, entry_length) = match prefix {
+                IpPrefix::V4 { .. } => (TLV_EXTENDED_IP_REACHABILITY, 4 + 1 + prefix.octets()),
+                IpPrefix::V6 { .. } => (TLV_IPV6_REACHABILITY, 4 + 1 + 1 + prefix.octets()),
+            };
+            // start a new TLV if the code changes or the current one is full
+            if tlv_start.is_none() || tlv_code != code || buffer[tlv_start.unwrap() + 1] as usize + entry_length > 255 {
+                if buffer.len() < bytes + 2 {
+                    return Err(Error::new(std::io::ErrorKind::WriteZero, "given buffer too short for IP reachability TLV"));
+                }
+                buffer[bytes] = code;
+                buffer[bytes+1] = 0;
+                tlv_start = Some(bytes);
+                tlv_code = code;
+                bytes += 2;
+            }
+            if buffer.len() < bytes + entry_length {
+                return Err(Error::new(std::io::ErrorKind::WriteZero, "given buffer too short for IP reachability TLV"));
+            }
+            buffer[bytes..bytes+4].copy_from_slice(&entry.metric.min(WIDE_METRIC_MAX).to_be_bytes());
+            match prefix {
+                IpPrefix::V4 { address, prefix_length } => {
+                    // control octet: bit 8 up/down, bit 7 sub-TLVs present (never), 6 bits prefix length
+                    buffer[bytes+4] = (if entry.up_down { 0b1000_0000 } else { 0 }) | prefix_length;
+                    let prefix_bytes = entry_length - 5;
+                    buffer[bytes+5..bytes+5+prefix_bytes].copy_from_slice(&address.octets()[0..prefix_bytes]);
+                },
+                IpPrefix::V6 { address, prefix_length } => {
+                    // control octet: bit 8 up/down, bit 7 external, bit 6 sub-TLVs present (never)
+                    buffer[bytes+4] = (if entry.up_down { 0b1000_0000 } else { 0 }) | (if entry.external { 0b0100_0000 } else { 0 });
+                    buffer[bytes+5] = prefix_length;
+                    let prefix_bytes = entry_length - 6;
+                    buffer[bytes+6..bytes+6+prefix_bytes].copy_from_slice(&address.octets()[0..prefix_bytes]);
+                },
+            }
+            buffer[tlv_start.unwrap() + 1] += entry_length as u8;
+            bytes += entry_length;
+        }
+        return Ok(bytes);
+    }
+
+    /// decompose value of TLV 135 or 236
+    pub fn decompose_wide(tlv: &Tlv) -> Result<Vec<IpReachability>, Error> {
+        let mut entries = vec![];
+        let mut offset = 0;
+        let value = tlv.value;
+        while offset < value.len() {
+            if value.len() < offset + 5 {
+                return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "wide IP reachability entry too short"));
+            }
+            let metric = u32::from_be_bytes([value[offset], value[offset+1], value[offset+2], value[offset+3]]);
+            let control = value[offset+4];
+            let up_down = (control & 0b1000_0000) != 0;
+            let (prefix, external, sub_tlvs_present, header_length) = match *tlv.code {
+                TLV_EXTENDED_IP_REACHABILITY => {
+                    let prefix_length = control & 0b0011_1111;
+                    if prefix_length > 32 {
+                        return Err(Error::new(std::io::ErrorKind::InvalidData, "extended IP reachability prefix length > 32"));
+                    }
+                    let prefix_bytes = (prefix_length as usize).div_ceil(8);
+                    if value.len() < offset + 5 + prefix_bytes {
+                        return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "extended IP reachability prefix too short"));
+                    }
+                    let mut octets = [0u8; 4];
+                    octets[0..prefix_bytes].copy_from_slice(&value[offset+5..offset+5+prefix_bytes]);
+                    (IpPrefix::V4 { address: Ipv4Addr::from(octets), prefix_length: prefix_length }, false, (control & 0b0100_0000) != 0, 5 + prefix_bytes)
+                },
+                TLV_IPV6_REACHABILITY => {
+                    if value.len() < offset + 6 {
+                        return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "IPv6 reachability entry too short"));
+                    }
+                    let prefix_length = value[offset+5];
+                    if prefix_length > 128 {
+                        return Err(Error::new(std::io::ErrorKind::InvalidData, "IPv6 reachability prefix length > 128"));
+                    }
+                    let prefix_bytes = (prefix_length as usize).div_ceil(8);
+                    if value.len() < offset + 6 + prefix_bytes {
+                        return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "IPv6 reachability prefix too short"));
+                    }
+                    let mut octets = [0u8; 16];
+                    octets[0..prefix_bytes].copy_from_slice(&value[offset+6..offset+6+prefix_bytes]);
+                    (IpPrefix::V6 { address: Ipv6Addr::from(octets), prefix_length: prefix_length }, (control & 0b0100_0000) != 0, (control & 0b0010_0000) != 0, 6 + prefix_bytes)
+                },
+                _ => {
+                    return Err(Error::new(std::io::ErrorKind::InvalidInput, "not a wide IP reachability TLV"));
+                }
+            };
+            offset += header_length;
+            if sub_tlvs_present {
+                // skip sub-TLVs, none of them are supported yet
+                if value.len() < offset + 1 || value.len() < offset + 1 + value[offset] as usize {
+                    return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "IP reachability sub-TLVs too short"));
+                }
+                offset += 1 + value[offset] as usize;
+            }
+            if metric > WIDE_METRIC_MAX {
+                // RFC 5305 4 - shall not be used for SPF
+                debug!("ignoring IP reachability entry for {} with metric above maximum", prefix);
+                continue;
+            }
+            entries.push(IpReachability { prefix: prefix.normalized()?, metric: metric, external: external, external_metric: false, up_down: up_down });
+        }
+        return Ok(entries);
+    }
+
+    /// decompose all IP reachability information from the TLVs of an LSP
+    pub fn all_from_tlvs(tlvs: &[Tlv]) -> Result<Vec<IpReachability>, Error> {
+        let mut entries = vec![];
+        for tlv in tlvs {
+            match *tlv.code {
+                TLV_IP_INTERNAL_REACHABILITY | TLV_IP_EXTERNAL_REACHABILITY => { entries.append(&mut IpReachability::decompose_narrow(tlv)?); },
+                TLV_EXTENDED_IP_REACHABILITY | TLV_IPV6_REACHABILITY => { entries.append(&mut IpReachability::decompose_wide(tlv)?); },
+                _ => {}
+            }
+        }
+        return Ok(entries);
+    }
+}
+
+/// compose TLV 129 protocols supported
+/// returns number of bytes written
+pub fn compose_protocols_supported(nlpids: &[u8], buffer: &mut [u8]) -> Result<usize, Error> {
+    if nlpids.len() > 255 || buffer.len() < 2 + nlpids.len() {
+        return Err(Error::new(std::io::ErrorKind::WriteZero, "given buffer too short for protocols supported TLV"));
+    }
+    buffer[0] = TLV_PROTOCOLS_SUPPORTED;
+    buffer[1] = nlpids.len() as u8;
+    buffer[2..2+nlpids.len()].copy_from_slice(nlpids);
+    return Ok(2 + nlpids.len());
+}
+
+/// decompose TLV 129 protocols supported, returns the NLPIDs
+pub fn decompose_protocols_supported<'a>(tlv: &Tlv<'a>) -> &'a [u8] {
+    return tlv.value;
+}
+
+/// compose TLV 132 resp. TLV 232 interface addresses
+/// returns number of bytes written
+pub fn compose_interface_addresses(ipv4: &[Ipv4Addr], ipv6: &[Ipv6Addr], buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut bytes = 0;
+    //TODO optimize - duplicated for both address families
+    for chunk in ipv4.chunks(255 / 4) {
+        if buffer.len() < bytes + 2 + chunk.len() * 4 {
+            return Err(Error::new(std::io::ErrorKind::WriteZero, "given buffer too short for IP interface address TLV"));
+        }
+        buffer[bytes] = TLV_IP_INTERFACE_ADDRESS;
+        buffer[bytes+1] = (chunk.len() * 4) as u8;
+        bytes += 2;
+        for address in chunk {
+            buffer[bytes..bytes+4].copy_from_slice(&address.octets());
+            bytes += 4;
+        }
+    }
+    for chunk in ipv6.chunks(255 / 16) {
+        if buffer.len() < bytes + 2 + chunk.len() * 16 {
+            return Err(Error::new(std::io::ErrorKind::WriteZero, "given buffer too short for IPv6 interface address TLV"));
+        }
+        buffer[bytes] = TLV_IPV6_INTERFACE_ADDRESS;
+        buffer[bytes+1] = (chunk.len() * 16) as u8;
+        bytes += 2;
+        for address in chunk {
+            buffer[bytes..bytes+16].copy_from_slice(&address.octets());
+            bytes += 16;
+        }
+    }
+    return Ok(bytes);
+}
+
+/// a computed IP route, ready for export
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IpRoute {
+    pub prefix: IpPrefix,
+    /// total cost = path cost to the announcing system + announced metric
+    pub metric: u32,
+    /// system ID of the IS which announced the prefix
+    pub announced_by: Vec<u8>,
+    /// IP address of the next hop, as learned via the interface address TLVs of the adjacency
+    pub next_hop: Option<IpAddr>,
+    pub external: bool,
+    pub external_metric: bool,
+}
+
+/// what SPF has to give per reachable system: path cost to it, next hop and the IP reachability from its LSPs
+pub struct ReachableSystem<'a> {
+    pub system_id: &'a [u8],
+    pub path_cost: u32,
+    pub next_hop: Option<IpAddr>,
+    pub reachability: &'a [IpReachability],
+}
+
+/// RFC 1195 C.1.4 route selection: internal routes before external routes, internal metric type before external metric type,
+/// then lowest total metric
+//TODO ECMP - currently only the first best path is kept
+pub fn compute_ip_routes(systems: &[ReachableSystem]) -> Vec<IpRoute> {
+    let mut best: HashMap<IpPrefix, IpRoute> = HashMap::new();
+    for system in systems {
+        for entry in system.reachability {
+            let Ok(prefix) = entry.prefix.normalized() else {
+                warn!("ignoring IP reachability entry with invalid prefix {} from {:02x?}", entry.prefix, system.system_id);
+                continue;
+            };
+            let candidate = IpRoute {
+                prefix: prefix,
+                metric: system.path_cost.saturating_add(entry.metric),
+                announced_by: system.system_id.to_vec(),
+                next_hop: system.next_hop,
+                external: entry.external,
+                external_metric: entry.external_metric,
+            };
+            match best.get(&candidate.prefix) {
+                Some(existing) if (existing.external, existing.external_metric, existing.metric) <= (candidate.external, candidate.external_metric, candidate.metric) => {},
+                _ => { best.insert(candidate.prefix, candidate); }
+            }
+        }
+    }
+    let mut routes: Vec<IpRoute> = best.into_values().collect();
+    routes.sort_by_key(|route| route.prefix.to_string());   //TODO optimize - only for stable output
+    return routes;
+}
+
+/// where computed IP routes go, called after each SPF run with the complete set of routes
+pub trait IpRouteExport {
+    fn export(&mut self, routes: &[IpRoute]) -> Result<(), Error>;
+}
+
+/// writes the routes into a file, one route per line:  prefix metric next-hop
+/// the file is replaced atomically so that readers never see a half-written table
+pub struct FileRouteExport {
+    path: PathBuf,
+}
+
+impl FileRouteExport {
+    pub fn new(path: PathBuf) -> Self {
+        FileRouteExport { path: path }
+    }
+}
+
+impl IpRouteExport for FileRouteExport {
+    fn export(&mut self, routes: &[IpRoute]) -> Result<(), Error> {
+        let mut path_tmp = self.path.clone().into_os_string();
+        path_tmp.push(".tmp");
+        let mut file = File::create(&path_tmp)?;
+        for route in routes {
+            writeln!(file, "{} {} {}{}",
+                route.prefix,
+                route.metric,
+                route.next_hop.map_or("-".to_string(), |next_hop| next_hop.to_string()),
+                if route.external { " external" } else { "" }
+            )?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&path_tmp, &self.path)?;
+        debug!("exported {} IP routes into {:?}", routes.len(), self.path);
+        return Ok(());
+    }
+}
+
+/// hands the routes to a closure, for example to install them via netlink or into a different routing daemon
+//TODO add netlink export directly, but that needs a netlink crate and is Linux-only
+pub struct CallbackRouteExport<F: FnMut(&[IpRoute]) -> Result<(), Error>> {
+    callback: F,
+}
+
+impl<F: FnMut(&[IpRoute]) -> Result<(), Error>> CallbackRouteExport<F> {
+    pub fn new(callback: F) -> Self {
+        CallbackRouteExport { callback: callback }
+    }
+}
+
+impl<F: FnMut(&[IpRoute]) -> Result<(), Error>> IpRouteExport for CallbackRouteExport<F> {
+    fn export(&mut self, routes: &[IpRoute]) -> Result<(), Error> {
+        return (self.callback)(routes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// common header and fixed part of a level 1 LAN Hello from system 0000.0000.0001 with the given TLVs
+    fn lan_hello(tlvs: &[u8]) -> Vec<u8> {
+        let mut pdu = vec![NETWORK_LAYER_PROTOCOL_IDENTIFIER_ISIS, LAN_HELLO_HEADER_LENGTH as u8, 1, 0, PDU_TYPE_L1_LAN_HELLO, 1, 0, 0];
+        pdu.push(0x01);                                 // circuit type
+        pdu.extend_from_slice(&[0, 0, 0, 0, 0, 1]);     // source ID
+        pdu.extend_from_slice(&30u16.to_be_bytes());    // holding time
+        pdu.extend_from_slice(&((LAN_HELLO_HEADER_LENGTH + tlvs.len()) as u16).to_be_bytes());
+        pdu.push(64);                                   // priority
+        pdu.extend_from_slice(&[0, 0, 0, 0, 0, 1, 1]);  // LAN ID
+        pdu.extend_from_slice(tlvs);
+        return pdu;
+    }
+
+    /// common header and fixed part of a level 2 LSP 0000.0000.0002.00-00 with the given TLVs
+    fn lsp(tlvs: &[u8]) -> Vec<u8> {
+        let mut pdu = vec![NETWORK_LAYER_PROTOCOL_IDENTIFIER_ISIS, LSP_HEADER_LENGTH as u8, 1, 0, PDU_TYPE_L2_LSP, 1, 0, 0];
+        pdu.extend_from_slice(&((LSP_HEADER_LENGTH + tlvs.len()) as u16).to_be_bytes());
+        pdu.extend_from_slice(&1200u16.to_be_bytes());  // remaining lifetime
+        pdu.extend_from_slice(&[0, 0, 0, 0, 0, 2, 0, 0]);   // LSP ID
+        pdu.extend_from_slice(&1u32.to_be_bytes());     // sequence number
+        pdu.extend_from_slice(&[0, 0]);                 // checksum
+        pdu.push(0x03);                                 // P, ATT, OL, IS type
+        pdu.extend_from_slice(tlvs);
+        return pdu;
+    }
+
+    #[test]
+    fn hello_decode() {
+        let mut tlvs = vec![0u8; 64];
+        let mut length = compose_protocols_supported(&[NLPID_CLNP, NLPID_IPV4, NLPID_IPV6], &mut tlvs).unwrap();
+        length += compose_interface_addresses(&[Ipv4Addr::new(192, 0, 2, 1)], &[], &mut tlvs[length..]).unwrap();
+        let pdu = lan_hello(&tlvs[0..length]);
+
+        let (header, variable_part) = PduHeader::from_buf(&pdu).unwrap();
+        assert_eq!(header.pdu_type, PDU_TYPE_L1_LAN_HELLO);
+        assert_eq!(header.source_id, &[0, 0, 0, 0, 0, 1]);
+        assert_eq!(header.pdu_length as usize, pdu.len());
+        let tlvs = Tlv::all_from_buf(variable_part).unwrap();
+        assert_eq!(tlvs.len(), 2);
+        assert_eq!(tlvs[0].code(), TLV_PROTOCOLS_SUPPORTED);
+        assert_eq!(tlvs[0].length(), 3);
+        assert_eq!(decompose_protocols_supported(&tlvs[0]), &[NLPID_CLNP, NLPID_IPV4, NLPID_IPV6]);
+        assert_eq!(tlvs[1].code(), TLV_IP_INTERFACE_ADDRESS);
+        assert_eq!(tlvs[1].value(), &[192, 0, 2, 1]);
+    }
+
+    #[test]
+    fn lsp_decode() {
+        let entries = vec![
+            IpReachability { prefix: IpPrefix::V4 { address: Ipv4Addr::new(10, 1, 0, 0), prefix_length: 16 }, metric: 10, external: false, external_metric: false, up_down: false },
+            IpReachability { prefix: IpPrefix::V4 { address: Ipv4Addr::new(198, 51, 100, 0), prefix_length: 24 }, metric: 20, external: true, external_metric: true, up_down: false },
+        ];
+        let wide = vec![
+            IpReachability { prefix: IpPrefix::V4 { address: Ipv4Addr::new(10, 2, 2, 0), prefix_length: 23 }, metric: 100_000, external: false, external_metric: false, up_down: true },
+            IpReachability { prefix: IpPrefix::V6 { address: "2001:db8::".parse().unwrap(), prefix_length: 32 }, metric: 5, external: true, external_metric: false, up_down: false },
+        ];
+        let mut tlvs = vec![0u8; 256];
+        let mut length = IpReachability::compose_narrow(&entries, &mut tlvs).unwrap();
+        length += IpReachability::compose_wide(&wide, &mut tlvs[length..]).unwrap();
+        let pdu = lsp(&tlvs[0..length]);
+
+        let (header, variable_part) = PduHeader::from_buf(&pdu).unwrap();
+        assert_eq!(header.pdu_type, PDU_TYPE_L2_LSP);
+        assert_eq!(header.source_id, &[0, 0, 0, 0, 0, 2, 0, 0]);
+        let tlvs = Tlv::all_from_buf(variable_part).unwrap();
+        assert_eq!(tlvs.iter().map(|tlv| tlv.code()).collect::<Vec<u8>>(),
+            vec![TLV_IP_INTERNAL_REACHABILITY, TLV_IP_EXTERNAL_REACHABILITY, TLV_EXTENDED_IP_REACHABILITY, TLV_IPV6_REACHABILITY]);
+        let mut expected = entries.clone();
+        expected.extend(wide.iter().cloned());
+        assert_eq!(IpReachability::all_from_tlvs(&tlvs).unwrap(), expected);
+    }
+
+    #[test]
+    fn narrow_metric_type_not_external() {
+        // internal reachability with the I/E bit set - an internal route with a metric of external type
+        let value = [0b0100_0000 | 10, METRIC_UNSUPPORTED, METRIC_UNSUPPORTED, METRIC_UNSUPPORTED, 10, 1, 0, 0, 255, 255, 0, 0];
+        let mut buffer = vec![TLV_IP_INTERNAL_REACHABILITY, value.len() as u8];
+        buffer.extend_from_slice(&value);
+        let (tlv, _) = Tlv::from_buf(&buffer).unwrap();
+        let entries = IpReachability::decompose_narrow(&tlv).unwrap();
+        assert_eq!(entries, vec![IpReachability { prefix: IpPrefix::new("10.1.0.0".parse().unwrap(), 16).unwrap(), metric: 10, external: false, external_metric: true, up_down: false }]);
+        // and composed the same way again
+        let mut composed = vec![0u8; 16];
+        let length = IpReachability::compose_narrow(&entries, &mut composed).unwrap();
+        assert_eq!(&composed[0..length], &buffer[..]);
+    }
+
+    #[test]
+    fn prefix_length_checked() {
+        assert_eq!(IpPrefix::new("10.1.2.3".parse().unwrap(), 16).unwrap(), IpPrefix::V4 { address: Ipv4Addr::new(10, 1, 0, 0), prefix_length: 16 });
+        assert_eq!(IpPrefix::new("10.1.2.3".parse().unwrap(), 33).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert!(IpPrefix::new("2001:db8::".parse().unwrap(), 128).is_ok());
+        assert!(IpPrefix::new("2001:db8::".parse().unwrap(), 129).is_err());
+        assert_eq!(IpPrefix::new("2001:db8::".parse().unwrap(), 32).unwrap().to_string(), "2001:db8::/32");
+        // constructed directly, composing does not panic either
+        let invalid = IpReachability { prefix: IpPrefix::V4 { address: Ipv4Addr::new(10, 1, 0, 0), prefix_length: 40 }, metric: 1, external: false, external_metric: false, up_down: false };
+        let mut buffer = vec![0u8; 64];
+        assert!(IpReachability::compose_wide(std::slice::from_ref(&invalid), &mut buffer).is_err());
+        assert!(IpReachability::compose_narrow(std::slice::from_ref(&invalid), &mut buffer).is_err());
+        // and route selection skips it
+        let system = ReachableSystem { system_id: &[0, 0, 0, 0, 0, 2], path_cost: 10, next_hop: None, reachability: std::slice::from_ref(&invalid) };
+        assert!(compute_ip_routes(&[system]).is_empty());
+    }
+
+    #[test]
+    fn route_selection() {
+        let prefix = IpPrefix::new("10.1.0.0".parse().unwrap(), 16).unwrap();
+        let entry = |metric: u32, external: bool, external_metric: bool| IpReachability { prefix: prefix, metric: metric, external: external, external_metric: external_metric, up_down: false };
+        let internal_far = [entry(50, false, false)];
+        let internal_external_metric = [entry(1, false, true)];
+        let external_near = [entry(1, true, false)];
+        let systems = [
+            ReachableSystem { system_id: &[0, 0, 0, 0, 0, 1], path_cost: 1, next_hop: None, reachability: &external_near },
+            ReachableSystem { system_id: &[0, 0, 0, 0, 0, 2], path_cost: 1, next_hop: None, reachability: &internal_external_metric },
+            ReachableSystem { system_id: &[0, 0, 0, 0, 0, 3], path_cost: 10, next_hop: None, reachability: &internal_far },
+        ];
+        let routes = compute_ip_routes(&systems);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].announced_by, vec![0, 0, 0, 0, 0, 3]);
+        assert_eq!(routes[0].metric, 60);
+        assert_eq!(compute_ip_routes(&systems[0..2])[0].announced_by, vec![0, 0, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn pdu_length_checked() {
+        let mut tlvs = vec![0u8; 16];
+        let length = compose_protocols_supported(&[NLPID_IPV4], &mut tlvs).unwrap();
+        let pdu = lan_hello(&tlvs[0..length]);
+        // PDU length beyond the buffer
+        assert_eq!(PduHeader::from_buf(&pdu[0..pdu.len()-1]).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        // trailing octets after PDU length are not part of the variable length part
+        let mut padded = pdu.clone();
+        padded.extend_from_slice(&[0, 0, 0]);
+        assert_eq!(PduHeader::from_buf(&padded).unwrap().1, &tlvs[0..length]);
+        // PDU length shorter than the fixed part
+        let mut short = pdu.clone();
+        short[17..19].copy_from_slice(&10u16.to_be_bytes());
+        assert!(PduHeader::from_buf(&short).is_err());
+        // length indicator not matching the PDU type
+        let mut wrong = pdu.clone();
+        wrong[1] = LSP_HEADER_LENGTH as u8 + 1;
+        assert!(PduHeader::from_buf(&wrong).is_err());
+        // TLV length running past the variable length part
+        let mut truncated = pdu.clone();
+        truncated[LAN_HELLO_HEADER_LENGTH + 1] = 2;
+        let (_, variable_part) = PduHeader::from_buf(&truncated).unwrap();
+        assert_eq!(Tlv::all_from_buf(variable_part).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{fmt, fs, io::{Error, ErrorKind, Read, Write}, os::unix::net::{UnixListener, UnixStream}, path::{Path, PathBuf}, sync::{Arc, Mutex}, thread::{self, JoinHandle}};
+
+use super::{NetworkService, ManagementInformation};
+
+// NOTE: there is no protocol server yet which applications connect to (see doc 05 "xx"), every application brings up
+// its own stack instance. So each instance answers on a Unix domain socket with what it has learned, for osinetstat.
+
+/// where the stack instance with the given network entity title answers
+pub fn socket_path(network_entity_title: &str) -> PathBuf {
+    return std::env::temp_dir().join(format!("osistack-{}.sock", network_entity_title));
+}
+
+/// answer each connection on the socket with the current management information as text, then close it
+pub fn serve<N: NetworkService<'static> + Send + 'static>(ns: Arc<Mutex<N>>, path: &Path) -> Result<JoinHandle<()>, Error> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(Error::new(ErrorKind::AddrInUse, "another stack instance is answering on the management socket"));
+        }
+        // left behind by an instance which did not shut down cleanly
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("management information available on {}", path.display());
+    return thread::Builder::new().name("N management".to_string()).spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => { warn!("management socket: accept failed: {}", e); continue; }
+            };
+            let management_information = ns.lock().expect("failed to lock ns").management_information();
+            if let Err(e) = write!(stream, "{}", management_information) {
+                debug!("management socket: client went away: {}", e);
+            }
+        }
+    });
+}
+
+/// ask the stack instance answering on the given socket for its management information
+pub fn query(path: &Path) -> Result<String, Error> {
+    let mut stream = UnixStream::connect(path)?;
+    let mut report = String::new();
+    stream.read_to_string(&mut report)?;
+    return Ok(report);
+}
+
+impl fmt::Display for ManagementInformation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Network entity title: {}", self.network_entity_title)?;
+        writeln!(f)?;
+
+        writeln!(f, "Subnetworks")?;
+        writeln!(f, "  Index SNPA                Max SDU State Encapsulation")?;
+        for (index, subnetwork) in self.subnetworks.iter().enumerate() {
+            writeln!(f, "  {:<5} {:<18} {:>8} {:<5} {:?}", index, subnetwork.snpa.to_string(), subnetwork.maximum_sdu_size, if subnetwork.up { "up" } else { "down" }, subnetwork.encapsulation)?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "Serviced NSAPs")?;
+        for nsap in &self.serviced_nsaps {
+            writeln!(f, "  {}", nsap.to_string())?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "Routing table")?;
+        writeln!(f, "  Destination                                Next hop                                     Metric Source")?;
+        for entry in &self.forwarding_table {
+            writeln!(f, "  {:<42} {:<42} {:>8} {:?}", entry.destination.to_string(), hex(&entry.next_hop), entry.metric, entry.source)?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "Adjacencies")?;
+        writeln!(f, "  Neighbour                                  SNPA               Subnetwork Type                 Source  Expires")?;
+        for adjacency in &self.adjacencies {
+            writeln!(f, "  {:<42} {:<18} {:<10} {:<20} {:<7} {}", hex(&adjacency.neighbour), adjacency.snpa.to_string(), adjacency.subnetwork, format!("{:?}", adjacency.neighbour_type), format!("{:?}", adjacency.source), adjacency.expires.to_rfc3339())?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "NSAP to SNPA cache")?;
+        writeln!(f, "  System title         NSAP                                       SNPA               Subnetwork")?;
+        for entry in &self.snpa_cache {
+            writeln!(f, "  {:<20} {:<42} {:<18} {}", entry.system_title, entry.nsap.to_string(), entry.snpa.to_string(), entry.subnetwork.map_or("-".to_string(), |subnetwork| subnetwork.to_string()))?;
+        }
+        return Ok(());
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    //TODO optimize
+    bytes.iter().map(|octet| format!("{:02x}", octet)).collect::<Vec<String>>().join("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+    use advmac::MacAddr6;
+    use crate::n::{clnp, Adjacency, NeighbourType, RouteSource, SubnetworkAttachment};
+
+    #[test]
+    fn serve_and_query() {
+        let subnetwork = SubnetworkAttachment { snpa: MacAddr6::new([0x02, 0, 0, 0, 0, 0x01]), maximum_sdu_size: 1497, encapsulation: crate::dl::Encapsulation::EthernetII, up: true };
+        let bands = (0..crate::dl::PRIORITY_BANDS).map(|_| rtrb::RingBuffer::new(8).0).collect();
+        let mut ns: clnp::Service<'static> = NetworkService::new(
+            "management-test",
+            vec![subnetwork],
+            vec![bands],
+            vec![Arc::new(Mutex::new(None))],
+            vec![Arc::new(Mutex::new(vec![]))],
+            vec![rtrb::RingBuffer::new(8).1],
+            vec![rtrb::RingBuffer::new(8).1],
+        );
+        ns.add_known_host("far".to_owned(), "02:00:00:00:01:02", 0);
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+        for (neighbour, expires) in [(vec![0x0a], expired), (vec![0x0b], Utc::now() + chrono::Duration::seconds(30))] {
+            ns.adjacencies().lock().unwrap().push(Adjacency {
+                source: RouteSource::EsIs,
+                neighbour_type: NeighbourType::EndSystem,
+                neighbour: neighbour,
+                snpa: MacAddr6::new([0x02, 0, 0, 0, 1, 0x02]),
+                subnetwork: 0,
+                expires: expires,
+            });
+        }
+        let ns = Arc::new(Mutex::new(ns));
+        let path = socket_path(&format!("management-test-{}", std::process::id()));
+        serve(ns.clone(), &path).unwrap();
+        assert_eq!(serve(ns.clone(), &path).unwrap_err().kind(), ErrorKind::AddrInUse);
+
+        let report = query(&path).unwrap();
+        assert!(report.starts_with("Network entity title: management-test\n"));
+        assert!(report.contains("  far "));
+        // expired adjacencies are not reported, but only the maintenance thread removes them
+        assert!(!report.contains("\n  0a "));
+        assert!(report.contains("\n  0b "));
+        assert_eq!(ns.lock().unwrap().adjacencies().lock().unwrap().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1288 @@
+pub mod ppdu;
+
+use std::{collections::{HashMap, HashSet}, io::{Error, ErrorKind}, sync::{Arc, Mutex}, thread::{self, Thread}, time::Duration};
+
+use crate::asn1::{ber, Any, Asn1, Element, EncodingRules, ObjectIdentifier, OctetString};
+use crate::session::{self, FunctionalUnits, SIndication, SessionAddress, SessionService};
+use ppdu::{AbortPpdu, ArpPpdu, AruPpdu, ContextDefinition, ContextIdentifier, ContextResult, CpNormalModeParameters, CpPpdu, CpaNormalModeParameters, CpaPpdu, CprPpdu, DefaultContextName, ModeSelector, PdvList, PresentationDataValues, UserData};
+
+/// how long the reader thread waits for the PS user to make room in its queue
+const BACKPRESSURE_INTERVAL: Duration = Duration::from_millis(10);
+
+// X.690 transfer syntax names
+const BASIC_ENCODING: [u32; 3] = [2, 1, 1];
+const CANONICAL_ENCODING: [u32; 4] = [2, 1, 2, 0];
+const DISTINGUISHED_ENCODING: [u32; 4] = [2, 1, 2, 1];
+
+pub fn transfer_syntax_name(rules: EncodingRules) -> ObjectIdentifier {
+    let arcs: &[u32] = match rules {
+        EncodingRules::Ber => &BASIC_ENCODING,
+        EncodingRules::Cer => &CANONICAL_ENCODING,
+        EncodingRules::Der => &DISTINGUISHED_ENCODING,
+    };
+    return ObjectIdentifier::new(arcs).expect("valid transfer syntax name");
+}
+
+/// the encoding rules of a transfer syntax, if it is one of X.690
+pub fn transfer_syntax_rules(name: &ObjectIdentifier) -> Option<EncodingRules> {
+    match name.arcs() {
+        arcs if arcs == BASIC_ENCODING => { return Some(EncodingRules::Ber); },
+        arcs if arcs == CANONICAL_ENCODING => { return Some(EncodingRules::Cer); },
+        arcs if arcs == DISTINGUISHED_ENCODING => { return Some(EncodingRules::Der); },
+        _ => { return None; }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// transfer syntaxes proposed for each presentation context, the first one is also the one of the default context
+    /// NOTE: of the ones a peer proposes, the first one in this list is accepted
+    pub transfer_syntaxes: Vec<EncodingRules>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config { transfer_syntaxes: vec![EncodingRules::Ber] };
+    }
+}
+
+/// X.216 presentation address = session address and presentation selector
+#[derive(Clone, Debug, PartialEq)]
+pub struct PresentationAddress {
+    pub session_address: SessionAddress,
+    pub psel: Vec<u8>,
+}
+
+impl PresentationAddress {
+    pub fn new(session_address: SessionAddress, psel: &[u8]) -> Self {
+        return PresentationAddress { session_address: session_address, psel: psel.to_vec() };
+    }
+}
+
+/// X.216 presentation context, with its result once negotiated
+#[derive(Clone, Debug, PartialEq)]
+pub struct PresentationContext {
+    pub identifier: i64,
+    pub abstract_syntax: ObjectIdentifier,
+    /// as in ppdu::RESULT_*
+    pub result: i64,
+    /// the negotiated one if accepted - while proposed, the preferred one
+    pub transfer_syntax: Option<EncodingRules>,
+    /// for rejection by the PS provider, as in ppdu::CONTEXT_REASON_*
+    pub provider_reason: Option<i64>,
+}
+
+impl PresentationContext {
+    /// PDV of this context, in its transfer syntax
+    pub fn pdv<T: Asn1>(&self, value: &T) -> Result<Pdv, Error> {
+        let rules = self.transfer_syntax.ok_or(Error::new(ErrorKind::InvalidInput, "presentation context without transfer syntax"))?;
+        return Pdv::new(Some(self.identifier), &self.abstract_syntax, rules, value);
+    }
+}
+
+/// X.216 P-CONNECT request parameters about presentation contexts and the session connection
+#[derive(Clone, Debug)]
+pub struct ConnectProposal {
+    /// presentation context definition list - identifier and abstract syntax name, the initiator uses odd identifiers
+    pub contexts: Vec<(i64, ObjectIdentifier)>,
+    /// abstract syntax of the default context, used while the defined context set is empty
+    pub default_context: Option<ObjectIdentifier>,
+    pub functional_units: FunctionalUnits,
+}
+
+/// presentation data value - a value of the abstract syntax of a presentation context, decoded from its transfer syntax
+/// NOTE: the context identifier is none for the default context
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pdv {
+    pub context_identifier: Option<i64>,
+    pub abstract_syntax: ObjectIdentifier,
+    /// those of the transfer syntax of the context - the element differs between them, e.g. CER segments long strings
+    pub rules: EncodingRules,
+    pub value: Element,
+}
+
+impl Pdv {
+    /// the rules are those the context is negotiated with, see PresentationContext::pdv() - for the default context, the first of Config::transfer_syntaxes
+    pub fn new<T: Asn1>(context_identifier: Option<i64>, abstract_syntax: &ObjectIdentifier, rules: EncodingRules, value: &T) -> Result<Self, Error> {
+        return Ok(Pdv { context_identifier: context_identifier, abstract_syntax: abstract_syntax.clone(), rules: rules, value: value.to_element(rules)? });
+    }
+
+    pub fn decode<T: Asn1>(&self) -> Result<T, Error> {
+        if !T::matches(self.value.tag) {
+            return Err(Error::new(ErrorKind::InvalidData, "PDV not of the expected type"));
+        }
+        return T::from_element(&self.value, self.rules);
+    }
+}
+
+/// NOTE: what CER and DER encode is also valid BER
+fn check_rules(pdv: &Pdv, rules: EncodingRules) -> Result<(), Error> {
+    if pdv.rules != rules && rules != EncodingRules::Ber {
+        return Err(Error::new(ErrorKind::InvalidInput, "PDV not in the transfer syntax of its presentation context"));
+    }
+    return Ok(());
+}
+
+/// X.216 indication and confirm primitives from the PS provider towards the PS user
+/// NOTE: the presentation connection is identified by the reference of the session connection it runs on
+#[derive(Debug)]
+pub enum PIndication {
+    /// the contexts are accepted resp. rejected by the PS provider - the P-CONNECT response may reject accepted ones
+    /// NOTE: the addresses are boxed, they would make every indication large
+    ConnectIndication {
+        reference: u16,
+        calling_address: Box<PresentationAddress>,
+        called_address: Box<PresentationAddress>,
+        contexts: Vec<PresentationContext>,
+        default_context: Option<ObjectIdentifier>,
+        functional_units: FunctionalUnits,
+        pdvs: Vec<Pdv>,
+    },
+    /// P-CONNECT confirm, accepted
+    ConnectConfirm {
+        reference: u16,
+        contexts: Vec<PresentationContext>,
+        functional_units: FunctionalUnits,
+        pdvs: Vec<Pdv>,
+    },
+    /// P-CONNECT confirm, rejected - provider reason as in ppdu::REASON_*, none for rejection by the called PS user
+    ConnectReject {
+        reference: u16,
+        provider_reason: Option<i64>,
+        contexts: Vec<PresentationContext>,
+        pdvs: Vec<Pdv>,
+    },
+    DataIndication {
+        reference: u16,
+        pdvs: Vec<Pdv>,
+    },
+    ReleaseIndication {
+        reference: u16,
+        pdvs: Vec<Pdv>,
+    },
+    ReleaseConfirm {
+        reference: u16,
+        pdvs: Vec<Pdv>,
+    },
+    UAbortIndication {
+        reference: u16,
+        pdvs: Vec<Pdv>,
+    },
+    /// reason as in ppdu::ABORT_*
+    PAbortIndication {
+        reference: u16,
+        reason: i64,
+    },
+}
+
+/// X.216 presentation service
+/// NOTE: the PS user is told about indications and confirms via the queue it got when creating the service
+//TODO P-ALTER-CONTEXT, P-EXPEDITED-DATA, P-TYPED-DATA, P-CAPABILITY-DATA, tokens, synchronization and activities
+pub trait PresentationService {
+    fn run(&mut self, p_user_wakeup: Option<Thread>);
+    fn bind(&mut self, tsel: &[u8], ssel: &[u8], psel: &[u8]) -> Result<(), Error>;
+    fn unbind(&mut self, tsel: &[u8], ssel: &[u8], psel: &[u8]);
+    /// abstract syntaxes the PS user handles - presentation contexts resp. default contexts of others are rejected
+    fn register_abstract_syntax(&mut self, name: &ObjectIdentifier);
+    fn unregister_abstract_syntax(&mut self, name: &ObjectIdentifier);
+    /// returns the reference of the new presentation connection
+    fn p_connect_request(&mut self, called_address: &PresentationAddress, calling_tsel: &[u8], calling_ssel: &[u8], calling_psel: &[u8], proposal: &ConnectProposal, pdvs: &[Pdv]) -> Result<u16, Error>;
+    /// the functional units are out of the indicated ones, the user rejected contexts out of the ones the PS provider accepted
+    fn p_connect_response(&mut self, reference: u16, user_rejected: &[i64], functional_units: FunctionalUnits, pdvs: &[Pdv]) -> Result<(), Error>;
+    fn p_connect_reject(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error>;
+    fn p_data_request(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error>;
+    fn p_release_request(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error>;
+    fn p_release_response(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error>;
+    fn p_u_abort_request(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error>;
+    /// proposed resp. negotiated presentation contexts - the accepted ones are the defined context set
+    fn contexts(&self, reference: u16) -> Option<Vec<PresentationContext>>;
+}
+
+/// X.226 A states of the PPM
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// STAI1 - CP sent, waiting for CPA or CPR
+    WaitForAccept,
+    /// STAI2 - CP indicated, waiting for the P-CONNECT response
+    WaitForConnectResponse,
+    /// STAt0 - connected, also while releasing - the session service takes care of that
+    DataTransfer,
+}
+
+struct Connection {
+    state: State,
+    local_psel: Vec<u8>,
+    remote_psel: Vec<u8>,
+    /// in the order of the presentation context definition list
+    contexts: Vec<PresentationContext>,
+    /// abstract syntax and transfer syntax
+    default_context: Option<(ObjectIdentifier, EncodingRules)>,
+    /// P-RELEASE requested by this side - the connection stays until the confirm
+    release_requested: bool,
+}
+
+impl Connection {
+    fn new(state: State, local_psel: &[u8], remote_psel: &[u8]) -> Self {
+        return Connection {
+            state: state,
+            local_psel: local_psel.to_vec(),
+            remote_psel: remote_psel.to_vec(),
+            contexts: vec![],
+            default_context: None,
+            release_requested: false,
+        };
+    }
+
+    /// a context PDVs may be in - the proposed ones until the CPA, then the defined context set
+    fn usable(&self, identifier: i64) -> Option<&PresentationContext> {
+        return self.contexts.iter().find(|context| context.identifier == identifier && context.result == ppdu::RESULT_ACCEPTANCE);
+    }
+
+    /// X.226 8.4 - simply encoded while the defined context set is empty, fully encoded otherwise
+    /// NOTE: the transfer syntax names are needed in the CP when there is more than one proposed per context
+    fn encode_user_data(&self, pdvs: &[Pdv], transfer_syntax_names: bool) -> Result<Option<UserData>, Error> {
+        if pdvs.is_empty() {
+            return Ok(None);
+        }
+        if !self.contexts.iter().any(|context| context.result == ppdu::RESULT_ACCEPTANCE) {
+            let (abstract_syntax, rules) = self.default_context.as_ref().ok_or(Error::new(ErrorKind::InvalidInput, "neither presentation contexts nor default context"))?;
+            let mut octets = vec![];
+            for pdv in pdvs {
+                if pdv.context_identifier.is_some() || pdv.abstract_syntax != *abstract_syntax {
+                    return Err(Error::new(ErrorKind::InvalidInput, "PDV not of the default context"));
+                }
+                check_rules(pdv, *rules)?;
+                octets.extend(pdv.value.encode(*rules));
+            }
+            return Ok(Some(UserData::SimplyEncoded(OctetString(octets))));
+        }
+        let mut list = Vec::with_capacity(pdvs.len());
+        for pdv in pdvs {
+            let context = pdv.context_identifier.and_then(|identifier| self.usable(identifier))
+                .filter(|context| context.abstract_syntax == pdv.abstract_syntax)
+                .ok_or(Error::new(ErrorKind::InvalidInput, "PDV not of a presentation context of the connection"))?;
+            let rules = context.transfer_syntax.expect("transfer syntax of usable context");
+            check_rules(pdv, rules)?;
+            list.push(PdvList {
+                transfer_syntax_name: if transfer_syntax_names { Some(transfer_syntax_name(rules)) } else { None },
+                presentation_context_identifier: context.identifier,
+                presentation_data_values: data_values(&pdv.value, rules),
+            });
+        }
+        return Ok(Some(UserData::FullyEncoded(list)));
+    }
+
+    fn decode_user_data(&self, user_data: Option<UserData>) -> Result<Vec<Pdv>, Error> {
+        let mut pdvs = vec![];
+        match user_data {
+            None => {},
+            Some(UserData::SimplyEncoded(OctetString(octets))) => {
+                let (abstract_syntax, rules) = self.default_context.as_ref().ok_or(Error::new(ErrorKind::InvalidData, "simply encoded data without default context"))?;
+                let mut position = 0;
+                while position < octets.len() {
+                    let (element, length) = ber::decode(&octets[position..], *rules)?;
+                    position += length;
+                    pdvs.push(Pdv { context_identifier: None, abstract_syntax: abstract_syntax.clone(), rules: *rules, value: element });
+                }
+            },
+            Some(UserData::FullyEncoded(list)) => {
+                for item in list {
+                    let context = self.usable(item.presentation_context_identifier).ok_or(Error::new(ErrorKind::InvalidData, "PDV of an unknown presentation context"))?;
+                    let rules = match &item.transfer_syntax_name {
+                        Some(name) => transfer_syntax_rules(name).ok_or(Error::new(ErrorKind::InvalidData, "PDV in an unknown transfer syntax"))?,
+                        None => context.transfer_syntax.expect("transfer syntax of usable context"),
+                    };
+                    let value = match item.presentation_data_values {
+                        PresentationDataValues::SingleAsn1Type(Any(element)) => element,
+                        PresentationDataValues::OctetAligned(OctetString(octets)) => Element::decode(&octets, rules)?,
+                        PresentationDataValues::Arbitrary(_) => { return Err(Error::new(ErrorKind::InvalidData, "arbitrary encoding in an ASN.1 transfer syntax")); }
+                    };
+                    pdvs.push(Pdv { context_identifier: Some(context.identifier), abstract_syntax: context.abstract_syntax.clone(), rules: rules, value: value });
+                }
+            }
+        }
+        return Ok(pdvs);
+    }
+
+    /// User-data as the whole SS user data, which may be empty
+    fn decode_data(&self, data: &[u8]) -> Result<Vec<Pdv>, Error> {
+        if data.is_empty() {
+            return Ok(vec![]);
+        }
+        return self.decode_user_data(Some(UserData::decode(data, EncodingRules::Ber)?));
+    }
+
+    fn encode_data(&self, pdvs: &[Pdv]) -> Result<Vec<u8>, Error> {
+        match self.encode_user_data(pdvs, false)? {
+            Some(user_data) => { return user_data.encode(EncodingRules::Ber); },
+            None => { return Ok(vec![]); }
+        }
+    }
+
+    /// results of the CPA resp. CPR, in the order of the definition list
+    fn apply_results(&mut self, results: &[ContextResult], proposed: &[EncodingRules]) -> Result<(), Error> {
+        if results.len() != self.contexts.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "result list does not match the definition list"));
+        }
+        for (context, result) in self.contexts.iter_mut().zip(results) {
+            context.result = result.result;
+            context.provider_reason = result.provider_reason;
+            context.transfer_syntax = None;
+            if result.result == ppdu::RESULT_ACCEPTANCE {
+                let rules = result.transfer_syntax_name.as_ref().and_then(transfer_syntax_rules).filter(|rules| proposed.contains(rules));
+                context.transfer_syntax = Some(rules.ok_or(Error::new(ErrorKind::InvalidData, "accepted context without a proposed transfer syntax"))?);
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// single-ASN1-type where the BER of the PPDU is also the one of the transfer syntax, octet-aligned otherwise
+fn data_values(value: &Element, rules: EncodingRules) -> PresentationDataValues {
+    if rules == EncodingRules::Ber {
+        return PresentationDataValues::SingleAsn1Type(Any(value.clone()));
+    }
+    return PresentationDataValues::OctetAligned(OctetString(value.encode(rules)));
+}
+
+fn selector(psel: &[u8]) -> Option<OctetString> {
+    if psel.is_empty() {
+        return None;
+    }
+    return Some(OctetString(psel.to_vec()));
+}
+
+fn result_list(contexts: &[PresentationContext]) -> Option<Vec<ContextResult>> {
+    if contexts.is_empty() {
+        return None;
+    }
+    return Some(contexts.iter().map(|context| ContextResult {
+        result: context.result,
+        transfer_syntax_name: context.transfer_syntax.map(transfer_syntax_name),
+        provider_reason: context.provider_reason,
+    }).collect());
+}
+
+/// CPR without user data
+fn cpr(connection: &Connection, provider_reason: Option<i64>) -> CprPpdu {
+    return CprPpdu {
+        protocol_version: None,
+        responding_presentation_selector: selector(&connection.local_psel),
+        presentation_context_definition_result_list: result_list(&connection.contexts),
+        default_context_result: None,
+        provider_reason: provider_reason,
+        user_data: None,
+    };
+}
+
+/// what the PPM asks from the session service, done once the entity lock is released
+enum SRequest {
+    ConnectResponse(u16, FunctionalUnits, Vec<u8>),
+    ConnectReject(u16, Vec<u8>),
+    Data(u16, Vec<u8>),
+    Release(u16, Vec<u8>),
+    ReleaseResponse(u16, Vec<u8>),
+    UAbort(u16, Vec<u8>),
+}
+
+#[derive(Default)]
+struct Actions {
+    requests: Vec<SRequest>,
+    wakeup: bool,
+}
+
+/// TSAP selector and session selector
+type SessionSelectors = (Vec<u8>, Vec<u8>);
+
+/// the presentation protocol machines, shared between the PS user calls and the reader thread
+struct Entity {
+    config: Config,
+    /// registered by the PS user
+    abstract_syntaxes: HashSet<ObjectIdentifier>,
+    connections: HashMap<u16, Connection>,
+    /// presentation selectors bound on a TSAP and session selector
+    bound: HashMap<SessionSelectors, HashSet<Vec<u8>>>,
+    p_user_to: rtrb::Producer<PIndication>,
+}
+
+/// X.226 presentation protocol in normal mode over any session service, one session connection per presentation connection
+pub struct Service<S: SessionService + Send + 'static> {
+    ss: Arc<Mutex<S>>,
+    entity: Arc<Mutex<Entity>>,
+    ss_user_from: Arc<Mutex<rtrb::Consumer<SIndication>>>,
+    p_user_to_wakeup: Arc<Mutex<Option<Thread>>>,
+}
+
+impl<S: SessionService + Send + 'static> Service<S> {
+    /// takes the SS provider and the queue of its indications - returns the queue of indications towards the PS user
+    pub fn new(ss: S, ss_user_from: rtrb::Consumer<SIndication>, config: Config, queue_capacity: usize) -> (Self, rtrb::Consumer<PIndication>) {
+        let (p_user_to, p_user_from) = rtrb::RingBuffer::new(queue_capacity);
+        let service = Service {
+            ss: Arc::new(Mutex::new(ss)),
+            entity: Arc::new(Mutex::new(Entity {
+                config: config,
+                abstract_syntaxes: HashSet::new(),
+                connections: HashMap::new(),
+                bound: HashMap::new(),
+                p_user_to: p_user_to,
+            })),
+            ss_user_from: Arc::new(Mutex::new(ss_user_from)),
+            p_user_to_wakeup: Arc::new(Mutex::new(None)),
+        };
+        return (service, p_user_from);
+    }
+
+    /// runs the given PPM operation, then what it asks from the session service
+    fn request<R>(&self, operation: impl FnOnce(&mut Entity, &mut Actions) -> Result<R, Error>) -> Result<R, Error> {
+        let mut actions = Actions::default();
+        let result = operation(&mut self.entity.lock().expect("failed to lock entity"), &mut actions)?;
+        execute(&self.ss, &self.p_user_to_wakeup, actions)?;
+        return Ok(result);
+    }
+}
+
+impl<S: SessionService + Send + 'static> PresentationService for Service<S> {
+    /// starts the reader thread and the SS provider
+    fn run(&mut self, p_user_wakeup: Option<Thread>) {
+        *self.p_user_to_wakeup.lock().expect("failed to lock p_user_to_wakeup") = p_user_wakeup;
+
+        // read S indications from SS
+        let entity_arc = self.entity.clone();
+        let ss_arc = self.ss.clone();
+        let ss_user_from_arc = self.ss_user_from.clone();
+        let p_user_to_wakeup_arc = self.p_user_to_wakeup.clone();
+        let ss2ps_consumer = thread::Builder::new().name("P <- S".to_string()).spawn(move || {
+            // keep permanent lock on this
+            let mut ss_user_from = ss_user_from_arc.lock().expect("failed to lock ss_user_from");
+            loop {
+                // NOTE: while the PS user does not keep up, the indications stay in the SS queue
+                while entity_arc.lock().expect("failed to lock entity").p_user_to.slots() == 0 {
+                    thread::sleep(BACKPRESSURE_INTERVAL);
+                }
+                let indication = match ss_user_from.pop() {
+                    Ok(indication) => indication,
+                    Err(_) => {
+                        thread::park(); // wait for unpark wakeup call from SS
+                        continue;
+                    }
+                };
+                let mut actions = Actions::default();
+                entity_arc.lock().expect("failed to lock entity").s_indication(indication, &mut actions);
+                if let Err(e) = execute(&ss_arc, &p_user_to_wakeup_arc, actions) {
+                    info!("presentation: session request failed: {}", e);
+                }
+            }
+        }).expect("failed to start thread");
+        self.ss.lock().expect("failed to lock ss").run(Some(ss2ps_consumer.thread().clone()));
+    }
+
+    fn bind(&mut self, tsel: &[u8], ssel: &[u8], psel: &[u8]) -> Result<(), Error> {
+        let mut entity = self.entity.lock().expect("failed to lock entity");
+        let key = (tsel.to_vec(), ssel.to_vec());
+        if !entity.bound.contains_key(&key) {
+            self.ss.lock().expect("failed to lock ss").bind(tsel, ssel)?;
+        }
+        if !entity.bound.entry(key).or_default().insert(psel.to_vec()) {
+            return Err(Error::new(ErrorKind::AddrInUse, "presentation selector already bound"));
+        }
+        return Ok(());
+    }
+
+    fn unbind(&mut self, tsel: &[u8], ssel: &[u8], psel: &[u8]) {
+        let mut entity = self.entity.lock().expect("failed to lock entity");
+        let key = (tsel.to_vec(), ssel.to_vec());
+        if let Some(psels) = entity.bound.get_mut(&key) {
+            psels.remove(psel);
+            if psels.is_empty() {
+                entity.bound.remove(&key);
+                self.ss.lock().expect("failed to lock ss").unbind(tsel, ssel);
+            }
+        }
+    }
+
+    fn register_abstract_syntax(&mut self, name: &ObjectIdentifier) {
+        self.entity.lock().expect("failed to lock entity").abstract_syntaxes.insert(name.clone());
+    }
+
+    fn unregister_abstract_syntax(&mut self, name: &ObjectIdentifier) {
+        self.entity.lock().expect("failed to lock entity").abstract_syntaxes.remove(name);
+    }
+
+    fn p_connect_request(&mut self, called_address: &PresentationAddress, calling_tsel: &[u8], calling_ssel: &[u8], calling_psel: &[u8], proposal: &ConnectProposal, pdvs: &[Pdv]) -> Result<u16, Error> {
+        let mut entity = self.entity.lock().expect("failed to lock entity");
+        let proposed = entity.config.transfer_syntaxes.clone();
+        let preferred = *proposed.first().ok_or(Error::new(ErrorKind::InvalidInput, "no transfer syntax configured"))?;
+        let mut identifiers = HashSet::new();
+        if !proposal.contexts.iter().all(|(identifier, _)| identifiers.insert(*identifier)) {
+            return Err(Error::new(ErrorKind::InvalidInput, "presentation context identifier used twice"));
+        }
+        let mut connection = Connection::new(State::WaitForAccept, calling_psel, &called_address.psel);
+        connection.contexts = proposal.contexts.iter().map(|(identifier, abstract_syntax)| PresentationContext {
+            identifier: *identifier,
+            abstract_syntax: abstract_syntax.clone(),
+            result: ppdu::RESULT_ACCEPTANCE,
+            transfer_syntax: Some(preferred),
+            provider_reason: None,
+        }).collect();
+        connection.default_context = proposal.default_context.clone().map(|name| (name, preferred));
+        let transfer_syntax_names: Vec<ObjectIdentifier> = proposed.iter().map(|rules| transfer_syntax_name(*rules)).collect();
+        let definitions: Vec<ContextDefinition> = connection.contexts.iter().map(|context| ContextDefinition {
+            presentation_context_identifier: context.identifier,
+            abstract_syntax_name: context.abstract_syntax.clone(),
+            transfer_syntax_name_list: transfer_syntax_names.clone(),
+        }).collect();
+        let cp = CpPpdu {
+            mode_selector: ModeSelector::normal(),
+            normal_mode_parameters: Some(CpNormalModeParameters {
+                protocol_version: None,
+                calling_presentation_selector: selector(calling_psel),
+                called_presentation_selector: selector(&called_address.psel),
+                presentation_context_definition_list: if definitions.is_empty() { None } else { Some(definitions) },
+                default_context_name: proposal.default_context.clone().map(|name| DefaultContextName { abstract_syntax_name: name, transfer_syntax_name: transfer_syntax_name(preferred) }),
+                presentation_requirements: None,
+                user_session_requirements: None,
+                protocol_options: None,
+                initiators_nominated_context: None,
+                extensions: None,
+                user_data: connection.encode_user_data(pdvs, proposed.len() > 1)?,
+            }),
+        };
+        // NOTE: entity stays locked, so that the S-CONNECT confirm finds the connection
+        let reference = self.ss.lock().expect("failed to lock ss").s_connect_request(&called_address.session_address, calling_tsel, calling_ssel, proposal.functional_units, &cp.encode(EncodingRules::Ber)?)?;
+        entity.connections.insert(reference, connection);
+        return Ok(reference);
+    }
+
+    fn p_connect_response(&mut self, reference: u16, user_rejected: &[i64], functional_units: FunctionalUnits, pdvs: &[Pdv]) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.connect_response(reference, user_rejected, functional_units, pdvs, actions));
+    }
+
+    fn p_connect_reject(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.connect_reject(reference, pdvs, actions));
+    }
+
+    fn p_data_request(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.data_request(reference, pdvs, actions));
+    }
+
+    fn p_release_request(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.release_request(reference, pdvs, actions));
+    }
+
+    fn p_release_response(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.release_response(reference, pdvs, actions));
+    }
+
+    fn p_u_abort_request(&mut self, reference: u16, pdvs: &[Pdv]) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.u_abort_request(reference, pdvs, actions));
+    }
+
+    fn contexts(&self, reference: u16) -> Option<Vec<PresentationContext>> {
+        return self.entity.lock().expect("failed to lock entity").connections.get(&reference).map(|connection| connection.contexts.clone());
+    }
+}
+
+/// hands the requests to the SS provider and wakes up the PS user
+/// NOTE: done without the entity lock, since the SS provider may block
+fn execute<S: SessionService>(ss: &Mutex<S>, p_user_to_wakeup: &Mutex<Option<Thread>>, actions: Actions) -> Result<(), Error> {
+    if actions.wakeup {
+        if let Some(wakeup) = p_user_to_wakeup.lock().expect("failed to lock p_user_to_wakeup").as_ref() {
+            wakeup.unpark();
+        }
+    }
+    let mut ss = ss.lock().expect("failed to lock ss");
+    for request in actions.requests {
+        match request {
+            SRequest::ConnectResponse(reference, functional_units, data) => { ss.s_connect_response(reference, functional_units, &data)?; },
+            SRequest::ConnectReject(reference, data) => { ss.s_connect_reject(reference, session::spdu::REASON_REJECTED_BY_USER, &data)?; },
+            SRequest::Data(reference, data) => { ss.s_data_request(reference, &data)?; },
+            SRequest::Release(reference, data) => { ss.s_release_request(reference, &data)?; },
+            SRequest::ReleaseResponse(reference, data) => { ss.s_release_response(reference, &data)?; },
+            SRequest::UAbort(reference, data) => {
+                // NOTE: the session connection may be gone already
+                if let Err(e) = ss.s_u_abort_request(reference, &data) {
+                    debug!("presentation: session connection {} not aborted: {}", reference, e);
+                }
+            }
+        }
+    }
+    return Ok(());
+}
+
+impl Entity {
+    fn indicate(&mut self, actions: &mut Actions, indication: PIndication) {
+        if let Err(_) = self.p_user_to.push(indication) {
+            warn!("presentation: queue towards PS user full, indication lost");
+            return;
+        }
+        actions.wakeup = true;
+    }
+
+    fn connection(&mut self, reference: u16, state: State) -> Result<&mut Connection, Error> {
+        let connection = self.connections.get_mut(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such presentation connection"))?;
+        if connection.state != state {
+            return Err(Error::new(ErrorKind::InvalidInput, "not possible in the state of the presentation connection"));
+        }
+        return Ok(connection);
+    }
+
+    fn connect_response(&mut self, reference: u16, user_rejected: &[i64], functional_units: FunctionalUnits, pdvs: &[Pdv], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::WaitForConnectResponse)?;
+        if !user_rejected.iter().all(|identifier| connection.usable(*identifier).is_some()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "rejected presentation context not among the accepted ones"));
+        }
+        for context in connection.contexts.iter_mut().filter(|context| user_rejected.contains(&context.identifier)) {
+            context.result = ppdu::RESULT_USER_REJECTION;
+            context.transfer_syntax = None;
+        }
+        let cpa = CpaPpdu {
+            mode_selector: ModeSelector::normal(),
+            normal_mode_parameters: Some(CpaNormalModeParameters {
+                protocol_version: None,
+                responding_presentation_selector: selector(&connection.local_psel),
+                presentation_context_definition_result_list: result_list(&connection.contexts),
+                presentation_requirements: None,
+                user_session_requirements: None,
+                protocol_options: None,
+                responders_nominated_context: None,
+                user_data: connection.encode_user_data(pdvs, false)?,
+            }),
+        };
+        connection.state = State::DataTransfer;
+        actions.requests.push(SRequest::ConnectResponse(reference, functional_units, cpa.encode(EncodingRules::Ber)?));
+        return Ok(());
+    }
+
+    fn connect_reject(&mut self, reference: u16, pdvs: &[Pdv], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::WaitForConnectResponse)?;
+        let mut cpr = cpr(connection, None);
+        cpr.user_data = connection.encode_user_data(pdvs, false)?;
+        let data = cpr.encode(EncodingRules::Ber)?;
+        self.connections.remove(&reference);
+        actions.requests.push(SRequest::ConnectReject(reference, data));
+        return Ok(());
+    }
+
+    fn data_request(&mut self, reference: u16, pdvs: &[Pdv], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::DataTransfer)?;
+        if pdvs.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "P-DATA without PDVs"));
+        }
+        actions.requests.push(SRequest::Data(reference, connection.encode_data(pdvs)?));
+        return Ok(());
+    }
+
+    fn release_request(&mut self, reference: u16, pdvs: &[Pdv], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::DataTransfer)?;
+        let data = connection.encode_data(pdvs)?;
+        connection.release_requested = true;
+        actions.requests.push(SRequest::Release(reference, data));
+        return Ok(());
+    }
+
+    fn release_response(&mut self, reference: u16, pdvs: &[Pdv], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::DataTransfer)?;
+        let data = connection.encode_data(pdvs)?;
+        // NOTE: after a release collision, the release confirm is still to come
+        if !connection.release_requested {
+            self.connections.remove(&reference);
+        }
+        actions.requests.push(SRequest::ReleaseResponse(reference, data));
+        return Ok(());
+    }
+
+    fn u_abort_request(&mut self, reference: u16, pdvs: &[Pdv], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connections.get(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such presentation connection"))?;
+        let user_data = connection.encode_user_data(pdvs, false)?;
+        // X.226 8.3.6 - the contexts of fully encoded user data are listed with their transfer syntax
+        let identifiers = match &user_data {
+            Some(UserData::FullyEncoded(_)) => Some(connection.contexts.iter().filter(|context| context.result == ppdu::RESULT_ACCEPTANCE).map(|context| ContextIdentifier {
+                presentation_context_identifier: context.identifier,
+                transfer_syntax_name: transfer_syntax_name(context.transfer_syntax.expect("transfer syntax of usable context")),
+            }).collect()),
+            _ => None,
+        };
+        let aru = AbortPpdu::UserAbort(AruPpdu { presentation_context_identifier_list: identifiers, user_data: user_data });
+        let data = aru.encode(EncodingRules::Ber)?;
+        self.connections.remove(&reference);
+        actions.requests.push(SRequest::UAbort(reference, data));
+        return Ok(());
+    }
+
+    /// aborts the presentation connection because of the peer - ARP PPDU towards it, P-P-ABORT towards the PS user
+    fn provider_abort(&mut self, reference: u16, reason: i64, actions: &mut Actions) {
+        if self.connections.remove(&reference).is_none() {
+            return;
+        }
+        let arp = AbortPpdu::ProviderAbort(ArpPpdu { provider_reason: Some(reason), event_identifier: None });
+        match arp.encode(EncodingRules::Ber) {
+            Ok(data) => { actions.requests.push(SRequest::UAbort(reference, data)); },
+            Err(e) => { warn!("presentation: ARP on {} not encoded: {}", reference, e); }
+        }
+        self.indicate(actions, PIndication::PAbortIndication { reference: reference, reason: reason });
+    }
+
+    /// CPR towards the calling PPM, the PS user does not get to know about the connection
+    fn refuse(&mut self, reference: u16, cpr: CprPpdu, actions: &mut Actions) {
+        info!("presentation: refusing CP on {}, reason {:?}", reference, cpr.provider_reason);
+        self.connections.remove(&reference);
+        match cpr.encode(EncodingRules::Ber) {
+            Ok(data) => { actions.requests.push(SRequest::ConnectReject(reference, data)); },
+            Err(e) => { warn!("presentation: CPR on {} not encoded: {}", reference, e); }
+        }
+    }
+
+    fn s_indication(&mut self, indication: SIndication, actions: &mut Actions) {
+        match indication {
+            SIndication::ConnectIndication { reference, calling_address, called_address, functional_units, data } => {
+                self.connect_indication(reference, calling_address, called_address, functional_units, &data, actions);
+            },
+            SIndication::ConnectConfirm { reference, functional_units, data } => {
+                self.accept_indication(reference, functional_units, &data, actions);
+            },
+            SIndication::ConnectReject { reference, reason, data } => {
+                self.reject_indication(reference, reason, &data, actions);
+            },
+            SIndication::DataIndication { reference, data } => {
+                let pdvs = match self.connections.get(&reference) {
+                    Some(connection) if connection.state == State::DataTransfer && !data.is_empty() => connection.decode_data(&data),
+                    _ => { debug!("presentation: unexpected S-DATA on {}", reference); return; }
+                };
+                match pdvs {
+                    Ok(pdvs) => { self.indicate(actions, PIndication::DataIndication { reference: reference, pdvs: pdvs }); },
+                    Err(e) => {
+                        info!("presentation: invalid TD PPDU on {}: {}", reference, e);
+                        self.provider_abort(reference, ppdu::ABORT_INVALID_PPDU_PARAMETER_VALUE, actions);
+                    }
+                }
+            },
+            SIndication::ReleaseIndication { reference, data } => {
+                let pdvs = match self.connections.get(&reference) {
+                    Some(connection) if connection.state == State::DataTransfer => connection.decode_data(&data),
+                    _ => { debug!("presentation: unexpected S-RELEASE indication on {}", reference); return; }
+                };
+                match pdvs {
+                    Ok(pdvs) => { self.indicate(actions, PIndication::ReleaseIndication { reference: reference, pdvs: pdvs }); },
+                    Err(e) => {
+                        info!("presentation: invalid user data in S-RELEASE indication on {}: {}", reference, e);
+                        self.provider_abort(reference, ppdu::ABORT_INVALID_PPDU_PARAMETER_VALUE, actions);
+                    }
+                }
+            },
+            SIndication::ReleaseConfirm { reference, data } => {
+                let connection = match self.connections.remove(&reference) {
+                    Some(connection) => connection,
+                    None => { return; }
+                };
+                // NOTE: the session connection is gone, so unreadable user data is just left out
+                let pdvs = connection.decode_data(&data).unwrap_or_else(|e| {
+                    info!("presentation: invalid user data in S-RELEASE confirm on {}: {}", reference, e);
+                    vec![]
+                });
+                self.indicate(actions, PIndication::ReleaseConfirm { reference: reference, pdvs: pdvs });
+            },
+            SIndication::UAbortIndication { reference, data } => {
+                self.abort_indication(reference, &data, actions);
+            },
+            SIndication::PAbortIndication { reference, .. } => {
+                if self.connections.remove(&reference).is_some() {
+                    self.indicate(actions, PIndication::PAbortIndication { reference: reference, reason: ppdu::ABORT_REASON_NOT_SPECIFIED });
+                }
+            },
+            //TODO mapping of S-EXPEDITED-DATA, tokens, synchronization and activities
+            other => {
+                info!("presentation: session service primitive not supported: {:?}", other);
+            }
+        }
+    }
+
+    /// presentation context as far as the PS provider is concerned - X.226 6.2.2
+    fn negotiate(&self, definition: &ContextDefinition) -> PresentationContext {
+        let transfer_syntax = definition.transfer_syntax_name_list.iter().filter_map(transfer_syntax_rules).find(|rules| self.config.transfer_syntaxes.contains(rules));
+        let provider_reason = if !self.abstract_syntaxes.contains(&definition.abstract_syntax_name) {
+            Some(ppdu::CONTEXT_REASON_ABSTRACT_SYNTAX_NOT_SUPPORTED)
+        } else if transfer_syntax.is_none() {
+            Some(ppdu::CONTEXT_REASON_TRANSFER_SYNTAXES_NOT_SUPPORTED)
+        } else {
+            None
+        };
+        return PresentationContext {
+            identifier: definition.presentation_context_identifier,
+            abstract_syntax: definition.abstract_syntax_name.clone(),
+            result: if provider_reason.is_some() { ppdu::RESULT_PROVIDER_REJECTION } else { ppdu::RESULT_ACCEPTANCE },
+            transfer_syntax: if provider_reason.is_some() { None } else { transfer_syntax },
+            provider_reason: provider_reason,
+        };
+    }
+
+    fn connect_indication(&mut self, reference: u16, calling_address: SessionAddress, called_address: SessionAddress, functional_units: FunctionalUnits, data: &[u8], actions: &mut Actions) {
+        let mut connection = Connection::new(State::WaitForConnectResponse, &[], &[]);
+        let parameters = match CpPpdu::decode(data, EncodingRules::Ber) {
+            Ok(CpPpdu { mode_selector, normal_mode_parameters: Some(parameters) }) if mode_selector.mode_value == ppdu::MODE_NORMAL => parameters,
+            Ok(_) => {
+                self.refuse(reference, cpr(&connection, Some(ppdu::REASON_NOT_SPECIFIED)), actions);
+                return;
+            },
+            Err(e) => {
+                info!("presentation: invalid CP PPDU on {}: {}", reference, e);
+                self.refuse(reference, cpr(&connection, Some(ppdu::REASON_NOT_SPECIFIED)), actions);
+                return;
+            }
+        };
+        connection.local_psel = parameters.called_presentation_selector.map(|psel| psel.0).unwrap_or_default();
+        connection.remote_psel = parameters.calling_presentation_selector.map(|psel| psel.0).unwrap_or_default();
+        if parameters.protocol_version.as_ref().map(|version| !version.bit(ppdu::PROTOCOL_VERSION_1)).unwrap_or(false) {
+            self.refuse(reference, cpr(&connection, Some(ppdu::REASON_PROTOCOL_VERSION_NOT_SUPPORTED)), actions);
+            return;
+        }
+        let bound = self.bound.get(&(called_address.transport_address.tsel.clone(), called_address.ssel.clone()));
+        if !bound.map(|psels| psels.contains(&connection.local_psel)).unwrap_or(false) {
+            self.refuse(reference, cpr(&connection, Some(ppdu::REASON_CALLED_ADDRESS_UNKNOWN)), actions);
+            return;
+        }
+        connection.contexts = parameters.presentation_context_definition_list.unwrap_or_default().iter().map(|definition| self.negotiate(definition)).collect();
+        if let Some(name) = parameters.default_context_name {
+            let rules = transfer_syntax_rules(&name.transfer_syntax_name).filter(|rules| self.config.transfer_syntaxes.contains(rules));
+            match rules {
+                Some(rules) if self.abstract_syntaxes.contains(&name.abstract_syntax_name) => { connection.default_context = Some((name.abstract_syntax_name, rules)); },
+                _ => {
+                    let mut cpr = cpr(&connection, Some(ppdu::REASON_DEFAULT_CONTEXT_NOT_SUPPORTED));
+                    cpr.default_context_result = Some(ppdu::RESULT_PROVIDER_REJECTION);
+                    self.refuse(reference, cpr, actions);
+                    return;
+                }
+            }
+        }
+        let pdvs = match connection.decode_user_data(parameters.user_data) {
+            Ok(pdvs) => pdvs,
+            Err(e) => {
+                info!("presentation: unreadable user data in CP PPDU on {}: {}", reference, e);
+                self.refuse(reference, cpr(&connection, Some(ppdu::REASON_USER_DATA_NOT_READABLE)), actions);
+                return;
+            }
+        };
+        let indication = PIndication::ConnectIndication {
+            reference: reference,
+            calling_address: Box::new(PresentationAddress::new(calling_address, &connection.remote_psel)),
+            called_address: Box::new(PresentationAddress::new(called_address, &connection.local_psel)),
+            contexts: connection.contexts.clone(),
+            default_context: connection.default_context.as_ref().map(|(name, _)| name.clone()),
+            functional_units: functional_units,
+            pdvs: pdvs,
+        };
+        self.connections.insert(reference, connection);
+        self.indicate(actions, indication);
+    }
+
+    fn accept_indication(&mut self, reference: u16, functional_units: FunctionalUnits, data: &[u8], actions: &mut Actions) {
+        let proposed = self.config.transfer_syntaxes.clone();
+        let connection = match self.connections.get_mut(&reference) {
+            Some(connection) if connection.state == State::WaitForAccept => connection,
+            _ => { debug!("presentation: unexpected S-CONNECT confirm for {}", reference); return; }
+        };
+        let parameters = match CpaPpdu::decode(data, EncodingRules::Ber) {
+            Ok(CpaPpdu { mode_selector, normal_mode_parameters: Some(parameters) }) if mode_selector.mode_value == ppdu::MODE_NORMAL => parameters,
+            Ok(_) => {
+                info!("presentation: CPA PPDU on {} not in normal mode", reference);
+                self.provider_abort(reference, ppdu::ABORT_UNEXPECTED_PPDU_PARAMETER, actions);
+                return;
+            },
+            Err(e) => {
+                info!("presentation: invalid CPA PPDU on {}: {}", reference, e);
+                self.provider_abort(reference, ppdu::ABORT_UNRECOGNIZED_PPDU, actions);
+                return;
+            }
+        };
+        let results = parameters.presentation_context_definition_result_list.unwrap_or_default();
+        let pdvs = connection.apply_results(&results, &proposed).and_then(|_| connection.decode_user_data(parameters.user_data));
+        let pdvs = match pdvs {
+            Ok(pdvs) => pdvs,
+            Err(e) => {
+                info!("presentation: CPA PPDU on {}: {}", reference, e);
+                self.provider_abort(reference, ppdu::ABORT_INVALID_PPDU_PARAMETER_VALUE, actions);
+                return;
+            }
+        };
+        if let Some(psel) = parameters.responding_presentation_selector {
+            connection.remote_psel = psel.0;
+        }
+        connection.state = State::DataTransfer;
+        let contexts = connection.contexts.clone();
+        self.indicate(actions, PIndication::ConnectConfirm { reference: reference, contexts: contexts, functional_units: functional_units, pdvs: pdvs });
+    }
+
+    fn reject_indication(&mut self, reference: u16, reason: u8, data: &[u8], actions: &mut Actions) {
+        let mut connection = match self.connections.remove(&reference) {
+            Some(connection) if connection.state == State::WaitForAccept => connection,
+            _ => { debug!("presentation: unexpected S-CONNECT confirm for {}", reference); return; }
+        };
+        // rejected by the session service resp. a peer without presentation protocol
+        let session_reason = if reason == session::spdu::REASON_SELECTOR_UNKNOWN { ppdu::REASON_CALLED_ADDRESS_UNKNOWN } else { ppdu::REASON_NOT_SPECIFIED };
+        let (provider_reason, pdvs) = match CprPpdu::decode(data, EncodingRules::Ber) {
+            _ if data.is_empty() => (Some(session_reason), vec![]),
+            Ok(cpr) => {
+                if let Some(results) = &cpr.presentation_context_definition_result_list {
+                    if let Err(e) = connection.apply_results(results, &self.config.transfer_syntaxes) {
+                        info!("presentation: CPR PPDU on {}: {}", reference, e);
+                    }
+                }
+                let pdvs = connection.decode_user_data(cpr.user_data).unwrap_or_else(|e| {
+                    info!("presentation: unreadable user data in CPR PPDU on {}: {}", reference, e);
+                    vec![]
+                });
+                (cpr.provider_reason, pdvs)
+            },
+            Err(e) => {
+                info!("presentation: invalid CPR PPDU on {}: {}", reference, e);
+                (Some(ppdu::REASON_NOT_SPECIFIED), vec![])
+            }
+        };
+        self.indicate(actions, PIndication::ConnectReject { reference: reference, provider_reason: provider_reason, contexts: connection.contexts, pdvs: pdvs });
+    }
+
+    /// ARU resp. ARP PPDU - a peer without presentation protocol may also send no PPDU at all
+    fn abort_indication(&mut self, reference: u16, data: &[u8], actions: &mut Actions) {
+        let connection = match self.connections.remove(&reference) {
+            Some(connection) => connection,
+            None => { return; }
+        };
+        if data.is_empty() {
+            self.indicate(actions, PIndication::UAbortIndication { reference: reference, pdvs: vec![] });
+            return;
+        }
+        match AbortPpdu::decode(data, EncodingRules::Ber) {
+            Ok(AbortPpdu::UserAbort(aru)) => {
+                let pdvs = connection.decode_user_data(aru.user_data).unwrap_or_else(|e| {
+                    info!("presentation: unreadable user data in ARU PPDU on {}: {}", reference, e);
+                    vec![]
+                });
+                self.indicate(actions, PIndication::UAbortIndication { reference: reference, pdvs: pdvs });
+            },
+            Ok(AbortPpdu::ProviderAbort(arp)) => {
+                self.indicate(actions, PIndication::PAbortIndication { reference: reference, reason: arp.provider_reason.unwrap_or(ppdu::ABORT_REASON_NOT_SPECIFIED) });
+            },
+            Err(e) => {
+                info!("presentation: invalid abort PPDU on {}: {}", reference, e);
+                self.indicate(actions, PIndication::PAbortIndication { reference: reference, reason: ppdu::ABORT_UNRECOGNIZED_PPDU });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use crate::asn1::Null;
+    use crate::session::{ResyncType, Tokens};
+    use crate::t::{NetworkAddress, TransportAddress};
+
+    const ACSE: &str = "2.2.1.0.1";
+    const MMS: &str = "1.0.9506.2.1";
+
+    /// what the PPM asked from the session service, as primitive, reference and SS user data
+    type Requests = Arc<Mutex<Vec<(&'static str, u16, Vec<u8>)>>>;
+
+    /// session service recording the requests - the S indications are handed to the entity by the tests
+    struct MockSs {
+        requests: Requests,
+    }
+
+    impl MockSs {
+        fn record(&self, primitive: &'static str, reference: u16, data: &[u8]) -> Result<(), Error> {
+            self.requests.lock().unwrap().push((primitive, reference, data.to_vec()));
+            return Ok(());
+        }
+    }
+
+    fn unsupported<T>() -> Result<T, Error> {
+        return Err(Error::new(ErrorKind::Unsupported, "not in the mock session service"));
+    }
+
+    impl SessionService for MockSs {
+        fn run(&mut self, _s_user_wakeup: Option<Thread>) {}
+        fn bind(&mut self, _tsel: &[u8], _ssel: &[u8]) -> Result<(), Error> { return Ok(()); }
+        fn unbind(&mut self, _tsel: &[u8], _ssel: &[u8]) {}
+        fn s_connect_request(&mut self, _called_address: &SessionAddress, _calling_tsel: &[u8], _calling_ssel: &[u8], _functional_units: FunctionalUnits, ss_userdata: &[u8]) -> Result<u16, Error> {
+            self.record("CONNECT", 1, ss_userdata)?;
+            return Ok(1);
+        }
+        fn s_connect_response(&mut self, reference: u16, _functional_units: FunctionalUnits, ss_userdata: &[u8]) -> Result<(), Error> { return self.record("ACCEPT", reference, ss_userdata); }
+        fn s_connect_reject(&mut self, reference: u16, _reason: u8, ss_userdata: &[u8]) -> Result<(), Error> { return self.record("REFUSE", reference, ss_userdata); }
+        fn s_data_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error> { return self.record("DATA", reference, ss_userdata); }
+        fn s_expedited_data_request(&mut self, _reference: u16, _ss_userdata: &[u8]) -> Result<(), Error> { return unsupported(); }
+        fn s_release_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error> { return self.record("RELEASE", reference, ss_userdata); }
+        fn s_release_response(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error> { return self.record("RELEASE RESPONSE", reference, ss_userdata); }
+        fn s_u_abort_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error> { return self.record("ABORT", reference, ss_userdata); }
+        fn functional_units(&self, _reference: u16) -> Option<FunctionalUnits> { return Some(FunctionalUnits::DUPLEX); }
+        fn tokens(&self, _reference: u16) -> Option<Tokens> { return None; }
+        fn s_token_give_request(&mut self, _reference: u16, _tokens: Tokens) -> Result<(), Error> { return unsupported(); }
+        fn s_token_please_request(&mut self, _reference: u16, _tokens: Tokens, _ss_userdata: &[u8]) -> Result<(), Error> { return unsupported(); }
+        fn s_sync_minor_request(&mut self, _reference: u16, _ss_userdata: &[u8]) -> Result<u32, Error> { return unsupported(); }
+        fn s_sync_minor_response(&mut self, _reference: u16, _serial_number: u32, _ss_userdata: &[u8]) -> Result<(), Error> { return unsupported(); }
+        fn s_sync_major_request(&mut self, _reference: u16, _ss_userdata: &[u8]) -> Result<u32, Error> { return unsupported(); }
+        fn s_sync_major_response(&mut self, _reference: u16, _ss_userdata: &[u8]) -> Result<(), Error> { return unsupported(); }
+        fn s_resynchronize_request(&mut self, _reference: u16, _resync_type: ResyncType, _serial_number: u32, _tokens: Tokens, _ss_userdata: &[u8]) -> Result<u32, Error> { return unsupported(); }
+        fn s_resynchronize_response(&mut self, _reference: u16, _ss_userdata: &[u8]) -> Result<(), Error> { return unsupported(); }
+        fn s_activity_start_request(&mut self, _reference: u16, _activity_identifier: &[u8], _ss_userdata: &[u8]) -> Result<(), Error> { return unsupported(); }
+        fn s_activity_resume_request(&mut self, _reference: u16, _activity_identifier: &[u8], _old_activity_identifier: &[u8], _serial_number: u32, _ss_userdata: &[u8]) -> Result<(), Error> { return unsupported(); }
+        fn s_activity_interrupt_request(&mut self, _reference: u16, _reason: u8) -> Result<(), Error> { return unsupported(); }
+        fn s_activity_interrupt_response(&mut self, _reference: u16) -> Result<(), Error> { return unsupported(); }
+        fn s_activity_discard_request(&mut self, _reference: u16, _reason: u8) -> Result<(), Error> { return unsupported(); }
+        fn s_activity_discard_response(&mut self, _reference: u16) -> Result<(), Error> { return unsupported(); }
+        fn s_activity_end_request(&mut self, _reference: u16, _ss_userdata: &[u8]) -> Result<u32, Error> { return unsupported(); }
+        fn s_activity_end_response(&mut self, _reference: u16, _ss_userdata: &[u8]) -> Result<(), Error> { return unsupported(); }
+    }
+
+    /// presentation entity bound to psel on top of the mock, handling ACSE only
+    struct Side {
+        service: Service<MockSs>,
+        p_user_from: rtrb::Consumer<PIndication>,
+        requests: Requests,
+    }
+
+    impl Side {
+        fn new(transfer_syntaxes: &[EncodingRules]) -> Self {
+            let requests = Requests::default();
+            let (_, ss_user_from) = rtrb::RingBuffer::new(1);
+            let config = Config { transfer_syntaxes: transfer_syntaxes.to_vec() };
+            let (mut service, p_user_from) = Service::new(MockSs { requests: requests.clone() }, ss_user_from, config, 16);
+            service.bind(b"tsel", b"ssel", b"psel").unwrap();
+            service.register_abstract_syntax(&oid(ACSE));
+            return Side { service: service, p_user_from: p_user_from, requests: requests };
+        }
+
+        /// the S indication as if from the reader thread - returns the P indication, if any
+        fn deliver(&mut self, indication: SIndication) -> Option<PIndication> {
+            let mut actions = Actions::default();
+            self.service.entity.lock().unwrap().s_indication(indication, &mut actions);
+            execute(&self.service.ss, &self.service.p_user_to_wakeup, actions).unwrap();
+            return self.p_user_from.pop().ok();
+        }
+
+        /// the last S request, primitive and SS user data
+        fn sent(&self) -> (&'static str, Vec<u8>) {
+            let (primitive, _, data) = self.requests.lock().unwrap().pop().expect("S request");
+            return (primitive, data);
+        }
+    }
+
+    fn oid(value: &str) -> ObjectIdentifier {
+        return value.parse().unwrap();
+    }
+
+    fn session_address() -> SessionAddress {
+        return SessionAddress::new(TransportAddress::new(NetworkAddress::Ip(SocketAddr::from(([127, 0, 0, 1], 102))), b"tsel"), b"ssel");
+    }
+
+    fn proposal(contexts: &[(i64, &str)], default_context: Option<&str>) -> ConnectProposal {
+        return ConnectProposal {
+            contexts: contexts.iter().map(|(identifier, name)| (*identifier, oid(name))).collect(),
+            default_context: default_context.map(oid),
+            functional_units: FunctionalUnits::DUPLEX,
+        };
+    }
+
+    /// P-CONNECT request, its CP into the acceptor - returns the P-CONNECT indication resp. what the acceptor refused with
+    fn connect(initiator: &mut Side, acceptor: &mut Side, proposal: &ConnectProposal, pdvs: &[Pdv]) -> Option<PIndication> {
+        let address = PresentationAddress::new(session_address(), b"psel");
+        assert_eq!(initiator.service.p_connect_request(&address, b"tsel", b"ssel", b"psel", proposal, pdvs).unwrap(), 1);
+        let (primitive, data) = initiator.sent();
+        assert_eq!(primitive, "CONNECT");
+        return acceptor.deliver(SIndication::ConnectIndication {
+            reference: 1,
+            calling_address: session_address(),
+            called_address: session_address(),
+            functional_units: FunctionalUnits::DUPLEX,
+            data: data,
+        });
+    }
+
+    /// P-CONNECT response, its CPA into the initiator - returns the P-CONNECT confirm
+    fn accept(initiator: &mut Side, acceptor: &mut Side, user_rejected: &[i64], pdvs: &[Pdv]) -> PIndication {
+        acceptor.service.p_connect_response(1, user_rejected, FunctionalUnits::DUPLEX, pdvs).unwrap();
+        let (primitive, data) = acceptor.sent();
+        assert_eq!(primitive, "ACCEPT");
+        return initiator.deliver(SIndication::ConnectConfirm { reference: 1, functional_units: FunctionalUnits::DUPLEX, data: data }).expect("P-CONNECT confirm");
+    }
+
+    /// P-DATA request, its TD into the other side - returns the PDVs indicated
+    fn transfer(from: &mut Side, to: &mut Side, pdvs: &[Pdv]) -> Vec<Pdv> {
+        from.service.p_data_request(1, pdvs).unwrap();
+        let (primitive, data) = from.sent();
+        assert_eq!(primitive, "DATA");
+        match to.deliver(SIndication::DataIndication { reference: 1, data: data }) {
+            Some(PIndication::DataIndication { pdvs, .. }) => { return pdvs; },
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn context_negotiation() {
+        // the acceptor takes the first transfer syntax out of the proposed ones it supports
+        let mut initiator = Side::new(&[EncodingRules::Cer, EncodingRules::Ber]);
+        let mut acceptor = Side::new(&[EncodingRules::Ber, EncodingRules::Cer]);
+        let long = OctetString(vec![0x55; 2500]);
+        let pdv = Pdv::new(Some(1), &oid(ACSE), EncodingRules::Cer, &long).unwrap();
+        let contexts = match connect(&mut initiator, &mut acceptor, &proposal(&[(1, ACSE), (3, MMS)], None), &[pdv]) {
+            Some(PIndication::ConnectIndication { contexts, pdvs, default_context: None, .. }) => {
+                assert_eq!(pdvs.len(), 1);
+                assert_eq!((pdvs[0].context_identifier, pdvs[0].rules), (Some(1), EncodingRules::Cer));
+                assert_eq!(pdvs[0].decode::<OctetString>().unwrap(), long);
+                contexts
+            },
+            other => panic!("{:?}", other)
+        };
+        assert_eq!((contexts[0].result, contexts[0].transfer_syntax), (ppdu::RESULT_ACCEPTANCE, Some(EncodingRules::Cer)));
+        assert_eq!((contexts[1].result, contexts[1].provider_reason), (ppdu::RESULT_PROVIDER_REJECTION, Some(ppdu::CONTEXT_REASON_ABSTRACT_SYNTAX_NOT_SUPPORTED)));
+        match accept(&mut initiator, &mut acceptor, &[], &[contexts[0].pdv(&true).unwrap()]) {
+            PIndication::ConnectConfirm { contexts, pdvs, .. } => {
+                assert_eq!(contexts.iter().map(|context| context.result).collect::<Vec<i64>>(), [ppdu::RESULT_ACCEPTANCE, ppdu::RESULT_PROVIDER_REJECTION]);
+                assert!(pdvs[0].decode::<bool>().unwrap());
+            },
+            other => panic!("{:?}", other)
+        }
+        // data in CER, the long string in segments
+        let context = initiator.service.contexts(1).unwrap()[0].clone();
+        initiator.service.p_data_request(1, &[context.pdv(&long).unwrap()]).unwrap();
+        let (_, data) = initiator.sent();
+        match UserData::decode(&data, EncodingRules::Ber).unwrap() {
+            UserData::FullyEncoded(list) => match &list[0].presentation_data_values {
+                PresentationDataValues::OctetAligned(OctetString(octets)) => assert_eq!(&octets[..2], [0x24, 0x80]),
+                other => panic!("{:?}", other)
+            },
+            other => panic!("{:?}", other)
+        }
+        assert_eq!(transfer(&mut initiator, &mut acceptor, &[context.pdv(&long).unwrap()])[0].decode::<OctetString>().unwrap(), long);
+        // not in the transfer syntax of the context, resp. of a rejected context
+        let der = Pdv::new(Some(1), &oid(ACSE), EncodingRules::Der, &long).unwrap();
+        assert_eq!(initiator.service.p_data_request(1, &[der]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let rejected = Pdv::new(Some(3), &oid(MMS), EncodingRules::Cer, &Null).unwrap();
+        assert_eq!(initiator.service.p_data_request(1, &[rejected]).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn partial_rejection() {
+        let mut initiator = Side::new(&[EncodingRules::Ber]);
+        let mut acceptor = Side::new(&[EncodingRules::Der]);
+        // no transfer syntax in common - the provider rejects the context
+        let contexts = match connect(&mut initiator, &mut acceptor, &proposal(&[(1, ACSE)], None), &[]) {
+            Some(PIndication::ConnectIndication { contexts, .. }) => contexts,
+            other => panic!("{:?}", other)
+        };
+        assert_eq!(contexts[0].provider_reason, Some(ppdu::CONTEXT_REASON_TRANSFER_SYNTAXES_NOT_SUPPORTED));
+        assert!(contexts[0].pdv(&Null).is_err());
+        // the PS user rejects one of those the provider accepted
+        let mut initiator = Side::new(&[EncodingRules::Ber]);
+        let mut acceptor = Side::new(&[EncodingRules::Ber]);
+        connect(&mut initiator, &mut acceptor, &proposal(&[(1, ACSE), (3, ACSE), (5, MMS)], None), &[]).unwrap();
+        assert_eq!(acceptor.service.p_connect_response(1, &[5], FunctionalUnits::DUPLEX, &[]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        match accept(&mut initiator, &mut acceptor, &[3], &[]) {
+            PIndication::ConnectConfirm { contexts, .. } => {
+                let results: Vec<i64> = contexts.iter().map(|context| context.result).collect();
+                assert_eq!(results, [ppdu::RESULT_ACCEPTANCE, ppdu::RESULT_USER_REJECTION, ppdu::RESULT_PROVIDER_REJECTION]);
+                assert_eq!(contexts[1].transfer_syntax, None);
+            },
+            other => panic!("{:?}", other)
+        }
+        let pdv = Pdv::new(Some(3), &oid(ACSE), EncodingRules::Ber, &Null).unwrap();
+        assert!(initiator.service.p_data_request(1, &[pdv.clone()]).is_err());
+        assert!(acceptor.service.p_data_request(1, &[pdv]).is_err());
+        let pdv = Pdv::new(Some(1), &oid(ACSE), EncodingRules::Ber, &7i64).unwrap();
+        assert_eq!(transfer(&mut acceptor, &mut initiator, &[pdv.clone()]), [pdv]);
+    }
+
+    #[test]
+    fn default_context() {
+        let mut initiator = Side::new(&[EncodingRules::Ber]);
+        let mut acceptor = Side::new(&[EncodingRules::Ber]);
+        let pdv = Pdv::new(None, &oid(ACSE), EncodingRules::Ber, &7i64).unwrap();
+        match connect(&mut initiator, &mut acceptor, &proposal(&[], Some(ACSE)), &[pdv.clone()]) {
+            Some(PIndication::ConnectIndication { contexts, default_context, pdvs, .. }) => {
+                assert!(contexts.is_empty());
+                assert_eq!(default_context, Some(oid(ACSE)));
+                assert_eq!(pdvs, [pdv.clone()]);
+            },
+            other => panic!("{:?}", other)
+        }
+        accept(&mut initiator, &mut acceptor, &[], &[pdv.clone()]);
+        // simply encoded while the defined context set is empty
+        initiator.service.p_data_request(1, &[pdv.clone(), pdv.clone()]).unwrap();
+        assert_eq!(UserData::decode(&initiator.sent().1, EncodingRules::Ber).unwrap(), UserData::SimplyEncoded(OctetString(vec![0x02, 0x01, 0x07, 0x02, 0x01, 0x07])));
+        assert_eq!(transfer(&mut initiator, &mut acceptor, &[pdv.clone(), pdv.clone()]).len(), 2);
+        let other = Pdv::new(None, &oid(MMS), EncodingRules::Ber, &7i64).unwrap();
+        assert!(initiator.service.p_data_request(1, &[other]).is_err());
+        // a default context the acceptor does not handle - the initiator gets the CPR
+        let mut initiator = Side::new(&[EncodingRules::Ber]);
+        let mut acceptor = Side::new(&[EncodingRules::Ber]);
+        assert!(connect(&mut initiator, &mut acceptor, &proposal(&[], Some(MMS)), &[]).is_none());
+        let (primitive, data) = acceptor.sent();
+        assert_eq!(primitive, "REFUSE");
+        match initiator.deliver(SIndication::ConnectReject { reference: 1, reason: session::spdu::REASON_REJECTED_BY_USER, data: data }) {
+            Some(PIndication::ConnectReject { provider_reason, .. }) => assert_eq!(provider_reason, Some(ppdu::REASON_DEFAULT_CONTEXT_NOT_SUPPORTED)),
+            other => panic!("{:?}", other)
+        }
+        assert!(initiator.service.contexts(1).is_none());
+    }
+
+    #[test]
+    fn connect_refused() {
+        let mut initiator = Side::new(&[EncodingRules::Ber]);
+        let mut acceptor = Side::new(&[EncodingRules::Ber]);
+        let address = PresentationAddress::new(session_address(), b"other");
+        initiator.service.p_connect_request(&address, b"tsel", b"ssel", b"psel", &proposal(&[(1, ACSE)], None), &[]).unwrap();
+        let (_, data) = initiator.sent();
+        assert!(acceptor.deliver(SIndication::ConnectIndication { reference: 1, calling_address: session_address(), called_address: session_address(), functional_units: FunctionalUnits::DUPLEX, data: data }).is_none());
+        let (primitive, data) = acceptor.sent();
+        assert_eq!(primitive, "REFUSE");
+        assert_eq!(CprPpdu::decode(&data, EncodingRules::Ber).unwrap().provider_reason, Some(ppdu::REASON_CALLED_ADDRESS_UNKNOWN));
+        // rejected by the PS user, with user data
+        let mut initiator = Side::new(&[EncodingRules::Ber]);
+        let mut acceptor = Side::new(&[EncodingRules::Ber]);
+        connect(&mut initiator, &mut acceptor, &proposal(&[(1, ACSE)], None), &[]).unwrap();
+        let pdv = Pdv::new(Some(1), &oid(ACSE), EncodingRules::Ber, &Null).unwrap();
+        acceptor.service.p_connect_reject(1, &[pdv.clone()]).unwrap();
+        let (_, data) = acceptor.sent();
+        match initiator.deliver(SIndication::ConnectReject { reference: 1, reason: session::spdu::REASON_REJECTED_BY_USER, data: data }) {
+            Some(PIndication::ConnectReject { provider_reason: None, pdvs, .. }) => assert_eq!(pdvs, [pdv]),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn aborts() {
+        let mut initiator = Side::new(&[EncodingRules::Ber]);
+        let mut acceptor = Side::new(&[EncodingRules::Ber]);
+        connect(&mut initiator, &mut acceptor, &proposal(&[(1, ACSE)], None), &[]).unwrap();
+        accept(&mut initiator, &mut acceptor, &[], &[]);
+        // ARU with user data, with the contexts listed
+        let pdv = Pdv::new(Some(1), &oid(ACSE), EncodingRules::Ber, &Null).unwrap();
+        initiator.service.p_u_abort_request(1, &[pdv.clone()]).unwrap();
+        let (primitive, data) = initiator.sent();
+        assert_eq!(primitive, "ABORT");
+        match AbortPpdu::decode(&data, EncodingRules::Ber).unwrap() {
+            AbortPpdu::UserAbort(aru) => assert_eq!(aru.presentation_context_identifier_list.unwrap()[0].transfer_syntax_name, transfer_syntax_name(EncodingRules::Ber)),
+            other => panic!("{:?}", other)
+        }
+        match acceptor.deliver(SIndication::UAbortIndication { reference: 1, data: data }) {
+            Some(PIndication::UAbortIndication { pdvs, .. }) => assert_eq!(pdvs, [pdv]),
+            other => panic!("{:?}", other)
+        }
+        assert!(initiator.service.contexts(1).is_none() && acceptor.service.contexts(1).is_none());
+        // an invalid TD - ARP towards the peer, P-P-ABORT towards the PS user
+        let mut initiator = Side::new(&[EncodingRules::Ber]);
+        let mut acceptor = Side::new(&[EncodingRules::Ber]);
+        connect(&mut initiator, &mut acceptor, &proposal(&[(1, ACSE)], None), &[]).unwrap();
+        accept(&mut initiator, &mut acceptor, &[], &[]);
+        match acceptor.deliver(SIndication::DataIndication { reference: 1, data: vec![0x04, 0x00] }) {
+            Some(PIndication::PAbortIndication { reason, .. }) => assert_eq!(reason, ppdu::ABORT_INVALID_PPDU_PARAMETER_VALUE),
+            other => panic!("{:?}", other)
+        }
+        let (primitive, data) = acceptor.sent();
+        assert_eq!(primitive, "ABORT");
+        match initiator.deliver(SIndication::UAbortIndication { reference: 1, data: data }) {
+            Some(PIndication::PAbortIndication { reason, .. }) => assert_eq!(reason, ppdu::ABORT_INVALID_PPDU_PARAMETER_VALUE),
+            other => panic!("{:?}", other)
+        }
+    }
+}
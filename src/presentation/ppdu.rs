@@ -0,0 +1,331 @@
+// X.226 8 presentation protocol data units in normal mode, as the ASN.1 of X.226 8.2 - encoded with BER
+//TODO X.410-1984 mode, context management (AC/ACA PPDUs), typed data, capability data and resynchronize (RS/RSA PPDUs)
+
+use crate::asn1::{Any, BitString, ObjectIdentifier, OctetString};
+use crate::{asn1_choice, asn1_sequence, asn1_set};
+
+// Mode-selector
+pub const MODE_X410_1984: i64 = 0;
+pub const MODE_NORMAL: i64 = 1;
+
+// Result of a presentation context resp. the default context
+pub const RESULT_ACCEPTANCE: i64 = 0;
+pub const RESULT_USER_REJECTION: i64 = 1;
+pub const RESULT_PROVIDER_REJECTION: i64 = 2;
+
+// provider-reason of a Result-list item
+pub const CONTEXT_REASON_NOT_SPECIFIED: i64 = 0;
+pub const CONTEXT_REASON_ABSTRACT_SYNTAX_NOT_SUPPORTED: i64 = 1;
+pub const CONTEXT_REASON_TRANSFER_SYNTAXES_NOT_SUPPORTED: i64 = 2;
+pub const CONTEXT_REASON_LOCAL_LIMIT_ON_DCS_EXCEEDED: i64 = 3;
+
+// Provider-reason of the CPR PPDU
+pub const REASON_NOT_SPECIFIED: i64 = 0;
+pub const REASON_TEMPORARY_CONGESTION: i64 = 1;
+pub const REASON_LOCAL_LIMIT_EXCEEDED: i64 = 2;
+pub const REASON_CALLED_ADDRESS_UNKNOWN: i64 = 3;
+pub const REASON_PROTOCOL_VERSION_NOT_SUPPORTED: i64 = 4;
+pub const REASON_DEFAULT_CONTEXT_NOT_SUPPORTED: i64 = 5;
+pub const REASON_USER_DATA_NOT_READABLE: i64 = 6;
+pub const REASON_NO_PSAP_AVAILABLE: i64 = 7;
+
+// Abort-reason of the ARP PPDU
+pub const ABORT_REASON_NOT_SPECIFIED: i64 = 0;
+pub const ABORT_UNRECOGNIZED_PPDU: i64 = 1;
+pub const ABORT_UNEXPECTED_PPDU: i64 = 2;
+pub const ABORT_UNEXPECTED_SESSION_SERVICE_PRIMITIVE: i64 = 3;
+pub const ABORT_UNRECOGNIZED_PPDU_PARAMETER: i64 = 4;
+pub const ABORT_UNEXPECTED_PPDU_PARAMETER: i64 = 5;
+pub const ABORT_INVALID_PPDU_PARAMETER_VALUE: i64 = 6;
+
+/// Protocol-version, bit 0 is version-1
+pub const PROTOCOL_VERSION_1: usize = 0;
+
+asn1_set! {
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct ModeSelector {
+        [CONTEXT 0] IMPLICIT pub mode_value: i64,
+    }
+}
+
+impl ModeSelector {
+    pub fn normal() -> Self {
+        return ModeSelector { mode_value: MODE_NORMAL };
+    }
+}
+
+asn1_sequence! {
+    /// item of the Presentation-context-definition-list
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct ContextDefinition {
+        pub presentation_context_identifier: i64,
+        pub abstract_syntax_name: ObjectIdentifier,
+        pub transfer_syntax_name_list: Vec<ObjectIdentifier>,
+    }
+}
+
+asn1_sequence! {
+    /// item of the Presentation-context-definition-result-list
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct ContextResult {
+        [CONTEXT 0] IMPLICIT pub result: i64,
+        [CONTEXT 1] IMPLICIT pub transfer_syntax_name: Option<ObjectIdentifier>,
+        [CONTEXT 2] IMPLICIT pub provider_reason: Option<i64>,
+    }
+}
+
+asn1_sequence! {
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct DefaultContextName {
+        [CONTEXT 0] IMPLICIT pub abstract_syntax_name: ObjectIdentifier,
+        [CONTEXT 1] IMPLICIT pub transfer_syntax_name: ObjectIdentifier,
+    }
+}
+
+asn1_choice! {
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum PresentationDataValues {
+        [CONTEXT 0] EXPLICIT SingleAsn1Type(Any),
+        [CONTEXT 1] IMPLICIT OctetAligned(OctetString),
+        [CONTEXT 2] IMPLICIT Arbitrary(BitString),
+    }
+}
+
+asn1_sequence! {
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct PdvList {
+        pub transfer_syntax_name: Option<ObjectIdentifier>,
+        pub presentation_context_identifier: i64,
+        pub presentation_data_values: PresentationDataValues,
+    }
+}
+
+asn1_choice! {
+    /// User-data - simply encoded in the default context, fully encoded otherwise
+    /// NOTE: also what TD PPDUs and the SS user data of S-RELEASE are
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum UserData {
+        [APPLICATION 0] IMPLICIT SimplyEncoded(OctetString),
+        [APPLICATION 1] IMPLICIT FullyEncoded(Vec<PdvList>),
+    }
+}
+
+asn1_sequence! {
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CpNormalModeParameters {
+        [CONTEXT 0] IMPLICIT pub protocol_version: Option<BitString>,
+        [CONTEXT 1] IMPLICIT pub calling_presentation_selector: Option<OctetString>,
+        [CONTEXT 2] IMPLICIT pub called_presentation_selector: Option<OctetString>,
+        [CONTEXT 4] IMPLICIT pub presentation_context_definition_list: Option<Vec<ContextDefinition>>,
+        [CONTEXT 6] IMPLICIT pub default_context_name: Option<DefaultContextName>,
+        [CONTEXT 8] IMPLICIT pub presentation_requirements: Option<BitString>,
+        [CONTEXT 9] IMPLICIT pub user_session_requirements: Option<BitString>,
+        [CONTEXT 11] EXPLICIT pub protocol_options: Option<BitString>,
+        [CONTEXT 12] EXPLICIT pub initiators_nominated_context: Option<i64>,
+        [CONTEXT 14] EXPLICIT pub extensions: Option<Any>,
+        pub user_data: Option<UserData>,
+    }
+}
+
+asn1_set! {
+    /// CP PPDU, the CP-type
+    /// NOTE: X.410-1984 mode parameters are not known, so such a CP does not decode
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CpPpdu {
+        [CONTEXT 0] IMPLICIT pub mode_selector: ModeSelector,
+        [CONTEXT 2] IMPLICIT pub normal_mode_parameters: Option<CpNormalModeParameters>,
+    }
+}
+
+asn1_sequence! {
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CpaNormalModeParameters {
+        [CONTEXT 0] IMPLICIT pub protocol_version: Option<BitString>,
+        [CONTEXT 3] IMPLICIT pub responding_presentation_selector: Option<OctetString>,
+        [CONTEXT 5] IMPLICIT pub presentation_context_definition_result_list: Option<Vec<ContextResult>>,
+        [CONTEXT 8] IMPLICIT pub presentation_requirements: Option<BitString>,
+        [CONTEXT 9] IMPLICIT pub user_session_requirements: Option<BitString>,
+        [CONTEXT 11] EXPLICIT pub protocol_options: Option<BitString>,
+        [CONTEXT 13] EXPLICIT pub responders_nominated_context: Option<i64>,
+        pub user_data: Option<UserData>,
+    }
+}
+
+asn1_set! {
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CpaPpdu {
+        [CONTEXT 0] IMPLICIT pub mode_selector: ModeSelector,
+        [CONTEXT 2] IMPLICIT pub normal_mode_parameters: Option<CpaNormalModeParameters>,
+    }
+}
+
+asn1_sequence! {
+    /// CPR PPDU, the normal-mode-parameters alternative - the X.410-1984 one is an RTORJapdu, a SET
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CprPpdu {
+        [CONTEXT 0] IMPLICIT pub protocol_version: Option<BitString>,
+        [CONTEXT 3] IMPLICIT pub responding_presentation_selector: Option<OctetString>,
+        [CONTEXT 5] IMPLICIT pub presentation_context_definition_result_list: Option<Vec<ContextResult>>,
+        [CONTEXT 7] IMPLICIT pub default_context_result: Option<i64>,
+        [CONTEXT 10] IMPLICIT pub provider_reason: Option<i64>,
+        pub user_data: Option<UserData>,
+    }
+}
+
+asn1_sequence! {
+    /// item of the Presentation-context-identifier-list
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct ContextIdentifier {
+        pub presentation_context_identifier: i64,
+        pub transfer_syntax_name: ObjectIdentifier,
+    }
+}
+
+asn1_sequence! {
+    /// ARU PPDU, the normal-mode-parameters alternative
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct AruPpdu {
+        [CONTEXT 0] IMPLICIT pub presentation_context_identifier_list: Option<Vec<ContextIdentifier>>,
+        pub user_data: Option<UserData>,
+    }
+}
+
+asn1_sequence! {
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct ArpPpdu {
+        [CONTEXT 0] IMPLICIT pub provider_reason: Option<i64>,
+        [CONTEXT 1] IMPLICIT pub event_identifier: Option<i64>,
+    }
+}
+
+asn1_choice! {
+    /// Abort-type - what the SS user data of S-U-ABORT carries
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum AbortPpdu {
+        [CONTEXT 0] IMPLICIT UserAbort(AruPpdu),
+        ProviderAbort(ArpPpdu),
+    }
+}
+
+/// Protocol-version with only version-1
+pub fn protocol_version_1() -> BitString {
+    return BitString::from_bits(&[true]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asn1::{Asn1, Element, EncodingRules, Tag};
+
+    fn oid(value: &str) -> ObjectIdentifier {
+        return value.parse().unwrap();
+    }
+
+    fn user_data() -> UserData {
+        return UserData::FullyEncoded(vec![
+            PdvList {
+                transfer_syntax_name: Some(oid("2.1.1")),
+                presentation_context_identifier: 1,
+                presentation_data_values: PresentationDataValues::SingleAsn1Type(Any(Element::primitive(Tag::INTEGER, vec![0x05]))),
+            },
+            PdvList {
+                transfer_syntax_name: None,
+                presentation_context_identifier: 3,
+                presentation_data_values: PresentationDataValues::OctetAligned(OctetString(vec![0x02, 0x01, 0x05])),
+            },
+        ]);
+    }
+
+    fn round_trip<T: Asn1 + PartialEq + std::fmt::Debug>(ppdu: &T) -> Vec<u8> {
+        let encoding = ppdu.encode(EncodingRules::Ber).unwrap();
+        assert_eq!(&T::decode(&encoding, EncodingRules::Ber).unwrap(), ppdu);
+        return encoding;
+    }
+
+    #[test]
+    fn cp_round_trip() {
+        let cp = CpPpdu {
+            mode_selector: ModeSelector::normal(),
+            normal_mode_parameters: Some(CpNormalModeParameters {
+                protocol_version: Some(protocol_version_1()),
+                calling_presentation_selector: Some(OctetString(vec![0, 1])),
+                called_presentation_selector: Some(OctetString(vec![0, 2])),
+                presentation_context_definition_list: Some(vec![
+                    ContextDefinition { presentation_context_identifier: 1, abstract_syntax_name: oid("2.2.1.0.1"), transfer_syntax_name_list: vec![oid("2.1.1")] },
+                    ContextDefinition { presentation_context_identifier: 3, abstract_syntax_name: oid("1.0.9506.2.1"), transfer_syntax_name_list: vec![oid("2.1.2.0"), oid("2.1.1")] },
+                ]),
+                default_context_name: Some(DefaultContextName { abstract_syntax_name: oid("2.2.1.0.1"), transfer_syntax_name: oid("2.1.1") }),
+                presentation_requirements: None,
+                user_session_requirements: Some(BitString::from_bits(&[false, true])),
+                protocol_options: None,
+                initiators_nominated_context: Some(1),
+                extensions: None,
+                user_data: Some(user_data()),
+            }),
+        };
+        let encoding = round_trip(&cp);
+        // X.226 8.2 - CP-type is a SET, the mode-selector [0] with the mode-value [0]
+        assert_eq!(encoding[0], 0x31);
+        assert_eq!(&encoding[2..7], [0xa0, 0x03, 0x80, 0x01, 0x01]);
+        // mode selector only, as in X.410-1984 mode without its parameters
+        round_trip(&CpPpdu { mode_selector: ModeSelector { mode_value: MODE_X410_1984 }, normal_mode_parameters: None });
+    }
+
+    #[test]
+    fn cpa_and_cpr_round_trip() {
+        let results = vec![
+            ContextResult { result: RESULT_ACCEPTANCE, transfer_syntax_name: Some(oid("2.1.1")), provider_reason: None },
+            ContextResult { result: RESULT_PROVIDER_REJECTION, transfer_syntax_name: None, provider_reason: Some(CONTEXT_REASON_ABSTRACT_SYNTAX_NOT_SUPPORTED) },
+        ];
+        let cpa = CpaPpdu {
+            mode_selector: ModeSelector::normal(),
+            normal_mode_parameters: Some(CpaNormalModeParameters {
+                protocol_version: None,
+                responding_presentation_selector: Some(OctetString(vec![0, 2])),
+                presentation_context_definition_result_list: Some(results.clone()),
+                presentation_requirements: None,
+                user_session_requirements: None,
+                protocol_options: None,
+                responders_nominated_context: Some(3),
+                user_data: Some(UserData::SimplyEncoded(OctetString(vec![0x05, 0x00]))),
+            }),
+        };
+        assert_eq!(round_trip(&cpa)[0], 0x31);
+        let cpr = CprPpdu {
+            protocol_version: Some(protocol_version_1()),
+            responding_presentation_selector: None,
+            presentation_context_definition_result_list: Some(results),
+            default_context_result: Some(RESULT_PROVIDER_REJECTION),
+            provider_reason: Some(REASON_DEFAULT_CONTEXT_NOT_SUPPORTED),
+            user_data: None,
+        };
+        assert_eq!(round_trip(&cpr)[0], 0x30);
+        // the normal mode parameters of a CPA are none of a CP
+        assert!(CpPpdu::decode(&cpa.encode(EncodingRules::Ber).unwrap(), EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn abort_round_trip() {
+        let aru = AbortPpdu::UserAbort(AruPpdu {
+            presentation_context_identifier_list: Some(vec![ContextIdentifier { presentation_context_identifier: 1, transfer_syntax_name: oid("2.1.1") }]),
+            user_data: Some(user_data()),
+        });
+        assert_eq!(round_trip(&aru)[0], 0xa0);
+        let arp = AbortPpdu::ProviderAbort(ArpPpdu { provider_reason: Some(ABORT_INVALID_PPDU_PARAMETER_VALUE), event_identifier: Some(7) });
+        assert_eq!(round_trip(&arp), [0x30, 0x06, 0x80, 0x01, 0x06, 0x81, 0x01, 0x07]);
+        round_trip(&AbortPpdu::UserAbort(AruPpdu { presentation_context_identifier_list: None, user_data: None }));
+        // the ARU user data is simply resp. fully encoded
+        assert!(AbortPpdu::decode(&[0xa0, 0x03, 0x04, 0x01, 0x00], EncodingRules::Ber).is_err());
+    }
+
+    #[test]
+    fn user_data_round_trip() {
+        assert_eq!(round_trip(&UserData::SimplyEncoded(OctetString(vec![0x05, 0x00]))), [0x40, 0x02, 0x05, 0x00]);
+        assert_eq!(round_trip(&user_data())[0], 0x61);
+        round_trip(&UserData::FullyEncoded(vec![PdvList {
+            transfer_syntax_name: None,
+            presentation_context_identifier: 5,
+            presentation_data_values: PresentationDataValues::Arbitrary(BitString::from_bits(&[true, false, true])),
+        }]));
+    }
+}
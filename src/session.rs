@@ -0,0 +1,1166 @@
+pub mod spdu;
+mod spm;
+
+use std::{collections::{HashMap, HashSet}, io::{Error, ErrorKind}, ops::{BitAnd, BitOr}, sync::{Arc, Mutex}, thread::{self, Thread}, time::Duration};
+
+use crate::t::{TIndication, TransportAddress, TransportService};
+use spdu::{Parameter, Spdu};
+use spm::{Output, Spm};
+
+/// all tokens initially with the initiator of the session connection
+const INITIAL_TOKEN_SETTING: u8 = 0x00;
+const INITIAL_SERIAL_NUMBER: u32 = 1;
+
+/// how often the reader waits for the SS user to make room in its queue - meanwhile transport flow control holds back the peer
+const BACKPRESSURE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// X.215 functional units, as the bits of the session user requirements parameter of X.225 8.3.1.17
+/// NOTE: the kernel functional unit is always there and has no bit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FunctionalUnits(pub u16);
+
+impl FunctionalUnits {
+    pub const NONE: FunctionalUnits = FunctionalUnits(0);
+    pub const HALF_DUPLEX: FunctionalUnits = FunctionalUnits(0x0001);
+    pub const DUPLEX: FunctionalUnits = FunctionalUnits(0x0002);
+    pub const EXPEDITED_DATA: FunctionalUnits = FunctionalUnits(0x0004);
+    pub const MINOR_SYNCHRONIZE: FunctionalUnits = FunctionalUnits(0x0008);
+    pub const MAJOR_SYNCHRONIZE: FunctionalUnits = FunctionalUnits(0x0010);
+    pub const RESYNCHRONIZE: FunctionalUnits = FunctionalUnits(0x0020);
+    pub const ACTIVITY_MANAGEMENT: FunctionalUnits = FunctionalUnits(0x0040);
+    pub const NEGOTIATED_RELEASE: FunctionalUnits = FunctionalUnits(0x0080);
+    pub const CAPABILITY_DATA: FunctionalUnits = FunctionalUnits(0x0100);
+    pub const EXCEPTIONS: FunctionalUnits = FunctionalUnits(0x0200);
+    pub const TYPED_DATA: FunctionalUnits = FunctionalUnits(0x0400);
+    pub const SYMMETRIC_SYNCHRONIZE: FunctionalUnits = FunctionalUnits(0x0800);
+    pub const DATA_SEPARATION: FunctionalUnits = FunctionalUnits(0x1000);
+    /// X.225 8.3.1.17 - if a CN resp. AC has no session user requirements parameter
+    pub const DEFAULT: FunctionalUnits = FunctionalUnits(0x0349);
+
+    pub fn contains(self, other: FunctionalUnits) -> bool {
+        return self.0 & other.0 == other.0;
+    }
+
+    pub fn intersects(self, other: FunctionalUnits) -> bool {
+        return self.0 & other.0 != 0;
+    }
+
+    pub fn without(self, other: FunctionalUnits) -> FunctionalUnits {
+        return FunctionalUnits(self.0 & !other.0);
+    }
+
+    fn from_parameter(value: Option<&[u8]>) -> FunctionalUnits {
+        match value {
+            Some([high, low]) => { return FunctionalUnits(u16::from_be_bytes([*high, *low])); },
+            _ => { return FunctionalUnits::DEFAULT; }
+        }
+    }
+
+    /// exactly one of half-duplex and duplex, as selected by the accepting SS user
+    fn is_selection(self) -> bool {
+        return self.contains(FunctionalUnits::HALF_DUPLEX) != self.contains(FunctionalUnits::DUPLEX);
+    }
+}
+
+impl BitOr for FunctionalUnits {
+    type Output = FunctionalUnits;
+    fn bitor(self, other: FunctionalUnits) -> FunctionalUnits {
+        return FunctionalUnits(self.0 | other.0);
+    }
+}
+
+impl BitAnd for FunctionalUnits {
+    type Output = FunctionalUnits;
+    fn bitand(self, other: FunctionalUnits) -> FunctionalUnits {
+        return FunctionalUnits(self.0 & other.0);
+    }
+}
+
+/// X.215 tokens, as the bits of the token item parameter of X.225 8.3.3.3
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tokens(pub u8);
+
+impl Tokens {
+    pub const NONE: Tokens = Tokens(0);
+    pub const DATA: Tokens = Tokens(0x01);
+    pub const MINOR_SYNCHRONIZE: Tokens = Tokens(0x04);
+    pub const MAJOR_ACTIVITY: Tokens = Tokens(0x10);
+    pub const RELEASE: Tokens = Tokens(0x40);
+    /// in the order of the token setting item, two bits each
+    const ALL: [Tokens; 4] = [Tokens::DATA, Tokens::MINOR_SYNCHRONIZE, Tokens::MAJOR_ACTIVITY, Tokens::RELEASE];
+
+    pub fn contains(self, other: Tokens) -> bool {
+        return self.0 & other.0 == other.0;
+    }
+
+    pub fn intersects(self, other: Tokens) -> bool {
+        return self.0 & other.0 != 0;
+    }
+
+    pub fn without(self, other: Tokens) -> Tokens {
+        return Tokens(self.0 & !other.0);
+    }
+
+    /// X.215 - which tokens there are on a session connection with the given functional units
+    pub fn available(functional_units: FunctionalUnits) -> Tokens {
+        let mut tokens = Tokens::NONE;
+        if functional_units.contains(FunctionalUnits::HALF_DUPLEX) {
+            tokens = tokens | Tokens::DATA;
+        }
+        if functional_units.contains(FunctionalUnits::MINOR_SYNCHRONIZE) {
+            tokens = tokens | Tokens::MINOR_SYNCHRONIZE;
+        }
+        if functional_units.intersects(FunctionalUnits::MAJOR_SYNCHRONIZE | FunctionalUnits::ACTIVITY_MANAGEMENT) {
+            tokens = tokens | Tokens::MAJOR_ACTIVITY;
+        }
+        if functional_units.contains(FunctionalUnits::NEGOTIATED_RELEASE) {
+            tokens = tokens | Tokens::RELEASE;
+        }
+        return tokens;
+    }
+
+    /// token setting item value putting these tokens on one side and the others on the other side
+    fn setting(self, side: u8, other_side: u8) -> u8 {
+        let mut setting = 0;
+        for (position, token) in Tokens::ALL.iter().enumerate() {
+            setting |= (if self.contains(*token) { side } else { other_side }) << (2 * position);
+        }
+        return setting;
+    }
+
+    /// which tokens a token setting item puts on the given side
+    fn from_setting(setting: u8, side: u8) -> Tokens {
+        let mut tokens = Tokens::NONE;
+        for (position, token) in Tokens::ALL.iter().enumerate() {
+            if (setting >> (2 * position)) & 0x03 == side {
+                tokens = tokens | *token;
+            }
+        }
+        return tokens;
+    }
+}
+
+impl BitOr for Tokens {
+    type Output = Tokens;
+    fn bitor(self, other: Tokens) -> Tokens {
+        return Tokens(self.0 | other.0);
+    }
+}
+
+impl BitAnd for Tokens {
+    type Output = Tokens;
+    fn bitand(self, other: Tokens) -> Tokens {
+        return Tokens(self.0 & other.0);
+    }
+}
+
+/// X.215 S-RESYNCHRONIZE type, as in the resync type parameter of X.225 8.3.27
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResyncType {
+    Restart = 0,
+    Abandon = 1,
+    Set = 2,
+}
+
+impl ResyncType {
+    fn from_u8(value: u8) -> Option<ResyncType> {
+        match value {
+            0 => { return Some(ResyncType::Restart); },
+            1 => { return Some(ResyncType::Abandon); },
+            2 => { return Some(ResyncType::Set); },
+            _ => { return None; }
+        }
+    }
+
+    /// X.225 7.14.3 resynchronize collisions - abandon wins over set, set over restart
+    fn precedence(self) -> u8 {
+        match self {
+            ResyncType::Abandon => { return 2; },
+            ResyncType::Set => { return 1; },
+            ResyncType::Restart => { return 0; }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// what this SPM supports - proposals and acceptances are cut down to it
+    pub functional_units: FunctionalUnits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config {
+            functional_units: FunctionalUnits::HALF_DUPLEX | FunctionalUnits::DUPLEX | FunctionalUnits::EXPEDITED_DATA
+                | FunctionalUnits::MINOR_SYNCHRONIZE | FunctionalUnits::MAJOR_SYNCHRONIZE | FunctionalUnits::RESYNCHRONIZE
+                | FunctionalUnits::ACTIVITY_MANAGEMENT | FunctionalUnits::NEGOTIATED_RELEASE,
+        };
+    }
+}
+
+/// X.215 session address = transport address and session selector
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionAddress {
+    pub transport_address: TransportAddress,
+    pub ssel: Vec<u8>,
+}
+
+impl SessionAddress {
+    pub fn new(transport_address: TransportAddress, ssel: &[u8]) -> Self {
+        return SessionAddress { transport_address: transport_address, ssel: ssel.to_vec() };
+    }
+}
+
+/// X.215 indication and confirm primitives from the SS provider towards the SS user
+/// NOTE: the session connection is identified by the reference of the transport connection it runs on
+#[derive(Debug)]
+pub enum SIndication {
+    /// the functional units are what the SPM can accept of the proposal - the S-CONNECT response selects from them
+    ConnectIndication {
+        reference: u16,
+        calling_address: SessionAddress,
+        called_address: SessionAddress,
+        functional_units: FunctionalUnits,
+        data: Vec<u8>,
+    },
+    /// S-CONNECT confirm, accepted
+    ConnectConfirm {
+        reference: u16,
+        functional_units: FunctionalUnits,
+        data: Vec<u8>,
+    },
+    /// S-CONNECT confirm, rejected by the called SS user or the SS provider - reason as in spdu::REASON_*
+    ConnectReject {
+        reference: u16,
+        reason: u8,
+        data: Vec<u8>,
+    },
+    DataIndication {
+        reference: u16,
+        data: Vec<u8>,
+    },
+    ExpeditedDataIndication {
+        reference: u16,
+        data: Vec<u8>,
+    },
+    ReleaseIndication {
+        reference: u16,
+        data: Vec<u8>,
+    },
+    ReleaseConfirm {
+        reference: u16,
+        data: Vec<u8>,
+    },
+    UAbortIndication {
+        reference: u16,
+        data: Vec<u8>,
+    },
+    /// reason as in spdu::ABORT_*
+    PAbortIndication {
+        reference: u16,
+        reason: u8,
+    },
+    GiveTokensIndication {
+        reference: u16,
+        tokens: Tokens,
+    },
+    PleaseTokensIndication {
+        reference: u16,
+        tokens: Tokens,
+        data: Vec<u8>,
+    },
+    SyncMinorIndication {
+        reference: u16,
+        serial_number: u32,
+        data: Vec<u8>,
+    },
+    SyncMinorConfirm {
+        reference: u16,
+        serial_number: u32,
+        data: Vec<u8>,
+    },
+    SyncMajorIndication {
+        reference: u16,
+        serial_number: u32,
+        data: Vec<u8>,
+    },
+    SyncMajorConfirm {
+        reference: u16,
+        data: Vec<u8>,
+    },
+    /// the tokens are the ones the requestor gets, the others go to the acceptor
+    ResynchronizeIndication {
+        reference: u16,
+        resync_type: ResyncType,
+        serial_number: u32,
+        tokens: Tokens,
+        data: Vec<u8>,
+    },
+    ResynchronizeConfirm {
+        reference: u16,
+        serial_number: u32,
+        data: Vec<u8>,
+    },
+    ActivityStartIndication {
+        reference: u16,
+        activity_identifier: Vec<u8>,
+        data: Vec<u8>,
+    },
+    ActivityResumeIndication {
+        reference: u16,
+        activity_identifier: Vec<u8>,
+        old_activity_identifier: Vec<u8>,
+        serial_number: u32,
+        data: Vec<u8>,
+    },
+    ActivityInterruptIndication {
+        reference: u16,
+        reason: u8,
+    },
+    ActivityInterruptConfirm {
+        reference: u16,
+    },
+    ActivityDiscardIndication {
+        reference: u16,
+        reason: u8,
+    },
+    ActivityDiscardConfirm {
+        reference: u16,
+    },
+    ActivityEndIndication {
+        reference: u16,
+        serial_number: u32,
+        data: Vec<u8>,
+    },
+    ActivityEndConfirm {
+        reference: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// X.215 session service
+/// NOTE: the SS user is told about indications and confirms via the queue it got when creating the service
+pub trait SessionService {
+    /// starts the SS provider - the given thread, if any, is unparked when there are new indications for the SS user
+    fn run(&mut self, s_user_wakeup: Option<Thread>);
+    /// incoming session connections to this session selector on this TSAP selector are indicated to the SS user, to all others refused
+    fn bind(&mut self, tsel: &[u8], ssel: &[u8]) -> Result<(), Error>;
+    fn unbind(&mut self, tsel: &[u8], ssel: &[u8]);
+    /// S-CONNECT request - returns the reference identifying the new session connection, S-CONNECT confirm follows
+    fn s_connect_request(&mut self, called_address: &SessionAddress, calling_tsel: &[u8], calling_ssel: &[u8], functional_units: FunctionalUnits, ss_userdata: &[u8]) -> Result<u16, Error>;
+    /// S-CONNECT response, accepting with the given functional units out of the ones indicated
+    fn s_connect_response(&mut self, reference: u16, functional_units: FunctionalUnits, ss_userdata: &[u8]) -> Result<(), Error>;
+    /// S-CONNECT response, rejecting - reason as in spdu::REASON_* for the SS user
+    fn s_connect_reject(&mut self, reference: u16, reason: u8, ss_userdata: &[u8]) -> Result<(), Error>;
+    fn s_data_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error>;
+    fn s_expedited_data_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error>;
+    /// S-RELEASE request, orderly release
+    fn s_release_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error>;
+    fn s_release_response(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error>;
+    fn s_u_abort_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error>;
+    /// what has been negotiated for the session connection - the proposal until it is established
+    fn functional_units(&self, reference: u16) -> Option<FunctionalUnits>;
+    /// the tokens this SS user owns
+    fn tokens(&self, reference: u16) -> Option<Tokens>;
+    fn s_token_give_request(&mut self, reference: u16, tokens: Tokens) -> Result<(), Error>;
+    fn s_token_please_request(&mut self, reference: u16, tokens: Tokens, ss_userdata: &[u8]) -> Result<(), Error>;
+    /// returns the serial number of the new minor synchronization point
+    fn s_sync_minor_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<u32, Error>;
+    /// confirms all minor synchronization points up to the given one
+    fn s_sync_minor_response(&mut self, reference: u16, serial_number: u32, ss_userdata: &[u8]) -> Result<(), Error>;
+    /// returns the serial number of the new major synchronization point
+    fn s_sync_major_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<u32, Error>;
+    fn s_sync_major_response(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error>;
+    /// the tokens are the ones the requestor gets - returns the serial number resynchronized to, chosen by the SPM for abandon
+    fn s_resynchronize_request(&mut self, reference: u16, resync_type: ResyncType, serial_number: u32, tokens: Tokens, ss_userdata: &[u8]) -> Result<u32, Error>;
+    fn s_resynchronize_response(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error>;
+    fn s_activity_start_request(&mut self, reference: u16, activity_identifier: &[u8], ss_userdata: &[u8]) -> Result<(), Error>;
+    /// continues an interrupted activity after the given serial number
+    fn s_activity_resume_request(&mut self, reference: u16, activity_identifier: &[u8], old_activity_identifier: &[u8], serial_number: u32, ss_userdata: &[u8]) -> Result<(), Error>;
+    fn s_activity_interrupt_request(&mut self, reference: u16, reason: u8) -> Result<(), Error>;
+    fn s_activity_interrupt_response(&mut self, reference: u16) -> Result<(), Error>;
+    fn s_activity_discard_request(&mut self, reference: u16, reason: u8) -> Result<(), Error>;
+    fn s_activity_discard_response(&mut self, reference: u16) -> Result<(), Error>;
+    /// returns the serial number of the major synchronization point ending the activity
+    fn s_activity_end_request(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<u32, Error>;
+    fn s_activity_end_response(&mut self, reference: u16, ss_userdata: &[u8]) -> Result<(), Error>;
+}
+
+/// X.225 A.4 states of the SPM, the ones within data transfer are those of the Spm
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// STA01B - waiting for the T-CONNECT confirm
+    WaitForTransportConnect,
+    /// STA01C - transport connection accepted, waiting for the CN
+    WaitForConnect,
+    /// STA02A - CN sent, waiting for AC or RF
+    WaitForAccept,
+    /// STA08 - CN indicated, waiting for the S-CONNECT response
+    WaitForConnectResponse,
+    /// STA713 - data transfer
+    DataTransfer,
+    /// STA03 - FN sent, waiting for DN
+    WaitForDisconnect,
+    /// STA09 - FN indicated, waiting for the S-RELEASE response
+    WaitForReleaseResponse,
+    /// STA16 - AB, RF or DN sent, waiting for the T-DISCONNECT indication
+    WaitForTransportDisconnect,
+}
+
+struct Connection {
+    state: State,
+    /// proposed resp. negotiated
+    functional_units: FunctionalUnits,
+    /// selected one, or the proposed ones as bits until then
+    version: u8,
+    local_ssel: Vec<u8>,
+    remote_ssel: Vec<u8>,
+    /// SS user data of the S-CONNECT request until the transport connection is there to send the CN
+    connect_data: Vec<u8>,
+    /// calling and called transport address of an incoming session connection
+    transport_addresses: Option<(TransportAddress, TransportAddress)>,
+    /// whether the transport connection has expedited data - needed for the expedited data functional unit
+    transport_expedited: bool,
+    /// both SS users asked for release at the same time
+    release_collision: bool,
+    /// X.225 8.3.1.13 token setting item, proposed resp. selected
+    token_setting: u8,
+    /// X.225 8.3.1.12 - serial number of the first synchronization point
+    initial_serial_number: u32,
+    /// tokens, synchronization and activities, from data transfer on
+    spm: Option<Spm>,
+}
+
+impl Connection {
+    fn new(state: State, functional_units: FunctionalUnits, local_ssel: &[u8], remote_ssel: &[u8]) -> Self {
+        return Connection {
+            state: state,
+            functional_units: functional_units,
+            version: spdu::VERSION_1 | spdu::VERSION_2,
+            local_ssel: local_ssel.to_vec(),
+            remote_ssel: remote_ssel.to_vec(),
+            connect_data: vec![],
+            transport_addresses: None,
+            transport_expedited: false,
+            release_collision: false,
+            token_setting: INITIAL_TOKEN_SETTING,
+            initial_serial_number: INITIAL_SERIAL_NUMBER,
+            spm: None,
+        };
+    }
+}
+
+/// what the SPM asks from the transport service, done once the entity lock is released
+enum TRequest {
+    ConnectResponse(u16),
+    Data(u16, Vec<u8>),
+    ExpeditedData(u16, Vec<u8>),
+    Disconnect(u16),
+}
+
+#[derive(Default)]
+struct Actions {
+    requests: Vec<TRequest>,
+    wakeup: bool,
+}
+
+impl Actions {
+    /// NOTE: the SS user data was checked against the length limits of the SPDUs already
+    fn send(&mut self, reference: u16, spdus: &[Spdu]) {
+        let mut tsdu = vec![];
+        if let Err(e) = spdu::into_tsdu(spdus, &mut tsdu) {
+            warn!("session {}: SPDU not sent: {}", reference, e);
+            return;
+        }
+        self.requests.push(TRequest::Data(reference, tsdu));
+    }
+}
+
+/// the session protocol machines, shared between the SS user calls and the reader thread
+struct Entity {
+    config: Config,
+    connections: HashMap<u16, Connection>,
+    /// TSAP selector and the session selectors bound on it
+    bound: HashMap<Vec<u8>, HashSet<Vec<u8>>>,
+    s_user_to: rtrb::Producer<SIndication>,
+}
+
+/// X.225 session protocol over any transport service, one transport connection per session connection
+pub struct Service<T: TransportService + Send + 'static> {
+    ts: Arc<Mutex<T>>,
+    entity: Arc<Mutex<Entity>>,
+    ts_user_from: Arc<Mutex<rtrb::Consumer<TIndication>>>,
+    s_user_to_wakeup: Arc<Mutex<Option<Thread>>>,
+}
+
+impl<T: TransportService + Send + 'static> Service<T> {
+    /// takes the TS provider and the queue of its indications - returns the queue of indications towards the SS user
+    pub fn new(ts: T, ts_user_from: rtrb::Consumer<TIndication>, config: Config, queue_capacity: usize) -> (Self, rtrb::Consumer<SIndication>) {
+        let (s_user_to, s_user_from) = rtrb::RingBuffer::new(queue_capacity);
+        let service = Service {
+            ts: Arc::new(Mutex::new(ts)),
+            entity: Arc::new(Mutex::new(Entity {
+                config: config,
+                connections: HashMap::new(),
+                bound: HashMap::new(),
+                s_user_to: s_user_to,
+            })),
+            ts_user_from: Arc::new(Mutex::new(ts_user_from)),
+            s_user_to_wakeup: Arc::new(Mutex::new(None)),
+        };
+        return (service, s_user_from);
+    }
+
+    /// runs the given SPM operation, then what it asks from the transport service
+    fn request<R>(&self, operation: impl FnOnce(&mut Entity, &mut Actions) -> Result<R, Error>) -> Result<R, Error> {
+        let mut actions = Actions::default();
+        let result = operation(&mut self.entity.lock().expect("failed to lock entity"), &mut actions)?;
+        execute(&self.ts, &self.s_user_to_wakeup, actions)?;
+        return Ok(result);
+    }
+}
+
+impl<T: TransportService + Send + 'static> SessionService for Service<T> {
+    /// starts the reader thread and the TS provider
+    fn run(&mut self, s_user_wakeup: Option<Thread>) {
+        *self.s_user_to_wakeup.lock().expect("failed to lock s_user_to_wakeup") = s_user_wakeup;
+
+        // read T indications from TS
+        let entity_arc = self.entity.clone();
+        let ts_arc = self.ts.clone();
+        let ts_user_from_arc = self.ts_user_from.clone();
+        let s_user_to_wakeup_arc = self.s_user_to_wakeup.clone();
+        let ts2ss_consumer = thread::Builder::new().name("S <- T".to_string()).spawn(move || {
+            // keep permanent lock on this
+            let mut ts_user_from = ts_user_from_arc.lock().expect("failed to lock ts_user_from");
+            loop {
+                // NOTE: while the SS user does not keep up, the indications stay in the TS queue
+                while entity_arc.lock().expect("failed to lock entity").s_user_to.slots() == 0 {
+                    thread::sleep(BACKPRESSURE_INTERVAL);
+                }
+                let indication = match ts_user_from.pop() {
+                    Ok(indication) => indication,
+                    Err(_) => {
+                        thread::park(); // wait for unpark wakeup call from TS
+                        continue;
+                    }
+                };
+                // NOTE: what the transport connection offers is final once it is established
+                let transport_expedited = match &indication {
+                    TIndication::ConnectIndication { reference, .. } | TIndication::ConnectConfirm { reference, .. } => {
+                        ts_arc.lock().expect("failed to lock ts").connection_options(*reference).map(|options| options.expedited).unwrap_or(false)
+                    },
+                    _ => false
+                };
+                let mut actions = Actions::default();
+                entity_arc.lock().expect("failed to lock entity").t_indication(indication, transport_expedited, &mut actions);
+                if let Err(e) = execute(&ts_arc, &s_user_to_wakeup_arc, actions) {
+                    info!("session: transport request failed: {}", e);
+                }
+            }
+        }).expect("failed to start thread");
+        self.ts.lock().expect("failed to lock ts").run(Some(ts2ss_consumer.thread().clone()));
+    }
+
+    fn bind(&mut self, tsel: &[u8], ssel: &[u8]) -> Result<(), Error> {
+        let mut entity = self.entity.lock().expect("failed to lock entity");
+        if !entity.bound.contains_key(tsel) {
+            self.ts.lock().expect("failed to lock ts").bind(tsel)?;
+        }
+        if !entity.bound.entry(tsel.to_vec()).or_default().insert(ssel.to_vec()) {
+            return Err(Error::new(ErrorKind::AddrInUse, "session selector already bound"));
+        }
+        return Ok(());
+    }
+
+    fn unbind(&mut self, tsel: &[u8], ssel: &[u8]) {
+        let mut entity = self.entity.lock().expect("failed to lock entity");
+        if let Some(ssels) = entity.bound.get_mut(tsel) {
+            ssels.remove(ssel);
+            if ssels.is_empty() {
+                entity.bound.remove(tsel);
+                self.ts.lock().expect("failed to lock ts").unbind(tsel);
+            }
+        }
+    }
+
+    fn s_connect_request(&mut self, called_address: &SessionAddress, calling_tsel: &[u8], calling_ssel: &[u8], functional_units: FunctionalUnits, data: &[u8]) -> Result<u16, Error> {
+        if data.len() > spdu::CONNECT_EXTENDED_DATA_MAXIMUM {
+            //TODO data overflow and OA SPDU, X.225 7.1.2
+            return Err(Error::new(ErrorKind::InvalidInput, "too much user data for CN SPDU"));
+        }
+        let mut entity = self.entity.lock().expect("failed to lock entity");
+        let functional_units = functional_units & entity.config.functional_units;
+        if !functional_units.intersects(FunctionalUnits::HALF_DUPLEX | FunctionalUnits::DUPLEX) {
+            return Err(Error::new(ErrorKind::InvalidInput, "neither half-duplex nor duplex proposed resp. supported"));
+        }
+        // NOTE: entity stays locked, so that the T-CONNECT confirm finds the connection
+        let reference = self.ts.lock().expect("failed to lock ts").t_connect_request(&called_address.transport_address, calling_tsel, &[])?;
+        let mut connection = Connection::new(State::WaitForTransportConnect, functional_units, calling_ssel, &called_address.ssel);
+        connection.connect_data = data.to_vec();
+        entity.connections.insert(reference, connection);
+        return Ok(reference);
+    }
+
+    fn s_connect_response(&mut self, reference: u16, functional_units: FunctionalUnits, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.connect_response(reference, functional_units, data, actions));
+    }
+
+    fn s_connect_reject(&mut self, reference: u16, reason: u8, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.connect_reject(reference, reason, data, actions));
+    }
+
+    fn s_data_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.data_request(reference, data, actions));
+    }
+
+    fn s_expedited_data_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.expedited_data_request(reference, data, actions));
+    }
+
+    fn s_release_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.release_request(reference, data, actions));
+    }
+
+    fn s_release_response(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.release_response(reference, data, actions));
+    }
+
+    fn s_u_abort_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.u_abort_request(reference, data, actions));
+    }
+
+    fn functional_units(&self, reference: u16) -> Option<FunctionalUnits> {
+        return self.entity.lock().expect("failed to lock entity").connections.get(&reference).map(|connection| connection.functional_units);
+    }
+
+    fn tokens(&self, reference: u16) -> Option<Tokens> {
+        return self.entity.lock().expect("failed to lock entity").connections.get(&reference).and_then(|connection| connection.spm.as_ref()).map(|spm| spm.owned());
+    }
+
+    fn s_token_give_request(&mut self, reference: u16, tokens: Tokens) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.give_tokens_request(tokens, output)));
+    }
+
+    fn s_token_please_request(&mut self, reference: u16, tokens: Tokens, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.please_tokens_request(tokens, data, output)));
+    }
+
+    fn s_sync_minor_request(&mut self, reference: u16, data: &[u8]) -> Result<u32, Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.sync_minor_request(data, output)));
+    }
+
+    fn s_sync_minor_response(&mut self, reference: u16, serial_number: u32, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.sync_minor_response(serial_number, data, output)));
+    }
+
+    fn s_sync_major_request(&mut self, reference: u16, data: &[u8]) -> Result<u32, Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.sync_major_request(data, output)));
+    }
+
+    fn s_sync_major_response(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.sync_major_response(data, output)));
+    }
+
+    fn s_resynchronize_request(&mut self, reference: u16, resync_type: ResyncType, serial_number: u32, tokens: Tokens, data: &[u8]) -> Result<u32, Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.resynchronize_request(resync_type, serial_number, tokens, data, output)));
+    }
+
+    fn s_resynchronize_response(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.resynchronize_response(data, output)));
+    }
+
+    fn s_activity_start_request(&mut self, reference: u16, activity_identifier: &[u8], data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.activity_start_request(activity_identifier, data, output)));
+    }
+
+    fn s_activity_resume_request(&mut self, reference: u16, activity_identifier: &[u8], old_activity_identifier: &[u8], serial_number: u32, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.activity_resume_request(activity_identifier, old_activity_identifier, serial_number, data, output)));
+    }
+
+    fn s_activity_interrupt_request(&mut self, reference: u16, reason: u8) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.activity_interrupt_request(reason, output)));
+    }
+
+    fn s_activity_interrupt_response(&mut self, reference: u16) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.activity_interrupt_response(output)));
+    }
+
+    fn s_activity_discard_request(&mut self, reference: u16, reason: u8) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.activity_discard_request(reason, output)));
+    }
+
+    fn s_activity_discard_response(&mut self, reference: u16) -> Result<(), Error> {
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.activity_discard_response(output)));
+    }
+
+    fn s_activity_end_request(&mut self, reference: u16, data: &[u8]) -> Result<u32, Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.activity_end_request(data, output)));
+    }
+
+    fn s_activity_end_response(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        check_user_data(data)?;
+        return self.request(|entity, actions| entity.spm_request(reference, actions, |spm, output| spm.activity_end_response(data, output)));
+    }
+}
+
+/// hands the requests to the TS provider and wakes up the SS user
+/// NOTE: done without the entity lock, since the TS provider may block, e.g. writing to TCP
+fn execute<T: TransportService>(ts: &Mutex<T>, s_user_to_wakeup: &Mutex<Option<Thread>>, actions: Actions) -> Result<(), Error> {
+    if actions.wakeup {
+        if let Some(wakeup) = s_user_to_wakeup.lock().expect("failed to lock s_user_to_wakeup").as_ref() {
+            wakeup.unpark();
+        }
+    }
+    let mut ts = ts.lock().expect("failed to lock ts");
+    for request in actions.requests {
+        match request {
+            TRequest::ConnectResponse(reference) => { ts.t_connect_response(reference, &[])?; },
+            TRequest::Data(reference, tsdu) => { ts.t_data_request(reference, &tsdu)?; },
+            TRequest::ExpeditedData(reference, tsdu) => { ts.t_expedited_data_request(reference, &tsdu)?; },
+            TRequest::Disconnect(reference) => {
+                // NOTE: the transport connection may be gone already
+                if let Err(e) = ts.t_disconnect_request(reference, &[]) {
+                    debug!("session: transport connection {} not disconnected: {}", reference, e);
+                }
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// user data parameter, if there is user data
+/// SS-user data going into the user data PGI unit, whose length is limited unlike the user information field of DT
+fn check_user_data(data: &[u8]) -> Result<(), Error> {
+    if data.len() > spdu::USER_DATA_MAXIMUM {
+        return Err(Error::new(ErrorKind::InvalidInput, "too much user data for SPDU"));
+    }
+    return Ok(());
+}
+
+fn user_data_parameter(code: u8, data: &[u8]) -> Vec<Parameter> {
+    if data.is_empty() {
+        return vec![];
+    }
+    return vec![Parameter::new(code, data)];
+}
+
+/// with token setting and initial serial number, as far as the functional units have tokens resp. synchronization
+fn connect_accept_item(connection: &Connection) -> Result<Parameter, Error> {
+    let mut parameters = vec![
+        Parameter::new(spdu::PI_PROTOCOL_OPTIONS, &[0]),
+        Parameter::new(spdu::PI_VERSION_NUMBER, &[connection.version]),
+    ];
+    if connection.functional_units.intersects(FunctionalUnits::MINOR_SYNCHRONIZE | FunctionalUnits::MAJOR_SYNCHRONIZE | FunctionalUnits::RESYNCHRONIZE) {
+        parameters.push(Parameter::new(spdu::PI_INITIAL_SERIAL_NUMBER, &spdu::serial_number_value(connection.initial_serial_number)));
+    }
+    if Tokens::available(connection.functional_units) != Tokens::NONE {
+        parameters.push(Parameter::new(spdu::PI_TOKEN_SETTING_ITEM, &[connection.token_setting]));
+    }
+    return Parameter::group(spdu::PGI_CONNECT_ACCEPT_ITEM, &parameters);
+}
+
+/// X.225 8.3.1.13 - where the acceptor may choose, this one leaves the tokens with the initiator
+fn resolve_token_setting(setting: u8) -> u8 {
+    let mut resolved = setting;
+    for position in 0..4 {
+        if (setting >> (2 * position)) & 0x03 == spdu::TOKEN_SETTING_ACCEPTOR_CHOOSES {
+            resolved &= !(0x03 << (2 * position));
+        }
+    }
+    return resolved;
+}
+
+impl Entity {
+    fn indicate(&mut self, actions: &mut Actions, indication: SIndication) {
+        if let Err(_) = self.s_user_to.push(indication) {
+            warn!("session: queue towards SS user full, indication lost");
+            return;
+        }
+        actions.wakeup = true;
+    }
+
+    fn connection(&mut self, reference: u16, state: State) -> Result<&mut Connection, Error> {
+        let connection = self.connections.get_mut(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such session connection"))?;
+        if connection.state != state {
+            return Err(Error::new(ErrorKind::InvalidInput, "not possible in the state of the session connection"));
+        }
+        return Ok(connection);
+    }
+
+    fn connect_response(&mut self, reference: u16, functional_units: FunctionalUnits, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::WaitForConnectResponse)?;
+        if !connection.functional_units.contains(functional_units) || !functional_units.is_selection() {
+            return Err(Error::new(ErrorKind::InvalidInput, "functional units not out of the indicated ones resp. not exactly one of half-duplex and duplex"));
+        }
+        connection.functional_units = functional_units;
+        let mut parameters = vec![
+            connect_accept_item(connection)?,
+            Parameter::new(spdu::PI_SESSION_USER_REQUIREMENTS, &functional_units.0.to_be_bytes()),
+            Parameter::new(spdu::PI_CALLED_SESSION_SELECTOR, &connection.local_ssel),
+        ];
+        parameters.extend(user_data_parameter(spdu::PGI_USER_DATA, data));
+        connection.spm = Some(Spm::new(reference, functional_units, false, connection.token_setting, connection.initial_serial_number));
+        connection.state = State::DataTransfer;
+        actions.send(reference, &[Spdu::new(spdu::SI_AC, parameters)]);
+        return Ok(());
+    }
+
+    fn connect_reject(&mut self, reference: u16, reason: u8, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::WaitForConnectResponse)?;
+        let version = connection.version;
+        connection.state = State::WaitForTransportDisconnect;
+        // reason user data only goes with its reason code
+        let mut reason_value = vec![if data.is_empty() { reason } else { spdu::REASON_REJECTED_BY_USER_WITH_DATA }];
+        reason_value.extend_from_slice(data);
+        self.refuse(reference, version, &reason_value, actions);
+        return Ok(());
+    }
+
+    /// RF SPDU, the peer releases the transport connection
+    fn refuse(&mut self, reference: u16, version: u8, reason: &[u8], actions: &mut Actions) {
+        actions.send(reference, &[Spdu::new(spdu::SI_RF, vec![
+            Parameter::new(spdu::PI_TRANSPORT_DISCONNECT, &[spdu::TRANSPORT_RELEASED]),
+            Parameter::new(spdu::PI_VERSION_NUMBER, &[version]),
+            Parameter::new(spdu::PI_REASON_CODE, reason),
+        ])]);
+    }
+
+    fn data_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        return self.spm_request(reference, actions, |spm, output| spm.data_request(data, output));
+    }
+
+    /// SS user request handled by the Spm of a session connection in data transfer
+    fn spm_request<R>(&mut self, reference: u16, actions: &mut Actions, operation: impl FnOnce(&mut Spm, &mut Output) -> Result<R, Error>) -> Result<R, Error> {
+        let connection = self.connection(reference, State::DataTransfer)?;
+        let mut output = Output::default();
+        let result = operation(connection.spm.as_mut().expect("SPM in data transfer"), &mut output)?;
+        self.output(reference, output, actions);
+        return Ok(result);
+    }
+
+    fn output(&mut self, reference: u16, output: Output, actions: &mut Actions) {
+        for tsdu in output.tsdus {
+            actions.send(reference, &tsdu);
+        }
+        for indication in output.indications {
+            self.indicate(actions, indication);
+        }
+    }
+
+    fn expedited_data_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::DataTransfer)?;
+        if !connection.functional_units.contains(FunctionalUnits::EXPEDITED_DATA) {
+            return Err(Error::new(ErrorKind::InvalidInput, "expedited data functional unit not selected"));
+        }
+        if data.len() > spdu::EXPEDITED_DATA_MAXIMUM {
+            return Err(Error::new(ErrorKind::InvalidInput, "too much user data for EX SPDU"));
+        }
+        let mut tsdu = vec![];
+        spdu::into_tsdu(&[Spdu::with_user_information(spdu::SI_EX, data)], &mut tsdu)?;
+        actions.requests.push(TRequest::ExpeditedData(reference, tsdu));
+        return Ok(());
+    }
+
+    fn release_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::DataTransfer)?;
+        if !connection.spm.as_ref().expect("SPM in data transfer").may_release() {
+            return Err(Error::new(ErrorKind::InvalidInput, "release needs all available tokens and no activity in progress"));
+        }
+        connection.state = State::WaitForDisconnect;
+        let mut parameters = vec![Parameter::new(spdu::PI_TRANSPORT_DISCONNECT, &[spdu::TRANSPORT_RELEASED])];
+        parameters.extend(user_data_parameter(spdu::PGI_USER_DATA, data));
+        actions.send(reference, &[Spdu::new(spdu::SI_FN, parameters)]);
+        return Ok(());
+    }
+
+    fn release_response(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connection(reference, State::WaitForReleaseResponse)?;
+        // NOTE: after a release collision, both wait for the DN of the other
+        connection.state = if connection.release_collision { State::WaitForDisconnect } else { State::WaitForTransportDisconnect };
+        actions.send(reference, &[Spdu::new(spdu::SI_DN, user_data_parameter(spdu::PGI_USER_DATA, data))]);
+        return Ok(());
+    }
+
+    fn u_abort_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connections.get_mut(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such session connection"))?;
+        if connection.state == State::WaitForTransportConnect {
+            self.connections.remove(&reference);
+            actions.requests.push(TRequest::Disconnect(reference));
+            return Ok(());
+        }
+        connection.state = State::WaitForTransportDisconnect;
+        //TODO timer in case the peer does not release the transport connection, X.225 7.9.3
+        let mut parameters = vec![Parameter::new(spdu::PI_TRANSPORT_DISCONNECT, &[spdu::TRANSPORT_RELEASED | spdu::ABORT_USER])];
+        parameters.extend(user_data_parameter(spdu::PGI_USER_DATA, data));
+        actions.send(reference, &[Spdu::new(spdu::SI_AB, parameters)]);
+        return Ok(());
+    }
+
+    /// aborts the session connection because of the peer, telling the SS user if it knows about it
+    fn protocol_error(&mut self, reference: u16, actions: &mut Actions) {
+        let state = match self.connections.get_mut(&reference) {
+            Some(connection) => {
+                let state = connection.state;
+                connection.state = State::WaitForTransportDisconnect;
+                state
+            },
+            None => { return; }
+        };
+        actions.send(reference, &[Spdu::new(spdu::SI_AB, vec![Parameter::new(spdu::PI_TRANSPORT_DISCONNECT, &[spdu::TRANSPORT_RELEASED | spdu::ABORT_PROTOCOL_ERROR])])]);
+        if state != State::WaitForConnect && state != State::WaitForTransportDisconnect {
+            self.indicate(actions, SIndication::PAbortIndication { reference: reference, reason: spdu::ABORT_PROTOCOL_ERROR });
+        }
+    }
+
+    fn t_indication(&mut self, indication: TIndication, transport_expedited: bool, actions: &mut Actions) {
+        match indication {
+            TIndication::ConnectIndication { reference, calling_address, called_address, .. } => {
+                // NOTE: only TSAP selectors with bound session selectors are bound at the TS provider
+                let mut connection = Connection::new(State::WaitForConnect, FunctionalUnits::NONE, &[], &[]);
+                connection.transport_addresses = Some((calling_address, called_address));
+                connection.transport_expedited = transport_expedited;
+                self.connections.insert(reference, connection);
+                actions.requests.push(TRequest::ConnectResponse(reference));
+            },
+            TIndication::ConnectConfirm { reference, .. } => {
+                let connection = match self.connections.get_mut(&reference) {
+                    Some(connection) if connection.state == State::WaitForTransportConnect => connection,
+                    _ => { debug!("session: unexpected T-CONNECT confirm for {}", reference); return; }
+                };
+                connection.transport_expedited = transport_expedited;
+                if !transport_expedited {
+                    connection.functional_units = connection.functional_units.without(FunctionalUnits::EXPEDITED_DATA);
+                }
+                let accept_item = match connect_accept_item(connection) {
+                    Ok(accept_item) => accept_item,
+                    Err(e) => { warn!("session {}: CN not sent: {}", reference, e); return; }
+                };
+                let data = std::mem::take(&mut connection.connect_data);
+                let mut parameters = vec![
+                    accept_item,
+                    Parameter::new(spdu::PI_SESSION_USER_REQUIREMENTS, &connection.functional_units.0.to_be_bytes()),
+                    Parameter::new(spdu::PI_CALLING_SESSION_SELECTOR, &connection.local_ssel),
+                    Parameter::new(spdu::PI_CALLED_SESSION_SELECTOR, &connection.remote_ssel),
+                ];
+                if data.len() > spdu::CONNECT_DATA_MAXIMUM {
+                    parameters.push(Parameter::new(spdu::PGI_EXTENDED_USER_DATA, &data));
+                } else {
+                    parameters.extend(user_data_parameter(spdu::PGI_USER_DATA, &data));
+                }
+                connection.state = State::WaitForAccept;
+                actions.send(reference, &[Spdu::new(spdu::SI_CN, parameters)]);
+            },
+            TIndication::DataIndication { reference, data } => {
+                self.tsdu_indication(reference, &data, actions);
+            },
+            TIndication::ExpeditedDataIndication { reference, data } => {
+                let connection = match self.connections.get(&reference) {
+                    Some(connection) => connection,
+                    None => { return; }
+                };
+                match spdu::from_tsdu(&data) {
+                    Ok(spdus) if spdus[0].si == spdu::SI_EX
+                        && connection.functional_units.contains(FunctionalUnits::EXPEDITED_DATA)
+                        && (connection.state == State::DataTransfer || connection.state == State::WaitForDisconnect) => {
+                        let data = spdus[0].user_information.clone();
+                        self.indicate(actions, SIndication::ExpeditedDataIndication { reference: reference, data: data });
+                    },
+                    _ => {
+                        info!("session: unexpected expedited TSDU on {}", reference);
+                        self.protocol_error(reference, actions);
+                    }
+                }
+            },
+            TIndication::DisconnectIndication { reference, .. } => {
+                let connection = match self.connections.remove(&reference) {
+                    Some(connection) => connection,
+                    None => { return; }
+                };
+                match connection.state {
+                    State::WaitForTransportDisconnect | State::WaitForConnect => {},
+                    State::WaitForTransportConnect | State::WaitForAccept => {
+                        self.indicate(actions, SIndication::ConnectReject { reference: reference, reason: spdu::REASON_SPM_NOT_SPECIFIED, data: vec![] });
+                    },
+                    _ => {
+                        self.indicate(actions, SIndication::PAbortIndication { reference: reference, reason: spdu::ABORT_NO_REASON });
+                    }
+                }
+            },
+        }
+    }
+
+    fn tsdu_indication(&mut self, reference: u16, tsdu: &[u8], actions: &mut Actions) {
+        let state = match self.connections.get(&reference) {
+            Some(connection) => connection.state,
+            None => { debug!("session: TSDU for unknown connection {}", reference); return; }
+        };
+        let spdus = match spdu::from_tsdu(tsdu) {
+            Ok(spdus) => spdus,
+            Err(e) => {
+                info!("session: invalid SPDU on {}: {}", reference, e);
+                self.protocol_error(reference, actions);
+                return;
+            }
+        };
+        if state == State::WaitForTransportDisconnect {
+            debug!("session: ignoring SPDU {} on {} while waiting for T-DISCONNECT", spdus[0].si, reference);
+            return;
+        }
+        // everything but release and abort, which come alone, is for the Spm
+        let release_or_abort = spdus.len() == 1 && [spdu::SI_FN, spdu::SI_DN, spdu::SI_AB].contains(&spdus[0].si);
+        if (state == State::DataTransfer || state == State::WaitForDisconnect) && !release_or_abort {
+            let mut output = Output::default();
+            let spm = self.connections.get_mut(&reference).and_then(|connection| connection.spm.as_mut()).expect("SPM in data transfer");
+            match spm.receive(&spdus, &mut output) {
+                Ok(()) => { self.output(reference, output, actions); },
+                Err(e) => {
+                    info!("session: {} on {}", e, reference);
+                    self.protocol_error(reference, actions);
+                }
+            }
+            return;
+        }
+        if spdus.len() == 2 {
+            info!("session: unexpected SPDUs {} and {} on {}", spdus[0].si, spdus[1].si, reference);
+            self.protocol_error(reference, actions);
+            return;
+        }
+        let spdu = &spdus[0];
+        match (state, spdu.si) {
+            (_, spdu::SI_AB) => { self.abort_indication(reference, state, spdu, actions); },
+            (State::WaitForConnect, spdu::SI_CN) => { self.connect_indication(reference, spdu, actions); },
+            (State::WaitForAccept, spdu::SI_AC) => { self.accept_indication(reference, spdu, actions); },
+            (State::WaitForAccept, spdu::SI_RF) => {
+                let reason = spdu.parameter(spdu::PI_REASON_CODE).unwrap_or(&[spdu::REASON_SPM_NOT_SPECIFIED]);
+                let (reason, data) = (reason.first().copied().unwrap_or(spdu::REASON_SPM_NOT_SPECIFIED), reason.get(1..).unwrap_or(&[]).to_vec());
+                self.connections.remove(&reference);
+                actions.requests.push(TRequest::Disconnect(reference));
+                self.indicate(actions, SIndication::ConnectReject { reference: reference, reason: reason, data: data });
+            },
+            (State::DataTransfer, spdu::SI_FN) | (State::WaitForDisconnect, spdu::SI_FN) => {
+                let connection = self.connections.get_mut(&reference).expect("session connection");
+                // NOTE: a release collision is only possible without tokens
+                if !connection.spm.as_ref().expect("SPM in data transfer").peer_may_release() {
+                    info!("session: FN on {} while owning tokens resp. within an activity", reference);
+                    self.protocol_error(reference, actions);
+                    return;
+                }
+                //TODO refusing the release with NF under negotiated release, X.225 7.7.1
+                connection.release_collision = state == State::WaitForDisconnect;
+                connection.state = State::WaitForReleaseResponse;
+                let data = spdu.user_data().to_vec();
+                self.indicate(actions, SIndication::ReleaseIndication { reference: reference, data: data });
+            },
+            (State::WaitForDisconnect, spdu::SI_DN) => {
+                // the side having sent the FN releases the transport connection
+                self.connections.remove(&reference);
+                actions.requests.push(TRequest::Disconnect(reference));
+                let data = spdu.user_data().to_vec();
+                self.indicate(actions, SIndication::ReleaseConfirm { reference: reference, data: data });
+            },
+            _ => {
+                info!("session: unexpected SPDU {} on {} in {:?}", spdu.si, reference, state);
+                self.protocol_error(reference, actions);
+            }
+        }
+    }
+
+    fn connect_indication(&mut self, reference: u16, cn: &Spdu, actions: &mut Actions) {
+        let config = self.config;
+        let called_ssel = cn.parameter(spdu::PI_CALLED_SESSION_SELECTOR).unwrap_or(&[]).to_vec();
+        let calling_ssel = cn.parameter(spdu::PI_CALLING_SESSION_SELECTOR).unwrap_or(&[]).to_vec();
+        let connection = self.connections.get_mut(&reference).expect("session connection");
+        let (calling_address, called_address) = connection.transport_addresses.clone().expect("transport addresses of incoming connection");
+        // X.225 7.1.4 - the highest version both support
+        let proposed_version = cn.parameter_octet(spdu::PI_VERSION_NUMBER).unwrap_or(spdu::VERSION_1);
+        let version = if proposed_version & spdu::VERSION_2 != 0 { spdu::VERSION_2 } else if proposed_version & spdu::VERSION_1 != 0 { spdu::VERSION_1 } else { 0 };
+        connection.version = version;
+        let reason = if version == 0 {
+            Some(spdu::REASON_VERSION_NOT_SUPPORTED)
+        } else if !self.bound.get(&called_address.tsel).map(|ssels| ssels.contains(&called_ssel)).unwrap_or(false) {
+            Some(spdu::REASON_SELECTOR_UNKNOWN)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            info!("session: refusing CN for session selector {:?} on {}, reason {:#04x}", called_ssel, reference, reason);
+            let version = if version == 0 { spdu::VERSION_1 } else { version };
+            connection.state = State::WaitForTransportDisconnect;
+            self.refuse(reference, version, &[reason], actions);
+            return;
+        }
+        // X.225 7.1.4 - what can be accepted of the proposal
+        let mut functional_units = FunctionalUnits::from_parameter(cn.parameter(spdu::PI_SESSION_USER_REQUIREMENTS)) & config.functional_units;
+        if !connection.transport_expedited {
+            functional_units = functional_units.without(FunctionalUnits::EXPEDITED_DATA);
+        }
+        if !functional_units.intersects(FunctionalUnits::HALF_DUPLEX | FunctionalUnits::DUPLEX) {
+            info!("session: refusing CN on {}, neither half-duplex nor duplex possible", reference);
+            connection.state = State::WaitForTransportDisconnect;
+            self.refuse(reference, version, &[spdu::REASON_SPM_NOT_SPECIFIED], actions);
+            return;
+        }
+        connection.functional_units = functional_units;
+        connection.token_setting = resolve_token_setting(cn.parameter_octet(spdu::PI_TOKEN_SETTING_ITEM).unwrap_or(INITIAL_TOKEN_SETTING));
+        connection.initial_serial_number = cn.parameter(spdu::PI_INITIAL_SERIAL_NUMBER).and_then(spdu::parse_serial_number).unwrap_or(INITIAL_SERIAL_NUMBER);
+        connection.local_ssel = called_ssel.clone();
+        connection.remote_ssel = calling_ssel.clone();
+        connection.state = State::WaitForConnectResponse;
+        self.indicate(actions, SIndication::ConnectIndication {
+            reference: reference,
+            calling_address: SessionAddress::new(calling_address, &calling_ssel),
+            called_address: SessionAddress::new(called_address, &called_ssel),
+            functional_units: functional_units,
+            data: cn.user_data().to_vec(),
+        });
+    }
+
+    fn accept_indication(&mut self, reference: u16, ac: &Spdu, actions: &mut Actions) {
+        let connection = self.connections.get_mut(&reference).expect("session connection");
+        let functional_units = FunctionalUnits::from_parameter(ac.parameter(spdu::PI_SESSION_USER_REQUIREMENTS));
+        let version = ac.parameter_octet(spdu::PI_VERSION_NUMBER).unwrap_or(spdu::VERSION_1);
+        if !connection.functional_units.contains(functional_units) || !functional_units.is_selection() || (version != spdu::VERSION_1 && version != spdu::VERSION_2) {
+            info!("session: AC on {} selects functional units {:#06x} resp. version {} not proposed", reference, functional_units.0, version);
+            self.protocol_error(reference, actions);
+            return;
+        }
+        connection.functional_units = functional_units;
+        connection.version = version;
+        let token_setting = ac.parameter_octet(spdu::PI_TOKEN_SETTING_ITEM).unwrap_or(connection.token_setting);
+        let initial_serial_number = ac.parameter(spdu::PI_INITIAL_SERIAL_NUMBER).and_then(spdu::parse_serial_number).unwrap_or(connection.initial_serial_number);
+        connection.spm = Some(Spm::new(reference, functional_units, true, token_setting, initial_serial_number));
+        connection.state = State::DataTransfer;
+        self.indicate(actions, SIndication::ConnectConfirm { reference: reference, functional_units: functional_units, data: ac.user_data().to_vec() });
+    }
+
+    fn abort_indication(&mut self, reference: u16, state: State, ab: &Spdu, actions: &mut Actions) {
+        let transport_disconnect = ab.parameter_octet(spdu::PI_TRANSPORT_DISCONNECT).unwrap_or(spdu::TRANSPORT_RELEASED);
+        self.connections.remove(&reference);
+        //TODO keeping the transport connection and AA, X.225 7.9.4
+        actions.requests.push(TRequest::Disconnect(reference));
+        if state == State::WaitForConnect {
+            return;
+        }
+        if transport_disconnect & spdu::ABORT_USER != 0 {
+            self.indicate(actions, SIndication::UAbortIndication { reference: reference, data: ab.user_data().to_vec() });
+        } else {
+            self.indicate(actions, SIndication::PAbortIndication { reference: reference, reason: transport_disconnect & !spdu::TRANSPORT_RELEASED });
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use std::io::{Error, ErrorKind};
+
+// X.225 8.3 SPDU identifiers
+// NOTE: some are the same for different SPDUs, told apart by category resp. position in the TSDU
+pub const SI_CN: u8 = 13;
+pub const SI_AC: u8 = 14;
+pub const SI_RF: u8 = 12;
+pub const SI_FN: u8 = 9;
+pub const SI_DN: u8 = 10;
+pub const SI_NF: u8 = 8;
+pub const SI_AB: u8 = 25;
+pub const SI_AA: u8 = 26;
+/// category 2 data transfer, the same as give tokens
+pub const SI_DT: u8 = 1;
+/// category 0 give tokens
+pub const SI_GT: u8 = 1;
+/// category 0 please tokens
+pub const SI_PT: u8 = 2;
+pub const SI_EX: u8 = 5;
+pub const SI_MIP: u8 = 49;
+pub const SI_MIA: u8 = 50;
+/// major sync point, the same as activity end - told apart by the sync type item
+pub const SI_MAP: u8 = 41;
+pub const SI_MAA: u8 = 42;
+pub const SI_RS: u8 = 53;
+pub const SI_RA: u8 = 34;
+pub const SI_AS: u8 = 45;
+pub const SI_AR: u8 = 29;
+/// category 2 activity interrupt, the same as abort
+pub const SI_AI: u8 = 25;
+pub const SI_AIA: u8 = 26;
+pub const SI_AD: u8 = 57;
+pub const SI_ADA: u8 = 58;
+pub const SI_AE: u8 = 41;
+pub const SI_AEA: u8 = 42;
+
+// X.225 8.3 parameter identifiers resp. parameter group identifiers
+pub const PGI_CONNECTION_IDENTIFIER: u8 = 1;
+pub const PGI_CONNECT_ACCEPT_ITEM: u8 = 5;
+pub const PI_SYNC_TYPE_ITEM: u8 = 15;
+pub const PI_TOKEN_ITEM: u8 = 16;
+pub const PI_TRANSPORT_DISCONNECT: u8 = 17;
+pub const PI_PROTOCOL_OPTIONS: u8 = 19;
+pub const PI_SESSION_USER_REQUIREMENTS: u8 = 20;
+pub const PI_TSDU_MAXIMUM_SIZE: u8 = 21;
+pub const PI_VERSION_NUMBER: u8 = 22;
+pub const PI_INITIAL_SERIAL_NUMBER: u8 = 23;
+pub const PI_ENCLOSURE_ITEM: u8 = 25;
+pub const PI_TOKEN_SETTING_ITEM: u8 = 26;
+pub const PI_RESYNC_TYPE: u8 = 27;
+pub const PGI_LINKING_INFORMATION: u8 = 33;
+pub const PI_ACTIVITY_IDENTIFIER: u8 = 41;
+pub const PI_SERIAL_NUMBER: u8 = 42;
+pub const PI_REFLECT_PARAMETER_VALUES: u8 = 49;
+pub const PI_REASON_CODE: u8 = 50;
+pub const PI_CALLING_SESSION_SELECTOR: u8 = 51;
+/// called resp. responding session selector
+pub const PI_CALLED_SESSION_SELECTOR: u8 = 52;
+pub const PI_DATA_OVERFLOW: u8 = 60;
+pub const PGI_USER_DATA: u8 = 193;
+pub const PGI_EXTENDED_USER_DATA: u8 = 194;
+
+/// parameter groups which contain parameters, as opposed to PGI user data which contains the SS-user data as-is
+const NESTED_GROUPS: [u8; 2] = [PGI_CONNECTION_IDENTIFIER, PGI_CONNECT_ACCEPT_ITEM];
+
+/// X.225 8.3.24 sync type item of MAP - without it, the SPDU is an AE
+pub const SYNC_TYPE_NOT_END_OF_ACTIVITY: u8 = 0x01;
+
+// X.225 8.3.1.13 token setting item, two bits per token
+pub const TOKEN_SETTING_INITIATOR_SIDE: u8 = 0;
+pub const TOKEN_SETTING_ACCEPTOR_SIDE: u8 = 1;
+pub const TOKEN_SETTING_ACCEPTOR_CHOOSES: u8 = 2;
+
+/// X.225 8.3.29 - serial numbers are up to 6 decimal digits
+pub const SERIAL_NUMBER_MAXIMUM: u32 = 999999;
+/// X.225 8.3.32 - activity identifiers are up to 6 octets
+pub const ACTIVITY_IDENTIFIER_MAXIMUM: usize = 6;
+
+/// X.225 8.3.1.9 version number bits
+pub const VERSION_1: u8 = 0x01;
+pub const VERSION_2: u8 = 0x02;
+
+// X.225 8.3.3.5 transport disconnect bits, in FN, RF and AB
+pub const TRANSPORT_RELEASED: u8 = 0x01;
+pub const ABORT_USER: u8 = 0x02;
+pub const ABORT_PROTOCOL_ERROR: u8 = 0x04;
+pub const ABORT_NO_REASON: u8 = 0x08;
+pub const ABORT_IMPLEMENTATION_RESTRICTION: u8 = 0x10;
+
+// X.225 8.3.4.8 reason code of RF
+pub const REASON_REJECTED_BY_USER: u8 = 0;
+pub const REASON_USER_CONGESTION: u8 = 1;
+/// reason user data follows the reason code
+pub const REASON_REJECTED_BY_USER_WITH_DATA: u8 = 2;
+pub const REASON_SELECTOR_UNKNOWN: u8 = 0x81;
+pub const REASON_USER_NOT_ATTACHED: u8 = 0x82;
+pub const REASON_SPM_CONGESTION: u8 = 0x83;
+pub const REASON_VERSION_NOT_SUPPORTED: u8 = 0x84;
+pub const REASON_SPM_NOT_SPECIFIED: u8 = 0x85;
+
+/// X.225 8.3.1.16 - SS-user data of CN in the user data parameter, up to 10240 octets in version 2 with the extended user data parameter
+pub const CONNECT_DATA_MAXIMUM: usize = 512;
+pub const CONNECT_EXTENDED_DATA_MAXIMUM: usize = 10240;
+/// X.225 8.3.12 - SS-user data of EX, has to fit into one transport expedited TSDU
+pub const EXPEDITED_DATA_MAXIMUM: usize = 14;
+/// X.225 8.2.5 - largest length of a parameter field resp. of a PI or PGI unit
+pub const LENGTH_MAXIMUM: usize = 65535;
+/// SS-user data in the user data PGI unit, leaving room in the parameter field for the other parameters of the SPDU
+pub const USER_DATA_MAXIMUM: usize = LENGTH_MAXIMUM - 256;
+
+/// serial number parameter value - decimal digits in IA5
+pub fn serial_number_value(serial_number: u32) -> Vec<u8> {
+    return serial_number.to_string().into_bytes();
+}
+
+pub fn parse_serial_number(value: &[u8]) -> Option<u32> {
+    if value.is_empty() || value.len() > 6 || !value.iter().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+    return std::str::from_utf8(value).ok()?.parse().ok();
+}
+
+/// PI value inside the value of a PGI unit, e.g. of the linking information
+pub fn group_parameter(group: &[u8], code: u8) -> Option<&[u8]> {
+    return find_parameter(group, code);
+}
+
+/// PI resp. PGI unit, PGI with nested PIs carry them encoded in the value
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    pub code: u8,
+    pub value: Vec<u8>,
+}
+
+impl Parameter {
+    pub fn new(code: u8, value: &[u8]) -> Self {
+        return Parameter { code: code, value: value.to_vec() };
+    }
+
+    /// PGI unit containing the given PI units
+    pub fn group(code: u8, parameters: &[Parameter]) -> Result<Self, Error> {
+        let mut value = vec![];
+        for parameter in parameters {
+            parameter.into_buf(&mut value)?;
+        }
+        return Ok(Parameter { code: code, value: value });
+    }
+
+    fn into_buf(&self, buffer: &mut Vec<u8>) -> Result<(), Error> {
+        buffer.push(self.code);
+        write_length(self.value.len(), buffer)?;
+        buffer.extend_from_slice(&self.value);
+        return Ok(());
+    }
+}
+
+/// X.225 8.2.5 length indicator - one octet up to 254, else 0xFF and two octets
+fn write_length(length: usize, buffer: &mut Vec<u8>) -> Result<(), Error> {
+    if length < 255 {
+        buffer.push(length as u8);
+    } else if length <= LENGTH_MAXIMUM {
+        buffer.push(0xFF);
+        buffer.extend_from_slice(&(length as u16).to_be_bytes());
+    } else {
+        //TODO segmenting of the SPDU, X.225 6.3.8 - only the user information field may be segmented, though
+        return Err(Error::new(ErrorKind::InvalidInput, "SPDU parameter resp. parameter field longer than 65535 octets"));
+    }
+    return Ok(());
+}
+
+/// returns the length and the octets used for it
+fn read_length(buffer: &[u8]) -> Result<(usize, usize), Error> {
+    match buffer.first() {
+        None => { return Err(Error::new(ErrorKind::InvalidData, "missing length indicator")); },
+        Some(0xFF) => {
+            if buffer.len() < 3 {
+                return Err(Error::new(ErrorKind::InvalidData, "truncated length indicator"));
+            }
+            return Ok((u16::from_be_bytes([buffer[1], buffer[2]]) as usize, 3));
+        },
+        Some(length) => { return Ok((*length as usize, 1)); }
+    }
+}
+
+/// PI resp. PGI units one after the other, as in the parameter field resp. in a PGI unit
+fn read_parameters(mut buffer: &[u8]) -> Result<Vec<Parameter>, Error> {
+    let mut parameters = vec![];
+    while !buffer.is_empty() {
+        let code = buffer[0];
+        let (length, length_octets) = read_length(&buffer[1..])?;
+        let start = 1 + length_octets;
+        if buffer.len() < start + length {
+            return Err(Error::new(ErrorKind::InvalidData, "parameter exceeds parameter field"));
+        }
+        parameters.push(Parameter { code: code, value: buffer[start..start + length].to_vec() });
+        buffer = &buffer[start + length..];
+    }
+    return Ok(parameters);
+}
+
+/// finds a PI value in a parameter field, also inside the PGI units containing PIs
+fn find_parameter(mut buffer: &[u8], wanted: u8) -> Option<&[u8]> {
+    while !buffer.is_empty() {
+        let code = buffer[0];
+        let (length, length_octets) = read_length(&buffer[1..]).ok()?;
+        let start = 1 + length_octets;
+        if buffer.len() < start + length {
+            return None;
+        }
+        let value = &buffer[start..start + length];
+        if code == wanted {
+            return Some(value);
+        }
+        if NESTED_GROUPS.contains(&code) {
+            if let Some(value) = find_parameter(value, wanted) {
+                return Some(value);
+            }
+        }
+        buffer = &buffer[start + length..];
+    }
+    return None;
+}
+
+/// X.225 8.2 SPDU = SI, LI, parameter field and, for some SPDUs, user information field
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spdu {
+    pub si: u8,
+    pub parameters: Vec<Parameter>,
+    pub user_information: Vec<u8>,
+}
+
+impl Spdu {
+    pub fn new(si: u8, parameters: Vec<Parameter>) -> Self {
+        return Spdu { si: si, parameters: parameters, user_information: vec![] };
+    }
+
+    /// DT resp. EX with the SS-user data in the user information field
+    pub fn with_user_information(si: u8, user_information: &[u8]) -> Self {
+        return Spdu { si: si, parameters: vec![], user_information: user_information.to_vec() };
+    }
+
+    /// PI value, also looked up inside PGI units
+    pub fn parameter(&self, code: u8) -> Option<&[u8]> {
+        for parameter in &self.parameters {
+            if parameter.code == code {
+                return Some(&parameter.value);
+            }
+            if NESTED_GROUPS.contains(&parameter.code) {
+                if let Some(value) = find_parameter(&parameter.value, code) {
+                    return Some(value);
+                }
+            }
+        }
+        return None;
+    }
+
+    /// single octet PI value, e.g. transport disconnect or version number
+    pub fn parameter_octet(&self, code: u8) -> Option<u8> {
+        return self.parameter(code).and_then(|value| value.first().copied());
+    }
+
+    /// PI value at the top level of the parameter field, not looked up inside PGI units
+    pub fn top_level_parameter(&self, code: u8) -> Option<&[u8]> {
+        return self.parameters.iter().find(|parameter| parameter.code == code).map(|parameter| &parameter.value[..]);
+    }
+
+    /// SS-user data of the user data resp. extended user data PGI
+    pub fn user_data(&self) -> &[u8] {
+        return self.parameter(PGI_USER_DATA).or(self.parameter(PGI_EXTENDED_USER_DATA)).unwrap_or(&[]);
+    }
+
+    fn into_buf(&self, buffer: &mut Vec<u8>) -> Result<(), Error> {
+        let mut parameter_field = vec![];
+        for parameter in &self.parameters {
+            parameter.into_buf(&mut parameter_field)?;
+        }
+        buffer.push(self.si);
+        write_length(parameter_field.len(), buffer)?;
+        buffer.extend_from_slice(&parameter_field);
+        buffer.extend_from_slice(&self.user_information);
+        return Ok(());
+    }
+}
+
+/// X.225 6.3.7 basic concatenation - category 0 SPDUs (GT, PT) go in front of a category 2 SPDU, e.g. GT in front of DT
+pub fn is_category_0(si: u8) -> bool {
+    return si == SI_GT || si == SI_PT;
+}
+
+/// decodes the SPDUs of a TSDU - either a single category 1 SPDU or a category 0 SPDU followed by an optional category 2 SPDU
+/// NOTE: the user information field is what follows the parameter field of the last SPDU in the TSDU
+pub fn from_tsdu(mut tsdu: &[u8]) -> Result<Vec<Spdu>, Error> {
+    let mut spdus = vec![];
+    loop {
+        if tsdu.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "empty SPDU"));
+        }
+        let si = tsdu[0];
+        let (length, length_octets) = read_length(&tsdu[1..])?;
+        let start = 1 + length_octets;
+        if tsdu.len() < start + length {
+            return Err(Error::new(ErrorKind::InvalidData, "SPDU shorter than its length indicator"));
+        }
+        let parameters = read_parameters(&tsdu[start..start + length])?;
+        let rest = &tsdu[start + length..];
+        if spdus.is_empty() && is_category_0(si) && !rest.is_empty() {
+            spdus.push(Spdu::new(si, parameters));
+            tsdu = rest;
+            continue;
+        }
+        //TODO extended concatenation, X.225 6.3.7.2
+        spdus.push(Spdu { si: si, parameters: parameters, user_information: rest.to_vec() });
+        return Ok(spdus);
+    }
+}
+
+/// encodes the SPDUs into one TSDU
+pub fn into_tsdu(spdus: &[Spdu], buffer: &mut Vec<u8>) -> Result<(), Error> {
+    for spdu in spdus {
+        spdu.into_buf(buffer)?;
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(spdus: &[Spdu]) -> Vec<u8> {
+        let mut tsdu = vec![];
+        into_tsdu(spdus, &mut tsdu).unwrap();
+        assert_eq!(from_tsdu(&tsdu).unwrap(), spdus);
+        return tsdu;
+    }
+
+    #[test]
+    fn length_forms() {
+        // one octet up to 254
+        let tsdu = round_trip(&[Spdu::new(SI_FN, vec![Parameter::new(PGI_USER_DATA, &[0x55; 252])])]);
+        assert_eq!(&tsdu[..4], [SI_FN, 254, PGI_USER_DATA, 252]);
+        // 0xFF and two octets from 255 on
+        let tsdu = round_trip(&[Spdu::new(SI_FN, vec![Parameter::new(PGI_USER_DATA, &[0x55; 255])])]);
+        assert_eq!(&tsdu[..8], [SI_FN, 0xFF, 0x01, 0x03, PGI_USER_DATA, 0xFF, 0x00, 0xFF]);
+        // up to the largest the length indicator can express
+        let tsdu = round_trip(&[Spdu::new(SI_FN, vec![Parameter::new(PGI_USER_DATA, &vec![0x55; LENGTH_MAXIMUM - 4])])]);
+        assert_eq!(&tsdu[..8], [SI_FN, 0xFF, 0xFF, 0xFF, PGI_USER_DATA, 0xFF, 0xFF, 0xFB]);
+        for length in [0, 1, 254, 255, 256, LENGTH_MAXIMUM] {
+            let mut buffer = vec![];
+            write_length(length, &mut buffer).unwrap();
+            assert_eq!(read_length(&buffer).unwrap(), (length, buffer.len()));
+        }
+    }
+
+    #[test]
+    fn too_long_rejected() {
+        let mut buffer = vec![];
+        assert_eq!(write_length(LENGTH_MAXIMUM + 1, &mut buffer).unwrap_err().kind(), ErrorKind::InvalidInput);
+        // the parameter itself resp. the parameter field it is in
+        assert!(into_tsdu(&[Spdu::new(SI_AB, vec![Parameter::new(PGI_USER_DATA, &vec![0; LENGTH_MAXIMUM + 1])])], &mut vec![]).is_err());
+        assert!(into_tsdu(&[Spdu::new(SI_AB, vec![Parameter::new(PGI_USER_DATA, &vec![0; LENGTH_MAXIMUM - 1])])], &mut vec![]).is_err());
+        assert!(Parameter::group(PGI_LINKING_INFORMATION, &[Parameter::new(PI_ACTIVITY_IDENTIFIER, &vec![0; LENGTH_MAXIMUM + 1])]).is_err());
+        // but the user information field is not limited - NOTE: DT always comes after a category 0 SPDU
+        round_trip(&[Spdu::new(SI_GT, vec![]), Spdu::with_user_information(SI_DT, &vec![0x55; 100000])]);
+        // what the SS user may hand over leaves room for the other parameters
+        let linking_information = Parameter::group(PGI_LINKING_INFORMATION, &[
+            Parameter::new(PI_ACTIVITY_IDENTIFIER, &[0xAA; ACTIVITY_IDENTIFIER_MAXIMUM]),
+            Parameter::new(PI_SERIAL_NUMBER, &serial_number_value(SERIAL_NUMBER_MAXIMUM)),
+        ]).unwrap();
+        round_trip(&[Spdu::new(SI_AR, vec![
+            linking_information,
+            Parameter::new(PI_ACTIVITY_IDENTIFIER, &[0xBB; ACTIVITY_IDENTIFIER_MAXIMUM]),
+            Parameter::new(PGI_USER_DATA, &vec![0x55; USER_DATA_MAXIMUM]),
+        ])]);
+    }
+
+    #[test]
+    fn truncated_rejected() {
+        assert!(from_tsdu(&[]).is_err());
+        assert!(from_tsdu(&[SI_FN]).is_err());
+        assert!(from_tsdu(&[SI_FN, 0xFF, 0x01]).is_err());
+        assert!(from_tsdu(&[SI_FN, 3, PI_TRANSPORT_DISCONNECT, 1]).is_err());
+        // parameter longer than the parameter field
+        assert!(from_tsdu(&[SI_FN, 3, PI_TRANSPORT_DISCONNECT, 2, 1, 0]).is_err());
+    }
+
+    #[test]
+    fn nested_parameters() {
+        let accept_item = Parameter::group(PGI_CONNECT_ACCEPT_ITEM, &[
+            Parameter::new(PI_PROTOCOL_OPTIONS, &[0]),
+            Parameter::new(PI_VERSION_NUMBER, &[VERSION_2]),
+            Parameter::new(PI_INITIAL_SERIAL_NUMBER, &serial_number_value(42)),
+        ]).unwrap();
+        assert_eq!(accept_item.value, [PI_PROTOCOL_OPTIONS, 1, 0, PI_VERSION_NUMBER, 1, VERSION_2, PI_INITIAL_SERIAL_NUMBER, 2, b'4', b'2']);
+        let cn = Spdu::new(SI_CN, vec![
+            accept_item,
+            Parameter::new(PI_SESSION_USER_REQUIREMENTS, &[0x00, 0x02]),
+            Parameter::new(PI_CALLING_SESSION_SELECTOR, b"calling"),
+            Parameter::new(PGI_USER_DATA, b"hello"),
+        ]);
+        let tsdu = round_trip(&[cn.clone()]);
+        let decoded = &from_tsdu(&tsdu).unwrap()[0];
+        // PIs inside the connect accept item are found, also at the top level of the parameter field
+        assert_eq!(decoded.parameter_octet(PI_VERSION_NUMBER), Some(VERSION_2));
+        assert_eq!(decoded.parameter(PI_INITIAL_SERIAL_NUMBER).and_then(parse_serial_number), Some(42));
+        assert_eq!(decoded.top_level_parameter(PI_VERSION_NUMBER), None);
+        assert_eq!(decoded.parameter(PI_CALLING_SESSION_SELECTOR), Some(&b"calling"[..]));
+        assert_eq!(decoded.user_data(), b"hello");
+        assert_eq!(decoded.parameter(PI_REASON_CODE), None);
+
+        // the linking information is no nested group for parameter(), since its activity identifier is another one
+        let ar = Spdu::new(SI_AR, vec![
+            Parameter::group(PGI_LINKING_INFORMATION, &[
+                Parameter::new(PI_ACTIVITY_IDENTIFIER, b"old"),
+                Parameter::new(PI_SERIAL_NUMBER, &serial_number_value(7)),
+            ]).unwrap(),
+            Parameter::new(PI_ACTIVITY_IDENTIFIER, b"new"),
+        ]);
+        let decoded = &from_tsdu(&round_trip(&[ar])).unwrap()[0];
+        assert_eq!(decoded.parameter(PI_ACTIVITY_IDENTIFIER), Some(&b"new"[..]));
+        let linking_information = decoded.parameter(PGI_LINKING_INFORMATION).unwrap();
+        assert_eq!(group_parameter(linking_information, PI_ACTIVITY_IDENTIFIER), Some(&b"old"[..]));
+        assert_eq!(group_parameter(linking_information, PI_SERIAL_NUMBER).and_then(parse_serial_number), Some(7));
+
+        // nested group with a long PI in it, in the 3-octet length form on both levels
+        let long = Parameter::group(PGI_CONNECT_ACCEPT_ITEM, &[Parameter::new(PI_TOKEN_SETTING_ITEM, &[0x01; 300])]).unwrap();
+        assert_eq!(&long.value[..4], [PI_TOKEN_SETTING_ITEM, 0xFF, 0x01, 0x2C]);
+        let decoded = &from_tsdu(&round_trip(&[Spdu::new(SI_AC, vec![long])])).unwrap()[0];
+        assert_eq!(decoded.parameter(PI_TOKEN_SETTING_ITEM).map(|value| value.len()), Some(300));
+    }
+
+    #[test]
+    fn basic_concatenation() {
+        let tsdu = round_trip(&[
+            Spdu::new(SI_GT, vec![Parameter::new(PI_TOKEN_ITEM, &[0x01])]),
+            Spdu::with_user_information(SI_DT, b"data"),
+        ]);
+        assert_eq!(tsdu, [SI_GT, 3, PI_TOKEN_ITEM, 1, 0x01, SI_DT, 0, b'd', b'a', b't', b'a']);
+        // a category 0 SPDU on its own keeps what follows as user information
+        let spdus = from_tsdu(&[SI_PT, 0]).unwrap();
+        assert_eq!(spdus, [Spdu::new(SI_PT, vec![])]);
+        assert!(is_category_0(SI_GT) && is_category_0(SI_PT) && !is_category_0(SI_EX));
+    }
+}
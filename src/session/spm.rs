@@ -0,0 +1,971 @@
+use std::io::{Error, ErrorKind};
+
+use super::{FunctionalUnits, ResyncType, SIndication, Tokens};
+use super::spdu::{self, Parameter, Spdu};
+
+/// X.225 A.4 states within STA713 data transfer - tokens, synchronization, resynchronization and activities
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Phase {
+    /// STA713 - data transfer
+    DataTransfer,
+    /// STA04A - MAP sent
+    WaitForMaa,
+    /// STA04B - AE sent
+    WaitForAea,
+    /// STA05A - RS sent
+    WaitForRa,
+    /// STA05B - AI sent
+    WaitForAia,
+    /// STA05C - AD sent
+    WaitForAda,
+    /// STA10A - MAP indicated
+    WaitForSyncMajorResponse,
+    /// STA10B - AE indicated
+    WaitForActivityEndResponse,
+    /// STA11A - RS indicated
+    WaitForResynchronizeResponse,
+    /// STA11B - AI indicated
+    WaitForActivityInterruptResponse,
+    /// STA11C - AD indicated
+    WaitForActivityDiscardResponse,
+}
+
+/// what the SPM wants sent and indicated, one TSDU per entry
+#[derive(Default)]
+pub(super) struct Output {
+    pub tsdus: Vec<Vec<Spdu>>,
+    pub indications: Vec<SIndication>,
+}
+
+impl Output {
+    /// X.225 6.3.7 - category 2 SPDUs go behind a GT without parameters
+    fn send(&mut self, spdu: Spdu) {
+        self.tsdus.push(vec![Spdu::new(spdu::SI_GT, vec![]), spdu]);
+    }
+
+    /// category 0 SPDU on its own
+    fn send_alone(&mut self, spdu: Spdu) {
+        self.tsdus.push(vec![spdu]);
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidInput, message.to_string());
+}
+
+fn protocol_error(message: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, message.to_string());
+}
+
+/// user data parameter, if there is user data
+fn with_user_data(mut parameters: Vec<Parameter>, data: &[u8]) -> Vec<Parameter> {
+    if !data.is_empty() {
+        parameters.push(Parameter::new(spdu::PGI_USER_DATA, data));
+    }
+    return parameters;
+}
+
+/// the session protocol machine of one session connection once it is established
+/// NOTE: SS user requests which the state tables do not allow are InvalidInput, SPDUs which they do not allow InvalidData - the latter abort the session connection
+pub(super) struct Spm {
+    reference: u16,
+    functional_units: FunctionalUnits,
+    /// initiator of the session connection - wins resynchronize collisions of equal type and serial number
+    initiator: bool,
+    phase: Phase,
+    available: Tokens,
+    owned: Tokens,
+    /// X.225 7.x V(M) - serial number of the next synchronization point
+    next_serial_number: u32,
+    /// X.225 7.x V(A) - lowest serial number not confirmed yet
+    lowest_unconfirmed: u32,
+    /// X.225 7.x V(R) - lowest serial number a resynchronize restart may go back to
+    lowest_restart: u32,
+    /// X.225 Vact - activity in progress
+    activity: bool,
+    /// type, serial number and requestor tokens of the RS sent resp. indicated
+    resync: Option<(ResyncType, u32, Tokens)>,
+}
+
+impl Spm {
+    /// token setting as in the connect/accept item, initial serial number if synchronization is used
+    pub(super) fn new(reference: u16, functional_units: FunctionalUnits, initiator: bool, token_setting: u8, initial_serial_number: u32) -> Self {
+        let available = Tokens::available(functional_units);
+        let own_side = if initiator { spdu::TOKEN_SETTING_INITIATOR_SIDE } else { spdu::TOKEN_SETTING_ACCEPTOR_SIDE };
+        return Spm {
+            reference: reference,
+            functional_units: functional_units,
+            initiator: initiator,
+            phase: Phase::DataTransfer,
+            available: available,
+            owned: Tokens::from_setting(token_setting, own_side) & available,
+            next_serial_number: initial_serial_number,
+            lowest_unconfirmed: initial_serial_number,
+            lowest_restart: initial_serial_number,
+            activity: false,
+            resync: None,
+        };
+    }
+
+    pub(super) fn owned(&self) -> Tokens {
+        return self.owned;
+    }
+
+    /// X.215 - release needs all available tokens, and no activity in progress
+    pub(super) fn may_release(&self) -> bool {
+        return self.phase == Phase::DataTransfer && !self.activity && self.owned.contains(self.available);
+    }
+
+    /// whether an FN from the peer is in order
+    pub(super) fn peer_may_release(&self) -> bool {
+        return self.phase == Phase::DataTransfer && !self.activity && !self.owned.intersects(self.available);
+    }
+
+    fn require_functional_unit(&self, functional_unit: FunctionalUnits) -> Result<(), Error> {
+        if !self.functional_units.contains(functional_unit) {
+            return Err(invalid("functional unit not selected"));
+        }
+        return Ok(());
+    }
+
+    fn require_phase(&self, phase: Phase) -> Result<(), Error> {
+        if self.phase != phase {
+            return Err(invalid("not possible in the state of the session connection"));
+        }
+        return Ok(());
+    }
+
+    /// the given tokens, as far as available
+    fn require_tokens(&self, tokens: Tokens) -> Result<(), Error> {
+        if !self.owned.contains(tokens & self.available) {
+            return Err(invalid("token not owned"));
+        }
+        return Ok(());
+    }
+
+    /// X.215 - with activity management, data transfer and synchronization only within an activity
+    fn require_activity_if_managed(&self) -> Result<(), Error> {
+        if self.functional_units.contains(FunctionalUnits::ACTIVITY_MANAGEMENT) && !self.activity {
+            return Err(invalid("no activity in progress"));
+        }
+        return Ok(());
+    }
+
+    fn indicate(&self, output: &mut Output, indication: SIndication) {
+        output.indications.push(indication);
+    }
+
+    pub(super) fn data_request(&mut self, data: &[u8], output: &mut Output) -> Result<(), Error> {
+        self.require_phase(Phase::DataTransfer)?;
+        self.require_activity_if_managed()?;
+        self.require_tokens(Tokens::DATA)?;
+        output.send(Spdu::with_user_information(spdu::SI_DT, data));
+        return Ok(());
+    }
+
+    pub(super) fn give_tokens_request(&mut self, tokens: Tokens, output: &mut Output) -> Result<(), Error> {
+        self.require_phase(Phase::DataTransfer)?;
+        if tokens == Tokens::NONE || !self.available.contains(tokens) || !self.owned.contains(tokens) {
+            return Err(invalid("tokens not available resp. not owned"));
+        }
+        self.owned = self.owned.without(tokens);
+        output.send_alone(Spdu::new(spdu::SI_GT, vec![Parameter::new(spdu::PI_TOKEN_ITEM, &[tokens.0])]));
+        return Ok(());
+    }
+
+    pub(super) fn please_tokens_request(&mut self, tokens: Tokens, data: &[u8], output: &mut Output) -> Result<(), Error> {
+        if tokens == Tokens::NONE || !self.available.contains(tokens) || self.owned.intersects(tokens) {
+            return Err(invalid("tokens not available resp. already owned"));
+        }
+        output.send_alone(Spdu::new(spdu::SI_PT, with_user_data(vec![Parameter::new(spdu::PI_TOKEN_ITEM, &[tokens.0])], data)));
+        return Ok(());
+    }
+
+    pub(super) fn sync_minor_request(&mut self, data: &[u8], output: &mut Output) -> Result<u32, Error> {
+        self.require_functional_unit(FunctionalUnits::MINOR_SYNCHRONIZE)?;
+        self.require_phase(Phase::DataTransfer)?;
+        self.require_activity_if_managed()?;
+        self.require_tokens(Tokens::MINOR_SYNCHRONIZE | Tokens::DATA)?;
+        let serial_number = self.take_serial_number()?;
+        output.send(Spdu::new(spdu::SI_MIP, with_user_data(vec![Parameter::new(spdu::PI_SERIAL_NUMBER, &spdu::serial_number_value(serial_number))], data)));
+        return Ok(serial_number);
+    }
+
+    /// V(M) for the next synchronization point resp. activity end, not beyond what fits into the serial number parameter
+    fn take_serial_number(&mut self) -> Result<u32, Error> {
+        if self.next_serial_number > spdu::SERIAL_NUMBER_MAXIMUM {
+            return Err(invalid("serial numbers exhausted, resynchronize to continue"));
+        }
+        let serial_number = self.next_serial_number;
+        self.next_serial_number += 1;
+        return Ok(serial_number);
+    }
+
+    pub(super) fn sync_minor_response(&mut self, serial_number: u32, data: &[u8], output: &mut Output) -> Result<(), Error> {
+        self.require_functional_unit(FunctionalUnits::MINOR_SYNCHRONIZE)?;
+        self.require_phase(Phase::DataTransfer)?;
+        if serial_number < self.lowest_unconfirmed || serial_number >= self.next_serial_number {
+            return Err(invalid("no such unconfirmed minor synchronization point"));
+        }
+        self.lowest_unconfirmed = serial_number + 1;
+        output.send(Spdu::new(spdu::SI_MIA, with_user_data(vec![Parameter::new(spdu::PI_SERIAL_NUMBER, &spdu::serial_number_value(serial_number))], data)));
+        return Ok(());
+    }
+
+    pub(super) fn sync_major_request(&mut self, data: &[u8], output: &mut Output) -> Result<u32, Error> {
+        self.require_functional_unit(FunctionalUnits::MAJOR_SYNCHRONIZE)?;
+        self.require_phase(Phase::DataTransfer)?;
+        self.require_activity_if_managed()?;
+        self.require_tokens(Tokens::MAJOR_ACTIVITY | Tokens::MINOR_SYNCHRONIZE | Tokens::DATA)?;
+        let serial_number = self.take_serial_number()?;
+        self.phase = Phase::WaitForMaa;
+        output.send(Spdu::new(spdu::SI_MAP, with_user_data(vec![
+            Parameter::new(spdu::PI_SYNC_TYPE_ITEM, &[spdu::SYNC_TYPE_NOT_END_OF_ACTIVITY]),
+            Parameter::new(spdu::PI_SERIAL_NUMBER, &spdu::serial_number_value(serial_number)),
+        ], data)));
+        return Ok(serial_number);
+    }
+
+    pub(super) fn sync_major_response(&mut self, data: &[u8], output: &mut Output) -> Result<(), Error> {
+        self.require_phase(Phase::WaitForSyncMajorResponse)?;
+        self.confirm_major();
+        output.send(Spdu::new(spdu::SI_MAA, with_user_data(vec![Parameter::new(spdu::PI_SERIAL_NUMBER, &spdu::serial_number_value(self.next_serial_number - 1))], data)));
+        return Ok(());
+    }
+
+    /// X.225 7.x - a major synchronization point confirms all before it and is where a restart may go back to at most
+    fn confirm_major(&mut self) {
+        self.lowest_unconfirmed = self.next_serial_number;
+        self.lowest_restart = self.next_serial_number;
+        self.phase = Phase::DataTransfer;
+    }
+
+    pub(super) fn resynchronize_request(&mut self, resync_type: ResyncType, serial_number: u32, tokens: Tokens, data: &[u8], output: &mut Output) -> Result<u32, Error> {
+        self.require_functional_unit(FunctionalUnits::RESYNCHRONIZE)?;
+        self.require_activity_if_managed()?;
+        match self.phase {
+            Phase::WaitForRa | Phase::WaitForAia | Phase::WaitForAda | Phase::WaitForResynchronizeResponse
+            | Phase::WaitForActivityInterruptResponse | Phase::WaitForActivityDiscardResponse => {
+                return Err(invalid("not possible in the state of the session connection"));
+            },
+            _ => {}
+        }
+        if !self.available.contains(tokens) {
+            return Err(invalid("tokens not available"));
+        }
+        let serial_number = match resync_type {
+            ResyncType::Restart if serial_number < self.lowest_restart || serial_number > self.next_serial_number => {
+                return Err(invalid("restart only back to the last major synchronization point"));
+            },
+            ResyncType::Abandon => self.next_serial_number,
+            _ if serial_number > spdu::SERIAL_NUMBER_MAXIMUM => { return Err(invalid("serial number too large")); },
+            _ => serial_number
+        };
+        self.resync = Some((resync_type, serial_number, tokens));
+        self.phase = Phase::WaitForRa;
+        output.send(Spdu::new(spdu::SI_RS, with_user_data(vec![
+            Parameter::new(spdu::PI_TOKEN_SETTING_ITEM, &[tokens.setting(spdu::TOKEN_SETTING_INITIATOR_SIDE, spdu::TOKEN_SETTING_ACCEPTOR_SIDE)]),
+            Parameter::new(spdu::PI_RESYNC_TYPE, &[resync_type as u8]),
+            Parameter::new(spdu::PI_SERIAL_NUMBER, &spdu::serial_number_value(serial_number)),
+        ], data)));
+        return Ok(serial_number);
+    }
+
+    pub(super) fn resynchronize_response(&mut self, data: &[u8], output: &mut Output) -> Result<(), Error> {
+        self.require_phase(Phase::WaitForResynchronizeResponse)?;
+        let (resync_type, serial_number, requestor_tokens) = self.resync.take().expect("resynchronize indicated");
+        self.resynchronized(resync_type, serial_number, self.available.without(requestor_tokens));
+        output.send(Spdu::new(spdu::SI_RA, with_user_data(vec![
+            Parameter::new(spdu::PI_TOKEN_SETTING_ITEM, &[requestor_tokens.setting(spdu::TOKEN_SETTING_INITIATOR_SIDE, spdu::TOKEN_SETTING_ACCEPTOR_SIDE)]),
+            Parameter::new(spdu::PI_SERIAL_NUMBER, &spdu::serial_number_value(serial_number)),
+        ], data)));
+        return Ok(());
+    }
+
+    /// X.225 7.14 - both sides continue at the serial number, tokens as agreed
+    fn resynchronized(&mut self, resync_type: ResyncType, serial_number: u32, owned: Tokens) {
+        self.next_serial_number = serial_number;
+        self.lowest_unconfirmed = serial_number;
+        if resync_type != ResyncType::Restart {
+            self.lowest_restart = 0;
+        }
+        self.owned = owned;
+        self.phase = Phase::DataTransfer;
+    }
+
+    fn require_activity_start(&self) -> Result<(), Error> {
+        self.require_functional_unit(FunctionalUnits::ACTIVITY_MANAGEMENT)?;
+        self.require_phase(Phase::DataTransfer)?;
+        if self.activity {
+            return Err(invalid("activity already in progress"));
+        }
+        return self.require_tokens(Tokens::MAJOR_ACTIVITY | Tokens::MINOR_SYNCHRONIZE | Tokens::DATA);
+    }
+
+    pub(super) fn activity_start_request(&mut self, activity_identifier: &[u8], data: &[u8], output: &mut Output) -> Result<(), Error> {
+        self.require_activity_start()?;
+        if activity_identifier.len() > spdu::ACTIVITY_IDENTIFIER_MAXIMUM {
+            return Err(invalid("activity identifier too long"));
+        }
+        self.start_activity(1);
+        output.send(Spdu::new(spdu::SI_AS, with_user_data(vec![Parameter::new(spdu::PI_ACTIVITY_IDENTIFIER, activity_identifier)], data)));
+        return Ok(());
+    }
+
+    pub(super) fn activity_resume_request(&mut self, activity_identifier: &[u8], old_activity_identifier: &[u8], serial_number: u32, data: &[u8], output: &mut Output) -> Result<(), Error> {
+        self.require_activity_start()?;
+        if activity_identifier.len() > spdu::ACTIVITY_IDENTIFIER_MAXIMUM || old_activity_identifier.len() > spdu::ACTIVITY_IDENTIFIER_MAXIMUM {
+            return Err(invalid("activity identifier too long"));
+        }
+        if serial_number >= spdu::SERIAL_NUMBER_MAXIMUM {
+            return Err(invalid("serial number too large"));
+        }
+        let linking_information = Parameter::group(spdu::PGI_LINKING_INFORMATION, &[
+            Parameter::new(spdu::PI_ACTIVITY_IDENTIFIER, old_activity_identifier),
+            Parameter::new(spdu::PI_SERIAL_NUMBER, &spdu::serial_number_value(serial_number)),
+        ])?;
+        self.start_activity(serial_number + 1);
+        output.send(Spdu::new(spdu::SI_AR, with_user_data(vec![
+            linking_information,
+            Parameter::new(spdu::PI_ACTIVITY_IDENTIFIER, activity_identifier),
+        ], data)));
+        return Ok(());
+    }
+
+    /// X.225 7.x - serial numbers start over with each activity, resp. continue behind the resumed synchronization point
+    fn start_activity(&mut self, serial_number: u32) {
+        self.activity = true;
+        self.next_serial_number = serial_number;
+        self.lowest_unconfirmed = serial_number;
+        self.lowest_restart = serial_number;
+    }
+
+    fn require_activity_end(&self) -> Result<(), Error> {
+        self.require_functional_unit(FunctionalUnits::ACTIVITY_MANAGEMENT)?;
+        self.require_phase(Phase::DataTransfer)?;
+        if !self.activity {
+            return Err(invalid("no activity in progress"));
+        }
+        return self.require_tokens(Tokens::MAJOR_ACTIVITY);
+    }
+
+    pub(super) fn activity_interrupt_request(&mut self, reason: u8, output: &mut Output) -> Result<(), Error> {
+        self.require_activity_end()?;
+        self.phase = Phase::WaitForAia;
+        output.send(Spdu::new(spdu::SI_AI, vec![Parameter::new(spdu::PI_REASON_CODE, &[reason])]));
+        return Ok(());
+    }
+
+    pub(super) fn activity_interrupt_response(&mut self, output: &mut Output) -> Result<(), Error> {
+        self.require_phase(Phase::WaitForActivityInterruptResponse)?;
+        self.activity_left(false);
+        output.send(Spdu::new(spdu::SI_AIA, vec![]));
+        return Ok(());
+    }
+
+    pub(super) fn activity_discard_request(&mut self, reason: u8, output: &mut Output) -> Result<(), Error> {
+        self.require_activity_end()?;
+        self.phase = Phase::WaitForAda;
+        output.send(Spdu::new(spdu::SI_AD, vec![Parameter::new(spdu::PI_REASON_CODE, &[reason])]));
+        return Ok(());
+    }
+
+    pub(super) fn activity_discard_response(&mut self, output: &mut Output) -> Result<(), Error> {
+        self.require_phase(Phase::WaitForActivityDiscardResponse)?;
+        self.activity_left(false);
+        output.send(Spdu::new(spdu::SI_ADA, vec![]));
+        return Ok(());
+    }
+
+    /// X.225 7.x - after an interrupt resp. discard, all tokens are with the side which asked for it
+    fn activity_left(&mut self, requestor: bool) {
+        self.activity = false;
+        self.owned = if requestor { self.available } else { Tokens::NONE };
+        self.phase = Phase::DataTransfer;
+    }
+
+    pub(super) fn activity_end_request(&mut self, data: &[u8], output: &mut Output) -> Result<u32, Error> {
+        self.require_activity_end()?;
+        self.require_tokens(Tokens::MINOR_SYNCHRONIZE | Tokens::DATA)?;
+        let serial_number = self.take_serial_number()?;
+        self.phase = Phase::WaitForAea;
+        output.send(Spdu::new(spdu::SI_AE, with_user_data(vec![Parameter::new(spdu::PI_SERIAL_NUMBER, &spdu::serial_number_value(serial_number))], data)));
+        return Ok(serial_number);
+    }
+
+    pub(super) fn activity_end_response(&mut self, data: &[u8], output: &mut Output) -> Result<(), Error> {
+        self.require_phase(Phase::WaitForActivityEndResponse)?;
+        self.confirm_major();
+        self.activity = false;
+        output.send(Spdu::new(spdu::SI_AEA, with_user_data(vec![Parameter::new(spdu::PI_SERIAL_NUMBER, &spdu::serial_number_value(self.next_serial_number - 1))], data)));
+        return Ok(());
+    }
+
+    /// SPDUs of one TSDU other than connection establishment, release and abort
+    pub(super) fn receive(&mut self, spdus: &[Spdu], output: &mut Output) -> Result<(), Error> {
+        let (first, second) = match spdus {
+            [first] => (first, None),
+            [first, second] => (first, Some(second)),
+            _ => { return Err(protocol_error("unexpected number of SPDUs")); }
+        };
+        // X.225 7.x - after RS, AI resp. AD sent, what the peer sent before seeing it is discarded
+        let awaited: &[u8] = match self.phase {
+            Phase::WaitForRa => &[spdu::SI_RS, spdu::SI_RA, spdu::SI_AI, spdu::SI_AD],
+            Phase::WaitForAia => &[spdu::SI_AIA],
+            Phase::WaitForAda => &[spdu::SI_ADA],
+            _ => &[]
+        };
+        if !awaited.is_empty() && !second.map(|spdu| awaited.contains(&spdu.si)).unwrap_or(false) {
+            debug!("session: discarding SPDU {} on {} in {:?}", second.unwrap_or(first).si, self.reference, self.phase);
+            return Ok(());
+        }
+        self.receive_category_0(first, output)?;
+        if let Some(second) = second {
+            self.receive_category_2(second, output)?;
+        }
+        return Ok(());
+    }
+
+    fn token_item(spdu: &Spdu) -> Tokens {
+        return Tokens(spdu.parameter_octet(spdu::PI_TOKEN_ITEM).unwrap_or(0));
+    }
+
+    fn receive_category_0(&mut self, spdu: &Spdu, output: &mut Output) -> Result<(), Error> {
+        let tokens = Spm::token_item(spdu);
+        match spdu.si {
+            spdu::SI_GT => {
+                if tokens == Tokens::NONE {
+                    return Ok(());
+                }
+                if self.phase != Phase::DataTransfer || !self.available.contains(tokens) || self.owned.intersects(tokens) {
+                    return Err(protocol_error("GT with tokens not owned by the peer"));
+                }
+                self.owned = self.owned | tokens;
+                self.indicate(output, SIndication::GiveTokensIndication { reference: self.reference, tokens: tokens });
+            },
+            spdu::SI_PT => {
+                if !self.available.contains(tokens) {
+                    return Err(protocol_error("PT for tokens not available"));
+                }
+                // NOTE: the tokens may be on their way already
+                self.indicate(output, SIndication::PleaseTokensIndication { reference: self.reference, tokens: tokens, data: spdu.user_data().to_vec() });
+            },
+            _ => { return Err(protocol_error("category 0 SPDU expected")); }
+        }
+        return Ok(());
+    }
+
+    /// serial number parameter, required
+    fn serial_number(spdu: &Spdu) -> Result<u32, Error> {
+        return spdu.parameter(spdu::PI_SERIAL_NUMBER).and_then(spdu::parse_serial_number).ok_or(protocol_error("missing resp. invalid serial number"));
+    }
+
+    /// X.225 A.4 state tables, for the SPDUs the peer sends when it owns the tokens for it
+    fn require_peer_tokens(&self, tokens: Tokens) -> Result<(), Error> {
+        if self.owned.intersects(tokens & self.available) {
+            return Err(protocol_error("SPDU needs a token the peer does not own"));
+        }
+        return Ok(());
+    }
+
+    fn receive_category_2(&mut self, spdu: &Spdu, output: &mut Output) -> Result<(), Error> {
+        let reference = self.reference;
+        let data = spdu.user_data().to_vec();
+        let managed = self.functional_units.contains(FunctionalUnits::ACTIVITY_MANAGEMENT);
+        match (self.phase, spdu.si) {
+            // NOTE: in duplex, the peer may go on sending data until it sees the MAP resp. AE
+            (Phase::DataTransfer, spdu::SI_DT) | (Phase::WaitForMaa, spdu::SI_DT) | (Phase::WaitForAea, spdu::SI_DT) => {
+                if managed && !self.activity {
+                    return Err(protocol_error("DT outside of an activity"));
+                }
+                self.require_peer_tokens(Tokens::DATA)?;
+                self.indicate(output, SIndication::DataIndication { reference: reference, data: spdu.user_information.clone() });
+            },
+            (Phase::DataTransfer, spdu::SI_MIP) => {
+                if !self.functional_units.contains(FunctionalUnits::MINOR_SYNCHRONIZE) || (managed && !self.activity) {
+                    return Err(protocol_error("MIP not allowed"));
+                }
+                self.require_peer_tokens(Tokens::MINOR_SYNCHRONIZE | Tokens::DATA)?;
+                let serial_number = Spm::serial_number(spdu)?;
+                if serial_number != self.next_serial_number {
+                    return Err(protocol_error("MIP with unexpected serial number"));
+                }
+                self.next_serial_number += 1;
+                self.indicate(output, SIndication::SyncMinorIndication { reference: reference, serial_number: serial_number, data: data });
+            },
+            // NOTE: the peer may confirm minor synchronization points before it sees the MAP resp. AE
+            (Phase::DataTransfer, spdu::SI_MIA) | (Phase::WaitForMaa, spdu::SI_MIA) | (Phase::WaitForAea, spdu::SI_MIA) => {
+                let serial_number = Spm::serial_number(spdu)?;
+                if serial_number < self.lowest_unconfirmed || serial_number >= self.next_serial_number {
+                    return Err(protocol_error("MIA for no unconfirmed minor synchronization point"));
+                }
+                self.lowest_unconfirmed = serial_number + 1;
+                self.indicate(output, SIndication::SyncMinorConfirm { reference: reference, serial_number: serial_number, data: data });
+            },
+            (Phase::DataTransfer, spdu::SI_MAP) => {
+                // X.225 8.3.24 - MAP and AE share the SI, AE is told by the sync type item missing only where activities exist
+                let end_of_activity = managed && spdu.parameter(spdu::PI_SYNC_TYPE_ITEM).is_none();
+                if end_of_activity && (!managed || !self.activity) {
+                    return Err(protocol_error("AE without activity"));
+                }
+                if !end_of_activity && (!self.functional_units.contains(FunctionalUnits::MAJOR_SYNCHRONIZE) || (managed && !self.activity)) {
+                    return Err(protocol_error("MAP not allowed"));
+                }
+                self.require_peer_tokens(Tokens::MAJOR_ACTIVITY | Tokens::MINOR_SYNCHRONIZE | Tokens::DATA)?;
+                let serial_number = Spm::serial_number(spdu)?;
+                if serial_number != self.next_serial_number {
+                    return Err(protocol_error("MAP resp. AE with unexpected serial number"));
+                }
+                self.next_serial_number += 1;
+                if end_of_activity {
+                    self.phase = Phase::WaitForActivityEndResponse;
+                    self.indicate(output, SIndication::ActivityEndIndication { reference: reference, serial_number: serial_number, data: data });
+                } else {
+                    self.phase = Phase::WaitForSyncMajorResponse;
+                    self.indicate(output, SIndication::SyncMajorIndication { reference: reference, serial_number: serial_number, data: data });
+                }
+            },
+            (Phase::WaitForMaa, spdu::SI_MAA) => {
+                self.confirm_major();
+                self.indicate(output, SIndication::SyncMajorConfirm { reference: reference, data: data });
+            },
+            (Phase::WaitForAea, spdu::SI_AEA) => {
+                self.confirm_major();
+                self.activity = false;
+                self.indicate(output, SIndication::ActivityEndConfirm { reference: reference, data: data });
+            },
+            (Phase::WaitForResynchronizeResponse, spdu::SI_RS) | (Phase::WaitForAia, spdu::SI_RS) | (Phase::WaitForAda, spdu::SI_RS)
+            | (Phase::WaitForActivityInterruptResponse, spdu::SI_RS) | (Phase::WaitForActivityDiscardResponse, spdu::SI_RS) => {
+                return Err(protocol_error("RS not allowed"));
+            },
+            (phase, spdu::SI_RS) => {
+                if !self.functional_units.contains(FunctionalUnits::RESYNCHRONIZE) {
+                    return Err(protocol_error("RS without resynchronize functional unit"));
+                }
+                let resync_type = spdu.parameter_octet(spdu::PI_RESYNC_TYPE).and_then(ResyncType::from_u8).ok_or(protocol_error("RS with invalid resync type"))?;
+                let serial_number = Spm::serial_number(spdu)?;
+                let setting = spdu.parameter_octet(spdu::PI_TOKEN_SETTING_ITEM).unwrap_or(0);
+                let requestor_tokens = Tokens::from_setting(setting, spdu::TOKEN_SETTING_INITIATOR_SIDE) & self.available;
+                if phase == Phase::WaitForRa {
+                    // X.225 7.14.3 collision - the higher precedence type wins, then the lower serial number, then the initiator of the session connection
+                    let (own_type, own_serial_number, _) = self.resync.expect("resynchronize requested");
+                    let ours_wins = own_type.precedence() > resync_type.precedence()
+                        || (own_type == resync_type && (own_serial_number < serial_number || (own_serial_number == serial_number && self.initiator)));
+                    if ours_wins {
+                        debug!("session: RS collision on {}, ours wins", reference);
+                        return Ok(());
+                    }
+                    debug!("session: RS collision on {}, peer wins", reference);
+                } else if resync_type == ResyncType::Restart && (serial_number < self.lowest_restart || serial_number > self.next_serial_number) {
+                    return Err(protocol_error("RS restart before the last major synchronization point"));
+                }
+                self.resync = Some((resync_type, serial_number, requestor_tokens));
+                self.phase = Phase::WaitForResynchronizeResponse;
+                self.indicate(output, SIndication::ResynchronizeIndication { reference: reference, resync_type: resync_type, serial_number: serial_number, tokens: requestor_tokens, data: data });
+            },
+            (Phase::WaitForRa, spdu::SI_RA) => {
+                let (resync_type, serial_number, tokens) = self.resync.take().expect("resynchronize requested");
+                self.resynchronized(resync_type, serial_number, tokens);
+                self.indicate(output, SIndication::ResynchronizeConfirm { reference: reference, serial_number: serial_number, data: data });
+            },
+            (Phase::DataTransfer, spdu::SI_AS) | (Phase::DataTransfer, spdu::SI_AR) => {
+                if !managed || self.activity {
+                    return Err(protocol_error("AS resp. AR not allowed"));
+                }
+                self.require_peer_tokens(Tokens::MAJOR_ACTIVITY | Tokens::MINOR_SYNCHRONIZE | Tokens::DATA)?;
+                let activity_identifier = spdu.top_level_parameter(spdu::PI_ACTIVITY_IDENTIFIER).unwrap_or(&[]).to_vec();
+                if spdu.si == spdu::SI_AS {
+                    self.start_activity(1);
+                    self.indicate(output, SIndication::ActivityStartIndication { reference: reference, activity_identifier: activity_identifier, data: data });
+                } else {
+                    let linking = spdu.top_level_parameter(spdu::PGI_LINKING_INFORMATION).ok_or(protocol_error("AR without linking information"))?;
+                    let old_activity_identifier = spdu::group_parameter(linking, spdu::PI_ACTIVITY_IDENTIFIER).unwrap_or(&[]).to_vec();
+                    let serial_number = spdu::group_parameter(linking, spdu::PI_SERIAL_NUMBER).and_then(spdu::parse_serial_number)
+                        .filter(|serial_number| *serial_number < spdu::SERIAL_NUMBER_MAXIMUM).ok_or(protocol_error("AR with invalid serial number"))?;
+                    self.start_activity(serial_number + 1);
+                    self.indicate(output, SIndication::ActivityResumeIndication {
+                        reference: reference,
+                        activity_identifier: activity_identifier,
+                        old_activity_identifier: old_activity_identifier,
+                        serial_number: serial_number,
+                        data: data,
+                    });
+                }
+            },
+            // NOTE: AI and AD win over a resynchronize
+            (Phase::DataTransfer, spdu::SI_AI) | (Phase::WaitForRa, spdu::SI_AI) | (Phase::DataTransfer, spdu::SI_AD) | (Phase::WaitForRa, spdu::SI_AD) => {
+                if !managed || !self.activity {
+                    return Err(protocol_error("AI resp. AD without activity"));
+                }
+                if self.phase == Phase::DataTransfer {
+                    self.require_peer_tokens(Tokens::MAJOR_ACTIVITY)?;
+                }
+                self.resync = None;
+                let reason = spdu.parameter_octet(spdu::PI_REASON_CODE).unwrap_or(0);
+                if spdu.si == spdu::SI_AI {
+                    self.phase = Phase::WaitForActivityInterruptResponse;
+                    self.indicate(output, SIndication::ActivityInterruptIndication { reference: reference, reason: reason });
+                } else {
+                    self.phase = Phase::WaitForActivityDiscardResponse;
+                    self.indicate(output, SIndication::ActivityDiscardIndication { reference: reference, reason: reason });
+                }
+            },
+            (Phase::WaitForAia, spdu::SI_AIA) => {
+                self.activity_left(true);
+                self.indicate(output, SIndication::ActivityInterruptConfirm { reference: reference });
+            },
+            (Phase::WaitForAda, spdu::SI_ADA) => {
+                self.activity_left(true);
+                self.indicate(output, SIndication::ActivityDiscardConfirm { reference: reference });
+            },
+            (phase, si) => {
+                debug!("session: SPDU {} not allowed on {} in {:?}", si, reference, phase);
+                return Err(protocol_error("SPDU not allowed in the state of the session connection"));
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: FunctionalUnits = FunctionalUnits(
+        FunctionalUnits::HALF_DUPLEX.0 | FunctionalUnits::MINOR_SYNCHRONIZE.0 | FunctionalUnits::MAJOR_SYNCHRONIZE.0
+        | FunctionalUnits::RESYNCHRONIZE.0 | FunctionalUnits::ACTIVITY_MANAGEMENT.0 | FunctionalUnits::NEGOTIATED_RELEASE.0
+    );
+
+    /// initiator and acceptor, all tokens initially on the initiator side
+    fn pair(functional_units: FunctionalUnits) -> (Spm, Spm) {
+        return (Spm::new(1, functional_units, true, 0x00, 1), Spm::new(2, functional_units, false, 0x00, 1));
+    }
+
+    /// what one SPM sent, through encoding and decoding, into the other one - returns the indications
+    fn transfer(output: Output, to: &mut Spm) -> Result<Vec<SIndication>, Error> {
+        let mut received = Output::default();
+        for tsdu in output.tsdus {
+            let mut buffer = vec![];
+            spdu::into_tsdu(&tsdu, &mut buffer).unwrap();
+            to.receive(&spdu::from_tsdu(&buffer).expect("decodable TSDU"), &mut received)?;
+        }
+        assert!(received.tsdus.is_empty());
+        return Ok(received.indications);
+    }
+
+    #[test]
+    fn initial_token_assignment() {
+        let (initiator, acceptor) = pair(ALL);
+        assert_eq!(initiator.owned(), Tokens::DATA | Tokens::MINOR_SYNCHRONIZE | Tokens::MAJOR_ACTIVITY | Tokens::RELEASE);
+        assert_eq!(acceptor.owned(), Tokens::NONE);
+        // data token on the acceptor side, the others with the initiator
+        let setting = Tokens::DATA.setting(spdu::TOKEN_SETTING_ACCEPTOR_SIDE, spdu::TOKEN_SETTING_INITIATOR_SIDE);
+        let acceptor = Spm::new(2, ALL, false, setting, 1);
+        assert_eq!(acceptor.owned(), Tokens::DATA);
+        // no tokens without the functional units
+        let initiator = Spm::new(1, FunctionalUnits::DUPLEX, true, 0x00, 1);
+        assert_eq!(initiator.owned(), Tokens::NONE);
+        assert!(initiator.may_release());
+    }
+
+    #[test]
+    fn data_token() {
+        let (mut initiator, mut acceptor) = pair(FunctionalUnits::HALF_DUPLEX);
+        let mut output = Output::default();
+        assert_eq!(acceptor.data_request(b"x", &mut output).unwrap_err().kind(), ErrorKind::InvalidInput);
+        initiator.data_request(b"hello", &mut output).unwrap();
+        match &transfer(output, &mut acceptor).unwrap()[..] {
+            [SIndication::DataIndication { data, .. }] => assert_eq!(data, b"hello"),
+            other => panic!("{:?}", other)
+        }
+        // please tokens, give tokens
+        let mut output = Output::default();
+        acceptor.please_tokens_request(Tokens::DATA, b"please", &mut output).unwrap();
+        assert!(matches!(&transfer(output, &mut initiator).unwrap()[..], [SIndication::PleaseTokensIndication { tokens: Tokens::DATA, .. }]));
+        let mut output = Output::default();
+        initiator.give_tokens_request(Tokens::DATA, &mut output).unwrap();
+        assert!(matches!(&transfer(output, &mut acceptor).unwrap()[..], [SIndication::GiveTokensIndication { tokens: Tokens::DATA, .. }]));
+        assert_eq!(acceptor.owned(), Tokens::DATA);
+        let mut output = Output::default();
+        assert!(initiator.data_request(b"x", &mut output).is_err());
+        assert!(initiator.give_tokens_request(Tokens::DATA, &mut output).is_err());
+        acceptor.data_request(b"back", &mut output).unwrap();
+        assert_eq!(transfer(output, &mut initiator).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn data_without_token_is_protocol_error() {
+        let (mut initiator, mut acceptor) = pair(FunctionalUnits::HALF_DUPLEX);
+        let mut output = Output::default();
+        output.send(Spdu::with_user_information(spdu::SI_DT, b"x"));
+        assert_eq!(transfer(output, &mut initiator).unwrap_err().kind(), ErrorKind::InvalidData);
+        // GT for a token the receiver already owns
+        let mut output = Output::default();
+        initiator.give_tokens_request(Tokens::DATA, &mut output).unwrap();
+        transfer(output, &mut acceptor).unwrap();
+        let mut output = Output::default();
+        output.send_alone(Spdu::new(spdu::SI_GT, vec![Parameter::new(spdu::PI_TOKEN_ITEM, &[Tokens::DATA.0])]));
+        assert!(transfer(output, &mut acceptor).is_err());
+    }
+
+    #[test]
+    fn minor_synchronization() {
+        let functional_units = FunctionalUnits::DUPLEX | FunctionalUnits::MINOR_SYNCHRONIZE;
+        let (mut initiator, mut acceptor) = pair(functional_units);
+        let mut output = Output::default();
+        assert_eq!(initiator.sync_minor_request(b"", &mut output).unwrap(), 1);
+        assert_eq!(initiator.sync_minor_request(b"two", &mut output).unwrap(), 2);
+        assert!(acceptor.sync_minor_request(b"", &mut Output::default()).is_err());
+        let indications = transfer(output, &mut acceptor).unwrap();
+        assert!(matches!(&indications[..], [SIndication::SyncMinorIndication { serial_number: 1, .. }, SIndication::SyncMinorIndication { serial_number: 2, .. }]));
+        // confirming the second one confirms the first one too
+        let mut output = Output::default();
+        assert!(acceptor.sync_minor_response(3, b"", &mut output).is_err());
+        acceptor.sync_minor_response(2, b"", &mut output).unwrap();
+        assert!(acceptor.sync_minor_response(1, b"", &mut output).is_err());
+        assert!(matches!(&transfer(output, &mut initiator).unwrap()[..], [SIndication::SyncMinorConfirm { serial_number: 2, .. }]));
+        assert_eq!(initiator.lowest_unconfirmed, 3);
+        // MIA again for the same one
+        let mut output = Output::default();
+        output.send(Spdu::new(spdu::SI_MIA, vec![Parameter::new(spdu::PI_SERIAL_NUMBER, b"2")]));
+        assert!(transfer(output, &mut initiator).is_err());
+    }
+
+    #[test]
+    fn major_synchronization() {
+        let functional_units = FunctionalUnits::HALF_DUPLEX | FunctionalUnits::MINOR_SYNCHRONIZE | FunctionalUnits::MAJOR_SYNCHRONIZE;
+        let (mut initiator, mut acceptor) = pair(functional_units);
+        let mut output = Output::default();
+        initiator.sync_minor_request(b"", &mut output).unwrap();
+        assert_eq!(initiator.sync_major_request(b"major", &mut output).unwrap(), 2);
+        assert_eq!(initiator.phase, Phase::WaitForMaa);
+        // STA04A - no data, no other synchronization until MAA
+        assert!(initiator.data_request(b"x", &mut Output::default()).is_err());
+        assert!(initiator.sync_major_request(b"", &mut Output::default()).is_err());
+        assert!(!initiator.may_release());
+        let indications = transfer(output, &mut acceptor).unwrap();
+        assert!(matches!(&indications[..], [SIndication::SyncMinorIndication { .. }, SIndication::SyncMajorIndication { serial_number: 2, .. }]));
+        assert_eq!(acceptor.phase, Phase::WaitForSyncMajorResponse);
+        let mut output = Output::default();
+        assert!(acceptor.sync_minor_response(1, b"", &mut output).is_err());
+        acceptor.sync_major_response(b"", &mut output).unwrap();
+        assert!(matches!(&transfer(output, &mut initiator).unwrap()[..], [SIndication::SyncMajorConfirm { .. }]));
+        assert_eq!(initiator.phase, Phase::DataTransfer);
+        assert_eq!((initiator.next_serial_number, initiator.lowest_unconfirmed, initiator.lowest_restart), (3, 3, 3));
+        assert_eq!((acceptor.next_serial_number, acceptor.lowest_unconfirmed, acceptor.lowest_restart), (3, 3, 3));
+        initiator.data_request(b"x", &mut Output::default()).unwrap();
+    }
+
+    #[test]
+    fn resynchronize() {
+        let functional_units = FunctionalUnits::HALF_DUPLEX | FunctionalUnits::MINOR_SYNCHRONIZE | FunctionalUnits::MAJOR_SYNCHRONIZE | FunctionalUnits::RESYNCHRONIZE;
+        let (mut initiator, mut acceptor) = pair(functional_units);
+        let mut output = Output::default();
+        initiator.sync_major_request(b"", &mut output).unwrap();
+        transfer(output, &mut acceptor).unwrap();
+        let mut output = Output::default();
+        acceptor.sync_major_response(b"", &mut output).unwrap();
+        transfer(output, &mut initiator).unwrap();
+        let mut output = Output::default();
+        initiator.sync_minor_request(b"", &mut output).unwrap();
+        initiator.sync_minor_request(b"", &mut output).unwrap();
+        transfer(output, &mut acceptor).unwrap();
+        // restart not before the major synchronization point, the acceptor may ask for it too
+        assert!(acceptor.resynchronize_request(ResyncType::Restart, 1, Tokens::NONE, b"", &mut Output::default()).is_err());
+        let mut output = Output::default();
+        assert_eq!(acceptor.resynchronize_request(ResyncType::Restart, 3, Tokens::DATA, b"again", &mut output).unwrap(), 3);
+        assert_eq!(acceptor.phase, Phase::WaitForRa);
+        match &transfer(output, &mut initiator).unwrap()[..] {
+            [SIndication::ResynchronizeIndication { resync_type: ResyncType::Restart, serial_number: 3, tokens, data, .. }] => {
+                assert_eq!(*tokens, Tokens::DATA);
+                assert_eq!(data, b"again");
+            },
+            other => panic!("{:?}", other)
+        }
+        // data sent before seeing the RS is discarded
+        let mut output = Output::default();
+        output.send(Spdu::with_user_information(spdu::SI_DT, b"stale"));
+        assert!(transfer(output, &mut acceptor).unwrap().is_empty());
+        let mut output = Output::default();
+        initiator.resynchronize_response(b"", &mut output).unwrap();
+        assert!(matches!(&transfer(output, &mut acceptor).unwrap()[..], [SIndication::ResynchronizeConfirm { serial_number: 3, .. }]));
+        assert_eq!(acceptor.owned(), Tokens::DATA);
+        assert_eq!(initiator.owned(), Tokens::MINOR_SYNCHRONIZE | Tokens::MAJOR_ACTIVITY);
+        assert_eq!((acceptor.next_serial_number, initiator.next_serial_number, initiator.lowest_restart), (3, 3, 2));
+        // abandon continues at V(M), set anywhere, both forget about where to restart
+        let mut output = Output::default();
+        assert_eq!(initiator.resynchronize_request(ResyncType::Abandon, 0, Tokens::NONE, b"", &mut output).unwrap(), 3);
+        transfer(output, &mut acceptor).unwrap();
+        let mut output = Output::default();
+        acceptor.resynchronize_response(b"", &mut output).unwrap();
+        transfer(output, &mut initiator).unwrap();
+        assert_eq!(initiator.lowest_restart, 0);
+        assert_eq!(acceptor.owned(), Tokens::DATA | Tokens::MINOR_SYNCHRONIZE | Tokens::MAJOR_ACTIVITY);
+        let mut output = Output::default();
+        assert_eq!(initiator.resynchronize_request(ResyncType::Set, 500, Tokens::DATA, b"", &mut output).unwrap(), 500);
+        transfer(output, &mut acceptor).unwrap();
+        let mut output = Output::default();
+        acceptor.resynchronize_response(b"", &mut output).unwrap();
+        transfer(output, &mut initiator).unwrap();
+        assert_eq!((initiator.next_serial_number, acceptor.next_serial_number), (500, 500));
+    }
+
+    #[test]
+    fn resynchronize_collision() {
+        let functional_units = FunctionalUnits::DUPLEX | FunctionalUnits::MINOR_SYNCHRONIZE | FunctionalUnits::RESYNCHRONIZE;
+        // abandon wins over restart
+        let (mut initiator, mut acceptor) = pair(functional_units);
+        let (mut to_acceptor, mut to_initiator) = (Output::default(), Output::default());
+        initiator.resynchronize_request(ResyncType::Restart, 1, Tokens::NONE, b"", &mut to_acceptor).unwrap();
+        acceptor.resynchronize_request(ResyncType::Abandon, 0, Tokens::NONE, b"", &mut to_initiator).unwrap();
+        assert!(transfer(to_acceptor, &mut acceptor).unwrap().is_empty());
+        assert!(matches!(&transfer(to_initiator, &mut initiator).unwrap()[..], [SIndication::ResynchronizeIndication { resync_type: ResyncType::Abandon, .. }]));
+        assert_eq!((initiator.phase, acceptor.phase), (Phase::WaitForResynchronizeResponse, Phase::WaitForRa));
+        // same type and serial number - the initiator of the session connection wins
+        let (mut initiator, mut acceptor) = pair(functional_units);
+        let (mut to_acceptor, mut to_initiator) = (Output::default(), Output::default());
+        initiator.resynchronize_request(ResyncType::Set, 7, Tokens::NONE, b"", &mut to_acceptor).unwrap();
+        acceptor.resynchronize_request(ResyncType::Set, 7, Tokens::NONE, b"", &mut to_initiator).unwrap();
+        assert!(transfer(to_initiator, &mut initiator).unwrap().is_empty());
+        assert_eq!(transfer(to_acceptor, &mut acceptor).unwrap().len(), 1);
+        assert_eq!((initiator.phase, acceptor.phase), (Phase::WaitForRa, Phase::WaitForResynchronizeResponse));
+    }
+
+    #[test]
+    fn activities() {
+        let (mut initiator, mut acceptor) = pair(ALL);
+        // STA713 without activity - no data, no synchronization
+        assert!(initiator.data_request(b"x", &mut Output::default()).is_err());
+        assert!(initiator.sync_minor_request(b"", &mut Output::default()).is_err());
+        assert!(initiator.activity_end_request(b"", &mut Output::default()).is_err());
+        assert!(acceptor.activity_start_request(b"A", b"", &mut Output::default()).is_err());
+        let mut output = Output::default();
+        initiator.activity_start_request(b"A", b"start", &mut output).unwrap();
+        assert!(!initiator.may_release());
+        assert!(initiator.activity_start_request(b"B", b"", &mut Output::default()).is_err());
+        initiator.data_request(b"x", &mut output).unwrap();
+        assert_eq!(initiator.sync_minor_request(b"", &mut output).unwrap(), 1);
+        match &transfer(output, &mut acceptor).unwrap()[..] {
+            [SIndication::ActivityStartIndication { activity_identifier, .. }, SIndication::DataIndication { .. }, SIndication::SyncMinorIndication { serial_number: 1, .. }] => assert_eq!(activity_identifier, b"A"),
+            other => panic!("{:?}", other)
+        }
+        // interrupt - afterwards all tokens with the requestor
+        let mut output = Output::default();
+        initiator.activity_interrupt_request(3, &mut output).unwrap();
+        assert_eq!(initiator.phase, Phase::WaitForAia);
+        assert!(matches!(&transfer(output, &mut acceptor).unwrap()[..], [SIndication::ActivityInterruptIndication { reason: 3, .. }]));
+        let mut output = Output::default();
+        acceptor.activity_interrupt_response(&mut output).unwrap();
+        assert!(matches!(&transfer(output, &mut initiator).unwrap()[..], [SIndication::ActivityInterruptConfirm { .. }]));
+        assert!(!initiator.activity && !acceptor.activity);
+        assert!(initiator.may_release());
+        assert!(acceptor.peer_may_release());
+        // resume behind synchronization point 1, then end
+        let mut output = Output::default();
+        initiator.activity_resume_request(b"B", b"A", 1, b"", &mut output).unwrap();
+        match &transfer(output, &mut acceptor).unwrap()[..] {
+            [SIndication::ActivityResumeIndication { activity_identifier, old_activity_identifier, serial_number: 1, .. }] => {
+                assert_eq!(activity_identifier, b"B");
+                assert_eq!(old_activity_identifier, b"A");
+            },
+            other => panic!("{:?}", other)
+        }
+        let mut output = Output::default();
+        assert_eq!(initiator.activity_end_request(b"end", &mut output).unwrap(), 2);
+        assert_eq!(initiator.phase, Phase::WaitForAea);
+        assert!(matches!(&transfer(output, &mut acceptor).unwrap()[..], [SIndication::ActivityEndIndication { serial_number: 2, .. }]));
+        let mut output = Output::default();
+        acceptor.activity_end_response(b"", &mut output).unwrap();
+        assert!(matches!(&transfer(output, &mut initiator).unwrap()[..], [SIndication::ActivityEndConfirm { .. }]));
+        assert!(!initiator.activity && initiator.phase == Phase::DataTransfer);
+        // discard
+        let mut output = Output::default();
+        initiator.activity_start_request(b"C", b"", &mut output).unwrap();
+        initiator.activity_discard_request(0, &mut output).unwrap();
+        let indications = transfer(output, &mut acceptor).unwrap();
+        assert!(matches!(&indications[..], [SIndication::ActivityStartIndication { .. }, SIndication::ActivityDiscardIndication { .. }]));
+        // data the acceptor sent meanwhile is discarded
+        let mut output = Output::default();
+        output.send(Spdu::with_user_information(spdu::SI_DT, b"stale"));
+        assert!(transfer(output, &mut initiator).unwrap().is_empty());
+        let mut output = Output::default();
+        acceptor.activity_discard_response(&mut output).unwrap();
+        assert!(matches!(&transfer(output, &mut initiator).unwrap()[..], [SIndication::ActivityDiscardConfirm { .. }]));
+    }
+
+    #[test]
+    fn activity_spdus_out_of_state() {
+        let (mut initiator, mut acceptor) = pair(ALL);
+        // AE without activity resp. AIA without AI
+        let mut output = Output::default();
+        output.send(Spdu::new(spdu::SI_AE, vec![Parameter::new(spdu::PI_SERIAL_NUMBER, b"1")]));
+        assert!(transfer(output, &mut acceptor).is_err());
+        let mut output = Output::default();
+        output.send(Spdu::new(spdu::SI_AIA, vec![]));
+        assert!(transfer(output, &mut acceptor).is_err());
+        // AS from the side without the major/activity token
+        let mut output = Output::default();
+        output.send(Spdu::new(spdu::SI_AS, vec![Parameter::new(spdu::PI_ACTIVITY_IDENTIFIER, b"X")]));
+        assert!(transfer(output, &mut initiator).is_err());
+    }
+
+    #[test]
+    fn data_while_waiting_for_maa() {
+        // duplex - the peer sends data before it sees the MAP
+        let functional_units = FunctionalUnits::DUPLEX | FunctionalUnits::MINOR_SYNCHRONIZE | FunctionalUnits::MAJOR_SYNCHRONIZE;
+        let (mut initiator, mut acceptor) = pair(functional_units);
+        initiator.sync_major_request(b"", &mut Output::default()).unwrap();
+        let mut output = Output::default();
+        acceptor.data_request(b"meanwhile", &mut output).unwrap();
+        match &transfer(output, &mut initiator).unwrap()[..] {
+            [SIndication::DataIndication { data, .. }] => assert_eq!(data, b"meanwhile"),
+            other => panic!("{:?}", other)
+        }
+        assert_eq!(initiator.phase, Phase::WaitForMaa);
+        // half duplex - the data token is ours while waiting
+        let functional_units = FunctionalUnits::HALF_DUPLEX | FunctionalUnits::MINOR_SYNCHRONIZE | FunctionalUnits::MAJOR_SYNCHRONIZE;
+        let (mut initiator, _) = pair(functional_units);
+        initiator.sync_major_request(b"", &mut Output::default()).unwrap();
+        let mut output = Output::default();
+        output.send(Spdu::with_user_information(spdu::SI_DT, b"x"));
+        assert_eq!(transfer(output, &mut initiator).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn map_without_sync_type_item() {
+        // without activity management, there is no AE - a MAP without sync type item is a MAP still
+        let functional_units = FunctionalUnits::HALF_DUPLEX | FunctionalUnits::MAJOR_SYNCHRONIZE;
+        let (_, mut acceptor) = pair(functional_units);
+        let mut output = Output::default();
+        output.send(Spdu::new(spdu::SI_MAP, vec![Parameter::new(spdu::PI_SERIAL_NUMBER, b"1")]));
+        assert!(matches!(&transfer(output, &mut acceptor).unwrap()[..], [SIndication::SyncMajorIndication { serial_number: 1, .. }]));
+        assert_eq!(acceptor.phase, Phase::WaitForSyncMajorResponse);
+    }
+
+    #[test]
+    fn serial_numbers_exhausted() {
+        let functional_units = FunctionalUnits::DUPLEX | FunctionalUnits::MINOR_SYNCHRONIZE | FunctionalUnits::MAJOR_SYNCHRONIZE;
+        let mut initiator = Spm::new(1, functional_units, true, 0x00, spdu::SERIAL_NUMBER_MAXIMUM);
+        let mut acceptor = Spm::new(2, functional_units, false, 0x00, spdu::SERIAL_NUMBER_MAXIMUM);
+        let mut output = Output::default();
+        assert_eq!(initiator.sync_minor_request(b"", &mut output).unwrap(), spdu::SERIAL_NUMBER_MAXIMUM);
+        transfer(output, &mut acceptor).unwrap();
+        assert_eq!(initiator.sync_minor_request(b"", &mut Output::default()).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(initiator.sync_major_request(b"", &mut Output::default()).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(initiator.phase, Phase::DataTransfer);
+        // whatever the peer sends as the next one cannot be it
+        let mut output = Output::default();
+        output.send(Spdu::new(spdu::SI_MIP, vec![Parameter::new(spdu::PI_SERIAL_NUMBER, b"999999")]));
+        assert_eq!(transfer(output, &mut acceptor).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn serial_numbers() {
+        assert_eq!(spdu::serial_number_value(0), b"0");
+        assert_eq!(spdu::serial_number_value(999999), b"999999");
+        assert_eq!(spdu::parse_serial_number(b"042"), Some(42));
+        assert_eq!(spdu::parse_serial_number(b""), None);
+        assert_eq!(spdu::parse_serial_number(b"1234567"), None);
+        assert_eq!(spdu::parse_serial_number(b"12a"), None);
+    }
+}
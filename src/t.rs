@@ -0,0 +1,93 @@
+pub mod tpdu;
+pub mod tp4;
+pub mod cltp;
+pub mod rfc1006;
+
+use std::io::Error;
+use std::net::SocketAddr;
+use std::thread::Thread;
+
+use crate::n::Nsap;
+
+/// X.214 transport service, whichever protocol class and network service provides it
+/// NOTE: the TS user is told about indications and confirms via the queue it got when creating the service
+pub trait TransportService {
+    /// starts the TS provider - the given thread, if any, is unparked when there are new indications for the TS user
+    fn run(&mut self, t_user_wakeup: Option<Thread>);
+    /// incoming transport connections to this TSAP selector are indicated to the TS user, to all others refused
+    fn bind(&mut self, tsel: &[u8]) -> Result<(), Error>;
+    fn unbind(&mut self, tsel: &[u8]);
+    /// T-CONNECT request - returns the local reference identifying the new transport connection, T-CONNECT confirm follows as ConnectConfirm
+    fn t_connect_request(&mut self, called_address: &TransportAddress, calling_tsel: &[u8], ts_userdata: &[u8]) -> Result<u16, Error>;
+    /// T-CONNECT response, accepting a ConnectIndication
+    fn t_connect_response(&mut self, reference: u16, ts_userdata: &[u8]) -> Result<(), Error>;
+    /// T-DATA request
+    fn t_data_request(&mut self, reference: u16, ts_userdata: &[u8]) -> Result<(), Error>;
+    /// T-EXPEDITED-DATA request
+    fn t_expedited_data_request(&mut self, reference: u16, ts_userdata: &[u8]) -> Result<(), Error>;
+    /// T-DISCONNECT request, also for refusing a ConnectIndication
+    fn t_disconnect_request(&mut self, reference: u16, ts_userdata: &[u8]) -> Result<(), Error>;
+    /// what has been negotiated for the transport connection - final once it is established
+    fn connection_options(&self, reference: u16) -> Option<ConnectionOptions>;
+}
+
+/// X.224 6.5 protocol class and options of a transport connection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectionOptions {
+    pub class: u8,
+    pub extended_format: bool,
+    pub explicit_flow_control: bool,
+    pub checksum: bool,
+    pub expedited: bool,
+    pub maximum_tpdu_size: usize,
+}
+
+/// where the network connection resp. NSDUs of a transport connection go
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetworkAddress {
+    Nsap(Nsap),
+    /// RFC 1006 resp. RFC 2126, transport over TCP
+    Ip(SocketAddr),
+}
+
+/// X.214 transport address = NSAP address and TSAP selector
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransportAddress {
+    pub network_address: NetworkAddress,
+    pub tsel: Vec<u8>,
+}
+
+impl TransportAddress {
+    pub fn new(network_address: NetworkAddress, tsel: &[u8]) -> Self {
+        return TransportAddress { network_address: network_address, tsel: tsel.to_vec() };
+    }
+}
+
+/// X.214 indication and confirm primitives from the TS provider towards the TS user
+/// NOTE: the transport connection is identified by the local reference
+#[derive(Debug)]
+pub enum TIndication {
+    ConnectIndication {
+        reference: u16,
+        calling_address: TransportAddress,
+        called_address: TransportAddress,
+        data: Vec<u8>,
+    },
+    ConnectConfirm {
+        reference: u16,
+        data: Vec<u8>,
+    },
+    DataIndication {
+        reference: u16,
+        data: Vec<u8>,
+    },
+    ExpeditedDataIndication {
+        reference: u16,
+        data: Vec<u8>,
+    },
+    DisconnectIndication {
+        reference: u16,
+        reason: u8,
+        data: Vec<u8>,
+    },
+}
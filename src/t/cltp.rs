@@ -0,0 +1,343 @@
+use std::{collections::HashMap, io::{Error, ErrorKind}, sync::{Arc, Mutex}, thread::{self, Thread, JoinHandle}, time::{Duration, Instant}};
+
+use crate::n::{NetworkService, Nsap, NSUnitDataIndication, NsUser, Qos};
+use super::tpdu::{self, Tpdu, Parameter};
+
+/// T-UNITDATA indication
+#[derive(Debug)]
+pub struct TUnitDataIndication {
+    pub source_address: Nsap,
+    pub source_tsel: Vec<u8>,
+    pub destination_tsel: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// a TSAP selector bound by a Socket
+struct Binding {
+    t_user_to: rtrb::Producer<TUnitDataIndication>,
+    t_user_to_wakeup: Arc<Mutex<Option<Thread>>>,
+}
+
+/// X.234 connectionless-mode transport protocol over the connectionless-mode network service
+pub struct Service<N: NetworkService<'static> + Send + 'static> {
+    ns: Arc<Mutex<N>>,
+    bindings: Arc<Mutex<HashMap<Vec<u8>, Binding>>>,
+    ns_user_from: Arc<Mutex<rtrb::Consumer<NSUnitDataIndication>>>,
+    ns_user_from_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
+    /// X.234 6.3 - whether to put a checksum on sent UD TPDUs, received ones are checked if they have one
+    checksum: bool,
+    queue_capacity: usize,
+}
+
+impl<N: NetworkService<'static> + Send + 'static> Service<N> {
+    /// registers itself as the user of the given NS for UD TPDUs
+    pub fn new(ns: Arc<Mutex<N>>, checksum: bool, queue_capacity: usize) -> Self {
+        let (ns_user_to, ns_user_from) = rtrb::RingBuffer::new(queue_capacity);
+        let ns_user_from_wakeup = Arc::new(Mutex::new(None));
+        ns.lock().expect("failed to lock ns").register_ns_user(NsUser {
+            accepts: tpdu::is_unit_data,
+            ns_user_to: ns_user_to,
+            ns_user_to_wakeup: ns_user_from_wakeup.clone(),
+        });
+        return Service {
+            ns: ns,
+            bindings: Arc::new(Mutex::new(HashMap::new())),
+            ns_user_from: Arc::new(Mutex::new(ns_user_from)),
+            ns_user_from_wakeup: ns_user_from_wakeup,
+            checksum: checksum,
+            queue_capacity: queue_capacity,
+        };
+    }
+
+    pub fn run(&mut self) {
+        // read N-UNITDATA-INDICATION from NS and demultiplex to the bound TSAPs
+        let bindings_arc = self.bindings.clone();
+        let ns_user_from_arc = self.ns_user_from.clone();
+        let ns2ts_consumer_wakeup = thread::Builder::new().name("T CLTP <- N".to_string()).spawn(move || {
+            // keep permanent lock on this
+            let mut ns_user_from = ns_user_from_arc.lock().expect("failed to lock ns_user_from");
+            loop {
+                while let Ok(indication) = ns_user_from.pop() {
+                    t_unitdata_indication(&bindings_arc, indication);
+                }
+                thread::park(); // wait for unpark wakeup call from NS
+            }
+        }).expect("failed to start thread");
+        // put thread handle into well-known place
+        self.ns_user_from_wakeup.lock().expect("failed to lock ns_user_from_wakeup (giver)").replace(ns2ts_consumer_wakeup);
+    }
+
+    /// like UdpSocket::bind() - UD TPDUs to this TSAP selector are received by the returned Socket until it is dropped
+    pub fn bind(&self, tsel: &[u8]) -> Result<Socket<N>, Error> {
+        let mut bindings = self.bindings.lock().expect("failed to lock bindings");
+        if bindings.contains_key(tsel) {
+            return Err(Error::new(ErrorKind::AddrInUse, "TSAP selector already bound"));
+        }
+        let (t_user_to, t_user_from) = rtrb::RingBuffer::new(self.queue_capacity);
+        let t_user_to_wakeup = Arc::new(Mutex::new(None));
+        bindings.insert(tsel.to_vec(), Binding { t_user_to: t_user_to, t_user_to_wakeup: t_user_to_wakeup.clone() });
+        return Ok(Socket {
+            local_tsel: tsel.to_vec(),
+            ns: self.ns.clone(),
+            bindings: self.bindings.clone(),
+            t_user_from: t_user_from,
+            t_user_from_wakeup: t_user_to_wakeup,
+            checksum: self.checksum,
+            read_timeout: None,
+        });
+    }
+}
+
+fn t_unitdata_indication(bindings: &Mutex<HashMap<Vec<u8>, Binding>>, indication: NSUnitDataIndication) {
+    let (parameters, data) = match Tpdu::from_buf(&indication.ns_userdata) {
+        Ok((Tpdu::UnitData { parameters, data }, _)) => (parameters, data),
+        Ok(_) => { info!("CLTP: not a UD TPDU, discarding"); return; },
+        Err(e) => { info!("CLTP: discarding invalid TPDU from {}: {}", indication.ns_source_address.to_string(), e); return; }
+    };
+    let tsel = |code: u8| parameters.iter().find(|parameter| parameter.code == code).map(|parameter| parameter.value.clone());
+    let destination_tsel = match tsel(tpdu::PARAMETER_CALLED_TSAP) {
+        Some(destination_tsel) => destination_tsel,
+        None => { info!("CLTP: UD TPDU without destination TSAP-ID, discarding"); return; }
+    };
+    let mut bindings = bindings.lock().expect("failed to lock bindings");
+    if let Some(binding) = bindings.get_mut(&destination_tsel) {
+        if let Err(_) = binding.t_user_to.push(TUnitDataIndication {
+            source_address: indication.ns_source_address,
+            source_tsel: tsel(tpdu::PARAMETER_CALLING_TSAP).unwrap_or_default(),
+            destination_tsel: destination_tsel,
+            data: data,
+        }) {
+            // NOTE: connectionless - lost like on the network
+            debug!("CLTP: queue towards TS user full, discarding UD TPDU");
+            return;
+        }
+        if let Some(wakeup) = binding.t_user_to_wakeup.lock().expect("failed to lock t_user_to_wakeup").as_ref() {
+            wakeup.unpark();
+        }
+    } else {
+        debug!("CLTP: no TS user bound to TSAP selector {:?}, discarding UD TPDU", destination_tsel);
+    }
+}
+
+/// TSAP bound for sending and receiving TSDUs without a connection, like a UdpSocket
+pub struct Socket<N: NetworkService<'static> + Send + 'static> {
+    local_tsel: Vec<u8>,
+    ns: Arc<Mutex<N>>,
+    bindings: Arc<Mutex<HashMap<Vec<u8>, Binding>>>,
+    t_user_from: rtrb::Consumer<TUnitDataIndication>,
+    t_user_from_wakeup: Arc<Mutex<Option<Thread>>>,
+    checksum: bool,
+    read_timeout: Option<Duration>,
+}
+
+impl<N: NetworkService<'static> + Send + 'static> Socket<N> {
+    pub fn local_tsel(&self) -> &[u8] {
+        return &self.local_tsel;
+    }
+
+    /// None blocks in recv_from() until a TSDU arrives
+    pub fn set_read_timeout(&mut self, read_timeout: Option<Duration>) {
+        self.read_timeout = read_timeout;
+    }
+
+    /// T-UNITDATA request
+    /// NOTE: the TSDU has to fit into one NSDU, there is no segmentation in CLTP
+    pub fn send_to(&mut self, data: &[u8], destination_address: &Nsap, destination_tsel: &[u8]) -> Result<usize, Error> {
+        // code, both TSAP-ID parameters and the checksum parameter have to fit behind the length indicator
+        if 1 + 2 + self.local_tsel.len() + 2 + destination_tsel.len() + 4 > 254 {
+            return Err(Error::new(ErrorKind::InvalidInput, "TSAP selectors too long for UD TPDU header"));
+        }
+        let ud = Tpdu::UnitData {
+            parameters: vec![
+                Parameter { code: tpdu::PARAMETER_CALLING_TSAP, value: self.local_tsel.clone() },
+                Parameter { code: tpdu::PARAMETER_CALLED_TSAP, value: destination_tsel.to_vec() },
+            ],
+            data: data.to_vec(),    //TODO optimize - encode straight from the slice
+        };
+        let mut buffer = vec![];
+        ud.into_buf(self.checksum, &mut buffer);
+        //TODO map transport QoS onto NS QoS
+        self.ns.lock().expect("failed to lock ns").n_unitdata_request_to_nsap(destination_address, &Qos::default(), &buffer)?;
+        return Ok(data.len());
+    }
+
+    /// like UdpSocket::recv_from() - the TSDU is truncated to the buffer, returns its length, source NSAP and source TSAP selector
+    /// blocks up to the read timeout, then WouldBlock
+    pub fn recv_from(&mut self, buffer: &mut [u8]) -> Result<(usize, Nsap, Vec<u8>), Error> {
+        self.t_user_from_wakeup.lock().expect("failed to lock t_user_from_wakeup").replace(thread::current());
+        let deadline = self.read_timeout.map(|read_timeout| Instant::now() + read_timeout);
+        loop {
+            if let Ok(indication) = self.t_user_from.pop() {
+                let bytes = indication.data.len().min(buffer.len());
+                buffer[0..bytes].copy_from_slice(&indication.data[0..bytes]);
+                return Ok((bytes, indication.source_address, indication.source_tsel));
+            }
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::new(ErrorKind::WouldBlock, "no TSDU received within read timeout"));
+                    }
+                    thread::park_timeout(deadline - now);
+                },
+                None => { thread::park(); }
+            }
+        }
+    }
+
+    /// without waiting, WouldBlock if nothing has arrived
+    pub fn try_recv_from(&mut self, buffer: &mut [u8]) -> Result<(usize, Nsap, Vec<u8>), Error> {
+        if let Ok(indication) = self.t_user_from.pop() {
+            let bytes = indication.data.len().min(buffer.len());
+            buffer[0..bytes].copy_from_slice(&indication.data[0..bytes]);
+            return Ok((bytes, indication.source_address, indication.source_tsel));
+        }
+        return Err(Error::new(ErrorKind::WouldBlock, "no TSDU received"));
+    }
+}
+
+impl<N: NetworkService<'static> + Send + 'static> Drop for Socket<N> {
+    fn drop(&mut self) {
+        self.bindings.lock().expect("failed to lock bindings").remove(&self.local_tsel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use advmac::MacAddr6;
+
+    use super::*;
+    use crate::{dl::SNUnitDataRequest, n::{clnp, SubnetworkAttachment}};
+
+    const NSAP_A: [u8; 12] = [0, 49, 0, 1, 0, 1, 0x02, 0, 0, 0, 0, 0x0a];
+    const NSAP_B: [u8; 12] = [0, 49, 0, 1, 0, 1, 0x02, 0, 0, 0, 0, 0x0b];
+
+    /// CLNP attached to one subnetwork as A, not running - returns the normal band of the queue towards the SN
+    fn network() -> (Arc<Mutex<clnp::Service<'static>>>, rtrb::Consumer<SNUnitDataRequest>) {
+        let snpa = MacAddr6::new([0x02, 0, 0, 0, 0, 0x0a]);
+        let (bands, mut consumers): (Vec<_>, Vec<_>) = (0..crate::dl::PRIORITY_BANDS).map(|_| rtrb::RingBuffer::new(8)).unzip();
+        let mut ns = clnp::Service::new(
+            "own",
+            vec![SubnetworkAttachment { snpa: snpa, maximum_sdu_size: 1497, encapsulation: crate::dl::Encapsulation::EthernetII, up: true }],
+            vec![bands],
+            vec![Arc::new(Mutex::new(Some(thread::spawn(thread::current))))],
+            vec![Arc::new(Mutex::new(vec![]))],
+            vec![rtrb::RingBuffer::new(8).1],
+            vec![rtrb::RingBuffer::new(8).1]
+        );
+        ns.add_serviced_subnet_nsap(1, 1, snpa);
+        return (Arc::new(Mutex::new(ns)), consumers.remove(crate::dl::BAND_NORMAL));
+    }
+
+    fn nsap(address: [u8; 12]) -> Nsap {
+        return Nsap::from_u8(&address).unwrap();
+    }
+
+    /// the UD TPDU inside the CLNP PDU sent, full protocol or inactive subset
+    fn sent_ud(sn: &mut rtrb::Consumer<SNUnitDataRequest>) -> Vec<u8> {
+        let pdu = sn.pop().unwrap().sn_userdata;
+        let header_length = if pdu[0] == 0 { 1 } else { pdu[1] as usize };
+        return pdu[header_length..].to_vec();
+    }
+
+    fn ud(source_tsel: &[u8], destination_tsel: &[u8], data: &[u8], checksum: bool) -> Vec<u8> {
+        let mut buffer = vec![];
+        Tpdu::UnitData {
+            parameters: vec![
+                Parameter { code: tpdu::PARAMETER_CALLING_TSAP, value: source_tsel.to_vec() },
+                Parameter { code: tpdu::PARAMETER_CALLED_TSAP, value: destination_tsel.to_vec() },
+            ],
+            data: data.to_vec(),
+        }.into_buf(checksum, &mut buffer);
+        return buffer;
+    }
+
+    fn receive<N: NetworkService<'static> + Send + 'static>(cltp: &Service<N>, ud: Vec<u8>) {
+        t_unitdata_indication(&cltp.bindings, NSUnitDataIndication { ns_source_address: nsap(NSAP_B), ns_destination_address: nsap(NSAP_A), ns_quality_of_service: Qos::default(), ns_userdata: ud });
+    }
+
+    #[test]
+    fn unit_data_encoding() {
+        for checksum in [false, true] {
+            let (ns, mut sn) = network();
+            let cltp = Service::new(ns, checksum, 4);
+            let mut socket = cltp.bind(b"A").unwrap();
+            assert_eq!(socket.send_to(b"datagram", &nsap(NSAP_B), b"B").unwrap(), 8);
+            let sent = sent_ud(&mut sn);
+            assert!(tpdu::is_unit_data(&sent));
+            let (decoded, _) = Tpdu::from_buf(&sent).unwrap();
+            assert_eq!(decoded.parameter(tpdu::PARAMETER_CALLING_TSAP), Some(&b"A"[..]));
+            assert_eq!(decoded.parameter(tpdu::PARAMETER_CALLED_TSAP), Some(&b"B"[..]));
+            assert_eq!(decoded.parameter(tpdu::PARAMETER_CHECKSUM).is_some(), checksum);
+            assert_eq!(sent, ud(b"A", b"B", b"datagram", checksum));
+            assert!(matches!(decoded, Tpdu::UnitData { ref data, .. } if data == b"datagram"));
+        }
+    }
+
+    #[test]
+    fn bad_checksum_discarded() {
+        let (ns, _sn) = network();
+        let cltp = Service::new(ns, true, 4);
+        let mut socket = cltp.bind(b"A").unwrap();
+        let mut corrupted = ud(b"B", b"A", b"datagram", true);
+        *corrupted.last_mut().unwrap() ^= 0x01;
+        receive(&cltp, corrupted);
+        assert_eq!(socket.try_recv_from(&mut [0u8; 16]).unwrap_err().kind(), ErrorKind::WouldBlock);
+        // without checksum, nothing to check
+        receive(&cltp, ud(b"B", b"A", b"datagram", false));
+        assert_eq!(socket.try_recv_from(&mut [0u8; 16]).unwrap().0, 8);
+    }
+
+    #[test]
+    fn demultiplexed_by_tsap_selector() {
+        let (ns, _sn) = network();
+        let cltp = Service::new(ns, false, 4);
+        let mut a = cltp.bind(b"A").unwrap();
+        let mut a2 = cltp.bind(b"A2").unwrap();
+        assert_eq!(cltp.bind(b"A").err().unwrap().kind(), ErrorKind::AddrInUse);
+        receive(&cltp, ud(b"B", b"A2", b"second", false));
+        receive(&cltp, ud(b"B", b"A", b"first", false));
+        receive(&cltp, ud(b"B", b"C", b"nobody", false));
+        let mut buffer = [0u8; 16];
+        let (bytes, source_address, source_tsel) = a.try_recv_from(&mut buffer).unwrap();
+        assert_eq!((&buffer[0..bytes], source_tsel.as_slice()), (&b"first"[..], &b"B"[..]));
+        assert_eq!(source_address, nsap(NSAP_B));
+        assert!(a.try_recv_from(&mut buffer).is_err());
+        let (bytes, _, _) = a2.try_recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[0..bytes], b"second");
+        // unbound when dropped
+        drop(a2);
+        let mut again = cltp.bind(b"A2").unwrap();
+        receive(&cltp, ud(b"B", b"A2", b"third", false));
+        assert_eq!(again.try_recv_from(&mut buffer).unwrap().0, 5);
+    }
+
+    #[test]
+    fn tsap_selectors_checked() {
+        let (ns, mut sn) = network();
+        let cltp = Service::new(ns, true, 4);
+        let mut socket = cltp.bind(b"A").unwrap();
+        // 1 + 2 + 1 + 2 + 244 + 4 = 254 still fits behind the length indicator
+        assert!(socket.send_to(b"datagram", &nsap(NSAP_B), &[0x42; 244]).is_ok());
+        assert_eq!(sent_ud(&mut sn)[0], 254);
+        assert_eq!(socket.send_to(b"datagram", &nsap(NSAP_B), &[0x42; 245]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(sn.pop().is_err());
+    }
+
+    #[test]
+    fn receive_truncated_and_timeout() {
+        let (ns, _sn) = network();
+        let cltp = Service::new(ns, false, 4);
+        let mut socket = cltp.bind(b"A").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(50)));
+        let start = Instant::now();
+        assert_eq!(socket.recv_from(&mut [0u8; 16]).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        // like UdpSocket, the rest of the TSDU is lost
+        receive(&cltp, ud(b"B", b"A", b"datagram", false));
+        let mut buffer = [0u8; 4];
+        assert_eq!(socket.recv_from(&mut buffer).unwrap().0, 4);
+        assert_eq!(&buffer, b"data");
+        assert!(socket.try_recv_from(&mut buffer).is_err());
+    }
+}
@@ -0,0 +1,924 @@
+use std::{collections::{HashMap, HashSet}, io::{Error, ErrorKind, Read, Write}, net::{Shutdown, SocketAddr, TcpListener, TcpStream}, sync::{Arc, Mutex}, thread::{self, Thread}, time::Duration};
+
+use super::{ConnectionOptions, NetworkAddress, TIndication, TransportAddress, TransportService};
+use super::tpdu::{self, Tpdu, Parameter};
+
+/// well-known TCP port for ISO transport services on top of TCP
+pub const PORT: u16 = 102;
+/// RFC 1006 6. TPKT
+pub const TPKT_VERSION: u8 = 3;
+const TPKT_HEADER_LENGTH: usize = 4;
+pub const TPKT_MAXIMUM_LENGTH: usize = 65535;
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// how often a reader waits for the TS user to make room in its queue - meanwhile TCP flow control holds back the peer
+const BACKPRESSURE_INTERVAL: Duration = Duration::from_millis(10);
+/// X.224 13.3.4 - user data of a CR and CC TPDU, not allowed at all in class 0
+pub const CONNECT_DATA_MAXIMUM: usize = 32;
+/// X.224 13.11 - user data of an ED TPDU
+pub const EXPEDITED_DATA_MAXIMUM: usize = 16;
+
+/// sends one TPKT
+pub fn write_tpkt(stream: &mut impl Write, payload: &[u8]) -> Result<(), Error> {
+    let length = TPKT_HEADER_LENGTH + payload.len();
+    if length > TPKT_MAXIMUM_LENGTH {
+        return Err(Error::new(ErrorKind::InvalidInput, "TPKT too long"));
+    }
+    let mut buffer = Vec::with_capacity(length);
+    buffer.push(TPKT_VERSION);
+    buffer.push(0);     // reserved
+    buffer.extend_from_slice(&(length as u16).to_be_bytes());
+    buffer.extend_from_slice(payload);
+    stream.write_all(&buffer)?;
+    return Ok(());
+}
+
+/// receives one TPKT, returns its payload = one or more concatenated TPDUs
+pub fn read_tpkt(stream: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let mut header = [0u8; TPKT_HEADER_LENGTH];
+    stream.read_exact(&mut header)?;
+    if header[0] != TPKT_VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, "not a TPKT"));
+    }
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if length < TPKT_HEADER_LENGTH + 1 {
+        return Err(Error::new(ErrorKind::InvalidData, "TPKT length too short"));
+    }
+    let mut payload = vec![0u8; length - TPKT_HEADER_LENGTH];
+    stream.read_exact(&mut payload)?;
+    return Ok(payload);
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// preferred class, 0 (RFC 1006) or 2 (RFC 2126, several transport connections multiplexed onto one TCP connection)
+    /// NOTE: a responder configured for class 2 falls back to class 0 if the initiator allows it, one for class 0 accepts only that
+    pub class: u8,
+    /// maximum TPDU size proposed resp. accepted, as code of the TPDU size parameter
+    /// NOTE: class 0 is limited to 2048 octets
+    pub tpdu_size_code: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config {
+            class: 0,
+            tpdu_size_code: 0x0B,   // 2048 octets
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// CR sent
+    WaitForCc,
+    /// CR received, waiting for the T-CONNECT response of the TS user
+    WaitForResponse,
+    Open,
+    /// DR sent, class 2 only
+    Closing,
+}
+
+struct Connection {
+    state: State,
+    remote_reference: u16,
+    link: u64,
+    local_tsel: Vec<u8>,
+    remote_tsel: Vec<u8>,
+    class: u8,
+    expedited: bool,
+    expedited_outstanding: bool,
+    tpdu_size_code: u8,
+    reassembly: Vec<u8>,
+}
+
+impl Connection {
+    /// user data per DT TPDU: TPDU size minus LI and fixed part
+    fn maximum_data_length(&self) -> usize {
+        let size = tpdu::tpdu_size_from_code(self.tpdu_size_code).unwrap_or(128);
+        return size - if self.class == 0 { 3 } else { 5 };
+    }
+}
+
+/// a TCP connection = the network connection of RFC 1006
+struct Link {
+    stream: Arc<Mutex<TcpStream>>,
+    peer: SocketAddr,
+    local: SocketAddr,
+    /// known once the first transport connection on it is established - only class 2 links can take more
+    class: Option<u8>,
+    connections: Vec<u16>,
+}
+
+enum Action {
+    Send(Arc<Mutex<TcpStream>>, Tpdu),
+    Close(Arc<Mutex<TcpStream>>),
+}
+
+/// what is to be done once the entity lock is released
+#[derive(Default)]
+struct Actions {
+    actions: Vec<Action>,
+    wakeup: bool,
+}
+
+/// the protocol machine, shared between the TS user calls and the reader threads
+struct Entity {
+    config: Config,
+    connections: HashMap<u16, Connection>,
+    links: HashMap<u64, Link>,
+    next_link: u64,
+    next_reference: u16,
+    /// TSAP selectors incoming connections are accepted for
+    bound: HashSet<Vec<u8>>,
+    t_user_to: rtrb::Producer<TIndication>,
+}
+
+/// X.224 class 0 and 2 over TCP, RFC 1006 and RFC 2126
+pub struct Service {
+    entity: Arc<Mutex<Entity>>,
+    t_user_to_wakeup: Arc<Mutex<Option<Thread>>>,
+}
+
+impl Service {
+    /// returns the queue of indications towards the TS user
+    pub fn new(config: Config, queue_capacity: usize) -> (Self, rtrb::Consumer<TIndication>) {
+        let (t_user_to, t_user_from) = rtrb::RingBuffer::new(queue_capacity);
+        let service = Service {
+            entity: Arc::new(Mutex::new(Entity {
+                config: config,
+                connections: HashMap::new(),
+                links: HashMap::new(),
+                next_link: 0,
+                next_reference: 0,
+                bound: HashSet::new(),
+                t_user_to: t_user_to,
+            })),
+            t_user_to_wakeup: Arc::new(Mutex::new(None)),
+        };
+        return (service, t_user_from);
+    }
+
+    /// accepts TCP connections on the given address, usually port 102 - returns the address actually bound
+    pub fn listen(&mut self, address: SocketAddr) -> Result<SocketAddr, Error> {
+        let listener = TcpListener::bind(address)?;
+        let local_address = listener.local_addr()?;
+        let entity_arc = self.entity.clone();
+        let t_user_to_wakeup_arc = self.t_user_to_wakeup.clone();
+        thread::Builder::new().name("T RFC1006 listener".to_string()).spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = open_link(&entity_arc, &t_user_to_wakeup_arc, stream) {
+                            info!("RFC1006: failed to set up accepted TCP connection: {}", e);
+                        }
+                    },
+                    Err(e) => { info!("RFC1006: accept failed: {}", e); }
+                }
+            }
+        })?;
+        return Ok(local_address);
+    }
+
+}
+
+impl TransportService for Service {
+    /// NOTE: the threads are per TCP connection and started as they come up
+    fn run(&mut self, t_user_wakeup: Option<Thread>) {
+        *self.t_user_to_wakeup.lock().expect("failed to lock t_user_to_wakeup") = t_user_wakeup;
+    }
+
+    fn bind(&mut self, tsel: &[u8]) -> Result<(), Error> {
+        if !self.entity.lock().expect("failed to lock entity").bound.insert(tsel.to_vec()) {
+            return Err(Error::new(ErrorKind::AddrInUse, "TSAP selector already bound"));
+        }
+        return Ok(());
+    }
+
+    fn unbind(&mut self, tsel: &[u8]) {
+        self.entity.lock().expect("failed to lock entity").bound.remove(tsel);
+    }
+
+    fn t_connect_request(&mut self, called_address: &TransportAddress, calling_tsel: &[u8], data: &[u8]) -> Result<u16, Error> {
+        let called_tsel = &called_address.tsel;
+        let called_address = match &called_address.network_address {
+            NetworkAddress::Ip(socket_address) => socket_address,
+            _ => { return Err(Error::new(ErrorKind::InvalidInput, "transport over TCP, called address needs an IP address and port")); }
+        };
+        let class = self.entity.lock().expect("failed to lock entity").config.class;
+        if data.len() > CONNECT_DATA_MAXIMUM || (class == 0 && !data.is_empty()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "too much user data for CR TPDU"));
+        }
+        // class 2 multiplexes onto an existing TCP connection to the same peer
+        let existing = self.entity.lock().expect("failed to lock entity").links.iter()
+            .find(|(_, link)| class == 2 && link.peer == *called_address && link.class == Some(2))
+            .map(|(link_id, _)| *link_id);
+        let link_id = match existing {
+            Some(link_id) => link_id,
+            None => {
+                let stream = TcpStream::connect_timeout(called_address, CONNECT_TIMEOUT)?;
+                open_link(&self.entity, &self.t_user_to_wakeup, stream)?
+            }
+        };
+        let mut actions = Actions::default();
+        let reference = self.entity.lock().expect("failed to lock entity").connect_request(link_id, calling_tsel, called_tsel, data, &mut actions)?;
+        execute(&self.t_user_to_wakeup, actions);
+        return Ok(reference);
+    }
+
+    fn t_connect_response(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        let mut actions = Actions::default();
+        self.entity.lock().expect("failed to lock entity").connect_response(reference, data, &mut actions)?;
+        execute(&self.t_user_to_wakeup, actions);
+        return Ok(());
+    }
+
+    /// the TSDU is segmented into DT TPDUs as needed
+    fn t_data_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        let mut actions = Actions::default();
+        self.entity.lock().expect("failed to lock entity").data_request(reference, data, &mut actions)?;
+        execute(&self.t_user_to_wakeup, actions);
+        return Ok(());
+    }
+
+    /// class 2 only, and only if negotiated
+    fn t_expedited_data_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        let mut actions = Actions::default();
+        self.entity.lock().expect("failed to lock entity").expedited_data_request(reference, data, &mut actions)?;
+        execute(&self.t_user_to_wakeup, actions);
+        return Ok(());
+    }
+
+    fn t_disconnect_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        let mut actions = Actions::default();
+        self.entity.lock().expect("failed to lock entity").disconnect_request(reference, data, &mut actions)?;
+        execute(&self.t_user_to_wakeup, actions);
+        return Ok(());
+    }
+
+    fn connection_options(&self, reference: u16) -> Option<ConnectionOptions> {
+        let entity = self.entity.lock().expect("failed to lock entity");
+        let connection = entity.connections.get(&reference)?;
+        return Some(ConnectionOptions {
+            class: connection.class,
+            extended_format: false,
+            explicit_flow_control: false,
+            // NOTE: TCP has a checksum of its own
+            checksum: false,
+            expedited: connection.expedited,
+            maximum_tpdu_size: tpdu::tpdu_size_from_code(connection.tpdu_size_code).unwrap_or(128),
+        });
+    }
+}
+
+/// registers the TCP connection and starts reading TPKTs from it
+fn open_link(entity: &Arc<Mutex<Entity>>, t_user_to_wakeup: &Arc<Mutex<Option<Thread>>>, stream: TcpStream) -> Result<u64, Error> {
+    // NOTE: TPDUs are written as a whole, no use waiting for more
+    stream.set_nodelay(true)?;
+    let peer = stream.peer_addr()?;
+    let local = stream.local_addr()?;
+    let mut reader = stream.try_clone()?;
+    let link_id = {
+        let mut entity_locked = entity.lock().expect("failed to lock entity");
+        let link_id = entity_locked.next_link;
+        entity_locked.next_link += 1;
+        entity_locked.links.insert(link_id, Link { stream: Arc::new(Mutex::new(stream)), peer: peer, local: local, class: None, connections: vec![] });
+        link_id
+    };
+    debug!("RFC1006: TCP connection {} with {}", link_id, peer);
+    let entity_arc = entity.clone();
+    let t_user_to_wakeup_arc = t_user_to_wakeup.clone();
+    thread::Builder::new().name("T RFC1006 <- TCP".to_string()).spawn(move || {
+        loop {
+            let payload = match read_tpkt(&mut reader) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    debug!("RFC1006: TCP connection {} with {} gone: {}", link_id, peer, e);
+                    let mut actions = Actions::default();
+                    entity_arc.lock().expect("failed to lock entity").link_closed(link_id, &mut actions);
+                    execute(&t_user_to_wakeup_arc, actions);
+                    return;
+                }
+            };
+            while entity_arc.lock().expect("failed to lock entity").t_user_to.is_full() {
+                thread::sleep(BACKPRESSURE_INTERVAL);
+            }
+            // X.224 6.4 separation of concatenated TPDUs
+            let mut offset = 0;
+            while offset < payload.len() {
+                match Tpdu::from_buf(&payload[offset..]) {
+                    Ok((tpdu, consumed)) => {
+                        debug!("RFC1006: got TPDU from {}: {:?}", peer, tpdu);
+                        let mut actions = Actions::default();
+                        entity_arc.lock().expect("failed to lock entity").tpdu_indication(link_id, tpdu, &mut actions);
+                        execute(&t_user_to_wakeup_arc, actions);
+                        offset += consumed;
+                    },
+                    Err(e) => {
+                        //TODO send ER TPDU
+                        info!("RFC1006: discarding invalid TPDU from {}: {}", peer, e);
+                        break;
+                    }
+                }
+            }
+        }
+    })?;
+    return Ok(link_id);
+}
+
+/// sends the TPDUs and wakes up the TS user
+/// NOTE: done without the entity lock, since writing to TCP may block
+fn execute(t_user_to_wakeup: &Mutex<Option<Thread>>, actions: Actions) {
+    for action in actions.actions {
+        match action {
+            Action::Send(stream, tpdu) => {
+                let mut buffer = vec![];
+                tpdu.into_buf(false, &mut buffer);  // NOTE: TCP has a checksum and no checksum exists in class 0 and 2
+                if let Err(e) = write_tpkt(&mut *stream.lock().expect("failed to lock stream"), &buffer) {
+                    // NOTE: the reader notices the broken TCP connection and disconnects
+                    info!("RFC1006: TPDU not sent: {}", e);
+                }
+            },
+            Action::Close(stream) => {
+                let _ = stream.lock().expect("failed to lock stream").shutdown(Shutdown::Both);
+            }
+        }
+    }
+    if actions.wakeup {
+        if let Some(wakeup) = t_user_to_wakeup.lock().expect("failed to lock t_user_to_wakeup").as_ref() {
+            wakeup.unpark();
+        }
+    }
+}
+
+fn indicate(t_user_to: &mut rtrb::Producer<TIndication>, actions: &mut Actions, indication: TIndication) {
+    if let Err(_) = t_user_to.push(indication) {
+        warn!("RFC1006: queue towards TS user full, indication lost");
+        return;
+    }
+    actions.wakeup = true;
+}
+
+impl Entity {
+    fn allocate_reference(&mut self) -> Result<u16, Error> {
+        for _ in 0..u16::MAX {
+            self.next_reference = self.next_reference.wrapping_add(1);
+            if self.next_reference != 0 && !self.connections.contains_key(&self.next_reference) {
+                return Ok(self.next_reference);
+            }
+        }
+        return Err(Error::new(ErrorKind::OutOfMemory, "no transport connection reference left"));
+    }
+
+    fn send(&self, link_id: u64, tpdu: Tpdu, actions: &mut Actions) {
+        if let Some(link) = self.links.get(&link_id) {
+            actions.actions.push(Action::Send(link.stream.clone(), tpdu));
+        }
+    }
+
+    /// what the responder of a CR may select - class 2 only if configured
+    fn supported_classes(&self) -> &'static [u8] {
+        if self.config.class == 2 {
+            return &[2, 0];
+        }
+        return &[0];
+    }
+
+    /// X.224 6.5.4 - what an initiator preferring the class allows the responder to select instead
+    fn alternative_classes(class: u8) -> &'static [u8] {
+        if class == 2 {
+            return &[0];
+        }
+        return &[];
+    }
+
+    fn class_option(class: u8) -> u8 {
+        if class == 2 {
+            return tpdu::CLASS_2 | tpdu::CLASS_2_NO_FLOW_CONTROL;
+        }
+        return tpdu::CLASS_0;
+    }
+
+    fn connection_parameters(calling_tsel: &[u8], called_tsel: &[u8], tpdu_size_code: u8, class: u8, expedited: bool) -> Vec<Parameter> {
+        let mut parameters = vec![
+            Parameter { code: tpdu::PARAMETER_CALLING_TSAP, value: calling_tsel.to_vec() },
+            Parameter { code: tpdu::PARAMETER_CALLED_TSAP, value: called_tsel.to_vec() },
+            Parameter { code: tpdu::PARAMETER_TPDU_SIZE, value: vec![tpdu_size_code] },
+        ];
+        // NOTE: class 0 peers may not know the parameter
+        if class == 2 {
+            parameters.push(Parameter { code: tpdu::PARAMETER_ADDITIONAL_OPTIONS, value: vec![if expedited { tpdu::OPTION_EXPEDITED } else { 0 }] });
+        }
+        return parameters;
+    }
+
+    /// removes the transport connection - a class 0 TCP connection goes with it, as does an idle class 2 one we opened
+    fn remove_connection(&mut self, reference: u16, actions: &mut Actions) {
+        let connection = match self.connections.remove(&reference) {
+            Some(connection) => connection,
+            None => { return; }
+        };
+        if let Some(link) = self.links.get_mut(&connection.link) {
+            link.connections.retain(|link_reference| *link_reference != reference);
+            if link.connections.is_empty() {
+                actions.actions.push(Action::Close(link.stream.clone()));
+            }
+        }
+    }
+
+    fn connect_request(&mut self, link_id: u64, calling_tsel: &[u8], called_tsel: &[u8], data: &[u8], actions: &mut Actions) -> Result<u16, Error> {
+        let reference = self.allocate_reference()?;
+        let class = self.config.class;
+        let tpdu_size_code = if class == 0 { self.config.tpdu_size_code.min(0x0B) } else { self.config.tpdu_size_code };
+        let link = self.links.get_mut(&link_id).ok_or(Error::new(ErrorKind::NotConnected, "TCP connection gone"))?;
+        link.connections.push(reference);
+        self.connections.insert(reference, Connection {
+            state: State::WaitForCc,
+            remote_reference: 0,
+            link: link_id,
+            local_tsel: calling_tsel.to_vec(),
+            remote_tsel: called_tsel.to_vec(),
+            class: class,
+            expedited: class == 2,
+            expedited_outstanding: false,
+            tpdu_size_code: tpdu_size_code,
+            reassembly: vec![],
+        });
+        let mut parameters = Self::connection_parameters(calling_tsel, called_tsel, tpdu_size_code, class, class == 2);
+        if !Self::alternative_classes(class).is_empty() {
+            parameters.push(tpdu::alternative_classes_parameter(Self::alternative_classes(class)));
+        }
+        let cr = Tpdu::ConnectionRequest {
+            credit: 0,
+            source_reference: reference,
+            class_option: Self::class_option(class),
+            parameters: parameters,
+            data: data.to_vec(),
+        };
+        self.send(link_id, cr, actions);
+        return Ok(reference);
+    }
+
+    fn connect_response(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connections.get_mut(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such transport connection"))?;
+        if connection.state != State::WaitForResponse {
+            return Err(Error::new(ErrorKind::InvalidInput, "transport connection not awaiting a connect response"));
+        }
+        if data.len() > CONNECT_DATA_MAXIMUM || (connection.class == 0 && !data.is_empty()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "too much user data for CC TPDU"));
+        }
+        connection.state = State::Open;
+        // X.224 13.4.4 - the TSAP-IDs of the CR, still calling and called as in the CR
+        let cc = Tpdu::ConnectionConfirm {
+            credit: 0,
+            destination_reference: connection.remote_reference,
+            source_reference: reference,
+            class_option: Self::class_option(connection.class),
+            parameters: Self::connection_parameters(&connection.remote_tsel, &connection.local_tsel, connection.tpdu_size_code, connection.class, connection.expedited),
+            data: data.to_vec(),
+        };
+        let (link_id, class) = (connection.link, connection.class);
+        if let Some(link) = self.links.get_mut(&link_id) {
+            link.class = Some(class);
+        }
+        self.send(link_id, cc, actions);
+        return Ok(());
+    }
+
+    fn data_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connections.get(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such transport connection"))?;
+        if connection.state != State::Open {
+            return Err(Error::new(ErrorKind::NotConnected, "transport connection not open"));
+        }
+        let (link_id, class, remote_reference) = (connection.link, connection.class, connection.remote_reference);
+        let mut segments = data.chunks(connection.maximum_data_length()).peekable();
+        let mut dts = vec![];
+        if data.is_empty() {
+            dts.push((true, vec![]));
+        }
+        while let Some(segment) = segments.next() {
+            dts.push((segments.peek().is_none(), segment.to_vec()));
+        }
+        for (end_of_tsdu, segment) in dts {
+            let dt = if class == 0 {
+                Tpdu::DataClass0 { end_of_tsdu: end_of_tsdu, data: segment }
+            } else {
+                // NOTE: without explicit flow control the sequence number is not significant
+                Tpdu::Data { destination_reference: remote_reference, end_of_tsdu: end_of_tsdu, sequence_number: 0, parameters: vec![], data: segment }
+            };
+            self.send(link_id, dt, actions);
+        }
+        return Ok(());
+    }
+
+    fn expedited_data_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        if data.len() > EXPEDITED_DATA_MAXIMUM || data.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "expedited TSDU must be 1 to 16 octets"));
+        }
+        let connection = self.connections.get_mut(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such transport connection"))?;
+        if connection.state != State::Open {
+            return Err(Error::new(ErrorKind::NotConnected, "transport connection not open"));
+        }
+        if connection.class == 0 || !connection.expedited {
+            return Err(Error::new(ErrorKind::Unsupported, "expedited data not negotiated"));
+        }
+        if connection.expedited_outstanding {
+            return Err(Error::new(ErrorKind::WouldBlock, "previous expedited TSDU not acknowledged yet"));
+        }
+        connection.expedited_outstanding = true;
+        let ed = Tpdu::ExpeditedData { destination_reference: connection.remote_reference, sequence_number: 0, parameters: vec![], data: data.to_vec() };
+        let link_id = connection.link;
+        self.send(link_id, ed, actions);
+        return Ok(());
+    }
+
+    fn disconnect_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connections.get_mut(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such transport connection"))?;
+        let (link_id, class, remote_reference, state) = (connection.link, connection.class, connection.remote_reference, connection.state);
+        if state == State::Closing {
+            return Ok(());
+        }
+        if class == 0 && state != State::WaitForResponse {
+            // X.224 6.7.1.4 - in class 0 the network connection is released instead
+            self.remove_connection(reference, actions);
+            return Ok(());
+        }
+        let reason = if state == State::WaitForResponse { tpdu::REASON_REFUSED } else { tpdu::REASON_NORMAL };
+        let dr = Tpdu::DisconnectRequest { destination_reference: remote_reference, source_reference: reference, reason: reason, parameters: vec![], data: if class == 0 { vec![] } else { data.to_vec() } };
+        if class != 0 {
+            connection.state = State::Closing;
+        }
+        self.send(link_id, dr, actions);
+        if class == 0 {
+            self.remove_connection(reference, actions);
+        }
+        return Ok(());
+    }
+
+    fn tpdu_indication(&mut self, link_id: u64, tpdu: Tpdu, actions: &mut Actions) {
+        let reference = match &tpdu {
+            Tpdu::ConnectionRequest { credit: _, source_reference, class_option, parameters, data } => {
+                let cr = Tpdu::ConnectionRequest { credit: 0, source_reference: *source_reference, class_option: *class_option, parameters: parameters.clone(), data: vec![] };
+                self.connection_request_indication(link_id, *source_reference, *class_option, &cr, data.clone(), actions);
+                return;
+            },
+            // class 0 DT belongs to the one transport connection of the TCP connection
+            Tpdu::DataClass0 { .. } => match self.links.get(&link_id).and_then(|link| link.connections.first()) {
+                Some(reference) => *reference,
+                None => { return; }
+            },
+            _ => tpdu.destination_reference(),
+        };
+        let connection = match self.connections.get_mut(&reference) {
+            Some(connection) if connection.link == link_id => connection,
+            _ => {
+                // a DR for an unknown connection is answered with a DC, everything else ignored
+                if let Tpdu::DisconnectRequest { source_reference, .. } = tpdu {
+                    self.send(link_id, Tpdu::DisconnectConfirm { destination_reference: source_reference, source_reference: reference, parameters: vec![] }, actions);
+                } else {
+                    debug!("RFC1006: TPDU for unknown reference {}, ignoring", reference);
+                }
+                return;
+            }
+        };
+
+        match tpdu {
+            Tpdu::ConnectionConfirm { source_reference, class_option, parameters, data, .. } => {
+                if connection.state != State::WaitForCc {
+                    return;
+                }
+                let class = class_option >> 4;
+                // X.224 6.5.4 - the responder may go down from class 2 to class 0, but not up, and no extended format since not proposed
+                if !tpdu::acceptable_classes(connection.class, Self::alternative_classes(connection.class)).contains(&class) || class_option & tpdu::OPTION_EXTENDED_FORMAT != 0 {
+                    info!("RFC1006: CC selects class {} resp. options {:#04x} not proposed", class, class_option & 0x0F);
+                    let dr = Tpdu::DisconnectRequest { destination_reference: source_reference, source_reference: reference, reason: tpdu::REASON_NEGOTIATION_FAILED, parameters: vec![], data: vec![] };
+                    self.send(link_id, dr, actions);
+                    indicate(&mut self.t_user_to, actions, TIndication::DisconnectIndication { reference: reference, reason: tpdu::REASON_NEGOTIATION_FAILED, data: vec![] });
+                    self.remove_connection(reference, actions);
+                    return;
+                }
+                connection.remote_reference = source_reference;
+                connection.class = class;
+                connection.state = State::Open;
+                let cc = Tpdu::ConnectionConfirm { credit: 0, destination_reference: reference, source_reference: source_reference, class_option: class_option, parameters: parameters, data: vec![] };
+                if let Some(tpdu_size_code) = cc.parameter(tpdu::PARAMETER_TPDU_SIZE) {
+                    if tpdu_size_code.len() == 1 && tpdu::tpdu_size_from_code(tpdu_size_code[0]).is_some() {
+                        connection.tpdu_size_code = tpdu_size_code[0];
+                    }
+                }
+                connection.expedited = class == 2 && cc.parameter(tpdu::PARAMETER_ADDITIONAL_OPTIONS).and_then(|options| options.first().copied()).unwrap_or(0) & tpdu::OPTION_EXPEDITED != 0;
+                if let Some(link) = self.links.get_mut(&link_id) {
+                    link.class = Some(class);
+                }
+                indicate(&mut self.t_user_to, actions, TIndication::ConnectConfirm { reference: reference, data: data });
+            },
+            Tpdu::DisconnectRequest { source_reference, reason, data, .. } => {
+                let class = connection.class;
+                let state = connection.state;
+                if class != 0 && source_reference != 0 {
+                    self.send(link_id, Tpdu::DisconnectConfirm { destination_reference: source_reference, source_reference: reference, parameters: vec![] }, actions);
+                }
+                if state != State::Closing {
+                    indicate(&mut self.t_user_to, actions, TIndication::DisconnectIndication { reference: reference, reason: reason, data: data });
+                }
+                self.remove_connection(reference, actions);
+            },
+            Tpdu::DisconnectConfirm { .. } => {
+                if connection.state == State::Closing {
+                    self.remove_connection(reference, actions);
+                }
+            },
+            Tpdu::DataClass0 { end_of_tsdu, data } | Tpdu::Data { end_of_tsdu, data, .. } => {
+                if connection.state != State::Open {
+                    return;
+                }
+                connection.reassembly.extend_from_slice(&data);
+                if end_of_tsdu {
+                    let tsdu = std::mem::take(&mut connection.reassembly);
+                    indicate(&mut self.t_user_to, actions, TIndication::DataIndication { reference: reference, data: tsdu });
+                }
+            },
+            Tpdu::ExpeditedData { sequence_number, data, .. } => {
+                if connection.state != State::Open || !connection.expedited {
+                    return;
+                }
+                let ea = Tpdu::ExpeditedAck { destination_reference: connection.remote_reference, next_expected: sequence_number.wrapping_add(1) % tpdu::SEQUENCE_MODULUS, parameters: vec![] };
+                indicate(&mut self.t_user_to, actions, TIndication::ExpeditedDataIndication { reference: reference, data: data });
+                self.send(link_id, ea, actions);
+            },
+            Tpdu::ExpeditedAck { .. } => {
+                connection.expedited_outstanding = false;
+            },
+            Tpdu::Error { cause, .. } => {
+                info!("RFC1006: peer rejected a TPDU on connection {} with cause {}", reference, cause);
+                indicate(&mut self.t_user_to, actions, TIndication::DisconnectIndication { reference: reference, reason: tpdu::REASON_PROTOCOL_ERROR, data: vec![] });
+                self.remove_connection(reference, actions);
+            },
+            // NOTE: no explicit flow control
+            Tpdu::Ack { .. } => {},
+            Tpdu::ConnectionRequest { .. } | Tpdu::UnitData { .. } => {}
+        }
+    }
+
+    fn connection_request_indication(&mut self, link_id: u64, source_reference: u16, class_option: u8, cr: &Tpdu, data: Vec<u8>, actions: &mut Actions) {
+        let link = match self.links.get(&link_id) {
+            Some(link) => link,
+            None => { return; }
+        };
+        let peer = link.peer;
+        let local = link.local;
+        let link_class = link.class;
+        let link_busy = !link.connections.is_empty();
+        // X.224 6.5.4 - preferred or alternative class, else what they allow to fall back to, e.g. class 4 to class 2
+        // NOTE: extended format and explicit flow control in class 2 are not supported, the CC selects their non-use
+        let class = match tpdu::select_class(class_option >> 4, &tpdu::alternative_classes(cr), self.supported_classes()) {
+            Some(class) => class,
+            None => {
+                info!("RFC1006: CR from {} for class {} without fallback to a supported class, refusing", peer, class_option >> 4);
+                self.send(link_id, Tpdu::DisconnectRequest { destination_reference: source_reference, source_reference: 0, reason: tpdu::REASON_NEGOTIATION_FAILED, parameters: vec![], data: vec![] }, actions);
+                return;
+            }
+        };
+        if link_busy && (class != 2 || link_class != Some(2)) {
+            // only class 2 multiplexes
+            info!("RFC1006: CR from {} on a TCP connection already in use, refusing", peer);
+            self.send(link_id, Tpdu::DisconnectRequest { destination_reference: source_reference, source_reference: 0, reason: tpdu::REASON_PROTOCOL_ERROR, parameters: vec![], data: vec![] }, actions);
+            return;
+        }
+        let calling_tsel = cr.parameter(tpdu::PARAMETER_CALLING_TSAP).unwrap_or(&[]).to_vec();
+        let called_tsel = cr.parameter(tpdu::PARAMETER_CALLED_TSAP).unwrap_or(&[]).to_vec();
+        if !self.bound.contains(&called_tsel) {
+            info!("RFC1006: CR from {} for unbound TSAP selector {:?}, refusing", peer, called_tsel);
+            self.send(link_id, Tpdu::DisconnectRequest { destination_reference: source_reference, source_reference: 0, reason: tpdu::REASON_NOT_ATTACHED, parameters: vec![], data: vec![] }, actions);
+            return;
+        }
+        let reference = match self.allocate_reference() {
+            Ok(reference) => reference,
+            Err(e) => {
+                info!("RFC1006: refusing CR from {}: {}", peer, e);
+                self.send(link_id, Tpdu::DisconnectRequest { destination_reference: source_reference, source_reference: 0, reason: tpdu::REASON_CONGESTION, parameters: vec![], data: vec![] }, actions);
+                return;
+            }
+        };
+        // X.224 6.5 negotiation of the TPDU size - the smaller one, class 0 up to 2048 octets
+        let proposed_size_code = cr.parameter(tpdu::PARAMETER_TPDU_SIZE).and_then(|size| size.first().copied()).filter(|code| tpdu::tpdu_size_from_code(*code).is_some()).unwrap_or(0x07);
+        let mut tpdu_size_code = proposed_size_code.min(self.config.tpdu_size_code);
+        if class == 0 {
+            tpdu_size_code = tpdu_size_code.min(0x0B);
+        }
+        let options = cr.parameter(tpdu::PARAMETER_ADDITIONAL_OPTIONS).and_then(|options| options.first().copied()).unwrap_or(0);
+        self.links.get_mut(&link_id).expect("link").connections.push(reference);
+        self.connections.insert(reference, Connection {
+            state: State::WaitForResponse,
+            remote_reference: source_reference,
+            link: link_id,
+            local_tsel: called_tsel.clone(),
+            remote_tsel: calling_tsel.clone(),
+            class: class,
+            expedited: class == 2 && options & tpdu::OPTION_EXPEDITED != 0,
+            expedited_outstanding: false,
+            tpdu_size_code: tpdu_size_code,
+            reassembly: vec![],
+        });
+        indicate(&mut self.t_user_to, actions, TIndication::ConnectIndication {
+            reference: reference,
+            calling_address: TransportAddress::new(NetworkAddress::Ip(peer), &calling_tsel),
+            called_address: TransportAddress::new(NetworkAddress::Ip(local), &called_tsel),
+            data: data,
+        });
+    }
+
+    /// TCP connection closed or broken - all transport connections on it are gone
+    fn link_closed(&mut self, link_id: u64, actions: &mut Actions) {
+        let link = match self.links.remove(&link_id) {
+            Some(link) => link,
+            None => { return; }
+        };
+        for reference in link.connections {
+            if let Some(connection) = self.connections.remove(&reference) {
+                if connection.state != State::Closing {
+                    indicate(&mut self.t_user_to, actions, TIndication::DisconnectIndication { reference: reference, reason: tpdu::REASON_NOT_SPECIFIED, data: vec![] });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Cursor, time::Instant};
+
+    /// hands out at most one octet per read, like a TCP connection delivering a TPKT in pieces
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let length = buf.len().min(1);
+            return self.0.read(&mut buf[..length]);
+        }
+    }
+
+    fn next_indication(t_user_from: &mut rtrb::Consumer<TIndication>) -> TIndication {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Ok(indication) = t_user_from.pop() {
+                return indication;
+            }
+            assert!(Instant::now() < deadline, "no indication from the transport entity");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn loopback(class: u8) -> (Service, rtrb::Consumer<TIndication>, Service, rtrb::Consumer<TIndication>, SocketAddr) {
+        let config = Config { class: class, ..Config::default() };
+        let (initiator, initiator_from) = Service::new(config, 16);
+        let (mut responder, responder_from) = Service::new(config, 16);
+        responder.bind(b"server").unwrap();
+        let address = responder.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+        return (initiator, initiator_from, responder, responder_from, address);
+    }
+
+    fn connect(initiator: &mut Service, initiator_from: &mut rtrb::Consumer<TIndication>, responder: &mut Service, responder_from: &mut rtrb::Consumer<TIndication>, address: SocketAddr, data: &[u8]) -> (u16, u16) {
+        let initiator_reference = initiator.t_connect_request(&TransportAddress::new(NetworkAddress::Ip(address), b"server"), b"client", data).unwrap();
+        let responder_reference = match next_indication(responder_from) {
+            TIndication::ConnectIndication { reference, calling_address, called_address, data: connect_data } => {
+                assert_eq!(calling_address.tsel, b"client");
+                assert_eq!(called_address.tsel, b"server");
+                assert_eq!(connect_data, data);
+                reference
+            },
+            other => panic!("{:?}", other)
+        };
+        responder.t_connect_response(responder_reference, &[]).unwrap();
+        assert!(matches!(next_indication(initiator_from), TIndication::ConnectConfirm { reference, .. } if reference == initiator_reference));
+        return (initiator_reference, responder_reference);
+    }
+
+    #[test]
+    fn tpkt_round_trip() {
+        let mut stream = vec![];
+        write_tpkt(&mut stream, &[0x02, 0xF0, 0x80, b'x']).unwrap();
+        assert_eq!(stream, [TPKT_VERSION, 0, 0, 8, 0x02, 0xF0, 0x80, b'x']);
+        write_tpkt(&mut stream, &[0x02, 0xF0, 0x80]).unwrap();
+        let mut reader = Cursor::new(stream);
+        assert_eq!(read_tpkt(&mut reader).unwrap(), [0x02, 0xF0, 0x80, b'x']);
+        assert_eq!(read_tpkt(&mut reader).unwrap(), [0x02, 0xF0, 0x80]);
+        assert_eq!(read_tpkt(&mut reader).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn tpkt_short_reads() {
+        let payload: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut stream = vec![];
+        write_tpkt(&mut stream, &payload).unwrap();
+        assert_eq!(read_tpkt(&mut Trickle(Cursor::new(stream.clone()))).unwrap(), payload);
+        // TCP connection closed in the middle of the header resp. the payload
+        assert_eq!(read_tpkt(&mut Trickle(Cursor::new(stream[..3].to_vec()))).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(read_tpkt(&mut Trickle(Cursor::new(stream[..500].to_vec()))).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn tpkt_length_checked() {
+        let mut stream = vec![];
+        write_tpkt(&mut stream, &vec![0u8; TPKT_MAXIMUM_LENGTH - TPKT_HEADER_LENGTH]).unwrap();
+        assert_eq!(stream.len(), TPKT_MAXIMUM_LENGTH);
+        assert_eq!(read_tpkt(&mut Cursor::new(stream)).unwrap().len(), TPKT_MAXIMUM_LENGTH - TPKT_HEADER_LENGTH);
+        let mut stream = vec![];
+        assert_eq!(write_tpkt(&mut stream, &vec![0u8; TPKT_MAXIMUM_LENGTH - TPKT_HEADER_LENGTH + 1]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(stream.is_empty());
+        // header only resp. not even that
+        assert_eq!(read_tpkt(&mut Cursor::new(vec![TPKT_VERSION, 0, 0, 4])).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_tpkt(&mut Cursor::new(vec![TPKT_VERSION, 0, 0, 2, 0])).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_tpkt(&mut Cursor::new(vec![4, 0, 0, 5, 0])).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn class_0_loopback() {
+        let (mut initiator, mut initiator_from, mut responder, mut responder_from, address) = loopback(0);
+        let (initiator_reference, responder_reference) = connect(&mut initiator, &mut initiator_from, &mut responder, &mut responder_from, address, &[]);
+        let options = initiator.connection_options(initiator_reference).unwrap();
+        assert_eq!(options.class, 0);
+        assert!(!options.expedited);
+        assert_eq!(options.maximum_tpdu_size, 2048);
+        assert_eq!(initiator.t_expedited_data_request(initiator_reference, b"urgent").unwrap_err().kind(), ErrorKind::Unsupported);
+
+        // segmented into several DT TPDUs and reassembled
+        let tsdu: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        initiator.t_data_request(initiator_reference, &tsdu).unwrap();
+        assert!(matches!(next_indication(&mut responder_from), TIndication::DataIndication { reference, ref data } if reference == responder_reference && *data == tsdu));
+        responder.t_data_request(responder_reference, b"reply").unwrap();
+        assert!(matches!(next_indication(&mut initiator_from), TIndication::DataIndication { ref data, .. } if data == b"reply"));
+
+        // released together with the TCP connection
+        initiator.t_disconnect_request(initiator_reference, &[]).unwrap();
+        assert!(matches!(next_indication(&mut responder_from), TIndication::DisconnectIndication { reference, .. } if reference == responder_reference));
+        assert!(initiator.connection_options(initiator_reference).is_none());
+    }
+
+    #[test]
+    fn class_2_loopback_multiplexed() {
+        let (mut initiator, mut initiator_from, mut responder, mut responder_from, address) = loopback(2);
+        let (first, first_remote) = connect(&mut initiator, &mut initiator_from, &mut responder, &mut responder_from, address, b"one");
+        let (second, second_remote) = connect(&mut initiator, &mut initiator_from, &mut responder, &mut responder_from, address, b"two");
+        assert_ne!(first, second);
+        {
+            let entity = initiator.entity.lock().unwrap();
+            assert_eq!(entity.links.len(), 1);
+            assert_eq!(entity.connections[&first].link, entity.connections[&second].link);
+        }
+        assert_eq!(initiator.connection_options(first).unwrap().class, 2);
+        assert!(initiator.connection_options(first).unwrap().expedited);
+
+        initiator.t_data_request(second, b"on the second").unwrap();
+        initiator.t_data_request(first, b"on the first").unwrap();
+        assert!(matches!(next_indication(&mut responder_from), TIndication::DataIndication { reference, ref data } if reference == second_remote && data == b"on the second"));
+        assert!(matches!(next_indication(&mut responder_from), TIndication::DataIndication { reference, ref data } if reference == first_remote && data == b"on the first"));
+        initiator.t_expedited_data_request(first, b"urgent").unwrap();
+        assert!(matches!(next_indication(&mut responder_from), TIndication::ExpeditedDataIndication { reference, ref data } if reference == first_remote && data == b"urgent"));
+
+        // DR and DC on one transport connection leave the other one and the TCP connection alone
+        // NOTE: the link is not reused for a new transport connection after that, see the race with closing an idle link
+        initiator.t_disconnect_request(first, b"bye").unwrap();
+        assert!(matches!(next_indication(&mut responder_from), TIndication::DisconnectIndication { reference, ref data, .. } if reference == first_remote && data == b"bye"));
+        responder.t_data_request(second_remote, b"still there").unwrap();
+        assert!(matches!(next_indication(&mut initiator_from), TIndication::DataIndication { reference, ref data } if reference == second && data == b"still there"));
+    }
+
+    #[test]
+    fn unbound_tsap_refused() {
+        let (mut initiator, mut initiator_from, _responder, mut responder_from, address) = loopback(0);
+        let reference = initiator.t_connect_request(&TransportAddress::new(NetworkAddress::Ip(address), b"nobody"), b"client", &[]).unwrap();
+        assert!(matches!(next_indication(&mut initiator_from), TIndication::DisconnectIndication { reference: refused, .. } if refused == reference));
+        assert!(responder_from.pop().is_err());
+    }
+
+    #[test]
+    fn class_2_falls_back_to_class_0() {
+        let (mut initiator, mut initiator_from) = Service::new(Config { class: 2, ..Config::default() }, 16);
+        let (mut responder, mut responder_from) = Service::new(Config::default(), 16);
+        responder.bind(b"server").unwrap();
+        let address = responder.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+        let (initiator_reference, responder_reference) = connect(&mut initiator, &mut initiator_from, &mut responder, &mut responder_from, address, &[]);
+        assert_eq!(initiator.connection_options(initiator_reference).unwrap().class, 0);
+        assert_eq!(responder.connection_options(responder_reference).unwrap().class, 0);
+        initiator.t_data_request(initiator_reference, b"class 0").unwrap();
+        assert!(matches!(next_indication(&mut responder_from), TIndication::DataIndication { ref data, .. } if data == b"class 0"));
+    }
+
+    #[test]
+    fn class_2_proposes_class_0() {
+        // a bare TCP listener in place of the responder, to look at the CR as sent
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (mut initiator, _initiator_from) = Service::new(Config { class: 2, ..Config::default() }, 16);
+        initiator.t_connect_request(&TransportAddress::new(NetworkAddress::Ip(address), b"server"), b"client", &[]).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let cr = Tpdu::from_buf(&read_tpkt(&mut stream).unwrap()).unwrap().0;
+        match &cr {
+            Tpdu::ConnectionRequest { class_option, .. } => assert_eq!(*class_option, tpdu::CLASS_2 | tpdu::CLASS_2_NO_FLOW_CONTROL),
+            other => panic!("{:?}", other)
+        }
+        assert_eq!(cr.parameter(tpdu::PARAMETER_ALTERNATIVE_CLASSES), Some(&[tpdu::CLASS_0][..]));
+        assert_eq!(tpdu::alternative_classes(&cr), vec![0]);
+    }
+}
@@ -0,0 +1,1348 @@
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, io::{Error, ErrorKind}, sync::{Arc, Mutex}, thread::{self, Thread, JoinHandle}, time::{Duration, Instant}};
+
+use crate::n::{NetworkService, Nsap, NSUnitDataIndication, NsUser, Qos};
+use super::{ConnectionOptions, NetworkAddress, TIndication, TransportAddress, TransportService};
+use super::tpdu::{self, Tpdu, Parameter};
+
+/// how often the timer thread looks at the retransmission, window and inactivity timers
+pub const TIMER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// X.224 13.11 - user data of an ED TPDU
+pub const EXPEDITED_DATA_MAXIMUM: usize = 16;
+/// X.224 13.3.4 - user data of a CR and CC TPDU
+pub const CONNECT_DATA_MAXIMUM: usize = 32;
+/// X.224 13.5.4 - user data of a DR TPDU
+pub const DISCONNECT_DATA_MAXIMUM: usize = 64;
+/// largest credit expressible in normal format, and in CR and CC TPDUs
+const CREDIT_MAXIMUM: u8 = 15;
+
+/// X.224 12.2.1 timers and protocol parameters
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// T1 - local retransmission time
+    pub retransmission_time: Duration,
+    /// N - maximum number of transmissions of a TPDU
+    pub maximum_transmissions: u32,
+    /// W - window time, an AK is sent at least this often on an otherwise idle connection
+    pub window_time: Duration,
+    /// I - inactivity time, the connection is given up when nothing was received for that long
+    /// NOTE: has to be well above the window time of the peer
+    pub inactivity_time: Duration,
+    /// credit offered to the peer, at most 15 in normal format
+    pub credit: u8,
+    /// maximum TPDU size proposed resp. accepted, as code of the TPDU size parameter
+    pub tpdu_size_code: u8,
+    /// propose resp. accept extended format, X.224 6.5.4 - 31 bit sequence numbers and 16 bit credit instead of
+    /// 7 bit resp. 4 bit, for paths with a large bandwidth-delay product
+    pub extended_format: bool,
+    /// largest TSDU accepted from the peer - X.224 sets no limit, exceeding this is treated as protocol error
+    pub maximum_tsdu_size: usize,
+    /// octets of TSDUs which may wait for credit of the peer, before a data request is refused with WouldBlock
+    pub send_buffer_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config {
+            retransmission_time: Duration::from_secs(1),
+            maximum_transmissions: 8,
+            window_time: Duration::from_secs(5),
+            inactivity_time: Duration::from_secs(30),
+            credit: 8,
+            tpdu_size_code: 0x0A,   // 1024 octets
+            extended_format: false,
+            maximum_tsdu_size: 1 << 20,
+            send_buffer_size: 1 << 20,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// CR sent
+    WaitForCc,
+    /// CR received, waiting for the T-CONNECT response of the TS user
+    WaitForResponse,
+    /// CC sent, waiting for the AK or DT completing the three-way handshake
+    WaitForAck,
+    Open,
+    /// DR sent
+    Closing,
+}
+
+/// a TPDU sent which awaits acknowledgement
+struct Sent {
+    sequence_number: u32,
+    tpdu: Tpdu,
+    sent_at: Instant,
+    transmissions: u32,
+}
+
+struct Connection {
+    state: State,
+    local_reference: u16,
+    remote_reference: u16,
+    peer: Nsap,
+    local_tsel: Vec<u8>,
+    remote_tsel: Vec<u8>,
+    checksum: bool,
+    expedited: bool,
+    extended_format: bool,
+    tpdu_size_code: u8,
+    /// CR, CC or DR which awaits a response
+    control: Option<Sent>,
+
+    // sending
+    send_next: u32,
+    send_window_lower: u32,
+    send_credit: u16,
+    unacknowledged: VecDeque<Sent>,
+    /// TSDU segments not yet sent for lack of credit
+    waiting: VecDeque<(bool, Vec<u8>)>,
+    expedited_send_next: u32,
+    expedited_unacknowledged: Option<Sent>,
+
+    // receiving
+    receive_next: u32,
+    out_of_order: BTreeMap<u32, (bool, Vec<u8>)>,
+    reassembly: Vec<u8>,
+    expedited_receive_next: u32,
+
+    last_heard: Instant,
+    last_ack_sent: Instant,
+}
+
+/// what is to be done once the entity lock is released
+#[derive(Default)]
+struct Actions {
+    /// destination, TPDU, whether to put a checksum on it and whether in extended format
+    tpdus: Vec<(Nsap, Tpdu, bool, bool)>,
+    wakeup: bool,
+}
+
+/// the protocol machine, shared between the TS user calls and the threads
+struct Entity {
+    config: Config,
+    connections: HashMap<u16, Connection>,
+    next_reference: u16,
+    /// TSAP selectors incoming connections are accepted for
+    bound: HashSet<Vec<u8>>,
+    t_user_to: rtrb::Producer<TIndication>,
+}
+
+/// X.224 Transport Protocol class 4 over the connectionless-mode network service
+pub struct Service<N: NetworkService<'static> + Send + 'static> {
+    entity: Arc<Mutex<Entity>>,
+    ns: Arc<Mutex<N>>,
+    ns_user_from: Arc<Mutex<rtrb::Consumer<NSUnitDataIndication>>>,
+    ns_user_from_wakeup: Arc<Mutex<Option<JoinHandle<Thread>>>>,
+    t_user_to_wakeup: Arc<Mutex<Option<Thread>>>,
+}
+
+impl<N: NetworkService<'static> + Send + 'static> Service<N> {
+    /// registers itself as the user of the given NS - returns the queue of indications towards the TS user
+    pub fn new(ns: Arc<Mutex<N>>, config: Config, queue_capacity: usize) -> (Self, rtrb::Consumer<TIndication>) {
+        let (ns_user_to, ns_user_from) = rtrb::RingBuffer::new(queue_capacity);
+        let (t_user_to, t_user_from) = rtrb::RingBuffer::new(queue_capacity);
+        let ns_user_from_wakeup = Arc::new(Mutex::new(None));
+        ns.lock().expect("failed to lock ns").register_ns_user(NsUser {
+            accepts: |nsdu| !tpdu::is_unit_data(nsdu),
+            ns_user_to: ns_user_to,
+            ns_user_to_wakeup: ns_user_from_wakeup.clone(),
+        });
+        let service = Service {
+            entity: Arc::new(Mutex::new(Entity {
+                config: config,
+                connections: HashMap::new(),
+                next_reference: 0,
+                bound: HashSet::new(),
+                t_user_to: t_user_to,
+            })),
+            ns: ns,
+            ns_user_from: Arc::new(Mutex::new(ns_user_from)),
+            ns_user_from_wakeup: ns_user_from_wakeup,
+            t_user_to_wakeup: Arc::new(Mutex::new(None)),
+        };
+        return (service, t_user_from);
+    }
+}
+
+impl<N: NetworkService<'static> + Send + 'static> TransportService for Service<N> {
+    /// starts the threads
+    fn run(&mut self, t_user_wakeup: Option<Thread>) {
+        *self.t_user_to_wakeup.lock().expect("failed to lock t_user_to_wakeup") = t_user_wakeup;
+
+        // read N-UNITDATA-INDICATION from NS
+        let entity_arc = self.entity.clone();
+        let ns_arc = self.ns.clone();
+        let ns_user_from_arc = self.ns_user_from.clone();
+        let t_user_to_wakeup_arc = self.t_user_to_wakeup.clone();
+        let ns2ts_consumer_wakeup = thread::Builder::new().name("T TP4 <- N".to_string()).spawn(move || {
+            // keep permanent lock on this
+            let mut ns_user_from = ns_user_from_arc.lock().expect("failed to lock ns_user_from");
+            loop {
+                while let Ok(indication) = ns_user_from.pop() {
+                    let mut actions = Actions::default();
+                    entity_arc.lock().expect("failed to lock entity").ns_unitdata_indication(indication, &mut actions);
+                    execute(&ns_arc, &t_user_to_wakeup_arc, actions);
+                }
+                thread::park(); // wait for unpark wakeup call from NS
+            }
+        }).expect("failed to start thread");
+        // put thread handle into well-known place
+        self.ns_user_from_wakeup.lock().expect("failed to lock ns_user_from_wakeup (giver)").replace(ns2ts_consumer_wakeup);
+
+        // timer thread
+        let entity_arc2 = self.entity.clone();
+        let ns_arc2 = self.ns.clone();
+        let t_user_to_wakeup_arc2 = self.t_user_to_wakeup.clone();
+        thread::Builder::new().name("T TP4 timers".to_string()).spawn(move || {
+            loop {
+                let mut actions = Actions::default();
+                entity_arc2.lock().expect("failed to lock entity").timers(Instant::now(), &mut actions);
+                execute(&ns_arc2, &t_user_to_wakeup_arc2, actions);
+                thread::sleep(TIMER_POLL_INTERVAL);
+            }
+        }).expect("failed to start thread");
+    }
+
+    fn bind(&mut self, tsel: &[u8]) -> Result<(), Error> {
+        if !self.entity.lock().expect("failed to lock entity").bound.insert(tsel.to_vec()) {
+            return Err(Error::new(ErrorKind::AddrInUse, "TSAP selector already bound"));
+        }
+        return Ok(());
+    }
+
+    fn unbind(&mut self, tsel: &[u8]) {
+        self.entity.lock().expect("failed to lock entity").bound.remove(tsel);
+    }
+
+    fn t_connect_request(&mut self, called_address: &TransportAddress, calling_tsel: &[u8], data: &[u8]) -> Result<u16, Error> {
+        let called_nsap = match &called_address.network_address {
+            NetworkAddress::Nsap(nsap) => nsap,
+            _ => { return Err(Error::new(ErrorKind::InvalidInput, "TP4 runs over CLNS, called address needs an NSAP")); }
+        };
+        let mut actions = Actions::default();
+        let reference = self.entity.lock().expect("failed to lock entity").connect_request(called_nsap, calling_tsel, &called_address.tsel, data, &mut actions)?;
+        execute(&self.ns, &self.t_user_to_wakeup, actions);
+        return Ok(reference);
+    }
+
+    fn t_connect_response(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        let mut actions = Actions::default();
+        self.entity.lock().expect("failed to lock entity").connect_response(reference, data, &mut actions)?;
+        execute(&self.ns, &self.t_user_to_wakeup, actions);
+        return Ok(());
+    }
+
+    /// the TSDU is segmented into DT TPDUs as needed and sent as far as the credit allows - WouldBlock while the send buffer is full
+    fn t_data_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        let mut actions = Actions::default();
+        self.entity.lock().expect("failed to lock entity").data_request(reference, data, &mut actions)?;
+        execute(&self.ns, &self.t_user_to_wakeup, actions);
+        return Ok(());
+    }
+
+    /// one at a time, WouldBlock while the previous one is not acknowledged yet
+    fn t_expedited_data_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        let mut actions = Actions::default();
+        self.entity.lock().expect("failed to lock entity").expedited_data_request(reference, data, &mut actions)?;
+        execute(&self.ns, &self.t_user_to_wakeup, actions);
+        return Ok(());
+    }
+
+    fn t_disconnect_request(&mut self, reference: u16, data: &[u8]) -> Result<(), Error> {
+        let mut actions = Actions::default();
+        self.entity.lock().expect("failed to lock entity").disconnect_request(reference, data, &mut actions)?;
+        execute(&self.ns, &self.t_user_to_wakeup, actions);
+        return Ok(());
+    }
+
+    fn connection_options(&self, reference: u16) -> Option<ConnectionOptions> {
+        let entity = self.entity.lock().expect("failed to lock entity");
+        let connection = entity.connections.get(&reference)?;
+        return Some(ConnectionOptions {
+            class: 4,
+            extended_format: connection.extended_format,
+            explicit_flow_control: true,
+            checksum: connection.checksum,
+            expedited: connection.expedited,
+            maximum_tpdu_size: tpdu::tpdu_size_from_code(connection.tpdu_size_code).unwrap_or(128),
+        });
+    }
+}
+
+/// sends the TPDUs via the NS and wakes up the TS user
+/// NOTE: done without the entity lock, since the NS may block on backpressure
+fn execute<N: NetworkService<'static>>(ns: &Mutex<N>, t_user_to_wakeup: &Mutex<Option<Thread>>, actions: Actions) {
+    // X.224 6.4 concatenation - TPDUs without user data go in front of the next TPDU to the same peer
+    let mut pending: Option<(Nsap, Vec<u8>)> = None;
+    for (peer, tpdu, checksum, extended) in actions.tpdus {
+        if let Some((pending_peer, buffer)) = pending.take() {
+            if pending_peer == peer {
+                pending = Some((pending_peer, buffer));
+            } else {
+                send_nsdu(ns, &pending_peer, &buffer);
+            }
+        }
+        let (_, buffer) = pending.get_or_insert_with(|| (peer.clone(), vec![]));
+        tpdu.into_buf_in_format(checksum, extended, buffer);
+        if carries_data(&tpdu) {
+            let (pending_peer, buffer) = pending.take().expect("pending NSDU");
+            send_nsdu(ns, &pending_peer, &buffer);
+        }
+    }
+    if let Some((pending_peer, buffer)) = pending {
+        send_nsdu(ns, &pending_peer, &buffer);
+    }
+    if actions.wakeup {
+        if let Some(wakeup) = t_user_to_wakeup.lock().expect("failed to lock t_user_to_wakeup").as_ref() {
+            wakeup.unpark();
+        }
+    }
+}
+
+fn send_nsdu<N: NetworkService<'static>>(ns: &Mutex<N>, peer: &Nsap, buffer: &[u8]) {
+    //TODO map transport QoS onto NS QoS
+    if let Err(e) = ns.lock().expect("failed to lock ns").n_unitdata_request_to_nsap(peer, &Qos::default(), buffer) {
+        // NOTE: lost like on the network, retransmission takes care of it
+        info!("TP4: NSDU to {} not sent: {}", peer.to_string(), e);
+    }
+}
+
+fn carries_data(tpdu: &Tpdu) -> bool {
+    match tpdu {
+        Tpdu::ConnectionRequest { .. } | Tpdu::ConnectionConfirm { .. } | Tpdu::DisconnectRequest { .. } | Tpdu::Data { .. } | Tpdu::ExpeditedData { .. } => { return true; },
+        _ => { return false; }
+    }
+}
+
+fn sequence_add(sequence_number: u32, n: u32, modulus: u32) -> u32 {
+    return ((sequence_number as u64 + n as u64) % modulus as u64) as u32;
+}
+
+/// how far to is ahead of from, modulo 128 resp. 2^31
+fn sequence_distance(from: u32, to: u32, modulus: u32) -> u32 {
+    return ((to as u64 + modulus as u64 - from as u64) % modulus as u64) as u32;
+}
+
+fn indicate(t_user_to: &mut rtrb::Producer<TIndication>, actions: &mut Actions, indication: TIndication) {
+    if let Err(_) = t_user_to.push(indication) {
+        warn!("TP4: queue towards TS user full, indication lost");
+        return;
+    }
+    actions.wakeup = true;
+}
+
+impl Connection {
+    fn new(state: State, local_reference: u16, peer: Nsap, now: Instant) -> Self {
+        return Connection {
+            state: state,
+            local_reference: local_reference,
+            remote_reference: 0,
+            peer: peer,
+            local_tsel: vec![],
+            remote_tsel: vec![],
+            checksum: true,
+            expedited: true,
+            extended_format: false,
+            tpdu_size_code: 0x07,
+            control: None,
+            send_next: 0,
+            send_window_lower: 0,
+            send_credit: 0,
+            unacknowledged: VecDeque::new(),
+            waiting: VecDeque::new(),
+            expedited_send_next: 0,
+            expedited_unacknowledged: None,
+            receive_next: 0,
+            out_of_order: BTreeMap::new(),
+            reassembly: vec![],
+            expedited_receive_next: 0,
+            last_heard: now,
+            last_ack_sent: now,
+        };
+    }
+
+    /// user data per DT TPDU: TPDU size minus LI, fixed part and checksum parameter
+    fn maximum_data_length(&self) -> usize {
+        let size = tpdu::tpdu_size_from_code(self.tpdu_size_code).unwrap_or(128);
+        return size - if self.extended_format { 8 } else { 5 } - if self.checksum { 4 } else { 0 };
+    }
+
+    fn modulus(&self) -> u32 {
+        return if self.extended_format { tpdu::EXTENDED_SEQUENCE_MODULUS } else { tpdu::SEQUENCE_MODULUS };
+    }
+
+    fn send(&self, actions: &mut Actions, tpdu: Tpdu) {
+        actions.tpdus.push((self.peer.clone(), tpdu, self.checksum, self.extended_format));
+    }
+
+    fn send_ack(&mut self, credit: u16, now: Instant, actions: &mut Actions) {
+        //TODO flow control confirmation and subsequence number parameters, X.224 12.2.3.8
+        self.send(actions, Tpdu::Ack { credit: credit, destination_reference: self.remote_reference, next_expected: self.receive_next, parameters: vec![] });
+        self.last_ack_sent = now;
+    }
+
+    /// sends waiting DT TPDUs as far as the credit of the peer allows
+    fn send_data(&mut self, now: Instant, actions: &mut Actions) {
+        while !self.waiting.is_empty() && sequence_distance(self.send_window_lower, self.send_next, self.modulus()) < self.send_credit as u32 {
+            let (end_of_tsdu, data) = self.waiting.pop_front().expect("waiting segment");
+            let dt = Tpdu::Data { destination_reference: self.remote_reference, end_of_tsdu: end_of_tsdu, sequence_number: self.send_next, parameters: vec![], data: data };
+            self.send(actions, dt.clone());
+            self.unacknowledged.push_back(Sent { sequence_number: self.send_next, tpdu: dt, sent_at: now, transmissions: 1 });
+            self.send_next = sequence_add(self.send_next, 1, self.modulus());
+        }
+    }
+
+    /// hands an in-sequence DT up, once the TSDU is complete - error if the TSDU grows beyond the given size
+    fn deliver(&mut self, end_of_tsdu: bool, data: Vec<u8>, maximum_tsdu_size: usize, t_user_to: &mut rtrb::Producer<TIndication>, actions: &mut Actions) -> Result<(), Error> {
+        if self.reassembly.len() + data.len() > maximum_tsdu_size {
+            return Err(Error::new(ErrorKind::InvalidData, "TSDU exceeds maximum size"));
+        }
+        self.reassembly.extend_from_slice(&data);
+        if end_of_tsdu {
+            let tsdu = std::mem::take(&mut self.reassembly);
+            indicate(t_user_to, actions, TIndication::DataIndication { reference: self.local_reference, data: tsdu });
+        }
+        return Ok(());
+    }
+}
+
+impl Entity {
+    fn allocate_reference(&mut self) -> Result<u16, Error> {
+        //TODO frozen references, X.224 6.18 - for now, references are only reused after wrapping around
+        for _ in 0..u16::MAX {
+            self.next_reference = self.next_reference.wrapping_add(1);
+            if self.next_reference != 0 && !self.connections.contains_key(&self.next_reference) {
+                return Ok(self.next_reference);
+            }
+        }
+        return Err(Error::new(ErrorKind::OutOfMemory, "no transport connection reference left"));
+    }
+
+    /// credit to offer - no more than the TS user queue can take, so that TSDUs do not have to be thrown away after acknowledging them
+    /// NOTE: CR and CC carry a credit of at most 15 even in extended format
+    fn receive_credit(&self, extended_format: bool) -> u16 {
+        let maximum = if extended_format { u16::MAX } else { CREDIT_MAXIMUM as u16 };
+        return (self.config.credit as u16).min(maximum).min(self.t_user_to.slots().min(maximum as usize) as u16);
+    }
+
+    fn connection_parameters(&self, calling_tsel: &[u8], called_tsel: &[u8], tpdu_size_code: u8, checksum: bool, expedited: bool) -> Vec<Parameter> {
+        let mut additional_options = 0;
+        if !checksum { additional_options |= tpdu::OPTION_NO_CHECKSUM; }
+        if expedited { additional_options |= tpdu::OPTION_EXPEDITED; }
+        return vec![
+            Parameter { code: tpdu::PARAMETER_CALLING_TSAP, value: calling_tsel.to_vec() },
+            Parameter { code: tpdu::PARAMETER_CALLED_TSAP, value: called_tsel.to_vec() },
+            Parameter { code: tpdu::PARAMETER_TPDU_SIZE, value: vec![tpdu_size_code] },
+            Parameter { code: tpdu::PARAMETER_VERSION, value: vec![0x01] },
+            Parameter { code: tpdu::PARAMETER_ADDITIONAL_OPTIONS, value: vec![additional_options] },
+        ];
+    }
+
+    fn connect_request(&mut self, called_address: &Nsap, calling_tsel: &[u8], called_tsel: &[u8], data: &[u8], actions: &mut Actions) -> Result<u16, Error> {
+        if data.len() > CONNECT_DATA_MAXIMUM {
+            return Err(Error::new(ErrorKind::InvalidInput, "too much user data for CR TPDU"));
+        }
+        let now = Instant::now();
+        let reference = self.allocate_reference()?;
+        let mut connection = Connection::new(State::WaitForCc, reference, called_address.clone(), now);
+        connection.local_tsel = calling_tsel.to_vec();
+        connection.remote_tsel = called_tsel.to_vec();
+        // NOTE: no alternative classes, classes 2 and 0 need a network connection
+        let cr = Tpdu::ConnectionRequest {
+            credit: self.receive_credit(false) as u8,
+            source_reference: reference,
+            class_option: tpdu::CLASS_4 | if self.config.extended_format { tpdu::OPTION_EXTENDED_FORMAT } else { 0 },
+            parameters: self.connection_parameters(calling_tsel, called_tsel, self.config.tpdu_size_code, true, true),
+            data: data.to_vec(),
+        };
+        connection.send(actions, cr.clone());
+        connection.control = Some(Sent { sequence_number: 0, tpdu: cr, sent_at: now, transmissions: 1 });
+        self.connections.insert(reference, connection);
+        return Ok(reference);
+    }
+
+    fn connect_response(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        if data.len() > CONNECT_DATA_MAXIMUM {
+            return Err(Error::new(ErrorKind::InvalidInput, "too much user data for CC TPDU"));
+        }
+        let credit = self.receive_credit(false) as u8;
+        let connection = self.connections.get(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such transport connection"))?;
+        if connection.state != State::WaitForResponse {
+            return Err(Error::new(ErrorKind::InvalidInput, "transport connection not awaiting a connect response"));
+        }
+        let (checksum, expedited, extended_format, tpdu_size_code) = (connection.checksum, connection.expedited, connection.extended_format, connection.tpdu_size_code);
+        // X.224 13.4.4 - the TSAP-IDs of the CR, still calling and called as in the CR
+        let parameters = self.connection_parameters(&connection.remote_tsel, &connection.local_tsel, tpdu_size_code, checksum, expedited);
+        let now = Instant::now();
+        let connection = self.connections.get_mut(&reference).expect("transport connection");
+        let cc = Tpdu::ConnectionConfirm {
+            credit: credit,
+            destination_reference: connection.remote_reference,
+            source_reference: reference,
+            class_option: tpdu::CLASS_4 | if extended_format { tpdu::OPTION_EXTENDED_FORMAT } else { 0 },
+            parameters: parameters,
+            data: data.to_vec(),
+        };
+        connection.send(actions, cc.clone());
+        connection.control = Some(Sent { sequence_number: 0, tpdu: cc, sent_at: now, transmissions: 1 });
+        connection.state = State::WaitForAck;
+        connection.last_ack_sent = now;
+        return Ok(());
+    }
+
+    fn data_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        let connection = self.connections.get_mut(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such transport connection"))?;
+        // NOTE: the responder may send as soon as it has sent the CC
+        if connection.state != State::Open && connection.state != State::WaitForAck {
+            return Err(Error::new(ErrorKind::NotConnected, "transport connection not open"));
+        }
+        // backpressure towards the TS user - but a TSDU larger than the send buffer can still go on its own
+        let waiting_size: usize = connection.waiting.iter().map(|(_, segment)| segment.len()).sum();
+        if !connection.waiting.is_empty() && waiting_size + data.len() > self.config.send_buffer_size {
+            return Err(Error::new(ErrorKind::WouldBlock, "too much data waiting for credit of the peer"));
+        }
+        let maximum_data_length = connection.maximum_data_length();
+        let mut segments = data.chunks(maximum_data_length).peekable();
+        if data.is_empty() {
+            connection.waiting.push_back((true, vec![]));
+        }
+        while let Some(segment) = segments.next() {
+            connection.waiting.push_back((segments.peek().is_none(), segment.to_vec()));
+        }
+        connection.send_data(Instant::now(), actions);
+        return Ok(());
+    }
+
+    fn expedited_data_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        if data.len() > EXPEDITED_DATA_MAXIMUM || data.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "expedited TSDU must be 1 to 16 octets"));
+        }
+        let connection = self.connections.get_mut(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such transport connection"))?;
+        if connection.state != State::Open && connection.state != State::WaitForAck {
+            return Err(Error::new(ErrorKind::NotConnected, "transport connection not open"));
+        }
+        if !connection.expedited {
+            return Err(Error::new(ErrorKind::Unsupported, "expedited data not negotiated"));
+        }
+        if connection.expedited_unacknowledged.is_some() {
+            return Err(Error::new(ErrorKind::WouldBlock, "previous expedited TSDU not acknowledged yet"));
+        }
+        let ed = Tpdu::ExpeditedData { destination_reference: connection.remote_reference, sequence_number: connection.expedited_send_next, parameters: vec![], data: data.to_vec() };
+        connection.send(actions, ed.clone());
+        connection.expedited_unacknowledged = Some(Sent { sequence_number: connection.expedited_send_next, tpdu: ed, sent_at: Instant::now(), transmissions: 1 });
+        connection.expedited_send_next = sequence_add(connection.expedited_send_next, 1, connection.modulus());
+        return Ok(());
+    }
+
+    fn disconnect_request(&mut self, reference: u16, data: &[u8], actions: &mut Actions) -> Result<(), Error> {
+        if data.len() > DISCONNECT_DATA_MAXIMUM {
+            return Err(Error::new(ErrorKind::InvalidInput, "too much user data for DR TPDU"));
+        }
+        let connection = self.connections.get_mut(&reference).ok_or(Error::new(ErrorKind::NotFound, "no such transport connection"))?;
+        let reason = match connection.state {
+            State::WaitForResponse => tpdu::REASON_REFUSED,
+            State::Closing => { return Ok(()); },
+            _ => tpdu::REASON_NORMAL,
+        };
+        let dr = Tpdu::DisconnectRequest { destination_reference: connection.remote_reference, source_reference: reference, reason: reason, parameters: vec![], data: data.to_vec() };
+        connection.send(actions, dr.clone());
+        connection.control = Some(Sent { sequence_number: 0, tpdu: dr, sent_at: Instant::now(), transmissions: 1 });
+        connection.state = State::Closing;
+        // NOTE: data not sent or not acknowledged yet is lost, X.224 disconnect is not graceful
+        connection.waiting.clear();
+        connection.unacknowledged.clear();
+        connection.expedited_unacknowledged = None;
+        return Ok(());
+    }
+
+    fn ns_unitdata_indication(&mut self, indication: NSUnitDataIndication, actions: &mut Actions) {
+        // X.224 6.4 separation of concatenated TPDUs
+        let mut offset = 0;
+        while offset < indication.ns_userdata.len() {
+            let extended_format = tpdu::format_dependent_reference(&indication.ns_userdata[offset..])
+                .and_then(|reference| self.connections.get(&reference))
+                .is_some_and(|connection| connection.peer == indication.ns_source_address && connection.extended_format);
+            match Tpdu::from_buf_in_format(&indication.ns_userdata[offset..], extended_format) {
+                Ok((tpdu, consumed)) => {
+                    debug!("TP4: got TPDU from {}: {:?}", indication.ns_source_address.to_string(), tpdu);
+                    let checksummed = tpdu.parameter(tpdu::PARAMETER_CHECKSUM).is_some();
+                    self.tpdu_indication(&indication.ns_source_address, &indication.ns_destination_address, tpdu, checksummed, actions);
+                    offset += consumed;
+                },
+                Err(e) => {
+                    //TODO send ER TPDU if the TPDU could be attributed to a connection
+                    info!("TP4: discarding invalid TPDU from {}: {}", indication.ns_source_address.to_string(), e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn tpdu_indication(&mut self, peer: &Nsap, local_address: &Nsap, tpdu: Tpdu, checksummed: bool, actions: &mut Actions) {
+        let now = Instant::now();
+        if let Tpdu::ConnectionRequest { .. } = tpdu {
+            self.connection_request_indication(peer, local_address, tpdu, checksummed, now, actions);
+            return;
+        }
+        let reference = tpdu.destination_reference();
+        let receive_credit = self.receive_credit(self.connections.get(&reference).is_some_and(|connection| connection.extended_format));
+        let connection = match self.connections.get_mut(&reference) {
+            Some(connection) if connection.peer == *peer => connection,
+            _ => {
+                // a DR for an unknown connection is answered with a DC, everything else ignored
+                if let Tpdu::DisconnectRequest { source_reference, .. } = tpdu {
+                    actions.tpdus.push((peer.clone(), Tpdu::DisconnectConfirm { destination_reference: source_reference, source_reference: reference, parameters: vec![] }, true, false));
+                } else {
+                    debug!("TP4: TPDU for unknown reference {}, ignoring", reference);
+                }
+                return;
+            }
+        };
+        if connection.checksum && !checksummed {
+            info!("TP4: TPDU without checksum on connection {}, discarding", reference);
+            return;
+        }
+        connection.last_heard = now;
+
+        match tpdu {
+            Tpdu::ConnectionRequest { .. } => unreachable!(),
+            Tpdu::UnitData { .. } => {
+                debug!("TP4: UD TPDU belongs to CLTP, ignoring");
+            },
+            Tpdu::DataClass0 { .. } => {
+                debug!("TP4: class 0 DT TPDU on CLNS, ignoring");
+            },
+            Tpdu::ConnectionConfirm { credit, source_reference, class_option, parameters, data, .. } => {
+                match connection.state {
+                    State::WaitForCc => {
+                        // X.224 6.5.4 - class 4 was proposed without alternatives, the responder could still fall back to class 2
+                        // which needs CONS, and it may not select extended format unless proposed
+                        let extended_format = class_option & tpdu::OPTION_EXTENDED_FORMAT != 0;
+                        if class_option & 0xF0 != tpdu::CLASS_4 || (extended_format && !self.config.extended_format) {
+                            info!("TP4: CC selects class {} resp. options {:#04x} not possible over CLNS or not proposed, disconnecting", class_option >> 4, class_option & 0x0F);
+                            let dr = Tpdu::DisconnectRequest { destination_reference: source_reference, source_reference: reference, reason: tpdu::REASON_NEGOTIATION_FAILED, parameters: vec![], data: vec![] };
+                            connection.send(actions, dr);
+                            indicate(&mut self.t_user_to, actions, TIndication::DisconnectIndication { reference: reference, reason: tpdu::REASON_NEGOTIATION_FAILED, data: vec![] });
+                            self.connections.remove(&reference);
+                            return;
+                        }
+                        connection.extended_format = extended_format;
+                        connection.remote_reference = source_reference;
+                        connection.send_credit = credit as u16;
+                        connection.control = None;
+                        connection.state = State::Open;
+                        let cc = Tpdu::ConnectionConfirm { credit: credit, destination_reference: reference, source_reference: source_reference, class_option: class_option, parameters: parameters, data: vec![] };
+                        if let Some(tpdu_size_code) = cc.parameter(tpdu::PARAMETER_TPDU_SIZE) {
+                            if tpdu_size_code.len() == 1 && tpdu::tpdu_size_from_code(tpdu_size_code[0]).is_some() {
+                                connection.tpdu_size_code = tpdu_size_code[0];
+                            }
+                        }
+                        if let Some(options) = cc.parameter(tpdu::PARAMETER_ADDITIONAL_OPTIONS) {
+                            if options.len() == 1 {
+                                connection.checksum = options[0] & tpdu::OPTION_NO_CHECKSUM == 0;
+                                connection.expedited = options[0] & tpdu::OPTION_EXPEDITED != 0;
+                            }
+                        } else {
+                            // X.224 13.3.4 k) defaults
+                            connection.expedited = false;
+                        }
+                        // three-way handshake - the AK confirms the CC, in the format just negotiated
+                        let receive_credit = if extended_format { receive_credit } else { receive_credit.min(CREDIT_MAXIMUM as u16) };
+                        connection.send_ack(receive_credit, now, actions);
+                        indicate(&mut self.t_user_to, actions, TIndication::ConnectConfirm { reference: reference, data: data });
+                        connection.send_data(now, actions);
+                    },
+                    State::Open => {
+                        // our AK got lost
+                        connection.send_ack(receive_credit, now, actions);
+                    },
+                    _ => {}
+                }
+            },
+            Tpdu::DisconnectRequest { source_reference, reason, data, .. } => {
+                actions.tpdus.push((peer.clone(), Tpdu::DisconnectConfirm { destination_reference: source_reference, source_reference: reference, parameters: vec![] }, connection.checksum, false));
+                if connection.state != State::Closing {
+                    indicate(&mut self.t_user_to, actions, TIndication::DisconnectIndication { reference: reference, reason: reason, data: data });
+                }
+                self.connections.remove(&reference);
+            },
+            Tpdu::DisconnectConfirm { .. } => {
+                if connection.state == State::Closing {
+                    self.connections.remove(&reference);
+                }
+            },
+            Tpdu::Data { end_of_tsdu, sequence_number, data, .. } => {
+                if connection.state == State::WaitForAck {
+                    connection.state = State::Open;
+                    connection.control = None;
+                }
+                if connection.state != State::Open {
+                    return;
+                }
+                let modulus = connection.modulus();
+                let offset = sequence_distance(connection.receive_next, sequence_number, modulus);
+                if offset == 0 {
+                    if end_of_tsdu && self.t_user_to.is_full() {
+                        // flow control towards the TS user - not acknowledged, so the peer sends it again
+                        debug!("TP4: TS user queue full, withholding DT {}", sequence_number);
+                        connection.send_ack(0, now, actions);
+                        return;
+                    }
+                    // a retransmission of what is already waiting out of order
+                    connection.out_of_order.remove(&sequence_number);
+                    let mut delivered = connection.deliver(end_of_tsdu, data, self.config.maximum_tsdu_size, &mut self.t_user_to, actions);
+                    connection.receive_next = sequence_add(connection.receive_next, 1, modulus);
+                    // close the gap with what arrived out of order - as far as the TS user queue takes complete TSDUs,
+                    // the rest stays unacknowledged until the peer retransmits it
+                    while delivered.is_ok() {
+                        match connection.out_of_order.get(&connection.receive_next) {
+                            Some((true, _)) if self.t_user_to.is_full() => { break; },
+                            None => { break; },
+                            _ => {}
+                        }
+                        let (end_of_tsdu, data) = connection.out_of_order.remove(&connection.receive_next).expect("out of order DT");
+                        delivered = connection.deliver(end_of_tsdu, data, self.config.maximum_tsdu_size, &mut self.t_user_to, actions);
+                        connection.receive_next = sequence_add(connection.receive_next, 1, modulus);
+                    }
+                    if let Err(e) = delivered {
+                        info!("TP4: disconnecting connection {}: {}", reference, e);
+                        let dr = Tpdu::DisconnectRequest { destination_reference: connection.remote_reference, source_reference: reference, reason: tpdu::REASON_PROTOCOL_ERROR, parameters: vec![], data: vec![] };
+                        connection.send(actions, dr);
+                        indicate(&mut self.t_user_to, actions, TIndication::DisconnectIndication { reference: reference, reason: tpdu::REASON_PROTOCOL_ERROR, data: vec![] });
+                        self.connections.remove(&reference);
+                        return;
+                    }
+                } else if offset < receive_credit as u32 {
+                    // X.224 12.2.3.4 resequencing
+                    connection.out_of_order.insert(sequence_number, (end_of_tsdu, data));
+                }
+                // NOTE: otherwise a duplicate or outside the window - the AK tells the peer where we are
+                connection.send_ack(receive_credit, now, actions);
+            },
+            Tpdu::ExpeditedData { sequence_number, data, .. } => {
+                if connection.state == State::WaitForAck {
+                    connection.state = State::Open;
+                    connection.control = None;
+                }
+                if connection.state != State::Open {
+                    return;
+                }
+                let modulus = connection.modulus();
+                if sequence_number == connection.expedited_receive_next {
+                    if self.t_user_to.is_full() {
+                        // not acknowledged, so the peer sends it again
+                        debug!("TP4: TS user queue full, withholding ED {}", sequence_number);
+                        return;
+                    }
+                    indicate(&mut self.t_user_to, actions, TIndication::ExpeditedDataIndication { reference: reference, data: data });
+                    connection.expedited_receive_next = sequence_add(connection.expedited_receive_next, 1, modulus);
+                } else if sequence_add(sequence_number, 1, modulus) != connection.expedited_receive_next {
+                    // neither the expected one nor a duplicate of the last one, so it was never delivered
+                    debug!("TP4: ED {} out of sequence, ignoring", sequence_number);
+                    return;
+                }
+                // duplicates are acknowledged again, since our EA may have been lost
+                let ea = Tpdu::ExpeditedAck { destination_reference: connection.remote_reference, next_expected: sequence_add(sequence_number, 1, connection.modulus()), parameters: vec![] };
+                connection.send(actions, ea);
+            },
+            Tpdu::Ack { credit, next_expected, .. } => {
+                if connection.state == State::WaitForAck {
+                    connection.state = State::Open;
+                    connection.control = None;
+                }
+                if connection.state != State::Open {
+                    return;
+                }
+                // only if it acknowledges something between the window edge and what has been sent
+                //TODO subsequence numbers, to tell an old AK from a new one with the same sequence number
+                let modulus = connection.modulus();
+                if sequence_distance(connection.send_window_lower, next_expected, modulus) > sequence_distance(connection.send_window_lower, connection.send_next, modulus) {
+                    debug!("TP4: AK for {} out of window, ignoring", next_expected);
+                    return;
+                }
+                while let Some(sent) = connection.unacknowledged.front() {
+                    if sent.sequence_number == next_expected {
+                        break;
+                    }
+                    connection.unacknowledged.pop_front();
+                }
+                connection.send_window_lower = next_expected;
+                connection.send_credit = credit;
+                connection.send_data(now, actions);
+            },
+            Tpdu::ExpeditedAck { next_expected, .. } => {
+                if let Some(sent) = &connection.expedited_unacknowledged {
+                    if sequence_add(sent.sequence_number, 1, connection.modulus()) == next_expected {
+                        connection.expedited_unacknowledged = None;
+                    }
+                }
+            },
+            Tpdu::Error { cause, .. } => {
+                info!("TP4: peer rejected a TPDU on connection {} with cause {}", reference, cause);
+                let dr = Tpdu::DisconnectRequest { destination_reference: connection.remote_reference, source_reference: reference, reason: tpdu::REASON_PROTOCOL_ERROR, parameters: vec![], data: vec![] };
+                connection.send(actions, dr);
+                indicate(&mut self.t_user_to, actions, TIndication::DisconnectIndication { reference: reference, reason: tpdu::REASON_PROTOCOL_ERROR, data: vec![] });
+                self.connections.remove(&reference);
+            },
+        }
+    }
+
+    fn connection_request_indication(&mut self, peer: &Nsap, local_address: &Nsap, cr: Tpdu, checksummed: bool, now: Instant, actions: &mut Actions) {
+        let (credit, source_reference, class_option) = match &cr {
+            Tpdu::ConnectionRequest { credit, source_reference, class_option, .. } => (*credit, *source_reference, *class_option),
+            _ => { return; }
+        };
+        let options = cr.parameter(tpdu::PARAMETER_ADDITIONAL_OPTIONS).and_then(|options| options.first().copied()).unwrap_or(0);
+        let checksum = options & tpdu::OPTION_NO_CHECKSUM == 0;
+        if checksum && !checksummed {
+            info!("TP4: CR without checksum from {}, discarding", peer.to_string());
+            return;
+        }
+        // retransmitted CR - our CC got lost
+        let existing = self.connections.values_mut().find(|connection| connection.peer == *peer && connection.remote_reference == source_reference);
+        if let Some(connection) = existing {
+            if connection.state == State::WaitForAck {
+                if let Some(control) = &connection.control {
+                    let cc = control.tpdu.clone();
+                    connection.send(actions, cc);
+                }
+            }
+            return;
+        }
+        // X.224 6.5.4 - over CLNS only class 4 is possible, whether preferred or alternative
+        if tpdu::select_class(class_option >> 4, &tpdu::alternative_classes(&cr), &[4]).is_none() {
+            info!("TP4: CR for class {} from {} does not allow class 4, refusing", class_option >> 4, peer.to_string());
+            actions.tpdus.push((peer.clone(), Tpdu::DisconnectRequest { destination_reference: source_reference, source_reference: 0, reason: tpdu::REASON_NEGOTIATION_FAILED, parameters: vec![], data: vec![] }, checksum, false));
+            return;
+        }
+        let calling_tsel = cr.parameter(tpdu::PARAMETER_CALLING_TSAP).unwrap_or(&[]).to_vec();
+        let called_tsel = cr.parameter(tpdu::PARAMETER_CALLED_TSAP).unwrap_or(&[]).to_vec();
+        if !self.bound.contains(&called_tsel) {
+            info!("TP4: CR from {} for unbound TSAP selector {:?}, refusing", peer.to_string(), called_tsel);
+            actions.tpdus.push((peer.clone(), Tpdu::DisconnectRequest { destination_reference: source_reference, source_reference: 0, reason: tpdu::REASON_NOT_ATTACHED, parameters: vec![], data: vec![] }, checksum, false));
+            return;
+        }
+        let reference = match self.allocate_reference() {
+            Ok(reference) => reference,
+            Err(e) => {
+                info!("TP4: refusing CR from {}: {}", peer.to_string(), e);
+                actions.tpdus.push((peer.clone(), Tpdu::DisconnectRequest { destination_reference: source_reference, source_reference: 0, reason: tpdu::REASON_CONGESTION, parameters: vec![], data: vec![] }, checksum, false));
+                return;
+            }
+        };
+        let mut connection = Connection::new(State::WaitForResponse, reference, peer.clone(), now);
+        connection.remote_reference = source_reference;
+        connection.send_credit = credit as u16;
+        connection.checksum = checksum;
+        connection.expedited = options & tpdu::OPTION_EXPEDITED != 0;
+        // X.224 6.5.4 - extended format if proposed and wanted, normal format otherwise
+        connection.extended_format = class_option & tpdu::OPTION_EXTENDED_FORMAT != 0 && self.config.extended_format;
+        // X.224 6.5 negotiation of the TPDU size - the smaller one
+        let proposed_size_code = cr.parameter(tpdu::PARAMETER_TPDU_SIZE).and_then(|size| size.first().copied()).filter(|code| tpdu::tpdu_size_from_code(*code).is_some()).unwrap_or(0x07);
+        connection.tpdu_size_code = proposed_size_code.min(self.config.tpdu_size_code);
+        connection.local_tsel = called_tsel.clone();
+        connection.remote_tsel = calling_tsel.clone();
+        self.connections.insert(reference, connection);
+        let data = match cr {
+            Tpdu::ConnectionRequest { data, .. } => data,
+            _ => vec![],
+        };
+        indicate(&mut self.t_user_to, actions, TIndication::ConnectIndication {
+            reference: reference,
+            calling_address: TransportAddress::new(NetworkAddress::Nsap(peer.clone()), &calling_tsel),
+            called_address: TransportAddress::new(NetworkAddress::Nsap(local_address.clone()), &called_tsel),
+            data: data,
+        });
+    }
+
+    /// X.224 12.2.1 retransmission (T1, N), window (W) and inactivity (I) timers
+    fn timers(&mut self, now: Instant, actions: &mut Actions) {
+        let config = self.config;
+        let receive_credit = self.receive_credit(false);
+        let extended_receive_credit = self.receive_credit(true);
+        let mut given_up = vec![];
+        for (reference, connection) in self.connections.iter_mut() {
+            // NOTE: also a CR the TS user never answers, otherwise CRs could use up all references
+            if now.duration_since(connection.last_heard) > config.inactivity_time {
+                info!("TP4: connection {} inactive for too long", reference);
+                given_up.push(*reference);
+                continue;
+            }
+            let mut exhausted = false;
+            let (checksum, extended_format) = (connection.checksum, connection.extended_format);
+            let peer = connection.peer.clone();
+            let mut retransmit = |sent: &mut Sent| {
+                if now.duration_since(sent.sent_at) <= config.retransmission_time {
+                    return;
+                }
+                if sent.transmissions >= config.maximum_transmissions {
+                    exhausted = true;
+                    return;
+                }
+                sent.transmissions += 1;
+                sent.sent_at = now;
+                actions.tpdus.push((peer.clone(), sent.tpdu.clone(), checksum, extended_format));
+            };
+            if let Some(control) = connection.control.as_mut() {
+                retransmit(control);
+            }
+            for sent in connection.unacknowledged.iter_mut() {
+                retransmit(sent);
+            }
+            if let Some(sent) = connection.expedited_unacknowledged.as_mut() {
+                retransmit(sent);
+            }
+            if exhausted {
+                info!("TP4: connection {} - no acknowledgement after {} transmissions", reference, config.maximum_transmissions);
+                given_up.push(*reference);
+                continue;
+            }
+            if connection.state == State::Open && now.duration_since(connection.last_ack_sent) > config.window_time {
+                connection.send_ack(if extended_format { extended_receive_credit } else { receive_credit }, now, actions);
+            }
+        }
+        for reference in given_up {
+            let connection = self.connections.remove(&reference).expect("transport connection");
+            if connection.state == State::Closing {
+                continue;
+            }
+            let reason = if connection.state == State::WaitForResponse { tpdu::REASON_REFUSED } else { tpdu::REASON_NORMAL };
+            let dr = Tpdu::DisconnectRequest { destination_reference: connection.remote_reference, source_reference: reference, reason: reason, parameters: vec![], data: vec![] };
+            connection.send(actions, dr);
+            indicate(&mut self.t_user_to, actions, TIndication::DisconnectIndication { reference: reference, reason: tpdu::REASON_NOT_SPECIFIED, data: vec![] });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::n::Qos;
+
+    const NSAP_A: [u8; 12] = [0, 49, 0, 1, 0, 1, 0x02, 0, 0, 0, 0, 0x0a];
+    const NSAP_B: [u8; 12] = [0, 49, 0, 1, 0, 1, 0x02, 0, 0, 0, 0, 0x0b];
+
+    fn entity() -> (Entity, rtrb::Consumer<TIndication>) {
+        return entity_with(Config::default());
+    }
+
+    fn entity_with(config: Config) -> (Entity, rtrb::Consumer<TIndication>) {
+        let (t_user_to, t_user_from) = rtrb::RingBuffer::new(16);
+        let entity = Entity { config: config, connections: HashMap::new(), next_reference: 0, bound: HashSet::new(), t_user_to: t_user_to };
+        return (entity, t_user_from);
+    }
+
+    fn nsap(address: [u8; 12]) -> Nsap {
+        return Nsap::from_u8(&address).unwrap();
+    }
+
+    /// encodes the TPDUs of the actions into one NSDU each, the way execute() does without concatenation
+    fn nsdus(actions: Actions) -> Vec<Vec<u8>> {
+        return actions.tpdus.into_iter().map(|(_, tpdu, checksum, extended_format)| {
+            let mut buffer = vec![];
+            tpdu.into_buf_in_format(checksum, extended_format, &mut buffer);
+            return buffer;
+        }).collect();
+    }
+
+    fn receive(entity: &mut Entity, from: [u8; 12], to: [u8; 12], nsdu: Vec<u8>) -> Actions {
+        let mut actions = Actions::default();
+        entity.ns_unitdata_indication(NSUnitDataIndication { ns_source_address: nsap(from), ns_destination_address: nsap(to), ns_quality_of_service: Qos::default(), ns_userdata: nsdu }, &mut actions);
+        return actions;
+    }
+
+    /// CR, CC and AK between A (initiator) and B (responder) - returns the references of both ends
+    fn handshake(a: &mut Entity, a_from: &mut rtrb::Consumer<TIndication>, b: &mut Entity, b_from: &mut rtrb::Consumer<TIndication>) -> (u16, u16) {
+        b.bound.insert(b"B".to_vec());
+        let mut actions = Actions::default();
+        let a_reference = a.connect_request(&nsap(NSAP_B), b"A", b"B", b"connect", &mut actions).unwrap();
+        let cr = nsdus(actions).remove(0);
+        assert_eq!(cr[1] & 0xF0, tpdu::CODE_CR);
+        assert!(nsdus(receive(b, NSAP_A, NSAP_B, cr)).is_empty());
+        let b_reference = match b_from.pop().unwrap() {
+            TIndication::ConnectIndication { reference, calling_address, data, .. } => {
+                assert_eq!(calling_address.tsel, b"A");
+                assert_eq!(data, b"connect");
+                reference
+            },
+            other => panic!("{:?}", other)
+        };
+        let mut actions = Actions::default();
+        b.connect_response(b_reference, b"accept", &mut actions).unwrap();
+        assert_eq!(b.connections[&b_reference].state, State::WaitForAck);
+        let ak = nsdus(receive(a, NSAP_B, NSAP_A, nsdus(actions).remove(0)));
+        assert!(matches!(a_from.pop().unwrap(), TIndication::ConnectConfirm { ref data, .. } if data == b"accept"));
+        assert_eq!(a.connections[&a_reference].state, State::Open);
+        assert_eq!(ak.len(), 1);
+        receive(b, NSAP_A, NSAP_B, ak[0].clone());
+        assert_eq!(b.connections[&b_reference].state, State::Open);
+        return (a_reference, b_reference);
+    }
+
+    #[test]
+    fn three_way_handshake() {
+        let (mut a, mut a_from) = entity();
+        let (mut b, mut b_from) = entity();
+        let (a_reference, b_reference) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        assert_eq!(a.connections[&a_reference].remote_reference, b_reference);
+        assert_eq!(b.connections[&b_reference].remote_reference, a_reference);
+        assert!(a.connections[&a_reference].checksum);
+        assert_eq!(a.connections[&a_reference].tpdu_size_code, 0x0A);
+        assert!(a.connections[&a_reference].control.is_none());
+        assert!(b.connections[&b_reference].control.is_none());
+    }
+
+    #[test]
+    fn refused_for_unbound_tsap() {
+        let (mut a, _a_from) = entity();
+        let (mut b, mut b_from) = entity();
+        let mut actions = Actions::default();
+        a.connect_request(&nsap(NSAP_B), b"A", b"nobody", &[], &mut actions).unwrap();
+        let replies = nsdus(receive(&mut b, NSAP_A, NSAP_B, nsdus(actions).remove(0)));
+        assert!(b_from.pop().is_err());
+        match Tpdu::from_buf(&replies[0]).unwrap().0 {
+            Tpdu::DisconnectRequest { reason, .. } => assert_eq!(reason, tpdu::REASON_NOT_ATTACHED),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn retransmission_on_timeout() {
+        let (mut a, mut a_from) = entity();
+        let mut actions = Actions::default();
+        let reference = a.connect_request(&nsap(NSAP_B), b"A", b"B", &[], &mut actions).unwrap();
+        let cr = nsdus(actions).remove(0);
+        let config = a.config;
+
+        // not before T1
+        let mut actions = Actions::default();
+        a.timers(Instant::now(), &mut actions);
+        assert!(actions.tpdus.is_empty());
+        // then the same CR again, up to N transmissions in total
+        let mut now = Instant::now();
+        for _ in 1..config.maximum_transmissions {
+            now += config.retransmission_time + Duration::from_millis(1);
+            let mut actions = Actions::default();
+            a.timers(now, &mut actions);
+            assert_eq!(nsdus(actions), vec![cr.clone()]);
+        }
+        // and then given up
+        now += config.retransmission_time + Duration::from_millis(1);
+        let mut actions = Actions::default();
+        a.timers(now, &mut actions);
+        assert!(!a.connections.contains_key(&reference));
+        assert!(matches!(a_from.pop().unwrap(), TIndication::DisconnectIndication { reference: given_up, .. } if given_up == reference));
+    }
+
+    #[test]
+    fn data_retransmitted_until_acknowledged() {
+        let (mut a, mut a_from) = entity();
+        let (mut b, mut b_from) = entity();
+        let (a_reference, _) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        let mut actions = Actions::default();
+        a.data_request(a_reference, b"lost", &mut actions).unwrap();
+        let dt = nsdus(actions).remove(0);
+        let mut actions = Actions::default();
+        a.timers(Instant::now() + a.config.retransmission_time + Duration::from_millis(1), &mut actions);
+        let retransmitted = nsdus(actions);
+        assert_eq!(retransmitted, vec![dt]);
+
+        // the AK of the retransmission ends it
+        let ak = nsdus(receive(&mut b, NSAP_A, NSAP_B, retransmitted[0].clone()));
+        assert!(matches!(b_from.pop().unwrap(), TIndication::DataIndication { ref data, .. } if data == b"lost"));
+        receive(&mut a, NSAP_B, NSAP_A, ak[0].clone());
+        assert!(a.connections[&a_reference].unacknowledged.is_empty());
+        let mut actions = Actions::default();
+        a.timers(Instant::now() + a.config.retransmission_time * 2, &mut actions);
+        assert!(actions.tpdus.iter().all(|(_, tpdu, _, _)| !matches!(tpdu, Tpdu::Data { .. })));
+    }
+
+    #[test]
+    fn out_of_order_resequenced() {
+        let (mut a, mut a_from) = entity();
+        let (mut b, mut b_from) = entity();
+        let (a_reference, _) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        let mut dts = vec![];
+        for tsdu in [&b"first"[..], b"second", b"third"] {
+            let mut actions = Actions::default();
+            a.data_request(a_reference, tsdu, &mut actions).unwrap();
+            dts.append(&mut nsdus(actions));
+        }
+        assert_eq!(dts.len(), 3);
+        // third and second are held back until the first arrives
+        receive(&mut b, NSAP_A, NSAP_B, dts[2].clone());
+        receive(&mut b, NSAP_A, NSAP_B, dts[1].clone());
+        assert!(b_from.pop().is_err());
+        let ak = nsdus(receive(&mut b, NSAP_A, NSAP_B, dts[0].clone()));
+        for expected in [&b"first"[..], b"second", b"third"] {
+            assert!(matches!(b_from.pop().unwrap(), TIndication::DataIndication { ref data, .. } if data == expected));
+        }
+        // a duplicate is not delivered again
+        receive(&mut b, NSAP_A, NSAP_B, dts[1].clone());
+        assert!(b_from.pop().is_err());
+        // acknowledges all three
+        match Tpdu::from_buf(&ak[0]).unwrap().0 {
+            Tpdu::Ack { next_expected, .. } => assert_eq!(next_expected, 3),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn segmented_tsdu_reassembled() {
+        let (mut a, mut a_from) = entity();
+        let (mut b, mut b_from) = entity();
+        let (a_reference, _) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        let tsdu: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let mut actions = Actions::default();
+        a.data_request(a_reference, &tsdu, &mut actions).unwrap();
+        let dts = nsdus(actions);
+        assert_eq!(dts.len(), 3);
+        for dt in dts {
+            receive(&mut b, NSAP_A, NSAP_B, dt);
+        }
+        assert!(matches!(b_from.pop().unwrap(), TIndication::DataIndication { ref data, .. } if *data == tsdu));
+    }
+
+    #[test]
+    fn extended_format_negotiated() {
+        let config = Config { extended_format: true, credit: 12, ..Config::default() };
+        let (mut a, mut a_from) = entity_with(config);
+        let (mut b, mut b_from) = entity_with(config);
+        let (a_reference, b_reference) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        assert!(a.connections[&a_reference].extended_format);
+        assert!(b.connections[&b_reference].extended_format);
+        // 8 octets DT header instead of 5
+        assert_eq!(a.connections[&a_reference].maximum_data_length(), 1024 - 8 - 4);
+
+        // sequence numbers beyond what normal format can express
+        let connection = a.connections.get_mut(&a_reference).unwrap();
+        connection.send_next = 200;
+        connection.send_window_lower = 200;
+        b.connections.get_mut(&b_reference).unwrap().receive_next = 200;
+        let mut actions = Actions::default();
+        a.data_request(a_reference, b"extended", &mut actions).unwrap();
+        let dt = nsdus(actions).remove(0);
+        match Tpdu::from_buf_in_format(&dt, true).unwrap().0 {
+            Tpdu::Data { sequence_number, .. } => assert_eq!(sequence_number, 200),
+            other => panic!("{:?}", other)
+        }
+        let ak = nsdus(receive(&mut b, NSAP_A, NSAP_B, dt));
+        assert!(matches!(b_from.pop().unwrap(), TIndication::DataIndication { ref data, .. } if data == b"extended"));
+        match Tpdu::from_buf_in_format(&ak[0], true).unwrap().0 {
+            Tpdu::Ack { next_expected, credit, .. } => {
+                assert_eq!(next_expected, 201);
+                assert_eq!(credit, 12);
+            },
+            other => panic!("{:?}", other)
+        }
+        receive(&mut a, NSAP_B, NSAP_A, ak[0].clone());
+        assert!(a.connections[&a_reference].unacknowledged.is_empty());
+        assert_eq!(a.connections[&a_reference].send_credit, 12);
+    }
+
+    #[test]
+    fn extended_format_declined() {
+        let (mut a, mut a_from) = entity_with(Config { extended_format: true, ..Config::default() });
+        let (mut b, mut b_from) = entity();
+        let (a_reference, b_reference) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        // the responder selects normal format, which the initiator has to go along with
+        assert!(!a.connections[&a_reference].extended_format);
+        assert!(!b.connections[&b_reference].extended_format);
+        let mut actions = Actions::default();
+        a.data_request(a_reference, b"normal", &mut actions).unwrap();
+        let dt = nsdus(actions).remove(0);
+        assert_eq!(dt[0], 4 + 4);     // fixed part and checksum parameter
+        receive(&mut b, NSAP_A, NSAP_B, dt);
+        assert!(matches!(b_from.pop().unwrap(), TIndication::DataIndication { ref data, .. } if data == b"normal"));
+    }
+
+    /// a CC the initiator did not allow for is answered with a DR, and the TS user told
+    fn refused_confirm(class_option: u8) {
+        let (mut a, mut a_from) = entity();
+        let mut actions = Actions::default();
+        let reference = a.connect_request(&nsap(NSAP_B), b"A", b"B", &[], &mut actions).unwrap();
+        let cc = Tpdu::ConnectionConfirm { credit: 1, destination_reference: reference, source_reference: 77, class_option: class_option, parameters: vec![], data: vec![] };
+        let mut buffer = vec![];
+        cc.into_buf(true, &mut buffer);
+        let replies = nsdus(receive(&mut a, NSAP_B, NSAP_A, buffer));
+        match Tpdu::from_buf(&replies[0]).unwrap().0 {
+            Tpdu::DisconnectRequest { destination_reference, source_reference, reason, .. } => {
+                assert_eq!((destination_reference, source_reference), (77, reference));
+                assert_eq!(reason, tpdu::REASON_NEGOTIATION_FAILED);
+            },
+            other => panic!("{:?}", other)
+        }
+        assert!(matches!(a_from.pop().unwrap(), TIndication::DisconnectIndication { reference: refused, reason, .. } if refused == reference && reason == tpdu::REASON_NEGOTIATION_FAILED));
+        assert!(!a.connections.contains_key(&reference));
+    }
+
+    #[test]
+    fn confirm_with_other_class_refused() {
+        refused_confirm(tpdu::CLASS_2);
+        refused_confirm(tpdu::CLASS_0);
+    }
+
+    #[test]
+    fn confirm_with_extended_format_not_proposed_refused() {
+        refused_confirm(tpdu::CLASS_4 | tpdu::OPTION_EXTENDED_FORMAT);
+    }
+
+    #[test]
+    fn request_without_class_4_refused() {
+        let (mut b, mut b_from) = entity();
+        b.bound.insert(b"B".to_vec());
+        let cr = Tpdu::ConnectionRequest { credit: 1, source_reference: 5, class_option: tpdu::CLASS_2, parameters: vec![
+            Parameter { code: tpdu::PARAMETER_CALLED_TSAP, value: b"B".to_vec() },
+            tpdu::alternative_classes_parameter(&[0]),
+        ], data: vec![] };
+        let mut buffer = vec![];
+        cr.into_buf(true, &mut buffer);
+        let replies = nsdus(receive(&mut b, NSAP_A, NSAP_B, buffer));
+        assert!(matches!(Tpdu::from_buf(&replies[0]).unwrap().0, Tpdu::DisconnectRequest { reason, .. } if reason == tpdu::REASON_NEGOTIATION_FAILED));
+        assert!(b_from.pop().is_err());
+        // class 4 as an alternative is fine
+        let cr = Tpdu::ConnectionRequest { credit: 1, source_reference: 6, class_option: tpdu::CLASS_2, parameters: vec![
+            Parameter { code: tpdu::PARAMETER_CALLED_TSAP, value: b"B".to_vec() },
+            tpdu::alternative_classes_parameter(&[4]),
+        ], data: vec![] };
+        let mut buffer = vec![];
+        cr.into_buf(true, &mut buffer);
+        assert!(nsdus(receive(&mut b, NSAP_A, NSAP_B, buffer)).is_empty());
+        assert!(matches!(b_from.pop().unwrap(), TIndication::ConnectIndication { .. }));
+    }
+
+    #[test]
+    fn oversized_tsdu_disconnected() {
+        let (mut a, mut a_from) = entity();
+        let (mut b, mut b_from) = entity_with(Config { maximum_tsdu_size: 2000, ..Config::default() });
+        let (a_reference, b_reference) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        let mut actions = Actions::default();
+        a.data_request(a_reference, &[0u8; 3000], &mut actions).unwrap();
+        let dts = nsdus(actions);
+        receive(&mut b, NSAP_A, NSAP_B, dts[0].clone());
+        // the second segment takes the TSDU beyond 2000 octets
+        let replies = nsdus(receive(&mut b, NSAP_A, NSAP_B, dts[1].clone()));
+        match Tpdu::from_buf(&replies[0]).unwrap().0 {
+            Tpdu::DisconnectRequest { destination_reference, reason, .. } => {
+                assert_eq!(destination_reference, a_reference);
+                assert_eq!(reason, tpdu::REASON_PROTOCOL_ERROR);
+            },
+            other => panic!("{:?}", other)
+        }
+        assert!(matches!(b_from.pop().unwrap(), TIndication::DisconnectIndication { reason, .. } if reason == tpdu::REASON_PROTOCOL_ERROR));
+        assert!(!b.connections.contains_key(&b_reference));
+    }
+
+    #[test]
+    fn data_request_would_block() {
+        let (mut a, mut a_from) = entity_with(Config { send_buffer_size: 2000, ..Config::default() });
+        let (mut b, mut b_from) = entity();
+        let (a_reference, _) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        // no credit, so everything waits
+        a.connections.get_mut(&a_reference).unwrap().send_credit = 0;
+        let mut actions = Actions::default();
+        a.data_request(a_reference, &[0u8; 1500], &mut actions).unwrap();
+        assert_eq!(a.data_request(a_reference, &[0u8; 1000], &mut actions).unwrap_err().kind(), ErrorKind::WouldBlock);
+        a.data_request(a_reference, &[0u8; 500], &mut actions).unwrap();
+        assert!(actions.tpdus.is_empty());
+
+        // credit from the peer sends what waits, then there is room again - even for a TSDU larger than the send buffer
+        let ak = Tpdu::Ack { credit: 8, destination_reference: a_reference, next_expected: 0, parameters: vec![] };
+        let mut buffer = vec![];
+        ak.into_buf(true, &mut buffer);
+        assert_eq!(nsdus(receive(&mut a, NSAP_B, NSAP_A, buffer)).len(), 3);
+        a.data_request(a_reference, &[0u8; 5000], &mut actions).unwrap();
+    }
+
+    #[test]
+    fn out_of_order_held_back_while_queue_full() {
+        let (mut a, mut a_from) = entity();
+        let (mut b, mut b_from) = entity();
+        let (a_reference, b_reference) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        let mut dts = vec![];
+        for tsdu in [&b"first"[..], b"second", b"third"] {
+            let mut actions = Actions::default();
+            a.data_request(a_reference, tsdu, &mut actions).unwrap();
+            dts.append(&mut nsdus(actions));
+        }
+        receive(&mut b, NSAP_A, NSAP_B, dts[2].clone());
+        receive(&mut b, NSAP_A, NSAP_B, dts[1].clone());
+        // the TS user queue fills up after the credit for them was given, e.g. by other connections
+        while b.t_user_to.slots() > 1 {
+            b.t_user_to.push(TIndication::DataIndication { reference: 0, data: vec![] }).unwrap();
+        }
+        let ak = nsdus(receive(&mut b, NSAP_A, NSAP_B, dts[0].clone()));
+        match Tpdu::from_buf(&ak[0]).unwrap().0 {
+            Tpdu::Ack { next_expected, .. } => assert_eq!(next_expected, 1),
+            other => panic!("{:?}", other)
+        }
+        assert_eq!(b.connections[&b_reference].out_of_order.len(), 2);
+        let mut last = None;
+        while let Ok(indication) = b_from.pop() {
+            last = Some(indication);
+        }
+        assert!(matches!(last.unwrap(), TIndication::DataIndication { ref data, .. } if data == b"first"));
+
+        // the retransmission closes the gap once there is room
+        receive(&mut b, NSAP_A, NSAP_B, dts[1].clone());
+        for expected in [&b"second"[..], b"third"] {
+            assert!(matches!(b_from.pop().unwrap(), TIndication::DataIndication { ref data, .. } if data == expected));
+        }
+        assert!(b.connections[&b_reference].out_of_order.is_empty());
+        assert_eq!(b.connections[&b_reference].receive_next, 3);
+    }
+
+    #[test]
+    fn expedited_acknowledged_only_in_sequence() {
+        let (mut a, mut a_from) = entity();
+        let (mut b, mut b_from) = entity();
+        let (_, b_reference) = handshake(&mut a, &mut a_from, &mut b, &mut b_from);
+        let ed = |sequence_number: u32| {
+            let mut buffer = vec![];
+            Tpdu::ExpeditedData { destination_reference: b_reference, sequence_number: sequence_number, parameters: vec![], data: b"urgent".to_vec() }.into_buf(true, &mut buffer);
+            return buffer;
+        };
+        let acknowledged = |replies: Vec<Vec<u8>>| {
+            match Tpdu::from_buf(&replies[0]).unwrap().0 {
+                Tpdu::ExpeditedAck { next_expected, .. } => next_expected,
+                other => panic!("{:?}", other)
+            }
+        };
+        // ahead of the expected one - neither delivered nor acknowledged
+        assert!(nsdus(receive(&mut b, NSAP_A, NSAP_B, ed(5))).is_empty());
+        assert!(b_from.pop().is_err());
+        assert_eq!(acknowledged(nsdus(receive(&mut b, NSAP_A, NSAP_B, ed(0)))), 1);
+        assert!(matches!(b_from.pop().unwrap(), TIndication::ExpeditedDataIndication { ref data, .. } if data == b"urgent"));
+        // a duplicate is acknowledged again, but not delivered again
+        assert_eq!(acknowledged(nsdus(receive(&mut b, NSAP_A, NSAP_B, ed(0)))), 1);
+        assert!(b_from.pop().is_err());
+    }
+
+    #[test]
+    fn unanswered_connect_indication_refused() {
+        let (mut a, _a_from) = entity();
+        let (mut b, mut b_from) = entity();
+        b.bound.insert(b"B".to_vec());
+        let mut actions = Actions::default();
+        let a_reference = a.connect_request(&nsap(NSAP_B), b"A", b"B", &[], &mut actions).unwrap();
+        receive(&mut b, NSAP_A, NSAP_B, nsdus(actions).remove(0));
+        let b_reference = match b_from.pop().unwrap() {
+            TIndication::ConnectIndication { reference, .. } => reference,
+            other => panic!("{:?}", other)
+        };
+        // the TS user never responds
+        let mut actions = Actions::default();
+        b.timers(Instant::now() + b.config.inactivity_time + Duration::from_millis(1), &mut actions);
+        match Tpdu::from_buf(&nsdus(actions)[0]).unwrap().0 {
+            Tpdu::DisconnectRequest { destination_reference, reason, .. } => {
+                assert_eq!(destination_reference, a_reference);
+                assert_eq!(reason, tpdu::REASON_REFUSED);
+            },
+            other => panic!("{:?}", other)
+        }
+        assert!(matches!(b_from.pop().unwrap(), TIndication::DisconnectIndication { reference, .. } if reference == b_reference));
+        assert!(b.connections.is_empty());
+    }
+}
@@ -0,0 +1,624 @@
+use std::io::{Error, ErrorKind};
+
+// X.224 13.1 TPDU codes, in the upper half of the octet after the length indicator
+pub const CODE_CR: u8 = 0xE0;
+pub const CODE_CC: u8 = 0xD0;
+pub const CODE_DR: u8 = 0x80;
+pub const CODE_DC: u8 = 0xC0;
+pub const CODE_DT: u8 = 0xF0;
+pub const CODE_ED: u8 = 0x10;
+pub const CODE_AK: u8 = 0x60;
+pub const CODE_EA: u8 = 0x20;
+pub const CODE_ER: u8 = 0x70;
+/// X.234 connectionless-mode transport
+pub const CODE_UD: u8 = 0x40;
+
+// X.224 13.3.4, 13.5.4 parameters in the variable part
+pub const PARAMETER_TPDU_SIZE: u8 = 0xC0;
+pub const PARAMETER_CALLING_TSAP: u8 = 0xC1;
+pub const PARAMETER_CALLED_TSAP: u8 = 0xC2;
+pub const PARAMETER_CHECKSUM: u8 = 0xC3;
+pub const PARAMETER_VERSION: u8 = 0xC4;
+pub const PARAMETER_ADDITIONAL_OPTIONS: u8 = 0xC6;
+pub const PARAMETER_ALTERNATIVE_CLASSES: u8 = 0xC7;
+pub const PARAMETER_ACKNOWLEDGEMENT_TIME: u8 = 0x85;
+pub const PARAMETER_INVALID_TPDU: u8 = 0xC1;    // in ER TPDU only
+
+/// class and options field of CR and CC
+pub const CLASS_4: u8 = 0x40;
+/// additional option selection - no checksum in class 4
+pub const OPTION_NO_CHECKSUM: u8 = 0x02;
+/// additional option selection - use of transport expedited data transfer
+pub const OPTION_EXPEDITED: u8 = 0x01;
+
+// X.224 13.5.3 d) disconnect reasons
+pub const REASON_NORMAL: u8 = 0x80;
+pub const REASON_CONGESTION: u8 = 0x81;
+pub const REASON_NEGOTIATION_FAILED: u8 = 0x82;
+pub const REASON_PROTOCOL_ERROR: u8 = 0x85;
+pub const REASON_REFUSED: u8 = 0x88;
+pub const REASON_NOT_SPECIFIED: u8 = 0x00;
+pub const REASON_NOT_ATTACHED: u8 = 0x02;  // session entity not attached to TSAP
+
+// X.224 13.12.3 reject causes
+pub const CAUSE_INVALID_PARAMETER_CODE: u8 = 0x01;
+pub const CAUSE_INVALID_TPDU_TYPE: u8 = 0x02;
+
+/// class option - use of extended format, classes 2 to 4
+pub const OPTION_EXTENDED_FORMAT: u8 = 0x02;
+/// class 2 option - no explicit flow control, e.g. over a reliable network connection
+pub const CLASS_2_NO_FLOW_CONTROL: u8 = 0x01;
+pub const CLASS_2: u8 = 0x20;
+pub const CLASS_0: u8 = 0x00;
+
+/// TPDU sequence numbers in normal format are 7 bits
+pub const SEQUENCE_MODULUS: u32 = 128;
+/// X.224 13.2.3.2 - and 31 bits in extended format
+pub const EXTENDED_SEQUENCE_MODULUS: u32 = 1 << 31;
+
+/// X.224 6.5.4 class negotiation - which classes the responder may select for a preferred class
+fn classes_allowed_for(preferred: u8) -> &'static [u8] {
+    match preferred {
+        0 => { return &[0]; },
+        1 => { return &[1, 0]; },
+        2 => { return &[2, 0]; },
+        3 => { return &[3, 2]; },
+        4 => { return &[4, 2]; },
+        _ => { return &[]; }
+    }
+}
+
+/// classes the responder may select, in order of preference - the preferred class, the alternative classes and
+/// what each of them allows to fall back to
+pub fn acceptable_classes(preferred: u8, alternatives: &[u8]) -> Vec<u8> {
+    let mut acceptable: Vec<u8> = vec![];
+    for class in std::iter::once(&preferred).chain(alternatives.iter()) {
+        for allowed in classes_allowed_for(*class) {
+            if !acceptable.contains(allowed) {
+                acceptable.push(*allowed);
+            }
+        }
+    }
+    // NOTE: the classes themselves before the fallbacks
+    acceptable.sort_by_key(|class| if *class == preferred { 0 } else if alternatives.contains(class) { 1 } else { 2 });
+    return acceptable;
+}
+
+/// responder side - the most preferred acceptable class this entity supports
+pub fn select_class(preferred: u8, alternatives: &[u8], supported: &[u8]) -> Option<u8> {
+    return acceptable_classes(preferred, alternatives).into_iter().find(|class| supported.contains(class));
+}
+
+/// alternative protocol class parameter of a CR, one class per octet
+pub fn alternative_classes(cr: &Tpdu) -> Vec<u8> {
+    return cr.parameter(PARAMETER_ALTERNATIVE_CLASSES).unwrap_or(&[]).iter().map(|class_option| class_option >> 4).collect();
+}
+
+pub fn alternative_classes_parameter(alternatives: &[u8]) -> Parameter {
+    return Parameter { code: PARAMETER_ALTERNATIVE_CLASSES, value: alternatives.iter().map(|class| class << 4).collect() };
+}
+
+/// 0x07 = 128 octets up to 0x0D = 8192 octets
+pub fn tpdu_size_from_code(code: u8) -> Option<usize> {
+    if code < 0x07 || code > 0x0D {
+        return None;
+    }
+    return Some(1 << code);
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    pub code: u8,
+    pub value: Vec<u8>,
+}
+
+/// X.224 13 Structure and encoding of TPDUs
+/// NOTE: sequence numbers and the credit of an AK are as wide as in extended format, whether a TPDU is encoded resp.
+/// decoded in normal or extended format depends on what was negotiated for its transport connection
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tpdu {
+    ConnectionRequest {
+        credit: u8,
+        source_reference: u16,
+        class_option: u8,
+        parameters: Vec<Parameter>,
+        data: Vec<u8>,
+    },
+    ConnectionConfirm {
+        credit: u8,
+        destination_reference: u16,
+        source_reference: u16,
+        class_option: u8,
+        parameters: Vec<Parameter>,
+        data: Vec<u8>,
+    },
+    DisconnectRequest {
+        destination_reference: u16,
+        source_reference: u16,
+        reason: u8,
+        parameters: Vec<Parameter>,
+        data: Vec<u8>,
+    },
+    DisconnectConfirm {
+        destination_reference: u16,
+        source_reference: u16,
+        parameters: Vec<Parameter>,
+    },
+    /// class 0 DT, which has neither reference nor sequence number
+    DataClass0 {
+        end_of_tsdu: bool,
+        data: Vec<u8>,
+    },
+    Data {
+        destination_reference: u16,
+        end_of_tsdu: bool,
+        sequence_number: u32,
+        parameters: Vec<Parameter>,
+        data: Vec<u8>,
+    },
+    ExpeditedData {
+        destination_reference: u16,
+        sequence_number: u32,
+        parameters: Vec<Parameter>,
+        data: Vec<u8>,
+    },
+    Ack {
+        credit: u16,
+        destination_reference: u16,
+        next_expected: u32,
+        parameters: Vec<Parameter>,
+    },
+    ExpeditedAck {
+        destination_reference: u16,
+        next_expected: u32,
+        parameters: Vec<Parameter>,
+    },
+    Error {
+        destination_reference: u16,
+        cause: u8,
+        parameters: Vec<Parameter>,
+    },
+    /// X.234 UD TPDU, with the TSAP-IDs in the parameters
+    UnitData {
+        parameters: Vec<Parameter>,
+        data: Vec<u8>,
+    },
+}
+
+/// whether the NSDU is for CLTP - UD TPDUs are never concatenated with others
+pub fn is_unit_data(nsdu: &[u8]) -> bool {
+    return nsdu.len() >= 2 && nsdu[1] == CODE_UD;
+}
+
+/// destination reference of the DT, ED, AK or EA TPDU at the start of the buffer - these are encoded in the format
+/// negotiated for their transport connection, so it has to be looked up before decoding them
+pub fn format_dependent_reference(buffer: &[u8]) -> Option<u16> {
+    // NOTE: a class 0 DT has a length indicator of 2 and no reference
+    if buffer.len() < 4 || buffer[0] < 3 {
+        return None;
+    }
+    match buffer[1] & 0xF0 {
+        CODE_DT | CODE_ED | CODE_AK | CODE_EA => { return Some(u16::from_be_bytes([buffer[2], buffer[3]])); },
+        _ => { return None; }
+    }
+}
+
+impl Tpdu {
+    pub fn destination_reference(&self) -> u16 {
+        match self {
+            Tpdu::ConnectionRequest { .. } | Tpdu::DataClass0 { .. } | Tpdu::UnitData { .. } => { return 0; },
+            Tpdu::ConnectionConfirm { destination_reference, .. } |
+            Tpdu::DisconnectRequest { destination_reference, .. } |
+            Tpdu::DisconnectConfirm { destination_reference, .. } |
+            Tpdu::Data { destination_reference, .. } |
+            Tpdu::ExpeditedData { destination_reference, .. } |
+            Tpdu::Ack { destination_reference, .. } |
+            Tpdu::ExpeditedAck { destination_reference, .. } |
+            Tpdu::Error { destination_reference, .. } => { return *destination_reference; }
+        }
+    }
+
+    pub fn parameters(&self) -> &[Parameter] {
+        match self {
+            Tpdu::ConnectionRequest { parameters, .. } |
+            Tpdu::ConnectionConfirm { parameters, .. } |
+            Tpdu::DisconnectRequest { parameters, .. } |
+            Tpdu::DisconnectConfirm { parameters, .. } |
+            Tpdu::Data { parameters, .. } |
+            Tpdu::ExpeditedData { parameters, .. } |
+            Tpdu::Ack { parameters, .. } |
+            Tpdu::ExpeditedAck { parameters, .. } |
+            Tpdu::Error { parameters, .. } |
+            Tpdu::UnitData { parameters, .. } => { return parameters; },
+            Tpdu::DataClass0 { .. } => { return &[]; }
+        }
+    }
+
+    pub fn parameter(&self, code: u8) -> Option<&[u8]> {
+        return self.parameters().iter().find(|parameter| parameter.code == code).map(|parameter| parameter.value.as_slice());
+    }
+
+    /// decodes the first TPDU in the buffer - returns it and how many bytes it took up, since several TPDUs
+    /// may be concatenated into one NSDU (X.224 6.4)
+    /// a checksum parameter, if present, is verified - whether one is required is for the caller to decide
+    pub fn from_buf(buffer: &[u8]) -> Result<(Tpdu, usize), Error> {
+        return Self::from_buf_in_format(buffer, false);
+    }
+
+    /// like from_buf, with DT, ED, AK and EA TPDUs in extended format if asked for
+    pub fn from_buf_in_format(buffer: &[u8], extended: bool) -> Result<(Tpdu, usize), Error> {
+        if buffer.len() < 2 {
+            return Err(Error::new(ErrorKind::InvalidData, "TPDU too short"));
+        }
+        let length_indicator = buffer[0] as usize;
+        if length_indicator == 255 || length_indicator < 1 || buffer.len() < 1 + length_indicator {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid TPDU length indicator"));
+        }
+        let header = &buffer[1..1 + length_indicator];
+        let code = header[0] & 0xF0;
+        let low_bits = header[0] & 0x0F;
+        // fixed part length after the length indicator, and whether user data follows up to the end of the NSDU
+        let (fixed_length, has_data) = match code {
+            CODE_CR | CODE_CC | CODE_DR => (6, true),
+            CODE_DC => (5, false),
+            CODE_DT if length_indicator == 2 => (2, true),
+            CODE_UD => (1, true),
+            CODE_DT | CODE_ED if extended => (7, true),
+            CODE_DT | CODE_ED => (4, true),
+            CODE_AK if extended => (9, false),
+            CODE_EA if extended => (7, false),
+            CODE_AK | CODE_EA | CODE_ER => (4, false),
+            _ => { return Err(Error::new(ErrorKind::InvalidData, "unknown TPDU code")); }
+        };
+        if length_indicator < fixed_length {
+            return Err(Error::new(ErrorKind::InvalidData, "TPDU fixed part too short"));
+        }
+        let consumed = if has_data { buffer.len() } else { 1 + length_indicator };
+        let parameters = parse_parameters(&header[fixed_length..])?;
+        if let Some(checksum_parameter) = parameters.iter().find(|parameter| parameter.code == PARAMETER_CHECKSUM) {
+            if checksum_parameter.value.len() != 2 || !verify_checksum(&buffer[0..consumed]) {
+                return Err(Error::new(ErrorKind::InvalidData, "TPDU checksum invalid"));
+            }
+        }
+        let data = buffer[1 + length_indicator..consumed].to_vec();
+        let reference_at = |at: usize| u16::from_be_bytes([header[at], header[at + 1]]);
+        // 7 resp. 31 bits, the EOT bit of DT and ED in front
+        let sequence_number = || if extended { u32::from_be_bytes([header[3], header[4], header[5], header[6]]) & 0x7FFF_FFFF } else { (header[3] & 0x7F) as u32 };
+
+        let tpdu = match code {
+            CODE_CR => Tpdu::ConnectionRequest { credit: low_bits, source_reference: reference_at(3), class_option: header[5], parameters: parameters, data: data },
+            CODE_CC => Tpdu::ConnectionConfirm { credit: low_bits, destination_reference: reference_at(1), source_reference: reference_at(3), class_option: header[5], parameters: parameters, data: data },
+            CODE_DR => Tpdu::DisconnectRequest { destination_reference: reference_at(1), source_reference: reference_at(3), reason: header[5], parameters: parameters, data: data },
+            CODE_DC => Tpdu::DisconnectConfirm { destination_reference: reference_at(1), source_reference: reference_at(3), parameters: parameters },
+            CODE_DT if length_indicator == 2 => Tpdu::DataClass0 { end_of_tsdu: header[1] & 0x80 != 0, data: data },
+            CODE_DT => Tpdu::Data { destination_reference: reference_at(1), end_of_tsdu: header[3] & 0x80 != 0, sequence_number: sequence_number(), parameters: parameters, data: data },
+            CODE_ED => Tpdu::ExpeditedData { destination_reference: reference_at(1), sequence_number: sequence_number(), parameters: parameters, data: data },
+            // X.224 13.9.3.2 - in extended format the credit follows the sequence number
+            CODE_AK if extended => Tpdu::Ack { credit: reference_at(7), destination_reference: reference_at(1), next_expected: sequence_number(), parameters: parameters },
+            CODE_AK => Tpdu::Ack { credit: low_bits as u16, destination_reference: reference_at(1), next_expected: sequence_number(), parameters: parameters },
+            CODE_EA => Tpdu::ExpeditedAck { destination_reference: reference_at(1), next_expected: sequence_number(), parameters: parameters },
+            CODE_ER => Tpdu::Error { destination_reference: reference_at(1), cause: header[3], parameters: parameters },
+            CODE_UD => Tpdu::UnitData { parameters: parameters, data: data },
+            _ => unreachable!(),
+        };
+        return Ok((tpdu, consumed));
+    }
+
+    /// appends the encoded TPDU to the buffer, with a checksum parameter if asked for
+    /// returns how many bytes were appended
+    pub fn into_buf(&self, checksum: bool, buffer: &mut Vec<u8>) -> usize {
+        return self.into_buf_in_format(checksum, false, buffer);
+    }
+
+    /// like into_buf, with DT, ED, AK and EA TPDUs in extended format if asked for
+    pub fn into_buf_in_format(&self, checksum: bool, extended: bool, buffer: &mut Vec<u8>) -> usize {
+        let start = buffer.len();
+        let push_sequence_number = |buffer: &mut Vec<u8>, end_of_tsdu: bool, sequence_number: u32| {
+            if extended {
+                buffer.extend_from_slice(&(if end_of_tsdu { 0x8000_0000 } else { 0 } | (sequence_number & 0x7FFF_FFFF)).to_be_bytes());
+            } else {
+                buffer.push(if end_of_tsdu { 0x80 } else { 0 } | (sequence_number & 0x7F) as u8);
+            }
+        };
+        buffer.push(0);     // length indicator, filled in below
+        let data: &[u8] = match self {
+            Tpdu::ConnectionRequest { credit, source_reference, class_option, data, .. } => {
+                buffer.push(CODE_CR | (credit & 0x0F));
+                buffer.extend_from_slice(&[0, 0]);
+                buffer.extend_from_slice(&source_reference.to_be_bytes());
+                buffer.push(*class_option);
+                data
+            },
+            Tpdu::ConnectionConfirm { credit, destination_reference, source_reference, class_option, data, .. } => {
+                buffer.push(CODE_CC | (credit & 0x0F));
+                buffer.extend_from_slice(&destination_reference.to_be_bytes());
+                buffer.extend_from_slice(&source_reference.to_be_bytes());
+                buffer.push(*class_option);
+                data
+            },
+            Tpdu::DisconnectRequest { destination_reference, source_reference, reason, data, .. } => {
+                buffer.push(CODE_DR);
+                buffer.extend_from_slice(&destination_reference.to_be_bytes());
+                buffer.extend_from_slice(&source_reference.to_be_bytes());
+                buffer.push(*reason);
+                data
+            },
+            Tpdu::DisconnectConfirm { destination_reference, source_reference, .. } => {
+                buffer.push(CODE_DC);
+                buffer.extend_from_slice(&destination_reference.to_be_bytes());
+                buffer.extend_from_slice(&source_reference.to_be_bytes());
+                &[]
+            },
+            Tpdu::DataClass0 { end_of_tsdu, data } => {
+                buffer.push(CODE_DT);
+                buffer.push(if *end_of_tsdu { 0x80 } else { 0 });
+                data
+            },
+            Tpdu::Data { destination_reference, end_of_tsdu, sequence_number, data, .. } => {
+                buffer.push(CODE_DT);
+                buffer.extend_from_slice(&destination_reference.to_be_bytes());
+                push_sequence_number(buffer, *end_of_tsdu, *sequence_number);
+                data
+            },
+            Tpdu::ExpeditedData { destination_reference, sequence_number, data, .. } => {
+                buffer.push(CODE_ED);
+                buffer.extend_from_slice(&destination_reference.to_be_bytes());
+                push_sequence_number(buffer, true, *sequence_number);   // EOT is always set on ED
+                data
+            },
+            Tpdu::Ack { credit, destination_reference, next_expected, .. } => {
+                buffer.push(CODE_AK | if extended { 0 } else { (*credit as u8) & 0x0F });
+                buffer.extend_from_slice(&destination_reference.to_be_bytes());
+                push_sequence_number(buffer, false, *next_expected);
+                if extended {
+                    buffer.extend_from_slice(&credit.to_be_bytes());
+                }
+                &[]
+            },
+            Tpdu::ExpeditedAck { destination_reference, next_expected, .. } => {
+                buffer.push(CODE_EA);
+                buffer.extend_from_slice(&destination_reference.to_be_bytes());
+                push_sequence_number(buffer, false, *next_expected);
+                &[]
+            },
+            Tpdu::Error { destination_reference, cause, .. } => {
+                buffer.push(CODE_ER);
+                buffer.extend_from_slice(&destination_reference.to_be_bytes());
+                buffer.push(*cause);
+                &[]
+            },
+            Tpdu::UnitData { data, .. } => {
+                buffer.push(CODE_UD);
+                data
+            },
+        };
+        for parameter in self.parameters() {
+            if parameter.code == PARAMETER_CHECKSUM {
+                continue;   // computed fresh below
+            }
+            buffer.push(parameter.code);
+            buffer.push(parameter.value.len() as u8);
+            buffer.extend_from_slice(&parameter.value);
+        }
+        let mut checksum_at = None;
+        if checksum {
+            buffer.push(PARAMETER_CHECKSUM);
+            buffer.push(2);
+            checksum_at = Some(buffer.len() - start);
+            buffer.extend_from_slice(&[0, 0]);
+        }
+        buffer[start] = (buffer.len() - start - 1) as u8;
+        buffer.extend_from_slice(data);
+        if let Some(checksum_at) = checksum_at {
+            compute_checksum(&mut buffer[start..], checksum_at);
+        }
+        return buffer.len() - start;
+    }
+}
+
+fn parse_parameters(mut variable_part: &[u8]) -> Result<Vec<Parameter>, Error> {
+    let mut parameters = vec![];
+    while variable_part.len() > 0 {
+        if variable_part.len() < 2 || variable_part.len() < 2 + variable_part[1] as usize {
+            return Err(Error::new(ErrorKind::InvalidData, "TPDU parameter exceeds header"));
+        }
+        let length = variable_part[1] as usize;
+        parameters.push(Parameter { code: variable_part[0], value: variable_part[2..2 + length].to_vec() });
+        variable_part = &variable_part[2 + length..];
+    }
+    return Ok(parameters);
+}
+
+/// X.224 6.17 Checksum, the same Fletcher arithmetic as X.233 Annex C, but over the whole TPDU
+/// and with the checksum at an arbitrary position (0-based) within it
+fn compute_checksum(tpdu: &mut [u8], checksum_at: usize) {
+    //TODO optimize, naive mod 255 arithmetic like in CLNP
+    let length = tpdu.len() as isize;
+    tpdu[checksum_at] = 0;
+    tpdu[checksum_at + 1] = 0;
+    let mut c0: isize = 0;
+    let mut c1: isize = 0;
+    for octet in tpdu.iter() {
+        c0 = (c0 + *octet as isize) % 255;
+        c1 = (c1 + c0) % 255;
+    }
+    // NOTE: the position n in X.224 counts from 1
+    let n = checksum_at as isize + 1;
+    let mut x = ((length - n) * c0 - c1).rem_euclid(255);
+    let mut y = (c1 - (length - n + 1) * c0).rem_euclid(255);
+    if x == 0 { x = 255; }
+    if y == 0 { y = 255; }
+    tpdu[checksum_at] = x as u8;
+    tpdu[checksum_at + 1] = y as u8;
+}
+
+fn verify_checksum(tpdu: &[u8]) -> bool {
+    let mut c0: usize = 0;
+    let mut c1: usize = 0;
+    for octet in tpdu.iter() {
+        c0 = (c0 + *octet as usize) % 255;
+        c1 = (c1 + c0) % 255;
+    }
+    return c0 == 0 && c1 == 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters() -> Vec<Parameter> {
+        return vec![
+            Parameter { code: PARAMETER_CALLING_TSAP, value: b"calling".to_vec() },
+            Parameter { code: PARAMETER_CALLED_TSAP, value: b"called".to_vec() },
+            Parameter { code: PARAMETER_TPDU_SIZE, value: vec![0x0A] },
+        ];
+    }
+
+    fn all_tpdus() -> Vec<Tpdu> {
+        return vec![
+            Tpdu::ConnectionRequest { credit: 8, source_reference: 0x1234, class_option: CLASS_4, parameters: parameters(), data: b"hello".to_vec() },
+            Tpdu::ConnectionConfirm { credit: 15, destination_reference: 0x1234, source_reference: 0x5678, class_option: CLASS_4, parameters: parameters(), data: vec![] },
+            Tpdu::DisconnectRequest { destination_reference: 0x5678, source_reference: 0x1234, reason: REASON_NORMAL, parameters: vec![], data: b"bye".to_vec() },
+            Tpdu::DisconnectConfirm { destination_reference: 0x1234, source_reference: 0x5678, parameters: vec![] },
+            Tpdu::Data { destination_reference: 0x5678, end_of_tsdu: true, sequence_number: 127, parameters: vec![], data: vec![0x42; 100] },
+            Tpdu::Data { destination_reference: 0x5678, end_of_tsdu: false, sequence_number: 0, parameters: vec![], data: vec![] },
+            Tpdu::ExpeditedData { destination_reference: 0x5678, sequence_number: 3, parameters: vec![], data: b"urgent".to_vec() },
+            Tpdu::Ack { credit: 7, destination_reference: 0x1234, next_expected: 42, parameters: vec![] },
+            Tpdu::ExpeditedAck { destination_reference: 0x1234, next_expected: 4, parameters: vec![] },
+            Tpdu::Error { destination_reference: 0x1234, cause: CAUSE_INVALID_TPDU_TYPE, parameters: vec![Parameter { code: PARAMETER_INVALID_TPDU, value: vec![0x03, 0xF0, 0x12, 0x34] }] },
+            Tpdu::UnitData { parameters: parameters(), data: b"datagram".to_vec() },
+        ];
+    }
+
+    #[test]
+    fn round_trip() {
+        for tpdu in all_tpdus() {
+            let mut buffer = vec![];
+            let length = tpdu.into_buf(false, &mut buffer);
+            assert_eq!(length, buffer.len());
+            assert_eq!(Tpdu::from_buf(&buffer).unwrap(), (tpdu.clone(), length));
+        }
+        // class 0 DT has a two octet header
+        let mut buffer = vec![];
+        Tpdu::DataClass0 { end_of_tsdu: true, data: b"class 0".to_vec() }.into_buf(false, &mut buffer);
+        assert_eq!(&buffer[0..3], &[2, CODE_DT, 0x80]);
+        assert_eq!(Tpdu::from_buf(&buffer).unwrap().0, Tpdu::DataClass0 { end_of_tsdu: true, data: b"class 0".to_vec() });
+    }
+
+    #[test]
+    fn checksum() {
+        for tpdu in all_tpdus() {
+            let mut buffer = vec![];
+            tpdu.into_buf(true, &mut buffer);
+            assert!(verify_checksum(&buffer));
+            let (decoded, _) = Tpdu::from_buf(&buffer).unwrap();
+            assert_eq!(decoded.parameter(PARAMETER_CHECKSUM).map(|value| value.len()), Some(2));
+            // any flipped bit is noticed, in the header as well as in the user data
+            for at in [1, buffer.len() - 1] {
+                let mut corrupted = buffer.clone();
+                corrupted[at] ^= 0x10;
+                assert!(Tpdu::from_buf(&corrupted).is_err(), "{:?} corrupted at {}", tpdu, at);
+            }
+            // encoding again with the decoded checksum parameter computes it fresh
+            let mut again = vec![];
+            decoded.into_buf(true, &mut again);
+            assert_eq!(again, buffer);
+        }
+    }
+
+    #[test]
+    fn concatenated() {
+        let mut buffer = vec![];
+        let ack_length = Tpdu::Ack { credit: 1, destination_reference: 1, next_expected: 1, parameters: vec![] }.into_buf(true, &mut buffer);
+        Tpdu::Data { destination_reference: 1, end_of_tsdu: true, sequence_number: 1, parameters: vec![], data: b"data".to_vec() }.into_buf(true, &mut buffer);
+        let (first, consumed) = Tpdu::from_buf(&buffer).unwrap();
+        assert_eq!(consumed, ack_length);
+        assert!(matches!(first, Tpdu::Ack { .. }));
+        // user data goes up to the end of the NSDU
+        let (second, consumed) = Tpdu::from_buf(&buffer[ack_length..]).unwrap();
+        assert_eq!(consumed, buffer.len() - ack_length);
+        assert!(matches!(second, Tpdu::Data { ref data, .. } if data == b"data"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Tpdu::from_buf(&[]).is_err());
+        assert!(Tpdu::from_buf(&[255, CODE_DT]).is_err());
+        // length indicator beyond the buffer
+        assert!(Tpdu::from_buf(&[6, CODE_CR, 0, 0]).is_err());
+        // unknown code
+        assert!(Tpdu::from_buf(&[4, 0x50, 0, 0, 0]).is_err());
+        // fixed part too short for an AK
+        assert!(Tpdu::from_buf(&[3, CODE_AK, 0, 0]).is_err());
+        // parameter longer than the header
+        assert!(Tpdu::from_buf(&[6, CODE_AK, 0, 1, 0, PARAMETER_VERSION, 5]).is_err());
+        // a wrong-length checksum parameter
+        assert!(Tpdu::from_buf(&[7, CODE_AK, 0, 1, 0, PARAMETER_CHECKSUM, 1, 0]).is_err());
+    }
+
+    #[test]
+    fn class_negotiation() {
+        assert_eq!(acceptable_classes(4, &[]), vec![4, 2]);
+        assert_eq!(acceptable_classes(4, &[2]), vec![4, 2, 0]);
+        assert_eq!(acceptable_classes(2, &[0]), vec![2, 0]);
+        assert_eq!(select_class(4, &[], &[4]), Some(4));
+        assert_eq!(select_class(2, &[0], &[4]), None);
+        assert_eq!(select_class(4, &[2, 0], &[0, 2]), Some(2));
+        let cr = Tpdu::ConnectionRequest { credit: 0, source_reference: 1, class_option: CLASS_4, parameters: vec![alternative_classes_parameter(&[2, 0])], data: vec![] };
+        assert_eq!(cr.parameter(PARAMETER_ALTERNATIVE_CLASSES), Some(&[0x20, 0x00][..]));
+        assert_eq!(alternative_classes(&cr), vec![2, 0]);
+        assert_eq!(tpdu_size_from_code(0x07), Some(128));
+        assert_eq!(tpdu_size_from_code(0x0D), Some(8192));
+        assert_eq!(tpdu_size_from_code(0x0E), None);
+        assert_eq!(tpdu_size_from_code(0x06), None);
+    }
+
+    #[test]
+    fn extended_format() {
+        let tpdus = vec![
+            Tpdu::Data { destination_reference: 0x5678, end_of_tsdu: true, sequence_number: EXTENDED_SEQUENCE_MODULUS - 1, parameters: vec![], data: vec![0x42; 10] },
+            Tpdu::Data { destination_reference: 0x5678, end_of_tsdu: false, sequence_number: 128, parameters: vec![], data: vec![] },
+            Tpdu::ExpeditedData { destination_reference: 0x5678, sequence_number: 0x0102_0304, parameters: vec![], data: b"urgent".to_vec() },
+            Tpdu::Ack { credit: 300, destination_reference: 0x1234, next_expected: 0x4000_0000, parameters: vec![] },
+            Tpdu::ExpeditedAck { destination_reference: 0x1234, next_expected: 70000, parameters: vec![] },
+        ];
+        for tpdu in tpdus {
+            for checksum in [false, true] {
+                let mut buffer = vec![];
+                let length = tpdu.into_buf_in_format(checksum, true, &mut buffer);
+                assert_eq!(format_dependent_reference(&buffer), Some(tpdu.destination_reference()));
+                let (decoded, consumed) = Tpdu::from_buf_in_format(&buffer, true).unwrap();
+                assert_eq!(consumed, length);
+                assert_eq!(decoded.destination_reference(), tpdu.destination_reference());
+                match (&decoded, &tpdu) {
+                    (Tpdu::Data { sequence_number: a, end_of_tsdu: eot_a, data: data_a, .. }, Tpdu::Data { sequence_number: b, end_of_tsdu: eot_b, data: data_b, .. }) => {
+                        assert_eq!((a, eot_a, data_a), (b, eot_b, data_b));
+                    },
+                    (Tpdu::ExpeditedData { sequence_number: a, data: data_a, .. }, Tpdu::ExpeditedData { sequence_number: b, data: data_b, .. }) => {
+                        assert_eq!((a, data_a), (b, data_b));
+                    },
+                    (Tpdu::Ack { credit: credit_a, next_expected: a, .. }, Tpdu::Ack { credit: credit_b, next_expected: b, .. }) => {
+                        assert_eq!((credit_a, a), (credit_b, b));
+                    },
+                    (Tpdu::ExpeditedAck { next_expected: a, .. }, Tpdu::ExpeditedAck { next_expected: b, .. }) => assert_eq!(a, b),
+                    _ => panic!("{:?} decoded as {:?}", tpdu, decoded)
+                }
+            }
+        }
+        // X.224 13.7.3 - LI, code, DST-REF and 4 octets EOT and TPDU-NR
+        let mut buffer = vec![];
+        Tpdu::Data { destination_reference: 1, end_of_tsdu: true, sequence_number: 5, parameters: vec![], data: vec![] }.into_buf_in_format(false, true, &mut buffer);
+        assert_eq!(buffer, [7, CODE_DT, 0, 1, 0x80, 0, 0, 5]);
+        // X.224 13.9.3.2 - the credit in the fixed part instead of the code octet
+        let mut buffer = vec![];
+        Tpdu::Ack { credit: 0x0102, destination_reference: 1, next_expected: 5, parameters: vec![] }.into_buf_in_format(false, true, &mut buffer);
+        assert_eq!(buffer, [9, CODE_AK, 0, 1, 0, 0, 0, 5, 0x01, 0x02]);
+        // the same TPDU in normal format is something else
+        assert_ne!(Tpdu::from_buf(&buffer).map(|(tpdu, _)| tpdu).ok(), Some(Tpdu::Ack { credit: 0x0102, destination_reference: 1, next_expected: 5, parameters: vec![] }));
+        // class 0 DT and the connection establishment TPDUs do not depend on the format
+        assert_eq!(format_dependent_reference(&[2, CODE_DT, 0x80]), None);
+        let mut buffer = vec![];
+        Tpdu::ConnectionConfirm { credit: 0, destination_reference: 1, source_reference: 2, class_option: CLASS_4 | OPTION_EXTENDED_FORMAT, parameters: vec![], data: vec![] }.into_buf(false, &mut buffer);
+        assert_eq!(format_dependent_reference(&buffer), None);
+    }
+}